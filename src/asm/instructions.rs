@@ -0,0 +1,507 @@
+//! Encoding of MIPS I instructions and pseudo-instructions.
+
+use super::{expect_operands, AsmResult, Assembler};
+use crate::control::isa::{Encoding, INSTRUCTIONS};
+use crate::control::registers::REGISTER_NAMES;
+use crate::Address;
+
+/// Assembler temporary register used by pseudo-instruction expansions.
+const AT: u32 = 1;
+
+fn r_type(rs: u32, rt: u32, rd: u32, shamt: u32, funct: u32) -> u32 {
+    (rs << 21) | (rt << 16) | (rd << 11) | (shamt << 6) | funct
+}
+
+fn i_type(opcode: u32, rs: u32, rt: u32, imm: i64) -> u32 {
+    (opcode << 26) | (rs << 21) | (rt << 16) | (imm as u32 & 0xffff)
+}
+
+/// Parses a general purpose register such as `$t0`, `$8` or `$zero`.
+fn register(operand: &str) -> AsmResult<u32> {
+    let name = operand
+        .strip_prefix('$')
+        .ok_or_else(|| format!("expected a register: {}", operand))?;
+
+    let number = match name {
+        "s8" => Some(30),
+        _ => REGISTER_NAMES.iter().position(|r| *r == name),
+    };
+    match number {
+        Some(number) => Ok(number as u32),
+        None => match name.parse() {
+            Ok(number @ 0..=31) => Ok(number),
+            _ => Err(format!("invalid register {}", operand)),
+        },
+    }
+}
+
+/// Parses a coprocessor register such as `$12` or `$f4`.
+fn coprocessor_register(operand: &str) -> AsmResult<u32> {
+    let number = operand
+        .strip_prefix("$f")
+        .or_else(|| operand.strip_prefix('$'))
+        .and_then(|n| n.parse().ok());
+
+    match number {
+        Some(number @ 0..=31) => Ok(number),
+        _ => Err(format!("invalid coprocessor register {}", operand)),
+    }
+}
+
+fn is_register(operand: &str) -> bool {
+    register(operand).is_ok()
+}
+
+/// Splits a memory operand `offset(base)` into its offset expression and base register.
+fn memory_operand(operand: &str) -> AsmResult<(&str, Option<u32>)> {
+    match operand.rfind('(') {
+        Some(i) if operand[i + 1..].trim_start().starts_with('$') => {
+            let base = operand[i + 1..]
+                .strip_suffix(')')
+                .ok_or_else(|| format!("invalid memory operand {}", operand))?;
+            let offset = operand[..i].trim();
+            Ok((
+                if offset.is_empty() { "0" } else { offset },
+                Some(register(base.trim())?),
+            ))
+        }
+        _ => Ok((operand, None)),
+    }
+}
+
+fn alu_funct(mnemonic: &str) -> Option<u32> {
+    Some(match mnemonic {
+        "add" => 0x20,
+        "addu" => 0x21,
+        "sub" => 0x22,
+        "subu" => 0x23,
+        "and" => 0x24,
+        "or" => 0x25,
+        "xor" => 0x26,
+        "nor" => 0x27,
+        "slt" => 0x2a,
+        "sltu" => 0x2b,
+        _ => return None,
+    })
+}
+
+/// Returns the opcode and signedness of the immediate form of an ALU instruction.
+fn alu_immediate(mnemonic: &str) -> Option<(u32, bool)> {
+    Some(match mnemonic {
+        "addi" | "add" | "sub" => (0x08, true),
+        "addiu" | "addu" | "subu" => (0x09, true),
+        "slti" | "slt" => (0x0a, true),
+        "sltiu" | "sltu" => (0x0b, true),
+        "andi" | "and" => (0x0c, false),
+        "ori" | "or" => (0x0d, false),
+        "xori" | "xor" => (0x0e, false),
+        _ => return None,
+    })
+}
+
+fn memory_opcode(mnemonic: &str) -> Option<u32> {
+    Some(match mnemonic {
+        "lb" => 0x20,
+        "lh" => 0x21,
+        "lwl" => 0x22,
+        "lw" => 0x23,
+        "lbu" => 0x24,
+        "lhu" => 0x25,
+        "lwr" => 0x26,
+        "sb" => 0x28,
+        "sh" => 0x29,
+        "swl" => 0x2a,
+        "sw" => 0x2b,
+        "swr" => 0x2e,
+        "lwc1" => 0x31,
+        "lwc2" => 0x32,
+        "lwc3" => 0x33,
+        "swc1" => 0x39,
+        "swc2" => 0x3a,
+        "swc3" => 0x3b,
+        _ => return None,
+    })
+}
+
+/// Returns the format and function code of a floating-point operation such as `add.d`.
+fn fpu_operation(mnemonic: &str) -> Option<(u32, u32)> {
+    INSTRUCTIONS.iter().find_map(|spec| match spec.encoding {
+        Encoding::Cop1Function(fmt, funct) if spec.mnemonic == mnemonic => {
+            Some((fmt as u32, funct))
+        }
+        _ => None,
+    })
+}
+
+impl<'a> Assembler<'a> {
+    /// Assembles one instruction or pseudo-instruction into the current section.
+    pub(super) fn instruction(&mut self, mnemonic: &str, ops: &[&str]) -> AsmResult<()> {
+        self.align(4);
+
+        if let Some(funct) = alu_funct(mnemonic) {
+            expect_operands(ops, 3)?;
+            let (rd, rs) = (register(ops[0])?, register(ops[1])?);
+            if is_register(ops[2]) {
+                self.emit(r_type(rs, register(ops[2])?, rd, 0, funct));
+                return Ok(());
+            }
+
+            // Accept an immediate as the last operand like the GNU assembler does
+            let (opcode, signed) = match (mnemonic, alu_immediate(mnemonic)) {
+                ("nor", _) | (_, None) => return Err(format!("{} requires registers", mnemonic)),
+                (_, Some(immediate)) => immediate,
+            };
+            let mut imm = self.eval(ops[2])?;
+            if mnemonic.starts_with("sub") {
+                imm = -imm;
+            }
+            return self.emit_immediate(opcode, signed, rs, rd, imm);
+        }
+
+        if let Some((opcode, signed)) = alu_immediate(mnemonic) {
+            expect_operands(ops, 3)?;
+            let (rt, rs) = (register(ops[0])?, register(ops[1])?);
+            let imm = self.eval(ops[2])?;
+            return self.emit_immediate(opcode, signed, rs, rt, imm);
+        }
+
+        if let Some(opcode) = memory_opcode(mnemonic) {
+            expect_operands(ops, 2)?;
+            let rt = match opcode & 0x10 {
+                0 => register(ops[0])?,
+                _ => coprocessor_register(ops[0])?,
+            };
+            return self.emit_memory(opcode, rt, ops[1]);
+        }
+
+        if let Some((fmt, funct)) = fpu_operation(mnemonic) {
+            let (fd, fs, ft) = match funct {
+                0..=3 => {
+                    expect_operands(ops, 3)?;
+                    let fd = coprocessor_register(ops[0])?;
+                    (
+                        fd,
+                        coprocessor_register(ops[1])?,
+                        coprocessor_register(ops[2])?,
+                    )
+                }
+                // Compares only set the condition
+                48..=63 => {
+                    expect_operands(ops, 2)?;
+                    (
+                        0,
+                        coprocessor_register(ops[0])?,
+                        coprocessor_register(ops[1])?,
+                    )
+                }
+                _ => {
+                    expect_operands(ops, 2)?;
+                    (
+                        coprocessor_register(ops[0])?,
+                        coprocessor_register(ops[1])?,
+                        0,
+                    )
+                }
+            };
+            self.emit((0x11 << 26) | r_type(fmt, ft, fs, fd, funct));
+            return Ok(());
+        }
+
+        match mnemonic {
+            "sll" | "srl" | "sra" => {
+                expect_operands(ops, 3)?;
+                let funct = match mnemonic {
+                    "sll" => 0x00,
+                    "srl" => 0x02,
+                    _ => 0x03,
+                };
+                let (rd, rt) = (register(ops[0])?, register(ops[1])?);
+                let shamt = self.eval(ops[2])?;
+                self.check_range(shamt, 0, 31)?;
+                self.emit(r_type(0, rt, rd, shamt as u32 & 0x1f, funct));
+            }
+            "sllv" | "srlv" | "srav" => {
+                expect_operands(ops, 3)?;
+                let funct = match mnemonic {
+                    "sllv" => 0x04,
+                    "srlv" => 0x06,
+                    _ => 0x07,
+                };
+                let (rd, rt, rs) = (register(ops[0])?, register(ops[1])?, register(ops[2])?);
+                self.emit(r_type(rs, rt, rd, 0, funct));
+            }
+            "mult" | "multu" | "div" | "divu" if mnemonic.starts_with("mult") || ops.len() == 2 => {
+                expect_operands(ops, 2)?;
+                let funct = match mnemonic {
+                    "mult" => 0x18,
+                    "multu" => 0x19,
+                    "div" => 0x1a,
+                    _ => 0x1b,
+                };
+                self.emit(r_type(register(ops[0])?, register(ops[1])?, 0, 0, funct));
+            }
+            "mul" | "div" | "divu" | "rem" | "remu" => {
+                // Three operand forms that read the result back from LO or HI
+                expect_operands(ops, 3)?;
+                let (funct, from) = match mnemonic {
+                    "mul" => (0x18, 0x12),
+                    "div" => (0x1a, 0x12),
+                    "divu" => (0x1b, 0x12),
+                    "rem" => (0x1a, 0x10),
+                    _ => (0x1b, 0x10),
+                };
+                let (rd, rs, rt) = (register(ops[0])?, register(ops[1])?, register(ops[2])?);
+                self.emit(r_type(rs, rt, 0, 0, funct));
+
+                // `div $zero, rs, rt` is the GNU spelling of the plain instruction
+                if rd != 0 || mnemonic == "mul" {
+                    self.emit(r_type(0, 0, rd, 0, from));
+                }
+            }
+            "mfhi" | "mflo" => {
+                expect_operands(ops, 1)?;
+                let funct = if mnemonic == "mfhi" { 0x10 } else { 0x12 };
+                self.emit(r_type(0, 0, register(ops[0])?, 0, funct));
+            }
+            "mthi" | "mtlo" => {
+                expect_operands(ops, 1)?;
+                let funct = if mnemonic == "mthi" { 0x11 } else { 0x13 };
+                self.emit(r_type(register(ops[0])?, 0, 0, 0, funct));
+            }
+            "jr" => {
+                expect_operands(ops, 1)?;
+                self.emit(r_type(register(ops[0])?, 0, 0, 0, 0x08));
+            }
+            "jalr" => {
+                let (rd, rs) = match ops {
+                    [rs] => (31, register(rs)?),
+                    [rd, rs] => (register(rd)?, register(rs)?),
+                    _ => return Err(String::from("expected 1 or 2 operands")),
+                };
+                self.emit(r_type(rs, 0, rd, 0, 0x09));
+            }
+            "syscall" | "break" => {
+                let code = match ops {
+                    [] => 0,
+                    [code] => self.eval(code)?,
+                    _ => return Err(String::from("expected at most 1 operand")),
+                };
+                self.check_range(code, 0, 0x3ff)?;
+                let (shift, funct) = if mnemonic == "syscall" {
+                    (6, 0x0c)
+                } else {
+                    (16, 0x0d)
+                };
+                self.emit(((code as u32 & 0x3ff) << shift) | funct);
+            }
+            "lui" => {
+                expect_operands(ops, 2)?;
+                let imm = self.eval(ops[1])?;
+                self.check_range(imm, 0, 0xffff)?;
+                self.emit(i_type(0x0f, 0, register(ops[0])?, imm));
+            }
+            "beq" | "bne" => {
+                expect_operands(ops, 3)?;
+                let opcode = if mnemonic == "beq" { 0x04 } else { 0x05 };
+                let (rs, rt) = (register(ops[0])?, register(ops[1])?);
+                self.emit_branch(opcode, rs, rt, ops[2])?;
+            }
+            "blez" | "bgtz" => {
+                expect_operands(ops, 2)?;
+                let opcode = if mnemonic == "blez" { 0x06 } else { 0x07 };
+                self.emit_branch(opcode, register(ops[0])?, 0, ops[1])?;
+            }
+            "bltz" | "bgez" | "bltzal" | "bgezal" => {
+                expect_operands(ops, 2)?;
+                let rt = match mnemonic {
+                    "bltz" => 0x00,
+                    "bgez" => 0x01,
+                    "bltzal" => 0x10,
+                    _ => 0x11,
+                };
+                self.emit_branch(0x01, register(ops[0])?, rt, ops[1])?;
+            }
+            "j" | "jal" => {
+                expect_operands(ops, 1)?;
+                let opcode = if mnemonic == "j" { 0x02 } else { 0x03 };
+                let target = self.eval(ops[0])? as Address;
+                if self.pass == super::Pass::Second
+                    && (target & 0x3 != 0
+                        || (target ^ self.pc().wrapping_add(4)) & 0xf000_0000 != 0)
+                {
+                    return Err(format!("jump target 0x{:08x} is unreachable", target));
+                }
+                self.emit((opcode << 26) | ((target >> 2) & 0x03ff_ffff));
+            }
+            "mfc0" | "mtc0" => {
+                expect_operands(ops, 2)?;
+                let rs = if mnemonic == "mfc0" { 0x00 } else { 0x04 };
+                let (rt, rd) = (register(ops[0])?, coprocessor_register(ops[1])?);
+                self.emit((0x10 << 26) | r_type(rs, rt, rd, 0, 0));
+            }
+            "mfc1" | "cfc1" | "mtc1" | "ctc1" => {
+                expect_operands(ops, 2)?;
+                let rs = match mnemonic {
+                    "mfc1" => 0x00,
+                    "cfc1" => 0x02,
+                    "mtc1" => 0x04,
+                    _ => 0x06,
+                };
+                let (rt, rd) = (register(ops[0])?, coprocessor_register(ops[1])?);
+                self.emit((0x11 << 26) | r_type(rs, rt, rd, 0, 0));
+            }
+            "bc1f" | "bc1t" => {
+                expect_operands(ops, 1)?;
+                let rt = if mnemonic == "bc1f" { 0 } else { 1 };
+                self.emit_branch(0x11, 0x08, rt, ops[0])?;
+            }
+            "tlbr" | "tlbwi" | "tlbwr" | "tlbp" | "rfe" => {
+                expect_operands(ops, 0)?;
+                let funct = match mnemonic {
+                    "tlbr" => 0x01,
+                    "tlbwi" => 0x02,
+                    "tlbwr" => 0x06,
+                    "tlbp" => 0x08,
+                    _ => 0x10,
+                };
+                self.emit(0x4200_0000 | funct);
+            }
+
+            // Pseudo-instructions
+            "nop" => {
+                expect_operands(ops, 0)?;
+                self.emit(0);
+            }
+            "move" => {
+                expect_operands(ops, 2)?;
+                self.emit(r_type(register(ops[1])?, 0, register(ops[0])?, 0, 0x21));
+            }
+            "neg" | "negu" | "not" => {
+                expect_operands(ops, 2)?;
+                let (rd, rs) = (register(ops[0])?, register(ops[1])?);
+                self.emit(match mnemonic {
+                    "neg" => r_type(0, rs, rd, 0, 0x22),
+                    "negu" => r_type(0, rs, rd, 0, 0x23),
+                    _ => r_type(rs, 0, rd, 0, 0x27),
+                });
+            }
+            "li" => {
+                expect_operands(ops, 2)?;
+                let rt = register(ops[0])?;
+                match self.constant(ops[1]) {
+                    Some(imm) => {
+                        self.check_range(imm, -(1 << 31), 0xffff_ffff)?;
+                        if (-0x8000..=0x7fff).contains(&imm) {
+                            self.emit(i_type(0x09, 0, rt, imm));
+                        } else if (0..=0xffff).contains(&imm) {
+                            self.emit(i_type(0x0d, 0, rt, imm));
+                        } else if imm & 0xffff == 0 {
+                            self.emit(i_type(0x0f, 0, rt, imm >> 16));
+                        } else {
+                            self.emit(i_type(0x0f, 0, rt, imm >> 16));
+                            self.emit(i_type(0x0d, rt, rt, imm));
+                        }
+                    }
+                    None => {
+                        let imm = self.eval(ops[1])?;
+                        self.emit(i_type(0x0f, 0, rt, imm >> 16));
+                        self.emit(i_type(0x0d, rt, rt, imm));
+                    }
+                }
+            }
+            "la" => {
+                expect_operands(ops, 2)?;
+                let rt = register(ops[0])?;
+                let address = self.eval(ops[1])?;
+                self.emit(i_type(0x0f, 0, rt, (address + 0x8000) >> 16));
+                self.emit(i_type(0x09, rt, rt, address));
+            }
+            "b" => {
+                expect_operands(ops, 1)?;
+                self.emit_branch(0x04, 0, 0, ops[0])?;
+            }
+            "bal" => {
+                expect_operands(ops, 1)?;
+                self.emit_branch(0x01, 0, 0x11, ops[0])?;
+            }
+            "beqz" | "bnez" => {
+                expect_operands(ops, 2)?;
+                let opcode = if mnemonic == "beqz" { 0x04 } else { 0x05 };
+                self.emit_branch(opcode, register(ops[0])?, 0, ops[1])?;
+            }
+            "blt" | "bltu" | "bge" | "bgeu" | "bgt" | "bgtu" | "ble" | "bleu" => {
+                expect_operands(ops, 3)?;
+                let (rs, rt) = (register(ops[0])?, register(ops[1])?);
+                let funct = if mnemonic.ends_with('u') { 0x2b } else { 0x2a };
+
+                // Compare into $at, swapping the operands for the greater-than forms
+                let (a, b) = match &mnemonic[..3] {
+                    "blt" | "bge" => (rs, rt),
+                    _ => (rt, rs),
+                };
+                let opcode = match &mnemonic[..3] {
+                    "blt" | "bgt" => 0x05,
+                    _ => 0x04,
+                };
+                self.emit(r_type(a, b, AT, 0, funct));
+                self.emit_branch(opcode, AT, 0, ops[2])?;
+            }
+            _ => return Err(format!("unknown instruction {}", mnemonic)),
+        }
+
+        Ok(())
+    }
+
+    fn emit_immediate(
+        &mut self,
+        opcode: u32,
+        signed: bool,
+        rs: u32,
+        rt: u32,
+        imm: i64,
+    ) -> AsmResult<()> {
+        if signed {
+            self.check_range(imm, -0x8000, 0x7fff)?;
+        } else {
+            self.check_range(imm, 0, 0xffff)?;
+        }
+        self.emit(i_type(opcode, rs, rt, imm));
+        Ok(())
+    }
+
+    /// Emits a load or store, going through `$at` for addresses that need more than 16 bits.
+    fn emit_memory(&mut self, opcode: u32, rt: u32, operand: &str) -> AsmResult<()> {
+        let (offset, base) = memory_operand(operand)?;
+        match base {
+            Some(base) => {
+                let offset = self.eval(offset)?;
+                self.check_range(offset, -0x8000, 0x7fff)?;
+                self.emit(i_type(opcode, base, rt, offset));
+            }
+            None => match self.constant(offset) {
+                Some(address) if (-0x8000..=0x7fff).contains(&address) => {
+                    self.emit(i_type(opcode, 0, rt, address));
+                }
+                _ => {
+                    let address = self.eval(offset)?;
+                    self.emit(i_type(0x0f, 0, AT, (address + 0x8000) >> 16));
+                    self.emit(i_type(opcode, AT, rt, address));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn emit_branch(&mut self, opcode: u32, rs: u32, rt: u32, target: &str) -> AsmResult<()> {
+        let target = self.eval(target)?;
+        let offset = (target - (self.pc() as i64 + 4)) >> 2;
+        if self.pass == super::Pass::Second {
+            if target & 0x3 != 0 {
+                return Err(format!("branch target 0x{:08x} is not aligned", target));
+            }
+            self.check_range(offset, -0x8000, 0x7fff)?;
+        }
+        self.emit(i_type(opcode, rs, rt, offset));
+        Ok(())
+    }
+}
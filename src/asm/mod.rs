@@ -0,0 +1,695 @@
+//! A two-pass MIPS I assembler.
+//!
+//! Lets programs be run straight from assembly source without installing a
+//! cross toolchain. The syntax follows the GNU assembler: `label:` definitions,
+//! `#` comments, `.text` and `.data` sections, the common data directives and
+//! the usual pseudo-instructions. Branch and load delay slots are not filled
+//! automatically, as if the source started with `.set noreorder`.
+//!
+//! The text section is placed at the load address and the data section
+//! follows it. Symbols defined with `.equ` must be defined before they are used.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::util::error::{Result, RmipsError};
+use crate::{Address, Endian};
+
+mod instructions;
+
+type AsmResult<T> = std::result::Result<T, String>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Section {
+    Text,
+    Data,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Pass {
+    /// Lays out the sections and records the label addresses.
+    First,
+    /// Encodes the final image with every symbol resolved.
+    Second,
+}
+
+/// Returns true if `path` names an assembly source file rather than a binary image.
+pub fn is_source_file(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".s") || path.ends_with(".asm")
+}
+
+/// An assembled program.
+#[derive(Debug)]
+pub struct Program {
+    /// Image to be loaded at the base address, in the byte order it was assembled for.
+    pub image: Vec<u8>,
+    /// Virtual address of every label.
+    pub labels: HashMap<String, Address>,
+}
+
+/// Assembles `source` into a program to be loaded at virtual address `base` of a CPU with the
+/// `endian` byte order.
+pub fn assemble(source: &str, base: Address, endian: Endian) -> Result<Program> {
+    let mut asm = Assembler::new(base, endian);
+    asm.run(source, Pass::First)?;
+    asm.data_base = base + asm.text.len().next_multiple_of(4) as Address;
+    asm.run(source, Pass::Second)?;
+
+    let labels = asm
+        .labels
+        .iter()
+        .map(|(name, (section, offset))| (name.to_string(), asm.address(*section, *offset)))
+        .collect();
+
+    let mut image = asm.text;
+    image.resize(image.len().next_multiple_of(4), 0);
+    image.extend_from_slice(&asm.data);
+    image.resize(image.len().next_multiple_of(4), 0);
+    Ok(Program { image, labels })
+}
+
+struct Assembler<'a> {
+    pass: Pass,
+    section: Section,
+    text_base: Address,
+    data_base: Address,
+    text: Vec<u8>,
+    data: Vec<u8>,
+    labels: HashMap<&'a str, (Section, usize)>,
+    equates: HashMap<&'a str, i64>,
+    endian: Endian,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(base: Address, endian: Endian) -> Self {
+        Self {
+            pass: Pass::First,
+            section: Section::Text,
+            text_base: base,
+            data_base: 0,
+            text: Vec::new(),
+            data: Vec::new(),
+            labels: HashMap::new(),
+            equates: HashMap::new(),
+            endian,
+        }
+    }
+
+    fn run(&mut self, source: &'a str, pass: Pass) -> Result<()> {
+        self.pass = pass;
+        self.section = Section::Text;
+        self.text.clear();
+        self.data.clear();
+        self.equates.clear();
+
+        for (number, line) in source.lines().enumerate() {
+            self.statement(line)
+                .map_err(|msg| RmipsError::Assembly(number + 1, msg))?;
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, line: &'a str) -> AsmResult<()> {
+        let mut rest = strip_comment(line).trim();
+        while let Some((label, after)) = split_label(rest) {
+            self.define_label(label)?;
+            rest = after.trim_start();
+        }
+
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+            Some(i) => (&rest[..i], rest[i..].trim()),
+            None => (rest, ""),
+        };
+        let operands = split_operands(operands)?;
+
+        if mnemonic.starts_with('.') {
+            self.directive(mnemonic, &operands)
+        } else {
+            self.instruction(&mnemonic.to_ascii_lowercase(), &operands)
+        }
+    }
+
+    fn define_label(&mut self, label: &'a str) -> AsmResult<()> {
+        let location = (self.section, self.buffer().len());
+        match self.pass {
+            Pass::First if self.labels.insert(label, location).is_some() => {
+                Err(format!("label {} is defined more than once", label))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn directive(&mut self, name: &str, operands: &[&'a str]) -> AsmResult<()> {
+        match name {
+            ".text" => self.section = Section::Text,
+            ".data" | ".rdata" | ".sdata" => self.section = Section::Data,
+            // Directives that only matter to a linker or debugger
+            ".globl" | ".global" | ".ent" | ".end" | ".set" | ".type" | ".size" | ".file"
+            | ".frame" | ".mask" | ".fmask" => {}
+            ".word" | ".half" | ".byte" => {
+                let size = match name {
+                    ".word" => 4,
+                    ".half" => 2,
+                    _ => 1,
+                };
+                self.align(size);
+                for operand in operands {
+                    let value = self.eval(operand)?;
+                    self.check_range(value, -(1 << (size * 8 - 1)), (1 << (size * 8)) - 1)?;
+                    let bytes = match self.endian {
+                        Endian::Big => value.to_be_bytes()[8 - size..].to_vec(),
+                        Endian::Little => value.to_le_bytes()[..size].to_vec(),
+                    };
+                    self.emit_bytes(&bytes);
+                }
+            }
+            ".ascii" | ".asciiz" => {
+                for operand in operands {
+                    let mut bytes = parse_string(operand)?;
+                    if name == ".asciiz" {
+                        bytes.push(0);
+                    }
+                    self.emit_bytes(&bytes);
+                }
+            }
+            ".space" => {
+                expect_operands(operands, 1)?;
+                let len = self.require_constant(operands[0])?;
+                let len = usize::try_from(len).map_err(|_| format!("invalid size {}", len))?;
+                self.emit_bytes(&vec![0; len]);
+            }
+            ".align" => {
+                expect_operands(operands, 1)?;
+                match self.require_constant(operands[0])? {
+                    n @ 0..=12 => self.align(1 << n),
+                    n => return Err(format!("invalid alignment {}", n)),
+                }
+            }
+            ".equ" | ".eqv" => {
+                expect_operands(operands, 2)?;
+                let value = self.require_constant(operands[1])?;
+                self.equates.insert(operands[0], value);
+            }
+            _ => return Err(format!("unknown directive {}", name)),
+        }
+
+        Ok(())
+    }
+
+    fn buffer(&mut self) -> &mut Vec<u8> {
+        match self.section {
+            Section::Text => &mut self.text,
+            Section::Data => &mut self.data,
+        }
+    }
+
+    /// Returns the address of the next byte emitted into the current section.
+    fn pc(&self) -> Address {
+        self.address(self.section, self.section_len())
+    }
+
+    fn section_len(&self) -> usize {
+        match self.section {
+            Section::Text => self.text.len(),
+            Section::Data => self.data.len(),
+        }
+    }
+
+    fn address(&self, section: Section, offset: usize) -> Address {
+        let base = match section {
+            Section::Text => self.text_base,
+            Section::Data => self.data_base,
+        };
+        base.wrapping_add(offset as Address)
+    }
+
+    fn align(&mut self, alignment: usize) {
+        let buffer = self.buffer();
+        buffer.resize(buffer.len().next_multiple_of(alignment), 0);
+    }
+
+    fn emit_bytes(&mut self, bytes: &[u8]) {
+        self.buffer().extend_from_slice(bytes);
+    }
+
+    fn emit(&mut self, word: u32) {
+        self.align(4);
+        let bytes = self.endian.word_bytes(word);
+        self.emit_bytes(&bytes);
+    }
+
+    /// Evaluates `expr`, resolving labels and equates.
+    ///
+    /// Labels are not laid out yet during the first pass, so they evaluate to zero.
+    fn eval(&self, expr: &str) -> AsmResult<i64> {
+        let resolve = |name: &str| {
+            if let Some(value) = self.equates.get(name) {
+                Ok(*value)
+            } else if let Some((section, offset)) = self.labels.get(name) {
+                Ok(self.address(*section, *offset) as i64)
+            } else if self.pass == Pass::First {
+                Ok(0)
+            } else {
+                Err(format!("undefined symbol {}", name))
+            }
+        };
+
+        ExprParser::new(expr, &resolve).parse()
+    }
+
+    /// Evaluates `expr` if it only refers to equates defined so far.
+    ///
+    /// Used to choose between instruction sequences of different lengths, so the
+    /// answer must not change between the passes.
+    fn constant(&self, expr: &str) -> Option<i64> {
+        let resolve = |name: &str| {
+            self.equates
+                .get(name)
+                .copied()
+                .ok_or_else(|| format!("{} is not a constant", name))
+        };
+
+        ExprParser::new(expr, &resolve).parse().ok()
+    }
+
+    fn require_constant(&self, expr: &str) -> AsmResult<i64> {
+        self.constant(expr)
+            .ok_or_else(|| format!("expected a constant expression: {}", expr))
+    }
+
+    /// Checks that `value` lies in `min..=max`. Values are meaningless during the first pass.
+    fn check_range(&self, value: i64, min: i64, max: i64) -> AsmResult<()> {
+        if self.pass == Pass::Second && !(min..=max).contains(&value) {
+            Err(format!("value {} is out of range {}..={}", value, min, max))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn expect_operands(operands: &[&str], count: usize) -> AsmResult<()> {
+    if operands.len() == count {
+        Ok(())
+    } else {
+        Err(format!(
+            "expected {} operands but found {}",
+            count,
+            operands.len()
+        ))
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+/// Removes a `#` comment, ignoring `#` characters in string and character literals.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits a leading `label:` from `s`.
+fn split_label(s: &str) -> Option<(&str, &str)> {
+    if !s.starts_with(is_ident_start) {
+        return None;
+    }
+
+    let end = s.find(|c| !is_ident_char(c)).unwrap_or(s.len());
+    s[end..].strip_prefix(':').map(|rest| (&s[..end], rest))
+}
+
+/// Splits operands on commas that are not inside literals or parentheses.
+fn split_operands(s: &str) -> AsmResult<Vec<&str>> {
+    if s.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut operands = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                operands.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    operands.push(s[start..].trim());
+
+    if operands.iter().any(|op| op.is_empty()) {
+        return Err(String::from("empty operand"));
+    }
+    Ok(operands)
+}
+
+/// Parses the escape sequence following a backslash.
+fn parse_escape(chars: &mut std::str::Chars) -> AsmResult<u8> {
+    match chars.next() {
+        Some('n') => Ok(b'\n'),
+        Some('t') => Ok(b'\t'),
+        Some('r') => Ok(b'\r'),
+        Some('0') => Ok(0),
+        Some(c @ '\\') | Some(c @ '"') | Some(c @ '\'') => Ok(c as u8),
+        Some(c) => Err(format!("unknown escape sequence \\{}", c)),
+        None => Err(String::from("unterminated escape sequence")),
+    }
+}
+
+/// Parses a double-quoted string literal.
+fn parse_string(s: &str) -> AsmResult<Vec<u8>> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a string literal: {}", s))?;
+
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => bytes.push(parse_escape(&mut chars)?),
+            c => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Recursive descent parser for operand expressions.
+///
+/// Supports integer and character literals, symbols, unary `-` and `~`, binary
+/// `+` and `-`, parentheses and the `%hi()`/`%lo()` operators.
+struct ExprParser<'s, 'r> {
+    s: &'s str,
+    pos: usize,
+    resolve: &'r dyn Fn(&str) -> AsmResult<i64>,
+}
+
+impl<'s, 'r> ExprParser<'s, 'r> {
+    fn new(s: &'s str, resolve: &'r dyn Fn(&str) -> AsmResult<i64>) -> Self {
+        Self { s, pos: 0, resolve }
+    }
+
+    fn parse(mut self) -> AsmResult<i64> {
+        let value = self.expr()?;
+        self.skip_whitespace();
+        if self.pos != self.s.len() {
+            return Err(format!("invalid expression: {}", self.s));
+        }
+        Ok(value)
+    }
+
+    fn rest(&self) -> &'s str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expr(&mut self) -> AsmResult<i64> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat("+") {
+                value = value.wrapping_add(self.unary()?);
+            } else if self.eat("-") {
+                value = value.wrapping_sub(self.unary()?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> AsmResult<i64> {
+        if self.eat("-") {
+            Ok(self.unary()?.wrapping_neg())
+        } else if self.eat("~") {
+            Ok(!self.unary()?)
+        } else {
+            self.primary()
+        }
+    }
+
+    fn parenthesized(&mut self) -> AsmResult<i64> {
+        if !self.eat("(") {
+            return Err(format!("expected '(' in {}", self.s));
+        }
+        let value = self.expr()?;
+        if !self.eat(")") {
+            return Err(format!("expected ')' in {}", self.s));
+        }
+        Ok(value)
+    }
+
+    fn primary(&mut self) -> AsmResult<i64> {
+        self.skip_whitespace();
+        let rest = self.rest();
+
+        if rest.starts_with('(') {
+            self.parenthesized()
+        } else if self.eat("%hi") {
+            // Adjusted for the sign extension of the matching %lo
+            Ok(((self.parenthesized()? + 0x8000) >> 16) & 0xffff)
+        } else if self.eat("%lo") {
+            Ok(((self.parenthesized()? & 0xffff) ^ 0x8000) - 0x8000)
+        } else if let Some(literal) = rest.strip_prefix('\'') {
+            let mut chars = literal.chars();
+            let value = match chars.next() {
+                Some('\\') => parse_escape(&mut chars)?,
+                Some(c) if c.is_ascii() => c as u8,
+                _ => return Err(format!("invalid character literal in {}", self.s)),
+            };
+            if !chars.as_str().starts_with('\'') {
+                return Err(format!("unterminated character literal in {}", self.s));
+            }
+            self.pos = self.s.len() - chars.as_str().len() + 1;
+            Ok(value as i64)
+        } else if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            self.pos += end;
+            parse_number(&rest[..end])
+        } else if rest.starts_with(is_ident_start) {
+            let end = rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len());
+            self.pos += end;
+            (self.resolve)(&rest[..end])
+        } else {
+            Err(format!("invalid expression: {}", self.s))
+        }
+    }
+}
+
+fn parse_number(s: &str) -> AsmResult<i64> {
+    let lower = s.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        lower.parse()
+    };
+
+    parsed.map_err(|_| format!("invalid number {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn words(source: &str) -> Vec<u32> {
+        assemble(source, 0xbfc0_0000, Endian::Little)
+            .unwrap()
+            .image
+            .chunks(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect()
+    }
+
+    fn error_line(source: &str) -> usize {
+        match assemble(source, 0xbfc0_0000, Endian::Little) {
+            Err(RmipsError::Assembly(line, _)) => line,
+            result => panic!("expected an assembly error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn assemble_instructions() {
+        let source = "
+            lui   $a0, 0xbfc0       # comment
+            ori   $a0, $a0, 0x40
+            lw    $t0, 4($t0)
+            jalr  $t0
+            addu  $s0, $v0, $zero
+            sll   $t1, $t2, 4
+            sw    $ra, -8($sp)
+            mtc0  $t0, $12
+            mtc1  $t0, $f2
+            cfc1  $t0, $31
+            add.d $f2, $f4, $f2
+            cvt.d.s $f0, $f4
+            c.lt.s $f2, $f4
+            break
+        ";
+
+        assert_eq!(
+            words(source),
+            vec![
+                0x3c04bfc0, 0x34840040, 0x8d080004, 0x0100f809, 0x00408021, 0x000a4900, 0xafbffff8,
+                0x40886000, 0x44881000, 0x4448f800, 0x46222080, 0x46002021, 0x4604103c, 0x0000000d,
+            ]
+        );
+    }
+
+    #[test]
+    fn assemble_big_endian() {
+        let source = "nop\n li $t0, 1\n .half 0x1234\n .word 0x11223344";
+        let image = assemble(source, 0xbfc0_0000, Endian::Big).unwrap().image;
+        assert_eq!(
+            image,
+            [0, 0, 0, 0, 0x24, 0x08, 0, 1, 0x12, 0x34, 0, 0, 0x11, 0x22, 0x33, 0x44]
+        );
+    }
+
+    #[test]
+    fn assemble_branches_and_jumps() {
+        let source = "
+        start:
+            beq   $t0, $t1, done
+            nop
+        loop: bnez $t0, loop
+            j     start
+        done:
+            jal   loop
+        ";
+
+        assert_eq!(
+            words(source),
+            vec![0x11090003, 0x00000000, 0x1500ffff, 0x0bf00000, 0x0ff00002]
+        );
+    }
+
+    #[test]
+    fn assemble_pseudo_instructions() {
+        let source = "
+            li    $t0, 5
+            li    $t0, -1
+            li    $t0, 0xffff
+            li    $t0, 0x12340000
+            li    $t0, 0x12345678
+            la    $a0, message
+            move  $v0, $a0
+            blt   $t0, $t1, end
+        end:
+            .data
+        message:
+            .asciiz \"hi\"
+        ";
+
+        assert_eq!(
+            words(source),
+            vec![
+                0x24080005, 0x2408ffff, 0x3408ffff, 0x3c081234, 0x3c081234, 0x35085678, 0x3c04bfc0,
+                0x2484002c, 0x00801021, 0x0109082a, 0x14200000, 0x00006968,
+            ]
+        );
+    }
+
+    #[test]
+    fn assemble_data_directives() {
+        let source = "
+            .equ  COUNT, 3
+            .data
+            .byte 1, 'a', COUNT
+            .word table, -1
+            .half 0x1234
+            .align 3
+        table:
+            .space 2
+            .ascii \"a#b\\n\"
+        ";
+
+        let image = assemble(source, 0xbfc0_0000, Endian::Little).unwrap().image;
+        assert_eq!(
+            image,
+            vec![
+                1, b'a', 3, 0, 0x10, 0, 0xc0, 0xbf, 0xff, 0xff, 0xff, 0xff, 0x34, 0x12, 0, 0, 0, 0,
+                b'a', b'#', b'b', b'\n', 0, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn assemble_reports_labels() {
+        let source = "
+        start:
+            b     start
+            nop
+            .data
+        value:
+            .word 1
+        ";
+
+        let labels = assemble(source, 0xbfc0_0000, Endian::Little)
+            .unwrap()
+            .labels;
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels["start"], 0xbfc0_0000);
+        assert_eq!(labels["value"], 0xbfc0_0008);
+    }
+
+    #[test]
+    fn assemble_errors_report_line() {
+        assert_eq!(error_line("nop\nfoo $t0\n"), 2);
+        assert_eq!(error_line("nop\n\nj nowhere\n"), 3);
+        assert_eq!(error_line("addiu $t0, $t0, 40000"), 1);
+        assert_eq!(error_line("a:\na:\n"), 2);
+        assert_eq!(error_line("lw $t0, 4($t10)"), 1);
+    }
+}
@@ -0,0 +1,147 @@
+//! Dynamic basic-block and instruction mix profiling.
+//!
+//! Blocks are discovered from the executed instruction stream: a block ends after the
+//! delay slot of a branch or jump, or wherever execution does not continue with the next
+//! sequential instruction, such as when an exception is raised. The profile can be written
+//! as JSON for import into static analysis tools.
+
+use std::collections::BTreeMap;
+
+use crate::control::instruction::Instruction;
+use crate::Address;
+
+#[derive(Debug, Default)]
+pub struct BlockProfile {
+    instructions: u64,
+    mix: BTreeMap<&'static str, u64>,
+    /// Execution counts of blocks keyed by their first and last instruction addresses.
+    blocks: BTreeMap<(Address, Address), u64>,
+    /// Transitions from the last instruction of a block to the first instruction of the next.
+    edges: BTreeMap<(Address, Address), u64>,
+    /// Start of the block that is currently executing.
+    current: Option<Address>,
+    end_after_next: bool,
+}
+
+impl BlockProfile {
+    /// Records that `instr` at `pc` executed and that execution continues at `next_pc`.
+    pub fn record(&mut self, pc: Address, instr: Instruction, next_pc: Address) {
+        self.instructions += 1;
+        *self.mix.entry(instr.mnemonic()).or_default() += 1;
+
+        let start = *self.current.get_or_insert(pc);
+
+        // Branches and jumps end the block once their delay slot has executed
+        let ends = self.end_after_next || next_pc != pc.wrapping_add(4);
+        self.end_after_next = !ends && instr.is_control_transfer();
+
+        if ends {
+            *self.blocks.entry((start, pc)).or_default() += 1;
+            *self.edges.entry((pc, next_pc)).or_default() += 1;
+            self.current = None;
+        }
+    }
+
+    /// Returns the number of distinct blocks executed so far.
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Serializes the instruction mix, blocks and edges as JSON.
+    pub fn to_json(&self) -> String {
+        let mix: Vec<String> = self
+            .mix
+            .iter()
+            .map(|(mnemonic, count)| format!("    \"{}\": {}", mnemonic, count))
+            .collect();
+
+        let blocks: Vec<String> = self
+            .blocks
+            .iter()
+            .map(|((start, end), count)| {
+                format!(
+                    "    {{ \"start\": \"0x{:08x}\", \"end\": \"0x{:08x}\", \"size\": {}, \"executions\": {} }}",
+                    start,
+                    end,
+                    end.wrapping_sub(*start) + 4,
+                    count
+                )
+            })
+            .collect();
+
+        let edges: Vec<String> = self
+            .edges
+            .iter()
+            .map(|((from, to), count)| {
+                format!(
+                    "    {{ \"from\": \"0x{:08x}\", \"to\": \"0x{:08x}\", \"count\": {} }}",
+                    from, to, count
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\n  \"instructions\": {},\n  \"mix\": {{\n{}\n  }},\n  \"blocks\": [\n{}\n  ],\n  \"edges\": [\n{}\n  ]\n}}\n",
+            self.instructions,
+            mix.join(",\n"),
+            blocks.join(",\n"),
+            edges.join(",\n")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const ADDIU: Instruction = Instruction(0x2508_0001);
+    const BNE: Instruction = Instruction(0x1509_fffe);
+    const NOP: Instruction = Instruction(0);
+
+    #[test]
+    fn block_profile_loop() {
+        let mut profile = BlockProfile::default();
+
+        // Two iterations of a loop where the branch is taken and then falls through
+        profile.record(0x100, ADDIU, 0x104);
+        profile.record(0x104, BNE, 0x108);
+        profile.record(0x108, NOP, 0x100);
+        profile.record(0x100, ADDIU, 0x104);
+        profile.record(0x104, BNE, 0x108);
+        profile.record(0x108, NOP, 0x10c);
+        profile.record(0x10c, ADDIU, 0x110);
+
+        assert_eq!(profile.block_count(), 1);
+        assert_eq!(profile.blocks[&(0x100, 0x108)], 2);
+        assert_eq!(profile.edges[&(0x108, 0x100)], 1);
+        assert_eq!(profile.edges[&(0x108, 0x10c)], 1);
+        assert_eq!(profile.mix["addiu"], 3);
+        assert_eq!(profile.current, Some(0x10c));
+    }
+
+    #[test]
+    fn block_profile_json() {
+        let mut profile = BlockProfile::default();
+        profile.record(0x100, BNE, 0x104);
+        profile.record(0x104, NOP, 0x100);
+
+        assert_eq!(
+            profile.to_json(),
+            r#"{
+  "instructions": 2,
+  "mix": {
+    "bne": 1,
+    "nop": 1
+  },
+  "blocks": [
+    { "start": "0x00000100", "end": "0x00000104", "size": 8, "executions": 1 }
+  ],
+  "edges": [
+    { "from": "0x00000104", "to": "0x00000100", "count": 1 }
+  ]
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,422 @@
+//! Validated emulator configuration.
+//!
+//! `Opts` is the command line layer. Clap only enforces the requirements between options when
+//! they come from the command line, so library users building `Opts` by hand could create
+//! emulators that fail halfway through their setup, or much later while running. A `Config`
+//! can only be created from options that passed `Config::validate`, which checks them before
+//! any device or host resource is set up and names the offending option in its errors.
+
+use std::convert::TryFrom;
+use std::net::IpAddr;
+
+use crate::control::model::MAX_TLB_ENTRIES;
+use crate::machine;
+use crate::util::error::{Result, RmipsError};
+use crate::util::opts::{Opts, RamBase};
+
+/// Size of the physical address space.
+const PHYSICAL_SPACE: u64 = 1 << 32;
+
+/// Options that were checked to describe a machine the emulator can build.
+#[derive(Clone)]
+pub struct Config {
+    opts: Opts,
+    /// Problems that do not stop the machine from running, printed when it is created.
+    warnings: Vec<String>,
+}
+
+/// Returns an error about the configuration.
+fn invalid<T>(msg: String) -> Result<T> {
+    Err(RmipsError::Config(msg))
+}
+
+/// Checks the requirements between options that clap only enforces on the command line.
+fn check_requirements(opts: &Opts) -> Result<()> {
+    let requirements = [
+        (
+            opts.rewind.is_some() && opts.stopat.is_none(),
+            "--rewind requires --stop-at",
+        ),
+        (
+            opts.malloc.is_some() != opts.free.is_some(),
+            "--malloc and --free must be given together",
+        ),
+        (
+            !opts.netpeer.is_empty() && opts.netlisten.is_none(),
+            "--net-peer requires --net-listen",
+        ),
+        (
+            opts.rtos.is_some() && !opts.debug,
+            "--rtos requires --debug",
+        ),
+        (
+            opts.nommu && opts.monitorprom,
+            "--no-mmu cannot be used with --monitor-prom",
+        ),
+        (
+            opts.verifydeterminism.is_some() && opts.debug,
+            "--verify-determinism cannot be used with --debug",
+        ),
+    ];
+
+    match requirements.iter().find(|(violated, _)| *violated) {
+        Some((_, msg)) => invalid(msg.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Checks the size and placement of RAM.
+fn check_memory(opts: &Opts) -> Result<()> {
+    if opts.memsize == 0 {
+        return invalid("--memsize must be larger than 0".to_owned());
+    }
+
+    let base = match opts.rambase {
+        Some(RamBase::Fixed(base)) => base as u64,
+        // A random base is chosen below 512MB
+        Some(RamBase::Random(_)) => 0,
+        None => 0,
+    };
+    if base + opts.memsize as u64 > PHYSICAL_SPACE {
+        return invalid(format!(
+            "--memsize of {} bytes at physical address 0x{:08x} exceeds the 4GB physical address space",
+            opts.memsize, base
+        ));
+    }
+    if matches!(opts.rambase, Some(RamBase::Random(_))) && opts.memsize as u64 >= 0x2000_0000 {
+        return invalid("--ram-base random needs a --memsize below 512MB".to_owned());
+    }
+    Ok(())
+}
+
+/// Checks that addresses the emulator accesses words at are word aligned.
+fn check_alignment(opts: &Opts) -> Result<()> {
+    let addresses = [
+        ("--loadaddress", Some(opts.loadaddress)),
+        ("--halt-device-at", opts.haltdeviceat),
+        ("--test-device-at", opts.testdeviceat),
+    ];
+
+    for (option, address) in addresses.iter() {
+        match address {
+            Some(address) if address % 4 != 0 => {
+                return invalid(format!("{} 0x{:08x} is not word aligned", option, address))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks the values of options with a limited range.
+fn check_ranges(opts: &Opts) -> Result<()> {
+    if let Some(entries) = opts.tlbentries {
+        if !(1..=MAX_TLB_ENTRIES).contains(&entries) {
+            return Err(RmipsError::TlbSize(entries));
+        }
+    }
+    if opts.debug {
+        if opts.debugport == 0 {
+            return invalid("--port must be a fixed port for GDB to connect to".to_owned());
+        }
+        if opts.debugip.parse::<IpAddr>().is_err() {
+            return invalid(format!("--ip {} is not an IP address", opts.debugip));
+        }
+    }
+    if opts.nvram.is_some() && opts.nvramsize == 0 {
+        return invalid("--nvramsize must be larger than 0".to_owned());
+    }
+    if opts.sharedmemory.is_some() && opts.sharedmemorysize == 0 {
+        return invalid("--shared-memory-size must be larger than 0".to_owned());
+    }
+    if opts.faultrate > 1_000_000 {
+        return invalid(format!(
+            "--fault-rate of {} exceeds one million per million accesses",
+            opts.faultrate
+        ));
+    }
+    if let Some(runs) = opts.verifydeterminism.filter(|runs| *runs < 2) {
+        return invalid(format!(
+            "--verify-determinism needs at least 2 runs to compare, not {}",
+            runs
+        ));
+    }
+    if let Some(quota) = opts.cpuquota.filter(|quota| !(1..=100).contains(quota)) {
+        return invalid(format!(
+            "--cpu-quota of {}% is not between 1 and 100",
+            quota
+        ));
+    }
+    Ok(())
+}
+
+/// Instruction set architectures of the `EF_MIPS_ARCH` field of the ELF header flags.
+const ELF_ARCHES: [&str; 11] = [
+    "MIPS I", "MIPS II", "MIPS III", "MIPS IV", "MIPS V", "MIPS32", "MIPS64", "MIPS32r2",
+    "MIPS64r2", "MIPS32r6", "MIPS64r6",
+];
+
+/// The `e_machine` of MIPS programs.
+const EM_MIPS: u16 = 8;
+
+/// The fields of an ELF header that describe the machine a program was built for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ElfHeader {
+    /// `EI_CLASS`, 1 for 32-bit and 2 for 64-bit files.
+    class: u8,
+    /// `EI_DATA`, 1 for little-endian and 2 for big-endian files.
+    data: u8,
+    machine: u16,
+    flags: u32,
+}
+
+impl ElfHeader {
+    /// Reads the header of `path`, or returns `None` if it is not an ELF file.
+    fn read(path: &str) -> Option<Self> {
+        let mut header = [0; 40];
+        std::fs::File::open(path)
+            .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut header))
+            .ok()?;
+        Self::parse(&header)
+    }
+
+    fn parse(header: &[u8; 40]) -> Option<Self> {
+        if &header[..4] != b"\x7fELF" {
+            return None;
+        }
+        let (class, data) = (header[4], header[5]);
+        let machine = [header[18], header[19]];
+        let flags = [header[36], header[37], header[38], header[39]];
+        let (machine, flags) = match data {
+            2 => (u16::from_be_bytes(machine), u32::from_be_bytes(flags)),
+            _ => (u16::from_le_bytes(machine), u32::from_le_bytes(flags)),
+        };
+        Some(Self {
+            class,
+            data,
+            machine,
+            flags,
+        })
+    }
+
+    /// Returns the instruction set architecture in the MIPS flags.
+    fn arch(&self) -> &'static str {
+        ELF_ARCHES
+            .get((self.flags >> 28) as usize)
+            .copied()
+            .unwrap_or("an unknown MIPS architecture")
+    }
+}
+
+/// Checks that an ELF ROM was built for a 32-bit MIPS with the configured endianness, and
+/// returns a warning if it was built for a newer instruction set than MIPS I.
+///
+/// ELF files are mapped as raw images like any other ROM file, so the headers are only used
+/// to catch the garbled instructions of a mismatched machine early.
+fn check_elf(opts: &Opts) -> Result<Option<String>> {
+    let Some(header) = ElfHeader::read(&opts.romfile) else {
+        return Ok(None);
+    };
+
+    if header.class != 1 {
+        return invalid(format!(
+            "{} is a 64-bit ELF file, only 32-bit programs can run",
+            opts.romfile
+        ));
+    }
+    if header.machine != EM_MIPS {
+        return invalid(format!(
+            "{} is an ELF file for machine {}, not for MIPS",
+            opts.romfile, header.machine
+        ));
+    }
+    match (header.data, opts.bigendian) {
+        (1, true) => {
+            return invalid(format!(
+                "{} is a little-endian ELF file, but --bigendian is set",
+                opts.romfile
+            ))
+        }
+        (2, false) => {
+            return invalid(format!(
+                "{} is a big-endian ELF file, run it with --bigendian",
+                opts.romfile
+            ))
+        }
+        _ => {}
+    }
+
+    Ok((header.flags >> 28 != 0).then(|| {
+        format!(
+            "{} is built for {}, but only MIPS I instructions are emulated and newer ones raise \
+             Reserved Instruction exceptions",
+            opts.romfile,
+            header.arch()
+        )
+    }))
+}
+
+impl Config {
+    /// Checks `opts` and returns the configuration if they describe a valid machine, after
+    /// reading the `--machine` file into the options that were left at their defaults.
+    pub fn validate(mut opts: Opts) -> Result<Self> {
+        machine::apply(&mut opts)?;
+        if opts.romfile.is_empty() {
+            return invalid("no ROM file is given".to_owned());
+        }
+        check_requirements(&opts)?;
+        check_memory(&opts)?;
+        check_alignment(&opts)?;
+        check_ranges(&opts)?;
+        let warnings = check_elf(&opts)?.into_iter().collect();
+        Ok(Self { opts, warnings })
+    }
+
+    /// Returns the problems found in the options that the machine can run with.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Returns the validated options.
+    pub fn opts(&self) -> &Opts {
+        &self.opts
+    }
+
+    /// Returns the validated options, consuming the configuration.
+    pub fn into_opts(self) -> Opts {
+        self.opts
+    }
+}
+
+impl TryFrom<Opts> for Config {
+    type Error = RmipsError;
+
+    fn try_from(opts: Opts) -> Result<Self> {
+        Config::validate(opts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn error(opts: Opts) -> String {
+        match Config::validate(opts) {
+            Ok(_) => panic!("invalid options were accepted"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn config_validate() {
+        let opts = || Opts {
+            romfile: "rom.bin".to_owned(),
+            ..Default::default()
+        };
+        assert!(Config::validate(opts()).is_ok());
+
+        assert_eq!(
+            error(Opts::default()),
+            "Invalid configuration: no ROM file is given"
+        );
+        assert_eq!(
+            error(Opts {
+                rewind: Some(10),
+                ..opts()
+            }),
+            "Invalid configuration: --rewind requires --stop-at"
+        );
+        assert_eq!(
+            error(Opts {
+                memsize: 0x2000_0000,
+                rambase: Some("0xf0000000".parse().unwrap()),
+                ..opts()
+            }),
+            "Invalid configuration: --memsize of 536870912 bytes at physical address 0xf0000000 \
+             exceeds the 4GB physical address space"
+        );
+        assert_eq!(
+            error(Opts {
+                loadaddress: 0xbfc00002,
+                ..opts()
+            }),
+            "Invalid configuration: --loadaddress 0xbfc00002 is not word aligned"
+        );
+        assert_eq!(
+            error(Opts {
+                debug: true,
+                debugip: "localhost:9001".to_owned(),
+                ..opts()
+            }),
+            "Invalid configuration: --ip localhost:9001 is not an IP address"
+        );
+        assert_eq!(
+            error(Opts {
+                verifydeterminism: Some(1),
+                ..opts()
+            }),
+            "Invalid configuration: --verify-determinism needs at least 2 runs to compare, not 1"
+        );
+        assert!(matches!(
+            Config::validate(Opts {
+                tlbentries: Some(0),
+                ..opts()
+            }),
+            Err(RmipsError::TlbSize(0))
+        ));
+    }
+
+    #[test]
+    fn config_elf_endianness() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("rmips-{}-be.elf", std::process::id()));
+        let mut header = [0; 40];
+        header[..6].copy_from_slice(b"\x7fELF\x01\x02");
+        header[19] = EM_MIPS as u8;
+        std::fs::write(&path, header)?;
+
+        let opts = |bigendian| Opts {
+            romfile: path.to_string_lossy().into_owned(),
+            bigendian,
+            ..Default::default()
+        };
+        assert!(Config::validate(opts(true)).is_ok());
+        assert!(error(opts(false)).contains("is a big-endian ELF file, run it with --bigendian"));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn config_elf_machine() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("rmips-{}-arch.elf", std::process::id()));
+        let opts = || Opts {
+            romfile: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let mut header = [0; 40];
+        header[..6].copy_from_slice(b"\x7fELF\x01\x01");
+        header[18] = EM_MIPS as u8;
+
+        std::fs::write(&path, header)?;
+        assert_eq!(Config::validate(opts())?.warnings(), &[] as &[String]);
+
+        // EF_MIPS_ARCH_32R2 with the microMIPS and o32 ABI flags
+        header[36..40].copy_from_slice(&0x7200_1000u32.to_le_bytes());
+        std::fs::write(&path, header)?;
+        let config = Config::validate(opts())?;
+        assert_eq!(config.warnings().len(), 1);
+        assert!(config.warnings()[0].contains("is built for MIPS32r2, but only MIPS I"));
+
+        header[18] = 62; // EM_X86_64
+        std::fs::write(&path, header)?;
+        assert!(error(opts()).contains("is an ELF file for machine 62, not for MIPS"));
+
+        header[4] = 2;
+        std::fs::write(&path, header)?;
+        assert!(error(opts()).contains("is a 64-bit ELF file"));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+//! Host terminal console that passes standard input through to the guest.
+//!
+//! While standard input is a terminal, it is switched to raw mode so that keys reach the guest
+//! as they are typed, including control characters like Ctrl-C. Like in QEMU, Ctrl-A starts an
+//! escape sequence that is handled by the emulator instead: Ctrl-A x quits, Ctrl-A c shows the
+//! monitor with the machine state, and Ctrl-A Ctrl-A sends a Ctrl-A to the guest. The terminal
+//! settings are restored when the console is dropped.
+
+use std::io::Read;
+use std::sync::mpsc::{self, Receiver};
+
+/// The key that starts an escape sequence, Ctrl-A.
+pub const ESCAPE: u8 = 0x01;
+
+/// A command given to the emulator with an escape sequence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsoleCommand {
+    /// Ctrl-A x stops the emulator.
+    Quit,
+    /// Ctrl-A c prints the machine state.
+    Monitor,
+}
+
+/// A key for the guest or a command for the emulator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsoleInput {
+    Key(u8),
+    Command(ConsoleCommand),
+}
+
+/// Separates escape sequences from the keys passed through to the guest.
+#[derive(Debug, Default)]
+pub struct EscapeFilter {
+    escaped: bool,
+}
+
+impl EscapeFilter {
+    /// Returns the input completed by `byte`, if any. Unknown escape sequences are dropped.
+    pub fn feed(&mut self, byte: u8) -> Option<ConsoleInput> {
+        if !std::mem::take(&mut self.escaped) {
+            if byte == ESCAPE {
+                self.escaped = true;
+                return None;
+            }
+            return Some(ConsoleInput::Key(byte));
+        }
+
+        match byte {
+            ESCAPE => Some(ConsoleInput::Key(ESCAPE)),
+            b'x' | b'X' => Some(ConsoleInput::Command(ConsoleCommand::Quit)),
+            b'c' | b'C' => Some(ConsoleInput::Command(ConsoleCommand::Monitor)),
+            _ => None,
+        }
+    }
+}
+
+/// Terminal settings of standard input from before raw mode was enabled.
+#[cfg(unix)]
+struct RawMode {
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    /// Switches standard input to raw mode if it is a terminal.
+    ///
+    /// Output processing is left enabled, so the emulator's own output needs no changes.
+    fn enable() -> Option<Self> {
+        // SAFETY: The termios structs are plain data that tcgetattr fills in before use
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return None;
+            }
+            let mut original = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return None;
+            }
+
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            Some(Self { original })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: Restores the settings read by tcgetattr
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct RawMode;
+
+#[cfg(not(unix))]
+impl RawMode {
+    fn enable() -> Option<Self> {
+        None
+    }
+}
+
+/// The escape sequence commands typed on standard input.
+pub struct Console {
+    commands: Receiver<ConsoleCommand>,
+    raw_mode: Option<RawMode>,
+}
+
+impl Console {
+    /// Reads standard input on a background thread and returns the console together with a
+    /// channel receiving the keys for the guest.
+    pub fn stdin() -> (Self, Receiver<u8>) {
+        let raw_mode = RawMode::enable();
+        let (keys, key_receiver) = mpsc::channel();
+        let (commands, command_receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let mut filter = EscapeFilter::default();
+            for byte in std::io::stdin().lock().bytes() {
+                let sent = match byte.map(|byte| filter.feed(byte)) {
+                    Ok(Some(ConsoleInput::Key(key))) => keys.send(key).is_ok(),
+                    Ok(Some(ConsoleInput::Command(command))) => commands.send(command).is_ok(),
+                    Ok(None) => true,
+                    Err(_) => false,
+                };
+                if !sent {
+                    break;
+                }
+            }
+        });
+
+        let console = Self {
+            commands: command_receiver,
+            raw_mode,
+        };
+        (console, key_receiver)
+    }
+
+    /// Returns true if standard input was switched to raw mode.
+    pub fn is_raw(&self) -> bool {
+        self.raw_mode.is_some()
+    }
+
+    /// Returns the next command typed since the last call, if any.
+    pub fn command(&self) -> Option<ConsoleCommand> {
+        self.commands.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn escape_filter_sequences() {
+        let mut filter = EscapeFilter::default();
+        let inputs: Vec<ConsoleInput> = b"a\x01\x01\x03\x01c\x01?b\x01x"
+            .iter()
+            .filter_map(|byte| filter.feed(*byte))
+            .collect();
+
+        assert_eq!(
+            inputs,
+            vec![
+                ConsoleInput::Key(b'a'),
+                ConsoleInput::Key(ESCAPE),
+                ConsoleInput::Key(0x03),
+                ConsoleInput::Command(ConsoleCommand::Monitor),
+                ConsoleInput::Key(b'b'),
+                ConsoleInput::Command(ConsoleCommand::Quit),
+            ]
+        );
+    }
+}
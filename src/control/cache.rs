@@ -0,0 +1,85 @@
+//! Cache maintenance performed by the guest.
+//!
+//! The caches themselves are not modelled and memory is always up to date. What matters is
+//! where a program tells the hardware that instructions changed, such as after copying code
+//! into RAM, since those are the points where cached decodings of the old instructions have to
+//! be dropped: stores to the isolated instruction cache on the R3000, and the `cache`
+//! instruction on later models. Both are reported through `Memory::invalidate_instructions`.
+
+use crate::memory::Memory;
+use crate::util::error::Result;
+use crate::{Address, Endian};
+
+/// Memory as seen by loads and stores while the Status IsC bit isolates the cache of an R3000.
+///
+/// The accesses only reach the cache, so flush routines that store to every line do not
+/// overwrite memory. With the SwC bit set the instruction cache takes the place of the data
+/// cache and each store invalidates the line of instructions it hits. Loads return zero, as
+/// the contents of the cache are not modelled.
+pub struct IsolatedCache<'a, M> {
+    memory: &'a mut M,
+    /// True if the caches are swapped, so that the instruction cache is isolated.
+    swapped: bool,
+    /// Size of a cache line in bytes.
+    line: usize,
+}
+
+impl<'a, M: Memory> IsolatedCache<'a, M> {
+    pub fn new(memory: &'a mut M, swapped: bool, line: usize) -> Self {
+        Self {
+            memory,
+            swapped,
+            line,
+        }
+    }
+
+    fn store(&mut self, address: Address) -> Result<()> {
+        if self.swapped {
+            let line = address & !(self.line as Address - 1);
+            self.memory.invalidate_instructions(line, self.line);
+        }
+        Ok(())
+    }
+}
+
+impl<M: Memory> Memory for IsolatedCache<'_, M> {
+    fn endian(&self) -> Endian {
+        self.memory.endian()
+    }
+
+    fn interrupt_lines(&mut self) -> u8 {
+        self.memory.interrupt_lines()
+    }
+
+    fn fetch_instruction(&mut self, address: Address) -> Result<u32> {
+        self.memory.fetch_instruction(address)
+    }
+
+    fn fetch_word(&mut self, _address: Address) -> Result<u32> {
+        Ok(0)
+    }
+
+    fn fetch_halfword(&mut self, _address: Address) -> Result<u16> {
+        Ok(0)
+    }
+
+    fn fetch_byte(&mut self, _address: Address) -> Result<u8> {
+        Ok(0)
+    }
+
+    fn store_word(&mut self, address: Address, _data: u32) -> Result<()> {
+        self.store(address)
+    }
+
+    fn store_halfword(&mut self, address: Address, _data: u16) -> Result<()> {
+        self.store(address)
+    }
+
+    fn store_byte(&mut self, address: Address, _data: u8) -> Result<()> {
+        self.store(address)
+    }
+
+    fn invalidate_instructions(&mut self, address: Address, len: usize) {
+        self.memory.invalidate_instructions(address, len);
+    }
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+
+use crate::control::exception::Exception;
+use crate::control::instruction::Instruction;
+use crate::util::error;
+use crate::util::state::{StateReader, StateWriter};
+
+/// Interface for coprocessors that can be attached to the CP1-CP3 slots of the `Cpu`.
+///
+/// The `Cpu` moves data between memory and the coprocessor general registers
+/// for the LWCz and SWCz instructions, and between its own registers and the
+/// coprocessor for MFCz, MTCz, CFCz and CTCz. CP0 is always present and is
+/// handled separately by `CPZero`.
+pub trait Coprocessor: fmt::Debug {
+    /// Reads the value of general register `reg`.
+    fn read_register(&self, reg: usize) -> u32;
+    /// Writes `value` into general register `reg`.
+    fn write_register(&mut self, reg: usize, value: u32);
+
+    /// Reads the value of control register `reg`.
+    fn read_control(&self, _reg: usize) -> u32 {
+        0
+    }
+
+    /// Writes `value` into control register `reg`.
+    fn write_control(&mut self, _reg: usize, _value: u32) {}
+
+    /// Returns the condition signal that the BCzT and BCzF branches test.
+    fn condition(&self) -> bool {
+        false
+    }
+
+    /// Executes the coprocessor operation `instr`, a COPz instruction with the CO bit set.
+    ///
+    /// Returns the exception that the `Cpu` raises instead if the operation traps.
+    fn execute(&mut self, _instr: Instruction) -> Result<(), Exception> {
+        Err(Exception::ReservedInstruction)
+    }
+
+    /// Writes the registers to a machine snapshot.
+    fn save(&self, _state: &mut StateWriter) {}
+
+    /// Restores the registers written by `save`.
+    fn restore(&mut self, _state: &mut StateReader) -> error::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,618 @@
+//! The R3010 floating-point coprocessor, attached to the CP1 slot.
+//!
+//! Single precision values and words occupy one of the 32 floating-point general registers, a
+//! double occupies an even/odd pair with its low word in the even register. Operations are
+//! computed with the host arithmetic, the exact error of the result then decides the rounding
+//! in the mode selected by the FCSR and the exceptions that the operation signals.
+
+use std::cmp::Ordering;
+
+use bit_field::BitField;
+
+use crate::control::coprocessor::Coprocessor;
+use crate::control::exception::Exception;
+use crate::control::instruction::Instruction;
+use crate::util::error;
+use crate::util::state::{StateReader, StateWriter};
+
+/// Control register 0, the read-only implementation and revision register.
+pub const FIR: usize = 0;
+/// Control register 31, the control and status register.
+pub const FCSR: usize = 31;
+
+/// Value of the FIR, implementation 3 is the R3010.
+pub const FIR_VALUE: u32 = 0x0000_0300;
+
+/// Exception bit of an inexact result, in the order of the flag, enable and cause fields.
+pub const INEXACT: u32 = 1 << 0;
+/// Exception bit of a tiny and inexact result.
+pub const UNDERFLOW: u32 = 1 << 1;
+/// Exception bit of a result that is too large for the format.
+pub const OVERFLOW: u32 = 1 << 2;
+/// Exception bit of a division of a finite number by zero.
+pub const DIVIDE_BY_ZERO: u32 = 1 << 3;
+/// Exception bit of an invalid operation, such as an operation on a signaling NaN.
+pub const INVALID: u32 = 1 << 4;
+/// Exception bit of an operation that the FPU does not implement, only found in the cause field.
+pub const UNIMPLEMENTED: u32 = 1 << 5;
+
+/// Position of the sticky flag field in the FCSR.
+pub const FLAGS_SHIFT: u32 = 2;
+/// Position of the trap enable field in the FCSR.
+pub const ENABLES_SHIFT: u32 = 7;
+/// Position of the cause field in the FCSR.
+pub const CAUSE_SHIFT: u32 = 12;
+/// Bit of the FCSR that holds the condition of the last compare, tested by BC1T and BC1F.
+pub const CONDITION_BIT: usize = 23;
+
+/// Rounding mode field of the FCSR.
+pub const ROUNDING_MODE: u32 = 0b11;
+pub const ROUND_NEAREST: u32 = 0;
+pub const ROUND_ZERO: u32 = 1;
+pub const ROUND_UP: u32 = 2;
+pub const ROUND_DOWN: u32 = 3;
+
+/// Bits of the FCSR that CTC1 can write.
+const FCSR_WRITABLE: u32 = 0x0083_ffff;
+
+/// The quiet bit of MIPS I NaNs is set for signaling NaNs.
+const SINGLE_SIGNALING: u32 = 1 << 22;
+const DOUBLE_SIGNALING: u64 = 1 << 51;
+/// The NaNs that invalid operations return.
+const SINGLE_DEFAULT_NAN: u32 = 0x7fbf_ffff;
+const DOUBLE_DEFAULT_NAN: u64 = 0x7ff7_ffff_ffff_ffff;
+
+/// The fmt field of an operation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Single,
+    Double,
+    Word,
+}
+
+impl Format {
+    fn decode(fmt: usize) -> Option<Self> {
+        match fmt {
+            16 => Some(Format::Single),
+            17 => Some(Format::Double),
+            20 => Some(Format::Word),
+            _ => None,
+        }
+    }
+
+    fn default_nan(self) -> Output {
+        match self {
+            Format::Double => Output::Double(DOUBLE_DEFAULT_NAN),
+            _ => Output::Word(SINGLE_DEFAULT_NAN),
+        }
+    }
+}
+
+/// An operand of an operation, widened to a double.
+#[derive(Clone, Copy, Debug)]
+struct Operand {
+    value: f64,
+    signaling: bool,
+}
+
+/// The result of an operation, which is only stored if the operation does not trap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Output {
+    Word(u32),
+    Double(u64),
+    Condition(bool),
+}
+
+#[derive(Debug, Default)]
+pub struct Fpu {
+    /// Floating-point general registers.
+    fgr: [u32; 32],
+    fcsr: u32,
+}
+
+impl Fpu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn double(&self, reg: usize) -> u64 {
+        (self.fgr[reg + 1] as u64) << 32 | self.fgr[reg] as u64
+    }
+
+    /// Returns `reg` if it can hold a value of `format`, doubles need an even register.
+    fn register(format: Format, reg: usize) -> Option<usize> {
+        match format == Format::Double && !reg.is_multiple_of(2) {
+            true => None,
+            false => Some(reg),
+        }
+    }
+
+    fn operand(&self, format: Format, reg: usize) -> Option<Operand> {
+        let reg = Self::register(format, reg)?;
+        Some(match format {
+            Format::Single => {
+                let value = f32::from_bits(self.fgr[reg]);
+                Operand {
+                    value: value as f64,
+                    signaling: value.is_nan() && self.fgr[reg] & SINGLE_SIGNALING != 0,
+                }
+            }
+            Format::Double => {
+                let value = f64::from_bits(self.double(reg));
+                Operand {
+                    value,
+                    signaling: value.is_nan() && self.double(reg) & DOUBLE_SIGNALING != 0,
+                }
+            }
+            Format::Word => Operand {
+                value: self.fgr[reg] as i32 as f64,
+                signaling: false,
+            },
+        })
+    }
+
+    /// Performs the operation selected by `funct` on operands of `format`.
+    ///
+    /// Returns the exceptions it signals and its result, or `None` for unimplemented operations.
+    fn operate(
+        &self,
+        format: Format,
+        funct: u32,
+        ft: usize,
+        fs: usize,
+        fd: usize,
+    ) -> Option<(u32, Output)> {
+        let mode = self.fcsr & ROUNDING_MODE;
+        let float = format != Format::Word;
+        match funct {
+            // add, sub, mul, div
+            0..=3 if float => {
+                let (a, b) = (self.operand(format, fs)?, self.operand(format, ft)?);
+                Self::register(format, fd)?;
+                let (value, error, cause) = arithmetic(funct, a, b);
+                if value.is_nan() {
+                    return Some((cause, format.default_nan()));
+                }
+                let (rounding, output) = round(format, value, error, mode);
+                Some((cause | rounding, output))
+            }
+            // abs, neg
+            5 | 7 if float => {
+                let a = self.operand(format, fs)?;
+                Self::register(format, fd)?;
+                if a.value.is_nan() {
+                    return Some((signaling(a.signaling), format.default_nan()));
+                }
+                let value = if funct == 5 { a.value.abs() } else { -a.value };
+                Some(round(format, value, Ordering::Equal, mode))
+            }
+            // mov
+            6 if float => {
+                let fs = Self::register(format, fs)?;
+                Self::register(format, fd)?;
+                match format {
+                    Format::Double => Some((0, Output::Double(self.double(fs)))),
+                    _ => Some((0, Output::Word(self.fgr[fs]))),
+                }
+            }
+            // cvt.s, cvt.d
+            32 | 33 => {
+                let target = if funct == 32 {
+                    Format::Single
+                } else {
+                    Format::Double
+                };
+                if format == target {
+                    return None;
+                }
+                let a = self.operand(format, fs)?;
+                Self::register(target, fd)?;
+                if a.value.is_nan() {
+                    return Some((signaling(a.signaling), target.default_nan()));
+                }
+                Some(round(target, a.value, Ordering::Equal, mode))
+            }
+            // cvt.w
+            36 if float => {
+                let a = self.operand(format, fs)?;
+                let rounded = match mode {
+                    ROUND_NEAREST => a.value.round_ties_even(),
+                    ROUND_ZERO => a.value.trunc(),
+                    ROUND_UP => a.value.ceil(),
+                    _ => a.value.floor(),
+                };
+                if a.value.is_nan() || !(i32::MIN as f64..=i32::MAX as f64).contains(&rounded) {
+                    return Some((INVALID, Output::Word(i32::MAX as u32)));
+                }
+                let cause = if rounded != a.value { INEXACT } else { 0 };
+                Some((cause, Output::Word(rounded as i32 as u32)))
+            }
+            // c.cond, the low bits select the less, equal and unordered relations
+            48..=63 if float => {
+                let (a, b) = (self.operand(format, fs)?, self.operand(format, ft)?);
+                let unordered = a.value.is_nan() || b.value.is_nan();
+                let condition = (funct & 4 != 0 && a.value < b.value)
+                    || (funct & 2 != 0 && a.value == b.value)
+                    || (funct & 1 != 0 && unordered);
+                let invalid = a.signaling || b.signaling || (unordered && funct & 8 != 0);
+                Some((signaling(invalid), Output::Condition(condition)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the exception of an operation on a signaling NaN.
+fn signaling(invalid: bool) -> u32 {
+    match invalid {
+        true => INVALID,
+        false => 0,
+    }
+}
+
+/// Computes add, sub, mul or div of `a` and `b` rounded to the nearest double.
+///
+/// Returns the result, how the exact result compares to it and the exceptions other than the
+/// ones of rounding.
+fn arithmetic(funct: u32, a: Operand, b: Operand) -> (f64, Ordering, u32) {
+    if a.signaling || b.signaling {
+        return (f64::NAN, Ordering::Equal, INVALID);
+    }
+    let (a, b) = (a.value, b.value);
+    if a.is_nan() || b.is_nan() {
+        return (f64::NAN, Ordering::Equal, 0);
+    }
+
+    let value = match funct {
+        0 => a + b,
+        1 => a - b,
+        2 => a * b,
+        _ => a / b,
+    };
+    if value.is_nan() {
+        return (value, Ordering::Equal, INVALID);
+    }
+    if funct == 3 && b == 0.0 {
+        return (value, Ordering::Equal, DIVIDE_BY_ZERO);
+    }
+    if value.is_infinite() {
+        // Infinite operands give an exact infinity, finite ones overflowed
+        let error = match (a.is_finite() && b.is_finite(), value > 0.0) {
+            (false, _) => Ordering::Equal,
+            (true, true) => Ordering::Less,
+            (true, false) => Ordering::Greater,
+        };
+        return (value, error, 0);
+    }
+
+    // The rounding error of a sum and a product is a double, the remainder of a quotient is
+    let error = match funct {
+        0 | 1 => {
+            let b = if funct == 1 { -b } else { b };
+            let b_part = value - a;
+            (a - (value - b_part)) + (b - b_part)
+        }
+        2 => a.mul_add(b, -value),
+        _ => (-value).mul_add(b, a) * b.signum(),
+    };
+    (value, error.partial_cmp(&0.0).unwrap_or(Ordering::Equal), 0)
+}
+
+/// Returns the direction in which to move a result rounded to the nearest value in `mode`,
+/// where the exact result compares as `error` to it.
+fn adjustment(mode: u32, error: Ordering, negative: bool) -> Ordering {
+    match (mode, error) {
+        (ROUND_UP, Ordering::Greater) | (ROUND_DOWN, Ordering::Less) => error,
+        (ROUND_ZERO, Ordering::Greater) if negative => error,
+        (ROUND_ZERO, Ordering::Less) if !negative => error,
+        _ => Ordering::Equal,
+    }
+}
+
+/// Returns the exceptions of rounding an inexact result.
+fn rounding_cause(error: Ordering, infinite: bool, tiny: bool) -> u32 {
+    match error {
+        Ordering::Equal => 0,
+        _ if infinite => OVERFLOW | INEXACT,
+        _ if tiny => UNDERFLOW | INEXACT,
+        _ => INEXACT,
+    }
+}
+
+/// Rounds `value` to `format` in `mode`, where the exact result compares as `error` to `value`.
+fn round(format: Format, value: f64, error: Ordering, mode: u32) -> (u32, Output) {
+    match format {
+        Format::Single => {
+            let nearest = value as f32;
+            let error = match (nearest as f64).partial_cmp(&value) {
+                Some(Ordering::Equal) | None => error,
+                Some(ordering) => ordering.reverse(),
+            };
+            let rounded = match adjustment(mode, error, nearest.is_sign_negative()) {
+                Ordering::Less => nearest.next_down(),
+                Ordering::Greater => nearest.next_up(),
+                Ordering::Equal => nearest,
+            };
+            let tiny = nearest.abs() < f32::MIN_POSITIVE;
+            let cause = rounding_cause(error, nearest.is_infinite(), tiny);
+            (cause, Output::Word(rounded.to_bits()))
+        }
+        _ => {
+            let rounded = match adjustment(mode, error, value.is_sign_negative()) {
+                Ordering::Less => value.next_down(),
+                Ordering::Greater => value.next_up(),
+                Ordering::Equal => value,
+            };
+            let tiny = value.abs() < f64::MIN_POSITIVE;
+            let cause = rounding_cause(error, value.is_infinite(), tiny);
+            (cause, Output::Double(rounded.to_bits()))
+        }
+    }
+}
+
+impl Coprocessor for Fpu {
+    fn read_register(&self, reg: usize) -> u32 {
+        self.fgr[reg]
+    }
+
+    fn write_register(&mut self, reg: usize, value: u32) {
+        self.fgr[reg] = value;
+    }
+
+    fn read_control(&self, reg: usize) -> u32 {
+        match reg {
+            FIR => FIR_VALUE,
+            FCSR => self.fcsr,
+            _ => 0,
+        }
+    }
+
+    fn write_control(&mut self, reg: usize, value: u32) {
+        if reg == FCSR {
+            self.fcsr = value & FCSR_WRITABLE;
+        }
+    }
+
+    fn condition(&self) -> bool {
+        self.fcsr.get_bit(CONDITION_BIT)
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        for value in self.fgr.iter() {
+            state.word(*value);
+        }
+        state.word(self.fcsr);
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> error::Result<()> {
+        for value in self.fgr.iter_mut() {
+            *value = state.word()?;
+        }
+        self.fcsr = state.word()? & FCSR_WRITABLE;
+        Ok(())
+    }
+
+    fn execute(&mut self, instr: Instruction) -> Result<(), Exception> {
+        let fd = instr.shamt() as usize;
+        let (cause, output) = match Format::decode(instr.rs())
+            .and_then(|format| self.operate(format, instr.funct(), instr.rt(), instr.rd(), fd))
+        {
+            Some((cause, output)) => (cause, Some(output)),
+            None => (UNIMPLEMENTED, None),
+        };
+
+        // Every operation replaces the cause field, an enabled exception traps before the
+        // flags and the destination are updated
+        self.fcsr = self.fcsr & !(0x3f << CAUSE_SHIFT) | cause << CAUSE_SHIFT;
+        let enabled = (self.fcsr >> ENABLES_SHIFT) & 0x1f | UNIMPLEMENTED;
+        if cause & enabled != 0 {
+            return Err(Exception::FloatingPoint);
+        }
+        self.fcsr |= cause << FLAGS_SHIFT;
+
+        match output {
+            Some(Output::Word(value)) => self.fgr[fd] = value,
+            Some(Output::Double(value)) => {
+                self.fgr[fd] = value as u32;
+                self.fgr[fd + 1] = (value >> 32) as u32;
+            }
+            Some(Output::Condition(condition)) => {
+                self.fcsr.set_bit(CONDITION_BIT, condition);
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const S: usize = 16;
+    const D: usize = 17;
+    const W: usize = 20;
+
+    /// Encodes the COP1 operation `funct` of `fmt` with the registers `fd`, `fs` and `ft`.
+    fn op(fmt: usize, funct: u32, fd: usize, fs: usize, ft: usize) -> Instruction {
+        Instruction(
+            0x11 << 26
+                | 1 << 25
+                | (fmt as u32) << 21
+                | (ft as u32) << 16
+                | (fs as u32) << 11
+                | (fd as u32) << 6
+                | funct,
+        )
+    }
+
+    fn set_single(fpu: &mut Fpu, reg: usize, value: f32) {
+        fpu.write_register(reg, value.to_bits());
+    }
+
+    fn set_double(fpu: &mut Fpu, reg: usize, value: f64) {
+        fpu.write_register(reg, value.to_bits() as u32);
+        fpu.write_register(reg + 1, (value.to_bits() >> 32) as u32);
+    }
+
+    fn single(fpu: &Fpu, reg: usize) -> f32 {
+        f32::from_bits(fpu.read_register(reg))
+    }
+
+    fn double(fpu: &Fpu, reg: usize) -> f64 {
+        f64::from_bits(fpu.double(reg))
+    }
+
+    fn cause(fpu: &Fpu) -> u32 {
+        (fpu.read_control(FCSR) >> CAUSE_SHIFT) & 0x3f
+    }
+
+    #[test]
+    fn fpu_arithmetic() {
+        let mut fpu = Fpu::new();
+        set_single(&mut fpu, 2, 1.5);
+        set_single(&mut fpu, 4, 2.25);
+        for (funct, expected) in [(0, 3.75), (1, -0.75), (2, 3.375), (3, 1.5 / 2.25)] {
+            fpu.execute(op(S, funct, 0, 2, 4)).unwrap();
+            assert_eq!(single(&fpu, 0), expected);
+        }
+        // 1.5 / 2.25 is inexact
+        assert_eq!(cause(&fpu), INEXACT);
+
+        set_double(&mut fpu, 2, 1e300);
+        set_double(&mut fpu, 4, 1e-300);
+        fpu.execute(op(D, 2, 6, 2, 4)).unwrap();
+        assert_eq!(double(&fpu, 6), 1e300 * 1e-300);
+
+        // Overflow to infinity and division by zero only set the flags while the traps are off
+        fpu.execute(op(D, 3, 6, 2, 4)).unwrap();
+        assert_eq!(double(&fpu, 6), f64::INFINITY);
+        assert_eq!(cause(&fpu), OVERFLOW | INEXACT);
+        set_double(&mut fpu, 4, 0.0);
+        fpu.execute(op(D, 3, 6, 2, 4)).unwrap();
+        assert_eq!(cause(&fpu), DIVIDE_BY_ZERO);
+        assert_eq!(
+            (fpu.read_control(FCSR) >> FLAGS_SHIFT) & 0x1f,
+            OVERFLOW | DIVIDE_BY_ZERO | INEXACT
+        );
+
+        // abs, mov and neg
+        set_double(&mut fpu, 2, -2.5);
+        fpu.execute(op(D, 5, 6, 2, 0)).unwrap();
+        assert_eq!(double(&fpu, 6), 2.5);
+        fpu.execute(op(D, 6, 8, 6, 0)).unwrap();
+        fpu.execute(op(D, 7, 8, 8, 0)).unwrap();
+        assert_eq!(double(&fpu, 8), -2.5);
+    }
+
+    #[test]
+    fn fpu_rounding_modes() {
+        let mut fpu = Fpu::new();
+        set_single(&mut fpu, 2, 1.0);
+        set_single(&mut fpu, 4, 3.0);
+        let third = 1.0_f32 / 3.0;
+        for (mode, expected) in [
+            (ROUND_NEAREST, third),
+            (ROUND_ZERO, third.next_down()),
+            (ROUND_UP, third),
+            (ROUND_DOWN, third.next_down()),
+        ] {
+            fpu.write_control(FCSR, mode);
+            fpu.execute(op(S, 3, 0, 2, 4)).unwrap();
+            assert_eq!(single(&fpu, 0), expected, "mode {}", mode);
+        }
+
+        // Overflow rounds to the largest finite value towards zero
+        set_double(&mut fpu, 2, f64::MAX);
+        fpu.write_control(FCSR, ROUND_ZERO);
+        fpu.execute(op(D, 0, 4, 2, 2)).unwrap();
+        assert_eq!(double(&fpu, 4), f64::MAX);
+        assert_eq!(cause(&fpu), OVERFLOW | INEXACT);
+
+        // A sum whose rounding error is too small to change the nearest double
+        set_single(&mut fpu, 2, 1.0);
+        set_single(&mut fpu, 4, 2f32.powi(-60));
+        fpu.write_control(FCSR, ROUND_NEAREST);
+        fpu.execute(op(S, 0, 0, 2, 4)).unwrap();
+        assert_eq!(single(&fpu, 0), 1.0);
+        assert_eq!(cause(&fpu), INEXACT);
+        fpu.write_control(FCSR, ROUND_UP);
+        fpu.execute(op(S, 0, 0, 2, 4)).unwrap();
+        assert_eq!(single(&fpu, 0), 1.0_f32.next_up());
+
+        // cvt.w honors the rounding mode and saturates invalid conversions
+        set_double(&mut fpu, 2, -2.5);
+        for (mode, expected) in [
+            (ROUND_NEAREST, -2),
+            (ROUND_ZERO, -2),
+            (ROUND_UP, -2),
+            (ROUND_DOWN, -3),
+        ] {
+            fpu.write_control(FCSR, mode);
+            fpu.execute(op(D, 36, 0, 2, 0)).unwrap();
+            assert_eq!(fpu.read_register(0) as i32, expected, "mode {}", mode);
+        }
+        set_double(&mut fpu, 2, 3e9);
+        fpu.execute(op(D, 36, 0, 2, 0)).unwrap();
+        assert_eq!(fpu.read_register(0), 0x7fff_ffff);
+        assert_eq!(cause(&fpu), INVALID);
+    }
+
+    #[test]
+    fn fpu_conversions_and_compares() {
+        let mut fpu = Fpu::new();
+        fpu.write_register(2, -7_i32 as u32);
+        fpu.execute(op(W, 33, 4, 2, 0)).unwrap();
+        assert_eq!(double(&fpu, 4), -7.0);
+        fpu.execute(op(D, 32, 6, 4, 0)).unwrap();
+        assert_eq!(single(&fpu, 6), -7.0);
+        fpu.execute(op(S, 33, 8, 6, 0)).unwrap();
+        assert_eq!(double(&fpu, 8), -7.0);
+
+        // c.lt.d, c.eq.d and c.un.d
+        set_double(&mut fpu, 2, 1.0);
+        fpu.execute(op(D, 60, 0, 4, 2)).unwrap();
+        assert!(fpu.condition());
+        fpu.execute(op(D, 50, 0, 4, 2)).unwrap();
+        assert!(!fpu.condition());
+        set_double(&mut fpu, 2, f64::from_bits(DOUBLE_DEFAULT_NAN));
+        fpu.execute(op(D, 49, 0, 4, 2)).unwrap();
+        assert!(fpu.condition());
+        assert_eq!(cause(&fpu), 0);
+
+        // c.lt.d signals on unordered operands, a signaling NaN propagates as the default NaN
+        fpu.execute(op(D, 60, 0, 4, 2)).unwrap();
+        assert_eq!(cause(&fpu), INVALID);
+        fpu.write_register(2, 0x7fc0_0000);
+        fpu.execute(op(S, 0, 4, 2, 2)).unwrap();
+        assert_eq!(fpu.read_register(4), SINGLE_DEFAULT_NAN);
+        assert_eq!(cause(&fpu), INVALID);
+    }
+
+    #[test]
+    fn fpu_traps() {
+        let mut fpu = Fpu::new();
+        assert_eq!(fpu.read_control(FIR), FIR_VALUE);
+        set_single(&mut fpu, 2, 1.0);
+        set_single(&mut fpu, 0, 5.0);
+
+        // An enabled exception leaves the destination and the flags alone
+        fpu.write_control(FCSR, DIVIDE_BY_ZERO << ENABLES_SHIFT);
+        assert_eq!(
+            fpu.execute(op(S, 3, 0, 2, 4)),
+            Err(Exception::FloatingPoint)
+        );
+        assert_eq!(single(&fpu, 0), 5.0);
+        assert_eq!(
+            fpu.read_control(FCSR),
+            DIVIDE_BY_ZERO << ENABLES_SHIFT | DIVIDE_BY_ZERO << CAUSE_SHIFT
+        );
+
+        // Unimplemented operations and odd double registers always trap
+        for instr in [op(W, 0, 0, 2, 4), op(D, 0, 0, 3, 4), op(S, 32, 0, 2, 0)] {
+            assert_eq!(fpu.execute(instr), Err(Exception::FloatingPoint));
+            assert_eq!(cause(&fpu), UNIMPLEMENTED);
+        }
+    }
+}
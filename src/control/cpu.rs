@@ -3,17 +3,22 @@ use std::fmt;
 use capstone::prelude::*;
 use log::{error, warn};
 
+use crate::control::cache::IsolatedCache;
+use crate::control::coprocessor::Coprocessor;
 use crate::control::cpzero::CPZero;
 use crate::control::exception::Exception;
 use crate::control::instruction::Instruction;
+use crate::control::isa::Op;
 use crate::control::registers::Register;
-use crate::memory::Memory;
+use crate::memory::{AccessContext, Memory};
 use crate::util::error::{Result, RmipsError};
-use crate::Address;
+use crate::util::state::{StateReader, StateWriter};
+use crate::{Address, HaltReason};
 
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd)]
 pub enum DelayState {
     /// No delay slot handling needs to occur
+    #[default]
     Normal,
     /// The last instruction caused a branch to be taken
     Delaying,
@@ -21,9 +26,77 @@ pub enum DelayState {
     Delayslot,
 }
 
-impl Default for DelayState {
-    fn default() -> Self {
-        DelayState::Normal
+/// Accumulators ac1-ac3 and the DSPControl register of the MIPS DSP ASE.
+///
+/// The DSP instructions are not emulated, the registers only hold what the debugger writes so the
+/// register file of GDB's MIPS + DSP target is backed by real state. Accumulator ac0 is HI/LO.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DspRegisters {
+    pub high: [u32; 3],
+    pub low: [u32; 3],
+    pub control: u32,
+}
+
+/// The architectural state of a `Cpu`, which can be copied into another `Cpu`.
+///
+/// Attached coprocessors are not part of the state.
+#[derive(Clone, Copy, Debug)]
+pub struct CpuState {
+    pub pc: Address,
+    pub reg: [u32; 32],
+    pub instruction: Instruction,
+    pub high: u32,
+    pub low: u32,
+    pub delay_state: DelayState,
+    pub delay_pc: Address,
+    pub exception_pending: bool,
+    pub cpzero: CPZero,
+    pub dsp: DspRegisters,
+}
+
+/// Cycles from the assertion of an interrupt line to the first instruction of its handler, over
+/// the interrupts taken in a run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterruptLatency {
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub total: u64,
+}
+
+impl InterruptLatency {
+    fn new(cycles: u64) -> Self {
+        Self {
+            count: 1,
+            min: cycles,
+            max: cycles,
+            total: cycles,
+        }
+    }
+
+    fn record(&mut self, cycles: u64) {
+        self.count += 1;
+        self.min = self.min.min(cycles);
+        self.max = self.max.max(cycles);
+        self.total += cycles;
+    }
+
+    /// Returns the mean latency in cycles.
+    pub fn average(&self) -> f64 {
+        self.total as f64 / self.count as f64
+    }
+}
+
+impl fmt::Display for InterruptLatency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Interrupt latency: min {} / avg {:.1} / max {} cycles over {} interrupts",
+            self.min,
+            self.average(),
+            self.max,
+            self.count
+        )
     }
 }
 
@@ -35,6 +108,9 @@ pub struct Cpu {
     pub reg: [u32; 32],
     /// The current instruction.
     pub instruction: Instruction,
+    /// Indicates whether the last step fetched `instruction`, which it does not when it takes
+    /// an interrupt or an exception on the fetch.
+    pub fetched: bool,
     /// High division result register.
     pub high: u32,
     /// Low division result register.
@@ -47,8 +123,23 @@ pub struct Cpu {
     pub exception_pending: bool,
     /// The System Control Coprocessor (CP0).
     pub cpzero: CPZero,
+    /// DSP ASE accumulators, only accessed by the debugger.
+    pub dsp: DspRegisters,
+    /// Stop emulation with an error instead of raising an address exception when
+    /// user mode accesses a kernel segment.
+    pub privilege_errors: bool,
+    /// Coprocessors attached to the CP1-CP3 slots. Slot zero is unused since CP0 is always present.
+    coprocessors: [Option<Box<dyn Coprocessor>>; 4],
     /// Capstone instance for disassembly.
     disassembler: Option<Capstone>,
+    /// Number of calls to `step`, which times the interrupt latency.
+    cycles: u64,
+    /// Cause IP bits pending in the last step.
+    interrupt_lines: u32,
+    /// Cycle in which each Cause IP bit was asserted, until an interrupt is taken for it.
+    interrupt_asserted: [Option<u64>; 8],
+    /// Latency of the interrupts taken so far.
+    pub interrupt_latency: Option<InterruptLatency>,
 }
 
 impl Cpu {
@@ -74,18 +165,202 @@ impl Cpu {
     pub fn reset(&mut self) {
         self.reg[Register::Zero] = 0;
         self.pc = 0xbfc00000;
+        self.delay_state = DelayState::Normal;
+        self.exception_pending = false;
         self.cpzero.reset();
     }
 
+    /// Returns a copy of the architectural state.
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            reg: self.reg,
+            instruction: self.instruction,
+            high: self.high,
+            low: self.low,
+            delay_state: self.delay_state,
+            delay_pc: self.delay_pc,
+            exception_pending: self.exception_pending,
+            cpzero: self.cpzero,
+            dsp: self.dsp,
+        }
+    }
+
+    /// Replaces the architectural state with `state`.
+    pub fn restore_state(&mut self, state: &CpuState) {
+        self.pc = state.pc;
+        self.reg = state.reg;
+        self.instruction = state.instruction;
+        self.high = state.high;
+        self.low = state.low;
+        self.delay_state = state.delay_state;
+        self.delay_pc = state.delay_pc;
+        self.exception_pending = state.exception_pending;
+        self.cpzero = state.cpzero;
+        self.dsp = state.dsp;
+    }
+
+    /// Writes the architectural state and the registers of the attached coprocessors to a
+    /// machine snapshot.
+    pub fn save(&self, state: &mut StateWriter) {
+        state.word(self.pc);
+        for value in self.reg.iter() {
+            state.word(*value);
+        }
+        state.word(self.instruction.0);
+        state.word(self.high);
+        state.word(self.low);
+        state.word(self.delay_state as u32);
+        state.word(self.delay_pc);
+        state.bool(self.exception_pending);
+        let dsp = self.dsp.high.iter().chain(self.dsp.low.iter());
+        for value in dsp.chain(std::iter::once(&self.dsp.control)) {
+            state.word(*value);
+        }
+        self.cpzero.save(state);
+
+        for coprocessor in &self.coprocessors[1..] {
+            state.bool(coprocessor.is_some());
+            if let Some(coprocessor) = coprocessor {
+                coprocessor.save(state);
+            }
+        }
+    }
+
+    /// Restores the state written by `save` into a `Cpu` with the same coprocessors attached.
+    pub fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.pc = state.word()?;
+        for value in self.reg.iter_mut() {
+            *value = state.word()?;
+        }
+        self.instruction = Instruction(state.word()?);
+        self.high = state.word()?;
+        self.low = state.word()?;
+        self.delay_state = match state.word()? {
+            0 => DelayState::Normal,
+            1 => DelayState::Delaying,
+            2 => DelayState::Delayslot,
+            other => {
+                return Err(RmipsError::Snapshot(format!(
+                    "unknown delay state {}",
+                    other
+                )))
+            }
+        };
+        self.delay_pc = state.word()?;
+        self.exception_pending = state.bool()?;
+        let dsp = self.dsp.high.iter_mut().chain(self.dsp.low.iter_mut());
+        for value in dsp.chain(std::iter::once(&mut self.dsp.control)) {
+            *value = state.word()?;
+        }
+        self.cpzero.restore(state)?;
+        // The snapshot does not record when the pending lines were asserted
+        self.interrupt_lines = 0;
+        self.interrupt_asserted = Default::default();
+
+        for (slot, coprocessor) in self.coprocessors.iter_mut().enumerate().skip(1) {
+            let saved = state.bool()?;
+            let missing = match (coprocessor, saved) {
+                (Some(coprocessor), true) => {
+                    coprocessor.restore(state)?;
+                    continue;
+                }
+                (None, false) => continue,
+                (Some(_), false) => "in the snapshot",
+                (None, true) => "attached",
+            };
+            return Err(RmipsError::Snapshot(format!(
+                "coprocessor {} is not {}",
+                slot, missing
+            )));
+        }
+        Ok(())
+    }
+
+    /// Stamps the Cause IP bits that were asserted since the last step with the current cycle.
+    fn stamp_interrupts(&mut self) {
+        let pending = self.cpzero.cause.get_interrupt_pending();
+        for (line, asserted) in self.interrupt_asserted.iter_mut().enumerate() {
+            let bit = 1 << line;
+            if pending & bit == 0 {
+                *asserted = None;
+            } else if self.interrupt_lines & bit == 0 {
+                *asserted = Some(self.cycles);
+            }
+        }
+        self.interrupt_lines = pending;
+    }
+
+    /// Records the latency of the interrupt that is being taken, counted from the earliest of the
+    /// requesting lines. The handler starts in the next cycle.
+    fn record_interrupt_latency(&mut self) {
+        let requesting =
+            self.cpzero.cause.get_interrupt_pending() & self.cpzero.status.get_interrupt_mask();
+        let asserted = self
+            .interrupt_asserted
+            .iter_mut()
+            .enumerate()
+            .filter(|(line, _)| requesting & 1 << line != 0)
+            .filter_map(|(_, asserted)| asserted.take())
+            .min();
+
+        if let Some(asserted) = asserted {
+            let cycles = self.cycles + 1 - asserted;
+            match &mut self.interrupt_latency {
+                Some(latency) => latency.record(cycles),
+                None => self.interrupt_latency = Some(InterruptLatency::new(cycles)),
+            }
+        }
+    }
+
+    /// Forgets the interrupt latency measured so far.
+    pub fn reset_interrupt_latency(&mut self) {
+        self.interrupt_lines = 0;
+        self.interrupt_asserted = Default::default();
+        self.interrupt_latency = None;
+    }
+
     /// Decodes and executes the next instruction according to the value in the program counter
     pub fn step(&mut self, memory: &mut impl Memory) -> Result<()> {
         self.exception_pending = false;
+        self.fetched = false;
+
+        // Interrupts are taken between instructions, but not in a delay slot where the
+        // exception would lose the branch
+        self.cycles += 1;
+        self.cpzero
+            .set_hardware_interrupts(memory.interrupt_lines());
+        self.stamp_interrupts();
+        if self.cpzero.interrupt_requested() && self.delay_state != DelayState::Delayslot {
+            self.record_interrupt_latency();
+            self.exception(Exception::Interrupt)?;
+            return Ok(());
+        }
+
+        // Instruction fetches from an address that is not word-aligned raise an address error
+        if !self.pc.is_multiple_of(4) {
+            self.address_error(Exception::AddressLoadError, self.pc)?;
+            self.delay_state = DelayState::Normal;
+            return Ok(());
+        }
 
         // Get the physical address of the next instruction
-        let phys_pc = self.cpzero.translate(self.pc);
+        let Some(phys_pc) = self.translate(self.pc, AccessContext::CpuFetch)? else {
+            self.delay_state = DelayState::Normal;
+            return Ok(());
+        };
 
         // Fetch the next instruction from memory
-        self.instruction = Instruction(memory.fetch_word(phys_pc)?);
+        // Fetches from devices that do not hold code fail with a bus error
+        self.instruction = match memory.fetch_instruction(phys_pc) {
+            Ok(word) => Instruction(word),
+            Err(err) if err.is_bus_error() => {
+                self.exception(Exception::InstructionBusError)?;
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+        self.fetched = true;
 
         // Disassemble the instruction if enabled by the user
         if let Some(disassembler) = &self.disassembler {
@@ -117,104 +392,21 @@ impl Cpu {
         }
 
         // Decode and emulate the instruction
-        let instr = self.instruction;
-        match instr.opcode() {
-            0x00 => match instr.funct() {
-                0x00 => self.sll_emulate(instr),
-                0x02 => self.srl_emulate(instr),
-                0x03 => self.sra_emulate(instr),
-                0x04 => self.sllv_emulate(instr),
-                0x06 => self.srlv_emulate(instr),
-                0x07 => self.srav_emulate(instr),
-                0x08 => self.jr_emulate(instr),
-                0x09 => self.jalr_emulate(instr),
-                0x0c => self.syscall_emulate()?,
-                0x0d => self.break_emulate()?,
-                0x10 => self.mfhi_emulate(instr),
-                0x11 => self.mthi_emulate(instr),
-                0x12 => self.mflo_emulate(instr),
-                0x13 => self.mtlo_emulate(instr),
-                0x18 => self.mult_emulate(instr),
-                0x19 => self.multu_emulate(instr),
-                0x1a => self.div_emulate(instr),
-                0x1b => self.divu_emulate(instr),
-                0x20 => self.add_emulate(instr)?,
-                0x21 => self.addu_emulate(instr),
-                0x22 => self.sub_emulate(instr)?,
-                0x23 => self.subu_emulate(instr),
-                0x24 => self.and_emulate(instr),
-                0x25 => self.or_emulate(instr),
-                0x26 => self.xor_emulate(instr),
-                0x27 => self.nor_emulate(instr),
-                0x2a => self.slt_emulate(instr),
-                0x2b => self.sltu_emulate(instr),
-                _ => self.ri_emulate()?,
-            },
-            0x01 => match instr.rt() {
-                0 => self.bltz_emulate(instr),
-                1 => self.bgez_emulate(instr),
-                16 => self.bltzal_emulate(instr),
-                17 => self.bgezal_emulate(instr),
-                _ => self.ri_emulate()?,
-            },
-            0x02 => self.j_emulate(instr),
-            0x03 => self.jal_emulate(instr),
-            0x04 => self.beq_emulate(instr),
-            0x05 => self.bne_emulate(instr),
-            0x06 => self.blez_emulate(instr),
-            0x07 => self.bgtz_emulate(instr),
-            0x08 => self.addi_emulate(instr)?,
-            0x09 => self.addiu_emulate(instr),
-            0x0a => self.slti_emulate(instr),
-            0x0b => self.sltiu_emulate(instr),
-            0x0c => self.andi_emulate(instr),
-            0x0d => self.ori_emulate(instr),
-            0x0e => self.xori_emulate(instr),
-            0x0f => self.lui_emulate(instr),
-            0x10 => {
-                // Handle CP0 instructions
-                let rs = instr.rs();
-
-                if 15 < rs {
-                    match instr.funct() {
-                        1 => self.cpzero.tlbr_emulate(),
-                        2 => self.cpzero.tlbwi_emulate(),
-                        6 => self.cpzero.tlbwr_emulate(),
-                        8 => self.cpzero.tlbp_emulate(),
-                        16 => self.cpzero.rfe_emulate(),
-                        _ => self.exception(Exception::ReservedInstruction)?,
-                    }
-                } else {
-                    match rs {
-                        0 => self.mfc0_emulate(instr),
-                        4 => self.mtc0_emulate(instr),
-                        8 => self.cpzero.bc0x_emulate(instr, self.pc),
-                        _ => self.exception(Exception::ReservedInstruction)?,
-                    }
-                }
+        // Bus errors from loads and stores are reported to the program like on real hardware,
+        // including accesses that run past the end of a device or use a width it does not support
+        // Loads and stores only reach the cache while it is isolated
+        let model = self.cpzero.model();
+        let result = match self.cpzero.status.isc() && model.isolates_caches() {
+            true => {
+                let swapped = self.cpzero.status.swc();
+                let mut cache = IsolatedCache::new(memory, swapped, model.icache_line());
+                self.execute(&mut cache, self.instruction)
             }
-            0x11 => self.coprocessor_unimpl(1, instr)?,
-            0x12 => self.coprocessor_unimpl(2, instr)?,
-            0x13 => self.coprocessor_unimpl(3, instr)?,
-            0x20 => self.lb_emulate(memory, instr)?,
-            0x21 => self.lh_emulate(memory, instr)?,
-            0x22 => self.lwl_emulate(instr),
-            0x23 => self.lw_emulate(memory, instr)?,
-            0x24 => self.lbu_emulate(memory, instr)?,
-            0x25 => self.lhu_emulate(memory, instr)?,
-            0x26 => self.lwr_emulate(instr),
-            0x28 => self.sb_emulate(memory, instr)?,
-            0x29 => self.sh_emulate(memory, instr)?,
-            0x2a => self.swl_emulate(instr),
-            0x2b => self.sw_emulate(memory, instr)?,
-            0x2e => self.swr_emulate(instr),
-            0x31 => self.lwc1_emulate(instr)?,
-            0x32 => self.lwc2_emulate(instr)?,
-            0x33 => self.lwc3_emulate(instr)?,
-            0x38 => self.swc1_emulate(instr)?,
-            0x39 => self.swc2_emulate(instr)?,
-            0x3a => self.swc3_emulate(instr)?,
-            _ => self.ri_emulate()?,
+            false => self.execute(memory, self.instruction),
+        };
+        match result {
+            Err(err) if err.is_bus_error() => self.exception(Exception::DataBusError)?,
+            result => result?,
         }
 
         // Register $r0 is hardwired to a value of zero
@@ -249,6 +441,23 @@ impl Cpu {
         Ok(())
     }
 
+    /// Decodes and executes a single instruction.
+    pub(crate) fn execute(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        match Op::decode(instr) {
+            Some(op) if op.spec().isa <= self.cpzero.model().isa() => {
+                self.dispatch(op, memory, instr)
+            }
+            Some(_) => self.ri_emulate(),
+            // CP0 must be accessible before an encoding is found to be reserved
+            None if instr.opcode() == 0x10 && !self.cpzero.cp0_accessible() => {
+                self.coprocessor_unusable(0)
+            }
+            // The attached coprocessor decodes the instructions that the table does not list
+            None if instr.opcode() == 0x11 => self.copz_emulate(1, instr),
+            None => self.ri_emulate(),
+        }
+    }
+
     pub fn coprocessor_unimpl(&mut self, coprocno: u32, instr: Instruction) -> Result<()> {
         if self.cpzero.coprocessor_usable(coprocno) {
             error!(
@@ -259,18 +468,97 @@ impl Cpu {
             )
         }
 
-        self.exception(Exception::CoprocessorUnusable)
+        self.coprocessor_unusable(coprocno)
+    }
+
+    /// Attaches a coprocessor to one of the CP1-CP3 slots.
+    pub fn attach_coprocessor(&mut self, coprocno: u32, coprocessor: Box<dyn Coprocessor>) {
+        assert!(
+            (1..=3).contains(&coprocno),
+            "Invalid coprocessor number {}",
+            coprocno
+        );
+        self.coprocessors[coprocno as usize] = Some(coprocessor);
+    }
+
+    /// Returns the given coprocessor if it is attached and enabled in the Status register.
+    pub fn coprocessor_mut(&mut self, coprocno: u32) -> Option<&mut Box<dyn Coprocessor>> {
+        if self.cpzero.coprocessor_usable(coprocno) {
+            self.coprocessors[coprocno as usize].as_mut()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the given coprocessor if it is attached, even while it is disabled, for debugger
+    /// accesses.
+    pub fn attached_coprocessor(&self, coprocno: u32) -> Option<&dyn Coprocessor> {
+        self.coprocessors[coprocno as usize].as_deref()
+    }
+
+    /// Returns the given coprocessor mutably if it is attached, even while it is disabled, for
+    /// debugger accesses.
+    pub fn attached_coprocessor_mut(&mut self, coprocno: u32) -> Option<&mut dyn Coprocessor> {
+        match &mut self.coprocessors[coprocno as usize] {
+            Some(coprocessor) => Some(coprocessor.as_mut()),
+            None => None,
+        }
+    }
+
+    /// Raises a Coprocessor Unusable exception for the given coprocessor number.
+    pub fn coprocessor_unusable(&mut self, coprocno: u32) -> Result<()> {
+        self.exception(Exception::CoprocessorUnusable)?;
+
+        // Record the coprocessor that was referenced in the CE field of the Cause register
+        self.cpzero.cause.set_coprocessor_error(coprocno);
+        Ok(())
+    }
+
+    /// Translates `vaddress` for an access by the `Cpu`, enforcing the segment privileges.
+    ///
+    /// Returns `None` if the access raised an address or TLB exception instead.
+    pub fn translate(&mut self, vaddress: Address, ctx: AccessContext) -> Result<Option<Address>> {
+        match self.cpzero.translate_access(vaddress, ctx) {
+            Ok(paddress) => Ok(Some(paddress)),
+            Err(
+                exception @ (Exception::TLBModification
+                | Exception::TLBLoadMiss
+                | Exception::TLBStoreMiss),
+            ) => {
+                self.cpzero.tlb_miss_user =
+                    exception != Exception::TLBModification && self.cpzero.tlb_refill(vaddress);
+                self.tlb_exception(exception, vaddress)?;
+                Ok(None)
+            }
+            Err(_) if self.privilege_errors => Err(RmipsError::PrivilegeViolation(vaddress)),
+            Err(exception) => {
+                self.address_error(exception, vaddress)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Raises an address error exception and records the offending virtual address in BadVaddr.
+    pub fn address_error(&mut self, exception: Exception, vaddress: Address) -> Result<()> {
+        self.cpzero.badvaddr.address = vaddress;
+        self.exception(exception)
+    }
+
+    /// Raises a TLB exception, recording the faulting address in BadVaddr, Context and EntryHi.
+    pub fn tlb_exception(&mut self, exception: Exception, vaddress: Address) -> Result<()> {
+        self.cpzero.tlb_fault(vaddress);
+        self.exception(exception)
     }
 
     pub fn exception(&mut self, exception: Exception) -> Result<()> {
         match exception {
             Exception::InstructionBusError => {
                 warn!("Instruction bus error occurred");
-                return Err(RmipsError::Halt);
+                return Err(RmipsError::Halt(HaltReason::InstructionBusError));
             }
             Exception::Breakpoint => {
                 warn!("BREAK instruction reached");
-                return Err(RmipsError::Halt);
+                return Err(RmipsError::Halt(HaltReason::Break));
             }
             Exception::ReservedInstruction => warn!(
                 "Encountered a reserved instruction:\n{:?}",
@@ -330,22 +618,372 @@ impl fmt::Display for Cpu {
 
         for i in (0..32).step_by(4) {
             output = format!(
-                "{}\n{}",
+                "{}\n{} = {:>#10x} {} = {:>#10x} {} = {:>#10x} {} = {:>#10x}",
                 output,
-                format!(
-                    "{} = {:>#10x} {} = {:>#10x} {} = {:>#10x} {} = {:>#10x}",
-                    abi[i],
-                    self.reg[i],
-                    abi[i + 1],
-                    self.reg[i + 1],
-                    abi[i + 2],
-                    self.reg[i + 2],
-                    abi[i + 3],
-                    self.reg[i + 3],
-                )
+                abi[i],
+                self.reg[i],
+                abi[i + 1],
+                self.reg[i + 1],
+                abi[i + 2],
+                self.reg[i + 2],
+                abi[i + 3],
+                self.reg[i + 3],
             );
         }
 
         write!(f, "{}", output)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::bus::Bus;
+    use crate::memory::ram::Ram;
+    use pretty_assertions::assert_eq;
+
+    fn setup_bus(program: &[u32]) -> Bus {
+        let mut bus = Bus::new();
+        bus.register(Box::new(Ram::new(0x1000)), 0, 0x1000)
+            .expect("failed to map RAM");
+
+        for (i, instr) in program.iter().enumerate() {
+            bus.store_word(i as Address * 4, *instr)
+                .expect("failed to write program");
+        }
+        bus
+    }
+
+    /// Maps the page at `vaddress` to the same physical address in every address space.
+    fn map_page(cpu: &mut Cpu, index: u32, vaddress: Address) {
+        cpu.cpzero.write_index(index << 8);
+        cpu.cpzero.entryhi = vaddress & 0xffff_f000;
+        cpu.cpzero.entrylo = (vaddress & 0xffff_f000) | 0x700; // Dirty, valid and global
+        cpu.cpzero.tlbwi_emulate();
+    }
+
+    #[test]
+    fn step_misaligned_pc() -> Result<()> {
+        let mut bus = setup_bus(&[]);
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        cpu.pc = 0xa000_0002;
+
+        cpu.step(&mut bus)?;
+
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::AddressLoadError
+        );
+        assert_eq!(cpu.cpzero.badvaddr.address, 0xa000_0002);
+        assert_eq!(cpu.cpzero.epc.address, 0xa000_0002);
+        assert_eq!(cpu.pc, 0xbfc0_0180);
+        Ok(())
+    }
+
+    #[test]
+    fn step_jr_to_misaligned_address() -> Result<()> {
+        // jr $a0; nop
+        let mut bus = setup_bus(&[0x0080_0008, 0x0000_0000]);
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        cpu.pc = 0xa000_0000;
+        cpu.reg[Register::A0] = 0xa000_0101;
+
+        // Execute the jump and its delay slot
+        cpu.step(&mut bus)?;
+        cpu.step(&mut bus)?;
+        assert_eq!(cpu.pc, 0xa000_0101);
+        assert_eq!(cpu.exception_pending, false);
+
+        // The fetch from the misaligned target faults
+        cpu.step(&mut bus)?;
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::AddressLoadError
+        );
+        assert_eq!(cpu.cpzero.cause.is_branch_delay(), false);
+        assert_eq!(cpu.cpzero.badvaddr.address, 0xa000_0101);
+        assert_eq!(cpu.cpzero.epc.address, 0xa000_0101);
+        assert_eq!(cpu.delay_state, DelayState::Normal);
+        Ok(())
+    }
+
+    #[test]
+    fn step_load_past_device_end() -> Result<()> {
+        let mut bus = setup_bus(&[0x8c85_0000]); // lw $a1, 0($a0)
+        bus.store_word(0x80, 0x3408_0007)?; // ori $t0, $zero, 7
+        bus.register(Box::new(Ram::new(6)), 0x1000, 6)?;
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        cpu.pc = 0x8000_0000;
+        cpu.cpzero.status = 0.into(); // Kernel mode with BEV clear
+        cpu.reg[Register::A0] = 0x8000_1004;
+
+        // The word ends two bytes past the device, so the program takes a bus error
+        cpu.step(&mut bus)?;
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::DataBusError
+        );
+        assert_eq!(cpu.pc, 0x8000_0080);
+
+        cpu.step(&mut bus)?;
+        assert_eq!(cpu.reg[Register::T0], 7);
+        assert_eq!(cpu.reg[Register::A1], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn step_reverse_endian_user_mode() -> Result<()> {
+        let mut bus = setup_bus(&[
+            0xa005_0100, // sb $a1, 0x100($zero)
+            0x9006_0100, // lbu $a2, 0x100($zero)
+            0xa405_0104, // sh $a1, 0x104($zero)
+            0x9407_0104, // lhu $a3, 0x104($zero)
+            0xac05_0108, // sw $a1, 0x108($zero)
+            0x8008_0108, // lb $t0, 0x108($zero)
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        map_page(&mut cpu, 0, 0);
+        cpu.pc = 0;
+        cpu.cpzero.status = 0x0200_0002.into(); // User mode with RE set
+        cpu.reg[Register::A1] = 0x1234_5678;
+
+        for _ in 0..6 {
+            cpu.step(&mut bus)?;
+        }
+
+        assert_eq!(cpu.exception_pending, false);
+        assert_eq!(cpu.reg[Register::A2], 0x78);
+        assert_eq!(cpu.reg[Register::A3], 0x5678);
+        assert_eq!(cpu.reg[Register::T0], 0x12);
+
+        // Sub-word stores land in the opposite byte lanes of the little-endian bus
+        assert_eq!(bus.fetch_byte(0x103)?, 0x78);
+        assert_eq!(bus.fetch_halfword(0x106)?, 0x5678);
+        assert_eq!(bus.fetch_word(0x108)?, 0x1234_5678);
+        Ok(())
+    }
+
+    #[test]
+    fn step_reverse_endian_ignored_in_kernel_mode() -> Result<()> {
+        // lb $t0, 0x108($zero)
+        let mut bus = setup_bus(&[0x8008_0108]);
+        bus.store_word(0x108, 0x1234_5678)?;
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        map_page(&mut cpu, 0, 0);
+        cpu.pc = 0x8000_0000;
+        cpu.cpzero.status = 0x0200_0000.into(); // Kernel mode with RE set
+
+        cpu.step(&mut bus)?;
+
+        assert_eq!(cpu.reg[Register::T0], 0x78);
+        Ok(())
+    }
+
+    #[test]
+    fn step_cp0_from_user_mode() -> Result<()> {
+        for instr in &[
+            0x4004_6000, // mfc0 $a0, $12
+            0x4084_6000, // mtc0 $a0, $12
+            0x4200_0002, // tlbwi
+            0x4200_0010, // rfe
+        ] {
+            let mut bus = setup_bus(&[*instr]);
+            let mut cpu = Cpu::new(false);
+            cpu.reset();
+            map_page(&mut cpu, 0, 0);
+            cpu.pc = 0;
+            cpu.cpzero.status = 0x0000_0002.into(); // User mode with CU0 clear
+            cpu.reg[Register::A0] = 0xdead_beef;
+
+            cpu.step(&mut bus)?;
+
+            assert_eq!(cpu.exception_pending, true);
+            assert_eq!(
+                cpu.cpzero.cause.get_exception_code(),
+                Exception::CoprocessorUnusable
+            );
+            assert_eq!(cpu.cpzero.cause.get_coprocessor_error(), 0);
+            assert_eq!(cpu.cpzero.epc.address, 0);
+            assert_eq!(cpu.reg[Register::A0], 0xdead_beef);
+            assert_eq!(cpu.cpzero.kernel_mode(), true);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn step_interrupt_pending_across_rfe() -> Result<()> {
+        // jr $k0 with rfe in its delay slot, and an rfe that is not in a delay slot
+        for (program, resume) in &[
+            (&[0x0340_0008, 0x4200_0010][..], 0x8000_0100),
+            (&[0x4200_0010][..], 0x8000_0004),
+        ] {
+            let mut bus = setup_bus(program);
+            let mut cpu = Cpu::new(false);
+            cpu.reset();
+            cpu.pc = 0x8000_0000;
+            cpu.reg[Register::K0] = 0x8000_0100;
+            // The handler runs with interrupts disabled and the software interrupt still pending
+            cpu.cpzero.status = 0x0040_0104.into();
+            cpu.cpzero.cause.write(0x100);
+
+            for _ in 0..program.len() {
+                cpu.step(&mut bus)?;
+                assert_eq!(cpu.exception_pending, false);
+            }
+            assert_eq!(cpu.cpzero.interrupts_enabled(), true);
+            assert_eq!(cpu.pc, *resume);
+
+            // The interrupt is taken before the first instruction after the return
+            cpu.step(&mut bus)?;
+            assert_eq!(cpu.exception_pending, true);
+            assert_eq!(cpu.cpzero.cause.get_exception_code(), Exception::Interrupt);
+            assert_eq!(cpu.cpzero.epc.address, *resume);
+            assert_eq!(cpu.pc, 0xbfc0_0180);
+            assert_eq!(cpu.cpzero.interrupts_enabled(), false);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn step_cp0_from_user_mode_with_cu0() -> Result<()> {
+        // mfc0 $a0, $12
+        let mut bus = setup_bus(&[0x4004_6000]);
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        map_page(&mut cpu, 0, 0);
+        cpu.pc = 0;
+        cpu.cpzero.status = 0x1000_0002.into(); // User mode with CU0 set
+
+        cpu.step(&mut bus)?;
+
+        assert_eq!(cpu.exception_pending, false);
+        assert_eq!(cpu.reg[Register::A0], 0x1000_0002);
+        Ok(())
+    }
+
+    #[test]
+    fn step_user_mode_kernel_accesses() -> Result<()> {
+        for (instr, exception) in &[
+            (0x8c85_0000, Exception::AddressLoadError), // lw $a1, 0($a0)
+            (0xac85_0000, Exception::AddressStoreError), // sw $a1, 0($a0)
+            (0x9085_0000, Exception::AddressLoadError), // lbu $a1, 0($a0)
+        ] {
+            let mut bus = setup_bus(&[*instr]);
+            let mut cpu = Cpu::new(false);
+            cpu.reset();
+            map_page(&mut cpu, 0, 0);
+            cpu.pc = 0;
+            cpu.cpzero.status = 0x0000_0002.into(); // User mode
+            cpu.reg[Register::A0] = 0x8000_0100;
+            cpu.reg[Register::A1] = 0x1234_5678;
+
+            cpu.step(&mut bus)?;
+
+            assert_eq!(cpu.exception_pending, true);
+            assert_eq!(cpu.cpzero.cause.get_exception_code(), *exception);
+            assert_eq!(cpu.cpzero.badvaddr.address, 0x8000_0100);
+            assert_eq!(bus.fetch_word(0x100)?, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn step_user_mode_kernel_fetch() -> Result<()> {
+        let mut bus = setup_bus(&[]);
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        cpu.pc = 0x8000_0000;
+        cpu.cpzero.status = 0x0000_0002.into(); // User mode
+
+        cpu.step(&mut bus)?;
+
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::AddressLoadError
+        );
+        assert_eq!(cpu.cpzero.badvaddr.address, 0x8000_0000);
+        assert_eq!(cpu.cpzero.epc.address, 0x8000_0000);
+        Ok(())
+    }
+
+    #[test]
+    fn step_user_mode_kernel_access_as_error() {
+        // lw $a1, 0($a0)
+        let mut bus = setup_bus(&[0x8c85_0000]);
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        map_page(&mut cpu, 0, 0);
+        cpu.pc = 0;
+        cpu.cpzero.status = 0x0000_0002.into(); // User mode
+        cpu.privilege_errors = true;
+        cpu.reg[Register::A0] = 0xa000_0000;
+
+        assert!(matches!(
+            cpu.step(&mut bus),
+            Err(RmipsError::PrivilegeViolation(0xa000_0000))
+        ));
+        assert_eq!(cpu.exception_pending, false);
+    }
+
+    #[test]
+    fn tlb_exception_updates_context() {
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        cpu.pc = 0x0040_0010;
+        cpu.cpzero.write_context(0x8010_0000);
+
+        cpu.tlb_exception(Exception::TLBLoadMiss, 0x7fff_e008)
+            .unwrap();
+        assert_eq!(cpu.cpzero.badvaddr.address, 0x7fff_e008);
+        assert_eq!(u32::from(cpu.cpzero.context), 0x801f_fff8);
+        assert_eq!(cpu.cpzero.entryhi, 0x7fff_e000);
+        assert_eq!(cpu.cpzero.epc.address, 0x0040_0010);
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::TLBLoadMiss
+        );
+        assert_eq!(cpu.pc, 0xbfc0_0180);
+    }
+
+    #[test]
+    fn step_tlb_exceptions() -> Result<()> {
+        let lw = 0x8c85_0000; // lw $a1, 0($a0)
+        let sw = 0xac85_0000; // sw $a1, 0($a0)
+        for (instr, vaddress, exception, vector) in &[
+            (lw, 0x0040_1234, Exception::TLBLoadMiss, 0x8000_0000),
+            (sw, 0x0040_1234, Exception::TLBStoreMiss, 0x8000_0000),
+            (lw, 0xc000_0010, Exception::TLBLoadMiss, 0x8000_0080),
+            (sw, 0x0060_0010, Exception::TLBModification, 0x8000_0080),
+        ] {
+            let mut bus = setup_bus(&[*instr]);
+            let mut cpu = Cpu::new(false);
+            cpu.reset();
+            cpu.pc = 0x8000_0000;
+            cpu.cpzero.status = 0.into(); // Kernel mode with BEV clear
+            cpu.cpzero.entryhi = 0x0060_0000;
+            cpu.cpzero.entrylo = 0x0000_0300; // Valid and global, but not writable
+            cpu.cpzero.tlbwi_emulate();
+            cpu.reg[Register::A0] = *vaddress;
+
+            cpu.step(&mut bus)?;
+
+            assert_eq!(cpu.exception_pending, true);
+            assert_eq!(cpu.cpzero.cause.get_exception_code(), *exception);
+            assert_eq!(cpu.cpzero.badvaddr.address, *vaddress);
+            assert_eq!(cpu.cpzero.context.get_badvpn(), (vaddress >> 12) & 0x7_ffff);
+            assert_eq!(cpu.cpzero.entryhi, vaddress & 0xffff_f000);
+            assert_eq!(cpu.cpzero.epc.address, 0x8000_0000);
+            assert_eq!(cpu.pc, *vector);
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,31 @@
+use log::warn;
+
 use crate::control::exception::Exception;
 use crate::control::instruction::Instruction;
+use crate::control::model::{CpuModel, MAX_TLB_ENTRIES};
 use crate::control::registers::{
-    BadVaddrRegister, CauseRegister, ContextRegister, EpcRegister, IndexRegister, PridRegister,
-    RandomRegister, StatusRegister,
+    BadVaddrRegister, CauseRegister, ConfigRegister, ContextRegister, Cp0Register, EpcRegister,
+    IndexRegister, PridRegister, RandomRegister, StatusRegister,
 };
-use crate::control::tlbentry::TlbEntry;
-use crate::control::{KERNEL_SPACE_MASK, KSEG0, KSEG1, KSEG2, KSEG2_TOP, KSEG_SELECT_MASK, KUSEG};
+use crate::control::tlbentry::{TlbEntry, TlbFormat, TlbMapping};
+use crate::control::{KERNEL_SPACE_MASK, KSEG0, KSEG1, KSEG2, KSEG2_TOP, KSEG_SELECT_MASK};
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::util::state::{StateReader, StateWriter};
 use crate::Address;
 
-const TLB_ENTRIES: usize = 64;
-const RANDOM_UPPER_BOUND: u32 = 63;
+/// Mask of the index field in the Index and Random registers before shifting.
+const TLB_INDEX_MASK: u32 = 0x3f;
+
+/// How virtual addresses are mapped to physical addresses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Translation {
+    /// The R3000 memory model with the kuseg, kseg0, kseg1 and kseg2 segments.
+    #[default]
+    Segmented,
+    /// Addresses are physical and every access is allowed, as on a processor without an MMU.
+    Identity,
+}
 
 /// CP0 is the sytem control coprocessor that handles address translation and exception handling.
 #[derive(Copy, Clone, Debug)]
@@ -17,15 +33,21 @@ pub struct CPZero {
     pub index: IndexRegister,
     pub random: RandomRegister,
     pub entrylo: u32,
+    pub entrylo1: u32,
     pub context: ContextRegister,
+    pub pagemask: u32,
     pub badvaddr: BadVaddrRegister,
     pub entryhi: u32,
     pub status: StatusRegister,
     pub cause: CauseRegister,
     pub epc: EpcRegister,
     pub prid: PridRegister,
+    pub config: ConfigRegister,
     pub tlb_miss_user: bool,
-    tlb: [TlbEntry; TLB_ENTRIES],
+    pub translation: Translation,
+    model: CpuModel,
+    tlb_entries: usize,
+    tlb: [TlbEntry; MAX_TLB_ENTRIES],
 }
 
 impl Default for CPZero {
@@ -34,15 +56,21 @@ impl Default for CPZero {
             index: IndexRegister::new(),
             random: RandomRegister::new(),
             entrylo: 0,
+            entrylo1: 0,
             context: ContextRegister::new(),
+            pagemask: 0,
             badvaddr: BadVaddrRegister::new(),
             entryhi: 0,
             status: StatusRegister::new(),
             cause: CauseRegister::new(),
             epc: EpcRegister::new(),
             prid: PridRegister::new(),
+            config: ConfigRegister::new(),
             tlb_miss_user: false,
-            tlb: [TlbEntry::default(); TLB_ENTRIES],
+            translation: Translation::default(),
+            model: CpuModel::default(),
+            tlb_entries: CpuModel::default().tlb_entries(),
+            tlb: [TlbEntry::default(); MAX_TLB_ENTRIES],
         }
     }
 }
@@ -52,11 +80,105 @@ impl CPZero {
         Default::default()
     }
 
+    /// Returns a `CPZero` for `model` with `tlb_entries` entries in its TLB.
+    ///
+    /// `tlb_entries` must be between 1 and `MAX_TLB_ENTRIES`.
+    pub fn with_model(model: CpuModel, tlb_entries: usize) -> Self {
+        assert!((1..=MAX_TLB_ENTRIES).contains(&tlb_entries));
+        CPZero {
+            model,
+            tlb_entries,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the processor model being emulated.
+    pub fn model(&self) -> CpuModel {
+        self.model
+    }
+
+    /// Returns the layout of the TLB entries.
+    pub fn tlb_format(&self) -> TlbFormat {
+        self.model.tlb_format()
+    }
+
+    /// Returns the number of TLB entries.
+    pub fn tlb_entries(&self) -> usize {
+        self.tlb_entries
+    }
+
+    /// Writes the registers and the TLB to a machine snapshot.
+    ///
+    /// The processor model and the translation are part of the configuration and are not saved.
+    pub fn save(&self, state: &mut StateWriter) {
+        let registers = [
+            self.index.into(),
+            self.random.into(),
+            self.entrylo,
+            self.entrylo1,
+            self.context.into(),
+            self.pagemask,
+            self.badvaddr.into(),
+            self.entryhi,
+            self.status.into(),
+            self.cause.into(),
+            self.epc.into(),
+            self.prid.into(),
+            self.config.into(),
+        ];
+        for value in registers.iter() {
+            state.word(*value);
+        }
+        state.bool(self.tlb_miss_user);
+
+        state.word(self.tlb_entries as u32);
+        for entry in &self.tlb[..self.tlb_entries] {
+            state.word(entry.entryhi);
+            state.word(entry.entrylo);
+            state.word(entry.entrylo1);
+            state.word(entry.pagemask);
+        }
+    }
+
+    /// Restores the registers and the TLB written by `save`.
+    pub fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.index = state.word()?.into();
+        self.random = state.word()?.into();
+        self.entrylo = state.word()?;
+        self.entrylo1 = state.word()?;
+        self.context = state.word()?.into();
+        self.pagemask = state.word()?;
+        self.badvaddr = state.word()?.into();
+        self.entryhi = state.word()?;
+        self.status = state.word()?.into();
+        self.cause = state.word()?.into();
+        self.epc = state.word()?.into();
+        self.prid = state.word()?.into();
+        self.config = state.word()?.into();
+        self.tlb_miss_user = state.bool()?;
+
+        let entries = state.word()? as usize;
+        if entries != self.tlb_entries {
+            return Err(RmipsError::Snapshot(format!(
+                "the TLB has {} entries instead of {}",
+                entries, self.tlb_entries
+            )));
+        }
+        for entry in &mut self.tlb[..entries] {
+            entry.entryhi = state.word()?;
+            entry.entrylo = state.word()?;
+            entry.entrylo1 = state.word()?;
+            entry.pagemask = state.word()?;
+        }
+        Ok(())
+    }
+
     /// Resets the CP0 control registers to their initial states.
     /// Refer to Chapter 7 of the IDT R30xx Manual for details.
     pub fn reset(&mut self) {
-        // Random register is initialized to its maximum value (63) on reset
-        self.random.set_value(RANDOM_UPPER_BOUND);
+        // Random register is initialized to the last TLB entry on reset
+        let shift = self.tlb_format().index_shift();
+        self.random.bits = (self.tlb_entries as u32 - 1) << shift;
 
         // Enable bootstrap exception vector (BEV) on reset
         self.status.enter_bootstrap();
@@ -73,40 +195,190 @@ impl CPZero {
         // Caches are not switched
         self.status.clear_swc();
 
-        // Set processor revision identifier to indicate a MIPS R3000A
-        self.prid.bits = 0x230;
+        // Identify the processor model and describe the TLB geometry
+        self.prid.bits = self.model.prid();
+        self.config.set_mmu_size(self.tlb_entries as u32 - 1);
+    }
+
+    /// Returns the TLB entry selected by the Index register.
+    pub fn tlb_index(&self) -> usize {
+        let shift = self.tlb_format().index_shift();
+        ((self.index.bits >> shift) & TLB_INDEX_MASK) as usize
+    }
+
+    /// Returns the TLB entry selected by the Random register.
+    pub fn tlb_random(&self) -> usize {
+        let shift = self.tlb_format().index_shift();
+        ((self.random.bits >> shift) & TLB_INDEX_MASK) as usize
+    }
+
+    /// Writes `val` to the Index register the way the `mtc0` instruction would.
+    /// The position of the index field depends on the TLB format.
+    pub fn write_index(&mut self, val: u32) {
+        let mask = TLB_INDEX_MASK << self.tlb_format().index_shift();
+        self.index.bits = (self.index.bits & !mask) | (val & mask);
     }
 
-    /// Translates a virtual address to a physical address.
+    /// Writes `val` to the Context register the way the `mtc0` instruction would.
+    /// Only PTEBase is writable, its width depends on the TLB format.
+    pub fn write_context(&mut self, val: u32) {
+        let mask = self.tlb_format().context_write_mask();
+        self.context.bits = (self.context.bits & !mask) | (val & mask);
+    }
+
+    /// Records the virtual address of a TLB exception.
     ///
-    /// Addresses in kuseg and kseg2 use the TLB for translation.
-    pub fn translate(&self, vaddress: Address) -> Address {
-        // let mut cacheable = false;
-
-        if self.kernel_mode() {
-            // Determine which kernel segment the address is located in
-            match vaddress & KSEG_SELECT_MASK {
-                KSEG0 => {
-                    // cacheable = true;
-                    vaddress - KSEG0
-                }
-                KSEG1 => vaddress - KSEG1,
-                KSEG2 | KSEG2_TOP => self.tlb_translate(KSEG2, vaddress),
-                _ => self.tlb_translate(KUSEG, vaddress),
+    /// BadVaddr receives the full address, BadVPN in Context is updated while PTEBase is
+    /// preserved, and the page number is loaded into EntryHi without changing the ASID.
+    /// This lets a refill handler load the PTE through Context and write it with `tlbwr`.
+    pub fn tlb_fault(&mut self, vaddress: Address) {
+        let format = self.tlb_format();
+        let ptebase = self.context.bits & format.context_write_mask();
+        let vpn_mask = format.entryhi_vpn(0xffff_ffff);
+
+        self.badvaddr.address = vaddress;
+        self.context.bits = ptebase | format.context_badvpn(vaddress);
+        self.entryhi = (self.entryhi & !vpn_mask) | format.entryhi_vpn(vaddress);
+    }
+
+    /// Writes `val` to one of the registers describing the current TLB entry,
+    /// keeping only the bits defined by the TLB format.
+    pub fn write_entry_register(&mut self, reg: Cp0Register, val: u32) {
+        let format = self.tlb_format();
+        match reg {
+            Cp0Register::EntryHi => self.entryhi = val & format.entryhi_write_mask(),
+            Cp0Register::EntryLo => self.entrylo = val & format.entrylo_write_mask(),
+            Cp0Register::EntryLo1 => self.entrylo1 = val & format.entrylo1_write_mask(),
+            Cp0Register::PageMask => self.pagemask = val & format.pagemask_write_mask(),
+            _ => unreachable!("{:?} is not a TLB entry register", reg),
+        }
+    }
+
+    /// Writes `val` to `reg` the way the `mtc0` instruction would. Read-only fields and
+    /// reserved bits are preserved by each register's write mask.
+    pub fn write_register(&mut self, reg: Cp0Register, val: u32) {
+        match reg {
+            Cp0Register::Index => self.write_index(val),
+            Cp0Register::Random => self.random.write(val),
+            Cp0Register::EntryLo
+            | Cp0Register::EntryLo1
+            | Cp0Register::EntryHi
+            | Cp0Register::PageMask => self.write_entry_register(reg, val),
+            Cp0Register::Context => self.write_context(val),
+            Cp0Register::BadVaddr => self.badvaddr.write(val),
+            Cp0Register::Status => self.status.write(val),
+            Cp0Register::Cause => self.cause.write(val),
+            Cp0Register::Epc => self.epc.write(val),
+            Cp0Register::Prid => self.prid.write(val),
+            Cp0Register::Config => self.config.write(val),
+        }
+    }
+
+    fn write_tlb_entry(&mut self, index: usize) {
+        match self.tlb[..self.tlb_entries].get_mut(index) {
+            Some(entry) => {
+                entry.entryhi = self.entryhi;
+                entry.entrylo = self.entrylo;
+                entry.entrylo1 = self.entrylo1;
+                entry.pagemask = self.pagemask;
             }
-        } else if vaddress & KERNEL_SPACE_MASK != 0 {
-            // Attempted to access kernel-space while not in kernel mode
-            // Trigger an exception
-            0xffff_ffff
-        } else {
-            // Translate a user-space address
-            self.tlb_translate(KUSEG, vaddress)
+            None => warn!("Ignoring write to nonexistent TLB entry {}", index),
+        }
+    }
+
+    /// Returns the first TLB entry that maps `vaddress` in the address space of EntryHi.
+    fn tlb_lookup(&self, vaddress: Address) -> Option<(usize, TlbMapping)> {
+        let format = self.tlb_format();
+        self.tlb[..self.tlb_entries]
+            .iter()
+            .enumerate()
+            .find_map(|(index, entry)| Some((index, entry.lookup(format, vaddress, self.entryhi)?)))
+    }
+
+    /// Returns true if a TLB miss at `vaddress` is a refill, which has its own exception vector.
+    ///
+    /// Refills are misses without any matching entry, where the R3000 only refills kuseg.
+    pub fn tlb_refill(&self, vaddress: Address) -> bool {
+        let refilled = match vaddress & KSEG_SELECT_MASK {
+            KSEG2 | KSEG2_TOP => self.tlb_format() == TlbFormat::R4000,
+            _ => true,
+        };
+        refilled && self.tlb_lookup(vaddress).is_none()
+    }
+
+    /// Checks that the current processor mode may perform the access `ctx` at `vaddress`
+    /// and translates it to a physical address.
+    ///
+    /// This is the single place where segment privileges are enforced, so instruction
+    /// fetches, loads, stores, and debugger accesses all see the same address space.
+    /// Returns the address exception to raise when user mode touches a kernel segment, or
+    /// the TLB exception to raise when a mapped address has no valid translation.
+    pub fn translate_access(
+        &self,
+        vaddress: Address,
+        ctx: AccessContext,
+    ) -> std::result::Result<Address, Exception> {
+        if self.translation == Translation::Identity {
+            return Ok(vaddress);
+        }
+        if !self.kernel_mode() && vaddress & KERNEL_SPACE_MASK != 0 {
+            return Err(match ctx {
+                AccessContext::CpuStore => Exception::AddressStoreError,
+                _ => Exception::AddressLoadError,
+            });
+        }
+
+        self.segment_translate(vaddress, ctx)
+    }
+
+    /// Translates a virtual address to a physical address without checking privileges.
+    ///
+    /// Addresses in kuseg and kseg2 use the TLB for translation, and `None` is returned for a
+    /// mapped address without a valid translation.
+    pub fn translate(&self, vaddress: Address) -> Option<Address> {
+        match self.translation {
+            Translation::Identity => Some(vaddress),
+            Translation::Segmented => self
+                .segment_translate(vaddress, AccessContext::Debugger)
+                .ok(),
         }
     }
 
-    fn tlb_translate(&self, _segment: Address, vaddress: Address) -> Address {
-        // TODO: Implement TLB
-        vaddress
+    /// Determines which segment the address is located in and translates it for `ctx`.
+    fn segment_translate(
+        &self,
+        vaddress: Address,
+        ctx: AccessContext,
+    ) -> std::result::Result<Address, Exception> {
+        match vaddress & KSEG_SELECT_MASK {
+            KSEG0 => Ok(vaddress - KSEG0),
+            KSEG1 => Ok(vaddress - KSEG1),
+            // kseg2 and kuseg are both mapped through the TLB
+            _ => self.tlb_translate(vaddress, ctx),
+        }
+    }
+
+    /// Translates a kuseg or kseg2 address through the TLB.
+    ///
+    /// Stores to pages whose dirty bit is clear raise TLB Modification exceptions, debugger
+    /// writes are not write protected.
+    fn tlb_translate(
+        &self,
+        vaddress: Address,
+        ctx: AccessContext,
+    ) -> std::result::Result<Address, Exception> {
+        let miss = match ctx {
+            AccessContext::CpuStore => Exception::TLBStoreMiss,
+            _ => Exception::TLBLoadMiss,
+        };
+        match self.tlb_lookup(vaddress) {
+            Some((_, mapping)) if !mapping.valid => Err(miss),
+            Some((_, mapping)) if ctx == AccessContext::CpuStore && !mapping.dirty => {
+                Err(Exception::TLBModification)
+            }
+            Some((_, mapping)) => Ok(mapping.paddress),
+            None => Err(miss),
+        }
     }
 
     /// Handles processor exceptions by updating the state of `CPZero`.
@@ -115,20 +387,14 @@ impl CPZero {
         // Save current PC in the EPC register to point to the restart location
         self.epc.address = pc;
 
-        // Switch to kernel-mode
-        self.status.enter_kernel_mode();
-
-        // Disable interrupts
-        self.status.disable_interrupts();
+        // Save the current mode on the KU/IE stack, then switch to kernel-mode with interrupts disabled
+        self.status.push_mode_stack();
 
-        // Clear the Cause register
+        // Clear the Cause register, except for the interrupts that are still pending. The CE field
+        // stays clear, Cpu::coprocessor_unusable records the coprocessor that was referenced.
+        let pending = self.cause.get_interrupt_pending();
         self.cause.bits = 0;
-
-        // Set Cause register CE field if this is a Coprocessor Unusable exception
-        if exception == Exception::CoprocessorUnusable {
-            // TODO: This should be the coprocessor number that caused the error
-            self.cause.set_coprocessor_error(2);
-        }
+        self.cause.set_interrupt_pending(pending);
 
         // Save the ExcCode in the Cause register
         self.cause.set_exception_code(exception);
@@ -139,40 +405,51 @@ impl CPZero {
         if delayslot {
             self.cause.set_branch_delay();
         }
-
-        // Set the interrupt pending field of the Cause register
-        // TODO
     }
 
     /// Read Indexed TLB Entry
-    pub fn tlbr_emulate(&self) {
-        todo!()
+    pub fn tlbr_emulate(&mut self) {
+        let index = self.tlb_index();
+        match self.tlb[..self.tlb_entries].get(index) {
+            Some(entry) => {
+                self.entryhi = entry.entryhi;
+                self.entrylo = entry.entrylo;
+                self.entrylo1 = entry.entrylo1;
+                self.pagemask = entry.pagemask;
+            }
+            None => warn!("Ignoring read of nonexistent TLB entry {}", index),
+        }
     }
 
     /// Write Indexed TLB Entry
     pub fn tlbwi_emulate(&mut self) {
-        let index = self.index.get_index() as usize;
-        self.tlb[index].entryhi = self.entryhi;
-        self.tlb[index].entrylo = self.entrylo;
+        self.write_tlb_entry(self.tlb_index());
     }
 
     /// Write Random TLB Entry
     pub fn tlbwr_emulate(&mut self) {
-        let index = self.random.get_value() as usize;
-        self.tlb[index].entryhi = self.entryhi;
-        self.tlb[index].entrylo = self.entrylo;
+        self.write_tlb_entry(self.tlb_random());
     }
 
     /// Probe TLB For Matching Entry
-    pub fn tlbp_emulate(&self) {
-        todo!()
+    ///
+    /// Loads Index with the entry matching the page and ASID of EntryHi, or sets its P bit.
+    pub fn tlbp_emulate(&mut self) {
+        let vpn = self.tlb_format().entryhi_vpn(self.entryhi);
+        match self.tlb_lookup(vpn) {
+            Some((index, _)) => self.index.bits = (index as u32) << self.tlb_format().index_shift(),
+            None => self.index.set_p(),
+        }
     }
 
     /// Restore from Exception
     /// Restores the proper Interrupt Enable and Kernel/User mode
     /// bits of the status register on return from exception.
+    ///
+    /// An interrupt that is still pending is taken as soon as the restored IEc enables it, by
+    /// the check `Cpu::step` makes before the next instruction that is not in a delay slot.
     pub fn rfe_emulate(&mut self) {
-        self.status.bits = (self.status.bits & 0xfffffff0) | ((self.status.bits >> 2) & 0x0f);
+        self.status.pop_mode_stack();
     }
 
     pub fn bc0x_emulate(&self, _instr: Instruction, _pc: Address) {
@@ -190,16 +467,41 @@ impl CPZero {
         }
     }
 
+    /// Returns true if CP0 instructions may be executed.
+    ///
+    /// CP0 is always usable in kernel mode, user mode requires CU0 to be set.
+    pub fn cp0_accessible(&self) -> bool {
+        self.kernel_mode() || self.status.cu0()
+    }
+
     /// Returns true if the processor is in kernel-mode.
     pub fn kernel_mode(&self) -> bool {
         self.status.is_kernel_mode()
     }
 
+    /// Returns true if user-mode data accesses use the reverse of the bus endianness.
+    pub fn reverse_endian(&self) -> bool {
+        self.status.re() && !self.kernel_mode()
+    }
+
     /// Returns true if interrupts are currently enabled.
     pub fn interrupts_enabled(&self) -> bool {
         self.status.are_interrupts_enabled()
     }
 
+    /// Drives the six hardware interrupt lines, which are IP2 to IP7 in the Cause register.
+    pub fn set_hardware_interrupts(&mut self, lines: u8) {
+        let software = self.cause.get_interrupt_pending() & 0x3;
+        self.cause
+            .set_interrupt_pending(software | (lines as u32 & 0x3f) << 2);
+    }
+
+    /// Returns true if an unmasked interrupt is pending while interrupts are enabled.
+    pub fn interrupt_requested(&self) -> bool {
+        self.interrupts_enabled()
+            && self.cause.get_interrupt_pending() & self.status.get_interrupt_mask() != 0
+    }
+
     /// Returns true if the Bootstrap Exception Vector (BEV) is enabled.
     pub fn boot_exception_vector_enabled(&self) -> bool {
         self.status.is_bootstrap_mode()
@@ -216,7 +518,8 @@ mod tests {
         let mut cp0 = CPZero::new();
         cp0.reset();
 
-        assert_eq!(cp0.random.get_value(), RANDOM_UPPER_BOUND);
+        assert_eq!(cp0.random.get_value(), 63);
+        assert_eq!(cp0.config.get_mmu_size(), 63);
         assert_eq!(cp0.boot_exception_vector_enabled(), true);
         assert_eq!(cp0.kernel_mode(), true);
         assert_eq!(cp0.interrupts_enabled(), false);
@@ -226,6 +529,216 @@ mod tests {
         assert_eq!(cp0.coprocessor_usable(3), false);
     }
 
+    #[test]
+    fn cpzero_reset_r4000() {
+        let mut cp0 = CPZero::with_model(CpuModel::R4000, 48);
+        cp0.reset();
+
+        assert_eq!(cp0.tlb_format(), TlbFormat::R4000);
+        assert_eq!(u32::from(cp0.random), 47);
+        assert_eq!(cp0.tlb_random(), 47);
+        assert_eq!(u32::from(cp0.prid), 0x0422);
+        assert_eq!(cp0.config.get_mmu_size(), 47);
+    }
+
+    #[test]
+    fn cpzero_tlbwi_paired_entry() {
+        let mut cp0 = CPZero::with_model(CpuModel::R4000, 48);
+        cp0.reset();
+
+        cp0.write_index(0xffff_ff05);
+        cp0.write_entry_register(Cp0Register::EntryHi, 0xffff_ffff);
+        cp0.write_entry_register(Cp0Register::EntryLo, 0x0000_1017);
+        cp0.write_entry_register(Cp0Register::EntryLo1, 0xc000_1057);
+        cp0.write_entry_register(Cp0Register::PageMask, 0xffff_ffff);
+        cp0.tlbwi_emulate();
+
+        assert_eq!(cp0.tlb_index(), 5);
+        let entry = cp0.tlb[5];
+        assert_eq!(entry.entryhi, 0xffff_e0ff);
+        assert_eq!(entry.entrylo, 0x0000_1017);
+        assert_eq!(entry.entrylo1, 0x0000_1057);
+        assert_eq!(entry.pagemask, 0x01ff_e000);
+    }
+
+    #[test]
+    fn cpzero_tlbwi_outside_configured_size() {
+        let mut cp0 = CPZero::with_model(CpuModel::R3000, 16);
+        cp0.reset();
+        assert_eq!(cp0.tlb_random(), 15);
+
+        cp0.write_index(20 << 8);
+        cp0.entryhi = 0x1234_5000;
+        cp0.tlbwi_emulate();
+        assert_eq!(cp0.tlb[20].entryhi, 0);
+
+        // R3000 entries have no odd page or page mask
+        cp0.write_entry_register(Cp0Register::EntryLo1, 0xffff_ffff);
+        cp0.write_entry_register(Cp0Register::PageMask, 0xffff_ffff);
+        assert_eq!(cp0.entrylo1, 0);
+        assert_eq!(cp0.pagemask, 0);
+    }
+
+    #[test]
+    fn cpzero_tlb_fault() {
+        let mut cp0 = CPZero::new();
+        cp0.reset();
+        cp0.write_context(0xffff_ffff);
+        cp0.entryhi = 0x0000_0fc0;
+
+        cp0.tlb_fault(0x0040_1234);
+        assert_eq!(cp0.badvaddr.address, 0x0040_1234);
+        assert_eq!(u32::from(cp0.context), 0xffe0_1004);
+        assert_eq!(cp0.context.get_badvpn(), 0x401);
+        assert_eq!(cp0.entryhi, 0x0040_1fc0);
+
+        // A later fault replaces BadVPN but keeps PTEBase
+        cp0.tlb_fault(0xc000_3000);
+        assert_eq!(u32::from(cp0.context), 0xfff0_000c);
+        assert_eq!(cp0.entryhi, 0xc000_3fc0);
+    }
+
+    #[test]
+    fn cpzero_tlb_fault_r4000() {
+        let mut cp0 = CPZero::with_model(CpuModel::R4000, 48);
+        cp0.reset();
+        cp0.write_context(0xffff_ffff);
+        cp0.entryhi = 0x0000_0042;
+
+        cp0.tlb_fault(0x0040_3abc);
+        assert_eq!(u32::from(cp0.context), 0xff80_2010);
+        assert_eq!(cp0.entryhi, 0x0040_2042);
+    }
+
+    #[test]
+    fn cpzero_tlb_translate() {
+        let mut cp0 = CPZero::new();
+        cp0.reset();
+
+        // A global page, a page of ASID 3, an invalid page and a read-only page
+        let entries = [
+            (0x0040_0000, 0x0012_3700),
+            (0xc000_10c0, 0x0045_6600),
+            (0x0050_0000, 0x0000_0500),
+            (0x0060_0000, 0x0078_9300),
+        ];
+        for (index, (entryhi, entrylo)) in entries.iter().enumerate() {
+            cp0.write_index((index as u32) << 8);
+            cp0.entryhi = *entryhi;
+            cp0.entrylo = *entrylo;
+            cp0.tlbwi_emulate();
+        }
+        cp0.entryhi = 0x0000_00c0; // ASID 3
+
+        let translate = |cp0: &CPZero, vaddress, ctx| cp0.translate_access(vaddress, ctx);
+        assert_eq!(
+            translate(&cp0, 0x0040_0abc, AccessContext::CpuStore),
+            Ok(0x0012_3abc)
+        );
+        assert_eq!(
+            translate(&cp0, 0xc000_1ffc, AccessContext::CpuLoad),
+            Ok(0x0045_6ffc)
+        );
+        assert_eq!(
+            translate(&cp0, 0xc000_1ffc, AccessContext::CpuStore),
+            Ok(0x0045_6ffc)
+        );
+        assert_eq!(
+            translate(&cp0, 0x0050_0000, AccessContext::CpuFetch),
+            Err(Exception::TLBLoadMiss)
+        );
+        assert_eq!(
+            translate(&cp0, 0x0060_0010, AccessContext::CpuLoad),
+            Ok(0x0078_9010)
+        );
+        assert_eq!(
+            translate(&cp0, 0x0060_0010, AccessContext::CpuStore),
+            Err(Exception::TLBModification)
+        );
+        assert_eq!(
+            translate(&cp0, 0x0060_0010, AccessContext::Debugger),
+            Ok(0x0078_9010)
+        );
+        assert_eq!(cp0.tlb_refill(0x0050_0000), false);
+
+        // Another address space only sees the global page
+        cp0.entryhi = 0x0000_0100;
+        assert_eq!(
+            translate(&cp0, 0xc000_1000, AccessContext::CpuLoad),
+            Err(Exception::TLBLoadMiss)
+        );
+        assert_eq!(
+            translate(&cp0, 0x0070_0000, AccessContext::CpuStore),
+            Err(Exception::TLBStoreMiss)
+        );
+        assert_eq!(cp0.translate(0x0040_0004), Some(0x0012_3004));
+        assert_eq!(cp0.translate(0xc000_1000), None);
+
+        // The R3000 only has a refill vector for kuseg
+        assert_eq!(cp0.tlb_refill(0x0070_0000), true);
+        assert_eq!(cp0.tlb_refill(0xc000_1000), false);
+    }
+
+    #[test]
+    fn cpzero_tlb_translate_r4000() {
+        let mut cp0 = CPZero::with_model(CpuModel::R4000, 48);
+        cp0.reset();
+
+        // A pair of 16KB pages at 0x0040_0000 for ASID 0x42, the odd page is read-only
+        cp0.write_index(7);
+        cp0.write_entry_register(Cp0Register::EntryHi, 0x0040_0042);
+        cp0.write_entry_register(Cp0Register::EntryLo, (0x0100_0000 >> 6) | 0b110);
+        cp0.write_entry_register(Cp0Register::EntryLo1, (0x0200_0000 >> 6) | 0b010);
+        cp0.write_entry_register(Cp0Register::PageMask, 0x0000_6000);
+        cp0.tlbwi_emulate();
+
+        assert_eq!(
+            cp0.translate_access(0x0040_3abc, AccessContext::CpuStore),
+            Ok(0x0100_3abc)
+        );
+        assert_eq!(
+            cp0.translate_access(0x0040_7abc, AccessContext::CpuLoad),
+            Ok(0x0200_3abc)
+        );
+        assert_eq!(
+            cp0.translate_access(0x0040_7abc, AccessContext::CpuStore),
+            Err(Exception::TLBModification)
+        );
+
+        cp0.entryhi = 0x43;
+        assert_eq!(
+            cp0.translate_access(0x0040_0000, AccessContext::CpuLoad),
+            Err(Exception::TLBLoadMiss)
+        );
+        assert_eq!(cp0.tlb_refill(0xc000_0000), true);
+    }
+
+    #[test]
+    fn cpzero_tlbp_tlbr_emulate() {
+        let mut cp0 = CPZero::new();
+        cp0.reset();
+
+        cp0.write_index(9 << 8);
+        cp0.entryhi = 0x1234_5080;
+        cp0.entrylo = 0x0abc_d600;
+        cp0.tlbwi_emulate();
+
+        // The probe matches on the page and ASID of EntryHi
+        cp0.entryhi = 0x1234_5040;
+        cp0.tlbp_emulate();
+        assert_eq!(cp0.index.is_p(), true);
+        cp0.entryhi = 0x1234_5080;
+        cp0.tlbp_emulate();
+        assert_eq!(cp0.index.is_p(), false);
+        assert_eq!(cp0.tlb_index(), 9);
+
+        cp0.entryhi = 0;
+        cp0.entrylo = 0;
+        cp0.tlbr_emulate();
+        assert_eq!(cp0.entryhi, 0x1234_5080);
+        assert_eq!(cp0.entrylo, 0x0abc_d600);
+    }
+
     #[test]
     fn cpzero_exception_coprocessor_unusable() {
         let mut cp0 = CPZero::new();
@@ -235,7 +748,7 @@ mod tests {
         assert_eq!(cp0.kernel_mode(), true);
         assert_eq!(cp0.interrupts_enabled(), false);
 
-        assert_ne!(cp0.cause.get_coprocessor_error(), 0);
+        assert_eq!(cp0.cause.get_coprocessor_error(), 0);
         assert_eq!(
             cp0.cause.get_exception_code(),
             Exception::CoprocessorUnusable
@@ -257,6 +770,31 @@ mod tests {
         assert_eq!(cp0.cause.is_branch_delay(), true);
     }
 
+    #[test]
+    fn cpzero_hardware_interrupts() {
+        let mut cp0 = CPZero::new();
+        cp0.reset();
+        cp0.cause.write(0x100); // software interrupt 0
+
+        cp0.set_hardware_interrupts(0b10_0001);
+        assert_eq!(cp0.cause.get_interrupt_pending(), 0b1000_0101);
+        assert_eq!(cp0.interrupt_requested(), false);
+
+        // The interrupt has to be unmasked and interrupts enabled
+        cp0.status.set_interrupt_mask(0b100);
+        assert_eq!(cp0.interrupt_requested(), false);
+        cp0.status.enable_interrupts();
+        assert_eq!(cp0.interrupt_requested(), true);
+
+        // Taking the exception keeps the pending interrupts but disables interrupts
+        cp0.exception(0xbfc00400, Exception::Interrupt, false);
+        assert_eq!(cp0.cause.get_interrupt_pending(), 0b1000_0101);
+        assert_eq!(cp0.interrupt_requested(), false);
+
+        cp0.set_hardware_interrupts(0);
+        assert_eq!(cp0.cause.get_interrupt_pending(), 0b01);
+    }
+
     #[test]
     fn cpzero_rfe_emulate() {
         let mut cp0 = CPZero::new();
@@ -264,7 +802,7 @@ mod tests {
 
         cp0.status.set_kuo();
         cp0.status.clear_ieo();
-        cp0.status.clear_kup();
+        cp0.status.set_kup();
         cp0.status.set_iep();
         cp0.rfe_emulate();
 
@@ -275,4 +813,68 @@ mod tests {
         assert_eq!(cp0.status.is_kernel_mode(), false);
         assert_eq!(cp0.status.are_interrupts_enabled(), true);
     }
+
+    #[test]
+    fn cpzero_exception_from_user_mode() {
+        let mut cp0 = CPZero::new();
+        cp0.reset();
+        cp0.status.enter_user_mode();
+        cp0.status.enable_interrupts();
+
+        cp0.exception(0x0040_0000, Exception::Syscall, false);
+        assert_eq!(cp0.kernel_mode(), true);
+        assert_eq!(cp0.interrupts_enabled(), false);
+        assert_eq!(cp0.status.kup(), true);
+        assert_eq!(cp0.status.iep(), true);
+
+        cp0.rfe_emulate();
+        assert_eq!(cp0.kernel_mode(), false);
+        assert_eq!(cp0.interrupts_enabled(), true);
+    }
+
+    #[test]
+    fn cpzero_nested_exceptions() {
+        let mut cp0 = CPZero::new();
+        cp0.reset();
+        cp0.status.enter_user_mode();
+        cp0.status.enable_interrupts();
+
+        // A syscall from user mode followed by an exception inside the kernel handler
+        cp0.exception(0x0040_0000, Exception::Syscall, false);
+        cp0.status.enable_interrupts();
+        cp0.exception(0x8000_0100, Exception::Overflow, false);
+
+        assert_eq!(cp0.kernel_mode(), true);
+        assert_eq!(cp0.interrupts_enabled(), false);
+        assert_eq!(cp0.status.kup(), false);
+        assert_eq!(cp0.status.iep(), true);
+        assert_eq!(cp0.status.kuo(), true);
+        assert_eq!(cp0.status.ieo(), true);
+        assert_eq!(cp0.epc.address, 0x8000_0100);
+
+        // Returning from the inner handler resumes the kernel handler
+        cp0.rfe_emulate();
+        assert_eq!(cp0.kernel_mode(), true);
+        assert_eq!(cp0.interrupts_enabled(), true);
+
+        // Returning from the outer handler resumes the user program
+        cp0.rfe_emulate();
+        assert_eq!(cp0.kernel_mode(), false);
+        assert_eq!(cp0.interrupts_enabled(), true);
+    }
+
+    #[test]
+    fn cpzero_identity_translation() {
+        let mut cp0 = CPZero::new();
+        cp0.reset();
+        assert_eq!(cp0.translate(0xbfc0_0000), Some(0x1fc0_0000));
+
+        cp0.translation = Translation::Identity;
+        cp0.status.enter_user_mode();
+        assert_eq!(cp0.translate(0xbfc0_0000), Some(0xbfc0_0000));
+        assert_eq!(
+            cp0.translate_access(0x8000_0100, AccessContext::CpuStore),
+            Ok(0x8000_0100)
+        );
+    }
 }
@@ -0,0 +1,256 @@
+//! Disassembly of MIPS I instructions in the GNU assembler syntax using the instruction table.
+
+use crate::asm;
+use crate::control::instruction::Instruction;
+use crate::control::isa::{Op, Operands};
+use crate::control::registers::REGISTER_NAMES;
+use crate::util::error::{Result, RmipsError};
+use crate::util::opts::DisasmOpts;
+use crate::{Address, Endian};
+
+/// Returns the ABI name of a general purpose register, e.g. `$t0`.
+fn reg(index: usize) -> String {
+    format!("${}", REGISTER_NAMES[index])
+}
+
+/// Formats the `offset(base)` operand of a load or store.
+fn memory_operand(instr: Instruction) -> String {
+    format!("{}({})", instr.simmed() as i32, reg(instr.rs()))
+}
+
+/// Disassembles `instr` located at `pc` into an instruction with its operands, or into a
+/// `.word` directive for reserved encodings.
+pub fn disassemble(instr: Instruction, pc: Address) -> String {
+    let spec = match Op::decode(instr) {
+        _ if instr.0 == 0 => return "nop".to_owned(),
+        Some(op) => op.spec(),
+        None => return format!(".word 0x{:08x}", instr.0),
+    };
+    let (rs, rt, rd) = (reg(instr.rs()), reg(instr.rt()), reg(instr.rd()));
+    let (fd, fs, ft) = (instr.shamt(), instr.rd(), instr.rt());
+    let branch = pc.wrapping_add(4).wrapping_add(instr.simmed() << 2);
+    let jump = (pc.wrapping_add(4) & 0xf000_0000) | (instr.jumptarget() << 2);
+
+    let operands = match spec.operands {
+        Operands::None => String::new(),
+        Operands::Shift => format!("{}, {}, {}", rd, rt, instr.shamt()),
+        Operands::ShiftVariable => format!("{}, {}, {}", rd, rt, rs),
+        Operands::Rs => rs,
+        Operands::Jalr if instr.rd() == 31 => rs,
+        Operands::Jalr => format!("{}, {}", rd, rs),
+        Operands::Rd => rd,
+        Operands::RsRt => format!("{}, {}", rs, rt),
+        Operands::RdRsRt => format!("{}, {}, {}", rd, rs, rt),
+        Operands::RsBranch => format!("{}, 0x{:08x}", rs, branch),
+        Operands::Jump => format!("0x{:08x}", jump),
+        Operands::RsRtBranch => format!("{}, {}, 0x{:08x}", rs, rt, branch),
+        Operands::RtRsSigned => format!("{}, {}, {}", rt, rs, instr.simmed() as i32),
+        Operands::RtRsUnsigned => format!("{}, {}, 0x{:x}", rt, rs, instr.immed()),
+        Operands::RtUpper => format!("{}, 0x{:x}", rt, instr.immed()),
+        Operands::RtCopRegister => format!("{}, ${}", rt, instr.rd()),
+        Operands::RtFpr => format!("{}, $f{}", rt, fs),
+        Operands::Branch => format!("0x{:08x}", branch),
+        Operands::FpuThree => format!("$f{}, $f{}, $f{}", fd, fs, ft),
+        Operands::FpuTwo => format!("$f{}, $f{}", fd, fs),
+        Operands::FpuCompare => format!("$f{}, $f{}", fs, ft),
+        Operands::CopOperation => format!("0x{:07x}", instr.0 & 0x01ff_ffff),
+        Operands::Memory => format!("{}, {}", rt, memory_operand(instr)),
+        Operands::Cache => format!("0x{:x}, {}", instr.rt(), memory_operand(instr)),
+        Operands::FprMemory => format!("$f{}, {}", ft, memory_operand(instr)),
+        Operands::CopMemory => format!("${}, {}", instr.rt(), memory_operand(instr)),
+    };
+
+    match spec.op {
+        // Bit 0 of `rt` selects whether the branch is taken on a true or false condition
+        Op::Bc0 | Op::Bc1 => format!(
+            "{}{} {}",
+            spec.mnemonic,
+            ["f", "t"][instr.rt() & 1],
+            operands
+        ),
+        _ if operands.is_empty() => spec.mnemonic.to_owned(),
+        _ => format!("{} {}", spec.mnemonic, operands),
+    }
+}
+
+/// Recognizes the idioms that compilers and assemblers emit for `li`, `la` and `move`.
+///
+/// Returns the pseudo-instruction and the number of words it spans, which is two for a
+/// `lui` completed by an `ori` or `addiu` of the same register.
+fn fold(instr: Instruction, next: Option<Instruction>) -> Option<(String, usize)> {
+    let rt = instr.rt();
+    match (instr.opcode(), instr.funct()) {
+        // lui followed by the instruction that fills in the low halfword
+        (0x0f, _) if rt != 0 => {
+            let next = next.filter(|next| next.rs() == rt && next.rt() == rt)?;
+            let high = instr.immed() << 16;
+            let value = match next.opcode() {
+                0x09 => high.wrapping_add(next.simmed()),
+                0x0d => high | next.immed(),
+                _ => return None,
+            };
+            Some((format!("li {}, 0x{:08x}", reg(rt), value), 2))
+        }
+        (0x09, _) if instr.rs() == 0 && rt != 0 => {
+            Some((format!("li {}, {}", reg(rt), instr.simmed() as i32), 1))
+        }
+        (0x0d, _) if instr.rs() == 0 && rt != 0 => {
+            Some((format!("li {}, 0x{:x}", reg(rt), instr.immed()), 1))
+        }
+        // addu or or with $zero
+        (0x00, 0x21) | (0x00, 0x25) if instr.rd() != 0 && (instr.rs() == 0 || rt == 0) => {
+            let source = instr.rs().max(rt);
+            Some((format!("move {}, {}", reg(instr.rd()), reg(source)), 1))
+        }
+        _ => None,
+    }
+}
+
+/// Lists the instruction words of `image` loaded at virtual address `base` with their
+/// disassembly, one per line. A trailing partial word is ignored.
+///
+/// With `fold`, common idioms are listed as the pseudo-instruction they implement, and the
+/// second word of a two word idiom is listed without a disassembly of its own.
+pub fn listing(image: &[u8], base: Address, endian: Endian, fold_idioms: bool) -> String {
+    let words: Vec<u32> = image
+        .chunks_exact(4)
+        .map(|bytes| endian.word([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+
+    let mut lines = String::new();
+    let mut index = 0;
+    while index < words.len() {
+        let pc = base.wrapping_add(4 * index as Address);
+        let instr = Instruction(words[index]);
+        let next = words.get(index + 1).map(|word| Instruction(*word));
+        let (text, len) = match fold_idioms {
+            true => fold(instr, next).unwrap_or_else(|| (disassemble(instr, pc), 1)),
+            false => (disassemble(instr, pc), 1),
+        };
+
+        lines += &format!("0x{:08x}:  {:08x}  {}\n", pc, instr.0, text);
+        for (offset, word) in words[index + 1..index + len].iter().enumerate() {
+            let pc = pc.wrapping_add(4 * (offset as Address + 1));
+            lines += &format!("0x{:08x}:  {:08x}\n", pc, word);
+        }
+        index += len;
+    }
+    lines
+}
+
+/// Lists the ROM file or assembly source given to the `disasm` subcommand.
+pub fn rom_listing(opts: &DisasmOpts) -> Result<String> {
+    let err = || RmipsError::RomLoading(opts.romfile.to_owned());
+    let image = if asm::is_source_file(&opts.romfile) {
+        let source = std::fs::read_to_string(&opts.romfile).map_err(|_| err())?;
+        asm::assemble(&source, opts.base, endian(opts))?.image
+    } else {
+        let image = std::fs::read(&opts.romfile).map_err(|_| err())?;
+        image
+            .get(opts.romoffset as usize..)
+            .ok_or_else(err)?
+            .to_vec()
+    };
+
+    let len = opts
+        .count
+        .map_or(image.len(), |count| count * 4)
+        .min(image.len());
+    Ok(listing(
+        &image[..len],
+        opts.base,
+        endian(opts),
+        !opts.nofold,
+    ))
+}
+
+/// Returns the byte order that the `disasm` subcommand lists the code in.
+fn endian(opts: &DisasmOpts) -> Endian {
+    match opts.bigendian {
+        true => Endian::Big,
+        false => Endian::Little,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn disassemble_instructions() {
+        let pc = 0xbfc0_0000;
+        let cases = [
+            (0x0000_0000, "nop"),
+            (0x0008_4080, "sll $t0, $t0, 2"),
+            (0x012a_4021, "addu $t0, $t1, $t2"),
+            (0x2508_fffc, "addiu $t0, $t0, -4"),
+            (0x3c08_bfc0, "lui $t0, 0xbfc0"),
+            (0x3508_00ff, "ori $t0, $t0, 0xff"),
+            (0x8fa4_0010, "lw $a0, 16($sp)"),
+            (0xa088_ffff, "sb $t0, -1($a0)"),
+            (0x1509_0003, "bne $t0, $t1, 0xbfc00010"),
+            (0x0ff0_0010, "jal 0xbfc00040"),
+            (0x03e0_0008, "jr $ra"),
+            (0x0100_f809, "jalr $t0"),
+            (0x0109_001a, "div $t0, $t1"),
+            (0x0000_1012, "mflo $v0"),
+            (0x4088_6000, "mtc0 $t0, $12"),
+            (0x4101_0002, "bc0t 0xbfc0000c"),
+            (0x4200_0010, "rfe"),
+            (0x4488_1000, "mtc1 $t0, $f2"),
+            (0x4448_f800, "cfc1 $t0, $31"),
+            (0x4501_0002, "bc1t 0xbfc0000c"),
+            (0x4622_2080, "add.d $f2, $f4, $f2"),
+            (0x4600_2021, "cvt.d.s $f0, $f4"),
+            (0x4604_103c, "c.lt.s $f2, $f4"),
+            (0xc482_0008, "lwc1 $f2, 8($a0)"),
+            (0x0000_000d, "break"),
+            (0xfc00_0000, ".word 0xfc000000"),
+        ];
+
+        for (word, expected) in cases.iter() {
+            assert_eq!(disassemble(Instruction(*word), pc), *expected);
+        }
+    }
+
+    #[test]
+    fn listing_endianness() {
+        let image = [0x3c, 0x08, 0xbf, 0xc0, 0x00, 0x00, 0x00, 0x00, 0xff];
+        assert_eq!(
+            listing(&image, 0xbfc0_0000, Endian::Big, true),
+            "0xbfc00000:  3c08bfc0  lui $t0, 0xbfc0\n0xbfc00004:  00000000  nop\n"
+        );
+        assert_eq!(
+            listing(&image[..4], 0x8000_0000, Endian::Little, true),
+            "0x80000000:  c0bf083c  .word 0xc0bf083c\n"
+        );
+    }
+
+    #[test]
+    fn listing_folds_idioms() {
+        let words: [u32; 7] = [
+            0x3c08_bfc0, // lui $t0, 0xbfc0
+            0x2508_fffc, // addiu $t0, $t0, -4
+            0x3c09_1234, // lui $t1, 0x1234
+            0x3508_5678, // ori $t0, $t0, 0x5678
+            0x2402_fffe, // addiu $v0, $zero, -2
+            0x0080_2821, // addu $a1, $a0, $zero
+            0x0000_0000,
+        ];
+        let image: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+        assert_eq!(
+            listing(&image, 0xbfc0_0000, Endian::Little, true),
+            "0xbfc00000:  3c08bfc0  li $t0, 0xbfbffffc\n\
+             0xbfc00004:  2508fffc\n\
+             0xbfc00008:  3c091234  lui $t1, 0x1234\n\
+             0xbfc0000c:  35085678  ori $t0, $t0, 0x5678\n\
+             0xbfc00010:  2402fffe  li $v0, -2\n\
+             0xbfc00014:  00802821  move $a1, $a0\n\
+             0xbfc00018:  00000000  nop\n"
+        );
+        assert!(listing(&image, 0xbfc0_0000, Endian::Little, false)
+            .starts_with("0xbfc00000:  3c08bfc0  lui $t0, 0xbfc0\n0xbfc00004:  2508fffc  addiu"));
+    }
+}
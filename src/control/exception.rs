@@ -3,6 +3,7 @@ use numeric_enum_macro::numeric_enum;
 numeric_enum! {
     #[repr(u32)]
     /// Exception codes that are stored in the `Cause` register.
+    #[allow(clippy::enum_variant_names)]
     #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Ord, Eq, Hash)]
     pub enum Exception {
         /// External interrupt.
@@ -33,6 +34,8 @@ numeric_enum! {
         Overflow = 12,
         /// Trap instruction.
         TrapException = 13,
+        /// Floating-point exception, raised for enabled or unimplemented FPU operations.
+        FloatingPoint = 15,
 
         // 13-31 Reserved on R3000, defined on later processors
 
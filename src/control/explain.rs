@@ -0,0 +1,301 @@
+//! Plain-language descriptions of instructions and their effects for the `--explain` mode.
+
+use crate::control::cpu::Cpu;
+use crate::control::instruction::Instruction;
+use crate::control::registers::REGISTER_NAMES;
+use crate::Address;
+
+/// Returns the ABI name of a general purpose register, e.g. `$t0`.
+fn reg(index: usize) -> String {
+    format!("${}", REGISTER_NAMES[index])
+}
+
+/// Formats the effective address `base + offset` of a load or store.
+fn effective_address(instr: Instruction) -> String {
+    match instr.simmed() {
+        0 => reg(instr.rs()),
+        _ => add_immediate(instr),
+    }
+}
+
+/// Formats `$rs + imm` with the sign-extended immediate.
+fn add_immediate(instr: Instruction) -> String {
+    let imm = instr.simmed() as i32;
+    if imm < 0 {
+        format!("{} - {}", reg(instr.rs()), -(imm as i64))
+    } else {
+        format!("{} + {}", reg(instr.rs()), imm)
+    }
+}
+
+/// Returns the target of a PC-relative branch located at `pc`.
+fn branch_target(instr: Instruction, pc: Address) -> Address {
+    pc.wrapping_add(4).wrapping_add(instr.simmed() << 2)
+}
+
+/// Returns the target of a jump located at `pc`.
+fn jump_target(instr: Instruction, pc: Address) -> Address {
+    (pc.wrapping_add(4) & 0xf000_0000) | (instr.jumptarget() << 2)
+}
+
+/// Describes the operation performed by `instr` when executed at `pc` in one line.
+pub fn describe(instr: Instruction, pc: Address) -> String {
+    let (rs, rt, rd) = (reg(instr.rs()), reg(instr.rt()), reg(instr.rd()));
+    let shamt = instr.shamt();
+    let branch = branch_target(instr, pc);
+
+    match instr.opcode() {
+        0x00 => match instr.funct() {
+            0x00 if instr.0 == 0 => "no operation".to_owned(),
+            0x00 => format!("{} = {} << {}", rd, rt, shamt),
+            0x02 => format!("{} = {} >> {} (logical)", rd, rt, shamt),
+            0x03 => format!("{} = {} >> {} (arithmetic)", rd, rt, shamt),
+            0x04 => format!("{} = {} << ({} & 31)", rd, rt, rs),
+            0x06 => format!("{} = {} >> ({} & 31) (logical)", rd, rt, rs),
+            0x07 => format!("{} = {} >> ({} & 31) (arithmetic)", rd, rt, rs),
+            0x08 => format!("jump to the address in {}", rs),
+            0x09 => format!("{} = return address, jump to the address in {}", rd, rs),
+            0x0c => "raise a system call exception".to_owned(),
+            0x0d => "raise a breakpoint exception".to_owned(),
+            0x10 => format!("{} = hi", rd),
+            0x11 => format!("hi = {}", rs),
+            0x12 => format!("{} = lo", rd),
+            0x13 => format!("lo = {}", rs),
+            0x18 => format!("hi:lo = {} * {} (signed)", rs, rt),
+            0x19 => format!("hi:lo = {} * {} (unsigned)", rs, rt),
+            0x1a => format!("lo = {0} / {1}, hi = {0} % {1} (signed)", rs, rt),
+            0x1b => format!("lo = {0} / {1}, hi = {0} % {1} (unsigned)", rs, rt),
+            0x20 => format!("{} = {} + {} (trap on overflow)", rd, rs, rt),
+            0x21 => format!("{} = {} + {}", rd, rs, rt),
+            0x22 => format!("{} = {} - {} (trap on overflow)", rd, rs, rt),
+            0x23 => format!("{} = {} - {}", rd, rs, rt),
+            0x24 => format!("{} = {} & {}", rd, rs, rt),
+            0x25 => format!("{} = {} | {}", rd, rs, rt),
+            0x26 => format!("{} = {} ^ {}", rd, rs, rt),
+            0x27 => format!("{} = ~({} | {})", rd, rs, rt),
+            0x2a => format!("{} = ({} < {}) ? 1 : 0 (signed)", rd, rs, rt),
+            0x2b => format!("{} = ({} < {}) ? 1 : 0 (unsigned)", rd, rs, rt),
+            _ => "reserved instruction".to_owned(),
+        },
+        0x01 => match instr.rt() {
+            0 => format!("branch to 0x{:08x} if {} < 0", branch, rs),
+            1 => format!("branch to 0x{:08x} if {} >= 0", branch, rs),
+            16 => format!(
+                "$ra = return address, branch to 0x{:08x} if {} < 0",
+                branch, rs
+            ),
+            17 => format!(
+                "$ra = return address, branch to 0x{:08x} if {} >= 0",
+                branch, rs
+            ),
+            _ => "reserved instruction".to_owned(),
+        },
+        0x02 => format!("jump to 0x{:08x}", jump_target(instr, pc)),
+        0x03 => format!(
+            "$ra = return address, jump to 0x{:08x}",
+            jump_target(instr, pc)
+        ),
+        0x04 if instr.rs() == instr.rt() => format!("branch to 0x{:08x}", branch),
+        0x04 => format!("branch to 0x{:08x} if {} == {}", branch, rs, rt),
+        0x05 => format!("branch to 0x{:08x} if {} != {}", branch, rs, rt),
+        0x06 => format!("branch to 0x{:08x} if {} <= 0", branch, rs),
+        0x07 => format!("branch to 0x{:08x} if {} > 0", branch, rs),
+        0x08 => format!("{} = {} (trap on overflow)", rt, add_immediate(instr)),
+        0x09 => format!("{} = {}", rt, add_immediate(instr)),
+        0x0a => format!(
+            "{} = ({} < {}) ? 1 : 0 (signed)",
+            rt,
+            rs,
+            instr.simmed() as i32
+        ),
+        0x0b => format!(
+            "{} = ({} < {}) ? 1 : 0 (unsigned)",
+            rt,
+            rs,
+            instr.simmed() as i32
+        ),
+        0x0c => format!("{} = {} & 0x{:04x}", rt, rs, instr.immed()),
+        0x0d => format!("{} = {} | 0x{:04x}", rt, rs, instr.immed()),
+        0x0e => format!("{} = {} ^ 0x{:04x}", rt, rs, instr.immed()),
+        0x0f => format!("{} = 0x{:04x} << 16", rt, instr.immed()),
+        0x10 => match instr.rs() {
+            0 => format!("{} = CP0 register {}", rt, instr.rd()),
+            4 => format!("CP0 register {} = {}", instr.rd(), rt),
+            8 => format!("branch to 0x{:08x} on CP0 condition", branch),
+            rs if rs > 15 => match instr.funct() {
+                1 => "load EntryHi and EntryLo from the TLB entry selected by Index".to_owned(),
+                2 => "write EntryHi and EntryLo to the TLB entry selected by Index".to_owned(),
+                6 => "write EntryHi and EntryLo to the TLB entry selected by Random".to_owned(),
+                8 => "search the TLB for EntryHi and store the match in Index".to_owned(),
+                16 => "restore the previous interrupt and kernel mode bits".to_owned(),
+                _ => "reserved instruction".to_owned(),
+            },
+            _ => "reserved instruction".to_owned(),
+        },
+        op @ 0x11..=0x13 => format!("coprocessor {} operation", op & 3),
+        0x20 => format!(
+            "{} = sign-extended byte at ({})",
+            rt,
+            effective_address(instr)
+        ),
+        0x21 => format!(
+            "{} = sign-extended halfword at ({})",
+            rt,
+            effective_address(instr)
+        ),
+        0x22 => format!(
+            "merge the left part of the unaligned word at ({}) into {}",
+            effective_address(instr),
+            rt
+        ),
+        0x23 => format!("{} = word at ({})", rt, effective_address(instr)),
+        0x24 => format!(
+            "{} = zero-extended byte at ({})",
+            rt,
+            effective_address(instr)
+        ),
+        0x25 => format!(
+            "{} = zero-extended halfword at ({})",
+            rt,
+            effective_address(instr)
+        ),
+        0x26 => format!(
+            "merge the right part of the unaligned word at ({}) into {}",
+            effective_address(instr),
+            rt
+        ),
+        0x28 => format!(
+            "byte at ({}) = low byte of {}",
+            effective_address(instr),
+            rt
+        ),
+        0x29 => format!(
+            "halfword at ({}) = low halfword of {}",
+            effective_address(instr),
+            rt
+        ),
+        0x2a => format!(
+            "store the left part of {} to the unaligned word at ({})",
+            rt,
+            effective_address(instr)
+        ),
+        0x2b => format!("word at ({}) = {}", effective_address(instr), rt),
+        0x2e => format!(
+            "store the right part of {} to the unaligned word at ({})",
+            rt,
+            effective_address(instr)
+        ),
+        op @ 0x31..=0x33 => format!(
+            "CP{} register {} = word at ({})",
+            op & 3,
+            instr.rt(),
+            effective_address(instr)
+        ),
+        op @ 0x39..=0x3b => format!(
+            "word at ({}) = CP{} register {}",
+            effective_address(instr),
+            op & 3,
+            instr.rt()
+        ),
+        _ => "reserved instruction".to_owned(),
+    }
+}
+
+/// The user-visible `Cpu` registers, captured before a step so the changes can be reported.
+pub struct CpuSnapshot {
+    pc: Address,
+    reg: [u32; 32],
+    high: u32,
+    low: u32,
+}
+
+impl CpuSnapshot {
+    pub fn capture(cpu: &Cpu) -> Self {
+        Self {
+            pc: cpu.pc,
+            reg: cpu.reg,
+            high: cpu.high,
+            low: cpu.low,
+        }
+    }
+
+    /// Returns the program counter at the time of the snapshot.
+    pub fn pc(&self) -> Address {
+        self.pc
+    }
+
+    /// Lists the registers that differ in `cpu` as `name: old → new` lines.
+    ///
+    /// The program counter is only reported when it did not simply advance to the next instruction.
+    pub fn changes(&self, cpu: &Cpu) -> Vec<String> {
+        let mut changes: Vec<String> = (0..32)
+            .filter(|&i| self.reg[i] != cpu.reg[i])
+            .map(|i| format!("{}: 0x{:08x} → 0x{:08x}", reg(i), self.reg[i], cpu.reg[i]))
+            .collect();
+
+        if self.high != cpu.high {
+            changes.push(format!("hi: 0x{:08x} → 0x{:08x}", self.high, cpu.high));
+        }
+        if self.low != cpu.low {
+            changes.push(format!("lo: 0x{:08x} → 0x{:08x}", self.low, cpu.low));
+        }
+        if cpu.pc != self.pc.wrapping_add(4) {
+            changes.push(format!("pc: 0x{:08x} → 0x{:08x}", self.pc, cpu.pc));
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::registers::Register;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn describe_instructions() {
+        let pc = 0xbfc0_0000;
+        let cases = [
+            (0x0000_0000, "no operation"),
+            (0x012a_4021, "$t0 = $t1 + $t2"),
+            (0x2508_fffc, "$t0 = $t0 - 4"),
+            (0x3c08_bfc0, "$t0 = 0xbfc0 << 16"),
+            (0x8fa4_0010, "$a0 = word at ($sp + 16)"),
+            (0xa088_ffff, "byte at ($a0 - 1) = low byte of $t0"),
+            (0x1509_0003, "branch to 0xbfc00010 if $t0 != $t1"),
+            (0x0ff0_0010, "$ra = return address, jump to 0xbfc00040"),
+            (0x03e0_0008, "jump to the address in $ra"),
+            (0x0109_001a, "lo = $t0 / $t1, hi = $t0 % $t1 (signed)"),
+            (0xfc00_0000, "reserved instruction"),
+        ];
+
+        for (word, expected) in cases.iter() {
+            assert_eq!(describe(Instruction(*word), pc), *expected);
+        }
+    }
+
+    #[test]
+    fn snapshot_changes() {
+        let mut cpu = Cpu::new(false);
+        cpu.pc = 0xbfc0_0000;
+        let snapshot = CpuSnapshot::capture(&cpu);
+
+        cpu.reg[Register::T0] = 5;
+        cpu.low = 1;
+        cpu.pc += 4;
+        assert_eq!(
+            snapshot.changes(&cpu),
+            vec![
+                "$t0: 0x00000000 → 0x00000005".to_owned(),
+                "lo: 0x00000000 → 0x00000001".to_owned(),
+            ]
+        );
+
+        cpu.pc = 0x8000_0080;
+        assert_eq!(
+            snapshot.changes(&cpu).last().unwrap(),
+            "pc: 0xbfc00000 → 0x80000080"
+        );
+    }
+}
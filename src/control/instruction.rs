@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::control::isa::Op;
+
 /// Represents a 32-bit MIPS instruction and its fields.
 #[derive(Copy, Clone, Default, PartialEq, PartialOrd)]
 pub struct Instruction(pub u32);
@@ -50,6 +52,40 @@ impl Instruction {
     pub fn jumptarget(&self) -> u32 {
         self.0 & 0x03ffffff
     }
+
+    /// Returns the assembler mnemonic, or `"reserved"` for encodings that are not in the
+    /// instruction table.
+    pub fn mnemonic(&self) -> &'static str {
+        match Op::decode(*self) {
+            _ if self.0 == 0 => "nop",
+            Some(op) => op.spec().mnemonic,
+            None => "reserved",
+        }
+    }
+
+    /// Returns the access width in bytes and whether it is a store, for loads and stores.
+    pub fn data_access(&self) -> Option<(u32, bool)> {
+        match self.opcode() {
+            0x20 | 0x24 => Some((1, false)),
+            0x21 | 0x25 => Some((2, false)),
+            0x22 | 0x23 | 0x26 | 0x31..=0x33 => Some((4, false)),
+            0x28 => Some((1, true)),
+            0x29 => Some((2, true)),
+            0x2a | 0x2b | 0x2e | 0x39..=0x3b => Some((4, true)),
+            _ => None,
+        }
+    }
+
+    /// Returns true for branches and jumps, which are followed by a delay slot.
+    pub fn is_control_transfer(&self) -> bool {
+        match self.opcode() {
+            0x00 => matches!(self.funct(), 0x08 | 0x09),
+            0x01 => matches!(self.rt(), 0 | 1 | 16 | 17),
+            0x02..=0x07 => true,
+            0x10..=0x13 => self.rs() == 8,
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Debug for Instruction {
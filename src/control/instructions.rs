@@ -5,9 +5,9 @@ use crate::control::cpu::{Cpu, DelayState};
 use crate::control::exception::Exception;
 use crate::control::instruction::Instruction;
 use crate::control::registers::{Cp0Register, Register};
-use crate::memory::Memory;
+use crate::memory::{AccessContext, Memory};
 use crate::util::error::Result;
-use crate::Address;
+use crate::{Address, Endian};
 
 impl Cpu {
     /// Shift left logical
@@ -50,7 +50,7 @@ impl Cpu {
         let target_address = self.reg[instr.rs()];
         self.control_transfer(target_address);
 
-        self.reg[instr.rd()] = self.pc + 8;
+        self.reg[instr.rd()] = self.return_address();
     }
 
     /// System call
@@ -119,15 +119,16 @@ impl Cpu {
 
     /// Addition with overflow
     pub fn add_emulate(&mut self, instr: Instruction) -> Result<()> {
-        let rs = self.reg[instr.rs()];
-        let rt = self.reg[instr.rt()];
-        let (result, carry) = rs.overflowing_add(rt);
+        // Overflow is determined by the two's complement result, not the unsigned carry
+        let rs = self.reg[instr.rs()] as i32;
+        let rt = self.reg[instr.rt()] as i32;
 
-        if carry {
-            self.exception(Exception::Overflow)
-        } else {
-            self.reg[instr.rd()] = result;
-            Ok(())
+        match rs.checked_add(rt) {
+            Some(result) => {
+                self.reg[instr.rd()] = result as u32;
+                Ok(())
+            }
+            None => self.exception(Exception::Overflow),
         }
     }
 
@@ -140,15 +141,15 @@ impl Cpu {
 
     /// Subtract with overflow
     pub fn sub_emulate(&mut self, instr: Instruction) -> Result<()> {
-        let rs = self.reg[instr.rs()];
-        let rt = self.reg[instr.rt()];
-        let (result, carry) = rs.overflowing_sub(rt);
+        let rs = self.reg[instr.rs()] as i32;
+        let rt = self.reg[instr.rt()] as i32;
 
-        if carry {
-            self.exception(Exception::Overflow)
-        } else {
-            self.reg[instr.rd()] = result;
-            Ok(())
+        match rs.checked_sub(rt) {
+            Some(result) => {
+                self.reg[instr.rd()] = result as u32;
+                Ok(())
+            }
+            None => self.exception(Exception::Overflow),
         }
     }
 
@@ -231,7 +232,7 @@ impl Cpu {
 
     /// Branch on less than zero and link
     pub fn bltzal_emulate(&mut self, instr: Instruction) {
-        self.reg[Register::Ra] = self.pc + 8;
+        self.reg[Register::Ra] = self.return_address();
 
         if (self.reg[instr.rs()] as i32) < 0 {
             self.branch(instr);
@@ -240,7 +241,7 @@ impl Cpu {
 
     /// Branch on greater than or equal to zero and link
     pub fn bgezal_emulate(&mut self, instr: Instruction) {
-        self.reg[Register::Ra] = self.pc + 8;
+        self.reg[Register::Ra] = self.return_address();
 
         if 0 <= (self.reg[instr.rs()] as i32) {
             self.branch(instr);
@@ -279,15 +280,15 @@ impl Cpu {
 
     /// Add immediate (with overflow)
     pub fn addi_emulate(&mut self, instr: Instruction) -> Result<()> {
-        let rs = self.reg[instr.rs()];
-        let imm = instr.simmed();
-        let (result, carry) = rs.overflowing_add(imm);
-
-        if carry {
-            self.exception(Exception::Overflow)
-        } else {
-            self.reg[instr.rt()] = result;
-            Ok(())
+        let rs = self.reg[instr.rs()] as i32;
+        let imm = instr.simmed() as i32;
+
+        match rs.checked_add(imm) {
+            Some(result) => {
+                self.reg[instr.rt()] = result as u32;
+                Ok(())
+            }
+            None => self.exception(Exception::Overflow),
         }
     }
 
@@ -320,11 +321,12 @@ impl Cpu {
 
     /// Load byte
     pub fn lb_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
-        let base = self.reg[instr.rs()];
-        let offset = instr.simmed();
-        let vaddress = base + offset;
+        let vaddress = self.effective_address(instr);
 
-        let paddress = self.cpzero.translate(vaddress);
+        let Some(paddress) = self.translate(vaddress, AccessContext::CpuLoad)? else {
+            return Ok(());
+        };
+        let paddress = self.data_address(paddress, 1);
         let data = memory.fetch_byte(paddress)? as i8; // Sign-extend the byte first
         self.reg[instr.rt()] = data as u32;
         Ok(())
@@ -332,16 +334,16 @@ impl Cpu {
 
     /// Load halfword
     pub fn lh_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
-        // Calculate the virtual address
-        let base = self.reg[instr.rs()];
-        let offset = instr.simmed();
-        let vaddress = base + offset;
+        let vaddress = self.effective_address(instr);
 
         // Check for a halfword-aligned address
-        if vaddress % 2 != 0 {
-            self.exception(Exception::AddressLoadError)
+        if !vaddress.is_multiple_of(2) {
+            self.address_error(Exception::AddressLoadError, vaddress)
         } else {
-            let paddress = self.cpzero.translate(vaddress);
+            let Some(paddress) = self.translate(vaddress, AccessContext::CpuLoad)? else {
+                return Ok(());
+            };
+            let paddress = self.data_address(paddress, 2);
             let data = memory.fetch_halfword(paddress)? as i16; // Sign-extend the word first
             self.reg[instr.rt()] = data as u32;
             Ok(())
@@ -349,23 +351,22 @@ impl Cpu {
     }
 
     /// Load word left
-    pub fn lwl_emulate(&mut self, _instr: Instruction) {
-        todo!()
+    pub fn lwl_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        self.load_partial_word(memory, instr, true)
     }
 
     /// Load word
     pub fn lw_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
-        // Calculate the virtual address
-        let base = self.reg[instr.rs()];
-        let offset = instr.simmed();
-        let vaddress = base + offset;
+        let vaddress = self.effective_address(instr);
 
         // If either of the two least-significant bits of the virtual address
         // are non-zero a load address exception occurs
-        if vaddress % 4 != 0 {
-            self.exception(Exception::AddressLoadError)
+        if !vaddress.is_multiple_of(4) {
+            self.address_error(Exception::AddressLoadError, vaddress)
         } else {
-            let paddress = self.cpzero.translate(vaddress);
+            let Some(paddress) = self.translate(vaddress, AccessContext::CpuLoad)? else {
+                return Ok(());
+            };
             let data = memory.fetch_word(paddress)?;
             self.reg[instr.rt()] = data;
             Ok(())
@@ -374,11 +375,12 @@ impl Cpu {
 
     /// Load byte unsigned
     pub fn lbu_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
-        let base = self.reg[instr.rs()];
-        let offset = instr.simmed();
-        let vaddress = base + offset;
+        let vaddress = self.effective_address(instr);
 
-        let paddress = self.cpzero.translate(vaddress);
+        let Some(paddress) = self.translate(vaddress, AccessContext::CpuLoad)? else {
+            return Ok(());
+        };
+        let paddress = self.data_address(paddress, 1);
         let data = memory.fetch_byte(paddress)?;
         self.reg[instr.rt()] = data.into(); // Zero-extend the byte
         Ok(())
@@ -386,15 +388,16 @@ impl Cpu {
 
     /// Load halfword unsigned
     pub fn lhu_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
-        let base = self.reg[instr.rs()];
-        let offset = instr.simmed();
-        let vaddress = base + offset;
+        let vaddress = self.effective_address(instr);
 
         // Check for a halfword-aligned address
-        if vaddress % 2 != 0 {
-            self.exception(Exception::AddressLoadError)
+        if !vaddress.is_multiple_of(2) {
+            self.address_error(Exception::AddressLoadError, vaddress)
         } else {
-            let paddress = self.cpzero.translate(vaddress);
+            let Some(paddress) = self.translate(vaddress, AccessContext::CpuLoad)? else {
+                return Ok(());
+            };
+            let paddress = self.data_address(paddress, 2);
             let data = memory.fetch_halfword(paddress)?;
             self.reg[instr.rt()] = data.into();
             Ok(())
@@ -402,94 +405,149 @@ impl Cpu {
     }
 
     /// Load word right
-    pub fn lwr_emulate(&mut self, _instr: Instruction) {
-        todo!()
+    pub fn lwr_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        self.load_partial_word(memory, instr, false)
     }
 
     /// Store byte
     pub fn sb_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
         let data = self.reg[instr.rt()] as u8;
-        let base = self.reg[instr.rs()];
-        let offset = instr.simmed();
-        let vaddress = base + offset;
-        let paddress = self.cpzero.translate(vaddress);
+        let vaddress = self.effective_address(instr);
+        let Some(paddress) = self.translate(vaddress, AccessContext::CpuStore)? else {
+            return Ok(());
+        };
+        let paddress = self.data_address(paddress, 1);
         memory.store_byte(paddress, data)
     }
 
     /// Store halfword
     pub fn sh_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
         let data = self.reg[instr.rt()] as u16;
-        let base = self.reg[instr.rs()];
-        let offset = instr.simmed();
-        let vaddress = base + offset;
+        let vaddress = self.effective_address(instr);
 
         // If the least-significant bit of the virtual address
         // is non-zero, a store address exception occurs
-        if vaddress % 2 != 0 {
-            self.exception(Exception::AddressStoreError)?;
+        if !vaddress.is_multiple_of(2) {
+            self.address_error(Exception::AddressStoreError, vaddress)?;
         } else {
-            let paddress = self.cpzero.translate(vaddress);
+            let Some(paddress) = self.translate(vaddress, AccessContext::CpuStore)? else {
+                return Ok(());
+            };
+            let paddress = self.data_address(paddress, 2);
             memory.store_halfword(paddress, data)?;
         }
         Ok(())
     }
 
     /// Store word left
-    pub fn swl_emulate(&mut self, _instr: Instruction) {
-        todo!()
+    pub fn swl_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        self.store_partial_word(memory, instr, true)
     }
 
     /// Store word
     pub fn sw_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
         let data = self.reg[instr.rt()];
-        let base = self.reg[instr.rs()];
-        let offset = instr.simmed();
-        let vaddress = base + offset;
+        let vaddress = self.effective_address(instr);
 
         // If either of the two least-significant bits of the virtual address
         // are non-zero, a store address exception occurs
-        if vaddress % 4 != 0 {
-            self.exception(Exception::AddressStoreError)?;
+        if !vaddress.is_multiple_of(4) {
+            self.address_error(Exception::AddressStoreError, vaddress)?;
         } else {
-            let paddress = self.cpzero.translate(vaddress);
+            let Some(paddress) = self.translate(vaddress, AccessContext::CpuStore)? else {
+                return Ok(());
+            };
             memory.store_word(paddress, data)?;
         }
         Ok(())
     }
 
     /// Store word right
-    pub fn swr_emulate(&mut self, _instr: Instruction) {
-        todo!()
+    pub fn swr_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        self.store_partial_word(memory, instr, false)
+    }
+
+    /// Cache operation, which the instruction table only lets MIPS III models execute.
+    ///
+    /// Only the operations on the primary instruction cache matter, as memory is always up to
+    /// date. They drop the line that the effective address maps to, whether it is selected by
+    /// index or by a hit.
+    pub fn cache_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        if !self.cpzero.cp0_accessible() {
+            return self.coprocessor_unusable(0);
+        }
+        // The low bits of the operation select the cache, 0 is the primary instruction cache
+        if instr.rt() & 3 != 0 {
+            return Ok(());
+        }
+
+        let vaddress = self.effective_address(instr);
+        let Some(paddress) = self.translate(vaddress, AccessContext::CpuLoad)? else {
+            return Ok(());
+        };
+        let line = self.cpzero.model().icache_line();
+        memory.invalidate_instructions(paddress & !(line as Address - 1), line);
+        Ok(())
     }
 
-    /// Load word from CP1
-    pub fn lwc1_emulate(&mut self, instr: Instruction) -> Result<()> {
-        self.coprocessor_unimpl(1, instr)
+    /// Load word to CP1
+    pub fn lwc1_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        self.lwcz(1, memory, instr)
     }
 
-    /// Load word from CP2
-    pub fn lwc2_emulate(&mut self, instr: Instruction) -> Result<()> {
-        self.coprocessor_unimpl(1, instr)
+    /// Load word to CP2
+    pub fn lwc2_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        self.lwcz(2, memory, instr)
     }
 
-    /// Load word from CP3
-    pub fn lwc3_emulate(&mut self, instr: Instruction) -> Result<()> {
-        self.coprocessor_unimpl(1, instr)
+    /// Load word to CP3
+    pub fn lwc3_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        self.lwcz(3, memory, instr)
     }
 
     /// Store word from CP1
-    pub fn swc1_emulate(&mut self, instr: Instruction) -> Result<()> {
-        self.coprocessor_unimpl(1, instr)
+    pub fn swc1_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        self.swcz(1, memory, instr)
     }
 
     /// Store word from CP2
-    pub fn swc2_emulate(&mut self, instr: Instruction) -> Result<()> {
-        self.coprocessor_unimpl(2, instr)
+    pub fn swc2_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        self.swcz(2, memory, instr)
     }
 
     /// Store word from CP3
-    pub fn swc3_emulate(&mut self, instr: Instruction) -> Result<()> {
-        self.coprocessor_unimpl(3, instr)
+    pub fn swc3_emulate(&mut self, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        self.swcz(3, memory, instr)
+    }
+
+    /// Coprocessor instruction for CP1-CP3, a move, a branch on the condition signal or an
+    /// operation that the coprocessor executes itself.
+    pub fn copz_emulate(&mut self, coprocno: u32, instr: Instruction) -> Result<()> {
+        let (rt, rd) = (instr.rt(), instr.rd());
+        let value = self.reg[rt];
+        let Some(coprocessor) = self.coprocessor_mut(coprocno) else {
+            return self.coprocessor_unimpl(coprocno, instr);
+        };
+
+        match instr.rs() {
+            0 => self.reg[rt] = coprocessor.read_register(rd),
+            2 => self.reg[rt] = coprocessor.read_control(rd),
+            4 => coprocessor.write_register(rd, value),
+            6 => coprocessor.write_control(rd, value),
+            // BCzF and BCzT, only the low bit of rt selects the branch
+            8 => {
+                if coprocessor.condition() == (rt & 1 == 1) {
+                    self.branch(instr);
+                }
+            }
+            rs if rs > 15 => {
+                if let Err(exception) = coprocessor.execute(instr) {
+                    self.exception(exception)?;
+                }
+            }
+            _ => self.exception(Exception::ReservedInstruction)?,
+        }
+        Ok(())
     }
 
     /// Jump
@@ -502,7 +560,7 @@ impl Cpu {
         self.jump(instr);
 
         // Store the address of the instruction after the delay slot in the return address register
-        self.reg[Register::Ra] = self.pc + 8;
+        self.reg[Register::Ra] = self.return_address();
     }
 
     /// Move From System Control Coprocessor
@@ -513,13 +571,16 @@ impl Cpu {
             Cp0Register::Index => self.cpzero.index.into(),
             Cp0Register::Random => self.cpzero.random.into(),
             Cp0Register::EntryLo => self.cpzero.entrylo,
+            Cp0Register::EntryLo1 => self.cpzero.entrylo1,
             Cp0Register::Context => self.cpzero.context.into(),
+            Cp0Register::PageMask => self.cpzero.pagemask,
             Cp0Register::BadVaddr => self.cpzero.badvaddr.into(),
             Cp0Register::EntryHi => self.cpzero.entryhi,
             Cp0Register::Status => self.cpzero.status.into(),
             Cp0Register::Cause => self.cpzero.cause.into(),
             Cp0Register::Epc => self.cpzero.epc.into(),
             Cp0Register::Prid => self.cpzero.prid.into(),
+            Cp0Register::Config => self.cpzero.config.into(),
         };
     }
 
@@ -529,18 +590,7 @@ impl Cpu {
         let rd = Cp0Register::try_from(instr.rd() as u32)
             .expect("invalid cp0 register number encountered");
 
-        match rd {
-            Cp0Register::Index => self.cpzero.index = rt.into(),
-            Cp0Register::Random => self.cpzero.random = rt.into(),
-            Cp0Register::EntryLo => self.cpzero.entrylo = rt,
-            Cp0Register::Context => self.cpzero.context = rt.into(),
-            Cp0Register::BadVaddr => self.cpzero.badvaddr = rt.into(),
-            Cp0Register::EntryHi => self.cpzero.entryhi = rt,
-            Cp0Register::Status => self.cpzero.status = rt.into(),
-            Cp0Register::Cause => self.cpzero.cause = rt.into(),
-            Cp0Register::Epc => self.cpzero.epc = rt.into(),
-            Cp0Register::Prid => self.cpzero.prid = rt.into(),
-        }
+        self.cpzero.write_register(rd, rt);
     }
 
     /// Reserved instruction
@@ -566,16 +616,163 @@ impl Cpu {
         }
     }
 
+    /// Calculates the virtual address used by loads and stores.
+    /// The sign-extended offset is allowed to wrap around the address space.
+    fn effective_address(&self, instr: Instruction) -> Address {
+        self.reg[instr.rs()].wrapping_add(instr.simmed())
+    }
+
+    /// Adjusts the physical address of a `len`-byte data access for reverse-endian mode.
+    ///
+    /// When the RE bit is set in user mode, byte and halfword accesses select the
+    /// opposite lanes of the word, which gives data accesses of the other byte order
+    /// than the bus. Word accesses are unaffected.
+    fn data_address(&self, paddress: Address, len: Address) -> Address {
+        if self.cpzero.reverse_endian() {
+            paddress ^ (4 - len)
+        } else {
+            paddress
+        }
+    }
+
+    /// Stores the part of `rt` that SWL (`left`) or SWR places in the word containing the
+    /// unaligned effective address, leaving the other bytes of the word unchanged.
+    ///
+    /// The addressed byte is found in its lane of the bus word, which already accounts for
+    /// reverse-endian mode. Lanes are numbered by significance, so lane 0 holds the least
+    /// significant byte at the lowest address on a little-endian bus and at the highest on a
+    /// big-endian one. SWL stores the most significant bytes of `rt` from the addressed lane
+    /// down to the least significant lane, and SWR the least significant bytes from that lane
+    /// up to the most significant one.
+    fn store_partial_word(
+        &mut self,
+        memory: &mut impl Memory,
+        instr: Instruction,
+        left: bool,
+    ) -> Result<()> {
+        let data = self.reg[instr.rt()];
+        let vaddress = self.effective_address(instr);
+        let Some(paddress) = self.translate(vaddress, AccessContext::CpuStore)? else {
+            return Ok(());
+        };
+        let paddress = self.data_address(paddress, 1);
+        let (word, offset) = (paddress & !3, paddress & 3);
+        let endian = memory.endian();
+        let byte_offset = |lane: Address| match endian {
+            Endian::Big => 3 - lane,
+            Endian::Little => lane,
+        };
+        let lane = byte_offset(offset);
+
+        // Index of the byte of `rt` that is stored in the lane of the addressed byte
+        let (lanes, first) = match left {
+            true => (0..=lane, 3),
+            false => (lane..=3, 0),
+        };
+        if lanes.clone().count() == 4 {
+            return memory.store_word(word, data);
+        }
+        for target in lanes {
+            let byte = data >> (8 * (target + first - lane));
+            memory.store_byte(word + byte_offset(target), byte as u8)?;
+        }
+        Ok(())
+    }
+
+    /// Merges the bytes of the aligned word holding the effective address into `rt`, the
+    /// loading counterpart of `store_partial_word` that uses the same byte lanes.
+    fn load_partial_word(
+        &mut self,
+        memory: &mut impl Memory,
+        instr: Instruction,
+        left: bool,
+    ) -> Result<()> {
+        let vaddress = self.effective_address(instr);
+        let Some(paddress) = self.translate(vaddress, AccessContext::CpuLoad)? else {
+            return Ok(());
+        };
+        let paddress = self.data_address(paddress, 1);
+        let (word, offset) = (paddress & !3, paddress & 3);
+        let endian = memory.endian();
+        let byte_offset = |lane: Address| match endian {
+            Endian::Big => 3 - lane,
+            Endian::Little => lane,
+        };
+        let lane = byte_offset(offset);
+
+        // Index of the byte of `rt` that is loaded from the lane of the addressed byte
+        let (lanes, first) = match left {
+            true => (0..=lane, 3),
+            false => (lane..=3, 0),
+        };
+        if lanes.clone().count() == 4 {
+            self.reg[instr.rt()] = memory.fetch_word(word)?;
+            return Ok(());
+        }
+        let mut data = self.reg[instr.rt()];
+        for target in lanes {
+            let shift = 8 * (target + first - lane);
+            let byte = memory.fetch_byte(word + byte_offset(target))?;
+            data = (data & !(0xff << shift)) | (u32::from(byte) << shift);
+        }
+        self.reg[instr.rt()] = data;
+        Ok(())
+    }
+
+    /// Loads a word from memory into general register `rt` of the given coprocessor.
+    fn lwcz(&mut self, coprocno: u32, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        if self.coprocessor_mut(coprocno).is_none() {
+            return self.coprocessor_unusable(coprocno);
+        }
+
+        let vaddress = self.effective_address(instr);
+        if !vaddress.is_multiple_of(4) {
+            return self.address_error(Exception::AddressLoadError, vaddress);
+        }
+
+        let Some(paddress) = self.translate(vaddress, AccessContext::CpuLoad)? else {
+            return Ok(());
+        };
+        let data = memory.fetch_word(paddress)?;
+        if let Some(coprocessor) = self.coprocessor_mut(coprocno) {
+            coprocessor.write_register(instr.rt(), data);
+        }
+        Ok(())
+    }
+
+    /// Stores general register `rt` of the given coprocessor into memory.
+    fn swcz(&mut self, coprocno: u32, memory: &mut impl Memory, instr: Instruction) -> Result<()> {
+        let data = match self.coprocessor_mut(coprocno) {
+            Some(coprocessor) => coprocessor.read_register(instr.rt()),
+            None => return self.coprocessor_unusable(coprocno),
+        };
+
+        let vaddress = self.effective_address(instr);
+        if !vaddress.is_multiple_of(4) {
+            return self.address_error(Exception::AddressStoreError, vaddress);
+        }
+
+        let Some(paddress) = self.translate(vaddress, AccessContext::CpuStore)? else {
+            return Ok(());
+        };
+        memory.store_word(paddress, data)
+    }
+
+    /// Returns the address of the instruction following the delay slot.
+    fn return_address(&self) -> Address {
+        self.pc.wrapping_add(8)
+    }
+
     fn branch(&mut self, instr: Instruction) {
         // Calculate the target address for the PC-relative branch
         let offset = instr.simmed() << 2;
-        let target_address = (self.pc + 4).wrapping_add(offset);
+        let target_address = self.pc.wrapping_add(4).wrapping_add(offset);
         self.control_transfer(target_address);
     }
 
     fn jump(&mut self, instr: Instruction) {
         // Calculate the address to jump to as the result of a J-format instruction
-        let target_address = ((self.pc + 4) & 0xf000_0000) | (instr.jumptarget() << 2);
+        let target_address = (self.pc.wrapping_add(4) & 0xf000_0000) | (instr.jumptarget() << 2);
         self.control_transfer(target_address);
     }
 
@@ -587,9 +784,103 @@ impl Cpu {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
+    use bit_field::BitField;
+
     use super::*;
+    use crate::control::coprocessor::Coprocessor;
+    use crate::control::cpzero::CPZero;
+    use crate::control::model::CpuModel;
     use pretty_assertions::assert_eq;
 
+    /// Sparse little-endian memory for exercising loads and stores.
+    #[derive(Default)]
+    struct TestMemory {
+        data: HashMap<Address, u8>,
+        /// Instruction ranges invalidated by cache operations.
+        invalidated: Vec<(Address, usize)>,
+    }
+
+    impl TestMemory {
+        fn read(&self, address: Address, len: u32) -> u32 {
+            (0..len).rev().fold(0, |acc, i| {
+                let byte = self.data.get(&address.wrapping_add(i)).copied();
+                (acc << 8) | byte.unwrap_or(0) as u32
+            })
+        }
+
+        fn write(&mut self, address: Address, data: u32, len: u32) {
+            for i in 0..len {
+                self.data
+                    .insert(address.wrapping_add(i), (data >> (i * 8)) as u8);
+            }
+        }
+    }
+
+    /// Returns a reset `Cpu` whose TLB maps the first and the last page of the address space
+    /// to the same physical addresses, so loads and stores can wrap around zero.
+    fn mapped_cpu() -> Cpu {
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        for (index, page) in [0, 0xffff_f000_u32].iter().enumerate() {
+            cpu.cpzero.write_index((index as u32) << 8);
+            cpu.cpzero.entryhi = *page;
+            cpu.cpzero.entrylo = page | 0x700; // Dirty, valid and global
+            cpu.cpzero.tlbwi_emulate();
+        }
+        cpu
+    }
+
+    /// Coprocessor with a plain general register file.
+    #[derive(Debug, Default)]
+    struct TestCoprocessor {
+        reg: [u32; 32],
+    }
+
+    impl Coprocessor for TestCoprocessor {
+        fn read_register(&self, reg: usize) -> u32 {
+            self.reg[reg]
+        }
+
+        fn write_register(&mut self, reg: usize, value: u32) {
+            self.reg[reg] = value;
+        }
+    }
+
+    impl Memory for TestMemory {
+        fn fetch_word(&mut self, address: Address) -> Result<u32> {
+            Ok(self.read(address, 4))
+        }
+
+        fn fetch_halfword(&mut self, address: Address) -> Result<u16> {
+            Ok(self.read(address, 2) as u16)
+        }
+
+        fn fetch_byte(&mut self, address: Address) -> Result<u8> {
+            Ok(self.read(address, 1) as u8)
+        }
+
+        fn store_word(&mut self, address: Address, data: u32) -> Result<()> {
+            self.write(address, data, 4);
+            Ok(())
+        }
+
+        fn store_halfword(&mut self, address: Address, data: u16) -> Result<()> {
+            self.write(address, data.into(), 2);
+            Ok(())
+        }
+
+        fn store_byte(&mut self, address: Address, data: u8) -> Result<()> {
+            self.write(address, data.into(), 1);
+            Ok(())
+        }
+
+        fn invalidate_instructions(&mut self, address: Address, len: usize) {
+            self.invalidated.push((address, len));
+        }
+    }
+
     #[test]
     fn sll_emulate() {
         let mut cpu = Cpu::new(false);
@@ -643,6 +934,19 @@ mod tests {
         assert_eq!(cpu.delay_state, DelayState::Delaying);
     }
 
+    #[test]
+    fn jalr_emulate_wraps_return_address() {
+        let mut cpu = Cpu::new(false);
+        cpu.pc = 0xffff_fff8;
+
+        let instr = Instruction(0x0040f809);
+        cpu.reg[instr.rs()] = 0xbfc0_0000;
+        cpu.jalr_emulate(instr);
+
+        assert_eq!(cpu.reg[Register::Ra], 0);
+        assert_eq!(cpu.delay_pc, 0xbfc0_0000);
+    }
+
     #[test]
     fn syscall_emulate() {}
 
@@ -739,7 +1043,7 @@ mod tests {
         let mut cpu = Cpu::new(false);
         let instr = Instruction(0x0109001a);
 
-        cpu.reg[instr.rs()] = -16 as i32 as u32;
+        cpu.reg[instr.rs()] = -16_i32 as u32;
         cpu.reg[instr.rt()] = 4;
         cpu.div_emulate(instr);
 
@@ -791,7 +1095,7 @@ mod tests {
         let mut cpu = Cpu::new(false);
         let instr = Instruction(0x0109001b);
 
-        cpu.reg[instr.rs()] = -16 as i32 as u32;
+        cpu.reg[instr.rs()] = -16_i32 as u32;
         cpu.reg[instr.rt()] = 4;
         cpu.divu_emulate(instr);
 
@@ -813,17 +1117,44 @@ mod tests {
     }
 
     #[test]
-    fn add_emulate() {}
+    fn add_emulate() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let instr = Instruction(0x00a62020);
+        cpu.reg[instr.rt()] = 0xffff_0fff;
+        cpu.reg[instr.rs()] = 0x0001_0000;
+        cpu.add_emulate(instr)?;
+        assert_eq!(cpu.reg[instr.rd()], 0x0000_0fff);
+        assert_eq!(cpu.exception_pending, false);
+        Ok(())
+    }
+
+    #[test]
+    fn add_emulate_negative() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let instr = Instruction(0x00a62020);
+        cpu.reg[instr.rt()] = 1;
+        cpu.reg[instr.rs()] = 0xffff_ffff;
+        cpu.add_emulate(instr)?;
+        assert_eq!(cpu.reg[instr.rd()], 0);
+        assert_eq!(cpu.exception_pending, false);
+        Ok(())
+    }
 
     #[test]
-    fn add_emulate_exception() {
-        // TODO: Ensure an overflow exception is triggered by add on a carry
-        // let mut cpu = Cpu::new(false);
-        // let instr = Instruction(0x00a62020);
-        // cpu.reg[instr.rt()] = 0xffff_0fff;
-        // cpu.reg[instr.rs()] = 0x0001_0000;
-        // cpu.add_emulate(instr);
-        // assert_eq!(cpu.reg[instr.rd()], 0x0000_0fff);
+    fn add_emulate_exception() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+
+        let instr = Instruction(0x00a62020);
+        cpu.reg[instr.rt()] = 1;
+        cpu.reg[instr.rs()] = 0x7fff_ffff;
+        cpu.add_emulate(instr)?;
+
+        // The destination register must not be modified when an overflow occurs
+        assert_eq!(cpu.reg[instr.rd()], 0);
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(cpu.cpzero.cause.get_exception_code(), Exception::Overflow);
+        Ok(())
     }
 
     #[test]
@@ -848,8 +1179,31 @@ mod tests {
     }
 
     #[test]
-    fn sub_emulate_exception() {
-        // TODO: Ensure an overflow exception is triggered by sub
+    fn sub_emulate_negative() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let instr = Instruction(0x00a62022);
+        cpu.reg[instr.rt()] = 5;
+        cpu.reg[instr.rs()] = 2;
+        cpu.sub_emulate(instr)?;
+        assert_eq!(cpu.reg[instr.rd()], -3_i32 as u32);
+        assert_eq!(cpu.exception_pending, false);
+        Ok(())
+    }
+
+    #[test]
+    fn sub_emulate_exception() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+
+        let instr = Instruction(0x00a62022);
+        cpu.reg[instr.rt()] = 1;
+        cpu.reg[instr.rs()] = 0x8000_0000;
+        cpu.sub_emulate(instr)?;
+
+        assert_eq!(cpu.reg[instr.rd()], 0);
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(cpu.cpzero.cause.get_exception_code(), Exception::Overflow);
+        Ok(())
     }
 
     #[test]
@@ -907,7 +1261,7 @@ mod tests {
         let mut cpu = Cpu::new(false);
         let instr = Instruction(0x014b482a);
         cpu.reg[instr.rt()] = 0;
-        cpu.reg[instr.rs()] = -1 as i32 as u32;
+        cpu.reg[instr.rs()] = -1_i32 as u32;
         cpu.slt_emulate(instr);
         assert_eq!(cpu.reg[instr.rd()], 1);
     }
@@ -926,7 +1280,7 @@ mod tests {
     fn slti_emulate_less_than() {
         let mut cpu = Cpu::new(false);
         let instr = Instruction(0x2949ff9c);
-        cpu.reg[instr.rs()] = -128 as i32 as u32;
+        cpu.reg[instr.rs()] = -128_i32 as u32;
         cpu.slti_emulate(instr);
         assert_eq!(cpu.reg[instr.rt()], 1);
     }
@@ -935,7 +1289,7 @@ mod tests {
     fn slti_emulate_greater_than() {
         let mut cpu = Cpu::new(false);
         let instr = Instruction(0x2949ff80);
-        cpu.reg[instr.rs()] = -100 as i32 as u32;
+        cpu.reg[instr.rs()] = -100_i32 as u32;
         cpu.slti_emulate(instr);
         assert_eq!(cpu.reg[instr.rt()], 0);
     }
@@ -953,7 +1307,7 @@ mod tests {
     fn sltiu_emulate_greater_than() {
         let mut cpu = Cpu::new(false);
         let instr = Instruction(0x2949000a);
-        cpu.reg[instr.rs()] = -1 as i32 as u32;
+        cpu.reg[instr.rs()] = -1_i32 as u32;
         cpu.sltiu_emulate(instr);
         assert_eq!(cpu.reg[instr.rt()], 0);
     }
@@ -973,7 +1327,7 @@ mod tests {
         let mut cpu = Cpu::new(false);
         let instr = Instruction(0x00400004);
         cpu.reg[instr.rt()] = 0;
-        cpu.reg[instr.rs()] = -1 as i32 as u32;
+        cpu.reg[instr.rs()] = -1_i32 as u32;
         cpu.sltu_emulate(instr);
         assert_eq!(cpu.reg[instr.rd()], 0);
     }
@@ -984,7 +1338,7 @@ mod tests {
         cpu.pc = 0xbfc00004;
 
         let instr = Instruction(0x05200004);
-        cpu.reg[instr.rs()] = -101 as i32 as u32;
+        cpu.reg[instr.rs()] = -101_i32 as u32;
         cpu.bltz_emulate(instr);
 
         assert_eq!(cpu.delay_pc, 0xbfc00018);
@@ -1038,7 +1392,7 @@ mod tests {
         cpu.pc = 0xbfc00004;
 
         let instr = Instruction(0x05210004);
-        cpu.reg[instr.rs()] = -101 as i32 as u32;
+        cpu.reg[instr.rs()] = -101_i32 as u32;
         cpu.bgez_emulate(instr);
 
         assert_eq!(cpu.delay_pc, 0);
@@ -1051,7 +1405,7 @@ mod tests {
         cpu.pc = 0xbfc00004;
 
         let instr = Instruction(0x05300004);
-        cpu.reg[instr.rs()] = -101 as i32 as u32;
+        cpu.reg[instr.rs()] = -101_i32 as u32;
         cpu.bltzal_emulate(instr);
 
         assert_eq!(cpu.reg[Register::Ra], 0xbfc0000c);
@@ -1109,13 +1463,27 @@ mod tests {
         cpu.pc = 0xbfc00004;
 
         let instr = Instruction(0x05310004);
-        cpu.reg[instr.rs()] = -101 as i32 as u32;
+        cpu.reg[instr.rs()] = -101_i32 as u32;
         cpu.bgezal_emulate(instr);
 
         assert_eq!(cpu.delay_pc, 0);
         assert_eq!(cpu.delay_state, DelayState::Normal)
     }
 
+    #[test]
+    fn bgezal_emulate_wraps_at_top_of_memory() {
+        let mut cpu = Cpu::new(false);
+        cpu.pc = 0xffff_fff8;
+
+        let instr = Instruction(0x05310004);
+        cpu.reg[instr.rs()] = 0;
+        cpu.bgezal_emulate(instr);
+
+        assert_eq!(cpu.reg[Register::Ra], 0);
+        assert_eq!(cpu.delay_pc, 0x0000_000c);
+        assert_eq!(cpu.delay_state, DelayState::Delaying)
+    }
+
     #[test]
     fn beq_emulate_taken() {
         let mut cpu = Cpu::new(false);
@@ -1172,6 +1540,21 @@ mod tests {
         assert_eq!(cpu.delay_state, DelayState::Normal)
     }
 
+    #[test]
+    fn bne_emulate_backwards_at_top_of_memory() {
+        let mut cpu = Cpu::new(false);
+        cpu.pc = 0xffff_fffc;
+
+        // bne $v0, $s3, -4
+        let instr = Instruction(0x1453fffc);
+        cpu.reg[instr.rt()] = 24;
+        cpu.reg[instr.rs()] = 42;
+        cpu.bne_emulate(instr);
+
+        assert_eq!(cpu.delay_pc, 0xffff_fff0);
+        assert_eq!(cpu.delay_state, DelayState::Delaying)
+    }
+
     #[test]
     fn blez_emulate_taken() {
         let mut cpu = Cpu::new(false);
@@ -1191,7 +1574,7 @@ mod tests {
         cpu.pc = 0xbfc00004;
 
         let instr = Instruction(0x19200004);
-        cpu.reg[instr.rs()] = -101 as i32 as u32;
+        cpu.reg[instr.rs()] = -101_i32 as u32;
         cpu.blez_emulate(instr);
 
         assert_eq!(cpu.delay_pc, 0xbfc00018);
@@ -1248,8 +1631,29 @@ mod tests {
     }
 
     #[test]
-    fn addi_emulate_exception() {
-        // TODO: Ensure an overflow exception is triggered by add
+    fn addi_emulate_negative() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let instr = Instruction(0x2084fffc);
+        cpu.reg[instr.rs()] = 2;
+        cpu.addi_emulate(instr)?;
+        assert_eq!(cpu.reg[Register::A0], -2_i32 as u32);
+        assert_eq!(cpu.exception_pending, false);
+        Ok(())
+    }
+
+    #[test]
+    fn addi_emulate_exception() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+
+        let instr = Instruction(0x20840080);
+        cpu.reg[instr.rs()] = 0x7fff_fff0;
+        cpu.addi_emulate(instr)?;
+
+        assert_eq!(cpu.reg[Register::A0], 0x7fff_fff0);
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(cpu.cpzero.cause.get_exception_code(), Exception::Overflow);
+        Ok(())
     }
 
     #[test]
@@ -1297,58 +1701,522 @@ mod tests {
     }
 
     #[test]
-    fn lb_emulate() {}
+    fn lb_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0x8085fffc);
+        cpu.reg[instr.rs()] = 0x104;
+        memory.store_byte(0x100, 0x80)?;
+        cpu.lb_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.reg[instr.rt()], 0xffff_ff80);
+        Ok(())
+    }
+
+    #[test]
+    fn lb_emulate_wraps_below_zero() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0x8085ffff);
+        cpu.reg[instr.rs()] = 0;
+        memory.store_byte(0xffff_ffff, 0x7f)?;
+        cpu.lb_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.reg[instr.rt()], 0x7f);
+        Ok(())
+    }
 
     #[test]
-    fn lh_emulate() {}
+    fn lh_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0x8485fffe);
+        cpu.reg[instr.rs()] = 0x10;
+        memory.store_halfword(0xe, 0x8234)?;
+        cpu.lh_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.reg[instr.rt()], 0xffff_8234);
+        Ok(())
+    }
 
     #[test]
-    fn lwl_emulate() {}
+    fn lh_emulate_wraps_above_max() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0x84850004);
+        cpu.reg[instr.rs()] = 0xffff_fffe;
+        memory.store_halfword(0x2, 0x0bcd)?;
+        cpu.lh_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.reg[instr.rt()], 0x0bcd);
+        Ok(())
+    }
 
     #[test]
-    fn lw_emulate() {}
+    fn lh_emulate_unaligned() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let mut memory = TestMemory::default();
+        cpu.reset();
+
+        let instr = Instruction(0x8485ffff);
+        cpu.reg[instr.rs()] = 0x10;
+        cpu.lh_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(cpu.cpzero.badvaddr.address, 0xf);
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::AddressLoadError
+        );
+        Ok(())
+    }
 
     #[test]
-    fn lbu_emulate() {}
+    fn lwl_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        // lwl $a1, 0($a0) for each byte of a word
+        let instr = Instruction(0x8885_0000);
+        memory.store_word(0x10, 0x1122_3344)?;
+        let expected = [0x44bb_ccdd, 0x3344_ccdd, 0x2233_44dd, 0x1122_3344];
+        for (offset, expected) in expected.iter().enumerate() {
+            cpu.reg[instr.rt()] = 0xaabb_ccdd;
+            cpu.reg[instr.rs()] = 0x10 + offset as u32;
+            cpu.lwl_emulate(&mut memory, instr)?;
+            assert_eq!(cpu.reg[instr.rt()], *expected);
+        }
+        Ok(())
+    }
 
     #[test]
-    fn lhu_emulate() {}
+    fn lw_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0x8c85fff8);
+        cpu.reg[instr.rs()] = 0x20;
+        memory.store_word(0x18, 0xdead_beef)?;
+        cpu.lw_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.reg[instr.rt()], 0xdead_beef);
+        Ok(())
+    }
 
     #[test]
-    fn lwr_emulate() {}
+    fn lw_emulate_wraps_below_zero() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0x8c85fff8);
+        cpu.reg[instr.rs()] = 0x4;
+        memory.store_word(0xffff_fffc, 0xcafe_babe)?;
+        cpu.lw_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.reg[instr.rt()], 0xcafe_babe);
+        Ok(())
+    }
 
     #[test]
-    fn sb_emulate() {}
+    fn lbu_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0x9085ffff);
+        cpu.reg[instr.rs()] = 0;
+        memory.store_byte(0xffff_ffff, 0x80)?;
+        cpu.lbu_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.reg[instr.rt()], 0x80);
+        Ok(())
+    }
 
     #[test]
-    fn sh_emulate() {}
+    fn lhu_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0x9485fffe);
+        cpu.reg[instr.rs()] = 0;
+        memory.store_halfword(0xffff_fffe, 0x8234)?;
+        cpu.lhu_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.reg[instr.rt()], 0x8234);
+        Ok(())
+    }
 
     #[test]
-    fn swl_emulate() {}
+    fn lwr_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        // lwr $a1, 0($a0) for each byte of a word
+        let instr = Instruction(0x9885_0000);
+        memory.store_word(0x10, 0x1122_3344)?;
+        let expected = [0x1122_3344, 0xaa11_2233, 0xaabb_1122, 0xaabb_cc11];
+        for (offset, expected) in expected.iter().enumerate() {
+            cpu.reg[instr.rt()] = 0xaabb_ccdd;
+            cpu.reg[instr.rs()] = 0x10 + offset as u32;
+            cpu.lwr_emulate(&mut memory, instr)?;
+            assert_eq!(cpu.reg[instr.rt()], *expected);
+        }
+        Ok(())
+    }
 
     #[test]
-    fn sw_emulate() {}
+    fn sb_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0xa085ffff);
+        cpu.reg[instr.rs()] = 0;
+        cpu.reg[instr.rt()] = 0x1234_5678;
+        cpu.sb_emulate(&mut memory, instr)?;
+
+        assert_eq!(memory.fetch_byte(0xffff_ffff)?, 0x78);
+        Ok(())
+    }
 
     #[test]
-    fn swr_emulate() {}
+    fn sh_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0xa4850004);
+        cpu.reg[instr.rs()] = 0xffff_fffe;
+        cpu.reg[instr.rt()] = 0x1234_5678;
+        cpu.sh_emulate(&mut memory, instr)?;
+
+        assert_eq!(memory.fetch_halfword(0x2)?, 0x5678);
+        Ok(())
+    }
 
     #[test]
-    fn lwc1_emulate() {}
+    fn swl_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        // swl $a1, 0($a0) for each byte of a word
+        let instr = Instruction(0xa8850000);
+        cpu.reg[instr.rt()] = 0x1122_3344;
+        let expected = [0xaabb_cc11, 0xaabb_1122, 0xaa11_2233, 0x1122_3344];
+        for (offset, expected) in expected.iter().enumerate() {
+            memory.store_word(0x10, 0xaabb_ccdd)?;
+            cpu.reg[instr.rs()] = 0x10 + offset as u32;
+            cpu.swl_emulate(&mut memory, instr)?;
+            assert_eq!(memory.fetch_word(0x10)?, *expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sw_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0xac85fff8);
+        cpu.reg[instr.rs()] = 0x8;
+        cpu.reg[instr.rt()] = 0x1234_5678;
+        cpu.sw_emulate(&mut memory, instr)?;
+
+        assert_eq!(memory.fetch_word(0x0)?, 0x1234_5678);
+        Ok(())
+    }
+
+    #[test]
+    fn sw_emulate_wraps_above_max() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        let instr = Instruction(0xac850008);
+        cpu.reg[instr.rs()] = 0xffff_fffc;
+        cpu.reg[instr.rt()] = 0x1234_5678;
+        cpu.sw_emulate(&mut memory, instr)?;
+
+        assert_eq!(memory.fetch_word(0x4)?, 0x1234_5678);
+        Ok(())
+    }
+
+    #[test]
+    fn sw_emulate_unaligned() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let mut memory = TestMemory::default();
+        cpu.reset();
+
+        let instr = Instruction(0xac850002);
+        cpu.reg[instr.rs()] = 0x8;
+        cpu.sw_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(cpu.cpzero.badvaddr.address, 0xa);
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::AddressStoreError
+        );
+        assert_eq!(memory.fetch_word(0x8)?, 0);
+        Ok(())
+    }
 
     #[test]
-    fn lwc2_emulate() {}
+    fn swr_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        // swr $a1, 0($a0) for each byte of a word
+        let instr = Instruction(0xb8850000);
+        cpu.reg[instr.rt()] = 0x1122_3344;
+        let expected = [0x1122_3344, 0x2233_44dd, 0x3344_ccdd, 0x44bb_ccdd];
+        for (offset, expected) in expected.iter().enumerate() {
+            memory.store_word(0x10, 0xaabb_ccdd)?;
+            cpu.reg[instr.rs()] = 0x10 + offset as u32;
+            cpu.swr_emulate(&mut memory, instr)?;
+            assert_eq!(memory.fetch_word(0x10)?, *expected);
+        }
+        Ok(())
+    }
 
     #[test]
-    fn lwc3_emulate() {}
+    fn swl_swr_emulate_reverse_endian() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+        cpu.cpzero.status.enter_user_mode();
+        cpu.cpzero.status.bits.set_bit(25, true);
+
+        // The big-endian unaligned store of `swl $a1, 0($a0)` and `swr $a1, 3($a0)` at 0x11
+        cpu.reg[5] = 0x1122_3344;
+        cpu.reg[4] = 0x11;
+        cpu.swl_emulate(&mut memory, Instruction(0xa8850000))?;
+        cpu.swr_emulate(&mut memory, Instruction(0xb8850003))?;
+
+        // The big-endian bytes 11 22 33 44 from 0x11, on the little-endian bus
+        assert_eq!(memory.fetch_word(0x10)?, 0x0011_2233);
+        assert_eq!(memory.fetch_word(0x14)?, 0x4400_0000);
+
+        // `lwl $a2, 0($a0)` and `lwr $a2, 3($a0)` load the word back
+        cpu.lwl_emulate(&mut memory, Instruction(0x8886_0000))?;
+        cpu.lwr_emulate(&mut memory, Instruction(0x9886_0003))?;
+        assert_eq!(cpu.reg[6], 0x1122_3344);
+        Ok(())
+    }
 
     #[test]
-    fn swc1_emulate() {}
+    fn cache_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+
+        // cache 0x10, 4($a0) is a reserved instruction on the R3000
+        let instr = Instruction(0xbc90_0004);
+        cpu.reg[instr.rs()] = 0x8000_1010;
+        cpu.execute(&mut memory, instr)?;
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::ReservedInstruction
+        );
+
+        // The R4000 invalidates the instruction cache line, but ignores data cache operations
+        let mut cpu = Cpu::new(false);
+        cpu.cpzero = CPZero::with_model(CpuModel::R4000, 48);
+        cpu.reset();
+        cpu.reg[instr.rs()] = 0x8000_1010;
+        cpu.execute(&mut memory, instr)?;
+        cpu.execute(&mut memory, Instruction(0xbc91_0004))?;
+        assert_eq!(cpu.exception_pending, false);
+        assert_eq!(memory.invalidated, vec![(0x1010, 16)]);
+
+        // Only the kernel may operate on the caches
+        cpu.cpzero.status.enter_user_mode();
+        cpu.reg[instr.rs()] = 0x1010;
+        cpu.execute(&mut memory, instr)?;
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::CoprocessorUnusable
+        );
+        Ok(())
+    }
 
     #[test]
-    fn swc2_emulate() {}
+    fn cop1_emulate() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        cpu.attach_coprocessor(1, Box::new(TestCoprocessor::default()));
+
+        // mtc1 $t0, $f2 raises Coprocessor Unusable while CU1 is clear
+        cpu.reg[Register::T0] = 0x1234_5678;
+        cpu.copz_emulate(1, Instruction(0x4488_1000))?;
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(cpu.cpzero.cause.get_coprocessor_error(), 1);
+
+        let mut cpu = mapped_cpu();
+        cpu.attach_coprocessor(1, Box::new(TestCoprocessor::default()));
+        cpu.cpzero.status.bits.set_bit(29, true);
+        cpu.reg[Register::T0] = 0x1234_5678;
+        cpu.copz_emulate(1, Instruction(0x4488_1000))?;
+        // mfc1 $t1, $f2
+        cpu.copz_emulate(1, Instruction(0x4409_1000))?;
+        assert_eq!(cpu.reg[Register::T1], 0x1234_5678);
+
+        // bc1f is taken as the condition of the test coprocessor is false, bc1t is not
+        cpu.copz_emulate(1, Instruction(0x4501_0002))?;
+        assert_eq!(cpu.delay_state, DelayState::Normal);
+        cpu.copz_emulate(1, Instruction(0x4500_0002))?;
+        assert_eq!(cpu.delay_state, DelayState::Delaying);
+        assert_eq!(cpu.delay_pc, cpu.pc + 12);
+
+        // The test coprocessor implements no operations
+        cpu.copz_emulate(1, Instruction(0x4622_2080))?;
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::ReservedInstruction
+        );
+        Ok(())
+    }
 
     #[test]
-    fn swc3_emulate() {}
+    fn lwc1_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+        cpu.attach_coprocessor(1, Box::new(TestCoprocessor::default()));
+        cpu.cpzero.status.bits.set_bit(29, true);
+
+        // lwc1 $f2, 8($a0)
+        let instr = Instruction(0xc4820008);
+        cpu.reg[instr.rs()] = 0x100;
+        memory.store_word(0x108, 0xdead_beef)?;
+        cpu.lwc1_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.exception_pending, false);
+        let coprocessor = cpu.coprocessor_mut(1).expect("CP1 should be usable");
+        assert_eq!(coprocessor.read_register(2), 0xdead_beef);
+        Ok(())
+    }
+
+    #[test]
+    fn lwc1_emulate_unusable() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let mut memory = TestMemory::default();
+        cpu.reset();
+
+        // The coprocessor is attached but CU1 is clear
+        cpu.attach_coprocessor(1, Box::new(TestCoprocessor::default()));
+
+        let instr = Instruction(0xc4820008);
+        cpu.lwc1_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::CoprocessorUnusable
+        );
+        assert_eq!(cpu.cpzero.cause.get_coprocessor_error(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn lwc2_emulate() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let mut memory = TestMemory::default();
+        cpu.reset();
+
+        // CU2 is set but nothing is attached to the slot
+        cpu.cpzero.status.bits.set_bit(30, true);
+
+        let instr = Instruction(0xc8820008);
+        cpu.lwc2_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::CoprocessorUnusable
+        );
+        assert_eq!(cpu.cpzero.cause.get_coprocessor_error(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn lwc3_emulate() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let mut memory = TestMemory::default();
+        cpu.reset();
+
+        let instr = Instruction(0xcc820008);
+        cpu.lwc3_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(cpu.cpzero.cause.get_coprocessor_error(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn swc1_emulate() -> Result<()> {
+        let mut cpu = mapped_cpu();
+        let mut memory = TestMemory::default();
+        cpu.attach_coprocessor(1, Box::new(TestCoprocessor::default()));
+        cpu.cpzero.status.bits.set_bit(29, true);
+
+        let coprocessor = cpu.coprocessor_mut(1).expect("CP1 should be usable");
+        coprocessor.write_register(2, 0xcafe_babe);
+
+        // swc1 $f2, -4($a0)
+        let instr = Instruction(0xe482fffc);
+        cpu.reg[instr.rs()] = 0x100;
+        cpu.swc1_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.exception_pending, false);
+        assert_eq!(memory.fetch_word(0xfc)?, 0xcafe_babe);
+        Ok(())
+    }
+
+    #[test]
+    fn swc1_emulate_unaligned() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let mut memory = TestMemory::default();
+        cpu.reset();
+        cpu.attach_coprocessor(1, Box::new(TestCoprocessor::default()));
+        cpu.cpzero.status.bits.set_bit(29, true);
+
+        let instr = Instruction(0xe4820002);
+        cpu.reg[instr.rs()] = 0x100;
+        cpu.swc1_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(
+            cpu.cpzero.cause.get_exception_code(),
+            Exception::AddressStoreError
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn swc2_emulate() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let mut memory = TestMemory::default();
+        cpu.reset();
+
+        let instr = Instruction(0xe8820008);
+        cpu.swc2_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(cpu.cpzero.cause.get_coprocessor_error(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn swc3_emulate() -> Result<()> {
+        let mut cpu = Cpu::new(false);
+        let mut memory = TestMemory::default();
+        cpu.reset();
+
+        let instr = Instruction(0xec820008);
+        cpu.swc3_emulate(&mut memory, instr)?;
+
+        assert_eq!(cpu.exception_pending, true);
+        assert_eq!(cpu.cpzero.cause.get_coprocessor_error(), 3);
+        Ok(())
+    }
 
     #[test]
     fn j_emulate() {
@@ -1375,6 +2243,94 @@ mod tests {
         assert_eq!(cpu.delay_state, DelayState::Delaying)
     }
 
+    #[test]
+    fn jal_emulate_wraps_at_top_of_memory() {
+        let mut cpu = Cpu::new(false);
+        let instr = Instruction(0x0c100006);
+
+        cpu.pc = 0xffff_fff8;
+        cpu.jal_emulate(instr);
+
+        assert_eq!(cpu.reg[Register::Ra], 0);
+        assert_eq!(cpu.delay_pc, 0xf040_0018);
+        assert_eq!(cpu.delay_state, DelayState::Delaying)
+    }
+
+    #[test]
+    fn jal_emulate_in_last_word() {
+        let mut cpu = Cpu::new(false);
+        let instr = Instruction(0x0c100006);
+
+        // The delay slot wraps around to address zero so the segment bits are cleared
+        cpu.pc = 0xffff_fffc;
+        cpu.jal_emulate(instr);
+
+        assert_eq!(cpu.reg[Register::Ra], 0x4);
+        assert_eq!(cpu.delay_pc, 0x0040_0018);
+    }
+
+    #[test]
+    fn mtc0_emulate_read_only_registers() {
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        cpu.cpzero.badvaddr.address = 0x1234;
+
+        // mtc0 $a0, $8 (BadVaddr) and mtc0 $a0, $15 (PRId)
+        cpu.reg[Register::A0] = 0xffff_ffff;
+        cpu.mtc0_emulate(Instruction(0x40844000));
+        cpu.mtc0_emulate(Instruction(0x40847800));
+
+        assert_eq!(cpu.cpzero.badvaddr.address, 0x1234);
+        assert_eq!(u32::from(cpu.cpzero.prid), 0x230);
+    }
+
+    #[test]
+    fn mtc0_emulate_status_reserved_bits() {
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+
+        // mtc0 $a0, $12 (Status)
+        cpu.reg[Register::A0] = 0xffff_ffff;
+        cpu.mtc0_emulate(Instruction(0x40846000));
+        assert_eq!(u32::from(cpu.cpzero.status), 0xf247_ff3f);
+
+        cpu.reg[Register::A0] = 0;
+        cpu.mtc0_emulate(Instruction(0x40846000));
+        assert_eq!(u32::from(cpu.cpzero.status), 0);
+    }
+
+    #[test]
+    fn mtc0_emulate_tlb_registers() {
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        cpu.reg[Register::A0] = 0xffff_ffff;
+
+        // mtc0 $a0 to Index, Random, EntryLo, Context, and EntryHi
+        cpu.mtc0_emulate(Instruction(0x40840000));
+        cpu.mtc0_emulate(Instruction(0x40840800));
+        cpu.mtc0_emulate(Instruction(0x40841000));
+        cpu.mtc0_emulate(Instruction(0x40842000));
+        cpu.mtc0_emulate(Instruction(0x40845000));
+
+        assert_eq!(u32::from(cpu.cpzero.index), 0x0000_3f00);
+        assert_eq!(cpu.cpzero.random.get_value(), 63);
+        assert_eq!(cpu.cpzero.entrylo, 0xffff_ff00);
+        assert_eq!(u32::from(cpu.cpzero.context), 0xffe0_0000);
+        assert_eq!(cpu.cpzero.entryhi, 0xffff_ffc0);
+    }
+
+    #[test]
+    fn mtc0_emulate_cause_software_interrupts() {
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+
+        // mtc0 $a0, $13 (Cause)
+        cpu.reg[Register::A0] = 0xffff_ffff;
+        cpu.mtc0_emulate(Instruction(0x40846800));
+
+        assert_eq!(u32::from(cpu.cpzero.cause), 0x0000_0300);
+    }
+
     #[test]
     fn ri_emulate() {}
 }
@@ -0,0 +1,140 @@
+//! Consistency checks of the `Cpu` state that catch emulator bugs close to their cause.
+//!
+//! Debug builds run them after every step, so an instruction that corrupts the state is
+//! reported together with the state it left behind, instead of surfacing as a confusing guest
+//! crash thousands of instructions later.
+
+use std::fmt;
+
+use crate::control::cpu::{Cpu, DelayState};
+use crate::control::disasm;
+use crate::control::instruction::Instruction;
+use crate::control::registers::StatusRegister;
+use crate::Address;
+
+/// An internal error, the `Cpu` state broke an invariant that no guest program can break.
+#[derive(Debug)]
+pub struct InvariantViolation {
+    /// Address of the instruction that was stepped.
+    pub pc: Address,
+    pub instruction: Instruction,
+    /// Descriptions of the broken invariants.
+    pub violations: Vec<String>,
+    /// Description of the `Cpu` state after the step, including its registers.
+    state: String,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Internal error after the instruction at PC=0x{:08x} ({:08x}  {}):",
+            self.pc,
+            self.instruction.0,
+            disasm::disassemble(self.instruction, self.pc)
+        )?;
+        for violation in &self.violations {
+            writeln!(f, "    {}", violation)?;
+        }
+        writeln!(f, "{}", self.state)?;
+        write!(
+            f,
+            "This is a bug in rmips, please report it with the program that triggered it"
+        )
+    }
+}
+
+/// Checks the state of `cpu` after it stepped the instruction at `pc`, starting in `delay_state`.
+pub fn check(cpu: &Cpu, pc: Address, delay_state: DelayState) -> Option<InvariantViolation> {
+    let mut violations = Vec::new();
+
+    if cpu.reg[0] != 0 {
+        violations.push(format!("$zero holds 0x{:08x}", cpu.reg[0]));
+    }
+
+    // A step always moves a pending branch into its delay slot
+    if cpu.delay_state == DelayState::Delaying {
+        violations.push("A branch is still waiting for its delay slot".to_owned());
+    }
+    if cpu.exception_pending && cpu.delay_state != DelayState::Normal {
+        violations.push(format!(
+            "An exception was raised, but the next instruction is in the {:?} state",
+            cpu.delay_state
+        ));
+    }
+
+    // Only a jump to a misaligned address reaches one, its fetch then raises an address error
+    let jumped = delay_state == DelayState::Delayslot && !cpu.exception_pending;
+    if !cpu.pc.is_multiple_of(4) && !jumped {
+        violations.push(format!(
+            "PC 0x{:08x} is not word-aligned, but no jump was taken",
+            cpu.pc
+        ));
+    }
+
+    let status = u32::from(cpu.cpzero.status);
+    if status & StatusRegister::RESERVED_BITS != 0 {
+        violations.push(format!(
+            "Status 0x{:08x} has reserved bits 0x{:08x} set",
+            status,
+            status & StatusRegister::RESERVED_BITS
+        ));
+    }
+
+    if violations.is_empty() {
+        return None;
+    }
+
+    let state = format!(
+        "PC=0x{:08x} delay state {:?} delay PC=0x{:08x} Status=0x{:08x} Cause=0x{:08x} \
+         HI=0x{:08x} LO=0x{:08x}{}",
+        cpu.pc,
+        cpu.delay_state,
+        cpu.delay_pc,
+        status,
+        u32::from(cpu.cpzero.cause),
+        cpu.high,
+        cpu.low,
+        cpu
+    );
+    Some(InvariantViolation {
+        pc,
+        instruction: cpu.instruction,
+        violations,
+        state,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn invariant_checks() {
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        assert!(check(&cpu, 0xbfc0_0000, DelayState::Normal).is_none());
+
+        // Completing the delay slot of a jump to a misaligned address is allowed
+        cpu.pc = 0xbfc0_0102;
+        assert!(check(&cpu, 0xbfc0_0004, DelayState::Delayslot).is_none());
+
+        cpu.reg[0] = 5;
+        cpu.delay_state = DelayState::Delaying;
+        cpu.cpzero.status = 0x0080_0000.into();
+        let violation = check(&cpu, 0xbfc0_00fe, DelayState::Normal).unwrap();
+        assert_eq!(
+            violation.violations,
+            [
+                "$zero holds 0x00000005",
+                "A branch is still waiting for its delay slot",
+                "PC 0xbfc00102 is not word-aligned, but no jump was taken",
+                "Status 0x00800000 has reserved bits 0x00800000 set",
+            ]
+        );
+        assert!(violation
+            .to_string()
+            .starts_with("Internal error after the instruction at PC=0xbfc000fe"));
+    }
+}
@@ -0,0 +1,686 @@
+//! The instruction set as a single table.
+//!
+//! Every instruction is one row of `instruction_table!` giving its encoding, the operands it
+//! is written with, the function that executes it, the ISA level that introduced it and the
+//! exceptions it may raise. The decoder of the `Cpu`, the mnemonics and operands of the
+//! disassembler and the instruction reference printed by `rmips instructions` are all generated
+//! from the rows, so adding an instruction is a matter of adding its row.
+//!
+//! Coprocessor operations only select the coprocessor in the table, the attached `Coprocessor`
+//! decodes the operation itself.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::control::cpu::Cpu;
+use crate::control::instruction::Instruction;
+use crate::control::registers::Cp0Register;
+use crate::memory::Memory;
+use crate::util::error::Result;
+
+/// The fields of an instruction word that select an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// The major opcode alone.
+    Opcode(u32),
+    /// The function code of opcode SPECIAL.
+    Special(u32),
+    /// The `rt` field of opcode REGIMM.
+    Regimm(usize),
+    /// The `rs` field of opcode COP0.
+    Cop0(usize),
+    /// The function code of a COP0 operation, which has the CO bit of `rs` set.
+    Cop0Function(u32),
+    /// The `rs` field of opcode COP1.
+    Cop1(usize),
+    /// The format in the `rs` field and the function code of a COP1 operation.
+    Cop1Function(usize, u32),
+}
+
+impl Encoding {
+    /// Returns an instruction word with this encoding and all other fields zero.
+    pub fn word(self) -> u32 {
+        match self {
+            Encoding::Opcode(opcode) => opcode << 26,
+            Encoding::Special(funct) => funct,
+            Encoding::Regimm(rt) => 0x01 << 26 | (rt as u32) << 16,
+            Encoding::Cop0(rs) => 0x10 << 26 | (rs as u32) << 21,
+            Encoding::Cop0Function(funct) => 0x10 << 26 | 16 << 21 | funct,
+            Encoding::Cop1(rs) => 0x11 << 26 | (rs as u32) << 21,
+            Encoding::Cop1Function(fmt, funct) => 0x11 << 26 | (fmt as u32) << 21 | funct,
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Encoding::Opcode(opcode) => write!(f, "opcode 0x{:02x}", opcode),
+            Encoding::Special(funct) => write!(f, "SPECIAL funct 0x{:02x}", funct),
+            Encoding::Regimm(rt) => write!(f, "REGIMM rt 0x{:02x}", rt),
+            Encoding::Cop0(rs) => write!(f, "COP0 rs 0x{:02x}", rs),
+            Encoding::Cop0Function(funct) => write!(f, "COP0 CO funct 0x{:02x}", funct),
+            Encoding::Cop1(rs) => write!(f, "COP1 rs 0x{:02x}", rs),
+            Encoding::Cop1Function(fmt, funct) => {
+                write!(f, "COP1 fmt 0x{:02x} funct 0x{:02x}", fmt, funct)
+            }
+        }
+    }
+}
+
+/// The operands that an instruction is written with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operands {
+    None,
+    Shift,
+    ShiftVariable,
+    Rs,
+    /// `jalr` leaves out `rd` when it is `$ra`.
+    Jalr,
+    Rd,
+    RsRt,
+    RdRsRt,
+    RsBranch,
+    Jump,
+    RsRtBranch,
+    RtRsSigned,
+    RtRsUnsigned,
+    RtUpper,
+    RtCopRegister,
+    RtFpr,
+    Branch,
+    FpuThree,
+    FpuTwo,
+    FpuCompare,
+    CopOperation,
+    Memory,
+    Cache,
+    FprMemory,
+    CopMemory,
+}
+
+impl Operands {
+    /// Returns the operands in the syntax of the MIPS manuals.
+    pub fn syntax(self) -> &'static str {
+        match self {
+            Operands::None => "",
+            Operands::Shift => "rd, rt, sa",
+            Operands::ShiftVariable => "rd, rt, rs",
+            Operands::Rs => "rs",
+            Operands::Jalr => "rd, rs",
+            Operands::Rd => "rd",
+            Operands::RsRt => "rs, rt",
+            Operands::RdRsRt => "rd, rs, rt",
+            Operands::RsBranch => "rs, offset",
+            Operands::Jump => "target",
+            Operands::RsRtBranch => "rs, rt, offset",
+            Operands::RtRsSigned | Operands::RtRsUnsigned => "rt, rs, immediate",
+            Operands::RtUpper => "rt, immediate",
+            Operands::RtCopRegister => "rt, rd",
+            Operands::RtFpr => "rt, fs",
+            Operands::Branch => "offset",
+            Operands::FpuThree => "fd, fs, ft",
+            Operands::FpuTwo => "fd, fs",
+            Operands::FpuCompare => "fs, ft",
+            Operands::CopOperation => "cofun",
+            Operands::Memory | Operands::CopMemory => "rt, offset(base)",
+            Operands::Cache => "op, offset(base)",
+            Operands::FprMemory => "ft, offset(base)",
+        }
+    }
+}
+
+/// The revision of the MIPS architecture that introduced an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IsaLevel {
+    MipsI,
+    MipsIII,
+}
+
+impl fmt::Display for IsaLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IsaLevel::MipsI => write!(f, "MIPS I"),
+            IsaLevel::MipsIII => write!(f, "MIPS III"),
+        }
+    }
+}
+
+bitflags! {
+    /// Exceptions that an instruction may raise.
+    pub struct Traps: u8 {
+        /// Integer overflow of a signed add or subtract.
+        const OVERFLOW = 1 << 0;
+        /// Misaligned or privileged data address.
+        const ADDRESS_ERROR = 1 << 1;
+        /// TLB refill, invalid or modified page.
+        const TLB = 1 << 2;
+        /// Data bus error.
+        const BUS_ERROR = 1 << 3;
+        const SYSCALL = 1 << 4;
+        const BREAKPOINT = 1 << 5;
+        const COPROCESSOR_UNUSABLE = 1 << 6;
+        /// Floating-point exception raised by the FPU.
+        const FLOATING_POINT = 1 << 7;
+    }
+}
+
+impl fmt::Display for Traps {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = [
+            (Traps::OVERFLOW, "overflow"),
+            (Traps::ADDRESS_ERROR, "address error"),
+            (Traps::TLB, "TLB"),
+            (Traps::BUS_ERROR, "bus error"),
+            (Traps::SYSCALL, "syscall"),
+            (Traps::BREAKPOINT, "breakpoint"),
+            (Traps::COPROCESSOR_UNUSABLE, "coprocessor unusable"),
+            (Traps::FLOATING_POINT, "floating point"),
+        ];
+        let names: Vec<&str> = names
+            .iter()
+            .filter(|(trap, _)| self.contains(*trap))
+            .map(|(_, name)| *name)
+            .collect();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+/// One row of the instruction table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstructionSpec {
+    pub op: Op,
+    pub mnemonic: &'static str,
+    pub encoding: Encoding,
+    pub operands: Operands,
+    pub isa: IsaLevel,
+    pub traps: Traps,
+}
+
+/// Lets the semantics of a row return either nothing or a `Result`.
+trait Completion {
+    fn complete(self) -> Result<()>;
+}
+
+impl Completion for () {
+    fn complete(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Completion for Result<()> {
+    fn complete(self) -> Result<()> {
+        self
+    }
+}
+
+/// Expands an encoding into a pattern on the opcode, `rs`, `rt` and function code.
+macro_rules! encoding_pattern {
+    (Opcode($opcode:literal)) => {
+        ($opcode, _, _, _)
+    };
+    (Special($funct:literal)) => {
+        (0x00, _, _, $funct)
+    };
+    (Regimm($rt:literal)) => {
+        (0x01, _, $rt, _)
+    };
+    (Cop0($rs:literal)) => {
+        (0x10, $rs, _, _)
+    };
+    (Cop0Function($funct:literal)) => {
+        (0x10, 16..=31, _, $funct)
+    };
+    (Cop1($rs:literal)) => {
+        (0x11, $rs, _, _)
+    };
+    (Cop1Function($fmt:literal, $funct:literal)) => {
+        (0x11, $fmt, _, $funct)
+    };
+}
+
+/// Generates `Op`, `INSTRUCTIONS`, the decoder and the dispatch of the `Cpu` from the rows.
+///
+/// The semantics of a row is an expression of the `Cpu`, the memory and the instruction named
+/// at the top of the table.
+macro_rules! instruction_table {
+    (
+        |$cpu:ident, $memory:ident, $instr:ident|
+        $(
+            $op:ident $mnemonic:literal $encoding:ident($($field:literal),+) $operands:ident
+                $isa:ident [$($trap:ident)|*] => $semantics:expr,
+        )+
+    ) => {
+        /// An instruction of the table.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum Op {
+            $($op,)+
+        }
+
+        /// The instruction table, indexed by `Op`.
+        pub static INSTRUCTIONS: &[InstructionSpec] = &[
+            $(InstructionSpec {
+                op: Op::$op,
+                mnemonic: $mnemonic,
+                encoding: Encoding::$encoding($($field),+),
+                operands: Operands::$operands,
+                isa: IsaLevel::$isa,
+                traps: Traps::from_bits_truncate(0 $(| Traps::$trap.bits())*),
+            },)+
+        ];
+
+        impl Op {
+            /// Returns the instruction that `instr` encodes, or `None` for reserved encodings
+            /// and the coprocessor instructions that the table does not list.
+            pub fn decode(instr: Instruction) -> Option<Op> {
+                match (instr.opcode(), instr.rs(), instr.rt(), instr.funct()) {
+                    $(encoding_pattern!($encoding($($field),+)) => Some(Op::$op),)+
+                    _ => None,
+                }
+            }
+        }
+
+        impl Cpu {
+            /// Executes the decoded instruction `op`.
+            pub(crate) fn dispatch(
+                &mut self,
+                op: Op,
+                memory: &mut impl Memory,
+                instr: Instruction,
+            ) -> Result<()> {
+                let ($cpu, $memory, $instr) = (self, memory, instr);
+                match op {
+                    $(Op::$op => Completion::complete($semantics),)+
+                }
+            }
+        }
+    };
+}
+
+/// A register that an instruction writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Destination {
+    /// A general-purpose register.
+    Gpr(usize),
+    Hi,
+    Lo,
+    Cp0(Cp0Register),
+    /// A floating-point register of the FPU.
+    Fpr(usize),
+    /// The control and status register of the FPU.
+    Fcsr,
+}
+
+impl Op {
+    /// Returns the row of the instruction.
+    pub fn spec(self) -> &'static InstructionSpec {
+        &INSTRUCTIONS[self as usize]
+    }
+
+    /// Returns the registers that `instr`, an encoding of this instruction, writes when it
+    /// completes without an exception. Writes to `$zero` are left out, as they are discarded.
+    pub fn destinations(self, instr: Instruction) -> Vec<Destination> {
+        use Destination::*;
+
+        let mut destinations = match self {
+            Op::Mthi => vec![Hi],
+            Op::Mtlo => vec![Lo],
+            Op::Mult | Op::Multu | Op::Div | Op::Divu => vec![Hi, Lo],
+            Op::Jal | Op::Bltzal | Op::Bgezal => vec![Gpr(31)],
+            Op::Mfc0 | Op::Mfc1 | Op::Cfc1 => vec![Gpr(instr.rt())],
+            Op::Mtc0 => Cp0Register::try_from(instr.rd() as u32)
+                .map(Cp0)
+                .into_iter()
+                .collect(),
+            Op::Mtc1 => vec![Fpr(instr.rd())],
+            Op::Ctc1 => vec![Fcsr],
+            Op::Tlbr => vec![
+                Cp0(Cp0Register::EntryHi),
+                Cp0(Cp0Register::EntryLo),
+                Cp0(Cp0Register::EntryLo1),
+                Cp0(Cp0Register::PageMask),
+            ],
+            Op::Tlbp => vec![Cp0(Cp0Register::Index)],
+            Op::Rfe => vec![Cp0(Cp0Register::Status)],
+            Op::Lwc1 => vec![Fpr(instr.rt())],
+            _ => match self.spec().operands {
+                Operands::Shift
+                | Operands::ShiftVariable
+                | Operands::Jalr
+                | Operands::Rd
+                | Operands::RdRsRt => vec![Gpr(instr.rd())],
+                Operands::RtRsSigned | Operands::RtRsUnsigned | Operands::RtUpper => {
+                    vec![Gpr(instr.rt())]
+                }
+                Operands::Memory => match instr.data_access() {
+                    Some((_, false)) => vec![Gpr(instr.rt())],
+                    _ => Vec::new(),
+                },
+                // Every operation replaces the cause field of the FCSR
+                Operands::FpuThree | Operands::FpuTwo => {
+                    let fd = instr.shamt() as usize;
+                    let double = match self {
+                        Op::CvtDS | Op::CvtDW => true,
+                        Op::CvtSD | Op::CvtWD => false,
+                        _ => instr.rs() == 17,
+                    };
+                    match double {
+                        true => vec![Fpr(fd), Fpr(fd + 1), Fcsr],
+                        false => vec![Fpr(fd), Fcsr],
+                    }
+                }
+                Operands::FpuCompare => vec![Fcsr],
+                _ => Vec::new(),
+            },
+        };
+        destinations.retain(|destination| *destination != Gpr(0));
+        destinations
+    }
+}
+
+impl Cpu {
+    /// Runs a CP0 instruction, or raises a Coprocessor Unusable exception if CP0 is not
+    /// accessible in the current mode.
+    fn privileged(&mut self, operation: impl FnOnce(&mut Self)) -> Result<()> {
+        match self.cpzero.cp0_accessible() {
+            true => {
+                operation(self);
+                Ok(())
+            }
+            false => self.coprocessor_unusable(0),
+        }
+    }
+}
+
+instruction_table! {
+    |cpu, memory, instr|
+
+    Sll "sll" Special(0x00) Shift MipsI [] => cpu.sll_emulate(instr),
+    Srl "srl" Special(0x02) Shift MipsI [] => cpu.srl_emulate(instr),
+    Sra "sra" Special(0x03) Shift MipsI [] => cpu.sra_emulate(instr),
+    Sllv "sllv" Special(0x04) ShiftVariable MipsI [] => cpu.sllv_emulate(instr),
+    Srlv "srlv" Special(0x06) ShiftVariable MipsI [] => cpu.srlv_emulate(instr),
+    Srav "srav" Special(0x07) ShiftVariable MipsI [] => cpu.srav_emulate(instr),
+    Jr "jr" Special(0x08) Rs MipsI [] => cpu.jr_emulate(instr),
+    Jalr "jalr" Special(0x09) Jalr MipsI [] => cpu.jalr_emulate(instr),
+    Syscall "syscall" Special(0x0c) None MipsI [SYSCALL] => cpu.syscall_emulate(),
+    Break "break" Special(0x0d) None MipsI [BREAKPOINT] => cpu.break_emulate(),
+    Mfhi "mfhi" Special(0x10) Rd MipsI [] => cpu.mfhi_emulate(instr),
+    Mthi "mthi" Special(0x11) Rs MipsI [] => cpu.mthi_emulate(instr),
+    Mflo "mflo" Special(0x12) Rd MipsI [] => cpu.mflo_emulate(instr),
+    Mtlo "mtlo" Special(0x13) Rs MipsI [] => cpu.mtlo_emulate(instr),
+    Mult "mult" Special(0x18) RsRt MipsI [] => cpu.mult_emulate(instr),
+    Multu "multu" Special(0x19) RsRt MipsI [] => cpu.multu_emulate(instr),
+    Div "div" Special(0x1a) RsRt MipsI [] => cpu.div_emulate(instr),
+    Divu "divu" Special(0x1b) RsRt MipsI [] => cpu.divu_emulate(instr),
+    Add "add" Special(0x20) RdRsRt MipsI [OVERFLOW] => cpu.add_emulate(instr),
+    Addu "addu" Special(0x21) RdRsRt MipsI [] => cpu.addu_emulate(instr),
+    Sub "sub" Special(0x22) RdRsRt MipsI [OVERFLOW] => cpu.sub_emulate(instr),
+    Subu "subu" Special(0x23) RdRsRt MipsI [] => cpu.subu_emulate(instr),
+    And "and" Special(0x24) RdRsRt MipsI [] => cpu.and_emulate(instr),
+    Or "or" Special(0x25) RdRsRt MipsI [] => cpu.or_emulate(instr),
+    Xor "xor" Special(0x26) RdRsRt MipsI [] => cpu.xor_emulate(instr),
+    Nor "nor" Special(0x27) RdRsRt MipsI [] => cpu.nor_emulate(instr),
+    Slt "slt" Special(0x2a) RdRsRt MipsI [] => cpu.slt_emulate(instr),
+    Sltu "sltu" Special(0x2b) RdRsRt MipsI [] => cpu.sltu_emulate(instr),
+
+    Bltz "bltz" Regimm(0) RsBranch MipsI [] => cpu.bltz_emulate(instr),
+    Bgez "bgez" Regimm(1) RsBranch MipsI [] => cpu.bgez_emulate(instr),
+    Bltzal "bltzal" Regimm(16) RsBranch MipsI [] => cpu.bltzal_emulate(instr),
+    Bgezal "bgezal" Regimm(17) RsBranch MipsI [] => cpu.bgezal_emulate(instr),
+
+    J "j" Opcode(0x02) Jump MipsI [] => cpu.j_emulate(instr),
+    Jal "jal" Opcode(0x03) Jump MipsI [] => cpu.jal_emulate(instr),
+    Beq "beq" Opcode(0x04) RsRtBranch MipsI [] => cpu.beq_emulate(instr),
+    Bne "bne" Opcode(0x05) RsRtBranch MipsI [] => cpu.bne_emulate(instr),
+    Blez "blez" Opcode(0x06) RsBranch MipsI [] => cpu.blez_emulate(instr),
+    Bgtz "bgtz" Opcode(0x07) RsBranch MipsI [] => cpu.bgtz_emulate(instr),
+    Addi "addi" Opcode(0x08) RtRsSigned MipsI [OVERFLOW] => cpu.addi_emulate(instr),
+    Addiu "addiu" Opcode(0x09) RtRsSigned MipsI [] => cpu.addiu_emulate(instr),
+    Slti "slti" Opcode(0x0a) RtRsSigned MipsI [] => cpu.slti_emulate(instr),
+    Sltiu "sltiu" Opcode(0x0b) RtRsSigned MipsI [] => cpu.sltiu_emulate(instr),
+    Andi "andi" Opcode(0x0c) RtRsUnsigned MipsI [] => cpu.andi_emulate(instr),
+    Ori "ori" Opcode(0x0d) RtRsUnsigned MipsI [] => cpu.ori_emulate(instr),
+    Xori "xori" Opcode(0x0e) RtRsUnsigned MipsI [] => cpu.xori_emulate(instr),
+    Lui "lui" Opcode(0x0f) RtUpper MipsI [] => cpu.lui_emulate(instr),
+
+    Mfc0 "mfc0" Cop0(0) RtCopRegister MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.privileged(|cpu| cpu.mfc0_emulate(instr)),
+    Mtc0 "mtc0" Cop0(4) RtCopRegister MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.privileged(|cpu| cpu.mtc0_emulate(instr)),
+    Bc0 "bc0" Cop0(8) Branch MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.privileged(|cpu| cpu.cpzero.bc0x_emulate(instr, cpu.pc)),
+    Tlbr "tlbr" Cop0Function(1) None MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.privileged(|cpu| cpu.cpzero.tlbr_emulate()),
+    Tlbwi "tlbwi" Cop0Function(2) None MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.privileged(|cpu| cpu.cpzero.tlbwi_emulate()),
+    Tlbwr "tlbwr" Cop0Function(6) None MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.privileged(|cpu| cpu.cpzero.tlbwr_emulate()),
+    Tlbp "tlbp" Cop0Function(8) None MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.privileged(|cpu| cpu.cpzero.tlbp_emulate()),
+    Rfe "rfe" Cop0Function(16) None MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.privileged(|cpu| cpu.cpzero.rfe_emulate()),
+
+    Mfc1 "mfc1" Cop1(0) RtFpr MipsI [COPROCESSOR_UNUSABLE] => cpu.copz_emulate(1, instr),
+    Cfc1 "cfc1" Cop1(2) RtCopRegister MipsI [COPROCESSOR_UNUSABLE] => cpu.copz_emulate(1, instr),
+    Mtc1 "mtc1" Cop1(4) RtFpr MipsI [COPROCESSOR_UNUSABLE] => cpu.copz_emulate(1, instr),
+    Ctc1 "ctc1" Cop1(6) RtCopRegister MipsI [COPROCESSOR_UNUSABLE] => cpu.copz_emulate(1, instr),
+    Bc1 "bc1" Cop1(8) Branch MipsI [COPROCESSOR_UNUSABLE] => cpu.copz_emulate(1, instr),
+
+    AddS "add.s" Cop1Function(16, 0) FpuThree MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    AddD "add.d" Cop1Function(17, 0) FpuThree MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    SubS "sub.s" Cop1Function(16, 1) FpuThree MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    SubD "sub.d" Cop1Function(17, 1) FpuThree MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    MulS "mul.s" Cop1Function(16, 2) FpuThree MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    MulD "mul.d" Cop1Function(17, 2) FpuThree MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    DivS "div.s" Cop1Function(16, 3) FpuThree MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    DivD "div.d" Cop1Function(17, 3) FpuThree MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    AbsS "abs.s" Cop1Function(16, 5) FpuTwo MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    AbsD "abs.d" Cop1Function(17, 5) FpuTwo MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    MovS "mov.s" Cop1Function(16, 6) FpuTwo MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.copz_emulate(1, instr),
+    MovD "mov.d" Cop1Function(17, 6) FpuTwo MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.copz_emulate(1, instr),
+    NegS "neg.s" Cop1Function(16, 7) FpuTwo MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    NegD "neg.d" Cop1Function(17, 7) FpuTwo MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CvtSD "cvt.s.d" Cop1Function(17, 32) FpuTwo MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CvtSW "cvt.s.w" Cop1Function(20, 32) FpuTwo MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CvtDS "cvt.d.s" Cop1Function(16, 33) FpuTwo MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CvtDW "cvt.d.w" Cop1Function(20, 33) FpuTwo MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CvtWS "cvt.w.s" Cop1Function(16, 36) FpuTwo MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CvtWD "cvt.w.d" Cop1Function(17, 36) FpuTwo MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+
+    CFS "c.f.s" Cop1Function(16, 48) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CUnS "c.un.s" Cop1Function(16, 49) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CEqS "c.eq.s" Cop1Function(16, 50) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CUeqS "c.ueq.s" Cop1Function(16, 51) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    COltS "c.olt.s" Cop1Function(16, 52) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CUltS "c.ult.s" Cop1Function(16, 53) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    COleS "c.ole.s" Cop1Function(16, 54) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CUleS "c.ule.s" Cop1Function(16, 55) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CSfS "c.sf.s" Cop1Function(16, 56) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CNgleS "c.ngle.s" Cop1Function(16, 57) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CSeqS "c.seq.s" Cop1Function(16, 58) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CNglS "c.ngl.s" Cop1Function(16, 59) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CLtS "c.lt.s" Cop1Function(16, 60) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CNgeS "c.nge.s" Cop1Function(16, 61) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CLeS "c.le.s" Cop1Function(16, 62) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CNgtS "c.ngt.s" Cop1Function(16, 63) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CFD "c.f.d" Cop1Function(17, 48) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CUnD "c.un.d" Cop1Function(17, 49) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CEqD "c.eq.d" Cop1Function(17, 50) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CUeqD "c.ueq.d" Cop1Function(17, 51) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    COltD "c.olt.d" Cop1Function(17, 52) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CUltD "c.ult.d" Cop1Function(17, 53) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    COleD "c.ole.d" Cop1Function(17, 54) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CUleD "c.ule.d" Cop1Function(17, 55) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CSfD "c.sf.d" Cop1Function(17, 56) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CNgleD "c.ngle.d" Cop1Function(17, 57) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CSeqD "c.seq.d" Cop1Function(17, 58) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CNglD "c.ngl.d" Cop1Function(17, 59) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CLtD "c.lt.d" Cop1Function(17, 60) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CNgeD "c.nge.d" Cop1Function(17, 61) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CLeD "c.le.d" Cop1Function(17, 62) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+    CNgtD "c.ngt.d" Cop1Function(17, 63) FpuCompare MipsI [COPROCESSOR_UNUSABLE | FLOATING_POINT]
+        => cpu.copz_emulate(1, instr),
+
+    Cop2 "cop2" Opcode(0x12) CopOperation MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.copz_emulate(2, instr),
+    Cop3 "cop3" Opcode(0x13) CopOperation MipsI [COPROCESSOR_UNUSABLE]
+        => cpu.copz_emulate(3, instr),
+
+    Lb "lb" Opcode(0x20) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.lb_emulate(memory, instr),
+    Lh "lh" Opcode(0x21) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.lh_emulate(memory, instr),
+    Lwl "lwl" Opcode(0x22) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.lwl_emulate(memory, instr),
+    Lw "lw" Opcode(0x23) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.lw_emulate(memory, instr),
+    Lbu "lbu" Opcode(0x24) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.lbu_emulate(memory, instr),
+    Lhu "lhu" Opcode(0x25) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.lhu_emulate(memory, instr),
+    Lwr "lwr" Opcode(0x26) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.lwr_emulate(memory, instr),
+    Sb "sb" Opcode(0x28) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.sb_emulate(memory, instr),
+    Sh "sh" Opcode(0x29) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.sh_emulate(memory, instr),
+    Swl "swl" Opcode(0x2a) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.swl_emulate(memory, instr),
+    Sw "sw" Opcode(0x2b) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.sw_emulate(memory, instr),
+    Swr "swr" Opcode(0x2e) Memory MipsI [ADDRESS_ERROR | TLB | BUS_ERROR]
+        => cpu.swr_emulate(memory, instr),
+    Cache "cache" Opcode(0x2f) Cache MipsIII [ADDRESS_ERROR | TLB | COPROCESSOR_UNUSABLE]
+        => cpu.cache_emulate(memory, instr),
+    Lwc1 "lwc1" Opcode(0x31) FprMemory MipsI
+        [ADDRESS_ERROR | TLB | BUS_ERROR | COPROCESSOR_UNUSABLE] => cpu.lwc1_emulate(memory, instr),
+    Lwc2 "lwc2" Opcode(0x32) CopMemory MipsI
+        [ADDRESS_ERROR | TLB | BUS_ERROR | COPROCESSOR_UNUSABLE] => cpu.lwc2_emulate(memory, instr),
+    Lwc3 "lwc3" Opcode(0x33) CopMemory MipsI
+        [ADDRESS_ERROR | TLB | BUS_ERROR | COPROCESSOR_UNUSABLE] => cpu.lwc3_emulate(memory, instr),
+    Swc1 "swc1" Opcode(0x39) FprMemory MipsI
+        [ADDRESS_ERROR | TLB | BUS_ERROR | COPROCESSOR_UNUSABLE] => cpu.swc1_emulate(memory, instr),
+    Swc2 "swc2" Opcode(0x3a) CopMemory MipsI
+        [ADDRESS_ERROR | TLB | BUS_ERROR | COPROCESSOR_UNUSABLE] => cpu.swc2_emulate(memory, instr),
+    Swc3 "swc3" Opcode(0x3b) CopMemory MipsI
+        [ADDRESS_ERROR | TLB | BUS_ERROR | COPROCESSOR_UNUSABLE] => cpu.swc3_emulate(memory, instr),
+}
+
+/// Returns the instruction reference as a Markdown table, which `rmips instructions` prints.
+pub fn reference() -> String {
+    let mut table = String::from(
+        "| Mnemonic | Operands | Encoding | ISA | May trap |\n\
+         |----------|----------|----------|-----|----------|\n",
+    );
+    for spec in INSTRUCTIONS {
+        table += &format!(
+            "| `{}` | {} | {} | {} | {} |\n",
+            spec.mnemonic,
+            spec.operands.syntax(),
+            spec.encoding,
+            spec.isa,
+            spec.traps
+        );
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn instruction_table() {
+        for (index, spec) in INSTRUCTIONS.iter().enumerate() {
+            assert_eq!(spec.op as usize, index);
+            let instr = Instruction(spec.encoding.word());
+            assert_eq!(Op::decode(instr), Some(spec.op), "{}", spec.mnemonic);
+            if instr.0 != 0 {
+                assert_eq!(instr.mnemonic(), spec.mnemonic);
+            }
+        }
+        assert_eq!(Op::decode(Instruction(0xfc00_0000)), None);
+        assert_eq!(Op::decode(Instruction(0x4210_0003)), None);
+
+        let reference = reference();
+        assert_eq!(reference.lines().count(), INSTRUCTIONS.len() + 2);
+        assert!(reference.contains(
+            "| `lw` | rt, offset(base) | opcode 0x23 | MIPS I | address error, TLB, bus error |\n"
+        ));
+        assert!(reference.contains("| `cache` | op, offset(base) | opcode 0x2f | MIPS III |"));
+    }
+    #[test]
+    fn destinations() {
+        use Destination::*;
+        let destinations = |word| {
+            let instr = Instruction(word);
+            Op::decode(instr).unwrap().destinations(instr)
+        };
+
+        // addu $t0, $t1, $t2 and lw $s0, 4($sp)
+        assert_eq!(destinations(0x012a_4021), vec![Gpr(8)]);
+        assert_eq!(destinations(0x8fb0_0004), vec![Gpr(16)]);
+        // sw $s0, 4($sp) and a nop, which writes $zero
+        assert_eq!(destinations(0xafb0_0004), vec![]);
+        assert_eq!(destinations(0x0000_0000), vec![]);
+        // mult $t0, $t1, jal and mtc0 $t0, $12
+        assert_eq!(destinations(0x0109_0018), vec![Hi, Lo]);
+        assert_eq!(destinations(0x0c00_0000), vec![Gpr(31)]);
+        assert_eq!(destinations(0x4088_6000), vec![Cp0(Cp0Register::Status)]);
+        // add.d $f2, $f4, $f6 writes a register pair
+        assert_eq!(destinations(0x4626_2080), vec![Fpr(2), Fpr(3), Fcsr]);
+    }
+}
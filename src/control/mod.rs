@@ -1,19 +1,26 @@
 use crate::Address;
 
+pub(crate) mod cache;
+pub(crate) mod coprocessor;
+pub mod cp1;
 pub(crate) mod cpu;
 pub(crate) mod cpzero;
-mod exception;
-mod instruction;
+pub mod disasm;
+pub(crate) mod exception;
+pub(crate) mod explain;
+pub(crate) mod instruction;
 mod instructions;
+pub(crate) mod invariants;
+pub mod isa;
+pub mod model;
 pub mod registers;
 mod tlbentry;
+pub(crate) mod verify;
 
 /// Address mask for determining which segment the address belongs to
 pub const KSEG_SELECT_MASK: Address = 0xe0000000;
 /// Start address of kernel-space
 pub const KERNEL_SPACE_MASK: Address = KSEG0;
-/// Start address of TLB-mapped cacheable user or kernel space
-pub const KUSEG: Address = 0;
 /// Start of direct-mapped cacheable kernel space
 pub const KSEG0: Address = 0x80000000;
 /// Start of direct-mapped non-cacheable kernel space
@@ -0,0 +1,103 @@
+//! Processor models that can be emulated by the same core.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::control::isa::IsaLevel;
+use crate::control::tlbentry::TlbFormat;
+
+/// Largest TLB that can be addressed by the 6-bit Index and Random fields.
+pub const MAX_TLB_ENTRIES: usize = 64;
+
+/// A MIPS implementation, which determines the PRId value and the default TLB geometry.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum CpuModel {
+    /// MIPS R3000A with 64 single-page TLB entries.
+    #[default]
+    R3000,
+    /// MIPS R4000 with 48 paired-page TLB entries.
+    R4000,
+}
+
+impl CpuModel {
+    /// Returns the layout of the TLB entries.
+    pub fn tlb_format(self) -> TlbFormat {
+        match self {
+            CpuModel::R3000 => TlbFormat::R3000,
+            CpuModel::R4000 => TlbFormat::R4000,
+        }
+    }
+
+    /// Returns the number of TLB entries implemented by the model.
+    pub fn tlb_entries(self) -> usize {
+        match self {
+            CpuModel::R3000 => 64,
+            CpuModel::R4000 => 48,
+        }
+    }
+
+    /// Returns the size of a line of the primary instruction cache in bytes.
+    pub fn icache_line(self) -> usize {
+        match self {
+            CpuModel::R3000 => 4,
+            CpuModel::R4000 => 16,
+        }
+    }
+
+    /// Returns the ISA level of the instructions the model executes.
+    pub fn isa(self) -> IsaLevel {
+        match self {
+            CpuModel::R3000 => IsaLevel::MipsI,
+            CpuModel::R4000 => IsaLevel::MipsIII,
+        }
+    }
+
+    /// Returns true if caches are isolated with the Status IsC bit, which the `cache`
+    /// instruction replaced after the R3000.
+    pub fn isolates_caches(self) -> bool {
+        self == CpuModel::R3000
+    }
+
+    /// Returns the value of the PRId register.
+    pub fn prid(self) -> u32 {
+        match self {
+            CpuModel::R3000 => 0x0230,
+            CpuModel::R4000 => 0x0422,
+        }
+    }
+}
+
+impl FromStr for CpuModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "r3000" | "r3000a" => Ok(CpuModel::R3000),
+            "r4000" => Ok(CpuModel::R4000),
+            _ => Err(format!("unknown CPU model: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for CpuModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuModel::R3000 => write!(f, "r3000"),
+            CpuModel::R4000 => write!(f, "r4000"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn cpu_model_from_str() {
+        assert_eq!("r3000".parse(), Ok(CpuModel::R3000));
+        assert_eq!("R3000A".parse(), Ok(CpuModel::R3000));
+        assert_eq!("r4000".parse(), Ok(CpuModel::R4000));
+        assert!("r10000".parse::<CpuModel>().is_err());
+    }
+}
@@ -24,6 +24,10 @@ impl BadVaddrRegister {
     pub fn new() -> Self {
         BadVaddrRegister { address: 0 }
     }
+
+    /// BadVaddr is read-only so software writes are ignored.
+    #[inline]
+    pub fn write(&mut self, _val: u32) {}
 }
 
 impl From<u32> for BadVaddrRegister {
@@ -29,6 +29,8 @@ impl CauseRegister {
     register_rw!(is_branch_delay, set_branch_delay, clear_branch_delay, 31);
     register_field!(get_coprocessor_error, set_coprocessor_error, 28, 29);
     register_field!(get_interrupt_pending, set_interrupt_pending, 8, 15);
+    // Only the two software interrupt pending bits can be written
+    register_write_mask!(0x0000_0300);
 
     /// Get the exception code field from the Cause register.
     #[inline]
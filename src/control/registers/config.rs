@@ -0,0 +1,42 @@
+//! MIPS CP0 Config register.
+//!
+//! Neither the R3000 nor the R4000 Config register describes the TLB, so the
+//! emulator follows the MIPS32 Config1 layout and reports the number of TLB
+//! entries minus one in the MMU Size field. This lets guest software size the
+//! TLB without knowing which model it is running on.
+use bit_field::BitField;
+
+/// Config Register.
+#[derive(Clone, Copy, Debug)]
+pub struct ConfigRegister {
+    pub bits: u32,
+}
+
+impl Default for ConfigRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConfigRegister {
+    /// Returns a new Config register.
+    pub fn new() -> Self {
+        ConfigRegister { bits: 0 }
+    }
+
+    register_field!(get_mmu_size, set_mmu_size, 25, 30);
+    // The TLB geometry is fixed by the CPU model
+    register_write_mask!(0);
+}
+
+impl From<u32> for ConfigRegister {
+    fn from(val: u32) -> Self {
+        ConfigRegister { bits: val }
+    }
+}
+
+impl From<ConfigRegister> for u32 {
+    fn from(val: ConfigRegister) -> Self {
+        val.bits
+    }
+}
@@ -23,6 +23,8 @@ impl ContextRegister {
 
     register_field!(get_ptebase, set_ptebase, 21, 31);
     register_field!(get_badvpn, set_badvpn, 2, 20);
+    // BadVPN is set by the hardware on TLB exceptions
+    register_write_mask!(0xffe0_0000);
 }
 
 impl From<u32> for ContextRegister {
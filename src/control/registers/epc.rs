@@ -22,6 +22,12 @@ impl EpcRegister {
     pub fn new() -> Self {
         EpcRegister { address: 0 }
     }
+
+    /// Writes a new return address to the EPC register.
+    #[inline]
+    pub fn write(&mut self, val: u32) {
+        self.address = val;
+    }
 }
 
 impl From<u32> for EpcRegister {
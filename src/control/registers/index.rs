@@ -24,6 +24,8 @@ impl IndexRegister {
     // Set when a tlbp instruction failed to find a valid translation.
     register_rw!(is_p, set_p, clear_p, 31);
     register_field!(get_index, set_index, 8, 13);
+    // The P bit is only set by the tlbp instruction
+    register_write_mask!(0x0000_3f00);
 }
 
 impl From<u32> for IndexRegister {
@@ -39,3 +39,17 @@ macro_rules! register_field {
         }
     };
 }
+
+macro_rules! register_write_mask {
+    ($mask: expr) => {
+        /// Bits of the register that can be modified by software.
+        pub const WRITE_MASK: u32 = $mask;
+
+        /// Writes `val` to the register the way the `mtc0` instruction would,
+        /// leaving read-only and reserved bits unchanged.
+        #[inline]
+        pub fn write(&mut self, val: u32) {
+            self.bits = (self.bits & !Self::WRITE_MASK) | (val & Self::WRITE_MASK);
+        }
+    };
+}
@@ -4,6 +4,7 @@ use numeric_enum_macro::numeric_enum;
 mod macros;
 mod badvaddr;
 mod cause;
+mod config;
 mod context;
 mod epc;
 mod index;
@@ -13,6 +14,7 @@ mod status;
 
 pub use badvaddr::BadVaddrRegister;
 pub use cause::CauseRegister;
+pub use config::ConfigRegister;
 pub use context::ContextRegister;
 pub use epc::EpcRegister;
 pub use index::IndexRegister;
@@ -20,6 +22,13 @@ pub use prid::PridRegister;
 pub use random::RandomRegister;
 pub use status::StatusRegister;
 
+/// ABI names of the general purpose registers, indexed by register number.
+pub const REGISTER_NAMES: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "t7", "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp",
+    "ra",
+];
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Register {
     /// Zero register.
@@ -84,10 +93,14 @@ numeric_enum! {
         Index = 0,
         /// TLB randomized access register.
         Random = 1,
-        /// Low-order word of "current" TLB entry.
+        /// Low-order word of "current" TLB entry, or the even page of a paired entry.
         EntryLo = 2,
+        /// Odd page of a paired TLB entry.
+        EntryLo1 = 3,
         /// Page-table lookup address.
         Context = 4,
+        /// Page size of a paired TLB entry.
+        PageMask = 5,
         /// Contains the last invalid program address which caused a trap.
         BadVaddr = 8,
         /// High-order word of "current" TLB entry.
@@ -100,5 +113,7 @@ numeric_enum! {
         Epc = 14,
         /// Processor Revision Identifier.
         Prid = 15,
+        /// Processor configuration, including the TLB size.
+        Config = 16,
     }
 }
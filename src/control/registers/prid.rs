@@ -21,6 +21,8 @@ impl PridRegister {
         PridRegister { bits: 0 }
     }
 
+    register_write_mask!(0);
+
     /// Get the implementation number from the PRId register.
     #[inline]
     pub fn imp(&self) -> u32 {
@@ -27,6 +27,8 @@ impl RandomRegister {
     }
 
     register_field!(get_value, set_value, 8, 13);
+    // Random is maintained by the hardware and cannot be written
+    register_write_mask!(0);
 }
 
 impl From<u32> for RandomRegister {
@@ -28,8 +28,8 @@ impl StatusRegister {
         disable_interrupts,
         0
     );
-    // Current Kernel / User Mode Status
-    register_rw!(is_kernel_mode, enter_kernel_mode, enter_user_mode, 1);
+    // Current Kernel / User Mode Status (set when in user mode)
+    register_rw!(kuc, set_kuc, clear_kuc, 1);
     // Previous Interrupt Enable Status
     register_rw!(iep, set_iep, clear_iep, 2);
     // Previous Kernel / User Status
@@ -64,6 +64,47 @@ impl StatusRegister {
     register_r!(cu3, 31);
     // Interrupt Mask
     register_field!(get_interrupt_mask, set_interrupt_mask, 8, 15);
+    // Reserved bits and the CM, PE, and TS status bits are read-only
+    register_write_mask!(0xf247_ff3f);
+
+    /// Bits that are reserved on every model, which always read as zero.
+    pub const RESERVED_BITS: u32 = 0x0d80_00c0;
+
+    /// Returns true if the processor is running in kernel mode.
+    #[inline]
+    pub fn is_kernel_mode(&self) -> bool {
+        !self.kuc()
+    }
+
+    /// Switches the processor to kernel mode.
+    #[inline]
+    pub fn enter_kernel_mode(&mut self) {
+        self.clear_kuc();
+    }
+
+    /// Switches the processor to user mode.
+    #[inline]
+    pub fn enter_user_mode(&mut self) {
+        self.set_kuc();
+    }
+
+    /// Pushes the KU/IE mode stack when an exception is taken.
+    ///
+    /// The previous mode becomes the old mode, the current mode becomes the
+    /// previous mode, and the processor enters kernel mode with interrupts disabled.
+    pub fn push_mode_stack(&mut self) {
+        let stack = self.bits.get_bits(0..=5);
+        self.bits.set_bits(0..=5, (stack << 2) & 0x3f);
+    }
+
+    /// Pops the KU/IE mode stack on return from exception.
+    ///
+    /// The previous mode becomes the current mode and the old mode becomes the
+    /// previous mode. The old mode is left unchanged.
+    pub fn pop_mode_stack(&mut self) {
+        let stack = self.bits.get_bits(0..=5);
+        self.bits.set_bits(0..=3, stack >> 2);
+    }
 }
 
 impl From<u32> for StatusRegister {
@@ -77,3 +118,42 @@ impl From<StatusRegister> for u32 {
         val.bits
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn status_push_mode_stack() {
+        let mut status = StatusRegister::new();
+        status.enter_user_mode();
+        status.enable_interrupts();
+        status.push_mode_stack();
+
+        assert_eq!(status.is_kernel_mode(), true);
+        assert_eq!(status.are_interrupts_enabled(), false);
+        assert_eq!(status.kup(), true);
+        assert_eq!(status.iep(), true);
+        assert_eq!(status.kuo(), false);
+        assert_eq!(status.ieo(), false);
+    }
+
+    #[test]
+    fn status_pop_mode_stack() {
+        let mut status = StatusRegister::from(0b11_10_00);
+        status.pop_mode_stack();
+
+        assert_eq!(status.bits, 0b11_11_10);
+    }
+
+    #[test]
+    fn status_mode_stack_preserves_other_bits() {
+        let mut status = StatusRegister::from(0x1040_ff3f);
+        status.push_mode_stack();
+        assert_eq!(status.bits, 0x1040_ff3c);
+
+        status.pop_mode_stack();
+        assert_eq!(status.bits, 0x1040_ff3f);
+    }
+}
@@ -26,17 +26,182 @@ bitflags! {
     }
 }
 
+/// Bits of the EntryHi register that can be modified by software.
+pub const ENTRYHI_WRITE_MASK: u32 = EntryHiMask::all().bits();
+/// Bits of the EntryLo register that can be modified by software.
+pub const ENTRYLO_WRITE_MASK: u32 = EntryLoMask::all().bits();
+
+/// Bits of the R4000 EntryHi register: VPN2 and an 8-bit ASID.
+const R4000_ENTRYHI_WRITE_MASK: u32 = 0xffff_e0ff;
+/// Bits of the R4000 EntryLo0/EntryLo1 registers: PFN, cache attribute, D, V and G.
+const R4000_ENTRYLO_WRITE_MASK: u32 = 0x3fff_ffff;
+/// Bits of the R4000 PageMask register.
+const R4000_PAGEMASK_WRITE_MASK: u32 = 0x01ff_e000;
+/// Bits of the R4000 EntryHi register that hold the ASID.
+const R4000_ASID_MASK: u32 = 0x0000_00ff;
+/// Offset bits of the smallest R4000 page pair, which PageMask extends.
+const R4000_PAIR_OFFSET_MASK: u32 = 0x0000_1fff;
+/// Bits of the R4000 EntryLo registers: D, V and G.
+const R4000_DIRTY: u32 = 1 << 2;
+const R4000_VALID: u32 = 1 << 1;
+const R4000_GLOBAL: u32 = 1 << 0;
+
+/// Layout of the TLB entries and of the CP0 registers used to access them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TlbFormat {
+    /// Each entry maps a single 4 KB page through EntryHi and EntryLo.
+    R3000,
+    /// Each entry maps an even/odd pair of pages through EntryHi, EntryLo0,
+    /// EntryLo1 and PageMask.
+    R4000,
+}
+
+impl TlbFormat {
+    /// Position of the index field in the Index and Random registers.
+    pub fn index_shift(self) -> u32 {
+        match self {
+            TlbFormat::R3000 => 8,
+            TlbFormat::R4000 => 0,
+        }
+    }
+
+    /// Lowest value taken by the Random register.
+    pub fn random_lower_bound(self) -> u32 {
+        match self {
+            // The first 8 entries are reserved for the operating system
+            TlbFormat::R3000 => 8,
+            TlbFormat::R4000 => 0,
+        }
+    }
+
+    /// Bits of the Context register that can be modified by software (PTEBase).
+    pub fn context_write_mask(self) -> u32 {
+        match self {
+            TlbFormat::R3000 => 0xffe0_0000,
+            TlbFormat::R4000 => 0xff80_0000,
+        }
+    }
+
+    /// Returns the BadVPN field of the Context register for a TLB exception at `vaddress`.
+    ///
+    /// The field is scaled so that PTEBase | BadVPN is the address of the page table entry:
+    /// 4-byte entries for the R3000 and 8-byte even/odd pairs for the R4000.
+    pub fn context_badvpn(self, vaddress: u32) -> u32 {
+        match self {
+            TlbFormat::R3000 => (vaddress >> 10) & 0x001f_fffc,
+            TlbFormat::R4000 => (vaddress >> 9) & 0x007f_fff0,
+        }
+    }
+
+    /// Returns the VPN (or VPN2) bits of EntryHi for a TLB exception at `vaddress`.
+    pub fn entryhi_vpn(self, vaddress: u32) -> u32 {
+        match self {
+            TlbFormat::R3000 => vaddress & EntryHiMask::VPN.bits(),
+            TlbFormat::R4000 => vaddress & 0xffff_e000,
+        }
+    }
+
+    /// Returns the ASID field of an EntryHi value, in place.
+    pub fn asid(self, entryhi: u32) -> u32 {
+        match self {
+            TlbFormat::R3000 => entryhi & EntryHiMask::ASID.bits(),
+            TlbFormat::R4000 => entryhi & R4000_ASID_MASK,
+        }
+    }
+
+    pub fn entryhi_write_mask(self) -> u32 {
+        match self {
+            TlbFormat::R3000 => ENTRYHI_WRITE_MASK,
+            TlbFormat::R4000 => R4000_ENTRYHI_WRITE_MASK,
+        }
+    }
+
+    pub fn entrylo_write_mask(self) -> u32 {
+        match self {
+            TlbFormat::R3000 => ENTRYLO_WRITE_MASK,
+            TlbFormat::R4000 => R4000_ENTRYLO_WRITE_MASK,
+        }
+    }
+
+    /// EntryLo1 only exists for paired entries.
+    pub fn entrylo1_write_mask(self) -> u32 {
+        match self {
+            TlbFormat::R3000 => 0,
+            TlbFormat::R4000 => R4000_ENTRYLO_WRITE_MASK,
+        }
+    }
+
+    /// PageMask only exists for paired entries.
+    pub fn pagemask_write_mask(self) -> u32 {
+        match self {
+            TlbFormat::R3000 => 0,
+            TlbFormat::R4000 => R4000_PAGEMASK_WRITE_MASK,
+        }
+    }
+}
+
+/// How a TLB entry maps a virtual address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TlbMapping {
+    pub paddress: u32,
+    /// Set if the mapping may be used, or else accesses raise a TLB miss exception.
+    pub valid: bool,
+    /// Set if the page may be written, or else stores raise a TLB Modification exception.
+    pub dirty: bool,
+}
+
 /// Represents an entry in the TLB for `CPZero`.
 ///
-/// A TLB entry is 64 bits wide but is represented here
-/// as two separate fields: `entryhi` and `entrylo`.
+/// An R3000 TLB entry is 64 bits wide but is represented here
+/// as two separate fields: `entryhi` and `entrylo`. R4000 entries
+/// also use `entrylo1` for the odd page and `pagemask`.
 #[derive(Copy, Clone, Debug, Default)]
 pub struct TlbEntry {
     pub entryhi: u32,
     pub entrylo: u32,
+    pub entrylo1: u32,
+    pub pagemask: u32,
 }
 
 impl TlbEntry {
+    /// Returns the mapping of `vaddress` if this entry maps its page for the address space
+    /// `asid`, given as an EntryHi value. Global entries match every address space.
+    pub fn lookup(&self, format: TlbFormat, vaddress: u32, asid: u32) -> Option<TlbMapping> {
+        match format {
+            TlbFormat::R3000 => {
+                let matches = self.vpn() == vaddress & EntryHiMask::VPN.bits()
+                    && (self.global() || self.asid() as u32 == format.asid(asid));
+                matches.then(|| TlbMapping {
+                    paddress: self.pfn() | (vaddress & !EntryHiMask::VPN.bits()),
+                    valid: self.valid(),
+                    dirty: self.dirty(),
+                })
+            }
+            TlbFormat::R4000 => {
+                // An entry maps an even and an odd page, each half of the pair
+                let pair_offset = self.pagemask | R4000_PAIR_OFFSET_MASK;
+                let global = self.entrylo & self.entrylo1 & R4000_GLOBAL != 0;
+                let matches = (self.entryhi ^ vaddress) & !pair_offset & !R4000_ASID_MASK == 0
+                    && (global || format.asid(self.entryhi) == format.asid(asid));
+                if !matches {
+                    return None;
+                }
+
+                let page_offset = pair_offset >> 1;
+                let entrylo = match vaddress & (page_offset + 1) != 0 {
+                    true => self.entrylo1,
+                    false => self.entrylo,
+                };
+                let frame = ((entrylo as u64 >> 6) << 12) as u32;
+                Some(TlbMapping {
+                    paddress: (frame & !page_offset) | (vaddress & page_offset),
+                    valid: entrylo & R4000_VALID != 0,
+                    dirty: entrylo & R4000_DIRTY != 0,
+                })
+            }
+        }
+    }
+
     fn vpn(&self) -> u32 {
         self.entryhi & EntryHiMask::VPN.bits()
     }
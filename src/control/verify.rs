@@ -0,0 +1,213 @@
+//! Cross-checking of the built-in instruction decoder against Capstone.
+//!
+//! Each instruction is disassembled by both and the mnemonics and operands are compared.
+//! Capstone prints aliases such as `move` or `beqz` and leaves out `$zero` operands, so
+//! aliases are mapped back to the base instruction and only the registers other than `$zero`
+//! and the nonzero numbers are compared. Coprocessor register names are not comparable and
+//! only the mnemonic is checked for coprocessor instructions.
+//!
+//! Capstone decodes MIPS32 rather than MIPS I. Encodings it fails to decode, like `rfe`, are
+//! skipped, as are the encodings that MIPS I reserves but MIPS32 defines and the CP3 opcodes
+//! that MIPS32 reuses for `pref` and the COP1X instructions.
+
+use std::fmt;
+
+use capstone::prelude::*;
+
+use crate::control::disasm::disassemble;
+use crate::control::instruction::Instruction;
+use crate::Address;
+
+/// An instruction that Capstone disassembles differently from the built-in decoder.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeMismatch {
+    pub pc: Address,
+    pub instruction: u32,
+    /// The disassembly from the built-in decoder.
+    pub rmips: String,
+    /// The disassembly from Capstone.
+    pub capstone: String,
+}
+
+impl fmt::Display for DecodeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "0x{:08x}: {:08x} decodes as `{}` but Capstone decodes `{}`",
+            self.pc, self.instruction, self.rmips, self.capstone
+        )
+    }
+}
+
+/// Returns the base instruction of a Capstone alias.
+fn base_mnemonic(mnemonic: &str) -> &str {
+    match mnemonic {
+        "nop" | "ssnop" | "ehb" => "sll",
+        "move" => "addu",
+        "b" | "beqz" => "beq",
+        "bnez" => "bne",
+        "bal" => "bgezal",
+        "negu" => "subu",
+        "neg" => "sub",
+        "not" => "nor",
+        mnemonic => mnemonic,
+    }
+}
+
+/// Splits operands into the sorted registers other than `$zero` and the sorted nonzero numbers.
+fn operand_fields(operands: &str) -> (Vec<&str>, Vec<i64>) {
+    let mut registers = Vec::new();
+    let mut numbers = Vec::new();
+    for token in operands
+        .split([',', '(', ')'])
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+    {
+        if token.starts_with('$') {
+            if token != "$zero" {
+                registers.push(token);
+            }
+            continue;
+        }
+
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+        let value = match digits.strip_prefix("0x") {
+            Some(hex) => i64::from_str_radix(hex, 16),
+            None => digits.parse(),
+        };
+        match value {
+            Ok(0) => {}
+            Ok(value) => numbers.push(if negative { -value } else { value }),
+            // Keep unparsable operands so that they are compared as a mismatch
+            Err(_) => registers.push(token),
+        }
+    }
+
+    registers.sort_unstable();
+    numbers.sort_unstable();
+    (registers, numbers)
+}
+
+/// Returns true if the built-in disassembly of `instr` agrees with Capstone's.
+fn agrees(instr: Instruction, rmips: &str, mnemonic: &str, operands: &str) -> bool {
+    let (rmips_mnemonic, rmips_operands) = rmips.split_once(' ').unwrap_or((rmips, ""));
+    if base_mnemonic(rmips_mnemonic) != base_mnemonic(mnemonic) {
+        return false;
+    }
+
+    // Instructions that either side prints without operands, like `nop` or `break`, and
+    // coprocessor instructions only have their mnemonic checked
+    let coprocessor = matches!(instr.opcode(), 0x10..=0x13 | 0x30..=0x3b);
+    coprocessor
+        || operands.is_empty()
+        || rmips_operands.is_empty()
+        || operand_fields(rmips_operands) == operand_fields(operands)
+}
+
+pub struct DecodeVerifier {
+    capstone: Capstone,
+}
+
+impl Default for DecodeVerifier {
+    fn default() -> Self {
+        Self {
+            capstone: Capstone::new()
+                .mips()
+                .mode(arch::mips::ArchMode::Mips32)
+                .build()
+                .expect("Capstone failed to initialize"),
+        }
+    }
+}
+
+impl DecodeVerifier {
+    /// Disassembles the instruction at `pc` with both decoders and returns the mismatch, if any.
+    pub fn check(&self, pc: Address, instr: Instruction) -> Option<DecodeMismatch> {
+        if instr.mnemonic() == "reserved" || matches!(instr.opcode(), 0x13 | 0x33 | 0x3b) {
+            return None;
+        }
+
+        let code = instr.0.to_le_bytes();
+        let instructions = self.capstone.disasm_count(&code, pc.into(), 1).ok()?;
+        let decoded = instructions.iter().next()?;
+        let mnemonic = decoded.mnemonic().unwrap_or_default();
+        let operands = decoded.op_str().unwrap_or_default();
+
+        let rmips = disassemble(instr, pc);
+        match agrees(instr, &rmips, mnemonic, operands) {
+            true => None,
+            false => Some(DecodeMismatch {
+                pc,
+                instruction: instr.0,
+                rmips,
+                capstone: format!("{} {}", mnemonic, operands).trim_end().to_owned(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn verify_decode_agrees() {
+        let verifier = DecodeVerifier::default();
+        let mut mismatches = Vec::new();
+
+        // Every primary opcode and function code with distinct register fields
+        for opcode in 0..64 {
+            for low in 0..64 {
+                let word = opcode << 26 | 9 << 21 | 10 << 16 | 8 << 11 | 3 << 6 | low;
+                let pc = 0xbfc0_0000 + 4 * low;
+                mismatches.extend(verifier.check(pc, Instruction(word)));
+            }
+        }
+        for word in [
+            0x0000_0000,
+            0x0120_4021,
+            0x1000_0004,
+            0x1100_fffe,
+            0x0004_4023,
+            0x4488_1000,
+            0x4448_f800,
+            0x4501_0002,
+            0x4622_2080,
+            0x4600_2021,
+            0x4604_103c,
+            0x4620_3024,
+        ] {
+            mismatches.extend(verifier.check(0xbfc0_0000, Instruction(word)));
+        }
+
+        let mnemonics: Vec<_> = mismatches.iter().map(|m| m.rmips.as_str()).collect();
+        assert_eq!(mnemonics, Vec::<&str>::new());
+    }
+
+    #[test]
+    fn verify_decode_mismatch() {
+        let instr = Instruction(0x0109_4021);
+        assert!(agrees(instr, "addu $t0, $t0, $t1", "addu", "$t0, $t0, $t1"));
+        assert!(agrees(instr, "addu $t0, $t1, $zero", "move", "$t0, $t1"));
+        assert!(!agrees(
+            instr,
+            "addu $t0, $t0, $t1",
+            "subu",
+            "$t0, $t0, $t1"
+        ));
+        assert!(!agrees(
+            instr,
+            "addu $t0, $t0, $t1",
+            "addu",
+            "$t0, $t0, $t2"
+        ));
+
+        let load = Instruction(0x8fa4_0010);
+        assert!(agrees(load, "lw $a0, 16($sp)", "lw", "$a0, 0x10($sp)"));
+        assert!(!agrees(load, "lw $a0, 16($sp)", "lw", "$a0, 0x14($sp)"));
+    }
+}
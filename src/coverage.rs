@@ -0,0 +1,97 @@
+//! Instruction set coverage of the executed code.
+//!
+//! Executed instructions are counted by mnemonic and compared against every instruction the
+//! decoder knows, which lists the instructions that a set of programs never exercised. `nop`
+//! is counted separately from `sll` so that shifts are only covered by real shifts.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::control::instruction::Instruction;
+use crate::control::isa::INSTRUCTIONS;
+
+/// Returns the mnemonics of every instruction the decoder knows.
+pub fn instruction_set() -> BTreeSet<&'static str> {
+    std::iter::once("nop")
+        .chain(INSTRUCTIONS.iter().map(|spec| spec.mnemonic))
+        .collect()
+}
+
+#[derive(Debug, Default)]
+pub struct InstructionCoverage {
+    counts: BTreeMap<&'static str, u64>,
+}
+
+impl InstructionCoverage {
+    /// Records that `instr` executed.
+    pub fn record(&mut self, instr: Instruction) {
+        *self.counts.entry(instr.mnemonic()).or_default() += 1;
+    }
+
+    /// Adds the counts of `other`, e.g. to combine the coverage of several programs.
+    pub fn merge(&mut self, other: &InstructionCoverage) {
+        for (mnemonic, count) in &other.counts {
+            *self.counts.entry(mnemonic).or_default() += count;
+        }
+    }
+
+    /// Returns how many times the instruction with `mnemonic` executed.
+    pub fn count(&self, mnemonic: &str) -> u64 {
+        self.counts.get(mnemonic).copied().unwrap_or(0)
+    }
+
+    /// Returns the mnemonics of the instructions that never executed.
+    pub fn untested(&self) -> Vec<&'static str> {
+        instruction_set()
+            .into_iter()
+            .filter(|mnemonic| !self.counts.contains_key(mnemonic))
+            .collect()
+    }
+}
+
+impl fmt::Display for InstructionCoverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let untested = self.untested();
+        let covered = self.counts.keys().filter(|m| **m != "reserved").count();
+        writeln!(
+            f,
+            "Instruction coverage: {} of {} instructions executed",
+            covered,
+            covered + untested.len()
+        )?;
+
+        for (mnemonic, count) in &self.counts {
+            writeln!(f, "    {:<8} {}", mnemonic, count)?;
+        }
+        write!(f, "Untested: {}", untested.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn coverage_report() {
+        let set = instruction_set();
+        assert_eq!(set.len(), 133);
+        assert!(set.contains("nop") && set.contains("rfe") && set.contains("bltzal"));
+
+        let mut coverage = InstructionCoverage::default();
+        coverage.record(Instruction(0x0000_0000)); // nop
+        coverage.record(Instruction(0x012a_4021)); // addu t0, t1, t2
+
+        let mut other = InstructionCoverage::default();
+        other.record(Instruction(0x012a_4021));
+        other.record(Instruction(0xfc00_0000)); // reserved
+        coverage.merge(&other);
+
+        assert_eq!(coverage.count("addu"), 2);
+        assert_eq!(coverage.count("sll"), 0);
+        assert_eq!(coverage.untested().len(), 131);
+        assert!(coverage
+            .to_string()
+            .starts_with("Instruction coverage: 2 of 133 instructions executed\n"));
+    }
+}
@@ -0,0 +1,348 @@
+//! Verification that repeated runs of the same configuration execute identically.
+//!
+//! Rewinding, forking and fuzzing all assume that a configuration replays exactly. Every run
+//! hashes the addresses and words of the instructions it executes, and the hash is recorded every
+//! `CHECKPOINT_INTERVAL` instructions so that a divergence can be narrowed down to an interval.
+//! When the runs halt, their registers and memory are compared as well. Host time leaking into the
+//! guest, such as through `--host-clock` or a random RAM base without a seed, shows up as a
+//! divergence.
+
+use std::fmt;
+
+use crate::control::cp1::FCSR;
+use crate::control::instruction::Instruction;
+use crate::control::registers::REGISTER_NAMES;
+use crate::emulator::{Emulator, RunSummary, SliceBudget, SliceOutcome};
+use crate::util::error::{Result, RmipsError};
+use crate::util::opts::Opts;
+use crate::Address;
+
+/// Number of instructions between two recorded hashes of the instruction stream.
+pub const CHECKPOINT_INTERVAL: u64 = 4096;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Running FNV-1a hash of the addresses and words of the executed instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstructionStream {
+    hash: u64,
+}
+
+impl Default for InstructionStream {
+    fn default() -> Self {
+        Self {
+            hash: FNV_OFFSET_BASIS,
+        }
+    }
+}
+
+impl InstructionStream {
+    /// Adds the instruction `instr` executed at `pc` to the stream.
+    pub(crate) fn record(&mut self, pc: Address, instr: Instruction) {
+        for byte in pc.to_le_bytes().iter().chain(instr.0.to_le_bytes().iter()) {
+            self.hash = (self.hash ^ *byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+/// The first difference between a run and the first run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    /// The runs executed different instructions between the two instruction counts.
+    Stream { from: u64, to: u64 },
+    /// The runs halted for different reasons or after a different number of instructions.
+    Halt {
+        first: RunSummary,
+        other: RunSummary,
+    },
+    /// A register has a different value at the end of the runs.
+    Register {
+        name: String,
+        first: u32,
+        other: u32,
+    },
+    /// The byte at a physical address differs at the end of the runs.
+    Memory {
+        address: Address,
+        first: u8,
+        other: u8,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Divergence::Stream { from, to } => write!(
+                f,
+                "executed different instructions between instructions {} and {}",
+                from, to
+            ),
+            Divergence::Halt { first, other } => write!(
+                f,
+                "halted with {:?} after {} instructions instead of {:?} after {}",
+                other.halt_reason, other.instructions, first.halt_reason, first.instructions
+            ),
+            Divergence::Register { name, first, other } => write!(
+                f,
+                "ended with {} = 0x{:08x} instead of 0x{:08x}",
+                name, other, first
+            ),
+            Divergence::Memory {
+                address,
+                first,
+                other,
+            } => write!(
+                f,
+                "ended with 0x{:02x} at physical address 0x{:08x} instead of 0x{:02x}",
+                other, address, first
+            ),
+        }
+    }
+}
+
+/// The outcome of `verify`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeterminismReport {
+    /// Number of runs that were compared, which stops at the first run that diverged.
+    pub runs: usize,
+    /// Number of instructions of the first run.
+    pub instructions: u64,
+    /// The run that diverged from the first run, numbered from 1, and how.
+    pub divergence: Option<(usize, Divergence)>,
+}
+
+impl fmt::Display for DeterminismReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.divergence {
+            None => write!(
+                f,
+                "All {} runs executed the same {} instructions and ended in the same state",
+                self.runs, self.instructions
+            ),
+            Some((run, divergence)) => {
+                write!(f, "Run {} diverged from run 1: {}", run, divergence)
+            }
+        }
+    }
+}
+
+/// The instruction stream and final state of one run.
+struct Run {
+    checkpoints: Vec<u64>,
+    summary: RunSummary,
+    registers: Vec<(String, u32)>,
+    memories: Vec<(Address, Vec<u8>)>,
+}
+
+impl Run {
+    /// Runs a new emulator built from `opts` until it halts.
+    fn record(opts: &Opts) -> Result<Self> {
+        let mut emulator = Emulator::new(opts.clone())?;
+        emulator.stream = Some(InstructionStream::default());
+        let mut checkpoints = Vec::new();
+        let budget = SliceBudget::Instructions(CHECKPOINT_INTERVAL);
+        let summary = loop {
+            let outcome = emulator.run_slice(budget)?;
+            if let Some(stream) = &emulator.stream {
+                checkpoints.push(stream.hash());
+            }
+            if let SliceOutcome::Halted(summary) = outcome {
+                break summary;
+            }
+        };
+
+        let snapshot = emulator.snapshot();
+        let cpu = snapshot.cpu();
+        let cpzero = &cpu.cpzero;
+        let mut registers = vec![("pc".to_owned(), cpu.pc)];
+        registers.extend(
+            REGISTER_NAMES
+                .iter()
+                .zip(cpu.reg.iter())
+                .map(|(name, value)| (format!("${}", name), *value)),
+        );
+        registers.extend(vec![
+            ("hi".to_owned(), cpu.high),
+            ("lo".to_owned(), cpu.low),
+            ("Index".to_owned(), cpzero.index.into()),
+            ("Random".to_owned(), cpzero.random.into()),
+            ("EntryLo".to_owned(), cpzero.entrylo),
+            ("Context".to_owned(), cpzero.context.into()),
+            ("BadVAddr".to_owned(), cpzero.badvaddr.into()),
+            ("EntryHi".to_owned(), cpzero.entryhi),
+            ("Status".to_owned(), cpzero.status.into()),
+            ("Cause".to_owned(), cpzero.cause.into()),
+            ("EPC".to_owned(), cpzero.epc.into()),
+        ]);
+        if let Some(fpu) = emulator.cpu.attached_coprocessor(1) {
+            registers.extend((0..32).map(|reg| (format!("$f{}", reg), fpu.read_register(reg))));
+            registers.push(("FCSR".to_owned(), fpu.read_control(FCSR)));
+        }
+
+        Ok(Self {
+            checkpoints,
+            summary,
+            registers,
+            memories: snapshot.memories,
+        })
+    }
+
+    /// Returns the first difference of `other` from this run.
+    fn compare(&self, other: &Run) -> Option<Divergence> {
+        let stream = self
+            .checkpoints
+            .iter()
+            .zip(other.checkpoints.iter())
+            .position(|(first, other)| first != other);
+        if let Some(checkpoint) = stream {
+            let from = checkpoint as u64 * CHECKPOINT_INTERVAL;
+            let last = self.summary.instructions.min(other.summary.instructions);
+            return Some(Divergence::Stream {
+                from,
+                to: (from + CHECKPOINT_INTERVAL).min(last),
+            });
+        }
+        if self.summary != other.summary {
+            return Some(Divergence::Halt {
+                first: self.summary,
+                other: other.summary,
+            });
+        }
+
+        let registers = self.registers.iter().zip(other.registers.iter());
+        for ((name, first), (_, value)) in registers {
+            if first != value {
+                return Some(Divergence::Register {
+                    name: name.clone(),
+                    first: *first,
+                    other: *value,
+                });
+            }
+        }
+
+        for ((base, first), (_, memory)) in self.memories.iter().zip(other.memories.iter()) {
+            let offset = first.iter().zip(memory.iter()).position(|(a, b)| a != b);
+            if let Some(offset) = offset {
+                return Some(Divergence::Memory {
+                    address: base + offset as Address,
+                    first: first[offset],
+                    other: memory[offset],
+                });
+            }
+        }
+        None
+    }
+}
+
+/// Returns an error naming the first device configured in `opts` whose input comes from the
+/// host, which differs between runs.
+fn check_replayable(opts: &Opts) -> Result<()> {
+    let inputs = [
+        (opts.keyboard, "keyboard"),
+        (opts.nvram.is_some(), "non-volatile storage"),
+        (opts.sharedmemory.is_some(), "shared memory"),
+        (opts.netlisten.is_some(), "network"),
+        (opts.seriallink.is_some(), "serial link"),
+    ];
+
+    match inputs.iter().find(|(enabled, _)| *enabled) {
+        Some((_, device)) => Err(RmipsError::HostInput(device)),
+        None => Ok(()),
+    }
+}
+
+/// Runs the configuration `opts` `runs` times, one run after the other, and compares every run
+/// with the first until one diverges.
+pub fn verify(opts: &Opts, runs: usize) -> Result<DeterminismReport> {
+    check_replayable(opts)?;
+
+    let first = Run::record(opts)?;
+    for run in 2..=runs {
+        println!("\nVerifying determinism: run {} of {}", run, runs);
+        if let Some(divergence) = first.compare(&Run::record(opts)?) {
+            return Ok(DeterminismReport {
+                runs: run,
+                instructions: first.summary.instructions,
+                divergence: Some((run, divergence)),
+            });
+        }
+    }
+
+    Ok(DeterminismReport {
+        runs,
+        instructions: first.summary.instructions,
+        divergence: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HaltReason;
+    use pretty_assertions::assert_eq;
+
+    fn run(checkpoints: Vec<u64>, instructions: u64) -> Run {
+        Run {
+            checkpoints,
+            summary: RunSummary {
+                halt_reason: HaltReason::Break,
+                instructions,
+                cycles: instructions,
+                exit_code: None,
+                interrupt_latency: None,
+            },
+            registers: vec![("pc".to_owned(), 0xbfc0_0100), ("$v0".to_owned(), 1)],
+            memories: vec![(0x1000, vec![0, 1, 2, 3])],
+        }
+    }
+
+    #[test]
+    fn determinism_divergences() {
+        let first = run(vec![1, 2, 3], 10_000);
+        assert_eq!(first.compare(&run(vec![1, 2, 3], 10_000)), None);
+
+        assert_eq!(
+            first
+                .compare(&run(vec![1, 5, 6], 10_000))
+                .unwrap()
+                .to_string(),
+            "executed different instructions between instructions 4096 and 8192"
+        );
+        assert_eq!(
+            first
+                .compare(&run(vec![1, 2, 7], 9_000))
+                .unwrap()
+                .to_string(),
+            "executed different instructions between instructions 8192 and 9000"
+        );
+
+        let mut other = run(vec![1, 2, 3], 10_000);
+        other.registers[1].1 = 2;
+        assert_eq!(
+            first.compare(&other).unwrap().to_string(),
+            "ended with $v0 = 0x00000002 instead of 0x00000001"
+        );
+
+        let mut other = run(vec![1, 2, 3], 10_000);
+        other.memories[0].1[2] = 0xff;
+        assert_eq!(
+            first.compare(&other),
+            Some(Divergence::Memory {
+                address: 0x1002,
+                first: 2,
+                other: 0xff
+            })
+        );
+
+        let mut stream = InstructionStream::default();
+        stream.record(0xbfc0_0000, Instruction(0));
+        let mut swapped = InstructionStream::default();
+        swapped.record(0, Instruction(0xbfc0_0000));
+        assert_ne!(stream.hash(), swapped.hash());
+    }
+}
@@ -0,0 +1,258 @@
+//! Bridge that swaps the byte lanes between the bus and a device, like boards that wire a
+//! peripheral of the other endianness straight onto the data bus.
+//!
+//! Byte `n` of each bus word is connected to byte `3 - n` of the device, so a word access
+//! reaches the device with its bytes reversed, and byte and halfword accesses reach the
+//! opposite end of the word. The wrapped device model does not need to know about it.
+//!
+//! `RegisterSwap` connects the little-endian device models to a big-endian CPU instead. It
+//! reverses the bytes of halfword and word accesses and leaves every access at its offset, so
+//! the CPU reads and writes the same register values in either byte order.
+
+use crate::devices::{AccessWidths, Device};
+use crate::memory::AccessContext;
+use crate::util::error::Result;
+use crate::util::state::{StateReader, StateWriter};
+use crate::Address;
+
+pub struct ByteSwap {
+    device: Box<dyn Device>,
+}
+
+impl ByteSwap {
+    pub fn new(device: Box<dyn Device>) -> Self {
+        Self { device }
+    }
+}
+
+/// Returns the device offset that a bus access of `len` bytes at `offset` is wired to, or
+/// `None` if the access has to be split into single bytes.
+fn swapped_offset(offset: Address, len: usize) -> Option<Address> {
+    match len {
+        1 | 2 | 4 if offset.is_multiple_of(len as Address) => Some(offset ^ (4 - len as Address)),
+        _ => None,
+    }
+}
+
+impl Device for ByteSwap {
+    fn debug_label(&self) -> String {
+        format!("{} (byte-swapped)", self.device.debug_label())
+    }
+
+    fn access_widths(&self) -> AccessWidths {
+        self.device.access_widths()
+    }
+
+    fn read(&mut self, offset: Address, data: &mut [u8], ctx: AccessContext) -> Result<()> {
+        match swapped_offset(offset, data.len()) {
+            Some(swapped) => self.device.read(swapped, data, ctx)?,
+            None => {
+                for (i, byte) in data.iter_mut().enumerate() {
+                    let swapped = (offset + i as Address) ^ 3;
+                    self.device.read(swapped, std::slice::from_mut(byte), ctx)?;
+                }
+                return Ok(());
+            }
+        }
+        data.reverse();
+        Ok(())
+    }
+
+    fn peek(&self, offset: Address, data: &mut [u8]) -> Result<()> {
+        match swapped_offset(offset, data.len()) {
+            Some(swapped) => self.device.peek(swapped, data)?,
+            None => {
+                for (i, byte) in data.iter_mut().enumerate() {
+                    let swapped = (offset + i as Address) ^ 3;
+                    self.device.peek(swapped, std::slice::from_mut(byte))?;
+                }
+                return Ok(());
+            }
+        }
+        data.reverse();
+        Ok(())
+    }
+
+    fn write(&mut self, offset: Address, data: &[u8], ctx: AccessContext) -> Result<()> {
+        match swapped_offset(offset, data.len()) {
+            Some(swapped) => {
+                let mut reversed = [0; 4];
+                reversed[..data.len()].copy_from_slice(data);
+                reversed[..data.len()].reverse();
+                self.device.write(swapped, &reversed[..data.len()], ctx)
+            }
+            None => {
+                for (i, byte) in data.iter().enumerate() {
+                    let swapped = (offset + i as Address) ^ 3;
+                    self.device
+                        .write(swapped, std::slice::from_ref(byte), ctx)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn is_memory(&self) -> bool {
+        self.device.is_memory()
+    }
+
+    fn executable(&self) -> bool {
+        self.device.executable()
+    }
+
+    fn dirty_pages(&self) -> Vec<Address> {
+        self.device.dirty_pages()
+    }
+
+    fn clear_dirty(&mut self) {
+        self.device.clear_dirty();
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        self.device.save(state);
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.device.restore(state)
+    }
+
+    fn interrupt_outputs(&self) -> u8 {
+        self.device.interrupt_outputs()
+    }
+
+    fn interrupt_lines(&mut self) -> u8 {
+        self.device.interrupt_lines()
+    }
+}
+
+pub struct RegisterSwap {
+    device: Box<dyn Device>,
+}
+
+impl RegisterSwap {
+    pub fn new(device: Box<dyn Device>) -> Self {
+        Self { device }
+    }
+}
+
+impl Device for RegisterSwap {
+    fn debug_label(&self) -> String {
+        self.device.debug_label()
+    }
+
+    fn access_widths(&self) -> AccessWidths {
+        self.device.access_widths()
+    }
+
+    fn read(&mut self, offset: Address, data: &mut [u8], ctx: AccessContext) -> Result<()> {
+        self.device.read(offset, data, ctx)?;
+        data.reverse();
+        Ok(())
+    }
+
+    fn peek(&self, offset: Address, data: &mut [u8]) -> Result<()> {
+        self.device.peek(offset, data)?;
+        data.reverse();
+        Ok(())
+    }
+
+    fn write(&mut self, offset: Address, data: &[u8], ctx: AccessContext) -> Result<()> {
+        let mut reversed = data.to_vec();
+        reversed.reverse();
+        self.device.write(offset, &reversed, ctx)
+    }
+
+    fn is_memory(&self) -> bool {
+        self.device.is_memory()
+    }
+
+    fn executable(&self) -> bool {
+        self.device.executable()
+    }
+
+    fn dirty_pages(&self) -> Vec<Address> {
+        self.device.dirty_pages()
+    }
+
+    fn clear_dirty(&mut self) {
+        self.device.clear_dirty();
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        self.device.save(state);
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.device.restore(state)
+    }
+
+    fn interrupt_outputs(&self) -> u8 {
+        self.device.interrupt_outputs()
+    }
+
+    fn interrupt_lines(&mut self) -> u8 {
+        self.device.interrupt_lines()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::ram::Ram;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn byte_swap_lanes() -> Result<()> {
+        let mut ram = Ram::new(0x10);
+        ram.write(0, &[1, 2, 3, 4], AccessContext::Debugger)?;
+        let mut device = ByteSwap::new(Box::new(ram));
+        assert_eq!(device.debug_label(), "RAM (byte-swapped)");
+
+        let mut word = [0; 4];
+        device.read(0, &mut word, AccessContext::CpuLoad)?;
+        assert_eq!(word, [4, 3, 2, 1]);
+
+        let mut half = [0; 2];
+        device.read(0, &mut half, AccessContext::CpuLoad)?;
+        assert_eq!(half, [4, 3]);
+        device.peek(2, &mut half)?;
+        assert_eq!(half, [2, 1]);
+
+        let mut byte = [0];
+        device.read(1, &mut byte, AccessContext::CpuLoad)?;
+        assert_eq!(byte, [3]);
+
+        // Writes are swapped the same way, unaligned ones one byte at a time
+        device.write(4, &[5, 6, 7, 8], AccessContext::CpuStore)?;
+        device.write(9, &[9, 10, 11], AccessContext::Debugger)?;
+        let mut data = [0; 8];
+        device.device.peek(4, &mut data)?;
+        assert_eq!(data, [8, 7, 6, 5, 11, 10, 9, 0]);
+
+        device.peek(3, &mut data[..3])?;
+        assert_eq!(&data[..3], &[1, 5, 6]);
+        Ok(())
+    }
+
+    #[test]
+    fn register_swap_values() -> Result<()> {
+        let mut ram = Ram::new(0x10);
+        ram.write(0, &[1, 2, 3, 4], AccessContext::Debugger)?;
+        let mut device = RegisterSwap::new(Box::new(ram));
+
+        let mut word = [0; 4];
+        device.read(0, &mut word, AccessContext::CpuLoad)?;
+        assert_eq!(u32::from_be_bytes(word), 0x0403_0201);
+
+        // Bytes stay at their offsets
+        let mut byte = [0];
+        device.peek(1, &mut byte)?;
+        assert_eq!(byte, [2]);
+
+        device.write(4, &0x1122_3344u32.to_be_bytes(), AccessContext::CpuStore)?;
+        let mut data = [0; 4];
+        device.device.peek(4, &mut data)?;
+        assert_eq!(u32::from_le_bytes(data), 0x1122_3344);
+        Ok(())
+    }
+}
@@ -0,0 +1,296 @@
+//! Programmable interval timer that interrupts the CPU.
+//!
+//! The timer counts down microseconds of virtual time, where every instruction takes one
+//! microsecond, so timer interrupts arrive after the same instruction on every run. Setting
+//! `CONTROL_HOST_TIME` counts the host time instead, which only differs from the virtual time
+//! when the emulator runs with `--host-clock`.
+
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::devices::time::TimeSource;
+use crate::devices::{AccessWidths, Device};
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::util::state::{StateReader, StateWriter};
+use crate::Address;
+
+/// The physical address for the clock device.
+pub const BASE_ADDRESS: Address = 0x0101_0000;
+/// Size of the clock device in memory.
+pub const SIZE: usize = 0x10;
+
+/// Offset of the `CONTROL` register.
+pub const CONTROL: Address = 0x0;
+/// Offset of the `STATUS` register. Writing a one to a bit clears it.
+pub const STATUS: Address = 0x4;
+/// Offset of the `PERIOD` register, the interval of the timer in microseconds.
+pub const PERIOD: Address = 0x8;
+/// Offset of the read-only `COUNT` register, the microseconds left until the timer expires.
+pub const COUNT: Address = 0xc;
+
+/// `CONTROL` bit that starts the timer. Setting it or writing `PERIOD` restarts the interval.
+pub const CONTROL_ENABLE: u32 = 1 << 0;
+/// `CONTROL` bit that asserts the interrupt line while `STATUS_EXPIRED` is set.
+pub const CONTROL_IRQ: u32 = 1 << 1;
+/// `CONTROL` bit that restarts the interval when it expires instead of stopping the timer.
+pub const CONTROL_PERIODIC: u32 = 1 << 2;
+/// `CONTROL` bit that counts the host time instead of the virtual time.
+pub const CONTROL_HOST_TIME: u32 = 1 << 3;
+/// `STATUS` bit that is set when the interval expires.
+pub const STATUS_EXPIRED: u32 = 1 << 0;
+/// `STATUS` bit that is set when the interval expired again before `STATUS_EXPIRED` was cleared.
+pub const STATUS_MISSED: u32 = 1 << 1;
+
+/// Interrupt line of the timer, IP7 like the timer of the original vmips machine.
+pub const IRQ_LINE: u8 = 5;
+
+pub struct ClockDevice {
+    clock: Arc<dyn TimeSource>,
+    control: u32,
+    status: u32,
+    period: u32,
+    /// When the running interval expires.
+    deadline: Option<Duration>,
+}
+
+impl ClockDevice {
+    pub fn new(clock: Arc<dyn TimeSource>) -> Self {
+        Self {
+            clock,
+            control: 0,
+            status: 0,
+            period: 0,
+            deadline: None,
+        }
+    }
+
+    fn now(&self) -> Duration {
+        match self.control & CONTROL_HOST_TIME {
+            0 => self.clock.virtual_time(),
+            _ => self.clock.host_time(),
+        }
+    }
+
+    fn restart(&mut self) {
+        self.deadline = match self.control & CONTROL_ENABLE {
+            0 => None,
+            _ => Some(self.now() + Duration::from_micros(self.period as u64)),
+        };
+    }
+
+    /// Expires the running interval if its deadline has passed.
+    fn update(&mut self) {
+        let Some(deadline) = self.deadline else {
+            return;
+        };
+        let now = self.now();
+        if now < deadline {
+            return;
+        }
+
+        if self.status & STATUS_EXPIRED != 0 {
+            self.status |= STATUS_MISSED;
+        }
+        self.status |= STATUS_EXPIRED;
+
+        // A periodic timer keeps its phase, intervals that passed entirely are missed
+        let period = Duration::from_micros(self.period.max(1) as u64);
+        self.deadline = match self.control & CONTROL_PERIODIC {
+            0 => {
+                self.control &= !CONTROL_ENABLE;
+                None
+            }
+            _ => {
+                let elapsed = (now - deadline).as_micros() / period.as_micros();
+                if elapsed > 0 {
+                    self.status |= STATUS_MISSED;
+                }
+                Some(deadline + period * (elapsed as u32 + 1))
+            }
+        };
+    }
+
+    fn register(&self, address: Address) -> u32 {
+        match address {
+            CONTROL => self.control,
+            STATUS => self.status,
+            PERIOD => self.period,
+            COUNT => self.deadline.map_or(0, |deadline| {
+                deadline.saturating_sub(self.now()).as_micros() as u32
+            }),
+            _ => 0,
+        }
+    }
+}
+
+impl Device for ClockDevice {
+    fn debug_label(&self) -> String {
+        "clock".to_owned()
+    }
+
+    fn access_widths(&self) -> AccessWidths {
+        AccessWidths::WORD
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.update();
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let word = self.register(address & !3).to_le_bytes();
+        let start = (address & 3) as usize;
+        let src = word
+            .get(start..start + data.len())
+            .ok_or(RmipsError::MemoryRead(address))?;
+        data.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+        debug!("write to clock @ 0x{:08x}", address);
+
+        let value = u32::from_le_bytes(
+            data.try_into()
+                .map_err(|_| RmipsError::MemoryWrite(address))?,
+        );
+        self.update();
+        match address {
+            CONTROL => {
+                let started = value & !self.control & CONTROL_ENABLE != 0;
+                self.control =
+                    value & (CONTROL_ENABLE | CONTROL_IRQ | CONTROL_PERIODIC | CONTROL_HOST_TIME);
+                if started || self.control & CONTROL_ENABLE == 0 {
+                    self.restart();
+                }
+            }
+            STATUS => self.status &= !value,
+            PERIOD => {
+                self.period = value;
+                self.restart();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.word(self.control);
+        state.word(self.status);
+        state.word(self.period);
+        state.bool(self.deadline.is_some());
+        state.u64(
+            self.deadline
+                .map_or(0, |deadline| deadline.as_micros() as u64),
+        );
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.control = state.word()?;
+        self.status = state.word()?;
+        self.period = state.word()?;
+        let running = state.bool()?;
+        let deadline = Duration::from_micros(state.u64()?);
+        self.deadline = Some(deadline).filter(|_| running);
+        Ok(())
+    }
+
+    fn interrupt_outputs(&self) -> u8 {
+        1 << IRQ_LINE
+    }
+
+    fn interrupt_lines(&mut self) -> u8 {
+        self.update();
+        match self.control & CONTROL_IRQ != 0 && self.status & STATUS_EXPIRED != 0 {
+            true => 1 << IRQ_LINE,
+            false => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::time::ManualClock;
+    use pretty_assertions::assert_eq;
+
+    fn read(clock: &mut ClockDevice, address: Address) -> u32 {
+        let mut data = [0; 4];
+        clock
+            .read(address, &mut data, AccessContext::CpuLoad)
+            .unwrap();
+        u32::from_le_bytes(data)
+    }
+
+    fn write(clock: &mut ClockDevice, address: Address, value: u32) {
+        clock
+            .write(address, &value.to_le_bytes(), AccessContext::CpuStore)
+            .unwrap();
+    }
+
+    #[test]
+    fn clock_interrupts() {
+        let time = Arc::new(ManualClock::default());
+        let mut clock = ClockDevice::new(time.clone());
+        write(&mut clock, PERIOD, 100);
+        write(
+            &mut clock,
+            CONTROL,
+            CONTROL_ENABLE | CONTROL_IRQ | CONTROL_PERIODIC,
+        );
+
+        time.advance(Duration::from_micros(60));
+        assert_eq!(clock.interrupt_lines(), 0);
+        assert_eq!(read(&mut clock, COUNT), 40);
+
+        time.advance(Duration::from_micros(40));
+        assert_eq!(clock.interrupt_lines(), 1 << IRQ_LINE);
+        assert_eq!(read(&mut clock, STATUS), STATUS_EXPIRED);
+        assert_eq!(read(&mut clock, COUNT), 100);
+
+        // The line stays asserted until the guest acknowledges the interrupt
+        write(&mut clock, STATUS, STATUS_EXPIRED);
+        assert_eq!(clock.interrupt_lines(), 0);
+
+        // Intervals that expire while the previous one is pending are reported as missed
+        time.advance(Duration::from_micros(250));
+        assert_eq!(clock.interrupt_lines(), 1 << IRQ_LINE);
+        assert_eq!(read(&mut clock, STATUS), STATUS_EXPIRED | STATUS_MISSED);
+        assert_eq!(read(&mut clock, COUNT), 50);
+
+        // A one-shot timer stops when it expires
+        write(&mut clock, STATUS, STATUS_EXPIRED | STATUS_MISSED);
+        write(&mut clock, CONTROL, CONTROL_IRQ);
+        write(&mut clock, CONTROL, CONTROL_ENABLE | CONTROL_IRQ);
+        time.advance(Duration::from_micros(100));
+        assert_eq!(clock.interrupt_lines(), 1 << IRQ_LINE);
+        assert_eq!(read(&mut clock, CONTROL), CONTROL_IRQ);
+        assert_eq!(read(&mut clock, COUNT), 0);
+    }
+
+    #[test]
+    fn clock_save_restore() -> Result<()> {
+        let time = Arc::new(ManualClock::default());
+        let mut clock = ClockDevice::new(time.clone());
+        write(&mut clock, PERIOD, 100);
+        write(&mut clock, CONTROL, CONTROL_ENABLE | CONTROL_IRQ);
+        time.advance(Duration::from_micros(60));
+        let mut state = StateWriter::new();
+        clock.save(&mut state);
+        let state = state.into_bytes();
+
+        // The deadline is kept in virtual time, which the restored machine continues from
+        let mut restored = ClockDevice::new(time.clone());
+        let mut reader = StateReader::new(&state);
+        restored.restore(&mut reader)?;
+        reader.finish()?;
+        assert_eq!(read(&mut restored, COUNT), 40);
+        time.advance(Duration::from_micros(40));
+        assert_eq!(restored.interrupt_lines(), 1 << IRQ_LINE);
+        Ok(())
+    }
+}
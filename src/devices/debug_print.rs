@@ -0,0 +1,106 @@
+//! Magic word that prints a string from guest memory, for debug output before a UART
+//! driver exists.
+//!
+//! Storing the virtual address of a NUL-terminated string to the word prints the string,
+//! so a debug print takes no more than loading the address and one store. The device
+//! cannot read guest memory itself, so it passes the address to the emulator, which reads
+//! and prints the string after the instruction.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::devices::{AccessWidths, Device};
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::util::state::{StateReader, StateWriter};
+use crate::Address;
+
+/// The physical address for the debug print device.
+pub const BASE_ADDRESS: Address = 0x0209_0000;
+/// Size of the debug print device in memory.
+pub const SIZE: usize = 4;
+
+pub struct DebugPrintDevice {
+    /// The last address written, which reads return.
+    latch: [u8; SIZE],
+    strings: Sender<Address>,
+}
+
+impl DebugPrintDevice {
+    /// Creates the device and the channel receiving the addresses of the strings to print.
+    pub fn new() -> (Self, Receiver<Address>) {
+        let (strings, receiver) = mpsc::channel();
+        let device = Self {
+            latch: [0; SIZE],
+            strings,
+        };
+        (device, receiver)
+    }
+}
+
+impl Device for DebugPrintDevice {
+    fn debug_label(&self) -> String {
+        "debug-print".to_owned()
+    }
+
+    fn access_widths(&self) -> AccessWidths {
+        AccessWidths::WORD
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let start = address as usize;
+        let src = self
+            .latch
+            .get(start..start + data.len())
+            .ok_or(RmipsError::MemoryRead(address))?;
+        data.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8], ctx: AccessContext) -> Result<()> {
+        let start = address as usize;
+        let dst = self
+            .latch
+            .get_mut(start..start + data.len())
+            .ok_or(RmipsError::MemoryWrite(address))?;
+        dst.copy_from_slice(data);
+
+        // Debugger writes only change the latch
+        if ctx != AccessContext::Debugger {
+            // The emulator may have stopped listening when it is shutting down
+            let _ = self.strings.send(u32::from_le_bytes(self.latch));
+        }
+        Ok(())
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.latch);
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.latch.copy_from_slice(state.bytes_of_len(SIZE)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn debug_print_sends_address() -> Result<()> {
+        let (mut device, strings) = DebugPrintDevice::new();
+        device.write(0, &0x8000_1234u32.to_le_bytes(), AccessContext::CpuStore)?;
+        device.write(0, &0x8000_5678u32.to_le_bytes(), AccessContext::Debugger)?;
+        assert_eq!(strings.try_iter().collect::<Vec<_>>(), vec![0x8000_1234]);
+
+        let mut data = [0; 4];
+        device.read(0, &mut data, AccessContext::CpuLoad)?;
+        assert_eq!(u32::from_le_bytes(data), 0x8000_5678);
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! Read-only block that identifies the emulator to the guest.
+//!
+//! Guest code and tests read the magic word to find out that they run on rmips, and the
+//! version and feature words to adapt to what the emulated machine provides. The time words
+//! count the microseconds of virtual time, so code can measure itself without a timer device.
+
+use std::sync::Arc;
+
+use crate::devices::time::TimeSource;
+use crate::devices::{AccessWidths, Device};
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::util::state::{StateReader, StateWriter};
+use crate::Address;
+
+/// The physical address for the emulator info device.
+pub const BASE_ADDRESS: Address = 0x020a_0000;
+/// Size of the emulator info device in memory.
+pub const SIZE: usize = 0x20;
+
+/// Offset of the `MAGIC` register, which reads `"RMIP"`.
+pub const MAGIC: Address = 0x00;
+/// Offset of the `VERSION` register, with the major, minor and patch version in bits 23-16,
+/// 15-8 and 7-0.
+pub const VERSION: Address = 0x04;
+/// Offset of the `FEATURES` register.
+pub const FEATURES: Address = 0x08;
+/// Offset of the `TLB_ENTRIES` register.
+pub const TLB_ENTRIES: Address = 0x0c;
+/// Offset of the `PRID` register, the processor ID of the emulated model.
+pub const PRID: Address = 0x10;
+/// Offset of the `TIME_LO` register, the low word of the virtual time in microseconds. Reading
+/// it latches the high word.
+pub const TIME_LO: Address = 0x18;
+/// Offset of the `TIME_HI` register, the high word latched by the last `TIME_LO` read.
+pub const TIME_HI: Address = 0x1c;
+
+/// The value of the `MAGIC` register.
+pub const MAGIC_VALUE: u32 = u32::from_le_bytes(*b"RMIP");
+
+bitflags! {
+    /// Bits of the `FEATURES` register.
+    pub struct Features: u32 {
+        /// A floating-point coprocessor is present.
+        const FPU = 1 << 0;
+        /// Addresses are translated through the kseg segments and the TLB.
+        const MMU = 1 << 1;
+        /// The monitor PROM provides putchar, getenv and exit services.
+        const MONITOR_PROM = 1 << 2;
+        /// Strings can be printed with the debug print device.
+        const DEBUG_PRINT = 1 << 3;
+        /// A debugger can attach through the GDB stub.
+        const GDB_STUB = 1 << 4;
+    }
+}
+
+/// Returns the `VERSION` register for a `major.minor.patch` version.
+fn version_word(version: &str) -> u32 {
+    version
+        .split('.')
+        .take(3)
+        .map(|part| part.parse::<u8>().unwrap_or(0) as u32)
+        .fold(0, |word, part| word << 8 | part)
+}
+
+pub struct EmulatorInfoDevice {
+    features: Features,
+    tlb_entries: u32,
+    prid: u32,
+    clock: Arc<dyn TimeSource>,
+    /// High word of the time, latched when the low word is read.
+    time_hi: u32,
+}
+
+impl EmulatorInfoDevice {
+    pub fn new(
+        features: Features,
+        tlb_entries: usize,
+        prid: u32,
+        clock: Arc<dyn TimeSource>,
+    ) -> Self {
+        Self {
+            features,
+            tlb_entries: tlb_entries as u32,
+            prid,
+            clock,
+            time_hi: 0,
+        }
+    }
+
+    fn time(&self) -> u64 {
+        self.clock.virtual_time().as_micros() as u64
+    }
+
+    fn register(&self, address: Address) -> u32 {
+        match address {
+            MAGIC => MAGIC_VALUE,
+            VERSION => version_word(env!("CARGO_PKG_VERSION")),
+            FEATURES => self.features.bits(),
+            TLB_ENTRIES => self.tlb_entries,
+            PRID => self.prid,
+            TIME_LO => self.time() as u32,
+            TIME_HI => self.time_hi,
+            _ => 0,
+        }
+    }
+}
+
+impl Device for EmulatorInfoDevice {
+    fn debug_label(&self) -> String {
+        "emulator-info".to_owned()
+    }
+
+    fn access_widths(&self) -> AccessWidths {
+        AccessWidths::WORD
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        if address == TIME_LO {
+            self.time_hi = (self.time() >> 32) as u32;
+        }
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let word = self.register(address & !3).to_le_bytes();
+        let start = (address & 3) as usize;
+        let src = word
+            .get(start..start + data.len())
+            .ok_or(RmipsError::MemoryRead(address))?;
+        data.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, _data: &[u8], _ctx: AccessContext) -> Result<()> {
+        Err(RmipsError::MemoryWrite(address))
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.word(self.time_hi);
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.time_hi = state.word()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::time::ManualClock;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    fn read(device: &mut EmulatorInfoDevice, address: Address) -> Result<u32> {
+        let mut data = [0; 4];
+        device.read(address, &mut data, AccessContext::CpuLoad)?;
+        Ok(u32::from_le_bytes(data))
+    }
+
+    #[test]
+    fn emulator_info_registers() -> Result<()> {
+        let clock = Arc::new(ManualClock::default());
+        let features = Features::MMU | Features::DEBUG_PRINT;
+        let mut device = EmulatorInfoDevice::new(features, 64, 0x0230, clock.clone());
+
+        assert_eq!(read(&mut device, MAGIC)?, 0x5049_4d52);
+        assert_eq!(read(&mut device, FEATURES)?, 0b1010);
+        assert_eq!(read(&mut device, TLB_ENTRIES)?, 64);
+        assert_eq!(read(&mut device, PRID)?, 0x0230);
+        assert_eq!(version_word("1.12.3"), 0x01_0c03);
+
+        // The high word stays at the value latched with the low word
+        clock.advance(Duration::from_micros(0x1_0000_0005));
+        assert_eq!(read(&mut device, TIME_LO)?, 5);
+        clock.advance(Duration::from_micros(0x1_0000_0000));
+        assert_eq!(read(&mut device, TIME_HI)?, 1);
+
+        assert!(device
+            .write(MAGIC, &[0; 4], AccessContext::CpuStore)
+            .is_err());
+        Ok(())
+    }
+}
@@ -1,8 +1,9 @@
 use log::debug;
 
 use crate::devices::Device;
+use crate::memory::AccessContext;
 use crate::util::error::{Result, RmipsError};
-use crate::Address;
+use crate::{Address, HaltReason};
 
 /// The physical address for the halt device.
 pub const BASE_ADDRESS: Address = 0x01010024;
@@ -14,9 +15,12 @@ impl Device for HaltDevice {
         "halt-device".to_owned()
     }
 
-    fn read(&mut self, address: Address, data: &mut [u8]) -> Result<()> {
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
         debug!("read from halt device @ 0x{:08x}", address);
+        self.peek(address, data)
+    }
 
+    fn peek(&self, _address: Address, data: &mut [u8]) -> Result<()> {
         // All reads to the halt device should return 0
         for v in data {
             *v = 0;
@@ -25,13 +29,13 @@ impl Device for HaltDevice {
         Ok(())
     }
 
-    fn write(&mut self, address: Address, data: &[u8]) -> Result<()> {
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
         debug!("write to halt device @ 0x{:08x}", address);
 
         // Any valid writes to the halt device trigger the system to halt
         for v in data {
             if *v != 0 {
-                return Err(RmipsError::Halt);
+                return Err(RmipsError::Halt(HaltReason::HaltDevice));
             }
         }
 
@@ -0,0 +1,462 @@
+//! I2C master controller and the slave device models that can be attached to it.
+//!
+//! The guest drives the bus one step at a time through the `COMMAND` register: it issues a
+//! start condition for the slave in `ADDRESS`, transfers bytes through `DATA` and finishes
+//! with a stop condition. `STATUS` reports whether the slave acknowledged the last step.
+
+use std::str::FromStr;
+
+use log::debug;
+
+use crate::devices::Device;
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::util::parse::parse_integer;
+use crate::util::state::{StateReader, StateWriter};
+use crate::Address;
+
+/// The physical address for the I2C controller.
+pub const BASE_ADDRESS: Address = 0x0205_0000;
+/// Size of the I2C controller in memory.
+pub const SIZE: usize = 0x10;
+
+/// 7-bit address of the slave to start a transfer with.
+pub const ADDRESS: Address = 0x0;
+/// Byte to send with `CMD_WRITE`, or the byte received by `CMD_READ`.
+pub const DATA: Address = 0x4;
+/// Writing a command performs one step of a transfer.
+pub const COMMAND: Address = 0x8;
+/// Bit 0 is set when the slave acknowledged the last start condition or byte written.
+pub const STATUS: Address = 0xc;
+
+pub const CMD_START_WRITE: u32 = 1;
+pub const CMD_START_READ: u32 = 2;
+pub const CMD_WRITE: u32 = 3;
+pub const CMD_READ: u32 = 4;
+pub const CMD_STOP: u32 = 5;
+
+/// A device model attached to the I2C bus.
+pub trait I2cSlave {
+    /// Returns the 7-bit address the slave responds to.
+    fn address(&self) -> u8;
+    /// Starts a transfer to (`read` is false) or from the slave, including repeated starts.
+    fn start(&mut self, read: bool);
+    /// Receives a byte from the master and returns true to acknowledge it.
+    fn write(&mut self, byte: u8) -> bool;
+    /// Sends the next byte to the master.
+    fn read(&mut self) -> u8;
+    /// Ends the transfer.
+    fn stop(&mut self) {}
+    /// Writes the state of the slave to a machine snapshot.
+    fn save(&self, _state: &mut StateWriter) {}
+    /// Restores the state written by `save`.
+    fn restore(&mut self, _state: &mut StateReader) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The slave models that can be attached from the command line as `MODEL@ADDRESS`, or from an
+/// `[[i2c_slave]]` table of a machine file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I2cSlaveSpec {
+    /// LM75 temperature sensor.
+    Lm75(u8),
+    /// 24C02 EEPROM with 256 bytes.
+    Eeprom24c02(u8),
+}
+
+impl I2cSlaveSpec {
+    /// Returns the slave of the named model at a 7-bit address.
+    pub fn new(model: &str, address: u8) -> std::result::Result<Self, String> {
+        if address >= 0x80 {
+            return Err(format!("I2C address is not 7 bits: 0x{:x}", address));
+        }
+        match model {
+            "lm75" => Ok(I2cSlaveSpec::Lm75(address)),
+            "24c02" => Ok(I2cSlaveSpec::Eeprom24c02(address)),
+            _ => Err(format!("unknown I2C slave model: {}", model)),
+        }
+    }
+
+    pub fn build(self) -> Box<dyn I2cSlave> {
+        match self {
+            I2cSlaveSpec::Lm75(address) => Box::new(Lm75::new(address)),
+            I2cSlaveSpec::Eeprom24c02(address) => Box::new(Eeprom24c02::new(address)),
+        }
+    }
+}
+
+impl FromStr for I2cSlaveSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("invalid I2C slave: {}", s);
+        let (model, address) = s.split_once('@').ok_or_else(invalid)?;
+        let address = parse_integer(address).map_err(|_| invalid())?;
+        I2cSlaveSpec::new(model, address).map_err(|_| invalid())
+    }
+}
+
+pub struct I2cController {
+    slaves: Vec<Box<dyn I2cSlave>>,
+    address: u8,
+    data: u8,
+    ack: bool,
+    /// Index of the slave taking part in the current transfer.
+    active: Option<usize>,
+}
+
+impl I2cController {
+    pub fn new(slaves: Vec<Box<dyn I2cSlave>>) -> Self {
+        Self {
+            slaves,
+            address: 0,
+            data: 0,
+            ack: false,
+            active: None,
+        }
+    }
+
+    fn command(&mut self, command: u32) {
+        match command {
+            CMD_START_WRITE | CMD_START_READ => {
+                let read = command == CMD_START_READ;
+                self.active = self
+                    .slaves
+                    .iter()
+                    .position(|slave| slave.address() == self.address);
+                if let Some(slave) = self.active.map(|index| &mut self.slaves[index]) {
+                    slave.start(read);
+                }
+                self.ack = self.active.is_some();
+            }
+            CMD_WRITE => {
+                self.ack = match self.active {
+                    Some(index) => self.slaves[index].write(self.data),
+                    None => false,
+                };
+            }
+            CMD_READ => {
+                // Nothing drives the bus without a slave, so the pull-ups read as ones
+                self.data = match self.active {
+                    Some(index) => self.slaves[index].read(),
+                    None => 0xff,
+                };
+            }
+            CMD_STOP => {
+                if let Some(index) = self.active.take() {
+                    self.slaves[index].stop();
+                }
+            }
+            _ => debug!("unknown I2C command {}", command),
+        }
+    }
+
+    fn register(&self, offset: Address) -> u32 {
+        match offset {
+            ADDRESS => self.address as u32,
+            DATA => self.data as u32,
+            STATUS => self.ack as u32,
+            _ => 0,
+        }
+    }
+}
+
+impl Device for I2cController {
+    fn debug_label(&self) -> String {
+        "i2c-controller".to_owned()
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let value = self.register(address & !3).to_le_bytes();
+        let offset = (address & 3) as usize;
+        let src = value
+            .get(offset..offset + data.len())
+            .ok_or(RmipsError::MemoryRead(address))?;
+        data.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+        let mut value = [0; 4];
+        let len = data.len().min(value.len());
+        value[..len].copy_from_slice(&data[..len]);
+        let value = u32::from_le_bytes(value);
+
+        match address {
+            ADDRESS => self.address = (value & 0x7f) as u8,
+            DATA => self.data = value as u8,
+            COMMAND => self.command(value),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.word(self.address as u32);
+        state.word(self.data as u32);
+        state.bool(self.ack);
+        state.bool(self.active.is_some());
+        state.word(self.active.unwrap_or(0) as u32);
+        for slave in &self.slaves {
+            slave.save(state);
+        }
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.address = state.word()? as u8;
+        self.data = state.word()? as u8;
+        self.ack = state.bool()?;
+        let active = state.bool()?;
+        let index = state.word()? as usize;
+        self.active = Some(index).filter(|index| active && *index < self.slaves.len());
+        for slave in &mut self.slaves {
+            slave.restore(state)?;
+        }
+        Ok(())
+    }
+}
+
+/// LM75 temperature sensor, which always reads 25°C.
+///
+/// The first byte written selects the register that following reads and writes access:
+/// 0 is the temperature, 1 the configuration, 2 the hysteresis and 3 the overtemperature limit.
+pub struct Lm75 {
+    address: u8,
+    pointer: u8,
+    /// Index of the next byte within a 16-bit register.
+    byte: usize,
+    /// Whether the pointer has been written in the current transfer.
+    pointer_set: bool,
+    config: u8,
+    hysteresis: u16,
+    overtemperature: u16,
+}
+
+impl Lm75 {
+    /// The temperature in units of 1/256°C, of which the sensor reports 1/2°C steps.
+    const TEMPERATURE: u16 = 25 << 8;
+
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            pointer: 0,
+            byte: 0,
+            pointer_set: false,
+            config: 0,
+            hysteresis: 75 << 8,
+            overtemperature: 80 << 8,
+        }
+    }
+
+    fn register_mut(&mut self) -> Option<&mut u16> {
+        match self.pointer {
+            2 => Some(&mut self.hysteresis),
+            3 => Some(&mut self.overtemperature),
+            _ => None,
+        }
+    }
+}
+
+impl I2cSlave for Lm75 {
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn start(&mut self, _read: bool) {
+        self.byte = 0;
+        self.pointer_set = false;
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        if !self.pointer_set {
+            self.pointer = byte & 3;
+            self.pointer_set = true;
+            return true;
+        }
+
+        // Registers are written most significant byte first
+        let shift = if self.byte == 0 { 8 } else { 0 };
+        if self.pointer == 1 {
+            self.config = byte;
+        } else if let Some(register) = self.register_mut() {
+            *register = (*register & !(0xff << shift)) | ((byte as u16) << shift);
+            *register &= 0xff80;
+            self.byte = (self.byte + 1) % 2;
+        }
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let value = match self.pointer {
+            0 => Lm75::TEMPERATURE & 0xff80,
+            1 => return self.config,
+            2 => self.hysteresis,
+            _ => self.overtemperature,
+        };
+        let byte = if self.byte == 0 { value >> 8 } else { value };
+        self.byte = (self.byte + 1) % 2;
+        byte as u8
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.word(self.pointer as u32);
+        state.word(self.byte as u32);
+        state.bool(self.pointer_set);
+        state.word(self.config as u32);
+        state.word(self.hysteresis as u32);
+        state.word(self.overtemperature as u32);
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.pointer = state.word()? as u8 & 3;
+        self.byte = state.word()? as usize % 2;
+        self.pointer_set = state.bool()?;
+        self.config = state.word()? as u8;
+        self.hysteresis = state.word()? as u16;
+        self.overtemperature = state.word()? as u16;
+        Ok(())
+    }
+}
+
+/// 24C02 EEPROM with 256 bytes, which are erased to `0xff`.
+///
+/// The first byte written sets the word address. Further bytes written are stored at the word
+/// address and reads return data from it, incrementing it after each byte.
+pub struct Eeprom24c02 {
+    address: u8,
+    data: [u8; 256],
+    word_address: u8,
+    word_address_set: bool,
+}
+
+impl Eeprom24c02 {
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            data: [0xff; 256],
+            word_address: 0,
+            word_address_set: false,
+        }
+    }
+}
+
+impl I2cSlave for Eeprom24c02 {
+    fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn start(&mut self, _read: bool) {
+        self.word_address_set = false;
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        if self.word_address_set {
+            self.data[self.word_address as usize] = byte;
+            self.word_address = self.word_address.wrapping_add(1);
+        } else {
+            self.word_address = byte;
+            self.word_address_set = true;
+        }
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let byte = self.data[self.word_address as usize];
+        self.word_address = self.word_address.wrapping_add(1);
+        byte
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.data);
+        state.word(self.word_address as u32);
+        state.bool(self.word_address_set);
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        let data = state.bytes_of_len(self.data.len())?;
+        self.data.copy_from_slice(data);
+        self.word_address = state.word()? as u8;
+        self.word_address_set = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn write(i2c: &mut I2cController, offset: Address, value: u32) {
+        i2c.write(offset, &value.to_le_bytes(), AccessContext::CpuStore)
+            .unwrap();
+    }
+
+    fn read(i2c: &mut I2cController, offset: Address) -> u32 {
+        let mut data = [0; 4];
+        i2c.read(offset, &mut data, AccessContext::CpuLoad).unwrap();
+        u32::from_le_bytes(data)
+    }
+
+    /// Writes `bytes` to the slave at `address` in one transfer.
+    fn transmit(i2c: &mut I2cController, address: u32, bytes: &[u8]) {
+        write(i2c, ADDRESS, address);
+        write(i2c, COMMAND, CMD_START_WRITE);
+        for byte in bytes {
+            write(i2c, DATA, *byte as u32);
+            write(i2c, COMMAND, CMD_WRITE);
+            assert_eq!(read(i2c, STATUS), 1);
+        }
+    }
+
+    /// Reads `len` bytes from the slave at `address` after a repeated start.
+    fn receive(i2c: &mut I2cController, address: u32, len: usize) -> Vec<u8> {
+        write(i2c, ADDRESS, address);
+        write(i2c, COMMAND, CMD_START_READ);
+        let bytes = (0..len)
+            .map(|_| {
+                write(i2c, COMMAND, CMD_READ);
+                read(i2c, DATA) as u8
+            })
+            .collect();
+        write(i2c, COMMAND, CMD_STOP);
+        bytes
+    }
+
+    #[test]
+    fn i2c_slave_spec_from_str() {
+        assert_eq!("lm75@0x48".parse(), Ok(I2cSlaveSpec::Lm75(0x48)));
+        assert_eq!("24c02@80".parse(), Ok(I2cSlaveSpec::Eeprom24c02(80)));
+        assert!("lm75@0x80".parse::<I2cSlaveSpec>().is_err());
+        assert!("lm75".parse::<I2cSlaveSpec>().is_err());
+        assert!("ds1307@0x68".parse::<I2cSlaveSpec>().is_err());
+    }
+
+    #[test]
+    fn i2c_controller_transfers() {
+        let slaves = vec![
+            I2cSlaveSpec::Lm75(0x48).build(),
+            I2cSlaveSpec::Eeprom24c02(0x50).build(),
+        ];
+        let mut i2c = I2cController::new(slaves);
+
+        // Read the temperature register of the sensor
+        transmit(&mut i2c, 0x48, &[0]);
+        assert_eq!(receive(&mut i2c, 0x48, 2), vec![25, 0]);
+
+        // Write to the EEPROM and read the data back from the same word address
+        transmit(&mut i2c, 0x50, &[0x10, 0xaa, 0xbb]);
+        write(&mut i2c, COMMAND, CMD_STOP);
+        transmit(&mut i2c, 0x50, &[0x0f]);
+        assert_eq!(receive(&mut i2c, 0x50, 4), vec![0xff, 0xaa, 0xbb, 0xff]);
+
+        // Nothing acknowledges an address without a slave
+        write(&mut i2c, ADDRESS, 0x20);
+        write(&mut i2c, COMMAND, CMD_START_WRITE);
+        assert_eq!(read(&mut i2c, STATUS), 0);
+    }
+}
@@ -0,0 +1,178 @@
+//! PS/2 keyboard that turns characters typed on the host into scan codes for the guest.
+//!
+//! Host characters arrive over a channel, usually fed from the standard input console, and
+//! are translated to scan code set 2 make and break codes, including the shift key for
+//! shifted characters. The guest polls `STATUS` and pops codes from `DATA`.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+
+use crate::devices::{AccessWidths, Device};
+use crate::memory::AccessContext;
+use crate::util::error::Result;
+use crate::Address;
+
+/// The physical address for the keyboard device.
+pub const BASE_ADDRESS: Address = 0x0207_0000;
+/// Size of the keyboard device in memory.
+pub const SIZE: usize = 8;
+
+/// Reading pops the next scan code, or returns 0 if there is none.
+pub const DATA: Address = 0x0;
+/// Bit 0 is set when a scan code is available.
+pub const STATUS: Address = 0x4;
+
+const LEFT_SHIFT: u8 = 0x12;
+const BREAK: u8 = 0xf0;
+
+/// Returns the scan code of the key for `c` and whether shift is held to type it.
+fn key(c: u8) -> Option<(u8, bool)> {
+    const LETTERS: &[u8; 26] = &[
+        0x1c, 0x32, 0x21, 0x23, 0x24, 0x2b, 0x34, 0x33, 0x43, 0x3b, 0x42, 0x4b, 0x3a, 0x31, 0x44,
+        0x4d, 0x15, 0x2d, 0x1b, 0x2c, 0x3c, 0x2a, 0x1d, 0x22, 0x35, 0x1a,
+    ];
+    const DIGITS: &[u8; 10] = &[0x45, 0x16, 0x1e, 0x26, 0x25, 0x2e, 0x36, 0x3d, 0x3e, 0x46];
+    const SHIFTED_DIGITS: &[u8; 10] = b")!@#$%^&*(";
+    const SYMBOLS: &[(u8, u8, u8)] = &[
+        (b'-', b'_', 0x4e),
+        (b'=', b'+', 0x55),
+        (b'[', b'{', 0x54),
+        (b']', b'}', 0x5b),
+        (b'\\', b'|', 0x5d),
+        (b';', b':', 0x4c),
+        (b'\'', b'"', 0x52),
+        (b',', b'<', 0x41),
+        (b'.', b'>', 0x49),
+        (b'/', b'?', 0x4a),
+        (b'`', b'~', 0x0e),
+    ];
+
+    match c {
+        b'a'..=b'z' => Some((LETTERS[(c - b'a') as usize], false)),
+        b'A'..=b'Z' => Some((LETTERS[(c - b'A') as usize], true)),
+        b'0'..=b'9' => Some((DIGITS[(c - b'0') as usize], false)),
+        b' ' => Some((0x29, false)),
+        b'\n' | b'\r' => Some((0x5a, false)),
+        b'\t' => Some((0x0d, false)),
+        0x08 | 0x7f => Some((0x66, false)),
+        0x1b => Some((0x76, false)),
+        _ => SHIFTED_DIGITS
+            .iter()
+            .position(|shifted| *shifted == c)
+            .map(|digit| (DIGITS[digit], true))
+            .or_else(|| {
+                SYMBOLS.iter().find_map(|&(plain, shifted, code)| {
+                    if c == plain {
+                        Some((code, false))
+                    } else if c == shifted {
+                        Some((code, true))
+                    } else {
+                        None
+                    }
+                })
+            }),
+    }
+}
+
+/// Returns the make and break codes for typing the character `c`.
+pub fn scancodes(c: u8) -> Vec<u8> {
+    match key(c) {
+        Some((code, false)) => vec![code, BREAK, code],
+        Some((code, true)) => vec![LEFT_SHIFT, code, BREAK, code, BREAK, LEFT_SHIFT],
+        None => Vec::new(),
+    }
+}
+
+pub struct KeyboardDevice {
+    keys: Receiver<u8>,
+    fifo: VecDeque<u8>,
+}
+
+impl KeyboardDevice {
+    /// Creates a keyboard that types the characters received from `keys`.
+    pub fn new(keys: Receiver<u8>) -> Self {
+        Self {
+            keys,
+            fifo: VecDeque::new(),
+        }
+    }
+
+    /// Queues the scan codes of the characters typed since the last poll.
+    fn poll(&mut self) {
+        while let Ok(c) = self.keys.try_recv() {
+            self.fifo.extend(scancodes(c));
+        }
+    }
+}
+
+impl Device for KeyboardDevice {
+    fn debug_label(&self) -> String {
+        "keyboard".to_owned()
+    }
+
+    fn access_widths(&self) -> AccessWidths {
+        AccessWidths::BYTE
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.poll();
+        self.peek(address, data)?;
+        if address == DATA {
+            self.fifo.pop_front();
+        }
+        Ok(())
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        data[0] = match address {
+            DATA => self.fifo.front().copied().unwrap_or(0),
+            STATUS => !self.fifo.is_empty() as u8,
+            _ => 0,
+        };
+        Ok(())
+    }
+
+    fn write(&mut self, _address: Address, _data: &[u8], _ctx: AccessContext) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn keyboard_scancodes() {
+        assert_eq!(scancodes(b'a'), vec![0x1c, 0xf0, 0x1c]);
+        assert_eq!(scancodes(b'!'), vec![0x12, 0x16, 0xf0, 0x16, 0xf0, 0x12]);
+        assert_eq!(scancodes(b'"'), vec![0x12, 0x52, 0xf0, 0x52, 0xf0, 0x12]);
+        assert_eq!(scancodes(b'\n'), vec![0x5a, 0xf0, 0x5a]);
+        assert_eq!(scancodes(0x80), vec![]);
+    }
+
+    #[test]
+    fn keyboard_fifo() -> Result<()> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut keyboard = KeyboardDevice::new(receiver);
+        let mut data = [0];
+
+        keyboard.read(STATUS, &mut data, AccessContext::CpuLoad)?;
+        assert_eq!(data, [0]);
+
+        sender.send(b'H').unwrap();
+        keyboard.read(STATUS, &mut data, AccessContext::CpuLoad)?;
+        assert_eq!(data, [1]);
+
+        let mut codes = Vec::new();
+        while !keyboard.fifo.is_empty() {
+            keyboard.read(DATA, &mut data, AccessContext::CpuLoad)?;
+            codes.push(data[0]);
+        }
+        assert_eq!(codes, vec![0x12, 0x33, 0xf0, 0x33, 0xf0, 0x12]);
+
+        keyboard.read(DATA, &mut data, AccessContext::CpuLoad)?;
+        assert_eq!(data, [0]);
+        Ok(())
+    }
+}
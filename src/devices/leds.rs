@@ -0,0 +1,141 @@
+//! Output latch driving a row of LEDs and a 7-segment display, like on teaching boards.
+//!
+//! The state is printed whenever the guest changes it, so programs that blink LEDs or count
+//! on the display have visible results under emulation.
+
+use std::fmt;
+
+use crate::devices::Device;
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::util::state::{StateReader, StateWriter};
+use crate::Address;
+
+/// The physical address for the LED device.
+pub const BASE_ADDRESS: Address = 0x0208_0000;
+/// Size of the LED device in memory.
+pub const SIZE: usize = 8;
+
+/// One bit per LED, with LED 0 in bit 0.
+pub const LEDS: Address = 0x0;
+/// One byte per display digit from left to right. Bits 0 to 6 drive segments `a` to `g`
+/// and bit 7 the decimal point.
+pub const DISPLAY: Address = 0x4;
+/// Number of LEDs in the row.
+pub const LED_COUNT: usize = 8;
+/// Number of digits on the 7-segment display.
+pub const DIGITS: usize = 4;
+
+/// Segment patterns of the hexadecimal digits.
+const HEX_SEGMENTS: [u8; 16] = [
+    0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07, 0x7f, 0x6f, 0x77, 0x7c, 0x39, 0x5e, 0x79, 0x71,
+];
+
+/// Returns the character shown by the segments `a` to `g` of a digit.
+fn segment_char(segments: u8) -> char {
+    match segments & 0x7f {
+        0x00 => ' ',
+        0x40 => '-',
+        0x08 => '_',
+        segments => HEX_SEGMENTS
+            .iter()
+            .position(|pattern| *pattern == segments)
+            .and_then(|digit| std::char::from_digit(digit as u32, 16))
+            .map_or('?', |c| c.to_ascii_uppercase()),
+    }
+}
+
+#[derive(Default)]
+pub struct LedDevice {
+    /// `LEDS` followed by `DISPLAY`.
+    latch: [u8; SIZE],
+}
+
+impl Device for LedDevice {
+    fn debug_label(&self) -> String {
+        "leds".to_owned()
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let start = address as usize;
+        let src = self
+            .latch
+            .get(start..start + data.len())
+            .ok_or(RmipsError::MemoryRead(address))?;
+        data.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+        let start = address as usize;
+        let dst = self
+            .latch
+            .get_mut(start..start + data.len())
+            .ok_or(RmipsError::MemoryWrite(address))?;
+        if dst != data {
+            dst.copy_from_slice(data);
+            println!("{}", self);
+        }
+        Ok(())
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.latch);
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.latch.copy_from_slice(state.bytes_of_len(SIZE)?);
+        Ok(())
+    }
+}
+
+impl fmt::Display for LedDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LEDs ")?;
+        for led in (0..LED_COUNT).rev() {
+            let on = self.latch[LEDS as usize] & (1 << led) != 0;
+            write!(f, "{}", if on { '●' } else { '○' })?;
+        }
+
+        write!(f, "  display [")?;
+        for segments in &self.latch[DISPLAY as usize..DISPLAY as usize + DIGITS] {
+            write!(f, "{}", segment_char(*segments))?;
+            if segments & 0x80 != 0 {
+                write!(f, ".")?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn leds_display() -> Result<()> {
+        let mut leds = LedDevice::default();
+        assert_eq!(leds.to_string(), "LEDs ○○○○○○○○  display [    ]");
+
+        leds.write(LEDS, &[0b1000_0101], AccessContext::CpuStore)?;
+        leds.write(
+            DISPLAY,
+            &[0x06, 0x5b | 0x80, 0x40, 0x77],
+            AccessContext::CpuStore,
+        )?;
+        assert_eq!(leds.to_string(), "LEDs ●○○○○●○●  display [12.-A]");
+
+        leds.write(DISPLAY + 3, &[0x01], AccessContext::CpuStore)?;
+        assert_eq!(leds.to_string(), "LEDs ●○○○○●○●  display [12.-?]");
+
+        let mut data = [0; 4];
+        leds.peek(DISPLAY, &mut data)?;
+        assert_eq!(data, [0x06, 0xdb, 0x40, 0x01]);
+        Ok(())
+    }
+}
@@ -1,16 +1,120 @@
 //! This module provides emulated hardware and virtual devices.
 
+use crate::memory::AccessContext;
 use crate::util::error::Result;
+use crate::util::state::{StateReader, StateWriter};
 use crate::Address;
 
+pub(crate) mod byte_swap;
+pub(crate) mod clock;
+pub(crate) mod debug_print;
+pub(crate) mod emulator_info;
 pub(crate) mod halt_device;
+pub(crate) mod i2c;
+pub(crate) mod keyboard;
+pub(crate) mod leds;
+pub(crate) mod network;
+pub(crate) mod nvram;
+pub(crate) mod prom;
+pub(crate) mod reset_device;
+pub(crate) mod serial_link;
+pub(crate) mod shared_memory;
+pub(crate) mod spi;
 pub(crate) mod test_device;
+pub(crate) mod time;
+
+bitflags! {
+    /// Access widths that a `Device` responds to.
+    pub struct AccessWidths: u8 {
+        const BYTE = 0b001;
+        const HALFWORD = 0b010;
+        const WORD = 0b100;
+    }
+}
+
+impl AccessWidths {
+    /// Returns the access width needed for a transfer of `len` bytes.
+    ///
+    /// Transfers that are not a single byte, halfword, or word are only serviced by
+    /// devices that support every width.
+    pub fn for_len(len: usize) -> Self {
+        match len {
+            1 => AccessWidths::BYTE,
+            2 => AccessWidths::HALFWORD,
+            4 => AccessWidths::WORD,
+            _ => AccessWidths::all(),
+        }
+    }
+}
 
 pub trait Device {
     /// Returns a device name for debug output.
     fn debug_label(&self) -> String;
-    /// Reads at `offset` from this device.
-    fn read(&mut self, offset: Address, data: &mut [u8]) -> Result<()>;
-    /// Writes at `offset` into this device.
-    fn write(&mut self, offset: Address, data: &[u8]) -> Result<()>;
+    /// Returns the access widths supported by this device.
+    fn access_widths(&self) -> AccessWidths {
+        AccessWidths::all()
+    }
+    /// Reads at `offset` from this device on behalf of `ctx`.
+    fn read(&mut self, offset: Address, data: &mut [u8], ctx: AccessContext) -> Result<()>;
+    /// Reads at `offset` from this device without side effects.
+    ///
+    /// Used for debugger accesses, which must never disturb the device state
+    /// (e.g. popping a receive FIFO or acknowledging an interrupt).
+    fn peek(&self, offset: Address, data: &mut [u8]) -> Result<()>;
+    /// Writes at `offset` into this device on behalf of `ctx`.
+    fn write(&mut self, offset: Address, data: &[u8], ctx: AccessContext) -> Result<()>;
+    /// Returns the backing storage of devices that behave like plain memory.
+    ///
+    /// Used to inspect guest memory in place without going through `peek`.
+    fn memory(&self) -> Option<&[u8]> {
+        None
+    }
+    /// Returns true if this device behaves like plain memory, even without contiguous
+    /// backing storage.
+    fn is_memory(&self) -> bool {
+        self.memory().is_some()
+    }
+    /// Returns true if instructions may be fetched from this device.
+    ///
+    /// Fetches from other devices raise an Instruction Bus Error, so wild jumps into
+    /// memory-mapped registers stop with a diagnostic instead of executing register reads.
+    fn executable(&self) -> bool {
+        self.is_memory()
+    }
+    /// Returns the offsets of the pages written since the last call to `clear_dirty`.
+    ///
+    /// Only devices holding guest state that is worth snapshotting track dirty pages.
+    fn dirty_pages(&self) -> Vec<Address> {
+        Vec::new()
+    }
+    /// Marks every page of this device as clean.
+    fn clear_dirty(&mut self) {}
+    /// Writes the state of this device to a machine snapshot.
+    ///
+    /// Devices that behave like plain memory save their backing storage. Devices whose state
+    /// lives on the host, like files and connections, save nothing.
+    fn save(&self, state: &mut StateWriter) {
+        if let Some(memory) = self.memory() {
+            state.bytes(memory);
+        }
+    }
+    /// Restores the state written by `save`.
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        if let Some(len) = self.memory().map(<[u8]>::len) {
+            let memory = state.bytes_of_len(len)?;
+            self.write(0, memory, AccessContext::Debugger)?;
+        }
+        Ok(())
+    }
+    /// Returns the hardware interrupt lines this device can assert, with IP2 in bit 0.
+    ///
+    /// Only devices with interrupt outputs are asked for their `interrupt_lines` before each
+    /// instruction.
+    fn interrupt_outputs(&self) -> u8 {
+        0
+    }
+    /// Returns the hardware interrupt lines this device is asserting, with IP2 in bit 0.
+    fn interrupt_lines(&mut self) -> u8 {
+        0
+    }
 }
@@ -0,0 +1,222 @@
+//! Network interface that exchanges frames with other emulator instances over UDP.
+//!
+//! Every frame the guest transmits is sent as one datagram to each configured peer, and
+//! datagrams received from any address are queued as incoming frames. Connecting several
+//! instances to each other this way behaves like a hub, so firmware that talks to a peer
+//! device over Ethernet can be tested against another emulated machine.
+//!
+//! The device is polled: the guest reads `STATUS` to check for a received frame, copies it
+//! out of the receive buffer and then writes `RX_DONE` to move on to the next one.
+
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+
+use log::{debug, error};
+
+use crate::devices::Device;
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::Address;
+
+/// The physical address for the network device.
+pub const BASE_ADDRESS: Address = 0x0203_0000;
+/// Size of the network device in memory.
+pub const SIZE: usize = 0x2000;
+
+/// Reads 1 when a received frame is available in the receive buffer.
+pub const STATUS: Address = 0x00;
+/// Length in bytes of the frame in the receive buffer, or 0 if there is none.
+pub const RX_LEN: Address = 0x04;
+/// Writing a length transmits that many bytes of the transmit buffer as a frame.
+pub const TX_LEN: Address = 0x08;
+/// Writing any value drops the frame in the receive buffer.
+pub const RX_DONE: Address = 0x0c;
+/// Offset of the transmit buffer.
+pub const TX_BUFFER: Address = 0x800;
+/// Offset of the receive buffer, which holds the oldest received frame.
+pub const RX_BUFFER: Address = 0x1000;
+/// Largest frame that can be transmitted or received.
+pub const MAX_FRAME: usize = 1536;
+
+pub struct NetworkDevice {
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+    tx: Vec<u8>,
+    rx: VecDeque<Vec<u8>>,
+}
+
+impl NetworkDevice {
+    /// Binds the device to the host UDP address `listen` and sends frames to `peers`.
+    pub fn new(listen: SocketAddr, peers: Vec<SocketAddr>) -> Result<Self> {
+        let socket = UdpSocket::bind(listen)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket,
+            peers,
+            tx: vec![0; MAX_FRAME],
+            rx: VecDeque::new(),
+        })
+    }
+
+    /// Returns the host address the device receives frames on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Queues the datagrams that have arrived since the last poll.
+    fn poll(&mut self) {
+        let mut frame = [0; MAX_FRAME];
+        loop {
+            match self.socket.recv_from(&mut frame) {
+                Ok((len, from)) => {
+                    debug!("network device received {} bytes from {}", len, from);
+                    self.rx.push_back(frame[..len].to_vec());
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    error!("Network device failed to receive: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn transmit(&mut self, len: usize) {
+        let frame = &self.tx[..len.min(MAX_FRAME)];
+        for peer in &self.peers {
+            if let Err(err) = self.socket.send_to(frame, peer) {
+                error!("Network device failed to send to {}: {}", peer, err);
+            }
+        }
+    }
+
+    fn register(&self, offset: Address) -> u32 {
+        let rx_len = self.rx.front().map_or(0, |frame| frame.len() as u32);
+        match offset {
+            STATUS => (rx_len > 0) as u32,
+            RX_LEN => rx_len,
+            _ => 0,
+        }
+    }
+}
+
+impl Device for NetworkDevice {
+    fn debug_label(&self) -> String {
+        "network-device".to_owned()
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        if address < TX_BUFFER {
+            self.poll();
+        }
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let start = address as usize;
+        let empty = Vec::new();
+        let (buffer, offset) = if address >= RX_BUFFER {
+            (
+                self.rx.front().unwrap_or(&empty),
+                start - RX_BUFFER as usize,
+            )
+        } else if address >= TX_BUFFER {
+            (&self.tx, start - TX_BUFFER as usize)
+        } else {
+            let value = self.register(address & !3).to_le_bytes();
+            let offset = (address & 3) as usize;
+            let src = value
+                .get(offset..offset + data.len())
+                .ok_or(RmipsError::MemoryRead(address))?;
+            data.copy_from_slice(src);
+            return Ok(());
+        };
+
+        // Bytes past the end of a received frame read as zero
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = buffer.get(offset + i).copied().unwrap_or(0);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+        debug!("write to network device @ 0x{:08x}", address);
+
+        if address >= RX_BUFFER {
+            return Err(RmipsError::MemoryWrite(address));
+        } else if address >= TX_BUFFER {
+            let start = (address - TX_BUFFER) as usize;
+            let dst = self
+                .tx
+                .get_mut(start..start + data.len())
+                .ok_or(RmipsError::MemoryWrite(address))?;
+            dst.copy_from_slice(data);
+            return Ok(());
+        }
+
+        let mut value = [0; 4];
+        let len = data.len().min(value.len());
+        value[..len].copy_from_slice(&data[..len]);
+        match address {
+            TX_LEN => self.transmit(u32::from_le_bytes(value) as usize),
+            RX_DONE => {
+                self.rx.pop_front();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn localhost() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    fn read_word(dev: &mut NetworkDevice, address: Address) -> Result<u32> {
+        let mut data = [0; 4];
+        dev.read(address, &mut data, AccessContext::CpuLoad)?;
+        Ok(u32::from_le_bytes(data))
+    }
+
+    #[test]
+    fn network_device_exchanges_frames() -> Result<()> {
+        let mut receiver = NetworkDevice::new(localhost(), Vec::new())?;
+        let mut sender = NetworkDevice::new(localhost(), vec![receiver.local_addr()?])?;
+
+        sender.write(TX_BUFFER, b"ping", AccessContext::CpuStore)?;
+        sender.write(TX_LEN, &4u32.to_le_bytes(), AccessContext::CpuStore)?;
+        sender.write(TX_BUFFER, b"pong", AccessContext::CpuStore)?;
+        sender.write(TX_LEN, &4u32.to_le_bytes(), AccessContext::CpuStore)?;
+
+        // Datagrams on the loopback interface may take a moment to arrive
+        for _ in 0..100 {
+            if receiver.rx.len() == 2 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            read_word(&mut receiver, STATUS)?;
+        }
+
+        assert_eq!(read_word(&mut receiver, STATUS)?, 1);
+        assert_eq!(read_word(&mut receiver, RX_LEN)?, 4);
+        let mut frame = [0; 4];
+        receiver.peek(RX_BUFFER, &mut frame)?;
+        assert_eq!(&frame, b"ping");
+
+        receiver.write(RX_DONE, &[1, 0, 0, 0], AccessContext::CpuStore)?;
+        receiver.peek(RX_BUFFER, &mut frame)?;
+        assert_eq!(&frame, b"pong");
+
+        receiver.write(RX_DONE, &[1, 0, 0, 0], AccessContext::CpuStore)?;
+        assert_eq!(read_word(&mut receiver, STATUS)?, 0);
+        assert_eq!(read_word(&mut receiver, RX_LEN)?, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+//! Non-volatile storage device backed by a host file.
+//!
+//! Every write is passed through to the backing file, so the contents survive
+//! emulator restarts (and crashes) and can be used to test firmware that keeps
+//! configuration across boot cycles.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use log::debug;
+
+use crate::devices::Device;
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::Address;
+
+/// The physical address for the NVRAM device.
+pub const BASE_ADDRESS: Address = 0x0102_0000;
+
+pub struct NvramDevice {
+    path: String,
+    file: File,
+    data: Vec<u8>,
+}
+
+impl NvramDevice {
+    /// Opens the backing file at `path`, creating it if it does not exist.
+    ///
+    /// The file is resized to `size` bytes, new bytes read as zero.
+    pub fn new(path: String, size: usize) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        file.set_len(size as u64)?;
+
+        let mut data = vec![0; size];
+        file.read_exact(&mut data)?;
+
+        Ok(Self { path, file, data })
+    }
+}
+
+impl Device for NvramDevice {
+    fn debug_label(&self) -> String {
+        format!("nvram ({})", self.path)
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let start = address as usize;
+        let src = self
+            .data
+            .get(start..start + data.len())
+            .ok_or(RmipsError::MemoryRead(address))?;
+        data.copy_from_slice(src);
+
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+        debug!("write to nvram device @ 0x{:08x}", address);
+
+        let start = address as usize;
+        let dst = self
+            .data
+            .get_mut(start..start + data.len())
+            .ok_or(RmipsError::MemoryWrite(address))?;
+        dst.copy_from_slice(data);
+
+        self.file.seek(SeekFrom::Start(address.into()))?;
+        self.file.write_all(data)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rmips-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn nvram_persists_across_instances() -> Result<()> {
+        let path = temp_path("nvram-persist");
+
+        let mut nvram = NvramDevice::new(path.clone(), 0x100)?;
+        nvram.write(0x10, &[0xde, 0xad, 0xbe, 0xef], AccessContext::CpuStore)?;
+        drop(nvram);
+
+        let nvram = NvramDevice::new(path.clone(), 0x100)?;
+        let mut data = [0; 4];
+        nvram.peek(0x10, &mut data)?;
+        assert_eq!(data, [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(std::fs::metadata(&path)?.len(), 0x100);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn nvram_out_of_range() -> Result<()> {
+        let path = temp_path("nvram-range");
+
+        let mut nvram = NvramDevice::new(path.clone(), 0x10)?;
+        assert!(nvram.write(0xe, &[0; 4], AccessContext::CpuStore).is_err());
+        assert!(nvram.peek(0x10, &mut [0]).is_err());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
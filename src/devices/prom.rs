@@ -0,0 +1,168 @@
+//! Built-in monitor PROM for kernels that expect firmware callbacks.
+//!
+//! The PROM image starts with a vector of entry points that guest software
+//! calls through, like the callback vectors of the SGI and DEC PROMs. Every
+//! entry point is a `jr ra` stub: the emulator services the call when the
+//! program counter reaches it, so no firmware code is actually executed.
+//! The environment is stored after the stubs as `NAME=VALUE` strings.
+
+use log::warn;
+
+use crate::control::KSEG1;
+use crate::devices::Device;
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::{Address, Endian};
+
+/// The physical address for the monitor PROM.
+pub const BASE_ADDRESS: Address = 0x1fd0_0000;
+/// Offset of the first entry point stub.
+const STUB_OFFSET: usize = 0x40;
+/// Size of each entry point stub.
+const STUB_LEN: usize = 8;
+/// Offset of the environment strings.
+pub const ENV_OFFSET: usize = 0x100;
+
+/// `jr ra` followed by a `nop` in its delay slot.
+const RETURN_STUB: [u32; 2] = [0x03e0_0008, 0x0000_0000];
+
+/// Firmware services provided by the monitor PROM, in callback vector order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PromCall {
+    /// `int putchar(int c)`: writes a character to the console.
+    Putchar,
+    /// `char *getenv(const char *name)`: returns the value of an environment variable or null.
+    Getenv,
+    /// `void exit(int status)`: stops the machine.
+    Exit,
+}
+
+impl PromCall {
+    const ALL: [PromCall; 3] = [PromCall::Putchar, PromCall::Getenv, PromCall::Exit];
+
+    /// Returns the service whose entry point is at physical address `paddress`.
+    pub fn at(paddress: Address) -> Option<Self> {
+        let offset = paddress.checked_sub(BASE_ADDRESS)? as usize;
+        let index = offset.checked_sub(STUB_OFFSET)?;
+        if index % STUB_LEN != 0 {
+            return None;
+        }
+
+        PromCall::ALL.get(index / STUB_LEN).copied()
+    }
+
+    /// Returns the kseg1 address of the entry point for this service.
+    pub fn entry_point(self) -> Address {
+        KSEG1 + BASE_ADDRESS + (STUB_OFFSET + self as usize * STUB_LEN) as Address
+    }
+}
+
+pub struct MonitorProm {
+    image: Vec<u8>,
+}
+
+impl MonitorProm {
+    /// Builds the PROM image with the environment variables in `env`, storing the vector and
+    /// the stubs in the byte order of the CPU.
+    pub fn new(env: &[(String, String)], endian: Endian) -> Self {
+        let mut image = vec![0; ENV_OFFSET];
+
+        for (i, call) in PromCall::ALL.iter().enumerate() {
+            image[i * 4..i * 4 + 4].copy_from_slice(&endian.word_bytes(call.entry_point()));
+
+            let stub = STUB_OFFSET + i * STUB_LEN;
+            for (j, word) in RETURN_STUB.iter().enumerate() {
+                image[stub + j * 4..stub + j * 4 + 4].copy_from_slice(&endian.word_bytes(*word));
+            }
+        }
+
+        // The environment is a list of NUL-terminated strings ending with an empty string
+        for (name, value) in env {
+            image.extend_from_slice(name.as_bytes());
+            image.push(b'=');
+            image.extend_from_slice(value.as_bytes());
+            image.push(0);
+        }
+        image.push(0);
+        image.resize(image.len().next_multiple_of(4), 0);
+
+        Self { image }
+    }
+
+    /// Returns the size of the PROM image in bytes.
+    pub fn size(&self) -> usize {
+        self.image.len()
+    }
+}
+
+impl Device for MonitorProm {
+    fn debug_label(&self) -> String {
+        "monitor-prom".to_owned()
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let start = address as usize;
+        let src = self
+            .image
+            .get(start..start + data.len())
+            .ok_or(RmipsError::MemoryRead(address))?;
+        data.copy_from_slice(src);
+
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, _data: &[u8], _ctx: AccessContext) -> Result<()> {
+        // Writes to a real PROM have no effect
+        warn!("Ignoring write to monitor PROM @ 0x{:08x}", address);
+        Ok(())
+    }
+
+    fn executable(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn word(prom: &MonitorProm, offset: Address) -> u32 {
+        let mut data = [0; 4];
+        prom.peek(offset, &mut data).unwrap();
+        u32::from_le_bytes(data)
+    }
+
+    #[test]
+    fn prom_callback_vector() {
+        let prom = MonitorProm::new(&[], Endian::Little);
+
+        assert_eq!(word(&prom, 0), 0xbfd0_0040);
+        assert_eq!(word(&prom, 4), 0xbfd0_0048);
+        assert_eq!(word(&prom, 8), 0xbfd0_0050);
+        assert_eq!(word(&prom, 0x48), 0x03e0_0008);
+
+        assert_eq!(PromCall::at(BASE_ADDRESS + 0x48), Some(PromCall::Getenv));
+        assert_eq!(PromCall::at(BASE_ADDRESS + 0x4c), None);
+        assert_eq!(PromCall::at(BASE_ADDRESS + 0x58), None);
+        assert_eq!(PromCall::at(BASE_ADDRESS), None);
+    }
+
+    #[test]
+    fn prom_environment() {
+        let env = vec![
+            ("console".to_owned(), "ttyS0".to_owned()),
+            ("root".to_owned(), "".to_owned()),
+        ];
+        let prom = MonitorProm::new(&env, Endian::Little);
+
+        let mut data = vec![0; prom.size() - ENV_OFFSET];
+        prom.peek(ENV_OFFSET as Address, &mut data).unwrap();
+        assert_eq!(&data[..21], b"console=ttyS0\0root=\0\0");
+        assert_eq!(prom.size() % 4, 0);
+    }
+}
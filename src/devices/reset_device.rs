@@ -0,0 +1,38 @@
+use log::debug;
+
+use crate::devices::Device;
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::Address;
+
+/// The physical address for the reset device, the word after the halt device.
+pub const BASE_ADDRESS: Address = 0x01010028;
+
+/// A word that resets the CPU when the guest writes a nonzero value to it, like the reset
+/// register of a board. Memory and the other devices keep their state.
+pub struct ResetDevice;
+
+impl Device for ResetDevice {
+    fn debug_label(&self) -> String {
+        "reset-device".to_owned()
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        debug!("read from reset device @ 0x{:08x}", address);
+        self.peek(address, data)
+    }
+
+    fn peek(&self, _address: Address, data: &mut [u8]) -> Result<()> {
+        data.fill(0);
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+        debug!("write to reset device @ 0x{:08x}", address);
+
+        if data.iter().any(|v| *v != 0) {
+            return Err(RmipsError::Reset);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,555 @@
+//! Null-modem serial device whose other end is a host socket.
+//!
+//! Bytes the guest writes to `DATA` are sent over the socket and bytes received from it are
+//! read back from `DATA`, so the console of one emulator instance can be wired to another
+//! instance or to a host terminal program like picocom. The link is 8-bit clean, which makes
+//! it usable for testing bootloader transfer protocols such as XMODEM.
+//!
+//! Received bytes wait in a FIFO of configurable depth, and the link can raise an interrupt
+//! once the FIFO fills to a threshold. Host input is only read while the FIFO has room, so
+//! overruns come from the internal loopback mode, in which bytes written to `DATA` are
+//! received again instead of being sent. This lets guest drivers test their flow control and
+//! error handling without depending on the timing of host input.
+//!
+//! Bytes from an input script are received like host input, but arrive at a fixed point of the
+//! run, so scripted sessions behave the same on every run.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use log::{debug, error, info};
+
+use crate::devices::Device;
+use crate::memory::AccessContext;
+use crate::util::error::Result;
+use crate::Address;
+
+/// The physical address for the serial link device.
+pub const BASE_ADDRESS: Address = 0x0204_0000;
+/// Size of the serial link device in memory.
+pub const SIZE: usize = 16;
+
+/// Reading pops the next received byte, writing sends a byte.
+pub const DATA: Address = 0x0;
+/// Status bits of the link.
+pub const STATUS: Address = 0x4;
+/// Interrupt and loopback enables.
+pub const CONTROL: Address = 0x8;
+/// Number of received bytes in the FIFO that raises the receive interrupt.
+pub const THRESHOLD: Address = 0xc;
+/// Set in `STATUS` when a received byte can be read from `DATA`.
+pub const STATUS_RX_READY: u8 = 1 << 0;
+/// Set in `STATUS` while the other end is connected.
+pub const STATUS_CONNECTED: u8 = 1 << 1;
+/// Set in `STATUS` when a byte was lost because the FIFO was full, cleared by reading `STATUS`.
+pub const STATUS_OVERRUN: u8 = 1 << 2;
+/// Set in `STATUS` while the receive interrupt is raised.
+pub const STATUS_RX_IRQ: u8 = 1 << 3;
+/// Set in `CONTROL` to raise an interrupt while the FIFO holds `THRESHOLD` bytes or more.
+pub const CONTROL_RX_IRQ: u8 = 1 << 0;
+/// Set in `CONTROL` to receive the bytes written to `DATA` instead of sending them.
+pub const CONTROL_LOOPBACK: u8 = 1 << 1;
+/// Hardware interrupt line of the receive interrupt, which is IP2 in the Cause register.
+pub const IRQ_LINE: u8 = 0;
+/// Default number of received bytes the FIFO holds.
+pub const DEFAULT_FIFO_DEPTH: usize = 16;
+
+/// Number of interrupt checks between polls of the host connection while the receive
+/// interrupt is enabled, since the guest does not access the device while it waits.
+const POLL_INTERVAL: u32 = 1024;
+
+/// The host end of the serial link, given in the style of `socat` addresses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SerialEndpoint {
+    /// `tcp-listen:ADDRESS:PORT` waits for a connection.
+    TcpListen(String),
+    /// `tcp:ADDRESS:PORT` connects to a listening socket.
+    Tcp(String),
+    /// `unix-listen:PATH` waits for a connection on a Unix domain socket.
+    UnixListen(String),
+    /// `unix:PATH` connects to a listening Unix domain socket.
+    Unix(String),
+}
+
+impl FromStr for SerialEndpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("invalid serial link endpoint: {}", s);
+        let (kind, address) = s.split_once(':').ok_or_else(invalid)?;
+        if address.is_empty() {
+            return Err(invalid());
+        }
+
+        let address = address.to_owned();
+        match kind {
+            "tcp-listen" => Ok(SerialEndpoint::TcpListen(address)),
+            "tcp" => Ok(SerialEndpoint::Tcp(address)),
+            "unix-listen" => Ok(SerialEndpoint::UnixListen(address)),
+            "unix" => Ok(SerialEndpoint::Unix(address)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for SerialEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SerialEndpoint::TcpListen(address) => write!(f, "tcp-listen:{}", address),
+            SerialEndpoint::Tcp(address) => write!(f, "tcp:{}", address),
+            SerialEndpoint::UnixListen(path) => write!(f, "unix-listen:{}", path),
+            SerialEndpoint::Unix(path) => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, address) = listener.accept()?;
+                info!("Serial link connected from {}", address);
+                Ok(Connection::Tcp(stream))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept()?;
+                info!("Serial link connected");
+                Ok(Connection::Unix(stream))
+            }
+        }
+    }
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn set_nonblocking(&self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_nonblocking(true),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_nonblocking(true),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+pub struct SerialLinkDevice {
+    endpoint: SerialEndpoint,
+    /// Accepts a new connection whenever the link is down, for listening endpoints.
+    listener: Option<Listener>,
+    connection: Option<Connection>,
+    /// Bytes from an input script, which wait here until the FIFO has room for them.
+    scripted: Option<Receiver<u8>>,
+    rx: VecDeque<u8>,
+    tx: VecDeque<u8>,
+    fifo_depth: usize,
+    control: u8,
+    threshold: u8,
+    overrun: bool,
+    /// Interrupt checks since the host connection was last polled.
+    idle_checks: u32,
+}
+
+impl SerialLinkDevice {
+    /// Opens the host end of the link.
+    ///
+    /// Listening endpoints are bound immediately and accept a connection once the guest polls
+    /// the device. Connecting endpoints connect immediately and fail if nothing is listening.
+    pub fn new(endpoint: SerialEndpoint, fifo_depth: usize) -> Result<Self> {
+        let (listener, connection) = match &endpoint {
+            SerialEndpoint::TcpListen(address) => {
+                let listener = TcpListener::bind(address)?;
+                listener.set_nonblocking(true)?;
+                (Some(Listener::Tcp(listener)), None)
+            }
+            SerialEndpoint::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_nodelay(true)?;
+                (None, Some(Connection::Tcp(stream)))
+            }
+            #[cfg(unix)]
+            SerialEndpoint::UnixListen(path) => {
+                let listener = UnixListener::bind(path)?;
+                listener.set_nonblocking(true)?;
+                (Some(Listener::Unix(listener)), None)
+            }
+            #[cfg(unix)]
+            SerialEndpoint::Unix(path) => {
+                (None, Some(Connection::Unix(UnixStream::connect(path)?)))
+            }
+            #[cfg(not(unix))]
+            SerialEndpoint::UnixListen(_) | SerialEndpoint::Unix(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "Unix domain sockets are not supported on this platform",
+                )
+                .into())
+            }
+        };
+        if let Some(connection) = &connection {
+            connection.set_nonblocking()?;
+        }
+
+        Ok(Self {
+            endpoint,
+            listener,
+            connection,
+            scripted: None,
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+            fifo_depth: fifo_depth.clamp(1, u8::MAX as usize),
+            control: 0,
+            threshold: 1,
+            overrun: false,
+            idle_checks: 0,
+        })
+    }
+
+    /// Returns the host address of a listening TCP endpoint, e.g. after binding port 0.
+    pub fn local_addr(&self) -> Option<std::net::SocketAddr> {
+        match &self.listener {
+            Some(Listener::Tcp(listener)) => listener.local_addr().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns a channel for bytes to receive in addition to the host input.
+    pub fn scripted_input(&mut self) -> Sender<u8> {
+        let (sender, receiver) = mpsc::channel();
+        self.scripted = Some(receiver);
+        sender
+    }
+
+    /// Moves the scripted bytes that fit into the FIFO.
+    fn receive_scripted(&mut self) {
+        let Some(scripted) = &self.scripted else {
+            return;
+        };
+        while self.rx.len() < self.fifo_depth {
+            match scripted.try_recv() {
+                Ok(byte) => self.rx.push_back(byte),
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Accepts a pending connection, receives the available bytes and sends queued ones.
+    fn poll(&mut self) {
+        self.receive_scripted();
+        if self.connection.is_none() {
+            match self.listener.as_ref().map(Listener::accept) {
+                Some(Ok(connection)) => match connection.set_nonblocking() {
+                    Ok(()) => self.connection = Some(connection),
+                    Err(err) => error!("Serial link failed to accept: {}", err),
+                },
+                Some(Err(err)) if err.kind() != ErrorKind::WouldBlock => {
+                    error!("Serial link failed to accept: {}", err)
+                }
+                _ => {}
+            }
+        }
+
+        let Some(connection) = &mut self.connection else {
+            return;
+        };
+        let mut buf = [0; 256];
+        let closed = loop {
+            // Input stays in the host socket until the FIFO has room for it
+            let room = self.fifo_depth.saturating_sub(self.rx.len()).min(buf.len());
+            if room == 0 {
+                break false;
+            }
+            match connection.read(&mut buf[..room]) {
+                Ok(0) => break true,
+                Ok(len) => self.rx.extend(&buf[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break false,
+                Err(err) => {
+                    error!("Serial link failed to receive: {}", err);
+                    break true;
+                }
+            }
+        };
+
+        while !closed && !self.tx.is_empty() {
+            let (pending, _) = self.tx.as_slices();
+            match connection.write(pending) {
+                Ok(len) => {
+                    self.tx.drain(..len);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    error!("Serial link failed to send: {}", err);
+                    break;
+                }
+            }
+        }
+
+        if closed {
+            info!("Serial link {} disconnected", self.endpoint);
+            self.connection = None;
+            self.tx.clear();
+        }
+    }
+
+    /// Returns true while the receive interrupt is enabled and the FIFO reached the threshold.
+    fn rx_interrupt(&self) -> bool {
+        self.control & CONTROL_RX_IRQ != 0 && self.rx.len() >= self.threshold as usize
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = 0;
+        if !self.rx.is_empty() {
+            status |= STATUS_RX_READY;
+        }
+        if self.connection.is_some() {
+            status |= STATUS_CONNECTED;
+        }
+        if self.overrun {
+            status |= STATUS_OVERRUN;
+        }
+        if self.rx_interrupt() {
+            status |= STATUS_RX_IRQ;
+        }
+        status
+    }
+
+    /// Sends a byte written to `DATA`, or receives it again in loopback mode.
+    fn transmit(&mut self, byte: u8) {
+        if self.control & CONTROL_LOOPBACK != 0 {
+            match self.rx.len() < self.fifo_depth {
+                true => self.rx.push_back(byte),
+                false => self.overrun = true,
+            }
+        } else if self.connection.is_some() {
+            // Bytes sent while nothing is connected are lost, like on a real serial line
+            self.tx.push_back(byte);
+        }
+    }
+}
+
+impl Device for SerialLinkDevice {
+    fn debug_label(&self) -> String {
+        format!("serial-link ({})", self.endpoint)
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.poll();
+        self.peek(address, data)?;
+        match address {
+            DATA => {
+                self.rx.pop_front();
+            }
+            STATUS => self.overrun = false,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        data.iter_mut().for_each(|byte| *byte = 0);
+        match address {
+            DATA => data[0] = self.rx.front().copied().unwrap_or(0),
+            STATUS => data[0] = self.status(),
+            CONTROL => data[0] = self.control,
+            THRESHOLD => data[0] = self.threshold,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+        debug!("write to serial link @ 0x{:08x}", address);
+
+        match address {
+            DATA => self.transmit(data[0]),
+            CONTROL => self.control = data[0] & (CONTROL_RX_IRQ | CONTROL_LOOPBACK),
+            THRESHOLD => self.threshold = data[0].clamp(1, self.fifo_depth as u8),
+            _ => {}
+        }
+        self.poll();
+        Ok(())
+    }
+
+    fn interrupt_outputs(&self) -> u8 {
+        1 << IRQ_LINE
+    }
+
+    fn interrupt_lines(&mut self) -> u8 {
+        self.receive_scripted();
+        if self.control & CONTROL_RX_IRQ == 0 {
+            return 0;
+        }
+
+        self.idle_checks += 1;
+        if self.idle_checks >= POLL_INTERVAL {
+            self.idle_checks = 0;
+            self.poll();
+        }
+        match self.rx_interrupt() {
+            true => 1 << IRQ_LINE,
+            false => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn read_byte(dev: &mut SerialLinkDevice, address: Address) -> Result<u8> {
+        let mut data = [0];
+        dev.read(address, &mut data, AccessContext::CpuLoad)?;
+        Ok(data[0])
+    }
+
+    /// Polls `dev` until a received byte is available.
+    fn wait_for_byte(dev: &mut SerialLinkDevice) -> Result<u8> {
+        for _ in 0..100 {
+            if read_byte(dev, STATUS)? & STATUS_RX_READY != 0 {
+                return read_byte(dev, DATA);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("no byte received");
+    }
+
+    #[test]
+    fn serial_endpoint_from_str() {
+        let tcp = |address: &str| Ok(SerialEndpoint::Tcp(address.to_owned()));
+
+        assert_eq!("tcp:127.0.0.1:4000".parse(), tcp("127.0.0.1:4000"));
+        assert_eq!(
+            "unix-listen:/tmp/rmips.sock".parse(),
+            Ok(SerialEndpoint::UnixListen("/tmp/rmips.sock".to_owned()))
+        );
+        assert!("tcp:".parse::<SerialEndpoint>().is_err());
+        assert!("udp:127.0.0.1:4000".parse::<SerialEndpoint>().is_err());
+        assert!("127.0.0.1".parse::<SerialEndpoint>().is_err());
+    }
+
+    #[test]
+    fn serial_link_null_modem() -> Result<()> {
+        let mut listener = SerialLinkDevice::new("tcp-listen:127.0.0.1:0".parse().unwrap(), 16)?;
+        let address = listener.local_addr().unwrap();
+        assert_eq!(read_byte(&mut listener, STATUS)?, 0);
+
+        let mut dialer = SerialLinkDevice::new(SerialEndpoint::Tcp(address.to_string()), 16)?;
+        assert_eq!(read_byte(&mut dialer, STATUS)?, STATUS_CONNECTED);
+
+        dialer.write(DATA, &[0x00], AccessContext::CpuStore)?;
+        dialer.write(DATA, &[0xff], AccessContext::CpuStore)?;
+        assert_eq!(wait_for_byte(&mut listener)?, 0x00);
+        assert_eq!(wait_for_byte(&mut listener)?, 0xff);
+        assert_eq!(read_byte(&mut listener, STATUS)?, STATUS_CONNECTED);
+
+        listener.write(DATA, b"C", AccessContext::CpuStore)?;
+        assert_eq!(wait_for_byte(&mut dialer)?, b'C');
+        Ok(())
+    }
+
+    #[test]
+    fn serial_link_loopback_fifo() -> Result<()> {
+        let mut link = SerialLinkDevice::new("tcp-listen:127.0.0.1:0".parse().unwrap(), 4)?;
+        link.write(
+            CONTROL,
+            &[CONTROL_LOOPBACK | CONTROL_RX_IRQ],
+            AccessContext::CpuStore,
+        )?;
+        link.write(THRESHOLD, &[3], AccessContext::CpuStore)?;
+
+        for byte in b"ab" {
+            link.write(DATA, &[*byte], AccessContext::CpuStore)?;
+        }
+        assert_eq!(link.interrupt_lines(), 0);
+        link.write(DATA, b"c", AccessContext::CpuStore)?;
+        assert_eq!(link.interrupt_lines(), 1 << IRQ_LINE);
+        assert_eq!(
+            read_byte(&mut link, STATUS)?,
+            STATUS_RX_READY | STATUS_RX_IRQ
+        );
+
+        // The fifth byte does not fit and is reported once as an overrun
+        for byte in b"de" {
+            link.write(DATA, &[*byte], AccessContext::CpuStore)?;
+        }
+        assert_eq!(
+            read_byte(&mut link, STATUS)?,
+            STATUS_RX_READY | STATUS_OVERRUN | STATUS_RX_IRQ
+        );
+        assert_eq!(read_byte(&mut link, STATUS)? & STATUS_OVERRUN, 0);
+
+        let received: Vec<u8> = (0..4)
+            .map(|_| read_byte(&mut link, DATA))
+            .collect::<Result<_>>()?;
+        assert_eq!(received, b"abcd");
+        assert_eq!(link.interrupt_lines(), 0);
+        assert_eq!(read_byte(&mut link, STATUS)?, 0);
+
+        // The threshold is limited to the FIFO depth
+        link.write(THRESHOLD, &[9], AccessContext::CpuStore)?;
+        assert_eq!(read_byte(&mut link, THRESHOLD)?, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn serial_link_scripted_input() -> Result<()> {
+        let mut link = SerialLinkDevice::new("tcp-listen:127.0.0.1:0".parse().unwrap(), 2)?;
+        let input = link.scripted_input();
+        input.send(b'a').unwrap();
+        input.send(b'b').unwrap();
+        input.send(b'c').unwrap();
+
+        // The third byte waits for room in the FIFO instead of overrunning it
+        assert_eq!(read_byte(&mut link, STATUS)?, STATUS_RX_READY);
+        assert_eq!(read_byte(&mut link, DATA)?, b'a');
+        assert_eq!(read_byte(&mut link, DATA)?, b'b');
+        assert_eq!(read_byte(&mut link, DATA)?, b'c');
+        assert_eq!(read_byte(&mut link, STATUS)?, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! Memory region shared between the guest and the host through a file-backed mapping.
+//!
+//! The backing file is mapped as shared memory, so guest accesses go straight to the pages
+//! that a host process sees when it maps the same file. Host-side test code can exchange
+//! large buffers with the guest this way without copying them through a device interface.
+
+use std::fs::OpenOptions;
+
+use log::debug;
+use memmap2::{MmapMut, MmapOptions};
+
+use crate::devices::Device;
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::util::state::{StateReader, StateWriter};
+use crate::Address;
+
+/// The physical address for the shared memory device.
+pub const BASE_ADDRESS: Address = 0x0400_0000;
+
+pub struct SharedMemory {
+    path: String,
+    data: MmapMut,
+}
+
+impl SharedMemory {
+    /// Maps the file at `path` as shared memory, creating it if it does not exist.
+    ///
+    /// The file is resized to `size` bytes, new bytes read as zero.
+    pub fn new(path: String, size: usize) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        file.set_len(size as u64)?;
+
+        // SAFETY: The mapping is shared with the host on purpose. Other processes may change
+        // the contents at any time, which the guest observes like memory written by DMA.
+        let data = unsafe { MmapOptions::new().len(size).map_mut(&file)? };
+
+        Ok(Self { path, data })
+    }
+}
+
+impl Device for SharedMemory {
+    fn debug_label(&self) -> String {
+        format!("shared memory ({})", self.path)
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let start = address as usize;
+        let src = self
+            .data
+            .get(start..start + data.len())
+            .ok_or(RmipsError::MemoryRead(address))?;
+        data.copy_from_slice(src);
+
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+        debug!("write to shared memory @ 0x{:08x}", address);
+
+        let start = address as usize;
+        let dst = self
+            .data
+            .get_mut(start..start + data.len())
+            .ok_or(RmipsError::MemoryWrite(address))?;
+        dst.copy_from_slice(data);
+
+        Ok(())
+    }
+
+    fn memory(&self) -> Option<&[u8]> {
+        Some(&self.data)
+    }
+
+    // The contents belong to the host processes sharing the file
+    fn save(&self, _state: &mut StateWriter) {}
+
+    fn restore(&mut self, _state: &mut StateReader) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn shared_memory_is_visible_to_host() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("rmips-{}-shmem", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let _ = std::fs::remove_file(&path);
+
+        let mut shmem = SharedMemory::new(path.clone(), 0x1000)?;
+        shmem.write(0x10, &[0xde, 0xad, 0xbe, 0xef], AccessContext::CpuStore)?;
+        assert_eq!(
+            &std::fs::read(&path)?[0x10..0x14],
+            &[0xde, 0xad, 0xbe, 0xef]
+        );
+
+        // A host process mapping the same file sees the guest's memory and vice versa
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let mut host = unsafe { MmapMut::map_mut(&file)? };
+        host[0x20..0x22].copy_from_slice(&[0x12, 0x34]);
+
+        let mut data = [0; 2];
+        shmem.peek(0x20, &mut data)?;
+        assert_eq!(data, [0x12, 0x34]);
+        assert!(shmem.peek(0xfff, &mut data).is_err());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
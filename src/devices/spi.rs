@@ -0,0 +1,403 @@
+//! SPI master controller and the slave device models that can be attached to it.
+//!
+//! Slaves are numbered by their chip select line in the order they were attached. The guest
+//! asserts a chip select by writing its number to `SELECT`, then every byte written to `DATA`
+//! is shifted out to the slave while the byte the slave shifts back is latched for reading.
+
+use std::str::FromStr;
+
+use crate::devices::Device;
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::util::state::{StateReader, StateWriter};
+use crate::Address;
+
+/// The physical address for the SPI controller.
+pub const BASE_ADDRESS: Address = 0x0206_0000;
+/// Size of the SPI controller in memory.
+pub const SIZE: usize = 0x8;
+
+/// Writing a slave number asserts its chip select, any other value releases it.
+pub const SELECT: Address = 0x0;
+/// Writing transfers a byte, reading returns the byte received by the last transfer.
+pub const DATA: Address = 0x4;
+
+/// Value of `SELECT` when no chip select is asserted.
+pub const SELECT_NONE: u32 = 0xff;
+
+/// A device model attached to the SPI bus.
+pub trait SpiSlave {
+    /// Called when the chip select of the slave is asserted.
+    fn select(&mut self) {}
+    /// Exchanges a byte with the master.
+    fn transfer(&mut self, byte: u8) -> u8;
+    /// Called when the chip select of the slave is released.
+    fn deselect(&mut self) {}
+    /// Writes the state of the slave to a machine snapshot.
+    fn save(&self, _state: &mut StateWriter) {}
+    /// Restores the state written by `save`.
+    fn restore(&mut self, _state: &mut StateReader) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The slave models that can be attached from the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpiSlaveSpec {
+    /// 25LC256 EEPROM with 32KB.
+    Eeprom25lc256,
+}
+
+impl SpiSlaveSpec {
+    pub fn build(self) -> Box<dyn SpiSlave> {
+        match self {
+            SpiSlaveSpec::Eeprom25lc256 => Box::new(Eeprom25lc256::new()),
+        }
+    }
+}
+
+impl FromStr for SpiSlaveSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "25lc256" => Ok(SpiSlaveSpec::Eeprom25lc256),
+            _ => Err(format!("invalid SPI slave: {}", s)),
+        }
+    }
+}
+
+pub struct SpiController {
+    slaves: Vec<Box<dyn SpiSlave>>,
+    selected: Option<usize>,
+    data: u8,
+}
+
+impl SpiController {
+    pub fn new(slaves: Vec<Box<dyn SpiSlave>>) -> Self {
+        Self {
+            slaves,
+            selected: None,
+            data: 0,
+        }
+    }
+
+    fn select(&mut self, value: u32) {
+        if let Some(index) = self.selected.take() {
+            self.slaves[index].deselect();
+        }
+
+        let index = value as usize;
+        if index < self.slaves.len() {
+            self.slaves[index].select();
+            self.selected = Some(index);
+        }
+    }
+
+    fn register(&self, offset: Address) -> u32 {
+        match offset {
+            SELECT => self.selected.map_or(SELECT_NONE, |index| index as u32),
+            DATA => self.data as u32,
+            _ => 0,
+        }
+    }
+}
+
+impl Device for SpiController {
+    fn debug_label(&self) -> String {
+        "spi-controller".to_owned()
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let value = self.register(address & !3).to_le_bytes();
+        let offset = (address & 3) as usize;
+        let src = value
+            .get(offset..offset + data.len())
+            .ok_or(RmipsError::MemoryRead(address))?;
+        data.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+        let mut value = [0; 4];
+        let len = data.len().min(value.len());
+        value[..len].copy_from_slice(&data[..len]);
+        let value = u32::from_le_bytes(value);
+
+        match address {
+            SELECT => self.select(value),
+            DATA => {
+                // MISO floats high when no slave is selected
+                self.data = match self.selected {
+                    Some(index) => self.slaves[index].transfer(value as u8),
+                    None => 0xff,
+                };
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.word(self.register(SELECT));
+        state.word(self.data as u32);
+        for slave in &self.slaves {
+            slave.save(state);
+        }
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        let selected = state.word()? as usize;
+        self.selected = Some(selected).filter(|index| *index < self.slaves.len());
+        self.data = state.word()? as u8;
+        for slave in &mut self.slaves {
+            slave.restore(state)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EepromState {
+    Command,
+    Address { command: u8, high: Option<u8> },
+    Read(u16),
+    Write(u16),
+    Status,
+    Ignore,
+}
+
+impl EepromState {
+    /// Returns the state as a tag and two operands for a machine snapshot.
+    fn encode(self) -> [u32; 3] {
+        match self {
+            EepromState::Command => [0, 0, 0],
+            EepromState::Address { command, high } => {
+                [1, command as u32, high.map_or(u32::MAX, |high| high as u32)]
+            }
+            EepromState::Read(address) => [2, address as u32, 0],
+            EepromState::Write(address) => [3, address as u32, 0],
+            EepromState::Status => [4, 0, 0],
+            EepromState::Ignore => [5, 0, 0],
+        }
+    }
+
+    fn decode([tag, a, b]: [u32; 3]) -> Result<Self> {
+        Ok(match tag {
+            0 => EepromState::Command,
+            1 => EepromState::Address {
+                command: a as u8,
+                high: Some(b as u8).filter(|_| b != u32::MAX),
+            },
+            2 => EepromState::Read(a as u16 & (Eeprom25lc256::SIZE - 1) as u16),
+            3 => EepromState::Write(a as u16 & (Eeprom25lc256::SIZE - 1) as u16),
+            4 => EepromState::Status,
+            5 => EepromState::Ignore,
+            _ => {
+                return Err(RmipsError::Snapshot(format!(
+                    "unknown 25LC256 state {}",
+                    tag
+                )))
+            }
+        })
+    }
+}
+
+/// 25LC256 EEPROM with 32KB, which are erased to `0xff`.
+///
+/// Supports the READ, WRITE, WREN, WRDI and RDSR instructions. Writes need the write enable
+/// latch to be set with WREN first, and the latch is cleared when a write completes.
+pub struct Eeprom25lc256 {
+    data: Vec<u8>,
+    state: EepromState,
+    write_enabled: bool,
+    written: bool,
+}
+
+impl Eeprom25lc256 {
+    const SIZE: usize = 0x8000;
+    const READ: u8 = 0x03;
+    const WRITE: u8 = 0x02;
+    const WRDI: u8 = 0x04;
+    const RDSR: u8 = 0x05;
+    const WREN: u8 = 0x06;
+
+    pub fn new() -> Self {
+        Self {
+            data: vec![0xff; Self::SIZE],
+            state: EepromState::Command,
+            write_enabled: false,
+            written: false,
+        }
+    }
+}
+
+impl SpiSlave for Eeprom25lc256 {
+    fn select(&mut self) {
+        self.state = EepromState::Command;
+        self.written = false;
+    }
+
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mask = (Self::SIZE - 1) as u16;
+        let (state, out) = match self.state {
+            EepromState::Command => match byte {
+                Self::READ | Self::WRITE => (
+                    EepromState::Address {
+                        command: byte,
+                        high: None,
+                    },
+                    0xff,
+                ),
+                Self::WREN => {
+                    self.write_enabled = true;
+                    (EepromState::Ignore, 0xff)
+                }
+                Self::WRDI => {
+                    self.write_enabled = false;
+                    (EepromState::Ignore, 0xff)
+                }
+                Self::RDSR => (EepromState::Status, 0xff),
+                _ => (EepromState::Ignore, 0xff),
+            },
+            EepromState::Address {
+                command,
+                high: None,
+            } => (
+                EepromState::Address {
+                    command,
+                    high: Some(byte),
+                },
+                0xff,
+            ),
+            EepromState::Address {
+                command,
+                high: Some(high),
+            } => {
+                let address = u16::from_be_bytes([high, byte]) & mask;
+                match command {
+                    Self::READ => (EepromState::Read(address), 0xff),
+                    _ if self.write_enabled => (EepromState::Write(address), 0xff),
+                    _ => (EepromState::Ignore, 0xff),
+                }
+            }
+            EepromState::Read(address) => (
+                EepromState::Read(address.wrapping_add(1) & mask),
+                self.data[address as usize],
+            ),
+            EepromState::Write(address) => {
+                self.data[address as usize] = byte;
+                self.written = true;
+                (EepromState::Write(address.wrapping_add(1) & mask), 0xff)
+            }
+            EepromState::Status => (EepromState::Status, (self.write_enabled as u8) << 1),
+            EepromState::Ignore => (EepromState::Ignore, 0xff),
+        };
+
+        self.state = state;
+        out
+    }
+
+    fn deselect(&mut self) {
+        if self.written {
+            self.write_enabled = false;
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.data);
+        for word in self.state.encode().iter() {
+            state.word(*word);
+        }
+        state.bool(self.write_enabled);
+        state.bool(self.written);
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.data.copy_from_slice(state.bytes_of_len(Self::SIZE)?);
+        self.state = EepromState::decode([state.word()?, state.word()?, state.word()?])?;
+        self.write_enabled = state.bool()?;
+        self.written = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Performs one transaction with slave 0 and returns the bytes received.
+    fn transaction(spi: &mut SpiController, bytes: &[u8]) -> Vec<u8> {
+        spi.write(SELECT, &[0, 0, 0, 0], AccessContext::CpuStore)
+            .unwrap();
+        let received = bytes
+            .iter()
+            .map(|byte| {
+                let mut data = [0];
+                spi.write(DATA, &[*byte], AccessContext::CpuStore).unwrap();
+                spi.read(DATA, &mut data, AccessContext::CpuLoad).unwrap();
+                data[0]
+            })
+            .collect();
+        spi.write(SELECT, &[0xff, 0, 0, 0], AccessContext::CpuStore)
+            .unwrap();
+        received
+    }
+
+    #[test]
+    fn spi_eeprom_read_write() {
+        let mut spi = SpiController::new(vec![SpiSlaveSpec::Eeprom25lc256.build()]);
+
+        // Writes are ignored until the write enable latch is set
+        transaction(&mut spi, &[0x02, 0x01, 0x00, 0x12]);
+        assert_eq!(
+            transaction(&mut spi, &[0x03, 0x01, 0x00, 0, 0]),
+            vec![0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+
+        transaction(&mut spi, &[0x06]);
+        assert_eq!(transaction(&mut spi, &[0x05, 0]), vec![0xff, 0x02]);
+        transaction(&mut spi, &[0x02, 0x01, 0x00, 0x12, 0x34]);
+        assert_eq!(
+            transaction(&mut spi, &[0x03, 0x01, 0x00, 0, 0, 0]),
+            vec![0xff, 0xff, 0xff, 0x12, 0x34, 0xff]
+        );
+
+        // Completing a write clears the latch
+        assert_eq!(transaction(&mut spi, &[0x05, 0]), vec![0xff, 0x00]);
+    }
+
+    #[test]
+    fn spi_save_restore() -> Result<()> {
+        let mut spi = SpiController::new(vec![SpiSlaveSpec::Eeprom25lc256.build()]);
+        transaction(&mut spi, &[0x06]);
+        transaction(&mut spi, &[0x02, 0x01, 0x00, 0x12, 0x34]);
+
+        // Save in the middle of a read
+        spi.write(SELECT, &[0, 0, 0, 0], AccessContext::CpuStore)?;
+        for byte in &[0x03, 0x01, 0x00] {
+            spi.write(DATA, &[*byte], AccessContext::CpuStore)?;
+        }
+        let mut state = StateWriter::new();
+        spi.save(&mut state);
+        let state = state.into_bytes();
+
+        let mut restored = SpiController::new(vec![SpiSlaveSpec::Eeprom25lc256.build()]);
+        let mut reader = StateReader::new(&state);
+        restored.restore(&mut reader)?;
+        reader.finish()?;
+        let mut data = [0];
+        restored.write(DATA, &[0], AccessContext::CpuStore)?;
+        restored.read(DATA, &mut data, AccessContext::CpuLoad)?;
+        assert_eq!(data, [0x12]);
+        restored.write(DATA, &[0], AccessContext::CpuStore)?;
+        restored.read(DATA, &mut data, AccessContext::CpuLoad)?;
+        assert_eq!(data, [0x34]);
+        Ok(())
+    }
+}
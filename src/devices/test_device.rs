@@ -1,26 +1,78 @@
+//! Unit test device that on-target test frameworks report their results to.
+//!
+//! The guest starts a test case by storing the virtual address of its NUL-terminated name to
+//! `CASE`, and reports every assertion by storing zero (failed) or nonzero (passed) to `ASSERT`.
+//! A message stored to `MESSAGE` first describes the next assertion. Storing to `DONE` halts the
+//! machine once all tests ran. The device cannot read guest memory itself, so it passes the
+//! events to the emulator, which reads the strings and aggregates the results into a report.
+
+use std::convert::TryInto;
+use std::sync::mpsc::{self, Receiver, Sender};
+
 use log::debug;
 
-use crate::devices::Device;
-use crate::util::error::Result;
-use crate::Address;
+use crate::devices::{AccessWidths, Device};
+use crate::memory::AccessContext;
+use crate::util::error::{Result, RmipsError};
+use crate::util::state::{StateReader, StateWriter};
+use crate::{Address, HaltReason};
 
 /// The address for the test device.
 pub const BASE_ADDRESS: Address = 0x0201_0000;
 /// Size of the test device in memory.
 pub const DATA_LEN: usize = 0x100;
 
+/// Offset of the `CASE` register. Writes start the test case with the name at the address,
+/// reads return the number of test cases started.
+pub const CASE: Address = 0x0;
+/// Offset of the `MESSAGE` register, the address of a string describing the next assertion.
+pub const MESSAGE: Address = 0x4;
+/// Offset of the `ASSERT` register. Writing zero fails an assertion and any other value passes
+/// it, reads return the number of failed assertions.
+pub const ASSERT: Address = 0x8;
+/// Offset of the `SKIP` register. Writes skip the running test case for the reason at the
+/// address.
+pub const SKIP: Address = 0xc;
+/// Offset of the `DONE` register. Any write halts the machine.
+pub const DONE: Address = 0x10;
+
+/// A result that the guest reported to the test device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TestEvent {
+    /// A test case with the name at this virtual address started.
+    Case(Address),
+    /// An assertion passed or failed, described by the message at the given virtual address.
+    Assert {
+        passed: bool,
+        message: Option<Address>,
+    },
+    /// The running test case was skipped for the reason at this virtual address.
+    Skip(Address),
+}
+
 pub struct TestDevice {
-    data: [u8; DATA_LEN],
+    cases: u32,
+    failures: u32,
+    message: Option<Address>,
+    events: Sender<TestEvent>,
 }
 
 impl TestDevice {
-    pub fn new() -> Self {
-        // Set initial device state
-        // data[0x0b] = 0x02;  // Status register in 24 hour mode
+    /// Creates the device and the channel receiving the results that the guest reports.
+    pub fn new() -> (Self, Receiver<TestEvent>) {
+        let (events, receiver) = mpsc::channel();
+        let device = Self {
+            cases: 0,
+            failures: 0,
+            message: None,
+            events,
+        };
+        (device, receiver)
+    }
 
-        Self {
-            data: [0; DATA_LEN],
-        }
+    fn send(&self, event: TestEvent) {
+        // The emulator may have stopped listening when it is shutting down
+        let _ = self.events.send(event);
     }
 }
 
@@ -29,22 +81,128 @@ impl Device for TestDevice {
         "test-device".to_owned()
     }
 
-    fn read(&mut self, address: Address, data: &mut [u8]) -> Result<()> {
-        debug!("read from test device @ 0x{:08x}", address);
+    fn access_widths(&self) -> AccessWidths {
+        AccessWidths::WORD
+    }
 
-        data[0] = self.data[0];
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        debug!("read from test device @ 0x{:08x}", address);
+        self.peek(address, data)
+    }
 
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let value = match address & !3 {
+            CASE => self.cases,
+            MESSAGE => self.message.unwrap_or(0),
+            ASSERT => self.failures,
+            _ => 0,
+        };
+        let word = value.to_le_bytes();
+        let start = (address & 3) as usize;
+        let src = word
+            .get(start..start + data.len())
+            .ok_or(RmipsError::MemoryRead(address))?;
+        data.copy_from_slice(src);
         Ok(())
     }
 
-    fn write(&mut self, address: Address, _data: &[u8]) -> Result<()> {
+    fn write(&mut self, address: Address, data: &[u8], ctx: AccessContext) -> Result<()> {
         debug!("write to test device @ 0x{:08x}", address);
 
-        // match address {
-        //     DATA_OFFSET => self.data[self.index as usize] = data[0],
-        //     addr => panic!("bad write to halt device: 0x{:08x}", addr)
-        // }
+        // Debugger writes must not report results on behalf of the guest
+        if ctx == AccessContext::Debugger {
+            return Ok(());
+        }
+
+        let value = u32::from_le_bytes(
+            data.try_into()
+                .map_err(|_| RmipsError::MemoryWrite(address))?,
+        );
+        match address {
+            CASE => {
+                self.cases += 1;
+                self.message = None;
+                self.send(TestEvent::Case(value));
+            }
+            MESSAGE => self.message = Some(value),
+            ASSERT => {
+                let passed = value != 0;
+                if !passed {
+                    self.failures += 1;
+                }
+                let message = self.message.take();
+                self.send(TestEvent::Assert { passed, message });
+            }
+            SKIP => self.send(TestEvent::Skip(value)),
+            DONE => return Err(RmipsError::Halt(HaltReason::TestsFinished)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.word(self.cases);
+        state.word(self.failures);
+        state.bool(self.message.is_some());
+        state.word(self.message.unwrap_or(0));
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.cases = state.word()?;
+        self.failures = state.word()?;
+        let has_message = state.bool()?;
+        let message = state.word()?;
+        self.message = Some(message).filter(|_| has_message);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn write(device: &mut TestDevice, address: Address, value: u32) -> Result<()> {
+        device.write(address, &value.to_le_bytes(), AccessContext::CpuStore)
+    }
+
+    #[test]
+    fn test_device_sends_results() -> Result<()> {
+        let (mut device, events) = TestDevice::new();
+        write(&mut device, CASE, 0x8000_1000)?;
+        write(&mut device, ASSERT, 1)?;
+        write(&mut device, MESSAGE, 0x8000_1010)?;
+        write(&mut device, ASSERT, 0)?;
+        write(&mut device, ASSERT, 0)?;
+        write(&mut device, SKIP, 0x8000_1020)?;
+        device.write(ASSERT, &[0; 4], AccessContext::Debugger)?;
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![
+                TestEvent::Case(0x8000_1000),
+                TestEvent::Assert {
+                    passed: true,
+                    message: None
+                },
+                TestEvent::Assert {
+                    passed: false,
+                    message: Some(0x8000_1010)
+                },
+                TestEvent::Assert {
+                    passed: false,
+                    message: None
+                },
+                TestEvent::Skip(0x8000_1020),
+            ]
+        );
 
+        let mut data = [0; 4];
+        device.read(ASSERT, &mut data, AccessContext::CpuLoad)?;
+        assert_eq!(u32::from_le_bytes(data), 2);
+        assert!(matches!(
+            write(&mut device, DONE, 0),
+            Err(RmipsError::Halt(HaltReason::TestsFinished))
+        ));
         Ok(())
     }
 }
@@ -0,0 +1,108 @@
+//! Time sources for device backends.
+//!
+//! Devices never read the host clock directly. They are handed a `TimeSource` instead, which
+//! reports the virtual time of the machine, where every executed instruction takes one
+//! microsecond as on the timeline, and a host time. Unless the emulator runs with
+//! `--host-clock`, the host time follows the virtual time as well, so devices that would
+//! naturally consult the host clock still replay deterministically. Tests can hand devices a
+//! `ManualClock` and move it by hand.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A clock that devices read the time from.
+pub trait TimeSource: Send + Sync {
+    /// Returns the time the machine has run for.
+    fn virtual_time(&self) -> Duration;
+    /// Returns the monotonic host time since the machine was created, or the virtual time if
+    /// the host clock is not used.
+    fn host_time(&self) -> Duration;
+}
+
+/// The clock of an emulator, advanced by the instructions it executes.
+#[derive(Debug)]
+pub struct MachineClock {
+    instructions: AtomicU64,
+    /// When the machine was created, if devices see the host clock.
+    host_start: Option<Instant>,
+}
+
+impl MachineClock {
+    /// Creates a clock at time zero that reports the host time if `host_clock` is set.
+    pub fn new(host_clock: bool) -> Self {
+        Self {
+            instructions: AtomicU64::new(0),
+            host_start: host_clock.then(Instant::now),
+        }
+    }
+
+    /// Moves the virtual time to `instructions` executed instructions.
+    pub(crate) fn set_instructions(&self, instructions: u64) {
+        self.instructions.store(instructions, Ordering::Relaxed);
+    }
+}
+
+impl TimeSource for MachineClock {
+    fn virtual_time(&self) -> Duration {
+        Duration::from_micros(self.instructions.load(Ordering::Relaxed))
+    }
+
+    fn host_time(&self) -> Duration {
+        match self.host_start {
+            Some(start) => start.elapsed(),
+            None => self.virtual_time(),
+        }
+    }
+}
+
+/// A clock that only moves when it is advanced, for testing devices.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    virtual_micros: AtomicU64,
+    host_micros: AtomicU64,
+}
+
+impl ManualClock {
+    /// Advances the virtual and host time together.
+    pub fn advance(&self, duration: Duration) {
+        let micros = duration.as_micros() as u64;
+        self.virtual_micros.fetch_add(micros, Ordering::Relaxed);
+        self.host_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// Advances only the host time, like a host that stalls the emulator.
+    pub fn advance_host(&self, duration: Duration) {
+        let micros = duration.as_micros() as u64;
+        self.host_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+}
+
+impl TimeSource for ManualClock {
+    fn virtual_time(&self) -> Duration {
+        Duration::from_micros(self.virtual_micros.load(Ordering::Relaxed))
+    }
+
+    fn host_time(&self) -> Duration {
+        Duration::from_micros(self.host_micros.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn time_sources() {
+        let clock = MachineClock::new(false);
+        clock.set_instructions(2500);
+        assert_eq!(clock.virtual_time(), Duration::from_micros(2500));
+        assert_eq!(clock.host_time(), Duration::from_micros(2500));
+
+        let clock = ManualClock::default();
+        clock.advance(Duration::from_millis(2));
+        clock.advance_host(Duration::from_millis(3));
+        assert_eq!(clock.virtual_time(), Duration::from_millis(2));
+        assert_eq!(clock.host_time(), Duration::from_millis(5));
+    }
+}
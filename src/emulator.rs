@@ -1,34 +1,222 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
 use std::net::{TcpListener, TcpStream};
-use std::time::Instant;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use gdbstub::GdbStub;
-use log::{error, info};
+use gdbstub::stub::GdbStub;
+use log::{error, info, warn};
 
-use crate::control::cpu::Cpu;
-use crate::control::KSEG1;
+use crate::asm;
+use crate::blocks::BlockProfile;
+pub use crate::config::Config;
+use crate::console::{Console, ConsoleCommand};
+use crate::control::cp1::Fpu;
+use crate::control::cpu::{Cpu, DelayState, InterruptLatency};
+use crate::control::cpzero::{CPZero, Translation};
+use crate::control::explain::{self, CpuSnapshot};
+use crate::control::instruction::Instruction;
+use crate::control::invariants;
+use crate::control::registers::Register;
+use crate::control::verify::DecodeVerifier;
+use crate::control::{KSEG0, KSEG1, KSEG_SELECT_MASK};
+use crate::coverage::InstructionCoverage;
+use crate::determinism::InstructionStream;
+use crate::devices::debug_print;
+use crate::devices::emulator_info;
 use crate::devices::halt_device;
-use crate::devices::test_device;
-use crate::memory::bus::Bus;
-use crate::memory::monitor::{AccessKind, Monitor};
-use crate::memory::ram::Ram;
+use crate::devices::i2c;
+use crate::devices::keyboard;
+use crate::devices::leds;
+use crate::devices::network;
+use crate::devices::nvram;
+use crate::devices::prom::{self, PromCall};
+use crate::devices::reset_device;
+use crate::devices::serial_link;
+use crate::devices::shared_memory;
+use crate::devices::spi;
+use crate::devices::test_device::{self, TestEvent};
+use crate::devices::time::{MachineClock, TimeSource};
+use crate::devices::Device;
+use crate::gdb::{self, BufferedConnection, FaultedAccess, GdbEventLoop, ThreadResume};
+use crate::governor::{self, Governor};
+use crate::heap::HeapTracker;
+use crate::inject::{InputAction, InputScript};
+use crate::inspect::{Inspector, Publisher, StateSample};
+use crate::intctrl::IntCtrl;
+use crate::lint::Linter;
+use crate::memory::bus::{Alias, Bus};
+use crate::memory::faults::FaultInjector;
+use crate::memory::heatmap::Heatmap;
+use crate::memory::monitor::{Access, AccessKind, WatchRegion};
+use crate::memory::ram::{Ram, SparseRam};
+use crate::memory::range::Range;
 use crate::memory::rom::Rom;
+use crate::memory::AccessContext;
+use crate::regmap::RegisterMap;
+use crate::session::DebugSession;
+use crate::shadow_stack::{self, ShadowStack, ShadowStackMode};
+use crate::snapshot::{Snapshot, SNAPSHOT_MAGIC, SNAPSHOT_VERSION};
+use crate::test_report::TestReport;
+use crate::timeline::Timeline;
+use crate::trace::{TraceStep, Tracer};
 use crate::util::error::{Result, RmipsError};
-use crate::util::opts::Opts;
-use crate::{Address, EmulationEvent, Endian};
+use crate::util::opts::{parse_address, Opts, RamBase, RtosLayout, StopAt};
+use crate::util::rng::XorShift;
+use crate::util::signals;
+use crate::util::state::{StateReader, StateWriter};
+use crate::watch::WatchExpr;
+use crate::{Address, DeviceRequest, EmulationEvent, Endian, HaltReason, ResetReason};
+
+/// A device mapped into the physical address space.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryMapEntry {
+    pub base: Address,
+    pub size: usize,
+    pub label: String,
+}
+
+/// The outcome of running the emulator until it stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunSummary {
+    pub halt_reason: HaltReason,
+    /// Number of instructions executed, not counting PROM services.
+    pub instructions: u64,
+    /// Number of cycles elapsed. Every instruction takes one cycle, since the pipeline is not
+    /// modelled.
+    pub cycles: u64,
+    /// Exit status of the guest, if it requested the halt.
+    pub exit_code: Option<i32>,
+    /// Latency of the interrupts taken, if there were any.
+    pub interrupt_latency: Option<InterruptLatency>,
+}
+
+impl RunSummary {
+    fn new(halt_reason: HaltReason, instructions: usize) -> Self {
+        Self {
+            halt_reason,
+            instructions: instructions as u64,
+            cycles: instructions as u64,
+            exit_code: halt_reason.exit_code(),
+            interrupt_latency: None,
+        }
+    }
+}
+
+/// Limit on the work that one call to `Emulator::run_slice` does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SliceBudget {
+    /// Execute at most this many instructions.
+    Instructions(u64),
+    /// Return once this much host time has passed. The time is checked every
+    /// `SLICE_CLOCK_INTERVAL` instructions, so a slice may overrun it slightly.
+    HostTime(Duration),
+}
+
+/// Number of instructions between checks of the host time budget of a slice.
+pub const SLICE_CLOCK_INTERVAL: usize = 256;
+
+/// Longest string, without its NUL, that the emulator reads from guest memory for the guest.
+pub const MAX_STRING_LEN: usize = 0x1000;
+
+/// How a call to `Emulator::run_slice` ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SliceOutcome {
+    /// The budget was used up. The next slice continues where this one stopped.
+    Yielded,
+    /// The machine stopped, with the summary that `Emulator::run` would have returned.
+    Halted(RunSummary),
+}
+
+/// Guest memory overwritten by `Emulator::patch`, which `Emulator::revert` restores.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatchHandle {
+    address: Address,
+    original: Vec<u8>,
+}
+
+impl PatchHandle {
+    /// Returns the virtual address of the first patched byte.
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Returns the bytes that the patch replaced.
+    pub fn original(&self) -> &[u8] {
+        &self.original
+    }
+}
 
 pub struct Emulator {
     pub cpu: Cpu,
     pub(crate) bus: Bus,
     pub(crate) breakpoints: Vec<Address>,
-    pub(crate) watchpoints: Vec<Address>,
+    /// The last GDB memory access that faulted, shown by `monitor fault`.
+    pub(crate) gdb_fault: Option<FaultedAccess>,
+    /// Where the guest kernel keeps its tasks, which GDB shows as threads.
+    pub(crate) rtos: Option<RtosLayout>,
+    /// How GDB asked to resume the CPU in the next multi-threaded resume.
+    pub(crate) thread_resume: Option<ThreadResume>,
+    watches: Vec<WatchExpr>,
+    /// Names of the device registers from `--regmap`.
+    regmap: Option<RegisterMap>,
+    /// The breakpoints, watchpoints and patches saved to `--debug-session` when the run ends.
+    pub(crate) session: Option<DebugSession>,
+    profile: Option<BlockProfile>,
+    coverage: Option<InstructionCoverage>,
+    shadow_stack: Option<ShadowStack>,
+    heap: Option<HeapTracker>,
+    lint: Option<Linter>,
+    verify_decode: Option<DecodeVerifier>,
+    timeline: Option<Timeline>,
+    /// Hardware interrupt lines that the CPU saw at the last instruction recorded on the
+    /// timeline, with IP2 in bit 0.
+    irq_lines: u8,
+    trace: Option<Tracer>,
+    /// Addresses of the strings stored to the debug print device.
+    debug_prints: Option<Receiver<Address>>,
+    /// Hash of the executed instructions while `--verify-determinism` compares runs.
+    pub(crate) stream: Option<InstructionStream>,
+    /// Results that the guest reported to the test device.
+    test_events: Receiver<TestEvent>,
+    test_report: TestReport,
+    /// Throttles or pauses the run loop under host resource pressure.
+    governor: Option<Governor>,
+    /// Escape sequences typed on the standard input console.
+    console: Option<Console>,
+    /// External inputs replayed from the `--inject` script.
+    inputs: Option<InputScript>,
+    /// Receives the scripted serial bytes in the serial link.
+    serial_input: Option<Sender<u8>>,
+    /// Publishes samples of the state for other threads.
+    publisher: Option<Publisher>,
     instruction_count: usize,
+    /// Time source of the devices, which follows `instruction_count`.
+    clock: Arc<MachineClock>,
+    /// Number of instructions to stop after when replaying a run for `--rewind`.
+    replay_limit: Option<usize>,
+    /// Machine state when the emulator was created, which `--rewind` replays the run from.
+    start_state: Option<Vec<u8>>,
+    /// Physical base address of the main RAM module.
+    ram_base: Address,
     start_time: Instant,
     opts: Opts,
 }
 
 impl Emulator {
+    /// Validates `opts` and creates an emulator from them.
     pub fn new(opts: Opts) -> Result<Emulator> {
-        let _endian = match opts.bigendian {
+        Self::with_config(Config::validate(opts)?)
+    }
+
+    /// Creates an emulator from a validated configuration.
+    pub fn with_config(config: Config) -> Result<Emulator> {
+        for warning in config.warnings() {
+            println!("Warning: {}", warning);
+        }
+        let opts = config.into_opts();
+        let endian = match opts.bigendian {
             true => {
                 println!("Interpreting ROM file as Big-Endian");
                 Endian::Big
@@ -40,42 +228,192 @@ impl Emulator {
         };
 
         // Setup the different machine components
-        // let intc = IntCtrl::new();
         let mut bus = Bus::new();
+        bus.set_endian(endian);
+        for region in &opts.byteswap {
+            println!(
+                "Swapping byte lanes of the devices in physical region 0x{:08x}-0x{:08x}",
+                region.address,
+                region.address as u64 + region.len as u64 - 1
+            );
+            bus.byte_swapped
+                .push(Range::new(region.address, region.len));
+        }
+
+        let inputs = load_input_script(&opts)?;
+        let regmap = load_register_map(&opts)?;
+        let session = load_debug_session(&opts)?;
+        let watches = resolve_watches(&opts.watch, regmap.as_ref())?;
+        let scripted_serial = inputs.as_ref().is_some_and(InputScript::has_serial_input);
 
         // Setup and connect the various devices
-        setup_rom(&opts, &mut bus)?;
-        setup_ram(&opts, &mut bus)?;
+        let clock = Arc::new(MachineClock::new(opts.hostclock));
+        let labels = setup_rom(&opts, &mut bus)?;
         setup_haltdevice(&opts, &mut bus)?;
-        // setup_clock()?;
-        setup_testdevice(&mut bus)?;
+        setup_resetdevice(&opts, &mut bus)?;
+        setup_nvram(&opts, &mut bus)?;
+        setup_shared_memory(&opts, &mut bus)?;
+        setup_network(&opts, &mut bus)?;
+        let serial_input = setup_serial_link(&opts, &mut bus, scripted_serial)?;
+        setup_i2c(&opts, &mut bus)?;
+        setup_spi(&opts, &mut bus)?;
+        let console = setup_keyboard(&opts, &mut bus)?;
+        setup_leds(&opts, &mut bus)?;
+        let debug_prints = setup_debug_print(&opts, &mut bus)?;
+        setup_prom(&opts, &mut bus)?;
+        setup_emulator_info(&opts, &mut bus, clock.clone())?;
+        setup_clock(&opts, &mut bus, clock.clone())?;
+        let test_events = setup_testdevice(&opts, &mut bus)?;
+        // RAM is mapped last so that a random base can avoid the other devices
+        let ram_base = setup_ram(&opts, &mut bus)?;
+        setup_aliases(&opts, &mut bus)?;
+
+        if opts.explain {
+            bus.stores.enable();
+        }
+        if opts.trace.is_some() {
+            bus.data_accesses.enable();
+        }
+        if opts.timeline.is_some() {
+            bus.device_accesses.enable();
+            bus.invalidations = Some(Vec::new());
+        }
+        if opts.heatmap.is_some() {
+            bus.heatmap = Some(Heatmap::default());
+        }
+        if opts.faultrate > 0 {
+            println!(
+                "Injecting {:?} faults into {} of every million accesses",
+                opts.faultkind, opts.faultrate
+            );
+            bus.faults = Some(FaultInjector::new(
+                opts.faultrate,
+                opts.faultkind,
+                opts.faultseed,
+            ));
+        }
+
+        let tlb_entries = opts
+            .tlbentries
+            .unwrap_or_else(|| opts.cpumodel.tlb_entries());
 
         let mut cpu = Cpu::new(opts.instrdump);
+        cpu.cpzero = CPZero::with_model(opts.cpumodel, tlb_entries);
+        cpu.privilege_errors = opts.privilegeerrors;
         cpu.reset();
+        if !opts.nofpu {
+            cpu.attach_coprocessor(1, Box::new(Fpu::new()));
+        }
+        if opts.nommu {
+            cpu.cpzero.translation = Translation::Identity;
+            cpu.pc = opts.loadaddress;
+        }
+
+        for region in &opts.breakonaccess {
+            bus.watchpoints.add_region(WatchRegion {
+                range: Range::new(option_address(&cpu, region.address), region.len),
+                vaddress: region.address,
+                read: region.read,
+                write: region.write,
+            });
+        }
+        for guard in &opts.guard {
+            let paddress = option_address(&cpu, guard.address);
+            println!(
+                "Guarding physical region 0x{:08x}-0x{:08x}",
+                paddress,
+                paddress as u64 + guard.len as u64 - 1
+            );
+            bus.guards.push(Range::new(paddress, guard.len));
+        }
+
+        let trace = match &opts.trace {
+            Some(path) => Some(Tracer::create(path, &cpu)?),
+            None => None,
+        };
 
-        Ok(Self {
+        let mut emulator = Self {
             cpu,
             bus,
             breakpoints: Default::default(),
-            watchpoints: Default::default(),
+            gdb_fault: None,
+            rtos: opts.rtos,
+            thread_resume: None,
+            watches,
+            regmap,
+            session,
+            profile: opts.blockprofile.as_ref().map(|_| BlockProfile::default()),
+            coverage: match opts.coverage {
+                true => Some(InstructionCoverage::default()),
+                false => None,
+            },
+            shadow_stack: opts.shadowstack.map(|_| ShadowStack::default()),
+            heap: match (opts.malloc, opts.free) {
+                (Some(malloc), Some(free)) => Some(HeapTracker::new(malloc, free)),
+                _ => None,
+            },
+            lint: match opts.lint {
+                true => Some(Linter::default()),
+                false => None,
+            },
+            verify_decode: match opts.verifydecode {
+                true => Some(DecodeVerifier::default()),
+                false => None,
+            },
+            timeline: opts.timeline.as_ref().map(|_| Timeline::default()),
+            irq_lines: 0,
+            trace,
+            debug_prints,
+            stream: None,
+            test_events,
+            test_report: TestReport::default(),
+            governor: match (opts.cpuquota, opts.minhostmemory) {
+                (None, None) => None,
+                (quota, memory) => Some(Governor::new(quota, memory.map(|mb| mb << 20))),
+            },
+            console,
+            inputs,
+            serial_input,
+            publisher: None,
             instruction_count: 0,
+            clock,
+            replay_limit: None,
+            start_state: None,
+            ram_base,
             start_time: Instant::now(),
             opts,
-        })
+        };
+
+        emulator.apply_patches(&labels)?;
+        if let Some(path) = &emulator.opts.loadsnapshot {
+            let path = path.clone();
+            emulator.load_snapshot(&path)?;
+        }
+        if emulator.opts.rewind.is_some() {
+            emulator.start_state = Some(emulator.machine_state());
+        }
+
+        if emulator.opts.memmap {
+            println!("\n{}", emulator.memory_map_summary());
+        }
+        Ok(emulator)
     }
 
-    pub fn run(&mut self) -> Result<()> {
+    pub fn run(&mut self) -> Result<RunSummary> {
         println!("\n*************[ RESET ]*************\n");
 
         // Save the current start time
         self.start_time = Instant::now();
 
         // Optionally start the GDB server before the program
-        if self.opts.debug {
+        let summary = if self.opts.debug {
             let connection = wait_for_tcp(&self.opts.debugip, self.opts.debugport)?;
-            let mut debugger = GdbStub::new(connection);
+            let debugger = GdbStub::builder(BufferedConnection::new(connection))
+                .packet_buffer_size(gdb::PACKET_SIZE)
+                .build()
+                .expect("the packet buffer is allocated by the stub");
 
-            match debugger.run(self) {
+            match debugger.run_blocking::<GdbEventLoop>(self) {
                 Ok(reason) => {
                     info!("GDB session closed: {:?}", reason);
                 }
@@ -85,136 +423,1949 @@ impl Emulator {
             }
 
             // Resume execution when the GDB session is disconnected
-            if let Err(err) = self.run_until_halt() {
+            self.run_until_halt().map_err(|err| {
                 error!("Failed to resume emulation after GDB disconnected: {}", err);
-            }
+                err
+            })?
         } else {
-            self.run_until_halt()?;
+            self.run_until_halt()?
+        };
+        self.conclude(summary)
+    }
+
+    /// Runs the guest for at most `budget` and returns, so that GUI event loops and other hosts
+    /// that cannot block in `run` can interleave the emulator with their own work.
+    ///
+    /// Slices end between two instructions, where the complete CPU state including a pending
+    /// branch delay slot is kept in the `Cpu`, so a guest run in slices executes exactly like
+    /// one `run`. The GDB stub is not started. When the machine stops the output files of `run`
+    /// are written and its summary is returned.
+    pub fn run_slice(&mut self, budget: SliceBudget) -> Result<SliceOutcome> {
+        let start = Instant::now();
+        let first = self.instruction_count;
+        loop {
+            let executed = self.instruction_count - first;
+            let exhausted = match budget {
+                SliceBudget::Instructions(limit) => executed as u64 >= limit,
+                SliceBudget::HostTime(limit) => {
+                    executed > 0
+                        && executed.is_multiple_of(SLICE_CLOCK_INTERVAL)
+                        && start.elapsed() >= limit
+                }
+            };
+            if exhausted {
+                return Ok(SliceOutcome::Yielded);
+            }
+
+            if let Some(reason) = self.next_halt()? {
+                self.report_halt(reason);
+                let summary = RunSummary::new(reason, self.instruction_count);
+                return Ok(SliceOutcome::Halted(self.conclude(summary)?));
+            }
+        }
+    }
+
+    /// Finishes a run that stopped with `summary`, rewinding it if requested and writing the
+    /// dumps, reports and profiles of the run.
+    fn conclude(&mut self, mut summary: RunSummary) -> Result<RunSummary> {
+        if let (HaltReason::StopAt, Some(rewind)) = (summary.halt_reason, self.opts.rewind) {
+            summary = self.replay(summary.instructions.saturating_sub(rewind) as usize)?;
+        }
+
+        if let (HaltReason::Signal(_), Some(path)) = (summary.halt_reason, &self.opts.signaldump) {
+            std::fs::write(path, self.crashdump())?;
+            println!("Wrote crash dump to {}", path);
+        }
+        if let Some(path) = &self.opts.ramdump {
+            self.dump_ram(path)?;
+        }
+        if let Some(path) = self.opts.savesnapshot.clone() {
+            self.save_snapshot(&path)?;
+        }
+        if let Some(latency) = self.cpu.interrupt_latency {
+            println!("{}", latency);
+            summary.interrupt_latency = Some(latency);
         }
+        if let Some(heap) = &self.heap {
+            println!("{}", heap.report());
+        }
+        if let Some(faults) = &self.bus.faults {
+            println!("{}", faults);
+        }
+        if let (Some(path), Some(profile)) = (&self.opts.blockprofile, &self.profile) {
+            std::fs::write(path, profile.to_json())?;
+            println!(
+                "Wrote block profile ({} blocks) to {}",
+                profile.block_count(),
+                path
+            );
+        }
+        if let Some(coverage) = &self.coverage {
+            println!("{}", coverage);
+        }
+        if !self.test_report.is_empty() {
+            println!("{}", self.test_report);
+            if let Some(path) = &self.opts.testreport {
+                let contents = match path.ends_with(".xml") {
+                    true => self.test_report.to_junit(),
+                    false => self.test_report.to_tap(),
+                };
+                std::fs::write(path, contents)?;
+                println!(
+                    "Wrote test report ({} test cases) to {}",
+                    self.test_report.cases().len(),
+                    path
+                );
+            }
+
+            // Failed tests fail the run, unless the guest already exited with an error
+            if self.test_report.failed() > 0 && summary.exit_code.unwrap_or(0) == 0 {
+                summary.exit_code = Some(1);
+            }
+        }
+        if let (Some(path), Some(session)) = (&self.opts.debugsession, &self.session) {
+            std::fs::write(path, session.to_string())?;
+            println!(
+                "Saved {} breakpoints, {} watchpoints and {} patches to {}",
+                session.breakpoints.len(),
+                session.watchpoints.len(),
+                session.patches.len(),
+                path
+            );
+        }
+        if let (Some(path), Some(heatmap)) = (&self.opts.heatmap, &self.bus.heatmap) {
+            let label = |page| match self.bus.get_device(page) {
+                Some((_, dev)) => dev.debug_label(),
+                None => "unmapped".to_owned(),
+            };
+            let contents = match path.ends_with(".csv") {
+                true => heatmap.to_csv(&label),
+                false => heatmap.to_json(&label),
+            };
+            std::fs::write(path, contents)?;
+            println!(
+                "Wrote heatmap ({} pages) to {}",
+                heatmap.pages().len(),
+                path
+            );
+        }
+        if let (Some(path), Some(trace)) = (&self.opts.trace, &mut self.trace) {
+            println!("Wrote trace ({} steps) to {}", trace.finish()?, path);
+        }
+        if let (Some(path), Some(timeline)) = (&self.opts.timeline, &self.timeline) {
+            std::fs::write(path, timeline.to_json())?;
+            println!(
+                "Wrote timeline ({} events) to {}",
+                timeline.event_count(),
+                path
+            );
+        }
+
+        Ok(summary)
+    }
 
+    /// Returns the time source that the devices of this emulator read the time from.
+    pub fn clock(&self) -> Arc<dyn TimeSource> {
+        self.clock.clone()
+    }
+
+    /// Returns the instructions executed so far when coverage is enabled with `--coverage`.
+    pub fn coverage(&self) -> Option<&InstructionCoverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Writes the contents of the main RAM module to the file at `path`.
+    pub fn dump_ram(&self, path: &str) -> Result<()> {
+        // Sparse RAM has no contiguous storage, so the contents are always copied out
+        let mut ram = Vec::new();
+        if let Some((range, dev)) = self.bus.get_device(self.ram_base) {
+            ram.resize(range.size(), 0);
+            dev.peek(0, &mut ram)?;
+        }
+
+        std::fs::write(path, &ram)?;
+        println!("Wrote RAM contents ({} bytes) to {}", ram.len(), path);
         Ok(())
     }
 
     // Steps the `Cpu` state until a halt event is triggered.
-    fn run_until_halt(&mut self) -> Result<()> {
+    fn run_until_halt(&mut self) -> Result<RunSummary> {
         loop {
-            if self.step()? == EmulationEvent::Halted {
-                let elapsed = self.start_time.elapsed().as_secs_f64();
-                let instr_per_second = self.instruction_count as f64 / elapsed;
-                println!(
-                    "Executed {} instructions in {:.5} seconds ({:.3} instructions per second)",
-                    self.instruction_count, elapsed, instr_per_second
-                );
+            if let Some(reason) = self.next_halt()? {
+                self.report_halt(reason);
+                return Ok(RunSummary::new(reason, self.instruction_count));
+            }
+        }
+    }
+
+    /// Executes the next instruction of a run, unless the run stops before it. Returns the
+    /// reason when the run stops.
+    fn next_halt(&mut self) -> Result<Option<HaltReason>> {
+        if self.governor.is_some()
+            && self
+                .instruction_count
+                .is_multiple_of(governor::CHECK_INTERVAL)
+        {
+            self.regulate();
+        }
+
+        let reason = match self.stop_condition() {
+            Some(reason) => reason,
+            None if self.console_command() == Some(ConsoleCommand::Quit) => HaltReason::Quit,
+            None => match self.step()? {
+                EmulationEvent::Watch { address, .. } => HaltReason::Watchpoint(address),
+                EmulationEvent::WatchExpression(index) => HaltReason::WatchExpression(index),
+                EmulationEvent::Halted(reason) => reason,
+                EmulationEvent::LimitReached => HaltReason::StopAt,
+                _ => return Ok(None),
+            },
+        };
+        Ok(Some(reason))
+    }
 
-                println!("\n*************[ HALT ]*************\n");
-                break;
+    /// Prints why a run stopped, with statistics when the machine halted.
+    fn report_halt(&self, reason: HaltReason) {
+        if let HaltReason::Watchpoint(_) | HaltReason::WatchExpression(_) | HaltReason::StopAt =
+            reason
+        {
+            println!("{}", self.cpu);
+            println!("\n*************[ BREAK ]*************\n");
+        } else {
+            match reason {
+                HaltReason::Signal(signal) => {
+                    println!("Stopped by signal {} at PC=0x{:08x}", signal, self.cpu.pc)
+                }
+                HaltReason::GuardRegion(address) => println!(
+                    "Stopped by an access to the guard region at 0x{:08x} at PC=0x{:08x}",
+                    address, self.cpu.pc
+                ),
+                _ => {}
             }
+            let elapsed = self.start_time.elapsed().as_secs_f64();
+            let instr_per_second = self.instruction_count as f64 / elapsed;
+            println!(
+                "Executed {} instructions in {:.5} seconds ({:.3} instructions per second)",
+                self.instruction_count, elapsed, instr_per_second
+            );
+
+            println!("\n*************[ HALT ]*************\n");
+        }
+    }
+
+    /// Returns `StopAt` if the next instruction should not be executed because the `--stop-at`
+    /// condition was reached, or `Signal` if a shutdown was requested.
+    fn stop_condition(&self) -> Option<HaltReason> {
+        if let Some(signal) = signals::pending() {
+            return Some(HaltReason::Signal(signal));
+        }
+
+        // A replay ends at its instruction limit instead, which `step` reports
+        let reached = match (self.replay_limit, self.opts.stopat) {
+            (None, Some(StopAt::Pc(pc))) => self.cpu.pc == pc,
+            _ => false,
+        };
+        reached.then_some(HaltReason::StopAt)
+    }
+
+    /// Returns a handle that other threads can use to sample the registers, the instruction count
+    /// and the memory words at the virtual addresses in `words` while the emulator runs. A new
+    /// sample is published every `interval` instructions, starting with the current state.
+    pub fn inspector(&mut self, words: Vec<Address>, interval: usize) -> Inspector {
+        let publisher = Publisher::new(words, interval);
+        let inspector = publisher.inspector();
+        self.publisher = Some(publisher);
+        self.publish_state();
+        inspector
+    }
+
+    /// Returns a handle to the interrupt controller, which other threads and the embedding
+    /// application can use to raise and lower the hardware interrupt lines of the running guest.
+    pub fn interrupt_controller(&self) -> IntCtrl {
+        self.bus.intctrl.clone()
+    }
+
+    /// Publishes a sample of the current state to the inspectors.
+    fn publish_state(&self) {
+        let Some(publisher) = &self.publisher else {
+            return;
+        };
+
+        let words = publisher
+            .words
+            .iter()
+            .map(|&address| {
+                let mut word = [0; 4];
+                let value = self
+                    .translate(address)
+                    .and_then(|paddress| self.bus.peek(paddress, &mut word))
+                    .ok()
+                    .map(|_| self.bus.endian().word(word));
+                (address, value)
+            })
+            .collect();
+        publisher.publish(StateSample {
+            pc: self.cpu.pc,
+            reg: self.cpu.reg,
+            high: self.cpu.high,
+            low: self.cpu.low,
+            instruction_count: self.instruction_count,
+            words,
+        });
+    }
+
+    /// Lets the governor throttle or pause the run loop, and reports the pauses.
+    fn regulate(&mut self) {
+        let Some(governor) = &mut self.governor else {
+            return;
+        };
+
+        let mut events = Vec::new();
+        governor.regulate(
+            |event| {
+                println!("{}", event);
+                events.push(event);
+            },
+            || signals::pending().is_some(),
+        );
+        for event in events {
+            self.record_event("governor", event.to_string(), Vec::new());
         }
+    }
+
+    /// Handles the commands typed on the console and returns the last one.
+    fn console_command(&self) -> Option<ConsoleCommand> {
+        let command = self.console.as_ref()?.command()?;
+        if command == ConsoleCommand::Monitor {
+            println!("\n{}\n", self.crashdump());
+        }
+        Some(command)
+    }
+
+    /// Restores the machine state saved when the emulator was created and runs the first
+    /// `instructions` instructions again.
+    ///
+    /// Execution is deterministic apart from host input, so the replay ends in the same state
+    /// the original run had after that many instructions. The devices keep their host files,
+    /// sockets and the console open, and the records of the run, such as the coverage and the
+    /// timeline, start over to describe the replay.
+    fn replay(&mut self, instructions: usize) -> Result<RunSummary> {
+        println!("Replaying the first {} instructions", instructions);
+
+        let state = self
+            .start_state
+            .take()
+            .expect("the start of the run is saved for --rewind");
+        let restored = self.restore_machine_state(&state);
+        self.start_state = Some(state);
+        restored?;
+        self.restart_records()?;
+        self.replay_limit = Some(instructions);
+
+        println!("\n*************[ RESET ]*************\n");
+        self.run_until_halt()
+    }
 
+    /// Clears what the emulator recorded about the run so far, and the input script and fault
+    /// injection, which follow the instruction count.
+    fn restart_records(&mut self) -> Result<()> {
+        if let Some(inputs) = &mut self.inputs {
+            inputs.rewind();
+        }
+        if let Some(faults) = &mut self.bus.faults {
+            *faults = FaultInjector::new(
+                self.opts.faultrate,
+                self.opts.faultkind,
+                self.opts.faultseed,
+            );
+        }
+        if let Some(heatmap) = &mut self.bus.heatmap {
+            *heatmap = Heatmap::default();
+        }
+        if let Some(path) = &self.opts.trace {
+            self.trace = Some(Tracer::create(path, &self.cpu)?);
+        }
+        if let (Some(malloc), Some(free)) = (self.opts.malloc, self.opts.free) {
+            self.heap = Some(HeapTracker::new(malloc, free));
+        }
+        while self.test_events.try_recv().is_ok() {}
+        self.test_report = TestReport::default();
+        self.profile = self.profile.as_ref().map(|_| BlockProfile::default());
+        self.coverage = self
+            .coverage
+            .as_ref()
+            .map(|_| InstructionCoverage::default());
+        self.shadow_stack = self.shadow_stack.as_ref().map(|_| ShadowStack::default());
+        self.lint = self.lint.as_ref().map(|_| Linter::default());
+        self.verify_decode = self
+            .verify_decode
+            .as_ref()
+            .map(|_| DecodeVerifier::default());
+        self.timeline = self.timeline.as_ref().map(|_| Timeline::default());
+        self.irq_lines = 0;
+        self.cpu.reset_interrupt_latency();
         Ok(())
     }
 
+    /// Executes the next instruction and returns the most significant event it caused.
     pub fn step(&mut self) -> Result<EmulationEvent> {
-        let mut hit_watchpoint = None;
+        if matches!(self.replay_limit, Some(limit) if self.instruction_count >= limit) {
+            return Ok(EmulationEvent::LimitReached);
+        }
+        if self.inputs.is_some() {
+            self.apply_inputs();
+        }
 
-        let mut monitor = Monitor::new(&mut self.bus, &self.watchpoints, |access| {
-            hit_watchpoint = Some(access)
-        });
+        let pc = self.cpu.pc;
+        let delay_state = self.cpu.delay_state;
+        let snapshot = match self.opts.explain {
+            true => Some(CpuSnapshot::capture(&self.cpu)),
+            false => None,
+        };
+        if let Some(trace) = &mut self.trace {
+            trace.begin(&self.cpu);
+        }
+
+        // Firmware calls are serviced in place of the PROM entry point stubs
+        let call = self.prom_call();
+        if call.is_none() {
+            self.check_heap(pc);
+            self.lint_instruction(pc);
+            self.verify_decode(pc);
+        }
+        let result = match call {
+            Some(call) => self.prom_service(call),
+            None => self.cpu.step(&mut self.bus),
+        };
+
+        // Debug builds stop at the step that corrupted the state with an internal error report
+        if cfg!(debug_assertions) && result.is_ok() {
+            if let Some(violation) = invariants::check(&self.cpu, pc, delay_state) {
+                return Err(RmipsError::Invariant(Box::new(violation)));
+            }
+        }
+
+        if let (Some(profile), None, Ok(())) = (&mut self.profile, call, &result) {
+            profile.record(pc, self.cpu.instruction, self.cpu.pc);
+        }
+        if let (Some(coverage), None, Ok(())) = (&mut self.coverage, call, &result) {
+            coverage.record(self.cpu.instruction);
+        }
+        if let (Some(stream), None, Ok(())) = (&mut self.stream, call, &result) {
+            stream.record(pc, self.cpu.instruction);
+        }
+        if self.trace.is_some() {
+            self.trace_step(pc, call, &result)?;
+        }
+        if let (Some(stack), None, Ok(())) = (&mut self.shadow_stack, call, &result) {
+            // A taken branch or jump leaves the next instruction in its delay slot
+            let target = match self.cpu.delay_state {
+                DelayState::Delayslot => Some(self.cpu.delay_pc),
+                _ => None,
+            };
+
+            if let Some(mismatch) = stack.check(pc, self.cpu.instruction, target) {
+                match self.opts.shadowstack {
+                    Some(ShadowStackMode::Stop) => return Err(RmipsError::ShadowStack(mismatch)),
+                    _ => println!("Warning: {}", mismatch),
+                }
+            }
+        }
+
+        if let Some(snapshot) = snapshot {
+            self.explain(&snapshot, call);
+        }
+        if self.timeline.is_some() {
+            self.record_step_events(pc, call);
+        }
+        let debug_print = match self.debug_prints {
+            Some(_) => self.print_debug_strings()?,
+            None => None,
+        };
+        self.collect_test_results();
 
         // Step the `Cpu` until a halt is triggered
-        if let Err(err) = self.cpu.step(&mut monitor) {
+        if let Err(err) = result {
             match err {
-                RmipsError::Halt => return Ok(EmulationEvent::Halted),
+                RmipsError::Halt(reason) => {
+                    self.record_event("halt", "Halt".to_owned(), Vec::new());
+                    return Ok(EmulationEvent::Halted(reason));
+                }
+                // Guard regions exist to catch stray accesses, so stop at the offending
+                // instruction instead of letting the program handle a bus error
+                RmipsError::GuardRegion(address) => {
+                    self.record_event("halt", err.to_string(), Vec::new());
+                    return Ok(EmulationEvent::Halted(HaltReason::GuardRegion(address)));
+                }
+                RmipsError::Reset => return Ok(self.reset(ResetReason::ResetDevice)),
                 _ => return Err(err),
             }
         }
 
         self.instruction_count += 1;
+        self.clock.set_instructions(self.instruction_count as u64);
+        let count = self.instruction_count;
+        if matches!(&self.publisher, Some(publisher) if count.is_multiple_of(publisher.interval)) {
+            self.publish_state();
+        }
+        let watch = self.check_watches(pc);
+
+        if let Some(access) = self.bus.watchpoints.take_hit() {
+            if let Some(region) = &access.region {
+                report_region_hit(region, &access, pc);
+            }
 
-        if let Some(access) = hit_watchpoint {
             // TODO: Do we need to set PC back one instruction here?
             // self.cpu.pc = self.cpu.pc.wrapping_sub(4);
 
-            Ok(match access.kind {
-                AccessKind::Read => EmulationEvent::WatchRead(access.address),
-                AccessKind::Write => EmulationEvent::WatchWrite(access.address),
+            Ok(EmulationEvent::Watch {
+                kind: access.kind,
+                address: access.address,
+                value: access.data,
             })
+        } else if let Some(index) = watch {
+            Ok(EmulationEvent::WatchExpression(index))
         } else if self.breakpoints.contains(&self.cpu.pc) {
-            Ok(EmulationEvent::Breakpoint)
+            Ok(EmulationEvent::Breakpoint {
+                address: self.cpu.pc,
+            })
+        } else if call.is_none() && self.cpu.exception_pending {
+            Ok(EmulationEvent::Exception {
+                code: self.cpu.cpzero.cause.get_exception_code(),
+                epc: self.cpu.cpzero.epc.address,
+            })
+        } else if call.is_none() && self.idle_loop(pc) {
+            Ok(EmulationEvent::IdleLoop { pc })
+        } else if let Some(address) = debug_print {
+            Ok(EmulationEvent::DeviceRequest(DeviceRequest::DebugPrint {
+                address,
+            }))
         } else {
             Ok(EmulationEvent::Step)
         }
     }
 
-    /// Prints useful information about the state of the emulator when an error occurs.
-    pub fn crashdump(&self) -> String {
-        format!("{}\n\n{}", self.cpu, self.bus)
+    /// Returns true if the instruction at `pc` branched to itself with a `nop` in its delay slot.
+    fn idle_loop(&self, pc: Address) -> bool {
+        self.cpu.delay_state == DelayState::Delayslot
+            && self.cpu.delay_pc == pc
+            && self
+                .peek_instruction(pc.wrapping_add(4))
+                .map(|instr| instr.0)
+                == Some(0)
     }
-}
 
-fn setup_rom(opts: &Opts, bus: &mut Bus) -> Result<()> {
-    // Translate the provided virtual load address to a physical address
-    // Initialization code should be located in kseg1 since it is non-cacheable
-    let loadaddress = opts.loadaddress;
-    if loadaddress < KSEG1 {
-        panic!("Provided load address must be greater than 0xa0000000");
+    /// Steps over the instruction at PC. A call runs until it returns past its delay slot, any
+    /// other instruction is stepped once.
+    ///
+    /// Returns `Step` when the call returned, or the event that stopped the run before.
+    pub fn step_over(&mut self) -> Result<EmulationEvent> {
+        let pc = self.cpu.pc;
+        if !self.peek_instruction(pc).is_some_and(shadow_stack::is_call) {
+            return self.step();
+        }
+
+        // A recursive call returns to the same address in a deeper frame, below the stack
+        // pointer of this one
+        let return_address = pc.wrapping_add(8);
+        let sp = self.cpu.reg[Register::Sp];
+        self.run_until(|emu, _| emu.cpu.pc == return_address && emu.cpu.reg[Register::Sp] >= sp)
     }
-    let paddress = loadaddress - KSEG1;
 
-    // Load the provided ROM file
-    let rom_path = &opts.romfile;
-    let rom = Rom::new(rom_path.to_string())?;
-    let size = rom.size();
+    /// Runs until the current function returns, stopping after the delay slot of its `jr ra`.
+    ///
+    /// The calls made on the way are tracked on a shadow stack, so that only the return of the
+    /// current function ends the run. Returns `Step` when it returned, or the event that
+    /// stopped the run before.
+    pub fn finish(&mut self) -> Result<EmulationEvent> {
+        let mut calls = ShadowStack::default();
+        let mut returning = None;
+        self.run_until(|emu, pc| {
+            if returning.is_some() && returning == Some(emu.cpu.pc) {
+                return true;
+            }
 
-    println!(
-        "Mapping ROM image ({}, {} words) to physical address 0x{:08x}",
-        rom_path,
-        size / 4,
-        paddress
-    );
+            let target = match emu.cpu.delay_state {
+                DelayState::Delayslot => Some(emu.cpu.delay_pc),
+                _ => None,
+            };
+            let instr = emu.cpu.instruction;
+            match target {
+                Some(target) if calls.depth() == 0 && shadow_stack::is_return(instr) => {
+                    returning = Some(target)
+                }
+                _ => {
+                    calls.check(pc, instr, target);
+                }
+            }
+            false
+        })
+    }
 
-    bus.register(Box::new(rom), paddress, size)
-}
+    /// Steps until `done` returns true, which is called with the PC of each executed
+    /// instruction, or until the run is stopped by an event, a signal or `--stop-at`.
+    fn run_until(
+        &mut self,
+        mut done: impl FnMut(&mut Self, Address) -> bool,
+    ) -> Result<EmulationEvent> {
+        loop {
+            if let Some(reason) = self.stop_condition() {
+                return Ok(EmulationEvent::Halted(reason));
+            }
+
+            let pc = self.cpu.pc;
+            let event = self.step()?;
+            if event.is_stop() {
+                return Ok(event);
+            }
+            if done(self, pc) {
+                return Ok(EmulationEvent::Step);
+            }
+        }
+    }
 
-// Create a new RAM module to install at physical address zero
-fn setup_ram(opts: &Opts, bus: &mut Bus) -> Result<()> {
-    let paddress = 0;
-    let ram = Ram::new(opts.memsize);
+    /// Adds a watch expression and returns its index, which is reported when it becomes true.
+    ///
+    /// An `mmio[...]` operand that is not in the register map never holds.
+    pub fn add_watch(&mut self, mut expr: WatchExpr) -> usize {
+        if let Err(msg) = expr.resolve(self.regmap.as_ref().unwrap_or(&RegisterMap::default())) {
+            warn!("Watch expression `{}`: {}", expr, msg);
+        }
+        self.watches.push(expr);
+        self.watches.len() - 1
+    }
 
-    println!(
-        "Mapping RAM module ({}KB) to physical address 0x{:08x}",
-        opts.memsize / 1024,
-        paddress
-    );
+    /// Returns the watch expressions in the order they were added.
+    pub fn watches(&self) -> &[WatchExpr] {
+        &self.watches
+    }
 
-    bus.register(Box::new(ram), paddress, opts.memsize)
-}
+    /// Evaluates all watch expressions and returns the first one that became true.
+    fn check_watches(&mut self, pc: Address) -> Option<usize> {
+        let mut first = None;
+        for (index, expr) in self.watches.iter_mut().enumerate() {
+            if expr.became_true(&self.cpu, &self.bus) && first.is_none() {
+                println!(
+                    "Watch expression `{}` became true after the instruction at PC=0x{:08x}",
+                    expr, pc
+                );
+                first = Some(index);
+            }
+        }
+        first
+    }
 
-fn setup_haltdevice(opts: &Opts, bus: &mut Bus) -> Result<()> {
-    use halt_device::*;
+    /// Returns the heap checker if the guest allocator entry points were given.
+    pub fn heap(&self) -> Option<&HeapTracker> {
+        self.heap.as_ref()
+    }
 
-    if !opts.nohaltdevice {
-        let paddress = BASE_ADDRESS;
-        let haltdev = HaltDevice;
+    /// Runs the heap checker hooks for the instruction about to execute at `pc`.
+    fn check_heap(&mut self, pc: Address) {
+        let Some(heap) = &mut self.heap else {
+            return;
+        };
+        let reg = &self.cpu.reg;
+        let mut errors = Vec::new();
+        errors.extend(heap.enter(pc, reg[Register::A0], reg[Register::V0], reg[Register::Ra]));
 
-        println!(
-            "Mapping Halt Device to physical address 0x{:08x}",
-            BASE_ADDRESS
-        );
-        bus.register(Box::new(haltdev), paddress, std::mem::size_of::<Address>())
+        // Check the data address of a load or store against the freed blocks
+        let mut word = [0; 4];
+        let bus = &self.bus;
+        let paddress = self.cpu.cpzero.translate(pc);
+        if !heap.in_allocator()
+            && paddress.is_some_and(|paddress| bus.peek(paddress, &mut word).is_ok())
+        {
+            let instr = Instruction(bus.endian().word(word));
+            if let Some((len, write)) = instr.data_access() {
+                let address = reg[instr.rs()].wrapping_add(instr.simmed());
+                errors.extend(heap.check_access(pc, address, len, write));
+            }
+        }
+
+        for error in errors {
+            println!("Warning: {}", error);
+        }
+    }
+
+    /// Reads the instruction at `pc` without side effects, or `None` if it cannot be fetched.
+    fn peek_instruction(&self, pc: Address) -> Option<Instruction> {
+        let mut word = [0; 4];
+        self.bus
+            .peek(self.cpu.cpzero.translate(pc)?, &mut word)
+            .ok()
+            .map(|_| Instruction(self.bus.endian().word(word)))
+    }
+
+    /// Runs the lint checks on the instruction about to execute at `pc`.
+    fn lint_instruction(&mut self, pc: Address) {
+        if self.lint.is_none() {
+            return;
+        }
+
+        if let (Some(instr), Some(linter)) = (self.peek_instruction(pc), &mut self.lint) {
+            for warning in linter.check(pc, instr) {
+                println!("Lint: {}", warning);
+            }
+        }
+    }
+
+    /// Reports when Capstone disagrees with the decoding of the instruction about to execute.
+    fn verify_decode(&self, pc: Address) {
+        let Some(verifier) = &self.verify_decode else {
+            return;
+        };
+
+        if let Some(mismatch) = self
+            .peek_instruction(pc)
+            .and_then(|instr| verifier.check(pc, instr))
+        {
+            println!("Decode mismatch: {}", mismatch);
+        }
+    }
+
+    /// Records an event at the current instruction count if a timeline is being written.
+    pub(crate) fn record_event(
+        &mut self,
+        category: &'static str,
+        name: String,
+        args: Vec<(&'static str, String)>,
+    ) {
+        if let Some(timeline) = &mut self.timeline {
+            timeline.record(self.instruction_count as u64, category, name, args);
+        }
+    }
+
+    /// Applies the scripted inputs that are due at the current instruction count.
+    fn apply_inputs(&mut self) {
+        let Some(inputs) = &mut self.inputs else {
+            return;
+        };
+
+        let mut applied = Vec::new();
+        while let Some(event) = inputs.next_due(self.instruction_count as u64) {
+            match &event.action {
+                InputAction::Serial(bytes) => {
+                    if let Some(serial) = &self.serial_input {
+                        bytes
+                            .iter()
+                            .for_each(|byte| serial.send(*byte).unwrap_or(()));
+                    }
+                }
+                InputAction::Irq { line, asserted } => self.bus.intctrl.set(*line, *asserted),
+            }
+            info!(
+                "Injecting {} at instruction {}",
+                event.action, self.instruction_count
+            );
+            applied.push(event.action.to_string());
+        }
+
+        for action in applied {
+            self.record_event("input", action, Vec::new());
+        }
+    }
+
+    /// Records the interrupt line changes, exception and device accesses of the instruction at
+    /// `pc` on the timeline.
+    fn record_step_events(&mut self, pc: Address, call: Option<PromCall>) {
+        // The lines driven by devices and through the `IntCtrl`, as polled before the instruction
+        let lines = (self.cpu.cpzero.cause.get_interrupt_pending() >> 2) as u8;
+        let changed = lines ^ self.irq_lines;
+        self.irq_lines = lines;
+        for line in (0..6).filter(|line| changed & (1 << line) != 0) {
+            let ip = line + 2;
+            let mut args = vec![("pc", format!("0x{:08x}", pc))];
+            let name = match lines & (1 << line) != 0 {
+                true => {
+                    let source = match self.bus.intctrl.is_raised(ip) {
+                        true => "interrupt controller",
+                        false => "device",
+                    };
+                    args.push(("source", source.to_owned()));
+                    format!("Assert IP{}", ip)
+                }
+                false => format!("Deassert IP{}", ip),
+            };
+            self.record_event("irq", name, args);
+        }
+
+        for access in self.bus.device_accesses.take() {
+            let name = match access.write {
+                true => format!("write {}", access.device),
+                false => format!("read {}", access.device),
+            };
+            // Device-initiated accesses are attributed to the device instead of an instruction
+            let initiator = match access.initiator {
+                Some(initiator) => ("initiator", initiator),
+                None => ("pc", format!("0x{:08x}", pc)),
+            };
+            let mut args = vec![
+                initiator,
+                ("address", format!("0x{:08x}", access.address)),
+                (
+                    "data",
+                    format!("0x{:0w$x}", access.data, w = access.len * 2),
+                ),
+            ];
+            if let Some(register) = self.decode_register(access.address, access.data) {
+                args.push(("register", register));
+            }
+            self.record_event("device", name, args);
+        }
+
+        let invalidations = self.bus.invalidations.as_mut().map(std::mem::take);
+        for range in invalidations.unwrap_or_default() {
+            let args = vec![
+                ("pc", format!("0x{:08x}", pc)),
+                ("address", format!("0x{:08x}", range.base())),
+                ("size", range.size().to_string()),
+            ];
+            self.record_event("cache", "Invalidate instructions".to_owned(), args);
+        }
+
+        if call.is_none() && self.cpu.exception_pending {
+            let code = self.cpu.cpzero.cause.get_exception_code();
+            let args = vec![
+                ("pc", format!("0x{:08x}", pc)),
+                ("epc", format!("0x{:08x}", self.cpu.cpzero.epc.address)),
+            ];
+            self.record_event("exception", format!("{:?}", code), args);
+        }
+    }
+
+    /// Writes the line of the last step to the `--trace` file.
+    ///
+    /// The instruction that halts the run is traced, while a step that failed with any other
+    /// error did not complete and is left out.
+    fn trace_step(
+        &mut self,
+        pc: Address,
+        call: Option<PromCall>,
+        result: &Result<()>,
+    ) -> Result<()> {
+        let accesses = self.bus.data_accesses.take();
+        let Some(trace) = &mut self.trace else {
+            return Ok(());
+        };
+        if !matches!(result, Ok(()) | Err(RmipsError::Halt(_))) {
+            return Ok(());
+        }
+
+        let step = TraceStep {
+            step: self.instruction_count,
+            pc,
+            instr: match (call, self.cpu.fetched) {
+                (None, true) => Some(self.cpu.instruction),
+                _ => None,
+            },
+            accesses: &accesses,
+            exception: match self.cpu.exception_pending {
+                true => Some(self.cpu.cpzero.cause.get_exception_code()),
+                false => None,
+            },
+        };
+        trace.record(&step, &self.cpu)
+    }
+
+    /// Prints what the last step did, along with the registers and memory it changed.
+    fn explain(&mut self, snapshot: &CpuSnapshot, call: Option<PromCall>) {
+        let description = match call {
+            Some(call) => format!("monitor PROM {:?} service", call),
+            None => explain::describe(self.cpu.instruction, snapshot.pc()),
+        };
+        println!("0x{:08x}: {}", snapshot.pc(), description);
+
+        for change in snapshot.changes(&self.cpu) {
+            println!("    {}", change);
+        }
+        for store in self.bus.stores.take() {
+            let width = store.len * 2;
+            match store.old {
+                Some(old) => println!(
+                    "    memory @ 0x{:08x}: 0x{:0w$x} → 0x{:0w$x}",
+                    store.address,
+                    old,
+                    store.new,
+                    w = width
+                ),
+                None => println!(
+                    "    memory @ 0x{:08x}: 0x{:0w$x}",
+                    store.address,
+                    store.new,
+                    w = width
+                ),
+            }
+        }
+        if self.cpu.exception_pending {
+            println!(
+                "    exception: {:?}",
+                self.cpu.cpzero.cause.get_exception_code()
+            );
+        }
+    }
+
+    /// Returns the monitor PROM service whose entry point is at the program counter.
+    fn prom_call(&self) -> Option<PromCall> {
+        if self.opts.monitorprom {
+            // A PC without a translation takes a TLB miss instead of entering the PROM
+            self.cpu
+                .cpzero
+                .translate(self.cpu.pc)
+                .and_then(PromCall::at)
+        } else {
+            None
+        }
+    }
+
+    /// Performs a monitor PROM service and returns to the caller like the firmware would.
+    fn prom_service(&mut self, call: PromCall) -> Result<()> {
+        let arg = self.cpu.reg[Register::A0];
+
+        let result = match call {
+            PromCall::Putchar => {
+                let mut stdout = io::stdout();
+                stdout.write_all(&[arg as u8])?;
+                stdout.flush()?;
+                arg & 0xff
+            }
+            // A name that cannot be read is not found, like a name that is not set
+            PromCall::Getenv => match self.translate(arg).and_then(|name| self.read_string(name)) {
+                Ok(name) => self.prom_getenv(&name)?.unwrap_or(0),
+                Err(err) => {
+                    warn!("PROM getenv of the name at 0x{:08x} failed: {}", arg, err);
+                    0
+                }
+            },
+            PromCall::Exit => {
+                info!("PROM exit called with status {}", arg as i32);
+                return Err(RmipsError::Halt(HaltReason::Exit(arg as i32)));
+            }
+        };
+
+        self.cpu.reg[Register::V0] = result;
+        self.cpu.pc = self.cpu.reg[Register::Ra];
+        Ok(())
+    }
+
+    /// Looks up `name` in the PROM environment and returns the kseg1 address of its value.
+    fn prom_getenv(&self, name: &[u8]) -> Result<Option<Address>> {
+        let mut paddress = prom::BASE_ADDRESS + prom::ENV_OFFSET as Address;
+        loop {
+            let var = self.read_string(paddress)?;
+            if var.is_empty() {
+                return Ok(None);
+            }
+
+            if var.len() > name.len() && var.starts_with(name) && var[name.len()] == b'=' {
+                return Ok(Some(KSEG1 + paddress + name.len() as Address + 1));
+            }
+            paddress += var.len() as Address + 1;
+        }
+    }
+
+    /// Prints the strings stored to the debug print device and returns the address of the last.
+    ///
+    /// A string that cannot be read is not printed, and the device word reads as zero to let
+    /// the guest notice.
+    fn print_debug_strings(&mut self) -> Result<Option<Address>> {
+        let Some(strings) = &self.debug_prints else {
+            return Ok(None);
+        };
+        let addresses: Vec<Address> = strings.try_iter().collect();
+
+        let mut stdout = io::stdout();
+        for &address in &addresses {
+            match self
+                .translate(address)
+                .and_then(|paddress| self.read_string(paddress))
+            {
+                Ok(string) => stdout.write_all(&string)?,
+                Err(err) => {
+                    warn!(
+                        "Debug print of the string at 0x{:08x} failed: {}",
+                        address, err
+                    );
+                    self.bus.write(
+                        debug_print::BASE_ADDRESS,
+                        &[0; debug_print::SIZE],
+                        AccessContext::Debugger,
+                    )?;
+                }
+            }
+        }
+        stdout.flush()?;
+        Ok(addresses.last().copied())
+    }
+
+    /// Adds the results that the guest reported to the test device to the test report.
+    fn collect_test_results(&mut self) {
+        let string = |emulator: &Self, address: Address| match emulator
+            .translate(address)
+            .and_then(|paddress| emulator.read_string(paddress))
+        {
+            Ok(string) => String::from_utf8_lossy(&string).into_owned(),
+            Err(err) => {
+                warn!(
+                    "Test device string at 0x{:08x} is unreadable: {}",
+                    address, err
+                );
+                format!("0x{:08x}", address)
+            }
+        };
+
+        while let Ok(event) = self.test_events.try_recv() {
+            match event {
+                TestEvent::Case(name) => {
+                    let name = string(self, name);
+                    self.test_report.begin(name);
+                }
+                TestEvent::Assert { passed, message } => {
+                    let message = message.map(|message| string(self, message));
+                    self.test_report.assert(passed, message);
+                }
+                TestEvent::Skip(reason) => {
+                    let reason = string(self, reason);
+                    self.test_report.skip(reason);
+                }
+            }
+        }
+    }
+
+    /// Returns the results that the guest reported to the test device so far.
+    pub fn test_report(&self) -> &TestReport {
+        &self.test_report
+    }
+
+    /// Translates a virtual address that the emulator accesses on behalf of the program.
+    fn translate(&self, address: Address) -> Result<Address> {
+        self.cpu
+            .cpzero
+            .translate(address)
+            .ok_or(RmipsError::NoTranslation(address))
+    }
+
+    /// Reads a NUL-terminated string from physical memory.
+    ///
+    /// Fails if the string is longer than `MAX_STRING_LEN` or runs past the end of memory.
+    fn read_string(&self, paddress: Address) -> Result<Vec<u8>> {
+        let mut string = Vec::new();
+        let mut address = paddress;
+        loop {
+            let mut byte = [0];
+            self.bus.peek(address, &mut byte)?;
+            if byte[0] == 0 {
+                return Ok(string);
+            }
+
+            string.push(byte[0]);
+            address = match address.checked_add(1) {
+                Some(next) if string.len() < MAX_STRING_LEN => next,
+                _ => return Err(RmipsError::UnterminatedString(paddress)),
+            };
+        }
+    }
+
+    /// Resets the CPU like the reset line of the board, so the program boots again from the
+    /// reset vector. Memory and devices keep their state, and a run or GDB session goes on.
+    pub fn reset(&mut self, reason: ResetReason) -> EmulationEvent {
+        println!("\n*************[ RESET ]*************\n");
+        self.cpu.reset();
+        if self.opts.nommu {
+            self.cpu.pc = self.opts.loadaddress;
+        }
+        self.record_event("reset", format!("Reset: {:?}", reason), Vec::new());
+        EmulationEvent::Reset(reason)
+    }
+
+    /// Captures the `Cpu` state and the contents of the memory devices.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            cpu: self.cpu.state(),
+            memories: self
+                .bus
+                .memory_regions()
+                .map(|(base, data)| (base, data.to_vec()))
+                .collect(),
+            instruction_count: self.instruction_count,
+        }
+    }
+
+    /// Restores a snapshot taken from an emulator created with the same options.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        for (base, data) in &snapshot.memories {
+            match self.bus.get_device_mut(*base) {
+                Some((range, dev)) if range.base() == *base && range.size() == data.len() => {
+                    dev.write(0, data, AccessContext::Debugger)?
+                }
+                _ => return Err(RmipsError::MemoryWrite(*base)),
+            }
+        }
+
+        self.cpu.restore_state(&snapshot.cpu);
+        self.instruction_count = snapshot.instruction_count;
+        self.clock
+            .set_instructions(snapshot.instruction_count as u64);
+        Ok(())
+    }
+
+    /// Writes the complete machine state to the file at `path`, from which a new emulator
+    /// created with the same options can resume the run with `load_snapshot`.
+    ///
+    /// Unlike `snapshot`, the file includes CP0 with the TLB, the attached coprocessors and
+    /// the registers of every device.
+    pub fn save_snapshot(&mut self, path: &str) -> Result<()> {
+        let mut file = SNAPSHOT_MAGIC.to_vec();
+        file.extend(self.machine_state());
+        std::fs::write(path, &file)?;
+        self.record_event(
+            "snapshot",
+            "Save snapshot".to_owned(),
+            vec![("path", path.to_owned())],
+        );
+        println!(
+            "Saved snapshot after {} instructions to {}",
+            self.instruction_count, path
+        );
+        Ok(())
+    }
+
+    /// Returns the complete machine state that a snapshot file holds after its magic number.
+    fn machine_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.word(SNAPSHOT_VERSION);
+        state.u64(self.instruction_count as u64);
+        self.cpu.save(&mut state);
+
+        state.word(self.bus.devices().count() as u32);
+        for (range, dev) in self.bus.devices() {
+            let mut device = StateWriter::new();
+            dev.save(&mut device);
+            state.word(range.base());
+            state.bytes(dev.debug_label().as_bytes());
+            state.bytes(&device.into_bytes());
+        }
+        state.into_bytes()
+    }
+
+    /// Restores the machine state from a snapshot file written by `save_snapshot`.
+    ///
+    /// The devices of the snapshot must match the devices of this emulator. The emulator is
+    /// left partly restored if the snapshot is invalid, and should not be run.
+    pub fn load_snapshot(&mut self, path: &str) -> Result<()> {
+        let file = std::fs::read(path)?;
+        let bytes = file
+            .strip_prefix(SNAPSHOT_MAGIC)
+            .ok_or_else(|| RmipsError::Snapshot(format!("{} is not a snapshot", path)))?;
+        self.restore_machine_state(bytes)?;
+        self.record_event(
+            "snapshot",
+            "Load snapshot".to_owned(),
+            vec![("path", path.to_owned())],
+        );
+        println!(
+            "Loaded snapshot after {} instructions from {}",
+            self.instruction_count, path
+        );
+        Ok(())
+    }
+
+    /// Restores the machine state returned by `machine_state`.
+    fn restore_machine_state(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut state = StateReader::new(bytes);
+        let version = state.word()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(RmipsError::Snapshot(format!(
+                "version {} is not supported, expected version {}",
+                version, SNAPSHOT_VERSION
+            )));
+        }
+        let instruction_count = state.u64()? as usize;
+        self.cpu.restore(&mut state)?;
+
+        let devices = state.word()? as usize;
+        if devices != self.bus.devices().count() {
+            return Err(RmipsError::Snapshot(format!(
+                "the snapshot has {} devices instead of {}",
+                devices,
+                self.bus.devices().count()
+            )));
+        }
+        for (range, dev) in self.bus.devices_mut() {
+            let base = state.word()?;
+            let label = String::from_utf8_lossy(state.bytes()?);
+            if base != range.base() || label != dev.debug_label() {
+                return Err(RmipsError::Snapshot(format!(
+                    "found {} at 0x{:08x} instead of {} at 0x{:08x}",
+                    label,
+                    base,
+                    dev.debug_label(),
+                    range.base()
+                )));
+            }
+            let mut device = StateReader::new(state.bytes()?);
+            dev.restore(&mut device)?;
+            device.finish()?;
+        }
+        state.finish()?;
+
+        self.instruction_count = instruction_count;
+        self.clock.set_instructions(instruction_count as u64);
+        Ok(())
+    }
+
+    /// Overwrites guest memory at the virtual address `address` with `bytes`, which also works
+    /// for ROM, and returns a handle to revert the patch.
+    ///
+    /// Instructions are decoded when they are fetched, so patched code takes effect at once.
+    pub fn patch(&mut self, address: Address, bytes: &[u8]) -> Result<PatchHandle> {
+        let mut original = Vec::with_capacity(bytes.len());
+        for (address, value) in (address..).zip(bytes.iter().copied()) {
+            let paddress = self.translate(address)?;
+            let mut old = [0];
+            self.bus.peek(paddress, &mut old)?;
+            self.bus
+                .write(paddress, &[value], AccessContext::Debugger)?;
+            original.push(old[0]);
+        }
+
+        Ok(PatchHandle { address, original })
+    }
+
+    /// Applies the `--patch` and `--skip-function` options and the debug session to the loaded
+    /// program, resolving function names with the labels of an assembled ROM.
+    fn apply_patches(&mut self, labels: &HashMap<String, Address>) -> Result<()> {
+        if let Some(session) = &mut self.session {
+            for patch in &self.opts.patch {
+                session.add_patch(*patch);
+            }
+            self.breakpoints.extend(&session.breakpoints);
+            for address in &session.watchpoints {
+                self.bus.watchpoints.add(*address);
+            }
+        }
+
+        let patches = match &self.session {
+            Some(session) => session.patches.clone(),
+            None => self.opts.patch.clone(),
+        };
+        for patch in patches {
+            println!(
+                "Patching 0x{:08x} with 0x{:08x}",
+                patch.address, patch.value
+            );
+            self.patch(patch.address, &self.bus.endian().word_bytes(patch.value))?;
+        }
+
+        for function in self.opts.skipfunction.clone() {
+            let address = match labels.get(&function) {
+                Some(address) => *address,
+                None => parse_address(&function)
+                    .map_err(|_| RmipsError::UnknownSymbol(function.clone()))?,
+            };
+            println!("Skipping function {} at 0x{:08x}", function, address);
+
+            // jr ra; move v0, zero
+            let stub: Vec<u8> = [0x03e0_0008_u32, 0x0000_1021]
+                .iter()
+                .flat_map(|&word| self.bus.endian().word_bytes(word))
+                .collect();
+            self.patch(address, &stub)?;
+        }
+
+        Ok(())
+    }
+
+    /// Restores the bytes replaced by a patch. Overlapping patches are reverted in the reverse
+    /// order they were applied in.
+    pub fn revert(&mut self, patch: PatchHandle) -> Result<()> {
+        for (address, value) in (patch.address..).zip(patch.original) {
+            let paddress = self.translate(address)?;
+            self.bus
+                .write(paddress, &[value], AccessContext::Debugger)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the devices mapped into the physical address space in address order.
+    pub fn memory_map(&self) -> Vec<MemoryMapEntry> {
+        self.bus
+            .map()
+            .map(|(range, dev)| MemoryMapEntry {
+                base: range.base(),
+                size: range.size(),
+                label: dev.debug_label(),
+            })
+            .collect()
+    }
+
+    /// Describes the physical memory map as printed by `--memmap`.
+    pub fn memory_map_summary(&self) -> String {
+        // Device registers are connected so that they read the same values in either byte order
+        let endian = self.bus.endian().name();
+        format!(
+            "Physical memory map (ROM interpreted as {}, devices are accessed in {} byte order):\n{}",
+            endian, endian, self.bus
+        )
+    }
+
+    /// Describes the machine that was built from the options for `rmips describe`: the CPU, the
+    /// memory map with the interrupt lines of each device, and the options after defaults.
+    pub fn describe(&self) -> String {
+        let translation = match self.cpu.cpzero.translation {
+            Translation::Segmented => "kuseg, kseg0, kseg1 and kseg2 segments",
+            Translation::Identity => "none, addresses are physical",
+        };
+        let mut description = format!(
+            "CPU: {} (PRId 0x{:08x}) with {} TLB entries\nAddress translation: {}\nReset PC: 0x{:08x}\n\n{}",
+            self.opts.cpumodel,
+            self.opts.cpumodel.prid(),
+            self.cpu.cpzero.tlb_entries(),
+            translation,
+            self.cpu.pc,
+            self.memory_map_summary()
+        );
+
+        description.push_str("\nInterrupt lines:\n");
+        let mut sources = 0;
+        for (range, dev) in self.bus.map() {
+            let outputs = dev.interrupt_outputs();
+            for line in (0..8).filter(|line| outputs & (1 << line) != 0) {
+                description.push_str(&format!(
+                    "  IP{}  {} at 0x{:08x}\n",
+                    line + 2,
+                    dev.debug_label(),
+                    range.base()
+                ));
+                sources += 1;
+            }
+        }
+        if let Some(inputs) = &self.inputs {
+            for line in inputs.interrupt_lines() {
+                description.push_str(&format!("  IP{}  input script\n", line));
+                sources += 1;
+            }
+        }
+        if sources == 0 {
+            description.push_str("  none\n");
+        }
+
+        description.push_str(&format!("\nEffective options: {:#?}", self.opts));
+        description
+    }
+
+    /// Iterates over the RAM regions of the machine as `(physical address, contents)` pairs.
+    pub fn memory_regions(&self) -> impl Iterator<Item = (Address, &[u8])> {
+        self.bus.memory_regions()
+    }
+
+    /// Returns the physical addresses where the byte string `pattern` occurs in RAM.
+    pub fn find_bytes<'a>(&'a self, pattern: &'a [u8]) -> impl Iterator<Item = Address> + 'a {
+        self.bus.find(pattern)
+    }
+
+    /// Returns the word-aligned physical addresses in RAM holding `value`.
+    pub fn find_word(&self, value: u32) -> Vec<Address> {
+        self.bus
+            .find(&self.bus.endian().word_bytes(value))
+            .filter(|address| address.is_multiple_of(4))
+            .collect()
+    }
+
+    /// Returns the physical addresses of the memory pages written since the last `clear_dirty`.
+    pub fn dirty_pages(&self) -> Vec<Address> {
+        self.bus.dirty_pages()
+    }
+
+    /// Resets dirty-page tracking, e.g. after taking a snapshot.
+    pub fn clear_dirty(&mut self) {
+        self.bus.clear_dirty()
+    }
+
+    /// Prints useful information about the state of the emulator when an error occurs.
+    pub fn crashdump(&self) -> String {
+        match self.regmap {
+            Some(_) => format!(
+                "{}\n\n{}\n\n{}",
+                self.cpu,
+                self.bus,
+                self.register_summary()
+            ),
+            None => format!("{}\n\n{}", self.cpu, self.bus),
+        }
+    }
+
+    /// Decodes a value of the device register at the physical `address` with the register map.
+    fn decode_register(&self, address: Address, value: u32) -> Option<String> {
+        self.regmap.as_ref()?.decode(address, value)
+    }
+
+    /// Reads and decodes every register of the register map, for `monitor mmio`.
+    pub(crate) fn register_summary(&self) -> String {
+        let Some(regmap) = &self.regmap else {
+            return "No register map is given".to_owned();
+        };
+
+        let mut summary = Vec::new();
+        for peripheral in regmap.peripherals() {
+            for register in &peripheral.registers {
+                let address = peripheral.base.wrapping_add(register.offset);
+                let mut data = [0; 4];
+                let line = match self.bus.peek(address, &mut data[..register.size]) {
+                    Ok(()) => regmap
+                        .decode(address, self.bus.endian().value(&data[..register.size]))
+                        .unwrap_or_default(),
+                    Err(_) => format!("{}.{} unmapped", peripheral.name, register.name),
+                };
+                summary.push(format!("0x{:08x}  {}", address, line));
+            }
+        }
+        summary.join("\n")
+    }
+}
+
+/// Prints the first access to a watched region and the instruction or device that performed it.
+fn report_region_hit(region: &WatchRegion, access: &Access, pc: Address) {
+    let kind = match access.kind {
+        AccessKind::Read => "read from",
+        AccessKind::Write => "write to",
+    };
+    let vaddress = region
+        .vaddress
+        .wrapping_add(access.address.wrapping_sub(region.range.base()));
+
+    let by = match &access.initiator {
+        Some(initiator) => format!("device {}", initiator),
+        None => format!("the instruction at PC=0x{:08x}", pc),
+    };
+
+    println!(
+        "First {} region 0x{:08x}+0x{:x}: {} bytes at 0x{:08x} (value 0x{:0w$x}) by {}",
+        kind,
+        region.vaddress,
+        region.range.size(),
+        access.len,
+        vaddress,
+        access.data,
+        by,
+        w = access.len * 2
+    );
+}
+
+/// Translates the address of a region given on the command line to a physical address.
+///
+/// The TLB is empty at reset, so like the `--loadaddress`, kuseg and kseg2 addresses are
+/// taken as physical addresses.
+fn option_address(cpu: &Cpu, address: Address) -> Address {
+    cpu.cpzero.translate(address).unwrap_or_else(|| {
+        warn!(
+            "0x{:08x} has no TLB translation at reset and is used as a physical address",
+            address
+        );
+        address
+    })
+}
+
+/// Returns the virtual and physical address to load the ROM at for the `--loadaddress`.
+///
+/// kseg0 and kseg1 addresses are translated to the physical address they map to, and
+/// addresses below 512MB are taken as physical addresses and run from kseg1. Without an
+/// MMU the address is used as is.
+fn rom_address(opts: &Opts) -> Result<(Address, Address)> {
+    let loadaddress = opts.loadaddress;
+    if opts.nommu {
+        return Ok((loadaddress, loadaddress));
+    }
+
+    match loadaddress & KSEG_SELECT_MASK {
+        KSEG1 => Ok((loadaddress, loadaddress - KSEG1)),
+        KSEG0 => {
+            println!(
+                "Warning: load address 0x{:08x} is in cached kseg0, boot code that runs before \
+                 the caches are set up is normally loaded in uncached kseg1 at 0x{:08x}",
+                loadaddress,
+                loadaddress - KSEG0 + KSEG1
+            );
+            Ok((loadaddress, loadaddress - KSEG0))
+        }
+        0 => {
+            println!(
+                "Treating load address 0x{:08x} as a physical address, mapped at 0x{:08x} in kseg1",
+                loadaddress,
+                loadaddress + KSEG1
+            );
+            Ok((loadaddress + KSEG1, loadaddress))
+        }
+        _ => Err(RmipsError::LoadAddress(loadaddress)),
+    }
+}
+
+fn setup_rom(opts: &Opts, bus: &mut Bus) -> Result<HashMap<String, Address>> {
+    let (loadaddress, paddress) = rom_address(opts)?;
+
+    // Load the provided ROM file, assembling it first if it is a source file
+    let rom_path = &opts.romfile;
+    let mut labels = HashMap::new();
+    let rom = if asm::is_source_file(rom_path) {
+        let source = std::fs::read_to_string(rom_path)
+            .map_err(|_| RmipsError::RomLoading(rom_path.to_string()))?;
+        let program = asm::assemble(&source, loadaddress, bus.endian())?;
+        println!("Assembled {} ({} bytes)", rom_path, program.image.len());
+        labels = program.labels;
+        Rom::from_image(rom_path.to_string(), &program.image)?
+    } else {
+        Rom::new(rom_path.to_string(), opts.romoffset, opts.romlength)?
+    };
+    let size = rom.size();
+
+    println!(
+        "Mapping ROM image ({}, {} words) to physical address 0x{:08x}",
+        rom_path,
+        size / 4,
+        paddress
+    );
+
+    bus.register(Box::new(rom), paddress, size)?;
+    Ok(labels)
+}
+
+/// Size of the unmapped guard pages kept around RAM at a random base.
+const RAM_GUARD_SIZE: usize = 0x1000;
+
+/// Returns a page-aligned RAM base below the 512MB reachable through kseg0 and kseg1 that leaves
+/// a guard page free of devices on both sides of RAM.
+fn random_ram_base(opts: &Opts, bus: &Bus, seed: u64) -> Result<Address> {
+    let span = opts.memsize + 2 * RAM_GUARD_SIZE;
+    let pages = (KSEG1 - KSEG0) as usize / RAM_GUARD_SIZE;
+    let slots = pages.saturating_sub(span / RAM_GUARD_SIZE);
+
+    let mut rng = XorShift::new(seed);
+    for _ in 0..1024 {
+        if slots == 0 {
+            break;
+        }
+        let base = (rng.next() % slots as u64) as usize * RAM_GUARD_SIZE;
+        if bus.is_free(base as Address, span) {
+            return Ok((base + RAM_GUARD_SIZE) as Address);
+        }
+    }
+    Err(RmipsError::MemoryRangeOverlap)
+}
+
+// Create a new RAM module to install at physical address zero, or at the `--ram-base`
+fn setup_ram(opts: &Opts, bus: &mut Bus) -> Result<Address> {
+    let paddress = match opts.rambase {
+        None => 0,
+        Some(RamBase::Fixed(address)) => address,
+        Some(RamBase::Random(seed)) => {
+            let seed = seed.unwrap_or_else(|| {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH);
+                now.map_or(1, |time| time.as_nanos() as u64)
+            });
+            println!("Randomizing the RAM base with seed {}", seed);
+            random_ram_base(opts, bus, seed)?
+        }
+    };
+    let image = match &opts.ramimage {
+        Some(image) => Some((image, std::fs::read(&image.path)?)),
+        None => None,
+    };
+
+    let preload = |load: &mut dyn FnMut(usize, &[u8]) -> bool| match &image {
+        Some((image, data)) if !load(image.offset, data) => {
+            Err(RmipsError::RamImage(image.path.to_owned()))
+        }
+        _ => Ok(()),
+    };
+
+    let ram: Box<dyn Device> = match opts.sparseram {
+        true => {
+            let mut ram = SparseRam::new(opts.memsize);
+            preload(&mut |offset, data| ram.load(offset, data))?;
+            Box::new(ram)
+        }
+        false => {
+            let mut ram = Ram::new(opts.memsize);
+            preload(&mut |offset, data| ram.load(offset, data))?;
+            Box::new(ram)
+        }
+    };
+
+    if let Some((image, data)) = &image {
+        println!(
+            "Preloaded RAM image ({}, {} bytes) at physical address 0x{:08x}",
+            image.path,
+            data.len(),
+            paddress + image.offset as Address
+        );
+    }
+
+    println!(
+        "Mapping {}RAM module ({}KB) to physical address 0x{:08x}",
+        if opts.sparseram { "sparse " } else { "" },
+        opts.memsize / 1024,
+        paddress
+    );
+
+    bus.register(ram, paddress, opts.memsize)?;
+    Ok(paddress)
+}
+
+fn setup_aliases(opts: &Opts, bus: &mut Bus) -> Result<()> {
+    for alias in &opts.alias {
+        let stride = alias.stride.unwrap_or(alias.len);
+        println!(
+            "Mirroring physical address 0x{:08x} ({} bytes) at 0x{:08x}-0x{:08x}",
+            alias.target,
+            stride.min(alias.len),
+            alias.address,
+            alias.address as u64 + alias.len as u64 - 1
+        );
+        bus.alias(Alias {
+            range: Range::new(alias.address, alias.len),
+            target: alias.target,
+            stride,
+        })?;
+    }
+    Ok(())
+}
+
+fn setup_haltdevice(opts: &Opts, bus: &mut Bus) -> Result<()> {
+    use halt_device::*;
+
+    if !opts.nohaltdevice {
+        let paddress = opts.haltdeviceat.unwrap_or(BASE_ADDRESS);
+        let haltdev = HaltDevice;
+
+        println!("Mapping Halt Device to physical address 0x{:08x}", paddress);
+        bus.register(Box::new(haltdev), paddress, std::mem::size_of::<Address>())
+    } else {
+        Ok(())
+    }
+}
+
+fn setup_resetdevice(opts: &Opts, bus: &mut Bus) -> Result<()> {
+    use reset_device::*;
+
+    if opts.resetdevice {
+        println!(
+            "Mapping Reset Device to physical address 0x{:08x}",
+            BASE_ADDRESS
+        );
+        bus.register(
+            Box::new(ResetDevice),
+            BASE_ADDRESS,
+            std::mem::size_of::<Address>(),
+        )
+    } else {
+        Ok(())
+    }
+}
+
+fn setup_nvram(opts: &Opts, bus: &mut Bus) -> Result<()> {
+    use nvram::*;
+
+    if let Some(path) = &opts.nvram {
+        let paddress = BASE_ADDRESS;
+        let nvram = NvramDevice::new(path.to_string(), opts.nvramsize)?;
+
+        println!(
+            "Mapping NVRAM ({}, {} bytes) to physical address 0x{:08x}",
+            path, opts.nvramsize, paddress
+        );
+        bus.register(Box::new(nvram), paddress, opts.nvramsize)
+    } else {
+        Ok(())
+    }
+}
+
+fn setup_shared_memory(opts: &Opts, bus: &mut Bus) -> Result<()> {
+    use shared_memory::*;
+
+    if let Some(path) = &opts.sharedmemory {
+        let paddress = BASE_ADDRESS;
+        let shmem = SharedMemory::new(path.to_string(), opts.sharedmemorysize)?;
+
+        println!(
+            "Mapping Shared Memory ({}, {} bytes) to physical address 0x{:08x}",
+            path, opts.sharedmemorysize, paddress
+        );
+        bus.register(Box::new(shmem), paddress, opts.sharedmemorysize)
+    } else {
+        Ok(())
+    }
+}
+
+fn setup_network(opts: &Opts, bus: &mut Bus) -> Result<()> {
+    use network::*;
+
+    if let Some(listen) = opts.netlisten {
+        let paddress = BASE_ADDRESS;
+        let network = NetworkDevice::new(listen, opts.netpeer.clone())?;
+
+        println!(
+            "Mapping Network Device (udp {}, {} peers) to physical address 0x{:08x}",
+            network.local_addr()?,
+            opts.netpeer.len(),
+            paddress
+        );
+        bus.register(Box::new(network), paddress, SIZE)
+    } else {
+        Ok(())
+    }
+}
+
+/// Loads the `--inject` input script, checking that the devices it drives are mapped.
+fn load_input_script(opts: &Opts) -> Result<Option<InputScript>> {
+    let Some(path) = &opts.inject else {
+        return Ok(None);
+    };
+
+    let invalid = |msg: String| RmipsError::InputScript(path.clone(), msg);
+    let inputs = InputScript::parse(&std::fs::read_to_string(path)?).map_err(invalid)?;
+    if inputs.has_serial_input() && opts.seriallink.is_none() {
+        return Err(invalid(
+            "serial inputs need the serial link, map it with --serial-link".to_owned(),
+        ));
+    }
+    println!("Replaying external inputs from {}", path);
+    Ok(Some(inputs))
+}
+
+/// Loads the `--regmap` register map.
+fn load_register_map(opts: &Opts) -> Result<Option<RegisterMap>> {
+    let Some(path) = &opts.regmap else {
+        return Ok(None);
+    };
+
+    let map = RegisterMap::parse(&std::fs::read_to_string(path)?)
+        .map_err(|msg| RmipsError::RegisterMap(path.clone(), msg))?;
+    println!(
+        "Naming the registers of {} peripherals from {}",
+        map.peripherals().len(),
+        path
+    );
+    Ok(Some(map))
+}
+
+/// Restores the `--debug-session`, which starts empty if the file does not exist yet.
+fn load_debug_session(opts: &Opts) -> Result<Option<DebugSession>> {
+    let Some(path) = &opts.debugsession else {
+        return Ok(None);
+    };
+    if !std::path::Path::new(path).exists() {
+        println!("Starting debug session {}", path);
+        return Ok(Some(DebugSession::default()));
+    }
+
+    let session = DebugSession::parse(&std::fs::read_to_string(path)?)
+        .map_err(|msg| RmipsError::DebugSession(path.clone(), msg))?;
+    println!(
+        "Restored {} breakpoints, {} watchpoints and {} patches from {}",
+        session.breakpoints.len(),
+        session.watchpoints.len(),
+        session.patches.len(),
+        path
+    );
+    Ok(Some(session))
+}
+
+/// Resolves the device registers that the `--watch` expressions read by name.
+fn resolve_watches(watches: &[WatchExpr], regmap: Option<&RegisterMap>) -> Result<Vec<WatchExpr>> {
+    let empty = RegisterMap::default();
+    let mut watches = watches.to_vec();
+    for expr in &mut watches {
+        expr.resolve(regmap.unwrap_or(&empty))
+            .map_err(|msg| RmipsError::Config(format!("watch expression `{}`: {}", expr, msg)))?;
+    }
+    Ok(watches)
+}
+
+/// Maps the serial link and returns a channel for scripted input to it if `scripted` is set.
+fn setup_serial_link(opts: &Opts, bus: &mut Bus, scripted: bool) -> Result<Option<Sender<u8>>> {
+    use serial_link::*;
+
+    if let Some(endpoint) = &opts.seriallink {
+        let paddress = BASE_ADDRESS;
+        let mut link = SerialLinkDevice::new(endpoint.clone(), opts.serialfifodepth)?;
+        let input = scripted.then(|| link.scripted_input());
+
+        // Show the port that was bound when listening on port 0
+        let endpoint = match link.local_addr() {
+            Some(address) => format!("tcp-listen:{}", address),
+            None => endpoint.to_string(),
+        };
+        println!(
+            "Mapping Serial Link ({}) to physical address 0x{:08x}",
+            endpoint, paddress
+        );
+        bus.register(Box::new(link), paddress, SIZE)?;
+        Ok(input)
+    } else {
+        Ok(None)
+    }
+}
+
+fn setup_i2c(opts: &Opts, bus: &mut Bus) -> Result<()> {
+    use i2c::*;
+
+    if !opts.i2cslave.is_empty() {
+        let paddress = BASE_ADDRESS;
+        let slaves = opts.i2cslave.iter().map(|spec| spec.build()).collect();
+
+        println!(
+            "Mapping I2C Controller ({} slaves) to physical address 0x{:08x}",
+            opts.i2cslave.len(),
+            paddress
+        );
+        bus.register(Box::new(I2cController::new(slaves)), paddress, SIZE)
+    } else {
+        Ok(())
+    }
+}
+
+fn setup_spi(opts: &Opts, bus: &mut Bus) -> Result<()> {
+    use spi::*;
+
+    if !opts.spislave.is_empty() {
+        let paddress = BASE_ADDRESS;
+        let slaves = opts.spislave.iter().map(|spec| spec.build()).collect();
+
+        println!(
+            "Mapping SPI Controller ({} slaves) to physical address 0x{:08x}",
+            opts.spislave.len(),
+            paddress
+        );
+        bus.register(Box::new(SpiController::new(slaves)), paddress, SIZE)
+    } else {
+        Ok(())
+    }
+}
+
+/// Maps the keyboard and returns the standard input console that feeds it.
+fn setup_keyboard(opts: &Opts, bus: &mut Bus) -> Result<Option<Console>> {
+    use keyboard::*;
+
+    if opts.keyboard {
+        let paddress = BASE_ADDRESS;
+        let (console, keys) = Console::stdin();
+        let keyboard = KeyboardDevice::new(keys);
+
+        println!(
+            "Mapping Keyboard (standard input) to physical address 0x{:08x}",
+            paddress
+        );
+        if console.is_raw() {
+            println!("Console escape is Ctrl-A: Ctrl-A x quits, Ctrl-A c shows the monitor");
+        }
+        bus.register(Box::new(keyboard), paddress, SIZE)?;
+        Ok(Some(console))
+    } else {
+        Ok(None)
+    }
+}
+
+fn setup_leds(opts: &Opts, bus: &mut Bus) -> Result<()> {
+    use leds::*;
+
+    if opts.leds {
+        let paddress = BASE_ADDRESS;
+
+        println!(
+            "Mapping LEDs ({} LEDs, {} digit display) to physical address 0x{:08x}",
+            LED_COUNT, DIGITS, paddress
+        );
+        bus.register(Box::new(LedDevice::default()), paddress, SIZE)
+    } else {
+        Ok(())
+    }
+}
+
+fn setup_debug_print(opts: &Opts, bus: &mut Bus) -> Result<Option<Receiver<Address>>> {
+    use debug_print::*;
+
+    if opts.debugprint {
+        let paddress = BASE_ADDRESS;
+        let (device, strings) = DebugPrintDevice::new();
+
+        println!("Mapping Debug Print to physical address 0x{:08x}", paddress);
+        bus.register(Box::new(device), paddress, SIZE)?;
+        Ok(Some(strings))
+    } else {
+        Ok(None)
+    }
+}
+
+fn setup_emulator_info(opts: &Opts, bus: &mut Bus, clock: Arc<dyn TimeSource>) -> Result<()> {
+    use emulator_info::*;
+
+    if opts.emulatorinfo {
+        let paddress = BASE_ADDRESS;
+        let mut features = Features::empty();
+        features.set(Features::FPU, !opts.nofpu);
+        features.set(Features::MMU, !opts.nommu);
+        features.set(Features::MONITOR_PROM, opts.monitorprom);
+        features.set(Features::DEBUG_PRINT, opts.debugprint);
+        features.set(Features::GDB_STUB, opts.debug);
+        let tlb_entries = opts
+            .tlbentries
+            .unwrap_or_else(|| opts.cpumodel.tlb_entries());
+        let device = EmulatorInfoDevice::new(features, tlb_entries, opts.cpumodel.prid(), clock);
+
+        println!(
+            "Mapping Emulator Info to physical address 0x{:08x}",
+            paddress
+        );
+        bus.register(Box::new(device), paddress, SIZE)
+    } else {
+        Ok(())
+    }
+}
+
+fn setup_clock(opts: &Opts, bus: &mut Bus, clock: Arc<dyn TimeSource>) -> Result<()> {
+    use crate::devices::clock::*;
+
+    if opts.clock {
+        let paddress = BASE_ADDRESS;
+
+        println!(
+            "Mapping Clock (IRQ {}) to physical address 0x{:08x}",
+            IRQ_LINE + 2,
+            paddress
+        );
+        bus.register(Box::new(ClockDevice::new(clock)), paddress, SIZE)
+    } else {
+        Ok(())
+    }
+}
+
+fn setup_prom(opts: &Opts, bus: &mut Bus) -> Result<()> {
+    use prom::*;
+
+    if opts.monitorprom {
+        let paddress = BASE_ADDRESS;
+        let monitor = MonitorProm::new(&opts.promenv, bus.endian());
+        let size = monitor.size();
+
+        println!(
+            "Mapping Monitor PROM ({} environment variables) to physical address 0x{:08x}",
+            opts.promenv.len(),
+            paddress
+        );
+        bus.register(Box::new(monitor), paddress, size)
     } else {
         Ok(())
     }
 }
 
-fn setup_testdevice(bus: &mut Bus) -> Result<()> {
+fn setup_testdevice(opts: &Opts, bus: &mut Bus) -> Result<Receiver<TestEvent>> {
     use test_device::*;
 
-    let paddress = BASE_ADDRESS;
-    let testdev = TestDevice::new();
+    let paddress = opts.testdeviceat.unwrap_or(BASE_ADDRESS);
+    let (testdev, events) = TestDevice::new();
 
     println!("Mapping Test Device to physical address 0x{:08x}", paddress);
-    bus.register(Box::new(testdev), paddress, DATA_LEN)
+    bus.register(Box::new(testdev), paddress, DATA_LEN)?;
+    Ok(events)
 }
 
 fn wait_for_tcp(ip: &str, port: u16) -> Result<TcpStream> {
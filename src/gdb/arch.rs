@@ -0,0 +1,359 @@
+//! GDB architecture description for the emulated R3000.
+//!
+//! GDB expects the register layout of its MIPS + DSP target, with only the CP0
+//! registers it requires (Status, BadVaddr and Cause). `RmipsArch` extends that
+//! register file with the remaining CP0 registers and advertises them through
+//! a custom target description, so they show up under `info registers cp0`.
+
+use std::convert::TryInto;
+use std::num::NonZeroUsize;
+
+use gdbstub::arch::{Arch, BreakpointKind, RegId, Registers};
+
+use crate::Endian;
+
+/// GDB register number of the first register in the `org.rmips.cp0` feature.
+const CP0_REGNUM_BASE: usize = 80;
+
+/// Number of registers in the `org.rmips.cp0` feature.
+const CP0_REGNUM_COUNT: usize = 7;
+
+/// Size of every register, the R3000 is a 32-bit CPU.
+const REG_SIZE: usize = 4;
+
+/// Implements `Arch` for the R3000 with the extra CP0 registers.
+pub enum RmipsArch {}
+
+impl Arch for RmipsArch {
+    type Usize = u32;
+    type Registers = RmipsRegs;
+    type RegId = RmipsRegId;
+    type BreakpointKind = MipsBreakpointKind;
+
+    fn target_description_xml() -> Option<&'static str> {
+        Some(include_str!("target.xml"))
+    }
+}
+
+/// Size of the instruction that GDB replaces with a software breakpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MipsBreakpointKind {
+    Mips16,
+    MicroMips16,
+    Mips32,
+    MicroMips32,
+}
+
+impl BreakpointKind for MipsBreakpointKind {
+    fn from_usize(kind: usize) -> Option<Self> {
+        let kind = match kind {
+            2 => MipsBreakpointKind::Mips16,
+            3 => MipsBreakpointKind::MicroMips16,
+            4 => MipsBreakpointKind::Mips32,
+            5 => MipsBreakpointKind::MicroMips32,
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+/// CP0 registers that GDB requires for MIPS targets.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MipsCp0Regs {
+    pub status: u32,
+    pub badvaddr: u32,
+    pub cause: u32,
+}
+
+/// Floating point registers of CP1.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MipsFpuRegs {
+    pub r: [u32; 32],
+    pub fcsr: u32,
+    pub fir: u32,
+}
+
+/// Registers of the `org.gnu.gdb.mips.cpu`, `cp0` and `fpu` features.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MipsCoreRegs {
+    pub r: [u32; 32],
+    pub lo: u32,
+    pub hi: u32,
+    pub pc: u32,
+    pub cp0: MipsCp0Regs,
+    pub fpu: MipsFpuRegs,
+}
+
+/// Registers of the DSP ASE and the Linux restart register.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MipsDspRegs {
+    pub hi1: u32,
+    pub lo1: u32,
+    pub hi2: u32,
+    pub lo2: u32,
+    pub hi3: u32,
+    pub lo3: u32,
+    pub dspctl: u32,
+    pub restart: u32,
+}
+
+/// Register file of GDB's MIPS + DSP target.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MipsCoreRegsWithDsp {
+    pub core: MipsCoreRegs,
+    pub dsp: MipsDspRegs,
+}
+
+/// CP0 registers not covered by `MipsCp0Regs`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RmipsCp0Regs {
+    pub index: u32,
+    pub random: u32,
+    pub entrylo: u32,
+    pub context: u32,
+    pub entryhi: u32,
+    pub epc: u32,
+    pub prid: u32,
+}
+
+/// Full register file, serialized in GDB register number order.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RmipsRegs {
+    pub mips: MipsCoreRegsWithDsp,
+    pub cp0: RmipsCp0Regs,
+    /// Byte order of the registers on the wire, which is the byte order of the target.
+    pub endian: Endian,
+}
+
+impl RmipsRegs {
+    /// Returns the registers that GDB sent for a target of the `endian` byte order.
+    ///
+    /// gdbstub decodes them into a default register file, which is little-endian, so the
+    /// values of a big-endian target have their bytes reversed until they are reinterpreted.
+    pub fn in_byte_order(&self, endian: Endian) -> RmipsRegs {
+        let mut regs = self.clone();
+        if regs.endian != endian {
+            regs.regs_mut()
+                .into_iter()
+                .for_each(|reg| *reg = reg.swap_bytes());
+            regs.endian = endian;
+        }
+        regs
+    }
+
+    /// Returns every register in GDB register number order.
+    fn regs_mut(&mut self) -> Vec<&mut u32> {
+        let core = &mut self.mips.core;
+        let dsp = &mut self.mips.dsp;
+        let cp0 = &mut self.cp0;
+
+        let mut regs: Vec<&mut u32> = core.r.iter_mut().collect();
+        regs.extend([
+            &mut core.cp0.status,
+            &mut core.lo,
+            &mut core.hi,
+            &mut core.cp0.badvaddr,
+            &mut core.cp0.cause,
+            &mut core.pc,
+        ]);
+        regs.extend(core.fpu.r.iter_mut());
+        regs.extend([
+            &mut core.fpu.fcsr,
+            &mut core.fpu.fir,
+            &mut dsp.hi1,
+            &mut dsp.lo1,
+            &mut dsp.hi2,
+            &mut dsp.lo2,
+            &mut dsp.hi3,
+            &mut dsp.lo3,
+            &mut dsp.dspctl,
+            &mut dsp.restart,
+            &mut cp0.index,
+            &mut cp0.random,
+            &mut cp0.entrylo,
+            &mut cp0.context,
+            &mut cp0.entryhi,
+            &mut cp0.epc,
+            &mut cp0.prid,
+        ]);
+        debug_assert_eq!(regs.len(), CP0_REGNUM_BASE + CP0_REGNUM_COUNT);
+        regs
+    }
+}
+
+impl Registers for RmipsRegs {
+    type ProgramCounter = u32;
+
+    fn pc(&self) -> Self::ProgramCounter {
+        self.mips.core.pc
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        for reg in self.clone().regs_mut() {
+            self.endian
+                .word_bytes(*reg)
+                .iter()
+                .for_each(|b| write_byte(Some(*b)));
+        }
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        let endian = self.endian;
+        let mut regs = self.regs_mut();
+        let bytes = bytes.get(..regs.len() * REG_SIZE).ok_or(())?;
+        for (reg, chunk) in regs.iter_mut().zip(bytes.chunks_exact(REG_SIZE)) {
+            **reg = endian.word(chunk.try_into().unwrap());
+        }
+        Ok(())
+    }
+}
+
+/// Identifier of a register of GDB's MIPS + DSP target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MipsRegId {
+    Gpr(u8),
+    Status,
+    Lo,
+    Hi,
+    Badvaddr,
+    Cause,
+    Pc,
+    Fpr(u8),
+    Fcsr,
+    Fir,
+    Hi1,
+    Lo1,
+    Hi2,
+    Lo2,
+    Hi3,
+    Lo3,
+    Dspctl,
+    Restart,
+}
+
+impl MipsRegId {
+    fn from_raw_id(id: usize) -> Option<Self> {
+        let reg = match id {
+            0..=31 => MipsRegId::Gpr(id as u8),
+            32 => MipsRegId::Status,
+            33 => MipsRegId::Lo,
+            34 => MipsRegId::Hi,
+            35 => MipsRegId::Badvaddr,
+            36 => MipsRegId::Cause,
+            37 => MipsRegId::Pc,
+            38..=69 => MipsRegId::Fpr((id - 38) as u8),
+            70 => MipsRegId::Fcsr,
+            71 => MipsRegId::Fir,
+            72 => MipsRegId::Hi1,
+            73 => MipsRegId::Lo1,
+            74 => MipsRegId::Hi2,
+            75 => MipsRegId::Lo2,
+            76 => MipsRegId::Hi3,
+            77 => MipsRegId::Lo3,
+            78 => MipsRegId::Dspctl,
+            79 => MipsRegId::Restart,
+            _ => return None,
+        };
+        Some(reg)
+    }
+}
+
+/// Register identifier covering the standard MIPS registers and the extra CP0 registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RmipsRegId {
+    Mips(MipsRegId),
+    Index,
+    Random,
+    EntryLo,
+    Context,
+    EntryHi,
+    Epc,
+    Prid,
+}
+
+impl RegId for RmipsRegId {
+    fn from_raw_id(id: usize) -> Option<(Self, Option<NonZeroUsize>)> {
+        let reg = match id.checked_sub(CP0_REGNUM_BASE) {
+            Some(0) => RmipsRegId::Index,
+            Some(1) => RmipsRegId::Random,
+            Some(2) => RmipsRegId::EntryLo,
+            Some(3) => RmipsRegId::Context,
+            Some(4) => RmipsRegId::EntryHi,
+            Some(5) => RmipsRegId::Epc,
+            Some(6) => RmipsRegId::Prid,
+            Some(_) => return None,
+            None => RmipsRegId::Mips(MipsRegId::from_raw_id(id)?),
+        };
+        Some((reg, NonZeroUsize::new(REG_SIZE)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn regs_roundtrip() {
+        let mut regs = RmipsRegs::default();
+        regs.mips.core.pc = 0xbfc0_0000;
+        regs.cp0.entryhi = 0x0000_1fc0;
+        regs.cp0.epc = 0x8000_0080;
+        regs.cp0.prid = 0x0000_0230;
+
+        let mut bytes = Vec::new();
+        regs.gdb_serialize(|b| bytes.push(b.unwrap_or(0)));
+        assert_eq!(bytes.len(), (CP0_REGNUM_BASE + CP0_REGNUM_COUNT) * 4);
+        assert_eq!(&bytes[37 * 4..38 * 4], &0xbfc0_0000_u32.to_le_bytes());
+        assert_eq!(&bytes[85 * 4..86 * 4], &0x8000_0080_u32.to_le_bytes());
+
+        let mut decoded = RmipsRegs::default();
+        decoded.gdb_deserialize(&bytes).unwrap();
+        assert_eq!(decoded, regs);
+    }
+
+    #[test]
+    fn regs_big_endian() {
+        let mut regs = RmipsRegs {
+            endian: Endian::Big,
+            ..Default::default()
+        };
+        regs.mips.core.pc = 0xbfc0_0000;
+
+        let mut bytes = Vec::new();
+        regs.gdb_serialize(|b| bytes.push(b.unwrap_or(0)));
+        assert_eq!(&bytes[37 * 4..38 * 4], &[0xbf, 0xc0, 0, 0]);
+
+        // gdbstub decodes the registers that GDB writes as little-endian
+        let mut decoded = RmipsRegs::default();
+        decoded.gdb_deserialize(&bytes).unwrap();
+        assert_eq!(decoded.in_byte_order(Endian::Big), regs);
+    }
+
+    #[test]
+    fn regid_from_raw_id() {
+        assert!(matches!(
+            RmipsRegId::from_raw_id(37),
+            Some((RmipsRegId::Mips(MipsRegId::Pc), Some(_)))
+        ));
+        assert!(matches!(
+            RmipsRegId::from_raw_id(80),
+            Some((RmipsRegId::Index, Some(_)))
+        ));
+        assert!(matches!(
+            RmipsRegId::from_raw_id(85),
+            Some((RmipsRegId::Epc, Some(_)))
+        ));
+        assert!(RmipsRegId::from_raw_id(87).is_none());
+    }
+
+    #[test]
+    fn target_description_lists_cp0_registers() {
+        let xml = RmipsArch::target_description_xml().unwrap();
+        for name in &[
+            "index", "random", "entrylo", "context", "entryhi", "epc", "prid",
+        ] {
+            assert!(xml.contains(&format!("<reg name=\"{}\"", name)));
+        }
+    }
+}
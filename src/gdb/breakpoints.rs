@@ -5,14 +5,20 @@ use gdbstub::target::TargetResult;
 use crate::emulator::Emulator;
 use crate::Address;
 
+use super::arch::MipsBreakpointKind;
+
 impl target::ext::breakpoints::Breakpoints for Emulator {
     #[inline(always)]
-    fn sw_breakpoint(&mut self) -> Option<target::ext::breakpoints::SwBreakpointOps<Self>> {
+    fn support_sw_breakpoint(
+        &mut self,
+    ) -> Option<target::ext::breakpoints::SwBreakpointOps<'_, Self>> {
         Some(self)
     }
 
     #[inline(always)]
-    fn hw_watchpoint(&mut self) -> Option<target::ext::breakpoints::HwWatchpointOps<Self>> {
+    fn support_hw_watchpoint(
+        &mut self,
+    ) -> Option<target::ext::breakpoints::HwWatchpointOps<'_, Self>> {
         Some(self)
     }
 }
@@ -21,7 +27,7 @@ impl target::ext::breakpoints::SwBreakpoint for Emulator {
     fn add_sw_breakpoint(
         &mut self,
         address: Address,
-        _kind: gdbstub_arch::mips::MipsBreakpointKind,
+        _kind: MipsBreakpointKind,
     ) -> TargetResult<bool, Self> {
         self.breakpoints.push(address);
         Ok(true)
@@ -30,7 +36,7 @@ impl target::ext::breakpoints::SwBreakpoint for Emulator {
     fn remove_sw_breakpoint(
         &mut self,
         address: Address,
-        _kind: gdbstub_arch::mips::MipsBreakpointKind,
+        _kind: MipsBreakpointKind,
     ) -> TargetResult<bool, Self> {
         match self.breakpoints.iter().position(|x| *x == address) {
             None => return Ok(false),
@@ -42,11 +48,16 @@ impl target::ext::breakpoints::SwBreakpoint for Emulator {
 }
 
 impl target::ext::breakpoints::HwWatchpoint for Emulator {
-    fn add_hw_watchpoint(&mut self, address: Address, kind: WatchKind) -> TargetResult<bool, Self> {
+    fn add_hw_watchpoint(
+        &mut self,
+        address: Address,
+        _len: Address,
+        kind: WatchKind,
+    ) -> TargetResult<bool, Self> {
         match kind {
-            WatchKind::Write => self.watchpoints.push(address),
-            WatchKind::Read => self.watchpoints.push(address),
-            WatchKind::ReadWrite => self.watchpoints.push(address),
+            WatchKind::Write => self.bus.watchpoints.add(address),
+            WatchKind::Read => self.bus.watchpoints.add(address),
+            WatchKind::ReadWrite => self.bus.watchpoints.add(address),
         };
 
         Ok(true)
@@ -55,19 +66,13 @@ impl target::ext::breakpoints::HwWatchpoint for Emulator {
     fn remove_hw_watchpoint(
         &mut self,
         address: Address,
+        _len: Address,
         kind: WatchKind,
     ) -> TargetResult<bool, Self> {
-        let pos = match self.watchpoints.iter().position(|x| *x == address) {
-            None => return Ok(false),
-            Some(pos) => pos,
-        };
-
-        match kind {
-            WatchKind::Write => self.watchpoints.remove(pos),
-            WatchKind::Read => self.watchpoints.remove(pos),
-            WatchKind::ReadWrite => self.watchpoints.remove(pos),
-        };
-
-        Ok(true)
+        Ok(match kind {
+            WatchKind::Write => self.bus.watchpoints.remove(address),
+            WatchKind::Read => self.bus.watchpoints.remove(address),
+            WatchKind::ReadWrite => self.bus.watchpoints.remove(address),
+        })
     }
 }
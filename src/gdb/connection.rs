@@ -0,0 +1,56 @@
+use std::net::TcpStream;
+
+use gdbstub::conn::{Connection, ConnectionExt};
+
+/// A GDB connection that sends each packet with a single write.
+///
+/// The `Connection` implementation of `TcpStream` writes every byte of a packet separately,
+/// and since the stub disables Nagle's algorithm, a memory dump sends one TCP segment per
+/// hex digit. Writes are buffered here until the stub flushes the end of a packet instead.
+pub struct BufferedConnection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl BufferedConnection {
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl Connection for BufferedConnection {
+    type Error = std::io::Error;
+
+    fn write(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.buffer.push(byte);
+        Ok(())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.buffer.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Connection::write_all(&mut self.stream, &self.buffer)?;
+        self.buffer.clear();
+        Connection::flush(&mut self.stream)
+    }
+
+    fn on_session_start(&mut self) -> Result<(), Self::Error> {
+        self.stream.on_session_start()
+    }
+}
+
+impl ConnectionExt for BufferedConnection {
+    fn read(&mut self) -> Result<u8, Self::Error> {
+        ConnectionExt::read(&mut self.stream)
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, Self::Error> {
+        ConnectionExt::peek(&mut self.stream)
+    }
+}
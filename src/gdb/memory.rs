@@ -0,0 +1,208 @@
+use std::fmt;
+
+use log::debug;
+
+use crate::control::exception::Exception;
+use crate::emulator::Emulator;
+use crate::memory::AccessContext;
+use crate::Address;
+
+/// Smallest page size mapped by the TLB, within which translated addresses are contiguous.
+const PAGE_SIZE: usize = 0x1000;
+
+/// Granularity that a failed region access is retried with to find the faulting address.
+const WORD_SIZE: usize = 4;
+
+/// Error code sent to GDB when an address has no translation, `EFAULT`.
+const TRANSLATION_ERRNO: u8 = 14;
+
+/// Error code sent to GDB when a physical access fails, `EIO`.
+const BUS_ERRNO: u8 = 5;
+
+/// Why the debugger could not access a virtual address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryFault {
+    /// The virtual address has no translation in the current processor mode.
+    Translation {
+        address: Address,
+        exception: Exception,
+    },
+    /// The translated address is not backed by a device, or the device refused the access.
+    Bus { address: Address, physical: Address },
+}
+
+impl MemoryFault {
+    /// Returns the error code GDB is answered with, which keeps both kinds of faults apart.
+    pub fn errno(&self) -> u8 {
+        match self {
+            MemoryFault::Translation { .. } => TRANSLATION_ERRNO,
+            MemoryFault::Bus { .. } => BUS_ERRNO,
+        }
+    }
+}
+
+impl fmt::Display for MemoryFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryFault::Translation { address, exception } => write!(
+                f,
+                "translation fault at 0x{:08x} ({:?})",
+                address, exception
+            ),
+            MemoryFault::Bus { address, physical } => write!(
+                f,
+                "bus error at 0x{:08x} (physical address 0x{:08x})",
+                address, physical
+            ),
+        }
+    }
+}
+
+/// A debugger access that stopped at a fault, kept for `monitor fault`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaultedAccess {
+    pub write: bool,
+    pub address: Address,
+    pub len: usize,
+    /// Number of bytes accessed before the fault.
+    pub done: usize,
+    pub fault: MemoryFault,
+}
+
+impl fmt::Display for FaultedAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = if self.write { "Write" } else { "Read" };
+        write!(
+            f,
+            "{} of {} bytes at 0x{:08x} stopped after {} bytes: {}",
+            access, self.len, self.address, self.done, self.fault
+        )
+    }
+}
+
+/// Splits an access into the pieces that fit into a page, and then into a word.
+fn pieces(address: Address, len: usize, size: usize) -> impl Iterator<Item = (Address, usize)> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        if offset == len {
+            return None;
+        }
+        let address = address.wrapping_add(offset as Address);
+        let piece = (size - (address as usize & (size - 1))).min(len - offset);
+        offset += piece;
+        Some((address, piece))
+    })
+}
+
+impl Emulator {
+    /// Translates a virtual address for a debugger access.
+    fn debugger_translate(&self, address: Address) -> Result<Address, MemoryFault> {
+        self.cpu
+            .cpzero
+            .translate_access(address, AccessContext::Debugger)
+            .map_err(|exception| MemoryFault::Translation { address, exception })
+    }
+
+    /// Accesses the virtual range `address..address+len` with `access`, translating once per
+    /// page and retrying a failed page a word at a time to find where it faults.
+    ///
+    /// Returns the number of bytes accessed and the fault that stopped the access, if any.
+    fn debugger_access(
+        &mut self,
+        address: Address,
+        len: usize,
+        mut access: impl FnMut(&mut Self, Address, usize, usize) -> bool,
+    ) -> (usize, Option<MemoryFault>) {
+        let mut done = 0;
+        for (page, page_len) in pieces(address, len, PAGE_SIZE) {
+            let physical = match self.debugger_translate(page) {
+                Ok(physical) => physical,
+                Err(fault) => return (done, Some(fault)),
+            };
+            if access(self, physical, done, page_len) {
+                done += page_len;
+                continue;
+            }
+
+            for (word, word_len) in pieces(page, page_len, WORD_SIZE) {
+                let physical = physical.wrapping_add(word.wrapping_sub(page));
+                if !access(self, physical, done, word_len) {
+                    let fault = MemoryFault::Bus {
+                        address: word,
+                        physical,
+                    };
+                    return (done, Some(fault));
+                }
+                done += word_len;
+            }
+        }
+        (done, None)
+    }
+
+    /// Reads virtual memory for the debugger, filling `data` up to the first fault.
+    ///
+    /// Returns the number of bytes read, which is only an error if the first byte faults.
+    pub(crate) fn debugger_read(&mut self, address: Address, data: &mut [u8]) -> Result<usize, u8> {
+        let (done, fault) = self.debugger_access(address, data.len(), |emu, physical, at, len| {
+            emu.bus.peek(physical, &mut data[at..at + len]).is_ok()
+        });
+        match self.record_fault(false, address, data.len(), done, fault) {
+            Err(errno) if done == 0 => Err(errno),
+            _ => Ok(done),
+        }
+    }
+
+    /// Writes virtual memory for the debugger, stopping at the first fault.
+    pub(crate) fn debugger_write(&mut self, address: Address, data: &[u8]) -> Result<(), u8> {
+        let (done, fault) = self.debugger_access(address, data.len(), |emu, physical, at, len| {
+            // A page spanning two devices fails, so the word retry finds where the first one ends
+            emu.bus
+                .write(physical, &data[at..at + len], AccessContext::Debugger)
+                .is_ok()
+        });
+        self.record_fault(true, address, data.len(), done, fault)
+    }
+
+    /// Keeps the fault of a debugger access and returns the error code for GDB.
+    fn record_fault(
+        &mut self,
+        write: bool,
+        address: Address,
+        len: usize,
+        done: usize,
+        fault: Option<MemoryFault>,
+    ) -> Result<(), u8> {
+        let fault = match fault {
+            Some(fault) => fault,
+            None => return Ok(()),
+        };
+        let access = FaultedAccess {
+            write,
+            address,
+            len,
+            done,
+            fault,
+        };
+        debug!("GDB memory access failed: {}", access);
+        self.gdb_fault = Some(access);
+        Err(fault.errno())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn access_pieces() {
+        let split: Vec<_> = pieces(0x0ff8, 0x1010, PAGE_SIZE).collect();
+        assert_eq!(split, vec![(0x0ff8, 8), (0x1000, 0x1000), (0x2000, 8)]);
+
+        let split: Vec<_> = pieces(0x0ffe, 7, WORD_SIZE).collect();
+        assert_eq!(split, vec![(0x0ffe, 2), (0x1000, 4), (0x1004, 1)]);
+
+        let split: Vec<_> = pieces(0xffff_fffe, 4, PAGE_SIZE).collect();
+        assert_eq!(split, vec![(0xffff_fffe, 2), (0, 2)]);
+    }
+}
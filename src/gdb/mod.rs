@@ -1,95 +1,295 @@
 use std::convert::TryInto;
 
 use gdbstub::arch::Arch;
+use gdbstub::common::Signal;
+use gdbstub::conn::{Connection, ConnectionExt};
+use gdbstub::stub::run_blocking::{BlockingEventLoop, Event, WaitForStopReasonError};
+use gdbstub::stub::{MultiThreadStopReason, SingleThreadStopReason};
 use gdbstub::target;
 use gdbstub::target::ext::base::singlethread::{
-    GdbInterrupt, ResumeAction, SingleThreadOps, StopReason,
+    SingleThreadBase, SingleThreadRangeStepping, SingleThreadRangeSteppingOps, SingleThreadResume,
+    SingleThreadResumeOps, SingleThreadSingleStep, SingleThreadSingleStepOps,
 };
 use gdbstub::target::ext::breakpoints::WatchKind;
+use gdbstub::target::ext::monitor_cmd::{outputln, ConsoleOutput, MonitorCmd};
 use gdbstub::target::{Target, TargetError, TargetResult};
-use gdbstub_arch::mips::reg::id::MipsRegId;
-use log::error;
 
+use crate::control::coprocessor::Coprocessor;
+use crate::control::cp1::{FCSR, FIR};
+use crate::control::cpu::DspRegisters;
+use crate::control::registers::Cp0Register;
 use crate::emulator::Emulator;
-use crate::memory::Memory;
+use crate::session::unique;
 use crate::util::error::RmipsError;
-use crate::{Address, EmulationEvent};
+use crate::{AccessKind, Address, EmulationEvent, HaltReason, ResetReason};
 
+use self::arch::{MipsRegId, RmipsArch, RmipsRegId};
+
+pub(crate) use self::connection::BufferedConnection;
+pub(crate) use self::memory::FaultedAccess;
+pub(crate) use self::threads::ThreadResume;
+
+mod arch;
 mod breakpoints;
+mod connection;
+mod memory;
+mod threads;
+
+/// Size of the packet buffer, which bounds the memory GDB reads with a single packet.
+pub(crate) const PACKET_SIZE: usize = 0x10000;
+
+/// How often a run checks the connection for an interrupt from GDB, in instructions.
+const INTERRUPT_CHECK_INTERVAL: usize = 1024;
 
 impl Target for Emulator {
-    type Arch = gdbstub_arch::mips::MipsWithDsp;
+    type Arch = RmipsArch;
     type Error = RmipsError;
 
     #[inline(always)]
-    fn base_ops(&mut self) -> target::ext::base::BaseOps<Self::Arch, Self::Error> {
-        target::ext::base::BaseOps::SingleThread(self)
+    fn base_ops(&mut self) -> target::ext::base::BaseOps<'_, Self::Arch, Self::Error> {
+        match self.rtos {
+            Some(_) => target::ext::base::BaseOps::MultiThread(self),
+            None => target::ext::base::BaseOps::SingleThread(self),
+        }
     }
 
     #[inline(always)]
-    fn breakpoints(&mut self) -> Option<target::ext::breakpoints::BreakpointsOps<Self>> {
+    fn support_breakpoints(
+        &mut self,
+    ) -> Option<target::ext::breakpoints::BreakpointsOps<'_, Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_monitor_cmd(&mut self) -> Option<target::ext::monitor_cmd::MonitorCmdOps<'_, Self>> {
         Some(self)
     }
 }
 
-impl Emulator {
-    fn inner_resume(
+impl MonitorCmd for Emulator {
+    fn handle_monitor_cmd(
         &mut self,
-        action: ResumeAction,
-        mut check_gdb_interrupt: impl FnMut() -> bool,
-    ) -> Result<StopReason<Address>, <Emulator as Target>::Error> {
-        let event = match action {
-            ResumeAction::Step | ResumeAction::StepWithSignal(_) => match self.step()? {
-                EmulationEvent::Step => return Ok(StopReason::DoneStep),
-                event => event,
+        cmd: &[u8],
+        mut out: ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        match cmd {
+            b"memmap" => outputln!(out, "{}", self.memory_map_summary()),
+            b"fault" => match self.gdb_fault {
+                Some(access) => outputln!(out, "{}", access),
+                None => outputln!(out, "No memory access has faulted"),
             },
-            ResumeAction::Continue | ResumeAction::ContinueWithSignal(_) => {
-                let mut cycles = 0;
-                loop {
-                    let event = self.step()?;
-                    if event != EmulationEvent::Step {
-                        break event;
-                    };
-
-                    // Check for GDB interrupt every 1024 instructions
-                    cycles += 1;
-                    if cycles % 1024 == 0 && check_gdb_interrupt() {
-                        return Ok(StopReason::GdbInterrupt);
-                    }
-                }
+            b"threads" => outputln!(out, "{}", self.thread_summary()),
+            b"mmio" => outputln!(out, "{}", self.register_summary()),
+            b"session" => outputln!(out, "{}", self.session_summary()),
+            // GDB inserts its own breakpoints again when it resumes the CPU
+            b"session clear" => {
+                self.breakpoints.clear();
+                self.bus.watchpoints.clear_addresses();
+                outputln!(
+                    out,
+                    "Removed the breakpoints and watchpoints of the session"
+                );
             }
+            b"reset" => {
+                self.reset(ResetReason::Debugger);
+                outputln!(out, "Reset the CPU, PC=0x{:08x}", self.cpu.pc);
+            }
+            _ => outputln!(
+                out,
+                "Supported monitor commands: memmap, fault, threads, mmio, session, reset"
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Runs the emulator between the resume and stop packets of a GDB session.
+pub(crate) enum GdbEventLoop {}
+
+impl BlockingEventLoop for GdbEventLoop {
+    type Target = Emulator;
+    type Connection = BufferedConnection;
+    type StopReason = MultiThreadStopReason<Address>;
+
+    fn wait_for_stop_reason(
+        target: &mut Emulator,
+        conn: &mut BufferedConnection,
+    ) -> Result<
+        Event<Self::StopReason>,
+        WaitForStopReasonError<RmipsError, <BufferedConnection as Connection>::Error>,
+    > {
+        // GDB inserts every enabled breakpoint and watchpoint before it resumes
+        target.update_debug_session();
+
+        // The action stays in place while a run is interrupted by a packet other than Ctrl-C
+        let action = target.thread_resume.unwrap_or(ThreadResume::Continue);
+        let reason = target
+            .resume_with(action, || !matches!(conn.peek(), Ok(None)))
+            .map_err(WaitForStopReasonError::Target)?;
+
+        match reason {
+            Some(reason) => {
+                target.thread_resume = None;
+                Ok(Event::TargetStopped(target.thread_stop_reason(reason)))
+            }
+            None => conn
+                .read()
+                .map(Event::IncomingData)
+                .map_err(WaitForStopReasonError::Connection),
+        }
+    }
+
+    fn on_interrupt(target: &mut Emulator) -> Result<Option<Self::StopReason>, RmipsError> {
+        target.thread_resume = None;
+        target.record_stop("Interrupt".to_owned());
+        Ok(Some(MultiThreadStopReason::Signal(Signal::SIGINT)))
+    }
+}
+
+impl Emulator {
+    /// Records a stop of the emulator reported to the debugger on the timeline.
+    fn record_stop(&mut self, reason: String) {
+        let args = vec![("pc", format!("0x{:08x}", self.cpu.pc))];
+        self.record_event("gdb", format!("GDB stop: {}", reason), args);
+    }
+
+    /// Takes the breakpoints and watchpoints that are inserted for a resume into the debug
+    /// session, while GDB removes them whenever the CPU stops.
+    fn update_debug_session(&mut self) {
+        if let Some(session) = &mut self.session {
+            session.breakpoints = unique(&self.breakpoints);
+            session.watchpoints = unique(self.bus.watchpoints.addresses());
+        }
+    }
+
+    /// Returns the FPU for register accesses, which fail when it is not attached.
+    fn fpu(&self) -> TargetResult<&dyn Coprocessor, Self> {
+        self.cpu
+            .attached_coprocessor(1)
+            .ok_or(TargetError::NonFatal)
+    }
+
+    fn fpu_mut(&mut self) -> TargetResult<&mut dyn Coprocessor, Self> {
+        self.cpu
+            .attached_coprocessor_mut(1)
+            .ok_or(TargetError::NonFatal)
+    }
+
+    /// Describes the breakpoints and watchpoints of the debug session, for `monitor session`.
+    fn session_summary(&self) -> String {
+        let Some(session) = &self.session else {
+            return "No --debug-session is given".to_owned();
         };
+        let addresses = |addresses: &[Address]| {
+            addresses
+                .iter()
+                .map(|address| format!(" 0x{:08x}", address))
+                .collect::<String>()
+        };
+        format!(
+            "Breakpoints:{}\nWatchpoints:{}\n{} patches",
+            addresses(&unique(&self.breakpoints)),
+            addresses(&unique(self.bus.watchpoints.addresses())),
+            session.patches.len()
+        )
+    }
 
-        Ok(match event {
-            EmulationEvent::Halted => StopReason::Terminated(19), // SIGSTOP
-            EmulationEvent::Breakpoint => StopReason::SwBreak,
-            EmulationEvent::Step => StopReason::DoneStep,
-            EmulationEvent::WatchWrite(address) => StopReason::Watch {
-                kind: WatchKind::Write,
-                addr: address,
+    /// Resumes the CPU as GDB requested, until it stops or `gdb_interrupt` returns true.
+    ///
+    /// Returns `None` if GDB sent a packet while the CPU was running.
+    fn resume_with(
+        &mut self,
+        action: ThreadResume,
+        gdb_interrupt: impl FnMut() -> bool,
+    ) -> Result<Option<SingleThreadStopReason<Address>>, RmipsError> {
+        match action {
+            ThreadResume::Step => match self.step()? {
+                event if !stops_debugger(&event) => Ok(Some(SingleThreadStopReason::DoneStep)),
+                event => Ok(Some(self.stop_reason(event))),
             },
-            EmulationEvent::WatchRead(address) => StopReason::Watch {
-                kind: WatchKind::Read,
+            ThreadResume::Continue => self.run_while(|_| true, gdb_interrupt),
+            ThreadResume::RangeStep { start, end } => {
+                self.run_while(|pc| (start..end).contains(&pc), gdb_interrupt)
+            }
+        }
+    }
+
+    /// Steps while `keep_going` returns true for the PC after each instruction, until an event
+    /// stops the run or GDB sends a packet, in which case `None` is returned.
+    fn run_while(
+        &mut self,
+        mut keep_going: impl FnMut(Address) -> bool,
+        mut gdb_interrupt: impl FnMut() -> bool,
+    ) -> Result<Option<SingleThreadStopReason<Address>>, RmipsError> {
+        let mut cycles = 0;
+        loop {
+            let event = self.step()?;
+            if stops_debugger(&event) {
+                return Ok(Some(self.stop_reason(event)));
+            }
+            if !keep_going(self.cpu.pc) {
+                return Ok(Some(SingleThreadStopReason::DoneStep));
+            }
+
+            cycles += 1;
+            if cycles % INTERRUPT_CHECK_INTERVAL == 0 && gdb_interrupt() {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Records an event that stopped a run and returns how it is reported to GDB.
+    fn stop_reason(&mut self, event: EmulationEvent) -> SingleThreadStopReason<Address> {
+        self.record_stop(format!("{:?}", event));
+        match event {
+            EmulationEvent::Halted(HaltReason::InstructionBusError) => {
+                SingleThreadStopReason::Terminated(Signal::SIGBUS)
+            }
+            // The guest stays stopped at the offending instruction
+            EmulationEvent::Halted(HaltReason::GuardRegion(_)) => {
+                SingleThreadStopReason::Signal(Signal::SIGSEGV)
+            }
+            // GDB only receives the low byte of the exit status, like a POSIX parent process
+            EmulationEvent::Halted(reason) => match reason.exit_code() {
+                Some(status) => SingleThreadStopReason::Exited(status as u8),
+                None => SingleThreadStopReason::Signal(Signal::SIGTRAP),
+            },
+            EmulationEvent::Breakpoint { .. } => SingleThreadStopReason::SwBreak(()),
+            EmulationEvent::Watch { kind, address, .. } => SingleThreadStopReason::Watch {
+                tid: (),
+                kind: match kind {
+                    AccessKind::Read => WatchKind::Read,
+                    AccessKind::Write => WatchKind::Write,
+                },
                 addr: address,
             },
-        })
+            EmulationEvent::WatchExpression(_) | EmulationEvent::LimitReached => {
+                SingleThreadStopReason::Signal(Signal::SIGTRAP)
+            }
+            // "Power fail/restart", the session stays connected across the reset
+            EmulationEvent::Reset(_) => SingleThreadStopReason::Signal(Signal::SIGPWR),
+            _ => SingleThreadStopReason::DoneStep,
+        }
     }
 }
 
-impl SingleThreadOps for Emulator {
-    fn resume(
-        &mut self,
-        action: ResumeAction,
-        gdb_interrupt: GdbInterrupt<'_>,
-    ) -> Result<StopReason<Address>, Self::Error> {
-        let mut gdb_interrupt = gdb_interrupt.no_async();
-        self.inner_resume(action, || gdb_interrupt.pending())
+/// Returns true if `event` stops a run for the debugger. A reset only stops a run under GDB,
+/// so the debugger can follow the program through a warm reboot.
+fn stops_debugger(event: &EmulationEvent) -> bool {
+    event.is_stop() || matches!(event, EmulationEvent::Reset(_))
+}
+
+impl SingleThreadBase for Emulator {
+    #[inline(always)]
+    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+        Some(self)
     }
 
     #[inline(always)]
-    fn single_register_access(
+    fn support_single_register_access(
         &mut self,
-    ) -> Option<target::ext::base::SingleRegisterAccessOps<(), Self>> {
+    ) -> Option<target::ext::base::single_register_access::SingleRegisterAccessOps<'_, (), Self>>
+    {
         Some(self)
     }
 
@@ -97,13 +297,40 @@ impl SingleThreadOps for Emulator {
         &mut self,
         regs: &mut <Self::Arch as Arch>::Registers,
     ) -> TargetResult<(), Self> {
-        regs.core.r = self.cpu.reg;
-        regs.core.lo = self.cpu.low;
-        regs.core.hi = self.cpu.high;
-        regs.core.pc = self.cpu.pc;
-        regs.core.cp0.status = self.cpu.cpzero.status.into();
-        regs.core.cp0.badvaddr = self.cpu.cpzero.badvaddr.into();
-        regs.core.cp0.cause = self.cpu.cpzero.cause.into();
+        regs.endian = self.bus.endian();
+        let cpzero = &self.cpu.cpzero;
+        let core = &mut regs.mips.core;
+        core.r = self.cpu.reg;
+        core.lo = self.cpu.low;
+        core.hi = self.cpu.high;
+        core.pc = self.cpu.pc;
+        core.cp0.status = cpzero.status.into();
+        core.cp0.badvaddr = cpzero.badvaddr.into();
+        core.cp0.cause = cpzero.cause.into();
+        // Without an FPU the registers read as zero, and the zero FIR tells GDB there is none
+        if let Some(fpu) = self.cpu.attached_coprocessor(1) {
+            core.fpu.r = std::array::from_fn(|reg| fpu.read_register(reg));
+            core.fpu.fcsr = fpu.read_control(FCSR);
+            core.fpu.fir = fpu.read_control(FIR);
+        }
+        let dsp = &mut regs.mips.dsp;
+        let accumulators = &self.cpu.dsp;
+        dsp.hi1 = accumulators.high[0];
+        dsp.lo1 = accumulators.low[0];
+        dsp.hi2 = accumulators.high[1];
+        dsp.lo2 = accumulators.low[1];
+        dsp.hi3 = accumulators.high[2];
+        dsp.lo3 = accumulators.low[2];
+        dsp.dspctl = accumulators.control;
+        // There is no interrupted system call for the Linux restart register to refer to
+        dsp.restart = 0;
+        regs.cp0.index = cpzero.index.into();
+        regs.cp0.random = cpzero.random.into();
+        regs.cp0.entrylo = cpzero.entrylo;
+        regs.cp0.context = cpzero.context.into();
+        regs.cp0.entryhi = cpzero.entryhi;
+        regs.cp0.epc = cpzero.epc.into();
+        regs.cp0.prid = cpzero.prid.into();
         Ok(())
     }
 
@@ -111,89 +338,403 @@ impl SingleThreadOps for Emulator {
         &mut self,
         regs: &<Self::Arch as Arch>::Registers,
     ) -> TargetResult<(), Self> {
-        self.cpu.reg = regs.core.r;
-        self.cpu.low = regs.core.lo;
-        self.cpu.high = regs.core.hi;
-        self.cpu.pc = regs.core.pc;
-        self.cpu.cpzero.status = regs.core.cp0.status.into();
-        self.cpu.cpzero.badvaddr = regs.core.cp0.badvaddr.into();
-        self.cpu.cpzero.cause = regs.core.cp0.cause.into();
+        let regs = &regs.in_byte_order(self.bus.endian());
+        let cpzero = &mut self.cpu.cpzero;
+        let core = &regs.mips.core;
+        self.cpu.reg = core.r;
+        self.cpu.low = core.lo;
+        self.cpu.high = core.hi;
+        self.cpu.pc = core.pc;
+        // CP0 is written like mtc0 does, so read-only fields and reserved bits are kept
+        cpzero.write_register(Cp0Register::Status, core.cp0.status);
+        cpzero.write_register(Cp0Register::BadVaddr, core.cp0.badvaddr);
+        cpzero.write_register(Cp0Register::Cause, core.cp0.cause);
+        let dsp = &regs.mips.dsp;
+        self.cpu.dsp = DspRegisters {
+            high: [dsp.hi1, dsp.hi2, dsp.hi3],
+            low: [dsp.lo1, dsp.lo2, dsp.lo3],
+            control: dsp.dspctl,
+        };
+        cpzero.write_register(Cp0Register::Index, regs.cp0.index);
+        cpzero.write_register(Cp0Register::Random, regs.cp0.random);
+        cpzero.write_register(Cp0Register::EntryLo, regs.cp0.entrylo);
+        cpzero.write_register(Cp0Register::Context, regs.cp0.context);
+        cpzero.write_register(Cp0Register::EntryHi, regs.cp0.entryhi);
+        cpzero.write_register(Cp0Register::Epc, regs.cp0.epc);
+        cpzero.write_register(Cp0Register::Prid, regs.cp0.prid);
+        if let Some(fpu) = self.cpu.attached_coprocessor_mut(1) {
+            for (reg, value) in regs.mips.core.fpu.r.iter().enumerate() {
+                fpu.write_register(reg, *value);
+            }
+            fpu.write_control(FCSR, regs.mips.core.fpu.fcsr);
+        }
         Ok(())
     }
 
-    fn read_addrs(&mut self, start_address: Address, data: &mut [u8]) -> TargetResult<(), Self> {
-        for (address, value) in (start_address..).zip(data.iter_mut()) {
-            let address = self.cpu.cpzero.translate(address);
-            *value = match self.bus.fetch_byte(address) {
-                Ok(v) => v,
-                Err(err) => {
-                    error!("GDB failed to access memory: {}", err);
-                    return Err(TargetError::NonFatal);
-                }
-            };
-        }
-        Ok(())
+    fn read_addrs(&mut self, start_address: Address, data: &mut [u8]) -> TargetResult<usize, Self> {
+        // GDB gets the bytes before a fault, `monitor fault` shows where and why it stopped
+        self.debugger_read(start_address, data)
+            .map_err(TargetError::Errno)
     }
 
     fn write_addrs(&mut self, start_address: Address, data: &[u8]) -> TargetResult<(), Self> {
-        for (address, value) in (start_address..).zip(data.iter().copied()) {
-            let address = self.cpu.cpzero.translate(address);
-            if let Err(err) = self.bus.store_byte(address, value) {
-                error!("GDB failed to access memory: {}", err);
-                return Err(TargetError::NonFatal);
-            };
-        }
+        self.debugger_write(start_address, data)
+            .map_err(TargetError::Errno)
+    }
+}
+
+/// The resume methods only record what GDB requested, `GdbEventLoop` runs the CPU.
+impl SingleThreadResume for Emulator {
+    fn resume(&mut self, _signal: Option<Signal>) -> Result<(), Self::Error> {
+        self.thread_resume = Some(ThreadResume::Continue);
         Ok(())
     }
+
+    #[inline(always)]
+    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_range_step(&mut self) -> Option<SingleThreadRangeSteppingOps<'_, Self>> {
+        Some(self)
+    }
 }
 
-impl target::ext::base::SingleRegisterAccess<()> for Emulator {
+impl SingleThreadSingleStep for Emulator {
+    fn step(&mut self, _signal: Option<Signal>) -> Result<(), Self::Error> {
+        self.thread_resume = Some(ThreadResume::Step);
+        Ok(())
+    }
+}
+
+impl SingleThreadRangeStepping for Emulator {
+    /// Steps while the PC stays in `start..end`, the lines of a source statement. A call leaves
+    /// the range, and GDB steps over it with a breakpoint at the return address.
+    fn resume_range_step(&mut self, start: Address, end: Address) -> Result<(), Self::Error> {
+        self.thread_resume = Some(ThreadResume::RangeStep { start, end });
+        Ok(())
+    }
+}
+
+impl target::ext::base::single_register_access::SingleRegisterAccess<()> for Emulator {
     fn read_register(
         &mut self,
         _tid: (),
-        reg_id: gdbstub_arch::mips::reg::id::MipsRegId<Address>,
+        reg_id: RmipsRegId,
         dst: &mut [u8],
-    ) -> TargetResult<(), Self> {
+    ) -> TargetResult<usize, Self> {
         let w = match reg_id {
-            MipsRegId::Gpr(i) => self.cpu.reg[i as usize],
-            MipsRegId::Status => self.cpu.cpzero.status.into(),
-            MipsRegId::Lo => self.cpu.low,
-            MipsRegId::Hi => self.cpu.high,
-            MipsRegId::Badvaddr => self.cpu.cpzero.badvaddr.into(),
-            MipsRegId::Cause => self.cpu.cpzero.cause.into(),
-            MipsRegId::Pc => self.cpu.pc,
-            // MipsRegId::Fpr(i) => todo!(),
-            // MipsRegId::Fcsr => todo!(),
-            // MipsRegId::Fir => todo!(),
-            _ => return Err(().into()),
+            RmipsRegId::Mips(MipsRegId::Gpr(i)) => self.cpu.reg[i as usize],
+            RmipsRegId::Mips(MipsRegId::Status) => self.cpu.cpzero.status.into(),
+            RmipsRegId::Mips(MipsRegId::Lo) => self.cpu.low,
+            RmipsRegId::Mips(MipsRegId::Hi) => self.cpu.high,
+            RmipsRegId::Mips(MipsRegId::Badvaddr) => self.cpu.cpzero.badvaddr.into(),
+            RmipsRegId::Mips(MipsRegId::Cause) => self.cpu.cpzero.cause.into(),
+            RmipsRegId::Mips(MipsRegId::Pc) => self.cpu.pc,
+            RmipsRegId::Mips(MipsRegId::Hi1) => self.cpu.dsp.high[0],
+            RmipsRegId::Mips(MipsRegId::Lo1) => self.cpu.dsp.low[0],
+            RmipsRegId::Mips(MipsRegId::Hi2) => self.cpu.dsp.high[1],
+            RmipsRegId::Mips(MipsRegId::Lo2) => self.cpu.dsp.low[1],
+            RmipsRegId::Mips(MipsRegId::Hi3) => self.cpu.dsp.high[2],
+            RmipsRegId::Mips(MipsRegId::Lo3) => self.cpu.dsp.low[2],
+            RmipsRegId::Mips(MipsRegId::Dspctl) => self.cpu.dsp.control,
+            RmipsRegId::Mips(MipsRegId::Restart) => 0,
+            RmipsRegId::Mips(MipsRegId::Fpr(i)) => self.fpu()?.read_register(i as usize),
+            RmipsRegId::Mips(MipsRegId::Fcsr) => self.fpu()?.read_control(FCSR),
+            RmipsRegId::Mips(MipsRegId::Fir) => self.fpu()?.read_control(FIR),
+            RmipsRegId::Index => self.cpu.cpzero.index.into(),
+            RmipsRegId::Random => self.cpu.cpzero.random.into(),
+            RmipsRegId::EntryLo => self.cpu.cpzero.entrylo,
+            RmipsRegId::Context => self.cpu.cpzero.context.into(),
+            RmipsRegId::EntryHi => self.cpu.cpzero.entryhi,
+            RmipsRegId::Epc => self.cpu.cpzero.epc.into(),
+            RmipsRegId::Prid => self.cpu.cpzero.prid.into(),
         };
 
-        dst.copy_from_slice(&w.to_le_bytes());
-        Ok(())
+        let bytes = self.bus.endian().word_bytes(w);
+        dst[..bytes.len()].copy_from_slice(&bytes);
+        Ok(bytes.len())
     }
 
     fn write_register(
         &mut self,
         _tid: (),
-        reg_id: gdbstub_arch::mips::reg::id::MipsRegId<Address>,
+        reg_id: RmipsRegId,
         value: &[u8],
     ) -> TargetResult<(), Self> {
-        let w = Address::from_le_bytes(value.try_into().expect("invalid write register data"));
+        let w = self
+            .bus
+            .endian()
+            .word(value.try_into().map_err(|_| TargetError::NonFatal)?);
+        // CP0 is written like mtc0 does, so read-only fields and reserved bits are kept
+        let cpzero = &mut self.cpu.cpzero;
 
         match reg_id {
-            MipsRegId::Gpr(i) => self.cpu.reg[i as usize] = w,
-            MipsRegId::Status => self.cpu.cpzero.status = w.into(),
-            MipsRegId::Lo => self.cpu.low = w,
-            MipsRegId::Hi => self.cpu.high = w,
-            MipsRegId::Badvaddr => self.cpu.cpzero.badvaddr = w.into(),
-            MipsRegId::Cause => self.cpu.cpzero.cause = w.into(),
-            MipsRegId::Pc => self.cpu.pc = w,
-            // MipsRegId::Fpr(i) => todo!() = w,
-            // MipsRegId::Fcsr => todo!() = w,
-            // MipsRegId::Fir => todo!() = w,
-            _ => return Err(().into()),
+            RmipsRegId::Mips(MipsRegId::Gpr(i)) => self.cpu.reg[i as usize] = w,
+            RmipsRegId::Mips(MipsRegId::Status) => cpzero.write_register(Cp0Register::Status, w),
+            RmipsRegId::Mips(MipsRegId::Lo) => self.cpu.low = w,
+            RmipsRegId::Mips(MipsRegId::Hi) => self.cpu.high = w,
+            RmipsRegId::Mips(MipsRegId::Badvaddr) => {
+                cpzero.write_register(Cp0Register::BadVaddr, w)
+            }
+            RmipsRegId::Mips(MipsRegId::Cause) => cpzero.write_register(Cp0Register::Cause, w),
+            RmipsRegId::Mips(MipsRegId::Pc) => self.cpu.pc = w,
+            RmipsRegId::Mips(MipsRegId::Hi1) => self.cpu.dsp.high[0] = w,
+            RmipsRegId::Mips(MipsRegId::Lo1) => self.cpu.dsp.low[0] = w,
+            RmipsRegId::Mips(MipsRegId::Hi2) => self.cpu.dsp.high[1] = w,
+            RmipsRegId::Mips(MipsRegId::Lo2) => self.cpu.dsp.low[1] = w,
+            RmipsRegId::Mips(MipsRegId::Hi3) => self.cpu.dsp.high[2] = w,
+            RmipsRegId::Mips(MipsRegId::Lo3) => self.cpu.dsp.low[2] = w,
+            RmipsRegId::Mips(MipsRegId::Dspctl) => self.cpu.dsp.control = w,
+            RmipsRegId::Mips(MipsRegId::Restart) => {}
+            RmipsRegId::Mips(MipsRegId::Fpr(i)) => self.fpu_mut()?.write_register(i as usize, w),
+            RmipsRegId::Mips(MipsRegId::Fcsr) => self.fpu_mut()?.write_control(FCSR, w),
+            // The implementation register is read-only
+            RmipsRegId::Mips(MipsRegId::Fir) => {}
+            RmipsRegId::Index => cpzero.write_register(Cp0Register::Index, w),
+            RmipsRegId::Random => cpzero.write_register(Cp0Register::Random, w),
+            RmipsRegId::EntryLo => cpzero.write_register(Cp0Register::EntryLo, w),
+            RmipsRegId::Context => cpzero.write_register(Cp0Register::Context, w),
+            RmipsRegId::EntryHi => cpzero.write_register(Cp0Register::EntryHi, w),
+            RmipsRegId::Epc => cpzero.write_register(Cp0Register::Epc, w),
+            RmipsRegId::Prid => cpzero.write_register(Cp0Register::Prid, w),
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::arch::RmipsRegs;
+    use super::*;
+    use crate::util::opts::Opts;
+    use gdbstub::target::ext::base::multithread::MultiThreadBase;
+    use gdbstub::target::ext::base::single_register_access::SingleRegisterAccess;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn range_stepping() -> crate::util::error::Result<()> {
+        let source = r#"
+                li    $t0, 3
+            loop:
+                addiu $t0, $t0, -1
+                bnez  $t0, loop
+                nop
+                li    $t1, 1     # 0xbfc00010
+                break
+        "#;
+        let path = std::env::temp_dir().join(format!("rmips-{}-range.s", std::process::id()));
+        std::fs::write(&path, source)?;
+        let mut emulator = Emulator::new(Opts {
+            romfile: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        })?;
+
+        // The loop stays in the range until it falls through to the next statement
+        let reason = emulator.run_while(|pc| (0xbfc0_0004..0xbfc0_0010).contains(&pc), || false)?;
+        assert_eq!(reason, Some(SingleThreadStopReason::DoneStep));
+        assert_eq!(emulator.cpu.pc, 0xbfc0_0010);
+
+        // An empty range is a single step
+        let reason = emulator.run_while(|pc| (0..0).contains(&pc), || false)?;
+        assert_eq!(reason, Some(SingleThreadStopReason::DoneStep));
+        assert_eq!(emulator.cpu.pc, 0xbfc0_0014);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn reset_stops_the_debugger() -> crate::util::error::Result<()> {
+        let source = r#"
+                li    $t0, 0xa1010028
+                li    $t1, 1
+                sw    $t1, 0($t0)
+                break
+        "#;
+        let path = std::env::temp_dir().join(format!("rmips-{}-gdbreset.s", std::process::id()));
+        std::fs::write(&path, source)?;
+        let mut emulator = Emulator::new(Opts {
+            romfile: path.to_string_lossy().into_owned(),
+            resetdevice: true,
+            ..Default::default()
+        })?;
+
+        // The run stops at the reset vector instead of ending the session
+        let reason = emulator.run_while(|_| true, || false)?;
+        assert_eq!(reason, Some(SingleThreadStopReason::Signal(Signal::SIGPWR)));
+        assert_eq!(emulator.cpu.pc, 0xbfc0_0000);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn dsp_registers() -> crate::util::error::Result<()> {
+        let path = std::env::temp_dir().join(format!("rmips-{}-dsp.s", std::process::id()));
+        std::fs::write(&path, "break")?;
+        let mut emulator = Emulator::new(Opts {
+            romfile: path.to_string_lossy().into_owned(),
+            debug: true,
+            ..Default::default()
+        })?;
+
+        let mut regs = RmipsRegs::default();
+        assert!(SingleThreadBase::read_registers(&mut emulator, &mut regs).is_ok());
+        regs.mips.dsp.hi2 = 0x1234;
+        regs.mips.dsp.lo3 = 0x5678;
+        regs.mips.dsp.dspctl = 0x3f;
+        regs.mips.dsp.restart = 1;
+        assert!(SingleThreadBase::write_registers(&mut emulator, &regs).is_ok());
+        assert_eq!(emulator.cpu.dsp.high, [0, 0x1234, 0]);
+        assert_eq!(emulator.cpu.dsp.low, [0, 0, 0x5678]);
+
+        let mut read = RmipsRegs::default();
+        assert!(SingleThreadBase::read_registers(&mut emulator, &mut read).is_ok());
+        assert_eq!(read.mips.dsp.dspctl, 0x3f);
+        assert_eq!(read.mips.dsp.restart, 0);
+
+        let mut dst = [0; 4];
+        let lo3 = RmipsRegId::Mips(MipsRegId::Lo3);
+        assert_eq!(emulator.read_register((), lo3, &mut dst).ok(), Some(4));
+        assert_eq!(dst, 0x5678u32.to_le_bytes());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn fpu_and_cp0_registers() -> crate::util::error::Result<()> {
+        let path = std::env::temp_dir().join(format!("rmips-{}-fpu-regs.s", std::process::id()));
+        std::fs::write(&path, "break")?;
+        let opts = Opts {
+            romfile: path.to_string_lossy().into_owned(),
+            debug: true,
+            ..Default::default()
+        };
+        let mut emulator = Emulator::new(opts.clone())?;
+
+        let mut regs = RmipsRegs::default();
+        assert!(SingleThreadBase::read_registers(&mut emulator, &mut regs).is_ok());
+        assert_eq!(regs.mips.core.fpu.fir, 0x300);
+        regs.mips.core.fpu.r[2] = 1.5f32.to_bits();
+        regs.mips.core.fpu.fcsr = 0x0080_0003;
+        regs.mips.core.fpu.fir = 0;
+        assert!(SingleThreadBase::write_registers(&mut emulator, &regs).is_ok());
+
+        let mut read = |reg_id| {
+            let mut dst = [0; 4];
+            emulator
+                .read_register((), reg_id, &mut dst)
+                .ok()
+                .map(|_| u32::from_le_bytes(dst))
         };
+        assert_eq!(read(RmipsRegId::Mips(MipsRegId::Fpr(2))), Some(0x3fc0_0000));
+        assert_eq!(read(RmipsRegId::Mips(MipsRegId::Fcsr)), Some(0x0080_0003));
+        assert_eq!(read(RmipsRegId::Mips(MipsRegId::Fir)), Some(0x300));
+        assert_eq!(read(RmipsRegId::Prid), Some(0x230));
+
+        let epc = 0x8000_0180u32.to_le_bytes();
+        assert!(emulator.write_register((), RmipsRegId::Epc, &epc).is_ok());
+        let entryhi = 0x0000_1fc0u32.to_le_bytes();
+        assert!(emulator
+            .write_register((), RmipsRegId::EntryHi, &entryhi)
+            .is_ok());
+        assert_eq!(u32::from(emulator.cpu.cpzero.epc), 0x8000_0180);
+        assert_eq!(emulator.cpu.cpzero.entryhi, 0x0000_1fc0);
+
+        // CP0 writes keep read-only registers and reserved bits like mtc0 does
+        let ones = 0xffff_ffffu32.to_le_bytes();
+        assert!(emulator.write_register((), RmipsRegId::Prid, &ones).is_ok());
+        let status = RmipsRegId::Mips(MipsRegId::Status);
+        assert!(emulator.write_register((), status, &ones).is_ok());
+        assert_eq!(u32::from(emulator.cpu.cpzero.prid), 0x230);
+        assert_eq!(u32::from(emulator.cpu.cpzero.status), 0xf247_ff3f);
+        regs.cp0.prid = 0;
+        regs.mips.core.cp0.badvaddr = 0x1234;
+        assert!(SingleThreadBase::write_registers(&mut emulator, &regs).is_ok());
+        assert_eq!(u32::from(emulator.cpu.cpzero.prid), 0x230);
+        assert_eq!(u32::from(emulator.cpu.cpzero.badvaddr), 0);
+
+        // Without an FPU its registers are unavailable
+        let mut emulator = Emulator::new(Opts {
+            nofpu: true,
+            ..opts
+        })?;
+        let mut dst = [0; 4];
+        let f0 = RmipsRegId::Mips(MipsRegId::Fpr(0));
+        assert!(emulator.read_register((), f0, &mut dst).is_err());
+        assert!(emulator.write_register((), f0, &dst).is_err());
+
+        // A value of the wrong size is rejected instead of stopping the stub
+        let pc = RmipsRegId::Mips(MipsRegId::Pc);
+        assert!(matches!(
+            emulator.write_register((), pc, &[0; 2]),
+            Err(TargetError::NonFatal)
+        ));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn rtos_threads() -> crate::util::error::Result<()> {
+        // Two TCBs with a name at offset 8, the running `main` and `idle` with a saved context
+        let source = r#"
+                li    $t0, 0x80002000
+                li    $t1, 0x80002100
+                li    $t2, 0x80001000
+                sw    $t0, 0($t2)
+                sw    $t0, 4($t2)
+                sw    $t1, 4($t0)
+                li    $t3, 0x6e69616d
+                sw    $t3, 8($t0)
+                li    $t3, 0x656c6469
+                sw    $t3, 8($t1)
+                li    $t2, 0x80003000
+                sw    $t2, 0($t1)
+                li    $t3, 0x1234
+                sw    $t3, 16($t2)
+                li    $t3, 0xbfc00100
+                sw    $t3, 136($t2)
+                break
+        "#;
+        let path = std::env::temp_dir().join(format!("rmips-{}-rtos.s", std::process::id()));
+        std::fs::write(&path, source)?;
+        let mut emulator = Emulator::new(Opts {
+            romfile: path.to_string_lossy().into_owned(),
+            debug: true,
+            rtos: Some(
+                "tcbs=0x80001000,current=0x80001004,next=4,name=8"
+                    .parse()
+                    .unwrap(),
+            ),
+            ..Default::default()
+        })?;
+        while !emulator.step()?.is_stop() {}
+
+        let mut tids = Vec::new();
+        MultiThreadBase::list_active_threads(&mut emulator, &mut |tid| tids.push(tid.get()))?;
+        assert_eq!(tids, vec![0x8000_2000, 0x8000_2100]);
+
+        let idle = gdbstub::common::Tid::new(0x8000_2100).unwrap();
+        let mut regs = Default::default();
+        assert!(MultiThreadBase::read_registers(&mut emulator, &mut regs, idle).is_ok());
+        assert_eq!(regs.mips.core.r[4], 0x1234);
+        assert_eq!(regs.mips.core.pc, 0xbfc0_0100);
+
+        assert_eq!(
+            emulator.thread_summary(),
+            format!(
+                "* 0x80002000  main              pc=0x{:08x}\n  \
+                 0x80002100  idle              pc=0xbfc00100",
+                emulator.cpu.pc
+            )
+        );
 
+        std::fs::remove_file(&path)?;
         Ok(())
     }
 }
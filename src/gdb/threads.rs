@@ -0,0 +1,349 @@
+use std::num::NonZeroUsize;
+
+use gdbstub::arch::Arch;
+use gdbstub::common::{Signal, Tid};
+use gdbstub::stub::{MultiThreadStopReason, SingleThreadStopReason};
+use gdbstub::target::ext::base::multithread::{
+    MultiThreadBase, MultiThreadRangeStepping, MultiThreadRangeSteppingOps, MultiThreadResume,
+    MultiThreadResumeOps, MultiThreadSchedulerLocking, MultiThreadSchedulerLockingOps,
+    MultiThreadSingleStep, MultiThreadSingleStepOps,
+};
+use gdbstub::target::ext::base::singlethread::SingleThreadBase;
+use gdbstub::target::{TargetError, TargetResult};
+
+use crate::emulator::Emulator;
+use crate::util::opts::RtosLayout;
+use crate::Address;
+
+/// Thread of the CPU itself, shown while no task of the kernel is running.
+pub(crate) const CPU_TID: Tid = NonZeroUsize::new(1).unwrap();
+
+/// Stops walking a corrupted TCB list after this many tasks.
+const MAX_THREADS: usize = 256;
+
+/// Longest task name that is read from a TCB.
+const MAX_NAME_LEN: usize = 32;
+
+/// Indices of the words after the general purpose registers in a saved context.
+const CONTEXT_LO: usize = 32;
+const CONTEXT_HI: usize = 33;
+const CONTEXT_PC: usize = 34;
+const CONTEXT_WORDS: usize = 35;
+
+/// How the CPU is resumed for the action GDB requested.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ThreadResume {
+    Step,
+    Continue,
+    RangeStep { start: Address, end: Address },
+}
+
+/// A task of the guest kernel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuestThread {
+    /// Address of the task control block, which is also the thread ID.
+    pub tcb: Address,
+    pub name: Option<String>,
+}
+
+impl GuestThread {
+    fn tid(&self) -> Tid {
+        NonZeroUsize::new(self.tcb as usize).unwrap_or(CPU_TID)
+    }
+}
+
+impl Emulator {
+    /// Reads a word of guest memory, or `None` if it has no translation or is not mapped.
+    fn guest_word(&self, address: Address) -> Option<u32> {
+        let mut word = [0; 4];
+        self.bus
+            .peek(self.cpu.cpzero.translate(address)?, &mut word)
+            .ok()
+            .map(|_| self.bus.endian().word(word))
+    }
+
+    /// Reads a NUL-terminated string of up to `MAX_NAME_LEN` bytes.
+    fn guest_string(&self, address: Address) -> Option<String> {
+        let mut name = Vec::new();
+        for address in (address..).take(MAX_NAME_LEN) {
+            let mut byte = [0];
+            self.bus
+                .peek(self.cpu.cpzero.translate(address)?, &mut byte)
+                .ok()?;
+            if byte[0] == 0 {
+                break;
+            }
+            name.push(byte[0]);
+        }
+        Some(String::from_utf8_lossy(&name).into_owned())
+    }
+
+    /// Walks the TCB list of the kernel. A TCB that is visited twice ends the walk.
+    pub(crate) fn guest_threads(&self, layout: &RtosLayout) -> Vec<GuestThread> {
+        let mut threads: Vec<GuestThread> = Vec::new();
+        let mut tcb = self.guest_word(layout.tcbs).unwrap_or(0);
+        while tcb != 0 && threads.len() < MAX_THREADS && threads.iter().all(|t| t.tcb != tcb) {
+            threads.push(GuestThread {
+                tcb,
+                name: layout
+                    .name
+                    .and_then(|offset| self.guest_string(tcb.wrapping_add(offset))),
+            });
+            tcb = self.guest_word(tcb.wrapping_add(layout.next)).unwrap_or(0);
+        }
+        threads
+    }
+
+    /// Returns the TCB of the running task, if the kernel runs one.
+    fn running_tcb(&self, layout: &RtosLayout) -> Option<Address> {
+        self.guest_word(layout.current).filter(|&tcb| tcb != 0)
+    }
+
+    /// Returns the thread whose registers are the registers of the CPU.
+    pub(crate) fn running_tid(&self) -> Tid {
+        self.rtos
+            .and_then(|layout| self.running_tcb(&layout))
+            .and_then(|tcb| NonZeroUsize::new(tcb as usize))
+            .unwrap_or(CPU_TID)
+    }
+
+    /// Returns the address of the context saved by a task that is not running.
+    fn saved_context(&self, tid: Tid) -> TargetResult<Address, Self> {
+        let layout = self.rtos.ok_or(TargetError::NonFatal)?;
+        self.guest_word((tid.get() as Address).wrapping_add(layout.context))
+            .filter(|&context| context != 0)
+            .ok_or(TargetError::NonFatal)
+    }
+
+    /// Lists the tasks for `monitor threads`, with the PC each one runs or resumes at.
+    pub(crate) fn thread_summary(&self) -> String {
+        let Some(layout) = self.rtos else {
+            return "No RTOS layout is given".to_owned();
+        };
+        let running = self.running_tcb(&layout);
+        let threads = self.guest_threads(&layout);
+        if threads.is_empty() {
+            return "The TCB list is empty".to_owned();
+        }
+
+        let mut summary = String::new();
+        for thread in threads {
+            let pc = match Some(thread.tcb) == running {
+                true => Some(self.cpu.pc),
+                false => self
+                    .saved_context(thread.tid())
+                    .ok()
+                    .and_then(|context| self.guest_word(context + 4 * CONTEXT_PC as Address)),
+            };
+            summary.push_str(&format!(
+                "{} 0x{:08x}  {:<16}  pc=",
+                if Some(thread.tcb) == running {
+                    '*'
+                } else {
+                    ' '
+                },
+                thread.tcb,
+                thread.name.as_deref().unwrap_or("-"),
+            ));
+            match pc {
+                Some(pc) => summary.push_str(&format!("0x{:08x}\n", pc)),
+                None => summary.push_str("unknown\n"),
+            }
+        }
+        summary.pop();
+        summary
+    }
+
+    /// Converts the stop of the CPU into the stop of the running thread.
+    pub(crate) fn thread_stop_reason(
+        &self,
+        reason: SingleThreadStopReason<Address>,
+    ) -> MultiThreadStopReason<Address> {
+        let tid = self.running_tid();
+        match reason {
+            SingleThreadStopReason::SwBreak(()) => MultiThreadStopReason::SwBreak(tid),
+            SingleThreadStopReason::HwBreak(()) => MultiThreadStopReason::HwBreak(tid),
+            SingleThreadStopReason::Watch { kind, addr, .. } => {
+                MultiThreadStopReason::Watch { tid, kind, addr }
+            }
+            reason => reason.into(),
+        }
+    }
+
+    /// Records the action for a thread. Stepping any thread steps the CPU, which wins over
+    /// continuing.
+    fn set_thread_resume(&mut self, action: ThreadResume) {
+        if self.thread_resume.is_none() || action != ThreadResume::Continue {
+            self.thread_resume = Some(action);
+        }
+    }
+}
+
+/// With `--rtos`, the tasks of the kernel are GDB threads. The running task has the registers
+/// of the CPU, the others have the context they saved when the kernel switched away from them.
+/// There is a single CPU to run, so resuming any thread resumes the CPU.
+impl MultiThreadBase for Emulator {
+    #[inline(always)]
+    fn support_resume(&mut self) -> Option<MultiThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+
+    fn read_registers(
+        &mut self,
+        regs: &mut <Self::Arch as Arch>::Registers,
+        tid: Tid,
+    ) -> TargetResult<(), Self> {
+        // The coprocessor 0 registers are shared by all tasks
+        SingleThreadBase::read_registers(self, regs)?;
+        if tid == self.running_tid() {
+            return Ok(());
+        }
+
+        let context = self.saved_context(tid)?;
+        let mut words = [0; CONTEXT_WORDS];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = self
+                .guest_word(context.wrapping_add(4 * i as Address))
+                .ok_or(TargetError::NonFatal)?;
+        }
+        let core = &mut regs.mips.core;
+        core.r.copy_from_slice(&words[..32]);
+        core.r[0] = 0;
+        core.lo = words[CONTEXT_LO];
+        core.hi = words[CONTEXT_HI];
+        core.pc = words[CONTEXT_PC];
+        Ok(())
+    }
+
+    fn write_registers(
+        &mut self,
+        regs: &<Self::Arch as Arch>::Registers,
+        tid: Tid,
+    ) -> TargetResult<(), Self> {
+        if tid == self.running_tid() {
+            return SingleThreadBase::write_registers(self, regs);
+        }
+
+        let regs = regs.in_byte_order(self.bus.endian());
+        let context = self.saved_context(tid)?;
+        let core = &regs.mips.core;
+        let mut words = [0; CONTEXT_WORDS];
+        words[..32].copy_from_slice(&core.r);
+        words[CONTEXT_LO] = core.lo;
+        words[CONTEXT_HI] = core.hi;
+        words[CONTEXT_PC] = core.pc;
+        let endian = self.bus.endian();
+        let bytes: Vec<u8> = words
+            .iter()
+            .flat_map(|&word| endian.word_bytes(word))
+            .collect();
+        self.debugger_write(context, &bytes)
+            .map_err(TargetError::Errno)
+    }
+
+    fn read_addrs(
+        &mut self,
+        start_addr: Address,
+        data: &mut [u8],
+        _tid: Tid,
+    ) -> TargetResult<usize, Self> {
+        SingleThreadBase::read_addrs(self, start_addr, data)
+    }
+
+    fn write_addrs(
+        &mut self,
+        start_addr: Address,
+        data: &[u8],
+        _tid: Tid,
+    ) -> TargetResult<(), Self> {
+        SingleThreadBase::write_addrs(self, start_addr, data)
+    }
+
+    fn list_active_threads(
+        &mut self,
+        thread_is_active: &mut dyn FnMut(Tid),
+    ) -> Result<(), Self::Error> {
+        let Some(layout) = self.rtos else {
+            thread_is_active(CPU_TID);
+            return Ok(());
+        };
+
+        // Kernels often take the running task off the list, and none runs before the scheduler
+        let threads = self.guest_threads(&layout);
+        let running = self.running_tid();
+        if threads.iter().all(|thread| thread.tid() != running) {
+            thread_is_active(running);
+        }
+        for thread in threads {
+            thread_is_active(thread.tid());
+        }
+        Ok(())
+    }
+}
+
+/// The CPU runs once GDB has set the action of every thread, in `GdbEventLoop`.
+impl MultiThreadResume for Emulator {
+    fn resume(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn clear_resume_actions(&mut self) -> Result<(), Self::Error> {
+        self.thread_resume = None;
+        Ok(())
+    }
+
+    fn set_resume_action_continue(
+        &mut self,
+        _tid: Tid,
+        _signal: Option<Signal>,
+    ) -> Result<(), Self::Error> {
+        self.set_thread_resume(ThreadResume::Continue);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn support_single_step(&mut self) -> Option<MultiThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_range_step(&mut self) -> Option<MultiThreadRangeSteppingOps<'_, Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_scheduler_locking(&mut self) -> Option<MultiThreadSchedulerLockingOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl MultiThreadSingleStep for Emulator {
+    fn set_resume_action_step(
+        &mut self,
+        _tid: Tid,
+        _signal: Option<Signal>,
+    ) -> Result<(), Self::Error> {
+        self.set_thread_resume(ThreadResume::Step);
+        Ok(())
+    }
+}
+
+impl MultiThreadRangeStepping for Emulator {
+    fn set_resume_action_range_step(
+        &mut self,
+        _tid: Tid,
+        start: Address,
+        end: Address,
+    ) -> Result<(), Self::Error> {
+        self.thread_resume = Some(ThreadResume::RangeStep { start, end });
+        Ok(())
+    }
+}
+
+/// The kernel schedules its tasks on the single CPU, so the other tasks cannot be kept from
+/// running while one is stepped or continued.
+impl MultiThreadSchedulerLocking for Emulator {
+    fn set_resume_action_scheduler_lock(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
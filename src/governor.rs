@@ -0,0 +1,139 @@
+//! Governor that keeps long-running emulators within the resources of a shared host.
+//!
+//! Every `CHECK_INTERVAL` instructions the run loop hands control to the governor. With a CPU
+//! quota it sleeps long enough that the emulator only runs for the given percentage of the
+//! wall-clock time. With a memory floor it pauses the emulation while the host has less
+//! memory available than the floor, so that the emulator waits for the pressure to pass
+//! instead of growing its memory and being killed by the OOM killer mid-run.
+
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of instructions between checks of the host resources.
+pub const CHECK_INTERVAL: usize = 1 << 16;
+
+/// How often the available memory is checked again while paused.
+const PAUSE_POLL: Duration = Duration::from_millis(500);
+
+/// A change of the emulation speed made by the governor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GovernorEvent {
+    /// The host had only this many bytes of memory available.
+    Paused { available: u64 },
+    /// The emulation resumed after being paused for this long.
+    Resumed { paused: Duration },
+}
+
+impl fmt::Display for GovernorEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GovernorEvent::Paused { available } => write!(
+                f,
+                "Pausing emulation, the host has only {}MB of memory available",
+                available >> 20
+            ),
+            GovernorEvent::Resumed { paused } => write!(
+                f,
+                "Resuming emulation after pausing for {:.1} seconds",
+                paused.as_secs_f64()
+            ),
+        }
+    }
+}
+
+/// Returns the `MemAvailable` value of `/proc/meminfo` in bytes.
+fn parse_mem_available(meminfo: &str) -> Option<u64> {
+    meminfo.lines().find_map(|line| {
+        let kilobytes = line.strip_prefix("MemAvailable:")?.trim();
+        let kilobytes = kilobytes.strip_suffix("kB").unwrap_or(kilobytes).trim();
+        kilobytes.parse::<u64>().ok().map(|kb| kb << 10)
+    })
+}
+
+/// Returns the memory available on the host, if the platform reports it.
+fn host_memory_available() -> Option<u64> {
+    parse_mem_available(&std::fs::read_to_string("/proc/meminfo").ok()?)
+}
+
+/// Returns how long to sleep after running for `busy` to use only `quota` percent of the CPU.
+fn throttle_delay(busy: Duration, quota: u32) -> Duration {
+    busy * (100 - quota) / quota
+}
+
+pub struct Governor {
+    /// Percentage of the wall-clock time the emulator may run for.
+    cpu_quota: Option<u32>,
+    /// Bytes of host memory that must stay available for the emulation to run.
+    min_available: Option<u64>,
+    /// When the emulator last started running after a check.
+    resumed_at: Instant,
+}
+
+impl Governor {
+    pub fn new(cpu_quota: Option<u32>, min_available: Option<u64>) -> Self {
+        let min_available = min_available.filter(|_| {
+            let supported = host_memory_available().is_some();
+            if !supported {
+                println!(
+                    "Warning: host memory is not reported on this platform, it is not monitored"
+                );
+            }
+            supported
+        });
+
+        Self {
+            cpu_quota,
+            min_available,
+            resumed_at: Instant::now(),
+        }
+    }
+
+    /// Throttles or pauses the emulation as needed, calling `report` with the events.
+    ///
+    /// A pause ends early when `interrupted` returns true, so that signals are still handled.
+    pub fn regulate(
+        &mut self,
+        mut report: impl FnMut(GovernorEvent),
+        interrupted: impl Fn() -> bool,
+    ) {
+        if let Some(quota) = self.cpu_quota {
+            thread::sleep(throttle_delay(self.resumed_at.elapsed(), quota));
+        }
+
+        if let Some(min_available) = self.min_available {
+            let available = host_memory_available().unwrap_or(u64::MAX);
+            if available < min_available {
+                let paused_at = Instant::now();
+                report(GovernorEvent::Paused { available });
+                while !interrupted() && host_memory_available().unwrap_or(u64::MAX) < min_available
+                {
+                    thread::sleep(PAUSE_POLL);
+                }
+                report(GovernorEvent::Resumed {
+                    paused: paused_at.elapsed(),
+                });
+            }
+        }
+
+        self.resumed_at = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn governor_host_resources() {
+        let meminfo = "MemTotal:       16303520 kB\nMemFree:         1234567 kB\n\
+                       MemAvailable:    8151760 kB\nBuffers:          123456 kB\n";
+        assert_eq!(parse_mem_available(meminfo), Some(8151760 << 10));
+        assert_eq!(parse_mem_available("MemTotal: 1 kB\n"), None);
+
+        let busy = Duration::from_millis(30);
+        assert_eq!(throttle_delay(busy, 100), Duration::ZERO);
+        assert_eq!(throttle_delay(busy, 25), Duration::from_millis(90));
+    }
+}
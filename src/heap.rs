@@ -0,0 +1,343 @@
+//! Guest heap checker driven by hooks on the guest allocator entry points.
+//!
+//! When the program counter reaches the `malloc` entry point, the requested size and return
+//! address are recorded, and the pointer in `$v0` is recorded as an allocation once the call
+//! returns. Calls to `free` are checked against the live allocations to find double and invalid
+//! frees. Loads and stores outside the allocator are checked against freed blocks to find uses
+//! after free, and the allocations that are still live when the emulator halts are leaks.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::Address;
+
+/// Heap misuse found while tracking the guest allocator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HeapError {
+    /// `free` was called again on a block at `address` that was freed at `freed_at`.
+    DoubleFree {
+        pc: Address,
+        address: Address,
+        freed_at: Address,
+    },
+    /// `free` was called on an address that was never returned by `malloc`.
+    InvalidFree { pc: Address, address: Address },
+    /// A load or store touched a block after it was freed at `freed_at`.
+    UseAfterFree {
+        pc: Address,
+        address: Address,
+        write: bool,
+        block: Address,
+        freed_at: Address,
+    },
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeapError::DoubleFree {
+                pc,
+                address,
+                freed_at,
+            } => write!(
+                f,
+                "Double free of 0x{:08x} called from PC=0x{:08x}, first freed from PC=0x{:08x}",
+                address, pc, freed_at
+            ),
+            HeapError::InvalidFree { pc, address } => write!(
+                f,
+                "Free of 0x{:08x} called from PC=0x{:08x}, which was not allocated",
+                address, pc
+            ),
+            HeapError::UseAfterFree {
+                pc,
+                address,
+                write,
+                block,
+                freed_at,
+            } => write!(
+                f,
+                "Use after free: {} 0x{:08x} at PC=0x{:08x}, in block 0x{:08x} freed from PC=0x{:08x}",
+                if write { "write to" } else { "read from" },
+                address,
+                pc,
+                block,
+                freed_at
+            ),
+        }
+    }
+}
+
+/// A heap block and the call site of the allocator function that produced or released it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub address: Address,
+    pub size: u32,
+    pub call_site: Address,
+}
+
+#[derive(Debug)]
+enum PendingCall {
+    Malloc { size: u32, call_site: Address },
+    Free,
+}
+
+#[derive(Debug)]
+pub struct HeapTracker {
+    malloc: Address,
+    free: Address,
+    /// Allocator calls that have not returned yet, with their return addresses.
+    pending: Vec<(Address, PendingCall)>,
+    live: BTreeMap<Address, Block>,
+    freed: BTreeMap<Address, Block>,
+    allocations: usize,
+    errors: Vec<HeapError>,
+}
+
+impl HeapTracker {
+    /// Creates a tracker for the allocator functions at the given entry points.
+    pub fn new(malloc: Address, free: Address) -> Self {
+        Self {
+            malloc,
+            free,
+            pending: Vec::new(),
+            live: BTreeMap::new(),
+            freed: BTreeMap::new(),
+            allocations: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Returns true while the guest is executing inside an allocator function.
+    pub fn in_allocator(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Handles the allocator hooks before the instruction at `pc` executes.
+    ///
+    /// `arg` is `$a0`, `result` is `$v0` and `ra` is the return address register.
+    /// Returns a heap error if this is a call to `free` with an invalid pointer.
+    pub fn enter(&mut self, pc: Address, arg: u32, result: u32, ra: Address) -> Option<HeapError> {
+        if let Some((return_address, _)) = self.pending.last() {
+            if *return_address == pc {
+                if let (_, PendingCall::Malloc { size, call_site }) = self.pending.pop().unwrap() {
+                    self.allocated(result, size, call_site);
+                }
+            }
+        }
+
+        let call_site = ra.wrapping_sub(8);
+        if pc == self.malloc {
+            self.pending.push((
+                ra,
+                PendingCall::Malloc {
+                    size: arg,
+                    call_site,
+                },
+            ));
+            None
+        } else if pc == self.free {
+            self.pending.push((ra, PendingCall::Free));
+            self.freeing(arg, call_site)
+        } else {
+            None
+        }
+    }
+
+    fn allocated(&mut self, address: Address, size: u32, call_site: Address) {
+        if address == 0 {
+            return;
+        }
+
+        // Memory handed out again is no longer considered freed
+        let end = address.saturating_add(size.max(1));
+        let reused: Vec<Address> = self
+            .freed
+            .values()
+            .filter(|block| {
+                block.address < end && address < block.address.saturating_add(block.size.max(1))
+            })
+            .map(|block| block.address)
+            .collect();
+        for block in reused {
+            self.freed.remove(&block);
+        }
+
+        self.allocations += 1;
+        self.live.insert(
+            address,
+            Block {
+                address,
+                size,
+                call_site,
+            },
+        );
+    }
+
+    fn freeing(&mut self, address: Address, call_site: Address) -> Option<HeapError> {
+        if address == 0 {
+            return None;
+        }
+
+        let error = match self.live.remove(&address) {
+            Some(block) => {
+                self.freed.insert(address, Block { call_site, ..block });
+                return None;
+            }
+            None => match self.freed.get(&address) {
+                Some(block) => HeapError::DoubleFree {
+                    pc: call_site,
+                    address,
+                    freed_at: block.call_site,
+                },
+                None => HeapError::InvalidFree {
+                    pc: call_site,
+                    address,
+                },
+            },
+        };
+
+        self.errors.push(error.clone());
+        Some(error)
+    }
+
+    /// Checks a data access of `len` bytes at virtual `address` by the instruction at `pc`.
+    pub fn check_access(
+        &mut self,
+        pc: Address,
+        address: Address,
+        len: u32,
+        write: bool,
+    ) -> Option<HeapError> {
+        if self.in_allocator() {
+            return None;
+        }
+
+        let block = self
+            .freed
+            .range(..address.saturating_add(len))
+            .next_back()?
+            .1;
+        if address >= block.address.saturating_add(block.size) {
+            return None;
+        }
+
+        let error = HeapError::UseAfterFree {
+            pc,
+            address,
+            write,
+            block: block.address,
+            freed_at: block.call_site,
+        };
+        self.errors.push(error.clone());
+        Some(error)
+    }
+
+    /// Returns the blocks that were allocated and not freed.
+    pub fn leaks(&self) -> impl Iterator<Item = &Block> {
+        self.live.values()
+    }
+
+    /// Returns the heap errors found so far.
+    pub fn errors(&self) -> &[HeapError] {
+        &self.errors
+    }
+
+    /// Returns a summary of the allocations, heap errors and leaks.
+    pub fn report(&self) -> String {
+        let leaked: u64 = self.leaks().map(|block| block.size as u64).sum();
+        let mut report = format!(
+            "Heap: {} allocations, {} errors, {} leaked blocks ({} bytes)",
+            self.allocations,
+            self.errors.len(),
+            self.live.len(),
+            leaked
+        );
+
+        for block in self.leaks() {
+            report.push_str(&format!(
+                "\n  Leaked {} bytes at 0x{:08x} allocated from PC=0x{:08x}",
+                block.size, block.address, block.call_site
+            ));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const MALLOC: Address = 0x8000_1000;
+    const FREE: Address = 0x8000_2000;
+
+    /// Simulates a call from `call_site` that returns `result`.
+    fn call(heap: &mut HeapTracker, entry: Address, arg: u32, result: u32, call_site: Address) {
+        let ra = call_site + 8;
+        heap.enter(entry, arg, 0, ra);
+        heap.enter(ra, 0, result, 0);
+    }
+
+    #[test]
+    fn heap_tracker_leaks_and_frees() {
+        let mut heap = HeapTracker::new(MALLOC, FREE);
+        call(&mut heap, MALLOC, 16, 0x8010_0000, 0x100);
+        call(&mut heap, MALLOC, 32, 0x8010_0010, 0x200);
+        call(&mut heap, FREE, 0x8010_0000, 0, 0x300);
+        call(&mut heap, FREE, 0, 0, 0x304);
+
+        assert_eq!(
+            heap.leaks().collect::<Vec<_>>(),
+            vec![&Block {
+                address: 0x8010_0010,
+                size: 32,
+                call_site: 0x200
+            }]
+        );
+        assert!(heap.errors().is_empty());
+
+        call(&mut heap, FREE, 0x8010_0000, 0, 0x400);
+        call(&mut heap, FREE, 0x8020_0000, 0, 0x500);
+        assert_eq!(
+            heap.errors(),
+            &[
+                HeapError::DoubleFree {
+                    pc: 0x400,
+                    address: 0x8010_0000,
+                    freed_at: 0x300
+                },
+                HeapError::InvalidFree {
+                    pc: 0x500,
+                    address: 0x8020_0000
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn heap_tracker_use_after_free() {
+        let mut heap = HeapTracker::new(MALLOC, FREE);
+        call(&mut heap, MALLOC, 16, 0x8010_0000, 0x100);
+        assert_eq!(heap.check_access(0x104, 0x8010_0000, 4, true), None);
+
+        call(&mut heap, FREE, 0x8010_0000, 0, 0x200);
+        assert_eq!(heap.check_access(0x204, 0x8010_0010, 4, false), None);
+        assert_eq!(
+            heap.check_access(0x208, 0x8010_000c, 4, false),
+            Some(HeapError::UseAfterFree {
+                pc: 0x208,
+                address: 0x8010_000c,
+                write: false,
+                block: 0x8010_0000,
+                freed_at: 0x200
+            })
+        );
+
+        // The allocator may touch freed memory, and reallocated memory is valid again
+        heap.enter(MALLOC, 8, 0, 0x308);
+        assert_eq!(heap.check_access(0x1004, 0x8010_0000, 4, true), None);
+        heap.enter(0x308, 0, 0x8010_0000, 0);
+        assert_eq!(heap.check_access(0x30c, 0x8010_0000, 4, true), None);
+    }
+}
@@ -0,0 +1,299 @@
+//! Scripted external inputs that are replayed at fixed points of a run.
+//!
+//! An input script schedules serial bytes and interrupt line changes by instruction count or
+//! by virtual time, where each executed instruction advances the clock by one microsecond.
+//! Since nothing depends on when the host delivers the input, interactive firmware sees the
+//! same input at the same instruction on every run.
+//!
+//! Scripts use a small subset of TOML with one `[[event]]` table per input:
+//!
+//! ```toml
+//! [[event]]
+//! at = 1000            # after 1000 instructions
+//! serial = "boot\n"    # received by the serial link
+//!
+//! [[event]]
+//! time = "2ms"         # after 2000 instructions
+//! irq = 3              # assert IP3, `level = false` deasserts it
+//! ```
+
+use std::fmt;
+
+use crate::util::parse::{self, parse_bool, parse_integer, parse_string};
+
+/// An external input applied to the machine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputAction {
+    /// Bytes received by the serial link.
+    Serial(Vec<u8>),
+    /// Asserts or deasserts the hardware interrupt line `IPn`, where `line` is from 2 to 7.
+    Irq { line: u8, asserted: bool },
+}
+
+impl fmt::Display for InputAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputAction::Serial(bytes) => {
+                write!(f, "serial \"{}\"", bytes.escape_ascii())
+            }
+            InputAction::Irq { line, asserted } => match asserted {
+                true => write!(f, "assert IP{}", line),
+                false => write!(f, "deassert IP{}", line),
+            },
+        }
+    }
+}
+
+/// An input and the instruction count it is applied at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    pub at: u64,
+    pub action: InputAction,
+}
+
+/// The keys of an `[[event]]` table read so far.
+#[derive(Default)]
+struct EventTable {
+    /// Line of the `[[event]]` header.
+    line: usize,
+    at: Option<u64>,
+    serial: Option<Vec<u8>>,
+    irq: Option<u8>,
+    level: Option<bool>,
+}
+
+impl EventTable {
+    fn finish(self) -> Result<InputEvent, String> {
+        let at = self
+            .at
+            .ok_or_else(|| format!("event on line {} has no `at` or `time`", self.line))?;
+        let action = match (self.serial, self.irq) {
+            (Some(_), Some(_)) => {
+                return Err(format!(
+                    "event on line {} can only have one of `serial` or `irq`",
+                    self.line
+                ))
+            }
+            (Some(_), None) if self.level.is_some() => {
+                return Err(format!(
+                    "event on line {} has a `level` without an `irq`",
+                    self.line
+                ))
+            }
+            (Some(bytes), None) => InputAction::Serial(bytes),
+            (None, Some(line)) => InputAction::Irq {
+                line,
+                asserted: self.level.unwrap_or(true),
+            },
+            (None, None) => {
+                return Err(format!(
+                    "event on line {} has no `serial` or `irq` input",
+                    self.line
+                ))
+            }
+        };
+        Ok(InputEvent { at, action })
+    }
+}
+
+/// Parses a virtual time such as `"250us"`, `"2ms"` or `"1s"` into an instruction count.
+fn parse_time(value: &str) -> Result<u64, String> {
+    let time = String::from_utf8(parse_string(value)?).map_err(|err| err.to_string())?;
+    let invalid = || format!("invalid time: {}", time);
+    let (amount, scale) = if let Some(amount) = time.strip_suffix("us") {
+        (amount, 1)
+    } else if let Some(amount) = time.strip_suffix("ms") {
+        (amount, 1_000)
+    } else if let Some(amount) = time.strip_suffix('s') {
+        (amount, 1_000_000)
+    } else {
+        return Err(invalid());
+    };
+
+    let amount: u64 = amount.trim().parse().map_err(|_| invalid())?;
+    amount.checked_mul(scale).ok_or_else(invalid)
+}
+
+/// External inputs ordered by the instruction count they are applied at.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputScript {
+    events: Vec<InputEvent>,
+    /// Index of the next event to apply.
+    next: usize,
+}
+
+impl InputScript {
+    /// Parses an input script, reporting the line of the first error.
+    pub fn parse(script: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+        let mut table: Option<EventTable> = None;
+
+        for (number, line) in parse::lines(script) {
+            let error = |msg: String| format!("line {}: {}", number, msg);
+
+            if line == "[[event]]" {
+                if let Some(table) = table.take() {
+                    events.push(table.finish()?);
+                }
+                table = Some(EventTable {
+                    line: number,
+                    ..Default::default()
+                });
+                continue;
+            }
+
+            let (key, value) = parse::key_value(line)
+                .ok_or_else(|| error(format!("expected `[[event]]` or `key = value`: {}", line)))?;
+            let table = table
+                .as_mut()
+                .ok_or_else(|| error(format!("`{}` is outside of an `[[event]]` table", key)))?;
+
+            let duplicate = match key {
+                "at" | "time" => table.at.is_some(),
+                "serial" => table.serial.is_some(),
+                "irq" => table.irq.is_some(),
+                "level" => table.level.is_some(),
+                _ => false,
+            };
+            if duplicate {
+                let key = if key == "time" { "at" } else { key };
+                return Err(error(format!("`{}` is given twice", key)));
+            }
+
+            match key {
+                "at" => table.at = Some(parse_integer(value).map_err(error)?),
+                "time" => table.at = Some(parse_time(value).map_err(error)?),
+                "serial" => table.serial = Some(parse_string(value).map_err(error)?),
+                "irq" => match parse_integer::<u64>(value).map_err(error)? {
+                    line @ 2..=7 => table.irq = Some(line as u8),
+                    _ => return Err(error(format!("interrupt line {} is not IP2-IP7", value))),
+                },
+                "level" => table.level = Some(parse_bool(value).map_err(error)?),
+                "gpio" => {
+                    return Err(error(
+                        "`gpio` inputs need a GPIO controller, which is not emulated".to_owned(),
+                    ))
+                }
+                _ => return Err(error(format!("unknown key `{}`", key))),
+            }
+        }
+        if let Some(table) = table {
+            events.push(table.finish()?);
+        }
+
+        // Events at the same instruction count are applied in the order they are listed
+        events.sort_by_key(|event| event.at);
+        Ok(Self { events, next: 0 })
+    }
+
+    /// Returns true if the script has an input for the serial link.
+    pub fn has_serial_input(&self) -> bool {
+        self.events
+            .iter()
+            .any(|event| matches!(event.action, InputAction::Serial(_)))
+    }
+
+    /// Returns the numbers of the `IPn` interrupt lines that the script drives, in order.
+    pub fn interrupt_lines(&self) -> Vec<u8> {
+        let mut lines: Vec<u8> = self
+            .events
+            .iter()
+            .filter_map(|event| match event.action {
+                InputAction::Irq { line, .. } => Some(line),
+                InputAction::Serial(_) => None,
+            })
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    /// Returns the next input that is due once `instructions` instructions have executed.
+    pub fn next_due(&mut self, instructions: u64) -> Option<&InputEvent> {
+        let event = self
+            .events
+            .get(self.next)
+            .filter(|e| e.at <= instructions)?;
+        self.next += 1;
+        Some(event)
+    }
+
+    /// Starts the script over, for a replay of the run.
+    pub fn rewind(&mut self) {
+        self.next = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn input_script_parse() {
+        let script = r#"
+            # Type a command once the prompt is up
+            [[event]]
+            time = "2ms"
+            irq = 3
+            level = false
+
+            [[event]]
+            at = 0x10  # hexadecimal
+            serial = "ls #1\n\x00"
+
+            [[event]]
+            at = 1_000
+            irq = 7
+        "#;
+        let mut script = InputScript::parse(script).unwrap();
+        assert!(script.has_serial_input());
+
+        assert_eq!(script.next_due(15), None);
+        assert_eq!(
+            script.next_due(16).cloned(),
+            Some(InputEvent {
+                at: 16,
+                action: InputAction::Serial(b"ls #1\n\0".to_vec()),
+            })
+        );
+        assert_eq!(script.next_due(16), None);
+        assert_eq!(
+            script.next_due(5000).map(|event| event.action.to_string()),
+            Some("assert IP7".to_owned())
+        );
+        assert_eq!(
+            script.next_due(5000).map(|event| event.action.to_string()),
+            Some("deassert IP3".to_owned())
+        );
+        assert_eq!(script.next_due(5000), None);
+    }
+
+    #[test]
+    fn input_script_errors() {
+        let error = |script: &str| InputScript::parse(script).unwrap_err();
+
+        assert_eq!(
+            error("at = 1"),
+            "line 1: `at` is outside of an `[[event]]` table"
+        );
+        assert_eq!(
+            error("[[event]]\nat = 1\ntime = \"1ms\"\nirq = 2"),
+            "line 3: `at` is given twice"
+        );
+        assert_eq!(
+            error("[[event]]\nat = 1\nirq = 8"),
+            "line 3: interrupt line 8 is not IP2-IP7"
+        );
+        assert_eq!(
+            error("[[event]]\nserial = \"a\"\n[[event]]\nat = 1\nirq = 2"),
+            "event on line 1 has no `at` or `time`"
+        );
+        assert_eq!(
+            error("[[event]]\nat = 1\nserial = \"a\"\nirq = 2"),
+            "event on line 1 can only have one of `serial` or `irq`"
+        );
+        assert!(error("[[event]]\nat = 1\ngpio = 3").contains("GPIO"));
+        assert!(error("[[event]]\ntime = \"5 minutes\"").contains("invalid time"));
+    }
+}
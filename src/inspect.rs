@@ -0,0 +1,95 @@
+//! Read-only samples of the machine state for other threads.
+//!
+//! Every `interval` instructions the run loop publishes the registers, the instruction count
+//! and a few memory words as a new `StateSample`. Publishing swaps the sample under a lock that
+//! is held only for the swap, so a dashboard thread can sample the state as often as it likes
+//! while the guest keeps running, and never sees a sample that is only partly updated.
+
+use crate::Address;
+use std::sync::{Arc, Mutex};
+
+/// The machine state at the end of an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateSample {
+    pub pc: Address,
+    pub reg: [u32; 32],
+    pub high: u32,
+    pub low: u32,
+    /// Number of instructions executed so far.
+    pub instruction_count: usize,
+    /// The sampled memory words with their virtual addresses, `None` if a word could not be
+    /// read without side effects.
+    pub words: Vec<(Address, Option<u32>)>,
+}
+
+/// Handle that other threads use to sample the state of a running emulator.
+#[derive(Debug, Clone, Default)]
+pub struct Inspector {
+    latest: Arc<Mutex<Option<Arc<StateSample>>>>,
+}
+
+impl Inspector {
+    /// Returns the most recently published sample, or `None` before the first one.
+    pub fn latest(&self) -> Option<Arc<StateSample>> {
+        self.latest.lock().unwrap().clone()
+    }
+}
+
+/// Publishing side of an `Inspector`, owned by the emulator.
+pub(crate) struct Publisher {
+    /// Virtual addresses of the memory words to sample.
+    pub words: Vec<Address>,
+    /// Number of instructions between samples.
+    pub interval: usize,
+    inspector: Inspector,
+}
+
+impl Publisher {
+    pub fn new(words: Vec<Address>, interval: usize) -> Self {
+        Publisher {
+            words,
+            interval: interval.max(1),
+            inspector: Inspector::default(),
+        }
+    }
+
+    pub fn inspector(&self) -> Inspector {
+        self.inspector.clone()
+    }
+
+    /// Replaces the published sample, which readers that still hold the old one keep.
+    pub fn publish(&self, sample: StateSample) {
+        *self.inspector.latest.lock().unwrap() = Some(Arc::new(sample));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readers_keep_their_sample() {
+        let publisher = Publisher::new(Vec::new(), 0);
+        let inspector = publisher.inspector();
+        assert_eq!(publisher.interval, 1);
+        assert!(inspector.latest().is_none());
+
+        let sample = StateSample {
+            pc: 0xbfc0_0000,
+            reg: [0; 32],
+            high: 0,
+            low: 0,
+            instruction_count: 1,
+            words: Vec::new(),
+        };
+        publisher.publish(sample.clone());
+        let first = inspector.latest().unwrap();
+
+        publisher.publish(StateSample {
+            instruction_count: 2,
+            ..sample
+        });
+        assert_eq!(first.instruction_count, 1);
+        assert_eq!(inspector.latest().unwrap().instruction_count, 2);
+    }
+}
@@ -0,0 +1,84 @@
+//! Interrupt controller for the hardware interrupt lines IP2 to IP7.
+//!
+//! Devices on the bus assert their lines when the CPU polls them before each instruction. Sources
+//! outside the bus, such as an input script, a host thread feeding the guest or an embedding
+//! application, drive the lines through an `IntCtrl` instead. Handles are cheap to clone and can
+//! be used from any thread, the CPU sees a raised line before its next instruction and takes the
+//! Interrupt exception once the line is unmasked and interrupts are enabled.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// Shared levels of the hardware interrupt lines, with IP2 in bit 0.
+#[derive(Debug, Clone, Default)]
+pub struct IntCtrl {
+    lines: Arc<AtomicU8>,
+}
+
+impl IntCtrl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the mask of hardware interrupt line `ip`, which is between 2 and 7.
+    fn mask(ip: u8) -> u8 {
+        assert!(
+            (2..=7).contains(&ip),
+            "IP{} is not a hardware interrupt line",
+            ip
+        );
+        1 << (ip - 2)
+    }
+
+    /// Asserts hardware interrupt line `ip`, which is between 2 and 7.
+    pub fn raise(&self, ip: u8) {
+        self.lines.fetch_or(Self::mask(ip), Ordering::SeqCst);
+    }
+
+    /// Deasserts hardware interrupt line `ip`, which is between 2 and 7.
+    pub fn lower(&self, ip: u8) {
+        self.lines.fetch_and(!Self::mask(ip), Ordering::SeqCst);
+    }
+
+    /// Drives hardware interrupt line `ip` to `asserted`.
+    pub fn set(&self, ip: u8, asserted: bool) {
+        match asserted {
+            true => self.raise(ip),
+            false => self.lower(ip),
+        }
+    }
+
+    /// Returns true if hardware interrupt line `ip` is asserted.
+    pub fn is_raised(&self, ip: u8) -> bool {
+        self.lines() & Self::mask(ip) != 0
+    }
+
+    /// Returns the asserted lines, with IP2 in bit 0.
+    pub fn lines(&self) -> u8 {
+        self.lines.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn intctrl_lines() {
+        let intctrl = IntCtrl::new();
+        let handle = intctrl.clone();
+        std::thread::spawn(move || {
+            handle.raise(2);
+            handle.raise(7);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(intctrl.lines(), 0b10_0001);
+
+        intctrl.set(7, false);
+        assert!(intctrl.is_raised(2) && !intctrl.is_raised(7));
+        intctrl.lower(2);
+        assert_eq!(intctrl.lines(), 0);
+    }
+}
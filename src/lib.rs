@@ -1,28 +1,209 @@
 #[macro_use]
 extern crate bitflags;
 
+mod asm;
+mod blocks;
+mod config;
+mod console;
 mod control;
+pub mod coverage;
+pub mod determinism;
 mod devices;
 pub mod emulator;
 mod gdb;
+mod governor;
+pub mod heap;
+mod inject;
+pub mod inspect;
+pub mod intctrl;
+pub mod lint;
+mod machine;
 mod memory;
+pub mod regmap;
+pub mod session;
+pub mod shadow_stack;
+pub mod snapshot;
+pub mod test_report;
+mod timeline;
+mod trace;
 pub mod util;
+pub mod watch;
 
 type Address = u32;
 
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord)]
+/// Byte order of the emulated machine, in which words are stored in memory.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Default)]
 pub enum Endian {
     Big,
+    #[default]
     Little,
 }
 
+impl Endian {
+    /// Returns the word stored in `bytes`.
+    pub fn word(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Endian::Big => u32::from_be_bytes(bytes),
+            Endian::Little => u32::from_le_bytes(bytes),
+        }
+    }
+
+    /// Returns the bytes that store `word`.
+    pub fn word_bytes(self, word: u32) -> [u8; 4] {
+        match self {
+            Endian::Big => word.to_be_bytes(),
+            Endian::Little => word.to_le_bytes(),
+        }
+    }
+
+    /// Returns the halfword stored in `bytes`.
+    pub fn halfword(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Endian::Big => u16::from_be_bytes(bytes),
+            Endian::Little => u16::from_le_bytes(bytes),
+        }
+    }
+
+    /// Returns the bytes that store `halfword`.
+    pub fn halfword_bytes(self, halfword: u16) -> [u8; 2] {
+        match self {
+            Endian::Big => halfword.to_be_bytes(),
+            Endian::Little => halfword.to_le_bytes(),
+        }
+    }
+
+    /// Zero-extends the value stored in up to four bytes to a word.
+    pub fn value(self, bytes: &[u8]) -> u32 {
+        let bytes = &bytes[..bytes.len().min(4)];
+        match self {
+            Endian::Big => bytes.iter().fold(0, |value, &b| (value << 8) | b as u32),
+            Endian::Little => bytes
+                .iter()
+                .rev()
+                .fold(0, |value, &b| (value << 8) | b as u32),
+        }
+    }
+
+    /// Returns the name of the byte order, as in "big-endian".
+    pub fn name(self) -> &'static str {
+        match self {
+            Endian::Big => "big-endian",
+            Endian::Little => "little-endian",
+        }
+    }
+}
+
+/// Why the emulated machine stopped running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HaltReason {
+    /// A `break` instruction was executed.
+    Break,
+    /// An instruction could not be fetched.
+    InstructionBusError,
+    /// The guest wrote a nonzero value to the halt device.
+    HaltDevice,
+    /// The guest called the monitor PROM exit service with the given status.
+    Exit(i32),
+    /// The guest wrote to the `DONE` register of the test device after running its tests.
+    TestsFinished,
+    /// A watchpoint or access breakpoint was hit at the given physical address.
+    Watchpoint(Address),
+    /// The watch expression with the given index became true.
+    WatchExpression(usize),
+    /// The user quit with the Ctrl-A x console escape sequence.
+    Quit,
+    /// SIGINT or SIGTERM with the given number was received.
+    Signal(i32),
+    /// The `--stop-at` condition was reached, or the point `--rewind` instructions before it.
+    StopAt,
+    /// A load, store or fetch touched a `--guard` region at the given physical address.
+    GuardRegion(Address),
+}
+
+impl HaltReason {
+    /// Returns the exit status if the guest requested the halt, where a halt device write
+    /// counts as a successful exit. Failed tests change the status of a finished test run.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            HaltReason::HaltDevice | HaltReason::TestsFinished => Some(0),
+            HaltReason::Exit(status) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+/// Why the CPU was reset while the emulator kept running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResetReason {
+    /// The guest wrote a nonzero value to the reset device.
+    ResetDevice,
+    /// The debugger requested a reset with `monitor reset`.
+    Debugger,
+}
+
+/// A request that a device made to the emulator during an instruction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceRequest {
+    /// The debug print device printed the string at this virtual address.
+    DebugPrint { address: Address },
+}
+
+/// What happened during a call to `Emulator::step`.
+///
+/// `Halted`, `Breakpoint`, `Watch`, `WatchExpression` and `LimitReached` stop a run, the other
+/// events only report what the instruction did.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum EmulationEvent {
+    /// The instruction executed without anything to report.
     Step,
-    Halted,
-    Breakpoint,
-    WatchWrite(Address),
-    WatchRead(Address),
+    /// The machine stopped.
+    Halted(HaltReason),
+    /// The program counter reached the breakpoint at `address`.
+    Breakpoint { address: Address },
+    /// The watched physical `address` was accessed, reading or writing `value`.
+    Watch {
+        kind: AccessKind,
+        address: Address,
+        value: u32,
+    },
+    /// The watch expression with the given index became true.
+    WatchExpression(usize),
+    /// The instruction raised an exception, which is handled at the exception vector.
+    Exception { code: Exception, epc: Address },
+    /// The instruction at `pc` branched to itself with a `nop` in its delay slot, so the guest
+    /// waits for an interrupt.
+    IdleLoop { pc: Address },
+    /// No instruction was executed because the instruction limit of a replay was reached.
+    LimitReached,
+    /// A device made a request that the emulator serviced.
+    DeviceRequest(DeviceRequest),
+    /// The CPU was reset and continues at the reset vector, with memory left as it was.
+    Reset(ResetReason),
+}
+
+impl EmulationEvent {
+    /// Returns true if the event stops a run.
+    pub fn is_stop(&self) -> bool {
+        matches!(
+            self,
+            EmulationEvent::Halted(_)
+                | EmulationEvent::Breakpoint { .. }
+                | EmulationEvent::Watch { .. }
+                | EmulationEvent::WatchExpression(_)
+                | EmulationEvent::LimitReached
+        )
+    }
 }
 
+pub use control::cpu::InterruptLatency;
+pub use control::disasm;
+pub use control::exception::Exception;
+pub use control::isa;
+pub use control::model::CpuModel;
 pub use control::registers;
+pub use devices::i2c::I2cSlaveSpec;
+pub use devices::serial_link::SerialEndpoint;
+pub use devices::spi::SpiSlaveSpec;
+pub use devices::time::{MachineClock, ManualClock, TimeSource};
+pub use memory::faults::FaultKind;
+pub use memory::monitor::AccessKind;
@@ -0,0 +1,240 @@
+//! Lint checks for common assembly bugs in the guest code as it executes.
+//!
+//! Each executed instruction is checked for writes to `$zero`, HI/LO hazards, reserved
+//! encodings and branches placed in the delay slot of another branch. Every finding is
+//! reported once per instruction address, so loops do not flood the output.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::control::instruction::Instruction;
+use crate::Address;
+
+/// A bug pattern found by the linter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// The instruction writes a result to `$zero`, where it is discarded.
+    ZeroWrite,
+    /// The instruction modifies HI or LO within two instructions of the `mfhi` or `mflo` at
+    /// `read_at`, which returns an undefined value on the R3000.
+    HiLoHazard { read_at: Address },
+    /// The instruction word is not a MIPS I instruction.
+    ReservedEncoding,
+    /// A branch or jump is in the delay slot of the branch at `branch`.
+    BranchInDelaySlot { branch: Address },
+}
+
+/// A lint finding at one instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LintWarning {
+    pub pc: Address,
+    pub instruction: u32,
+    pub lint: Lint,
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mnemonic = Instruction(self.instruction).mnemonic();
+        write!(f, "0x{:08x}: ", self.pc)?;
+        match self.lint {
+            Lint::ZeroWrite => write!(f, "{} writes to $zero, the result is discarded", mnemonic),
+            Lint::HiLoHazard { read_at } => write!(
+                f,
+                "{} modifies HI/LO within two instructions of the read at 0x{:08x}",
+                mnemonic, read_at
+            ),
+            Lint::ReservedEncoding => {
+                write!(
+                    f,
+                    "reserved instruction encoding 0x{:08x}",
+                    self.instruction
+                )
+            }
+            Lint::BranchInDelaySlot { branch } => write!(
+                f,
+                "{} is in the delay slot of the branch at 0x{:08x}",
+                mnemonic, branch
+            ),
+        }
+    }
+}
+
+/// Returns the general-purpose register written by `instr`, if any.
+fn destination(instr: Instruction) -> Option<usize> {
+    match instr.opcode() {
+        0x00 => match instr.funct() {
+            0x00..=0x07 | 0x10 | 0x12 | 0x20..=0x27 | 0x2a | 0x2b => Some(instr.rd()),
+            _ => None,
+        },
+        0x08..=0x0f | 0x20..=0x26 => Some(instr.rt()),
+        0x10 if instr.rs() == 0 => Some(instr.rt()),
+        _ => None,
+    }
+}
+
+/// Returns true for the shifts of `$zero` into `$zero` used as `nop`, `ssnop` and `ehb`.
+fn is_shift_nop(instr: Instruction) -> bool {
+    instr.opcode() == 0 && instr.funct() == 0 && instr.rd() == 0 && instr.rt() == 0
+}
+
+fn writes_hilo(instr: Instruction) -> bool {
+    instr.opcode() == 0 && matches!(instr.funct(), 0x11 | 0x13 | 0x18..=0x1b)
+}
+
+fn reads_hilo(instr: Instruction) -> bool {
+    instr.opcode() == 0 && matches!(instr.funct(), 0x10 | 0x12)
+}
+
+#[derive(Debug, Default)]
+pub struct Linter {
+    /// The last two instructions checked, the most recent last.
+    history: Vec<(Address, Instruction)>,
+    reported: HashSet<(Address, Lint)>,
+}
+
+impl Linter {
+    /// Checks the instruction at `pc` before it executes and returns the findings that were
+    /// not reported for this address before.
+    pub fn check(&mut self, pc: Address, instr: Instruction) -> Vec<LintWarning> {
+        // Only instructions that ran immediately before this one can interact with it
+        let previous = self.history.last().copied();
+        if previous.is_some_and(|(address, _)| address.wrapping_add(4) != pc) {
+            self.history.clear();
+        }
+
+        let mut lints = Vec::new();
+        if instr.mnemonic() == "reserved" {
+            lints.push(Lint::ReservedEncoding);
+        } else if destination(instr) == Some(0) && !is_shift_nop(instr) {
+            lints.push(Lint::ZeroWrite);
+        }
+        if writes_hilo(instr) {
+            let read = self.history.iter().find(|(_, prev)| reads_hilo(*prev));
+            if let Some((read_at, _)) = read {
+                lints.push(Lint::HiLoHazard { read_at: *read_at });
+            }
+        }
+        if instr.is_control_transfer() {
+            if let Some((branch, _)) = self
+                .history
+                .last()
+                .filter(|(_, prev)| prev.is_control_transfer())
+            {
+                lints.push(Lint::BranchInDelaySlot { branch: *branch });
+            }
+        }
+
+        if self.history.len() == 2 {
+            self.history.remove(0);
+        }
+        self.history.push((pc, instr));
+
+        lints
+            .into_iter()
+            .filter(|lint| self.reported.insert((pc, *lint)))
+            .map(|lint| LintWarning {
+                pc,
+                instruction: instr.0,
+                lint,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Checks `program` as straight-line code at 0xbfc00000 and returns the lints found.
+    fn lint(program: &[u32]) -> Vec<(Address, Lint)> {
+        let mut linter = Linter::default();
+        program
+            .iter()
+            .enumerate()
+            .flat_map(|(i, word)| linter.check(0xbfc0_0000 + 4 * i as Address, Instruction(*word)))
+            .map(|warning| (warning.pc, warning.lint))
+            .collect()
+    }
+
+    #[test]
+    fn lint_zero_writes() {
+        #[rustfmt::skip]
+        let program = [
+            0x00000000, // nop
+            0x00000040, // ssnop
+            0x24000005, // addiu zero, zero, 5
+            0x00090821, // addu  at, zero, t1
+            0x8c000000, // lw    zero, 0(zero)
+            0x00000010, // mfhi  zero
+        ];
+
+        assert_eq!(
+            lint(&program),
+            vec![
+                (0xbfc0_0008, Lint::ZeroWrite),
+                (0xbfc0_0010, Lint::ZeroWrite),
+                (0xbfc0_0014, Lint::ZeroWrite),
+            ]
+        );
+    }
+
+    #[test]
+    fn lint_hilo_hazards() {
+        #[rustfmt::skip]
+        let program = [
+            0x00850018, // mult  a0, a1
+            0x00001012, // mflo  v0
+            0x00000000, // nop
+            0x0085001a, // div   a0, a1
+            0x00000000, // nop
+            0x00000000, // nop
+            0x00001810, // mfhi  v1
+            0x00000000, // nop
+            0x00000000, // nop
+            0x00850019, // multu a0, a1
+        ];
+
+        assert_eq!(
+            lint(&program),
+            vec![(
+                0xbfc0_000c,
+                Lint::HiLoHazard {
+                    read_at: 0xbfc0_0004
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn lint_reserved_and_delay_slots() {
+        #[rustfmt::skip]
+        let program = [
+            0x10000004, // b     .+20
+            0x0c000000, // jal   0
+            0x00000000, // nop
+            0xfc000000, // reserved
+        ];
+
+        assert_eq!(
+            lint(&program),
+            vec![
+                (
+                    0xbfc0_0004,
+                    Lint::BranchInDelaySlot {
+                        branch: 0xbfc0_0000
+                    }
+                ),
+                (0xbfc0_000c, Lint::ReservedEncoding),
+            ]
+        );
+    }
+
+    #[test]
+    fn lint_reports_once_per_address() {
+        let mut linter = Linter::default();
+        assert_eq!(linter.check(0x100, Instruction(0x24000005)).len(), 1);
+        assert_eq!(linter.check(0x100, Instruction(0x24000005)).len(), 0);
+        assert_eq!(linter.check(0x104, Instruction(0x24000005)).len(), 1);
+    }
+}
@@ -0,0 +1,294 @@
+//! Machine files that describe a board: the CPU, memory, ROM and devices that would otherwise be
+//! given as options on every run.
+//!
+//! `--machine board.toml` reads a file in the TOML subset of `util::parse`, with a table for each
+//! part of the board. Host files are relative to the directory of the machine file:
+//!
+//! ```toml
+//! [cpu]
+//! model = "r3000"
+//! tlb_entries = 64
+//! fpu = false
+//! big_endian = true
+//!
+//! [memory]
+//! size = 0x40_0000
+//! base = 0x1000_0000
+//!
+//! [rom]
+//! file = "firmware.bin"
+//! load_address = 0xbfc0_0000
+//!
+//! [devices]
+//! clock = true
+//! nvram = "board.nvram"
+//!
+//! [[i2c_slave]]
+//! model = "lm75"
+//! address = 0x48
+//!
+//! [[spi_slave]]
+//! model = "25lc256"
+//! ```
+//!
+//! Options given on the command line take precedence, so a key only sets an option that still
+//! has its default value.
+
+use std::path::Path;
+
+use crate::control::model::CpuModel;
+use crate::devices::i2c::I2cSlaveSpec;
+use crate::devices::spi::SpiSlaveSpec;
+use crate::util::error::{Result, RmipsError};
+use crate::util::opts::{parse_address, Opts, RamBase};
+use crate::util::parse::{self, parse_bool, parse_integer, parse_text};
+
+/// Reads the `--machine` file, if any, into the options that were left at their defaults.
+pub fn apply(opts: &mut Opts) -> Result<()> {
+    let Some(path) = opts.machine.clone() else {
+        return Ok(());
+    };
+
+    let text = std::fs::read_to_string(&path)?;
+    let dir = Path::new(&path).parent().unwrap_or_else(|| Path::new(""));
+    apply_text(&text, dir, opts).map_err(|msg| RmipsError::Machine(path, msg))
+}
+
+/// Returns the host file named by the string `value`, relative to the directory of the machine
+/// file.
+fn host_path(dir: &Path, value: &str) -> std::result::Result<String, String> {
+    Ok(dir.join(parse_text(value)?).to_string_lossy().into_owned())
+}
+
+/// An `[[i2c_slave]]` table, which needs both of its keys.
+#[derive(Default)]
+struct I2cSlaveTable {
+    line: usize,
+    model: Option<String>,
+    address: Option<u8>,
+}
+
+impl I2cSlaveTable {
+    fn spec(&self) -> std::result::Result<I2cSlaveSpec, String> {
+        let error = |msg: &str| format!("line {}: the I2C slave has no `{}`", self.line, msg);
+        let model = self.model.as_ref().ok_or_else(|| error("model"))?;
+        let address = self.address.ok_or_else(|| error("address"))?;
+        I2cSlaveSpec::new(model, address).map_err(|msg| format!("line {}: {}", self.line, msg))
+    }
+}
+
+/// Sets the options that were left at their defaults from a machine file, reporting the line of
+/// the first error.
+fn apply_text(text: &str, dir: &Path, opts: &mut Opts) -> std::result::Result<(), String> {
+    let defaults = Opts::default();
+    // Sets an option unless it was changed from its default
+    macro_rules! set {
+        ($field:ident, $value:expr) => {{
+            let value = $value;
+            if opts.$field == defaults.$field {
+                opts.$field = value;
+            }
+        }};
+    }
+
+    let mut table: Option<&str> = None;
+    let mut i2c_slaves: Vec<I2cSlaveTable> = Vec::new();
+    let mut spi_slaves: Vec<Option<SpiSlaveSpec>> = Vec::new();
+    for (number, line) in parse::lines(text) {
+        let error = |msg: String| format!("line {}: {}", number, msg);
+
+        if let Some(header) = line.strip_prefix("[[").and_then(|s| s.strip_suffix("]]")) {
+            let header = header.trim();
+            match header {
+                "i2c_slave" => i2c_slaves.push(I2cSlaveTable {
+                    line: number,
+                    ..Default::default()
+                }),
+                "spi_slave" => spi_slaves.push(None),
+                _ => return Err(error(format!("unknown table [[{}]]", header))),
+            }
+            table = Some(header);
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            let header = header.trim();
+            if !["cpu", "memory", "rom", "devices"].contains(&header) {
+                return Err(error(format!("unknown table [{}]", header)));
+            }
+            table = Some(header);
+            continue;
+        }
+
+        let (key, value) = parse::key_value(line)
+            .ok_or_else(|| error(format!("expected a [table] or `key = value`: {}", line)))?;
+        let table = table.ok_or_else(|| error(format!("`{}` is outside of a table", key)))?;
+
+        match (table, key) {
+            ("cpu", "model") => set!(
+                cpumodel,
+                parse_text(value)
+                    .and_then(|model| model.parse::<CpuModel>())
+                    .map_err(error)?
+            ),
+            ("cpu", "tlb_entries") => set!(tlbentries, Some(parse_integer(value).map_err(error)?)),
+            ("cpu", "fpu") => set!(nofpu, !parse_bool(value).map_err(error)?),
+            ("cpu", "mmu") => set!(nommu, !parse_bool(value).map_err(error)?),
+            ("cpu", "big_endian") => set!(bigendian, parse_bool(value).map_err(error)?),
+            ("memory", "size") => set!(memsize, parse_integer(value).map_err(error)?),
+            ("memory", "base") => set!(
+                rambase,
+                Some(RamBase::Fixed(parse_address(value).map_err(error)?))
+            ),
+            ("memory", "sparse") => set!(sparseram, parse_bool(value).map_err(error)?),
+            ("rom", "file") => set!(romfile, host_path(dir, value).map_err(error)?),
+            ("rom", "load_address") => set!(loadaddress, parse_address(value).map_err(error)?),
+            ("rom", "offset") => set!(romoffset, parse_integer(value).map_err(error)?),
+            ("rom", "length") => set!(romlength, Some(parse_integer(value).map_err(error)?)),
+            ("devices", "nvram") => set!(nvram, Some(host_path(dir, value).map_err(error)?)),
+            ("devices", "nvram_size") => set!(nvramsize, parse_integer(value).map_err(error)?),
+            ("devices", "shared_memory") => {
+                set!(sharedmemory, Some(host_path(dir, value).map_err(error)?))
+            }
+            ("devices", "shared_memory_size") => {
+                set!(sharedmemorysize, parse_integer(value).map_err(error)?)
+            }
+            ("devices", "keyboard") => set!(keyboard, parse_bool(value).map_err(error)?),
+            ("devices", "leds") => set!(leds, parse_bool(value).map_err(error)?),
+            ("devices", "debug_print") => set!(debugprint, parse_bool(value).map_err(error)?),
+            ("devices", "emulator_info") => set!(emulatorinfo, parse_bool(value).map_err(error)?),
+            ("devices", "clock") => set!(clock, parse_bool(value).map_err(error)?),
+            ("devices", "monitor_prom") => set!(monitorprom, parse_bool(value).map_err(error)?),
+            ("devices", "reset_device") => set!(resetdevice, parse_bool(value).map_err(error)?),
+            ("devices", "halt_device") => set!(nohaltdevice, !parse_bool(value).map_err(error)?),
+            ("devices", "halt_device_at") => {
+                set!(haltdeviceat, Some(parse_address(value).map_err(error)?))
+            }
+            ("devices", "test_device_at") => {
+                set!(testdeviceat, Some(parse_address(value).map_err(error)?))
+            }
+            ("i2c_slave", "model") => {
+                i2c_slaves.last_mut().unwrap().model = Some(parse_text(value).map_err(error)?)
+            }
+            ("i2c_slave", "address") => {
+                i2c_slaves.last_mut().unwrap().address = Some(parse_integer(value).map_err(error)?)
+            }
+            ("spi_slave", "model") => {
+                let model = parse_text(value)
+                    .and_then(|model| model.parse())
+                    .map_err(error)?;
+                *spi_slaves.last_mut().unwrap() = Some(model);
+            }
+            _ => return Err(error(format!("unknown key `{}` in [{}]", key, table))),
+        }
+    }
+
+    // The slaves of the command line replace the tables instead of adding to them
+    let i2c_slaves = i2c_slaves
+        .iter()
+        .map(I2cSlaveTable::spec)
+        .collect::<std::result::Result<_, _>>()?;
+    set!(i2cslave, i2c_slaves);
+    let spi_slaves = spi_slaves
+        .into_iter()
+        .collect::<Option<_>>()
+        .ok_or("an SPI slave has no `model`")?;
+    set!(spislave, spi_slaves);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const BOARD: &str = r#"
+        [cpu]
+        model = "r4000"
+        fpu = false
+
+        [memory]
+        size = 0x40_0000   # 4MB
+        base = 0x1000_0000
+
+        [rom]
+        file = "firmware.bin"
+        load_address = 0xbfc0_0000
+
+        [devices]
+        clock = true
+        halt_device = false
+        test_device_at = 0x1f00_0100
+
+        [[i2c_slave]]
+        model = "lm75"
+        address = 0x48
+
+        [[i2c_slave]]
+        model = "24c02"
+        address = 0x50
+
+        [[spi_slave]]
+        model = "25lc256"
+    "#;
+
+    #[test]
+    fn machine_sets_defaults() {
+        let mut opts = Opts::default();
+        apply_text(BOARD, Path::new("boards"), &mut opts).unwrap();
+        assert_eq!(opts.cpumodel, CpuModel::R4000);
+        assert!(opts.nofpu);
+        assert_eq!(opts.memsize, 0x40_0000);
+        assert_eq!(opts.rambase, Some(RamBase::Fixed(0x1000_0000)));
+        assert_eq!(Path::new(&opts.romfile), Path::new("boards/firmware.bin"));
+        assert!(opts.clock);
+        assert!(opts.nohaltdevice);
+        assert_eq!(opts.testdeviceat, Some(0x1f00_0100));
+        assert_eq!(
+            opts.i2cslave,
+            vec![I2cSlaveSpec::Lm75(0x48), I2cSlaveSpec::Eeprom24c02(0x50)]
+        );
+        assert_eq!(opts.spislave, vec![SpiSlaveSpec::Eeprom25lc256]);
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let mut opts = Opts {
+            romfile: String::from("other.bin"),
+            memsize: 0x1000,
+            i2cslave: vec![I2cSlaveSpec::Lm75(0x49)],
+            ..Default::default()
+        };
+        apply_text(BOARD, Path::new(""), &mut opts).unwrap();
+        assert_eq!(opts.romfile, "other.bin");
+        assert_eq!(opts.i2cslave, vec![I2cSlaveSpec::Lm75(0x49)]);
+        assert_eq!(opts.memsize, 0x1000);
+        assert_eq!(opts.cpumodel, CpuModel::R4000);
+    }
+
+    #[test]
+    fn machine_errors() {
+        let error = |text: &str| apply_text(text, Path::new(""), &mut Opts::default()).unwrap_err();
+        assert_eq!(error("size = 1"), "line 1: `size` is outside of a table");
+        assert_eq!(error("[gpu]"), "line 1: unknown table [gpu]");
+        assert_eq!(
+            error("[cpu]\ncores = 2"),
+            "line 2: unknown key `cores` in [cpu]"
+        );
+        assert_eq!(
+            error("[memory]\nsize = big"),
+            "line 2: invalid integer: big"
+        );
+        assert_eq!(
+            error("[cpu]\nmodel = \"z80\""),
+            "line 2: unknown CPU model: z80"
+        );
+        assert_eq!(
+            error("[[i2c_slave]]\nmodel = \"lm75\""),
+            "line 1: the I2C slave has no `address`"
+        );
+        assert_eq!(
+            error("[[i2c_slave]]\nmodel = \"lm75\"\naddress = 0x80"),
+            "line 1: I2C address is not 7 bits: 0x80"
+        );
+    }
+}
@@ -7,8 +7,13 @@ use human_panic::setup_panic;
 use log::LevelFilter;
 use simplelog::{ColorChoice, CombinedLogger, TermLogger, TerminalMode, WriteLogger};
 
+use rmips::determinism;
+use rmips::disasm;
 use rmips::emulator::Emulator;
-use rmips::util::opts::Opts;
+use rmips::isa;
+use rmips::util::opts::{Cli, Command, Opts};
+use rmips::util::signals;
+use rmips::HaltReason;
 
 fn setup_logger(opts: &Opts) {
     let log_level = match opts.verbose {
@@ -38,20 +43,82 @@ fn setup_logger(opts: &Opts) {
 }
 
 fn main() -> Result<()> {
-    setup_panic!(Metadata {
-        name: env!("CARGO_PKG_NAME").into(),
-        version: env!("CARGO_PKG_VERSION").into(),
-        authors: "starfleetcadet75 <starfleetcadet75@gmail.com>".into(),
-        homepage: "github.com/starfleetcadet75/rmips".into(),
-    });
-
-    let opts = Opts::parse();
+    #[allow(deprecated)]
+    {
+        setup_panic!(Metadata {
+            name: env!("CARGO_PKG_NAME").into(),
+            version: env!("CARGO_PKG_VERSION").into(),
+            authors: "starfleetcadet75 <starfleetcadet75@gmail.com>".into(),
+            homepage: "github.com/starfleetcadet75/rmips".into(),
+        });
+    }
+
+    let opts = match Cli::parse() {
+        Cli {
+            command: None,
+            opts,
+        } => opts,
+        // `rmips disasm` lists a ROM image instead of running it
+        Cli {
+            command: Some(Command::Disasm(opts)),
+            ..
+        } => {
+            print!("{}", disasm::rom_listing(&opts)?);
+            return Ok(());
+        }
+        // `rmips instructions` prints the instruction reference generated from the instruction table
+        Cli {
+            command: Some(Command::Instructions),
+            ..
+        } => {
+            print!("{}", isa::reference());
+            return Ok(());
+        }
+        // `rmips describe` prints the machine the options build instead of running it
+        Cli {
+            command: Some(Command::Describe(opts)),
+            ..
+        } => {
+            setup_logger(&opts);
+            println!("\n{}", Emulator::new(*opts)?.describe());
+            return Ok(());
+        }
+    };
     setup_logger(&opts);
 
+    // `--verify-determinism` compares runs instead of running the guest once
+    if let Some(runs) = opts.verifydeterminism {
+        let report = determinism::verify(&opts, runs)?;
+        println!("\n{}", report);
+        if report.divergence.is_some() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let mut emulator = Emulator::new(opts)?;
-    if let Err(err) = emulator.run() {
-        eprintln!("Error: {:#}\n\n{}", err, emulator.crashdump());
-        std::process::exit(1);
+    signals::install();
+    match emulator.run() {
+        // Pass the guest's exit status on to the host
+        // `exit` skips destructors, so the emulator is dropped first to restore the terminal
+        Ok(summary) => {
+            // Exit like the default signal handler would have, after the dumps are written
+            let status = match summary.halt_reason {
+                HaltReason::Signal(signal) => Some(128 + signal),
+                HaltReason::GuardRegion(_) => Some(1),
+                _ => summary.exit_code,
+            };
+            if let Some(status) = status.filter(|status| *status != 0) {
+                drop(emulator);
+                std::process::exit(status);
+            }
+        }
+        Err(err) => {
+            let crashdump = emulator.crashdump();
+            drop(emulator);
+            eprintln!("Error: {:#}\n\n{}", err, crashdump);
+            std::process::exit(1);
+        }
     }
 
     Ok(())
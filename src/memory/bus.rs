@@ -1,21 +1,94 @@
 use std::collections::BTreeMap;
 use std::fmt;
 
-use crate::devices::Device;
+use log::warn;
+
+use crate::devices::byte_swap::{ByteSwap, RegisterSwap};
+use crate::devices::{AccessWidths, Device};
+use crate::intctrl::IntCtrl;
+use crate::memory::faults::{Fault, FaultInjector};
+use crate::memory::heatmap::Heatmap;
+use crate::memory::monitor::{AccessKind, DataAccessLog, DeviceAccessLog, StoreLog, Watchpoints};
+use crate::memory::pagetable::{PageEntry, PageTable};
 use crate::memory::range::Range;
-use crate::memory::Memory;
+use crate::memory::{AccessContext, Memory};
 use crate::util::error::{Result, RmipsError};
-use crate::Address;
+use crate::{Address, Endian};
 
 /// A container for routing reads and writes to the correct address space.
+///
+/// Devices are located through a `PageTable`, so most accesses are resolved with
+/// two array lookups. Only pages that are shared between devices, or that are
+/// partially mapped, fall back to searching the ordered device ranges.
 pub struct Bus {
-    devices: BTreeMap<Range, Box<dyn Device>>,
+    devices: Vec<(Range, Box<dyn Device>)>,
+    ranges: BTreeMap<Range, usize>,
+    pages: PageTable,
+    pub(crate) watchpoints: Watchpoints,
+    pub(crate) stores: StoreLog,
+    pub(crate) data_accesses: DataAccessLog,
+    pub(crate) device_accesses: DeviceAccessLog,
+    pub(crate) faults: Option<FaultInjector>,
+    /// Instruction ranges that the program flushed from the cache, recorded while enabled.
+    pub(crate) invalidations: Option<Vec<Range>>,
+    /// Counts the `Cpu` accesses to every page while enabled, for `--heatmap`.
+    pub(crate) heatmap: Option<Heatmap>,
+    /// Regions that are treated as unmapped even where a device is mapped.
+    pub(crate) guards: Vec<Range>,
+    /// Regions whose devices are connected with swapped byte lanes when they are registered.
+    pub(crate) byte_swapped: Vec<Range>,
+    aliases: Vec<Alias>,
+    /// Handles of the devices that can assert interrupt lines.
+    interrupt_sources: Vec<usize>,
+    /// Interrupt lines asserted from outside the machine, such as by an input script.
+    pub(crate) intctrl: IntCtrl,
+    /// Byte order of the CPU accesses.
+    endian: Endian,
+}
+
+/// A region of the physical address space that mirrors another one, like RAM that is
+/// visible at several bases because the address decoder ignores the upper address lines.
+#[derive(Clone, Copy, Debug)]
+pub struct Alias {
+    pub range: Range,
+    /// Physical address that the start of the region forwards to.
+    pub target: Address,
+    /// Size of the mirrored window, which repeats every `stride` bytes across the region.
+    pub stride: usize,
+}
+
+impl Alias {
+    /// Returns the address that `address` in the region forwards to, and the number of
+    /// bytes from there until the mirrored window wraps around.
+    fn resolve(&self, address: Address) -> (Address, usize) {
+        let offset = (address - self.range.base()) as usize % self.stride;
+        let remaining = (self.range.last() - address) as usize + 1;
+        (
+            self.target.wrapping_add(offset as Address),
+            remaining.min(self.stride - offset),
+        )
+    }
 }
 
 impl Bus {
     pub fn new() -> Self {
         Self {
-            devices: BTreeMap::new(),
+            devices: Vec::new(),
+            ranges: BTreeMap::new(),
+            pages: PageTable::new(),
+            watchpoints: Watchpoints::default(),
+            stores: StoreLog::default(),
+            data_accesses: DataAccessLog::default(),
+            device_accesses: DeviceAccessLog::default(),
+            faults: None,
+            invalidations: None,
+            heatmap: None,
+            guards: Vec::new(),
+            byte_swapped: Vec::new(),
+            aliases: Vec::new(),
+            interrupt_sources: Vec::new(),
+            intctrl: IntCtrl::new(),
+            endian: Endian::Little,
         }
     }
 
@@ -25,93 +98,545 @@ impl Bus {
         }
 
         // Validate that the addresses for the new `Device` do not overlap with an existing one.
-        if self
-            .devices
+        if !self.is_free(base, size) {
+            return Err(RmipsError::MemoryRangeOverlap);
+        }
+
+        // The device models keep their registers in little-endian order, so a big-endian CPU
+        // sees the same register values, as on boards whose peripherals match the CPU. Memory
+        // that code can run from holds the bytes in the order of the image instead.
+        let device: Box<dyn Device> = match self.endian == Endian::Big && !device.executable() {
+            true => Box::new(RegisterSwap::new(device)),
+            false => device,
+        };
+        let device: Box<dyn Device> = match self
+            .byte_swapped
             .iter()
-            .any(|(range, _)| range.overlaps(base, size))
+            .any(|region| region.overlaps(base, size))
         {
+            true => Box::new(ByteSwap::new(device)),
+            false => device,
+        };
+
+        let range = Range::new(base, size);
+        let handle = self.devices.len();
+        if self.ranges.insert(range, handle).is_some() {
             return Err(RmipsError::MemoryRangeOverlap);
         }
 
-        match self.devices.insert(Range::new(base, size), device) {
-            Some(_) => Err(RmipsError::MemoryRangeOverlap),
-            None => Ok(()),
+        self.pages.map(&range, handle);
+        if device.interrupt_outputs() != 0 {
+            self.interrupt_sources.push(handle);
         }
+        self.devices.push((range, device));
+        Ok(())
+    }
+
+    /// Sets the byte order of the CPU, which has to happen before any device is registered.
+    pub fn set_endian(&mut self, endian: Endian) {
+        self.endian = endian;
+        self.watchpoints.endian = endian;
+        self.stores.endian = endian;
+        self.data_accesses.endian = endian;
+        self.device_accesses.endian = endian;
+    }
+
+    /// Returns the byte order of the CPU.
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Returns true if no `Device` or alias is mapped in the `size` bytes starting at `base`.
+    pub fn is_free(&self, base: Address, size: usize) -> bool {
+        !self.ranges.keys().any(|range| range.overlaps(base, size))
+            && !self
+                .aliases
+                .iter()
+                .any(|alias| alias.range.overlaps(base, size))
+    }
+
+    /// Maps `alias` so that accesses to it are forwarded to the region it mirrors.
+    ///
+    /// The alias may not overlap a device or another alias, but its target does not need
+    /// to be mapped until it is accessed.
+    pub fn alias(&mut self, alias: Alias) -> Result<()> {
+        if alias.stride == 0 || !self.is_free(alias.range.base(), alias.range.size()) {
+            return Err(RmipsError::MemoryRangeOverlap);
+        }
+        self.aliases.push(alias);
+        Ok(())
+    }
+
+    /// Returns the address that an access to `address` goes to, following aliases, and the
+    /// number of bytes from there that can be accessed in one piece.
+    #[inline(always)]
+    fn resolve(&self, address: Address) -> (Address, usize) {
+        if self.aliases.is_empty() {
+            return (address, usize::MAX);
+        }
+        self.aliases
+            .iter()
+            .find(|alias| alias.range.contains(address))
+            .map_or((address, usize::MAX), |alias| alias.resolve(address))
+    }
+
+    /// Returns the address that an access of `len` bytes at `address` goes to, following
+    /// aliases.
+    ///
+    /// An access that runs past the end of a mirrored window is rejected like one that runs
+    /// past the end of a device, since its bytes would go to two places.
+    #[inline(always)]
+    fn resolve_access(&self, address: Address, len: usize) -> Result<Address> {
+        let (resolved, window) = self.resolve(address);
+        match len <= window {
+            true => Ok(resolved),
+            false => Err(RmipsError::DeviceBoundary(address)),
+        }
+    }
+
+    /// Returns the handle of the `Device` mapped at `address`.
+    #[inline(always)]
+    fn lookup(&self, address: Address) -> Option<usize> {
+        match self.pages.get(address) {
+            PageEntry::Unmapped => None,
+            PageEntry::Device(handle) => Some(handle),
+            PageEntry::Split => self
+                .ranges
+                .range(..=Range::new(address, 1))
+                .nth_back(0)
+                .filter(|(range, _)| address <= range.last())
+                .map(|(_, handle)| *handle),
+        }
+    }
+
+    pub fn get_device(&self, address: Address) -> Option<(&Range, &dyn Device)> {
+        let (range, dev) = &self.devices[self.lookup(address)?];
+        Some((range, dev.as_ref()))
     }
 
     pub fn get_device_mut(&mut self, address: Address) -> Option<(&Range, &mut Box<dyn Device>)> {
+        let handle = self.lookup(address)?;
+        let (range, dev) = &mut self.devices[handle];
+        Some((&*range, dev))
+    }
+
+    /// Iterates over the mapped devices in the order they were mapped.
+    pub(crate) fn devices(&self) -> impl Iterator<Item = (&Range, &dyn Device)> {
+        self.devices
+            .iter()
+            .map(|(range, dev)| (range, dev.as_ref()))
+    }
+
+    /// Iterates mutably over the mapped devices in the order they were mapped.
+    pub(crate) fn devices_mut(&mut self) -> impl Iterator<Item = (&Range, &mut Box<dyn Device>)> {
+        self.devices.iter_mut().map(|(range, dev)| (&*range, dev))
+    }
+
+    /// Iterates over the mapped devices in address order.
+    pub fn map(&self) -> impl Iterator<Item = (&Range, &dyn Device)> {
+        self.ranges
+            .iter()
+            .map(move |(range, handle)| (range, self.devices[*handle].1.as_ref()))
+    }
+
+    /// Iterates over the devices backed by plain memory, yielding the physical base address
+    /// and contents of each one in address order.
+    pub fn memory_regions(&self) -> impl Iterator<Item = (Address, &[u8])> {
+        self.ranges.iter().filter_map(move |(range, handle)| {
+            self.devices[*handle]
+                .1
+                .memory()
+                .map(|data| (range.base(), data))
+        })
+    }
+
+    /// Returns the physical address of every occurrence of `pattern` in the memory regions.
+    ///
+    /// Matches never span two regions.
+    pub fn find<'a>(&'a self, pattern: &'a [u8]) -> impl Iterator<Item = Address> + 'a {
+        self.memory_regions()
+            .filter(move |_| !pattern.is_empty())
+            .flat_map(move |(base, data)| {
+                data.windows(pattern.len())
+                    .enumerate()
+                    .filter(move |(_, window)| *window == pattern)
+                    .map(move |(offset, _)| base + offset as Address)
+            })
+    }
+
+    /// Returns the physical addresses of all device pages written since the last `clear_dirty`.
+    pub fn dirty_pages(&self) -> Vec<Address> {
+        self.ranges
+            .iter()
+            .flat_map(|(range, handle)| {
+                let base = range.base();
+                self.devices[*handle]
+                    .1
+                    .dirty_pages()
+                    .into_iter()
+                    .map(move |offset| base + offset)
+            })
+            .collect()
+    }
+
+    /// Marks the pages of every device as clean.
+    pub fn clear_dirty(&mut self) {
         self.devices
-            .range_mut(..=Range::new(address, 1))
-            .nth_back(0)
-            .filter(|pair| address <= pair.0.last())
+            .iter_mut()
+            .for_each(|(_, dev)| dev.clear_dirty());
     }
 
-    fn read(&mut self, address: Address, data: &mut [u8]) -> Result<()> {
-        if let Some((range, dev)) = self.get_device_mut(address) {
-            let offset = address - range.base();
-            dev.read(offset, data)
-        } else {
-            Err(RmipsError::UnmappedAddress(address))
+    /// Finds the `Device` that services an access of `len` bytes starting at `address`
+    /// and returns it together with the offset of the access into the device.
+    ///
+    /// Accesses that start in one device and end outside of it are rejected as a whole,
+    /// so no device ever observes a partial read or write. `Cpu` and DMA accesses with a
+    /// width that the device does not support are rejected as well, and so are instruction
+    /// fetches from devices that are not executable.
+    fn access_device(
+        &mut self,
+        address: Address,
+        len: usize,
+        ctx: AccessContext,
+    ) -> Result<(Address, &mut Box<dyn Device>)> {
+        let end = address as u64 + len as u64;
+        if self
+            .guards
+            .iter()
+            .any(|guard| (guard.base() as u64) < end && address <= guard.last())
+        {
+            return Err(RmipsError::GuardRegion(address));
+        }
+
+        let (range, dev) = self
+            .get_device_mut(address)
+            .ok_or(RmipsError::UnmappedAddress(address))?;
+
+        if ctx == AccessContext::CpuFetch && !dev.executable() {
+            warn!(
+                "Instruction fetch from non-executable device {} at 0x{:08x}",
+                dev.debug_label(),
+                address
+            );
+            return Err(RmipsError::BusError(address));
         }
+
+        let last = (len as Address)
+            .checked_sub(1)
+            .and_then(|extent| address.checked_add(extent));
+        match last {
+            Some(last) if last <= range.last() => {}
+            _ => return Err(RmipsError::DeviceBoundary(address)),
+        }
+
+        if ctx != AccessContext::Debugger
+            && !dev.access_widths().contains(AccessWidths::for_len(len))
+        {
+            return Err(RmipsError::AccessWidth(address, len));
+        }
+
+        Ok((address - range.base(), dev))
+    }
+
+    /// Reads `data.len()` bytes starting at `address` without side effects on the devices.
+    ///
+    /// Intended for debugger accesses, so device access width restrictions do not apply.
+    /// The bytes may span several devices.
+    pub fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        while done < data.len() {
+            let (address, window) = self.resolve(address.wrapping_add(done as Address));
+            let (range, dev) = self
+                .get_device(address)
+                .ok_or(RmipsError::UnmappedAddress(address))?;
+
+            let available = (range.last() - address) as usize + 1;
+            let len = available.min(window).min(data.len() - done);
+            dev.peek(address - range.base(), &mut data[done..done + len])?;
+            done += len;
+        }
+
+        Ok(())
     }
 
-    fn write(&mut self, address: Address, data: &[u8]) -> Result<()> {
-        if let Some((range, dev)) = self.get_device_mut(address) {
-            let offset = address - range.base();
-            dev.write(offset, data)
-        } else {
-            Err(RmipsError::UnmappedAddress(address))
+    /// Rolls for an injected fault on a `Cpu` data access of `len` bytes at `address`.
+    fn inject_fault(&mut self, address: Address, len: usize, ctx: AccessContext) -> Option<Fault> {
+        if !matches!(ctx, AccessContext::CpuLoad | AccessContext::CpuStore) {
+            return None;
         }
+
+        let ram = self
+            .get_device(address)
+            .is_some_and(|(_, dev)| dev.is_memory());
+        self.faults.as_mut()?.roll(len, ram)
+    }
+
+    /// Reads `data.len()` bytes starting at `address` on behalf of `ctx`.
+    pub fn read(&mut self, address: Address, data: &mut [u8], ctx: AccessContext) -> Result<()> {
+        let issued = address;
+        let address = self.resolve_access(address, data.len())?;
+        let fault = match self.faults {
+            Some(_) => self.inject_fault(address, data.len(), ctx),
+            None => None,
+        };
+        if fault == Some(Fault::BusError) {
+            return Err(RmipsError::BusError(address));
+        }
+
+        let log_device = self.device_accesses.is_enabled();
+        let (offset, dev) = self.access_device(address, data.len(), ctx)?;
+        if let Some(Fault::BitFlip { byte, bit }) = fault {
+            flip_bit(dev.as_mut(), offset + byte as Address, bit)?;
+        }
+        dev.read(offset, data, ctx)?;
+
+        let label = match log_device {
+            true => device_label(dev.as_ref(), ctx),
+            false => None,
+        };
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record(address, ctx);
+        }
+        if let Some(label) = label {
+            self.device_accesses.record(label, address, data, false);
+        }
+        if ctx == AccessContext::CpuLoad && self.data_accesses.is_enabled() {
+            self.data_accesses.record(AccessKind::Read, issued, data);
+        }
+
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.check(ctx, address, data);
+        }
+        Ok(())
+    }
+
+    /// Writes `data` starting at `address` on behalf of `ctx`.
+    pub fn write(&mut self, address: Address, data: &[u8], ctx: AccessContext) -> Result<()> {
+        let issued = address;
+        let address = self.resolve_access(address, data.len())?;
+        let fault = match self.faults {
+            Some(_) => self.inject_fault(address, data.len(), ctx),
+            None => None,
+        };
+        if fault == Some(Fault::BusError) {
+            return Err(RmipsError::BusError(address));
+        }
+
+        let log_store = ctx == AccessContext::CpuStore && self.stores.is_enabled();
+        let log_device = self.device_accesses.is_enabled();
+        let (offset, dev) = self.access_device(address, data.len(), ctx)?;
+        let label = match log_device {
+            true => device_label(dev.as_ref(), ctx),
+            false => None,
+        };
+
+        // Capture the previous contents first so the store can be reported
+        let mut previous = [0; 4];
+        let old = match log_store && data.len() <= previous.len() {
+            true => dev
+                .peek(offset, &mut previous[..data.len()])
+                .ok()
+                .map(|_| &previous[..data.len()]),
+            false => None,
+        };
+        dev.write(offset, data, ctx)?;
+        if let Some(Fault::BitFlip { byte, bit }) = fault {
+            flip_bit(dev.as_mut(), offset + byte as Address, bit)?;
+        }
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record(address, ctx);
+        }
+
+        if log_store {
+            self.stores.record(address, old, data);
+        }
+        if let Some(label) = label {
+            self.device_accesses.record(label, address, data, true);
+        }
+        if ctx == AccessContext::CpuStore && self.data_accesses.is_enabled() {
+            self.data_accesses.record(AccessKind::Write, issued, data);
+        }
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.check(ctx, address, data);
+        }
+        Ok(())
+    }
+
+    /// Reads `data.len()` bytes starting at `address` on behalf of the bus-mastering device
+    /// labelled `initiator`.
+    ///
+    /// The access is reported to watchpoints and the device access log under the label of
+    /// the initiating device, since no `Cpu` instruction performed it.
+    // No bus-mastering device is emulated yet
+    #[allow(dead_code)]
+    pub fn dma_read(&mut self, initiator: &str, address: Address, data: &mut [u8]) -> Result<()> {
+        let address = self.resolve_access(address, data.len())?;
+        let log_device = self.device_accesses.is_enabled();
+        let (offset, dev) = self.access_device(address, data.len(), AccessContext::Dma)?;
+        dev.read(offset, data, AccessContext::Dma)?;
+
+        if log_device {
+            let device = dev.debug_label();
+            self.device_accesses
+                .record_initiated(initiator, device, address, data, false);
+        }
+        if !self.watchpoints.is_empty() {
+            self.watchpoints
+                .check_device(initiator, AccessKind::Read, address, data);
+        }
+        Ok(())
+    }
+
+    /// Writes `data` starting at `address` on behalf of the bus-mastering device labelled
+    /// `initiator`.
+    #[allow(dead_code)]
+    pub fn dma_write(&mut self, initiator: &str, address: Address, data: &[u8]) -> Result<()> {
+        let address = self.resolve_access(address, data.len())?;
+        let log_device = self.device_accesses.is_enabled();
+        let (offset, dev) = self.access_device(address, data.len(), AccessContext::Dma)?;
+        dev.write(offset, data, AccessContext::Dma)?;
+
+        if log_device {
+            let device = dev.debug_label();
+            self.device_accesses
+                .record_initiated(initiator, device, address, data, true);
+        }
+        if !self.watchpoints.is_empty() {
+            self.watchpoints
+                .check_device(initiator, AccessKind::Write, address, data);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the label of `dev` if a `ctx` access to it is a `Cpu` access to a device
+/// that is not plain memory.
+fn device_label(dev: &dyn Device, ctx: AccessContext) -> Option<String> {
+    match ctx {
+        AccessContext::CpuLoad | AccessContext::CpuStore if !dev.is_memory() => {
+            Some(dev.debug_label())
+        }
+        _ => None,
     }
 }
 
+/// Corrupts one bit of a device's memory, like a soft error in DRAM.
+fn flip_bit(dev: &mut dyn Device, offset: Address, bit: u8) -> Result<()> {
+    let mut byte = [0];
+    dev.peek(offset, &mut byte)?;
+    byte[0] ^= 1 << bit;
+    dev.write(offset, &byte, AccessContext::Dma)
+}
+
 impl Memory for Bus {
+    fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    fn interrupt_lines(&mut self) -> u8 {
+        let devices = &mut self.devices;
+        self.interrupt_sources
+            .iter()
+            .fold(self.intctrl.lines(), |lines, handle| {
+                lines | devices[*handle].1.interrupt_lines()
+            })
+    }
+
+    fn fetch_instruction(&mut self, address: Address) -> Result<u32> {
+        let mut data = [0; 4];
+        self.read(address, &mut data, AccessContext::CpuFetch)?;
+        Ok(self.endian.word(data))
+    }
+
     fn fetch_word(&mut self, address: Address) -> Result<u32> {
         let mut data = [0; 4];
-        self.read(address, &mut data)?;
-        Ok(u32::from_le_bytes(data))
+        self.read(address, &mut data, AccessContext::CpuLoad)?;
+        Ok(self.endian.word(data))
     }
 
     fn fetch_halfword(&mut self, address: Address) -> Result<u16> {
         let mut data = [0; 2];
-        self.read(address, &mut data)?;
-        Ok(u16::from_le_bytes(data))
+        self.read(address, &mut data, AccessContext::CpuLoad)?;
+        Ok(self.endian.halfword(data))
     }
 
     fn fetch_byte(&mut self, address: Address) -> Result<u8> {
         let mut data = [0; 1];
-        self.read(address, &mut data)?;
+        self.read(address, &mut data, AccessContext::CpuLoad)?;
         Ok(u8::from_le_bytes(data))
     }
 
     fn store_word(&mut self, address: Address, data: u32) -> Result<()> {
-        let data = u32::to_le_bytes(data);
-        self.write(address, &data)
+        let data = self.endian.word_bytes(data);
+        self.write(address, &data, AccessContext::CpuStore)
     }
 
     fn store_halfword(&mut self, address: Address, data: u16) -> Result<()> {
-        let data = u16::to_le_bytes(data);
-        self.write(address, &data)
+        let data = self.endian.halfword_bytes(data);
+        self.write(address, &data, AccessContext::CpuStore)
     }
 
     fn store_byte(&mut self, address: Address, data: u8) -> Result<()> {
         let data = u8::to_le_bytes(data);
-        self.write(address, &data)
+        self.write(address, &data, AccessContext::CpuStore)
+    }
+
+    fn invalidate_instructions(&mut self, address: Address, len: usize) {
+        if let Some(invalidations) = &mut self.invalidations {
+            invalidations.push(Range::new(address, len));
+        }
     }
 }
 
 impl fmt::Display for Bus {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (range, device) in &self.devices {
-            writeln!(f, "  {}  {}", range, device.debug_label())?;
+        for (range, dev) in self.map() {
+            writeln!(
+                f,
+                "  0x{:08x}-0x{:08x}  {:>8}  {}",
+                range.base(),
+                range.last(),
+                format_size(range.size()),
+                dev.debug_label()
+            )?;
+        }
+        for alias in &self.aliases {
+            let stride = match alias.stride < alias.range.size() {
+                true => format!(" every {}", format_size(alias.stride)),
+                false => String::new(),
+            };
+            writeln!(
+                f,
+                "  0x{:08x}-0x{:08x}  {:>8}  Alias of 0x{:08x}{}",
+                alias.range.base(),
+                alias.range.last(),
+                format_size(alias.range.size()),
+                alias.target,
+                stride
+            )?;
         }
         write!(f, "")
     }
 }
 
+/// Formats a device size in the largest unit that divides it evenly.
+fn format_size(size: usize) -> String {
+    if size >= 1 << 20 && size.is_multiple_of(1 << 20) {
+        format!("{}MB", size >> 20)
+    } else if size >= 1 << 10 && size.is_multiple_of(1 << 10) {
+        format!("{}KB", size >> 10)
+    } else {
+        format!("{}B", size)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
     use super::*;
+    use crate::memory::ram::Ram;
 
     #[derive(Copy, Clone, Debug)]
     struct TestDevice {
@@ -123,7 +648,11 @@ mod tests {
             "test-device".to_owned()
         }
 
-        fn read(&mut self, address: Address, data: &mut [u8]) -> Result<()> {
+        fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+            self.peek(address, data)
+        }
+
+        fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
             for (i, v) in data.iter_mut().enumerate() {
                 *v = *self
                     .data
@@ -134,7 +663,7 @@ mod tests {
             Ok(())
         }
 
-        fn write(&mut self, address: Address, data: &[u8]) -> Result<()> {
+        fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
             for (i, v) in data.iter().enumerate() {
                 if let Some(elem) = self.data.get_mut((address as usize) + i) {
                     *elem = *v;
@@ -145,6 +674,10 @@ mod tests {
 
             Ok(())
         }
+
+        fn executable(&self) -> bool {
+            true
+        }
     }
 
     #[test]
@@ -167,15 +700,15 @@ mod tests {
         assert!(bus.register(device, 0x100, 0x8).is_ok());
 
         let mut data = [0; 4];
-        assert!(bus.read(0x100, &mut data).is_ok());
+        assert!(bus.read(0x100, &mut data, AccessContext::CpuLoad).is_ok());
         assert_eq!(data, [0xde, 0xad, 0xbe, 0xef]);
 
         let mut data = [0; 2];
-        assert!(bus.read(0x104, &mut data).is_ok());
+        assert!(bus.read(0x104, &mut data, AccessContext::CpuLoad).is_ok());
         assert_eq!(data, [0xca, 0xfe]);
 
-        assert!(bus.read(0x110, &mut data).is_err());
-        assert!(bus.read(0xff, &mut data).is_err());
+        assert!(bus.read(0x110, &mut data, AccessContext::CpuLoad).is_err());
+        assert!(bus.read(0xff, &mut data, AccessContext::CpuLoad).is_err());
     }
 
     #[test]
@@ -185,10 +718,10 @@ mod tests {
         assert!(bus.register(device, 0x100, 0x8).is_ok());
 
         let data = [0xde, 0xad, 0xbe, 0xef, 0xca, 0xfe, 0xba, 0xbe];
-        assert!(bus.write(0x100, &data).is_ok());
+        assert!(bus.write(0x100, &data, AccessContext::CpuStore).is_ok());
 
         let mut data = [0; 8];
-        assert!(bus.read(0x100, &mut data).is_ok());
+        assert!(bus.read(0x100, &mut data, AccessContext::CpuLoad).is_ok());
         assert_eq!(data, [0xde, 0xad, 0xbe, 0xef, 0xca, 0xfe, 0xba, 0xbe]);
     }
 
@@ -286,4 +819,502 @@ mod tests {
         assert!(bus.store_byte(0x108, 0xff).is_err());
         Ok(())
     }
+
+    #[test]
+    fn bus_cross_device_access() -> Result<()> {
+        let mut bus = Bus::new();
+        let device = Box::new(TestDevice { data: [0; 8] });
+        assert!(bus.register(device.clone(), 0x100, 0x8).is_ok());
+        assert!(bus.register(device, 0x108, 0x8).is_ok());
+
+        assert!(matches!(
+            bus.store_word(0x106, 0xdeadbeef),
+            Err(RmipsError::DeviceBoundary(0x106))
+        ));
+        assert!(matches!(
+            bus.fetch_halfword(0x107),
+            Err(RmipsError::DeviceBoundary(0x107))
+        ));
+
+        // Neither device observes part of the rejected store
+        assert_eq!(bus.fetch_halfword(0x106)?, 0);
+        assert_eq!(bus.fetch_halfword(0x108)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn bus_access_past_device_end() {
+        let mut bus = Bus::new();
+        let device = Box::new(TestDevice { data: [0; 8] });
+        assert!(bus.register(device, 0x100, 0x6).is_ok());
+
+        assert!(matches!(
+            bus.fetch_word(0x104),
+            Err(RmipsError::DeviceBoundary(0x104))
+        ));
+        assert!(matches!(
+            bus.store_halfword(0x105, 0xabcd),
+            Err(RmipsError::DeviceBoundary(0x105))
+        ));
+    }
+
+    #[test]
+    fn bus_access_at_top_of_address_space() {
+        let mut bus = Bus::new();
+        let device = Box::new(TestDevice { data: [0; 8] });
+        assert!(bus.register(device, 0xffff_fff8, 0x8).is_ok());
+
+        assert!(bus.store_word(0xffff_fffc, 0x1234_5678).is_ok());
+        assert!(matches!(
+            bus.fetch_word(0xffff_fffe),
+            Err(RmipsError::DeviceBoundary(0xffff_fffe))
+        ));
+    }
+
+    #[derive(Debug)]
+    struct WordDevice {
+        value: u32,
+    }
+
+    impl Device for WordDevice {
+        fn debug_label(&self) -> String {
+            "word-device".to_owned()
+        }
+
+        fn access_widths(&self) -> AccessWidths {
+            AccessWidths::WORD
+        }
+
+        fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+            self.peek(address, data)
+        }
+
+        fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+            let offset = address as usize;
+            data.copy_from_slice(&self.value.to_le_bytes()[offset..offset + data.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, _address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+            self.value = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bus_access_width_restrictions() -> Result<()> {
+        let mut bus = Bus::new();
+        assert!(bus
+            .register(Box::new(WordDevice { value: 0 }), 0x100, 0x4)
+            .is_ok());
+
+        assert!(bus.store_word(0x100, 0xdeadbeef).is_ok());
+        assert_eq!(bus.fetch_word(0x100)?, 0xdeadbeef);
+
+        assert!(matches!(
+            bus.fetch_byte(0x100),
+            Err(RmipsError::AccessWidth(0x100, 1))
+        ));
+        assert!(matches!(
+            bus.store_halfword(0x102, 0xabcd),
+            Err(RmipsError::AccessWidth(0x102, 2))
+        ));
+        assert_eq!(bus.fetch_word(0x100)?, 0xdeadbeef);
+        Ok(())
+    }
+
+    #[derive(Debug, Default)]
+    struct FifoDevice {
+        fifo: Vec<u8>,
+    }
+
+    impl Device for FifoDevice {
+        fn debug_label(&self) -> String {
+            "fifo-device".to_owned()
+        }
+
+        fn read(&mut self, _address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+            data[0] = self.fifo.pop().unwrap_or(0);
+            Ok(())
+        }
+
+        fn peek(&self, _address: Address, data: &mut [u8]) -> Result<()> {
+            data[0] = self.fifo.last().copied().unwrap_or(0);
+            Ok(())
+        }
+
+        fn write(&mut self, _address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+            self.fifo.push(data[0]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bus_peek_has_no_side_effects() -> Result<()> {
+        let mut bus = Bus::new();
+        assert!(bus
+            .register(Box::new(FifoDevice::default()), 0x100, 0x1)
+            .is_ok());
+        bus.store_byte(0x100, 0x12)?;
+        bus.store_byte(0x100, 0x34)?;
+
+        let mut data = [0; 1];
+        bus.peek(0x100, &mut data)?;
+        bus.peek(0x100, &mut data)?;
+        assert_eq!(data, [0x34]);
+
+        assert_eq!(bus.fetch_byte(0x100)?, 0x34);
+        assert_eq!(bus.fetch_byte(0x100)?, 0x12);
+        Ok(())
+    }
+
+    #[test]
+    fn bus_peek_across_devices() -> Result<()> {
+        let mut bus = Bus::new();
+        let device = Box::new(TestDevice {
+            data: [0xde, 0xad, 0xbe, 0xef, 0xca, 0xfe, 0xba, 0xbe],
+        });
+        assert!(bus.register(device.clone(), 0x100, 0x8).is_ok());
+        assert!(bus.register(device, 0x108, 0x8).is_ok());
+        assert!(bus
+            .register(Box::new(WordDevice { value: 0x1234_5678 }), 0x110, 0x4)
+            .is_ok());
+
+        let mut data = [0; 4];
+        bus.peek(0x106, &mut data)?;
+        assert_eq!(data, [0xba, 0xbe, 0xde, 0xad]);
+
+        let mut data = [0; 4];
+        bus.peek(0x110, &mut data)?;
+        assert_eq!(data, [0x78, 0x56, 0x34, 0x12]);
+
+        assert!(matches!(
+            bus.peek(0x112, &mut data),
+            Err(RmipsError::UnmappedAddress(0x114))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn bus_fetch_from_non_executable_device() -> Result<()> {
+        let mut bus = Bus::new();
+        let device = Box::new(WordDevice { value: 0x1234_5678 });
+        assert!(bus.register(device, 0x100, 0x4).is_ok());
+
+        assert_eq!(bus.fetch_word(0x100)?, 0x1234_5678);
+        assert!(matches!(
+            bus.fetch_instruction(0x100),
+            Err(RmipsError::BusError(0x100))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn bus_guard_regions() -> Result<()> {
+        let mut bus = Bus::new();
+        assert!(bus.register(Box::new(Ram::new(0x1000)), 0, 0x1000).is_ok());
+        bus.guards.push(Range::new(0x800, 0x100));
+        assert!(!bus.is_free(0xfff, 1));
+        assert!(bus.is_free(0x1000, 0x1000));
+
+        bus.store_word(0x7fc, 1)?;
+        bus.store_word(0x900, 2)?;
+        assert!(matches!(
+            bus.store_word(0x8fc, 3),
+            Err(RmipsError::GuardRegion(0x8fc))
+        ));
+        assert!(matches!(
+            bus.fetch_halfword(0x7ff),
+            Err(RmipsError::GuardRegion(0x7ff))
+        ));
+        Ok(())
+    }
+
+    #[derive(Debug, Default)]
+    struct ContextDevice {
+        accesses: Rc<RefCell<Vec<AccessContext>>>,
+    }
+
+    impl Device for ContextDevice {
+        fn debug_label(&self) -> String {
+            "context-device".to_owned()
+        }
+
+        fn access_widths(&self) -> AccessWidths {
+            AccessWidths::WORD
+        }
+
+        fn read(&mut self, address: Address, data: &mut [u8], ctx: AccessContext) -> Result<()> {
+            self.accesses.borrow_mut().push(ctx);
+            self.peek(address, data)
+        }
+
+        fn peek(&self, _address: Address, data: &mut [u8]) -> Result<()> {
+            data.iter_mut().for_each(|v| *v = 0);
+            Ok(())
+        }
+
+        fn write(&mut self, _address: Address, _data: &[u8], ctx: AccessContext) -> Result<()> {
+            self.accesses.borrow_mut().push(ctx);
+            Ok(())
+        }
+
+        fn executable(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn bus_access_context() -> Result<()> {
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let device = Box::new(ContextDevice {
+            accesses: Rc::clone(&accesses),
+        });
+        let mut bus = Bus::new();
+        assert!(bus.register(device, 0x100, 0x4).is_ok());
+
+        bus.fetch_instruction(0x100)?;
+        bus.fetch_word(0x100)?;
+        bus.store_word(0x100, 0)?;
+        bus.write(0x100, &[0; 4], AccessContext::Dma)?;
+
+        // Debugger accesses are not subject to the device access widths
+        bus.write(0x101, &[0], AccessContext::Debugger)?;
+        assert!(bus.write(0x101, &[0], AccessContext::Dma).is_err());
+
+        // Peeking never reaches `Device::read`
+        bus.peek(0x100, &mut [0; 4])?;
+
+        assert_eq!(
+            *accesses.borrow(),
+            vec![
+                AccessContext::CpuFetch,
+                AccessContext::CpuLoad,
+                AccessContext::CpuStore,
+                AccessContext::Dma,
+                AccessContext::Debugger,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bus_watchpoints() -> Result<()> {
+        let mut bus = Bus::new();
+        let device = Box::new(TestDevice { data: [0; 8] });
+        assert!(bus.register(device, 0x100, 0x8).is_ok());
+        bus.watchpoints.add(0x104);
+
+        bus.fetch_word(0x100)?;
+        assert!(bus.watchpoints.take_hit().is_none());
+
+        bus.store_word(0x104, 0xcafebabe)?;
+        let hit = bus.watchpoints.take_hit().unwrap();
+        assert!(matches!(hit.kind, AccessKind::Write));
+        assert_eq!((hit.address, hit.data, hit.len), (0x104, 0xcafebabe, 4));
+
+        bus.fetch_halfword(0x104)?;
+        let hit = bus.watchpoints.take_hit().unwrap();
+        assert!(matches!(hit.kind, AccessKind::Read));
+        assert_eq!((hit.address, hit.data, hit.len), (0x104, 0xbabe, 2));
+
+        // Instruction fetches and debugger accesses do not trigger watchpoints
+        bus.fetch_instruction(0x104)?;
+        bus.peek(0x104, &mut [0; 4])?;
+        bus.write(0x104, &[0], AccessContext::Debugger)?;
+        assert!(bus.watchpoints.take_hit().is_none());
+
+        assert!(bus.watchpoints.remove(0x104));
+        assert!(!bus.watchpoints.remove(0x104));
+        bus.store_word(0x104, 0)?;
+        assert!(bus.watchpoints.take_hit().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn bus_dma_attribution() -> Result<()> {
+        let mut bus = Bus::new();
+        assert!(bus.register(Box::new(Ram::new(0x1000)), 0, 0x1000).is_ok());
+        bus.device_accesses.enable();
+        bus.watchpoints.add(0x104);
+
+        bus.dma_write("disk", 0x104, &[0xef, 0xbe, 0xad, 0xde])?;
+        let hit = bus.watchpoints.take_hit().unwrap();
+        assert!(matches!(hit.kind, AccessKind::Write));
+        assert_eq!(hit.initiator.as_deref(), Some("disk"));
+        assert_eq!((hit.address, hit.data, hit.len), (0x104, 0xdeadbeef, 4));
+
+        let mut data = [0; 2];
+        bus.dma_read("disk", 0x104, &mut data)?;
+        assert_eq!(data, [0xef, 0xbe]);
+        assert!(matches!(
+            bus.watchpoints.take_hit().unwrap().kind,
+            AccessKind::Read
+        ));
+
+        // Unlike `Cpu` accesses, device-initiated accesses to plain memory are logged
+        let accesses = bus.device_accesses.take();
+        assert_eq!(accesses.len(), 2);
+        assert_eq!(accesses[0].initiator.as_deref(), Some("disk"));
+        assert_eq!((accesses[1].write, accesses[1].data), (false, 0xbeef));
+
+        // A CPU access to the same address is not attributed to a device
+        bus.store_word(0x104, 0)?;
+        assert!(bus.watchpoints.take_hit().unwrap().initiator.is_none());
+        assert!(bus.device_accesses.take().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn bus_aliases() -> Result<()> {
+        let mut bus = Bus::new();
+        assert!(bus.register(Box::new(Ram::new(0x1000)), 0, 0x1000).is_ok());
+        let alias = |base, size, stride| Alias {
+            range: Range::new(base, size),
+            target: 0,
+            stride,
+        };
+        bus.alias(alias(0x1_0000, 0x4000, 0x1000))?;
+
+        // Every window of the alias mirrors the RAM
+        bus.store_word(0x1_2004, 0xcafebabe)?;
+        assert_eq!(bus.fetch_word(0x4)?, 0xcafebabe);
+        assert_eq!(bus.fetch_word(0x1_3004)?, 0xcafebabe);
+
+        // Debugger reads wrap around at the end of each window
+        bus.store_word(0xffc, 0x0403_0201)?;
+        let mut data = [0; 12];
+        bus.peek(0x1_0ffc, &mut data)?;
+        assert_eq!(data, [1, 2, 3, 4, 0, 0, 0, 0, 0xbe, 0xba, 0xfe, 0xca]);
+
+        // Other accesses that cross the end of a window fail without touching the RAM
+        assert!(matches!(
+            bus.fetch_word(0x1_0ffe),
+            Err(RmipsError::DeviceBoundary(0x1_0ffe))
+        ));
+        assert!(matches!(
+            bus.write(0x1_3ffe, &[0xff; 4], AccessContext::Debugger),
+            Err(RmipsError::DeviceBoundary(0x1_3ffe))
+        ));
+        assert!(matches!(
+            bus.dma_write("dma", 0x1_1ffc, &[0xff; 8]),
+            Err(RmipsError::DeviceBoundary(0x1_1ffc))
+        ));
+        bus.peek(0xffc, &mut data[..4])?;
+        assert_eq!(data[..4], [1, 2, 3, 4]);
+        bus.peek(0, &mut data[..4])?;
+        assert_eq!(data[..4], [0, 0, 0, 0]);
+
+        // Aliases can neither overlap devices nor each other
+        assert!(bus.alias(alias(0x800, 0x1000, 0x1000)).is_err());
+        assert!(bus.alias(alias(0x1_3000, 0x2000, 0x1000)).is_err());
+        assert!(bus.alias(alias(0x2_0000, 0x1000, 0)).is_err());
+        assert!(bus
+            .register(Box::new(Ram::new(0x1000)), 0x1_3000, 0x1000)
+            .is_err());
+
+        // Writes through an alias are watched at the address they go to
+        bus.watchpoints.add(0x8);
+        bus.store_byte(0x1_1008, 1)?;
+        assert_eq!(bus.watchpoints.take_hit().unwrap().address, 0x8);
+        Ok(())
+    }
+
+    #[test]
+    fn bus_shared_page_lookup() -> Result<()> {
+        let mut bus = Bus::new();
+        let device = Box::new(TestDevice {
+            data: [0xef, 0xbe, 0xad, 0xde, 0xbe, 0xba, 0xfe, 0xca],
+        });
+        assert!(bus.register(device.clone(), 0x1ffc, 0x8).is_ok());
+        assert!(bus.register(device, 0x2010, 0x8).is_ok());
+        assert!(bus
+            .register(Box::new(WordDevice { value: 0x1234_5678 }), 0x2004, 0x4)
+            .is_ok());
+
+        assert_eq!(bus.fetch_word(0x1ffc)?, 0xdeadbeef);
+        assert_eq!(bus.fetch_word(0x2000)?, 0xcafebabe);
+        assert_eq!(bus.fetch_word(0x2004)?, 0x1234_5678);
+        assert_eq!(bus.fetch_word(0x2014)?, 0xcafebabe);
+        assert!(matches!(
+            bus.fetch_word(0x2008),
+            Err(RmipsError::UnmappedAddress(0x2008))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn bus_dirty_pages() -> Result<()> {
+        let mut bus = Bus::new();
+        assert!(bus
+            .register(Box::new(Ram::new(0x4000)), 0x1000, 0x4000)
+            .is_ok());
+        assert!(bus
+            .register(Box::new(Ram::new(0x1000)), 0x8000, 0x1000)
+            .is_ok());
+
+        bus.store_word(0x3004, 0xdeadbeef)?;
+        bus.store_byte(0x8fff, 0xff)?;
+        assert_eq!(bus.dirty_pages(), vec![0x3000, 0x8000]);
+
+        bus.clear_dirty();
+        assert!(bus.dirty_pages().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn bus_memory_regions() -> Result<()> {
+        let mut bus = Bus::new();
+        let device = Box::new(TestDevice { data: [0; 8] });
+        assert!(bus
+            .register(Box::new(Ram::new(0x100)), 0x2000, 0x100)
+            .is_ok());
+        assert!(bus.register(device, 0x100, 0x8).is_ok());
+        assert!(bus.register(Box::new(Ram::new(0x10)), 0x1000, 0x10).is_ok());
+
+        let regions: Vec<_> = bus
+            .memory_regions()
+            .map(|(base, data)| (base, data.len()))
+            .collect();
+        assert_eq!(regions, vec![(0x1000, 0x10), (0x2000, 0x100)]);
+        Ok(())
+    }
+
+    #[test]
+    fn bus_find() -> Result<()> {
+        let mut bus = Bus::new();
+        assert!(bus
+            .register(Box::new(Ram::new(0x100)), 0x1000, 0x100)
+            .is_ok());
+        assert!(bus
+            .register(Box::new(Ram::new(0x100)), 0x1100, 0x100)
+            .is_ok());
+
+        for (i, b) in b"hello".iter().enumerate() {
+            bus.store_byte(0x1010 + i as Address, *b)?;
+            bus.store_byte(0x10fe + i as Address, *b)?;
+            bus.store_byte(0x11f0 + i as Address, *b)?;
+        }
+
+        // The copy at 0x10fe spans both regions
+        assert_eq!(bus.find(b"hello").collect::<Vec<_>>(), vec![0x1010, 0x11f0]);
+        assert_eq!(bus.find(b"").count(), 0);
+        assert_eq!(bus.find(b"goodbye").count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn bus_display_map() {
+        let mut bus = Bus::new();
+        let device = Box::new(TestDevice { data: [0; 8] });
+        assert!(bus.register(device, 0x0201_0000, 0x8).is_ok());
+        assert!(bus
+            .register(Box::new(Ram::new(0x10_0000)), 0, 0x10_0000)
+            .is_ok());
+
+        assert_eq!(
+            bus.to_string(),
+            "  0x00000000-0x000fffff       1MB  RAM\n  0x02010000-0x02010007        8B  test-device\n"
+        );
+    }
 }
@@ -0,0 +1,140 @@
+//! Random hardware fault injection for exercising guest error handling.
+//!
+//! Faults are injected into `Cpu` loads and stores with a configured probability per million
+//! accesses. A bus error makes the access fail so the `Cpu` raises a Data Bus Error exception,
+//! and a bit flip corrupts one bit of RAM at the accessed location like a DRAM soft error.
+//! The generator is seeded so a failing run can be reproduced.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::util::rng::XorShift;
+
+/// The kinds of faults that may be injected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultKind {
+    BusError,
+    BitFlip,
+    /// Either of the above with equal probability.
+    Any,
+}
+
+impl FromStr for FaultKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bus" => Ok(FaultKind::BusError),
+            "bitflip" => Ok(FaultKind::BitFlip),
+            "any" => Ok(FaultKind::Any),
+            _ => Err(format!("invalid fault kind: {}", s)),
+        }
+    }
+}
+
+/// A fault chosen for a single access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    BusError,
+    /// Flip bit `bit` of byte `byte` of the access.
+    BitFlip {
+        byte: usize,
+        bit: u8,
+    },
+}
+
+pub struct FaultInjector {
+    rate: u32,
+    kind: FaultKind,
+    rng: XorShift,
+    bus_errors: u64,
+    bit_flips: u64,
+}
+
+impl FaultInjector {
+    /// Creates an injector that faults `rate` out of every million accesses.
+    pub fn new(rate: u32, kind: FaultKind, seed: u64) -> Self {
+        Self {
+            rate: rate.min(1_000_000),
+            kind,
+            rng: XorShift::new(seed),
+            bus_errors: 0,
+            bit_flips: 0,
+        }
+    }
+
+    /// Decides whether to fault an access of `len` bytes to `ram` or another device.
+    ///
+    /// Bit flips are only injected into RAM.
+    pub fn roll(&mut self, len: usize, ram: bool) -> Option<Fault> {
+        if self.rng.next() % 1_000_000 >= self.rate as u64 {
+            return None;
+        }
+
+        let random = self.rng.next();
+        let bit_flip = match self.kind {
+            FaultKind::BusError => false,
+            FaultKind::BitFlip => true,
+            FaultKind::Any => random & 1 == 1,
+        };
+
+        if bit_flip && ram && len > 0 {
+            self.bit_flips += 1;
+            Some(Fault::BitFlip {
+                byte: (random >> 8) as usize % len,
+                bit: ((random >> 4) & 7) as u8,
+            })
+        } else if !bit_flip {
+            self.bus_errors += 1;
+            Some(Fault::BusError)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for FaultInjector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Injected {} bus errors and {} bit flips",
+            self.bus_errors, self.bit_flips
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn fault_injector_rate() {
+        let mut never = FaultInjector::new(0, FaultKind::Any, 1);
+        assert!((0..10_000).all(|_| never.roll(4, true).is_none()));
+
+        let mut always = FaultInjector::new(1_000_000, FaultKind::BusError, 1);
+        assert!((0..100).all(|_| always.roll(4, true) == Some(Fault::BusError)));
+
+        // Bit flips are only injected into RAM
+        let mut flips = FaultInjector::new(1_000_000, FaultKind::BitFlip, 7);
+        assert_eq!(flips.roll(4, false), None);
+        match flips.roll(2, true) {
+            Some(Fault::BitFlip { byte, bit }) => assert!(byte < 2 && bit < 8),
+            fault => panic!("expected a bit flip, got {:?}", fault),
+        }
+        assert_eq!(flips.to_string(), "Injected 0 bus errors and 1 bit flips");
+    }
+
+    #[test]
+    fn fault_injector_is_reproducible() {
+        let mut a = FaultInjector::new(100_000, FaultKind::Any, 42);
+        let mut b = FaultInjector::new(100_000, FaultKind::Any, 42);
+        let faults: Vec<_> = (0..1000).map(|_| a.roll(4, true)).collect();
+        assert!(faults.iter().any(|fault| fault.is_some()));
+        assert_eq!(
+            faults,
+            (0..1000).map(|_| b.roll(4, true)).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Per-page counts of the memory accesses of the `Cpu`.
+//!
+//! The counts are kept for every 4KB page of the physical address space that was touched, so
+//! they can be exported as CSV or JSON to visualize which parts of RAM, ROM and the devices a
+//! program actually uses.
+
+use std::collections::BTreeMap;
+
+use crate::memory::pagetable::PAGE_SHIFT;
+use crate::memory::AccessContext;
+use crate::Address;
+
+/// Accesses to a single page.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PageCounts {
+    pub reads: u64,
+    pub writes: u64,
+    pub executes: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Heatmap {
+    pages: BTreeMap<Address, PageCounts>,
+}
+
+impl Heatmap {
+    /// Counts an access on behalf of `ctx` at the physical `address`.
+    pub fn record(&mut self, address: Address, ctx: AccessContext) {
+        let page = address >> PAGE_SHIFT << PAGE_SHIFT;
+        let counts = match ctx {
+            AccessContext::CpuLoad | AccessContext::CpuStore | AccessContext::CpuFetch => {
+                self.pages.entry(page).or_default()
+            }
+            AccessContext::Debugger | AccessContext::Dma => return,
+        };
+        match ctx {
+            AccessContext::CpuLoad => counts.reads += 1,
+            AccessContext::CpuStore => counts.writes += 1,
+            _ => counts.executes += 1,
+        }
+    }
+
+    /// Returns the counts of every touched page by its physical base address.
+    pub fn pages(&self) -> &BTreeMap<Address, PageCounts> {
+        &self.pages
+    }
+
+    /// Serializes the counts as CSV, with the device each page belongs to from `label`.
+    pub fn to_csv(&self, label: &dyn Fn(Address) -> String) -> String {
+        let mut csv = String::from("page,device,reads,writes,executes\n");
+        for (page, counts) in &self.pages {
+            csv += &format!(
+                "0x{:08x},{},{},{},{}\n",
+                page,
+                label(*page),
+                counts.reads,
+                counts.writes,
+                counts.executes
+            );
+        }
+        csv
+    }
+
+    /// Serializes the counts as JSON, with the device each page belongs to from `label`.
+    pub fn to_json(&self, label: &dyn Fn(Address) -> String) -> String {
+        let pages: Vec<String> = self
+            .pages
+            .iter()
+            .map(|(page, counts)| {
+                format!(
+                    "    {{ \"page\": \"0x{:08x}\", \"device\": \"{}\", \"reads\": {}, \"writes\": {}, \"executes\": {} }}",
+                    page,
+                    label(*page),
+                    counts.reads,
+                    counts.writes,
+                    counts.executes
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\n  \"page_size\": {},\n  \"pages\": [\n{}\n  ]\n}}\n",
+            1 << PAGE_SHIFT,
+            pages.join(",\n")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn heatmap_counts_pages() {
+        let mut heatmap = Heatmap::default();
+        heatmap.record(0x1fc0_0000, AccessContext::CpuFetch);
+        heatmap.record(0x1fc0_0004, AccessContext::CpuFetch);
+        heatmap.record(0x0000_1ffc, AccessContext::CpuStore);
+        heatmap.record(0x0000_1000, AccessContext::CpuLoad);
+        heatmap.record(0x0000_2000, AccessContext::Debugger);
+
+        let label = |page: Address| match page {
+            0x1fc0_0000 => "rom".to_owned(),
+            _ => "RAM".to_owned(),
+        };
+        assert_eq!(
+            heatmap.to_csv(&label),
+            "page,device,reads,writes,executes\n\
+             0x00001000,RAM,1,1,0\n\
+             0x1fc00000,rom,0,0,2\n"
+        );
+        assert!(heatmap.to_json(&label).contains(
+            r#"{ "page": "0x1fc00000", "device": "rom", "reads": 0, "writes": 0, "executes": 2 }"#
+        ));
+    }
+}
@@ -1,17 +1,52 @@
 use crate::util::error::Result;
-use crate::Address;
+use crate::{Address, Endian};
 
 pub(crate) mod bus;
+pub(crate) mod faults;
+pub(crate) mod heatmap;
 pub(crate) mod monitor;
+pub(crate) mod pagetable;
 pub(crate) mod ram;
 pub(crate) mod range;
 pub(crate) mod rom;
 
+/// Identifies who performed a memory access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessContext {
+    /// Data load by the `Cpu`.
+    CpuLoad,
+    /// Data store by the `Cpu`.
+    CpuStore,
+    /// Instruction fetch by the `Cpu`.
+    CpuFetch,
+    /// Access on behalf of an attached debugger.
+    Debugger,
+    /// Access initiated by a device rather than the `Cpu`.
+    Dma,
+}
+
 pub trait Memory {
+    /// Returns the byte order in which words are stored.
+    fn endian(&self) -> Endian {
+        Endian::Little
+    }
+    /// Returns the hardware interrupt lines that devices are asserting, with IP2 in bit 0.
+    fn interrupt_lines(&mut self) -> u8 {
+        0
+    }
+    /// Fetches the instruction word at `address`.
+    ///
+    /// Kept separate from `fetch_word` so instruction fetches can be told apart from data loads.
+    fn fetch_instruction(&mut self, address: Address) -> Result<u32> {
+        self.fetch_word(address)
+    }
     fn fetch_word(&mut self, address: Address) -> Result<u32>;
     fn fetch_halfword(&mut self, address: Address) -> Result<u16>;
     fn fetch_byte(&mut self, address: Address) -> Result<u8>;
     fn store_word(&mut self, address: Address, data: u32) -> Result<()>;
     fn store_halfword(&mut self, address: Address, data: u16) -> Result<()>;
     fn store_byte(&mut self, address: Address, data: u8) -> Result<()>;
+    /// Drops anything cached about the instructions in the `len` bytes at the physical
+    /// `address`, which the program flushed from the instruction cache.
+    fn invalidate_instructions(&mut self, _address: Address, _len: usize) {}
 }
@@ -1,74 +1,338 @@
-use crate::memory::Memory;
-use crate::util::error::Result;
-use crate::Address;
-
-macro_rules! impl_memsniff_r {
-    ($fn:ident, $ret:ty) => {
-        fn $fn(&mut self, address: Address) -> Result<$ret> {
-            let ret = self.memory.$fn(address)?;
-            if self.addresses.contains(&address) {
-                (self.on_access)(Access {
-                    kind: AccessKind::Read,
-                    address,
-                    data: ret as u32,
-                    len: ret.to_le_bytes().len(),
-                });
-            }
-            Ok(ret)
-        }
-    };
-}
-
-macro_rules! impl_memsniff_w {
-    ($fn:ident, $data:ty) => {
-        fn $fn(&mut self, address: Address, data: $data) -> Result<()> {
-            self.memory.$fn(address, data)?;
-            if self.addresses.contains(&address) {
-                (self.on_access)(Access {
-                    kind: AccessKind::Write,
-                    address,
-                    data: data as u32,
-                    len: data.to_le_bytes().len(),
-                });
-            }
-            Ok(())
-        }
-    };
-}
+use crate::memory::range::Range;
+use crate::memory::AccessContext;
+use crate::{Address, Endian};
 
+/// Whether a watched access read or wrote memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum AccessKind {
     Read,
     Write,
 }
 
+#[allow(dead_code)]
 pub struct Access {
     pub kind: AccessKind,
     pub address: Address,
     pub data: u32,
     pub len: usize,
+    /// The watched region that was hit, if the access did not hit a single watched address.
+    pub region: Option<WatchRegion>,
+    /// Label of the device that performed the access, or `None` for the `Cpu`.
+    pub initiator: Option<String>,
 }
 
-pub struct Monitor<'a, M: Memory, F: FnMut(Access)> {
-    memory: &'a mut M,
-    addresses: &'a [Address],
-    on_access: F,
+/// A physical memory region that stops emulation on the first matching access.
+#[derive(Clone, Copy, Debug)]
+pub struct WatchRegion {
+    pub range: Range,
+    /// Virtual address the region was requested at, used for reporting.
+    pub vaddress: Address,
+    pub read: bool,
+    pub write: bool,
 }
 
-impl<'a, M: Memory, F: FnMut(Access)> Monitor<'a, M, F> {
-    pub fn new(memory: &'a mut M, addresses: &'a [Address], on_access: F) -> Monitor<'a, M, F> {
-        Monitor {
-            memory,
-            addresses,
-            on_access,
+impl WatchRegion {
+    /// Returns true if an access of `kind` to `len` bytes at `address` touches the region.
+    fn matches(&self, kind: &AccessKind, address: Address, len: usize) -> bool {
+        let kind_matches = match kind {
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+        };
+        kind_matches && self.range.overlaps(address, len)
+    }
+}
+
+/// Watched addresses that are checked against every `Cpu` data access on the `Bus`.
+///
+/// The set is owned by the `Bus` and only updated when watchpoints are added or removed,
+/// so stepping the `Cpu` does not need to set up any per-instruction state.
+#[derive(Default)]
+pub struct Watchpoints {
+    addresses: Vec<Address>,
+    regions: Vec<WatchRegion>,
+    hit: Option<Access>,
+    /// Byte order in which the accessed values are reported.
+    pub(crate) endian: Endian,
+}
+
+impl Watchpoints {
+    pub fn add(&mut self, address: Address) {
+        self.addresses.push(address);
+    }
+
+    /// Removes a watchpoint, returning false if there was none at `address`.
+    pub fn remove(&mut self, address: Address) -> bool {
+        match self.addresses.iter().position(|x| *x == address) {
+            Some(pos) => {
+                self.addresses.remove(pos);
+                true
+            }
+            None => false,
         }
     }
+
+    /// Returns the watched addresses, in the order they were added.
+    pub fn addresses(&self) -> &[Address] {
+        &self.addresses
+    }
+
+    /// Removes every watched address, while the watched regions stay.
+    pub fn clear_addresses(&mut self) {
+        self.addresses.clear();
+    }
+
+    /// Watches a region until the first access to it, after which it is removed.
+    pub fn add_region(&mut self, region: WatchRegion) {
+        self.regions.push(region);
+    }
+
+    /// Returns true if no addresses are being watched.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty() && self.regions.is_empty()
+    }
+
+    /// Records a hit if the `Cpu` data access of `data` at `address` touches a watched address.
+    pub fn check(&mut self, ctx: AccessContext, address: Address, data: &[u8]) {
+        let kind = match ctx {
+            AccessContext::CpuLoad => AccessKind::Read,
+            AccessContext::CpuStore => AccessKind::Write,
+            _ => return,
+        };
+        self.check_access(kind, address, data, None);
+    }
+
+    /// Records a hit if an access initiated by the device labelled `initiator` touches a
+    /// watched address, e.g. a DMA transfer into a watched buffer.
+    pub fn check_device(
+        &mut self,
+        initiator: &str,
+        kind: AccessKind,
+        address: Address,
+        data: &[u8],
+    ) {
+        self.check_access(kind, address, data, Some(initiator));
+    }
+
+    fn check_access(
+        &mut self,
+        kind: AccessKind,
+        address: Address,
+        data: &[u8],
+        initiator: Option<&str>,
+    ) {
+        let region = if self.addresses.contains(&address) {
+            None
+        } else {
+            let pos = self
+                .regions
+                .iter()
+                .position(|region| region.matches(&kind, address, data.len()));
+            match pos {
+                Some(pos) => Some(self.regions.remove(pos)),
+                None => return,
+            }
+        };
+
+        self.hit = Some(Access {
+            kind,
+            address,
+            data: self.endian.value(data),
+            len: data.len(),
+            region,
+            initiator: initiator.map(str::to_owned),
+        });
+    }
+
+    /// Returns the last watchpoint hit, if any, and clears it.
+    pub fn take_hit(&mut self) -> Option<Access> {
+        self.hit.take()
+    }
 }
 
-impl<'a, M: Memory, F: FnMut(Access)> Memory for Monitor<'a, M, F> {
-    impl_memsniff_r!(fetch_word, u32);
-    impl_memsniff_r!(fetch_halfword, u16);
-    impl_memsniff_r!(fetch_byte, u8);
-    impl_memsniff_w!(store_word, u32);
-    impl_memsniff_w!(store_halfword, u16);
-    impl_memsniff_w!(store_byte, u8);
+/// A data store performed by the `Cpu`, with the memory contents before and after.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Store {
+    pub address: Address,
+    pub len: usize,
+    /// Previous contents, if the device could be read without side effects.
+    pub old: Option<u32>,
+    pub new: u32,
+}
+
+/// Records the `Cpu` data stores on the `Bus` while enabled, e.g. for the `--explain` mode.
+#[derive(Default)]
+pub struct StoreLog {
+    enabled: bool,
+    stores: Vec<Store>,
+    pub(crate) endian: Endian,
+}
+
+impl StoreLog {
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    /// Returns true if stores are being recorded.
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn record(&mut self, address: Address, old: Option<&[u8]>, new: &[u8]) {
+        self.stores.push(Store {
+            address,
+            len: new.len(),
+            old: old.map(|old| self.endian.value(old)),
+            new: self.endian.value(new),
+        });
+    }
+
+    /// Returns the stores recorded since the last call and clears the log.
+    pub fn take(&mut self) -> Vec<Store> {
+        std::mem::take(&mut self.stores)
+    }
+}
+
+/// A data load or store performed by the `Cpu`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DataAccess {
+    pub kind: AccessKind,
+    /// Physical address that the `Cpu` accessed, before any alias is resolved.
+    pub address: Address,
+    pub len: usize,
+    pub value: u32,
+}
+
+/// Records every `Cpu` data load and store on the `Bus` while enabled, for `--trace`.
+#[derive(Default)]
+pub struct DataAccessLog {
+    enabled: bool,
+    accesses: Vec<DataAccess>,
+    pub(crate) endian: Endian,
+}
+
+impl DataAccessLog {
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    /// Returns true if data accesses are being recorded.
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn record(&mut self, kind: AccessKind, address: Address, data: &[u8]) {
+        self.accesses.push(DataAccess {
+            kind,
+            address,
+            len: data.len(),
+            value: self.endian.value(data),
+        });
+    }
+
+    /// Returns the accesses recorded since the last call and clears the log.
+    pub fn take(&mut self) -> Vec<DataAccess> {
+        std::mem::take(&mut self.accesses)
+    }
+}
+
+/// A `Cpu` data access to a device that is not plain memory, or an access initiated by a
+/// device such as a DMA transfer.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DeviceAccess {
+    pub device: String,
+    /// Label of the device that performed the access, or `None` for the `Cpu`.
+    pub initiator: Option<String>,
+    pub address: Address,
+    pub len: usize,
+    pub write: bool,
+    pub data: u32,
+}
+
+/// Records the device accesses on the `Bus` while enabled, e.g. for `--timeline`.
+#[derive(Default)]
+pub struct DeviceAccessLog {
+    enabled: bool,
+    accesses: Vec<DeviceAccess>,
+    pub(crate) endian: Endian,
+}
+
+impl DeviceAccessLog {
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    /// Returns true if device accesses are being recorded.
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn record(&mut self, device: String, address: Address, data: &[u8], write: bool) {
+        self.accesses.push(DeviceAccess {
+            device,
+            initiator: None,
+            address,
+            len: data.len(),
+            write,
+            data: self.endian.value(data),
+        });
+    }
+
+    /// Records an access to `device` that the device labelled `initiator` performed.
+    pub fn record_initiated(
+        &mut self,
+        initiator: &str,
+        device: String,
+        address: Address,
+        data: &[u8],
+        write: bool,
+    ) {
+        self.accesses.push(DeviceAccess {
+            device,
+            initiator: Some(initiator.to_owned()),
+            address,
+            len: data.len(),
+            write,
+            data: self.endian.value(data),
+        });
+    }
+
+    /// Returns the accesses recorded since the last call and clears the log.
+    pub fn take(&mut self) -> Vec<DeviceAccess> {
+        std::mem::take(&mut self.accesses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn watch_region_first_access() {
+        let mut watchpoints = Watchpoints::default();
+        watchpoints.add_region(WatchRegion {
+            range: Range::new(0x100, 0x10),
+            vaddress: 0x8000_0100,
+            read: false,
+            write: true,
+        });
+
+        watchpoints.check(AccessContext::CpuLoad, 0x100, &[0; 4]);
+        watchpoints.check(AccessContext::CpuStore, 0xfc, &[0; 4]);
+        assert!(watchpoints.take_hit().is_none());
+
+        watchpoints.check(AccessContext::CpuStore, 0x10e, &[1, 2]);
+        let hit = watchpoints.take_hit().unwrap();
+        assert_eq!((hit.address, hit.data, hit.len), (0x10e, 0x0201, 2));
+        assert!(hit.region.is_some());
+
+        // Only the first access is reported
+        watchpoints.check(AccessContext::CpuStore, 0x104, &[0; 4]);
+        assert!(watchpoints.take_hit().is_none());
+        assert!(watchpoints.is_empty());
+    }
 }
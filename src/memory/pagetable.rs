@@ -0,0 +1,110 @@
+//! Two-level page table mapping physical pages to the `Device` that services them.
+
+use crate::memory::range::Range;
+use crate::Address;
+
+/// Number of address bits covered by a single page (4KB pages).
+pub const PAGE_SHIFT: u32 = 12;
+/// Number of address bits used to index each level of the table.
+const LEVEL_BITS: u32 = 10;
+const LEVEL_SIZE: usize = 1 << LEVEL_BITS;
+const LEVEL_MASK: Address = (LEVEL_SIZE as Address) - 1;
+
+/// Lookup result for a single page.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PageEntry {
+    /// No device is mapped anywhere in the page.
+    Unmapped,
+    /// The whole page is covered by the device with this handle.
+    Device(usize),
+    /// The page is only partially covered by one or more devices, so the
+    /// device has to be looked up by range.
+    Split,
+}
+
+type PageTableLevel = [PageEntry; LEVEL_SIZE];
+
+/// Maps each physical page to a `PageEntry` in O(1).
+///
+/// Second-level tables are only allocated for the parts of the address space
+/// that have devices mapped.
+pub struct PageTable {
+    directory: Vec<Option<Box<PageTableLevel>>>,
+}
+
+impl PageTable {
+    pub fn new() -> Self {
+        Self {
+            directory: vec![None; LEVEL_SIZE],
+        }
+    }
+
+    /// Returns the entry for the page containing `address`.
+    #[inline(always)]
+    pub fn get(&self, address: Address) -> PageEntry {
+        let page = address >> PAGE_SHIFT;
+        match &self.directory[(page >> LEVEL_BITS) as usize] {
+            Some(table) => table[(page & LEVEL_MASK) as usize],
+            None => PageEntry::Unmapped,
+        }
+    }
+
+    /// Maps all of the pages touched by `range` to the device `handle`.
+    ///
+    /// Pages that are only partly covered by `range` are marked as `Split`.
+    pub fn map(&mut self, range: &Range, handle: usize) {
+        let first_page = range.base() >> PAGE_SHIFT;
+        let last_page = range.last() >> PAGE_SHIFT;
+
+        for page in first_page..=last_page {
+            let page_base = page << PAGE_SHIFT;
+            let page_last = page_base | ((1 << PAGE_SHIFT) - 1);
+            let covered = range.base() <= page_base && page_last <= range.last();
+
+            let table = self.directory[(page >> LEVEL_BITS) as usize]
+                .get_or_insert_with(|| Box::new([PageEntry::Unmapped; LEVEL_SIZE]));
+            let entry = &mut table[(page & LEVEL_MASK) as usize];
+            *entry = match (covered, *entry) {
+                (true, PageEntry::Unmapped) => PageEntry::Device(handle),
+                _ => PageEntry::Split,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn pagetable_map_full_pages() {
+        let mut table = PageTable::new();
+        table.map(&Range::new(0x1000, 0x2000), 3);
+
+        assert_eq!(table.get(0x0fff), PageEntry::Unmapped);
+        assert_eq!(table.get(0x1000), PageEntry::Device(3));
+        assert_eq!(table.get(0x2abc), PageEntry::Device(3));
+        assert_eq!(table.get(0x3000), PageEntry::Unmapped);
+        assert_eq!(table.get(0xffff_ffff), PageEntry::Unmapped);
+    }
+
+    #[test]
+    fn pagetable_map_partial_pages() {
+        let mut table = PageTable::new();
+        table.map(&Range::new(0x0101_0024, 0x4), 0);
+        table.map(&Range::new(0x0000_0800, 0x1000), 1);
+
+        assert_eq!(table.get(0x0101_0000), PageEntry::Split);
+        assert_eq!(table.get(0x0000_0000), PageEntry::Split);
+        assert_eq!(table.get(0x0000_1000), PageEntry::Split);
+        assert_eq!(table.get(0x0000_2000), PageEntry::Unmapped);
+    }
+
+    #[test]
+    fn pagetable_map_top_of_address_space() {
+        let mut table = PageTable::new();
+        table.map(&Range::new(0xffff_f000, 0x1000), 7);
+        assert_eq!(table.get(0xffff_ffff), PageEntry::Device(7));
+    }
+}
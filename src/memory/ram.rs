@@ -1,16 +1,79 @@
+use std::collections::HashMap;
+
 use crate::devices::Device;
+use crate::memory::pagetable::PAGE_SHIFT;
+use crate::memory::AccessContext;
 use crate::util::error::{Result, RmipsError};
+use crate::util::state::{StateReader, StateWriter};
 use crate::Address;
 
+/// Size in bytes of the pages tracked by the dirty bitmap.
+const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+
+/// Size in bytes of the chunks that `SparseRam` allocates on first touch.
+const CHUNK_SIZE: usize = 0x1_0000;
+
+/// One bit per page of a RAM device, set when the page is written.
+#[derive(Clone, Debug)]
+struct DirtyPages(Vec<u64>);
+
+impl DirtyPages {
+    fn new(size: usize) -> Self {
+        let pages = size.div_ceil(PAGE_SIZE);
+        Self(vec![0; pages.div_ceil(64)])
+    }
+
+    #[inline(always)]
+    fn mark(&mut self, offset: usize, len: usize) {
+        let first_page = offset / PAGE_SIZE;
+        let last_page = (offset + len.max(1) - 1) / PAGE_SIZE;
+        for page in first_page..=last_page {
+            self.0[page / 64] |= 1 << (page % 64);
+        }
+    }
+
+    fn pages(&self) -> Vec<Address> {
+        self.0
+            .iter()
+            .enumerate()
+            .flat_map(|(word, bits)| {
+                (0..64)
+                    .filter(move |bit| bits & (1 << bit) != 0)
+                    .map(move |bit| ((word * 64 + bit) * PAGE_SIZE) as Address)
+            })
+            .collect()
+    }
+
+    fn clear(&mut self) {
+        self.0.iter_mut().for_each(|bits| *bits = 0);
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Ram {
     data: Vec<u8>,
+    dirty: DirtyPages,
 }
 
 impl Ram {
     pub fn new(size: usize) -> Self {
         Self {
             data: vec![0; size],
+            dirty: DirtyPages::new(size),
+        }
+    }
+
+    /// Copies `image` into RAM at `offset`, returning false if it does not fit.
+    pub fn load(&mut self, offset: usize, image: &[u8]) -> bool {
+        match self
+            .data
+            .get_mut(offset..offset.saturating_add(image.len()))
+        {
+            Some(dst) => {
+                dst.copy_from_slice(image);
+                true
+            }
+            None => false,
         }
     }
 }
@@ -20,7 +83,11 @@ impl Device for Ram {
         "RAM".to_owned()
     }
 
-    fn read(&mut self, address: Address, data: &mut [u8]) -> Result<()> {
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
         for (i, v) in data.iter_mut().enumerate() {
             *v = *self
                 .data
@@ -31,7 +98,7 @@ impl Device for Ram {
         Ok(())
     }
 
-    fn write(&mut self, address: Address, data: &[u8]) -> Result<()> {
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
         for (i, v) in data.iter().enumerate() {
             if let Some(elem) = self.data.get_mut((address as usize) + i) {
                 *elem = *v;
@@ -40,6 +107,266 @@ impl Device for Ram {
             }
         }
 
+        self.dirty.mark(address as usize, data.len());
+        Ok(())
+    }
+
+    fn memory(&self) -> Option<&[u8]> {
+        Some(&self.data)
+    }
+
+    fn dirty_pages(&self) -> Vec<Address> {
+        self.dirty.pages()
+    }
+
+    fn clear_dirty(&mut self) {
+        self.dirty.clear();
+    }
+}
+
+/// RAM that allocates its storage in 64KB chunks on first write.
+///
+/// Untouched chunks read as zero, so large memories only cost host memory for the parts
+/// the guest actually uses. There is no contiguous backing storage, so `memory` returns
+/// `None` and the contents can only be inspected through `peek`.
+#[derive(Clone, Debug)]
+pub struct SparseRam {
+    size: usize,
+    chunks: HashMap<usize, Box<[u8]>>,
+    dirty: DirtyPages,
+}
+
+impl SparseRam {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            chunks: HashMap::new(),
+            dirty: DirtyPages::new(size),
+        }
+    }
+
+    /// Copies `image` into RAM at `offset`, returning false if it does not fit.
+    pub fn load(&mut self, offset: usize, image: &[u8]) -> bool {
+        match offset.checked_add(image.len()) {
+            Some(end) if end <= self.size => {
+                self.store(offset, image);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Splits the `len` bytes at `offset` into pieces that each lie within one chunk,
+    /// yielding the chunk index, the offset into the chunk and the range of the piece.
+    fn pieces(offset: usize, len: usize) -> impl Iterator<Item = (usize, usize, usize, usize)> {
+        let mut done = 0;
+        std::iter::from_fn(move || {
+            if done == len {
+                return None;
+            }
+            let address = offset + done;
+            let start = address % CHUNK_SIZE;
+            let piece = (CHUNK_SIZE - start).min(len - done);
+            done += piece;
+            Some((address / CHUNK_SIZE, start, done - piece, done))
+        })
+    }
+
+    /// Writes `data` at `offset`, which must be in bounds.
+    fn store(&mut self, offset: usize, data: &[u8]) {
+        for (chunk, start, from, to) in Self::pieces(offset, data.len()) {
+            let data = &data[from..to];
+            // Zeroing an untouched chunk does not need to allocate it
+            if !self.chunks.contains_key(&chunk) && data.iter().all(|v| *v == 0) {
+                continue;
+            }
+            let chunk = self
+                .chunks
+                .entry(chunk)
+                .or_insert_with(|| vec![0; CHUNK_SIZE].into_boxed_slice());
+            chunk[start..start + data.len()].copy_from_slice(data);
+        }
+    }
+}
+
+impl Device for SparseRam {
+    fn debug_label(&self) -> String {
+        "RAM".to_owned()
+    }
+
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let offset = address as usize;
+        if offset + data.len() > self.size {
+            let first = offset.max(self.size);
+            return Err(RmipsError::MemoryRead(first as Address));
+        }
+
+        for (chunk, start, from, to) in Self::pieces(offset, data.len()) {
+            match self.chunks.get(&chunk) {
+                Some(chunk) => data[from..to].copy_from_slice(&chunk[start..start + to - from]),
+                None => data[from..to].fill(0),
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+        let offset = address as usize;
+        if offset + data.len() > self.size {
+            let first = offset.max(self.size);
+            return Err(RmipsError::MemoryWrite(first as Address));
+        }
+
+        self.store(offset, data);
+        self.dirty.mark(offset, data.len());
+        Ok(())
+    }
+
+    fn is_memory(&self) -> bool {
+        true
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        let mut chunks = self.chunks.keys().copied().collect::<Vec<_>>();
+        chunks.sort_unstable();
+        state.word(chunks.len() as u32);
+        for chunk in chunks {
+            state.word(chunk as u32);
+            state.bytes(&self.chunks[&chunk]);
+        }
+    }
+
+    fn restore(&mut self, state: &mut StateReader) -> Result<()> {
+        self.chunks.clear();
+        for _ in 0..state.word()? {
+            let chunk = state.word()? as usize;
+            if chunk * CHUNK_SIZE >= self.size {
+                return Err(RmipsError::Snapshot(format!(
+                    "RAM chunk {} is out of bounds",
+                    chunk
+                )));
+            }
+            let data = state.bytes_of_len(CHUNK_SIZE)?;
+            self.chunks.insert(chunk, data.into());
+            self.dirty.mark(
+                chunk * CHUNK_SIZE,
+                CHUNK_SIZE.min(self.size - chunk * CHUNK_SIZE),
+            );
+        }
+        Ok(())
+    }
+
+    fn dirty_pages(&self) -> Vec<Address> {
+        self.dirty.pages()
+    }
+
+    fn clear_dirty(&mut self) {
+        self.dirty.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn ram_dirty_pages() -> Result<()> {
+        let mut ram = Ram::new(0x10_0000);
+        assert!(ram.dirty_pages().is_empty());
+
+        ram.write(0x0, &[1], AccessContext::CpuStore)?;
+        ram.write(0x4_1ffe, &[1, 2, 3, 4], AccessContext::CpuStore)?;
+        ram.write(0xf_f000, &[1], AccessContext::Dma)?;
+        assert_eq!(ram.dirty_pages(), vec![0x0, 0x4_1000, 0x4_2000, 0xf_f000]);
+
+        // Reads never dirty a page
+        let mut data = [0; 4];
+        ram.read(0x8_0000, &mut data, AccessContext::CpuLoad)?;
+        assert_eq!(ram.dirty_pages().len(), 4);
+
+        ram.clear_dirty();
+        assert!(ram.dirty_pages().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn ram_load() -> Result<()> {
+        let mut ram = Ram::new(0x100);
+        assert!(ram.load(0xfc, &[1, 2, 3, 4]));
+        assert!(!ram.load(0xfd, &[1, 2, 3, 4]));
+        assert!(!ram.load(usize::MAX, &[1]));
+
+        let mut data = [0; 4];
+        ram.peek(0xfc, &mut data)?;
+        assert_eq!(data, [1, 2, 3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn ram_failed_write_is_not_dirty() {
+        let mut ram = Ram::new(0x1000);
+        assert!(ram.write(0x1000, &[1], AccessContext::CpuStore).is_err());
+        assert!(ram.dirty_pages().is_empty());
+    }
+
+    #[test]
+    fn sparse_ram_allocates_on_write() -> Result<()> {
+        let mut ram = SparseRam::new(0x2000_0000);
+        assert!(ram.chunks.is_empty());
+
+        // Reads and zero writes to untouched memory do not allocate
+        let mut data = [0xff; 4];
+        ram.read(0x1000_0000, &mut data, AccessContext::CpuLoad)?;
+        assert_eq!(data, [0; 4]);
+        ram.write(0x40, &[0; 8], AccessContext::CpuStore)?;
+        assert!(ram.chunks.is_empty());
+
+        // A write that straddles two chunks allocates both
+        ram.write(0x1_fffe, &[1, 2, 3, 4], AccessContext::CpuStore)?;
+        assert_eq!(ram.chunks.len(), 2);
+        ram.peek(0x1_fffc, &mut data)?;
+        assert_eq!(data, [0, 0, 1, 2]);
+        ram.peek(0x2_0000, &mut data)?;
+        assert_eq!(data, [3, 4, 0, 0]);
+        assert_eq!(ram.dirty_pages(), vec![0x0, 0x1_f000, 0x2_0000]);
+
+        assert!(ram.load(0x1fff_fffc, &[5, 6, 7, 8]));
+        assert!(!ram.load(0x1fff_fffd, &[5, 6, 7, 8]));
+        assert!(matches!(
+            ram.write(0x1fff_fffe, &[0; 4], AccessContext::CpuStore),
+            Err(RmipsError::MemoryWrite(0x2000_0000))
+        ));
+        assert!(ram.peek(0x2000_0000, &mut data).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sparse_ram_save_restore() -> Result<()> {
+        let mut ram = SparseRam::new(0x2000_0000);
+        ram.write(0x1_fffe, &[1, 2, 3, 4], AccessContext::CpuStore)?;
+        let mut state = StateWriter::new();
+        ram.save(&mut state);
+        let state = state.into_bytes();
+
+        let mut restored = SparseRam::new(0x2000_0000);
+        restored.write(0x1000_0000, &[9], AccessContext::CpuStore)?;
+        let mut reader = StateReader::new(&state);
+        restored.restore(&mut reader)?;
+        reader.finish()?;
+        assert_eq!(restored.chunks.len(), 2);
+        let mut data = [0xff; 4];
+        restored.peek(0x1_fffe, &mut data)?;
+        assert_eq!(data, [1, 2, 3, 4]);
+        restored.peek(0x1000_0000, &mut data)?;
+        assert_eq!(data, [0; 4]);
+
+        let mut small = SparseRam::new(0x1_0000);
+        assert!(small.restore(&mut StateReader::new(&state)).is_err());
         Ok(())
     }
 }
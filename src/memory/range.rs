@@ -15,9 +15,8 @@ impl Range {
     }
 
     /// Returns true if `address` is within the range.
-    #[allow(dead_code)]
     pub fn contains(&self, address: Address) -> bool {
-        self.base <= address && address < self.base + self.size as Address
+        self.base <= address && address <= self.last()
     }
 
     /// Returns true if there is an overlap with this range.
@@ -34,7 +33,6 @@ impl Range {
         self.base
     }
 
-    #[allow(dead_code)]
     pub fn size(&self) -> usize {
         self.size
     }
@@ -56,7 +54,7 @@ impl Ord for Range {
 
 impl PartialOrd for Range {
     fn partial_cmp(&self, other: &Range) -> Option<Ordering> {
-        self.base.partial_cmp(&other.base)
+        Some(self.cmp(other))
     }
 }
 
@@ -1,36 +1,83 @@
 use std::fs::File;
-use std::io::Read;
+
+use memmap2::{MmapMut, MmapOptions};
 
 use crate::devices::Device;
+use crate::memory::AccessContext;
 use crate::util::error::{Result, RmipsError};
 use crate::Address;
 
-#[derive(Clone, Debug)]
+// TODO: The current setup.s code tries to load one extra word at end of ROM
+// which causes a memory error. Need to either fix setup.s or align here.
+const ROM_PADDING: usize = 4;
+
+/// ROM image served from a private memory mapping of the ROM file.
+///
+/// Pages are only read from disk when the guest touches them, so large flash
+/// dumps can be mapped without loading them up front. Writes are copy-on-write
+/// and never reach the file.
+#[derive(Debug)]
 pub struct Rom {
     rom_path: String,
-    data: Vec<u8>,
+    data: Option<MmapMut>,
 }
 
 impl Rom {
-    pub fn new(rom_path: String) -> Result<Rom> {
-        let mut f =
-            File::open(&rom_path).map_err(|_| RmipsError::RomLoading(rom_path.to_owned()))?;
-        let mut data = Vec::new();
-        f.read_to_end(&mut data)
-            .map_err(|_| RmipsError::RomLoading(rom_path.to_owned()))?;
-
-        // TODO: The current setup.s code tries to load one extra word at end of ROM
-        // which causes a memory error. Need to either fix setup.s or align here.
-        data.push(0);
-        data.push(0);
-        data.push(0);
-        data.push(0);
+    /// Maps `length` bytes of the file at `rom_path` starting at `offset`.
+    ///
+    /// When `length` is `None` the rest of the file after `offset` is mapped.
+    pub fn new(rom_path: String, offset: u64, length: Option<u64>) -> Result<Rom> {
+        let err = || RmipsError::RomLoading(rom_path.to_owned());
+
+        let f = File::open(&rom_path).map_err(|_| err())?;
+        let file_len = f.metadata().map_err(|_| err())?.len();
+        let available = file_len.checked_sub(offset).ok_or_else(err)?;
+        let length = length.unwrap_or(available);
+        if length > available {
+            return Err(err());
+        }
+
+        let data = match length {
+            0 => None,
+            length => {
+                // SAFETY: The mapping is private, so guest writes never reach the file. The ROM
+                // file is expected to stay unmodified while the emulator runs.
+                let map = unsafe {
+                    MmapOptions::new()
+                        .offset(offset)
+                        .len(length as usize)
+                        .map_copy(&f)
+                };
+                Some(map.map_err(|_| err())?)
+            }
+        };
 
         Ok(Self { rom_path, data })
     }
 
+    /// Serves `image` as a ROM, e.g. a program assembled from source at startup.
+    pub fn from_image(label: String, image: &[u8]) -> Result<Rom> {
+        let data = match image.len() {
+            0 => None,
+            len => {
+                let mut map = MmapMut::map_anon(len)?;
+                map.copy_from_slice(image);
+                Some(map)
+            }
+        };
+
+        Ok(Self {
+            rom_path: label,
+            data,
+        })
+    }
+
     pub fn size(&self) -> usize {
-        self.data.len()
+        self.image().len() + ROM_PADDING
+    }
+
+    fn image(&self) -> &[u8] {
+        self.data.as_deref().unwrap_or(&[])
     }
 }
 
@@ -39,20 +86,28 @@ impl Device for Rom {
         self.rom_path.to_owned()
     }
 
-    fn read(&mut self, address: Address, data: &mut [u8]) -> Result<()> {
+    fn read(&mut self, address: Address, data: &mut [u8], _ctx: AccessContext) -> Result<()> {
+        self.peek(address, data)
+    }
+
+    fn peek(&self, address: Address, data: &mut [u8]) -> Result<()> {
+        let image = self.image();
         for (i, v) in data.iter_mut().enumerate() {
-            *v = *self
-                .data
-                .get((address as usize) + i)
-                .ok_or(RmipsError::MemoryRead(address + (i as u32)))?;
+            let offset = (address as usize) + i;
+            *v = match image.get(offset) {
+                Some(v) => *v,
+                None if offset < self.size() => 0,
+                None => return Err(RmipsError::MemoryRead(address + (i as u32))),
+            };
         }
 
         Ok(())
     }
 
-    fn write(&mut self, address: Address, data: &[u8]) -> Result<()> {
+    fn write(&mut self, address: Address, data: &[u8], _ctx: AccessContext) -> Result<()> {
+        let image = self.data.as_deref_mut().unwrap_or(&mut []);
         for (i, v) in data.iter().enumerate() {
-            if let Some(elem) = self.data.get_mut((address as usize) + i) {
+            if let Some(elem) = image.get_mut((address as usize) + i) {
                 *elem = *v;
             } else {
                 return Err(RmipsError::MemoryWrite(address + (i as u32)));
@@ -61,4 +116,63 @@ impl Device for Rom {
 
         Ok(())
     }
+
+    fn executable(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const ROM_PATH: &str = "./tests/build/memory.rom";
+
+    #[test]
+    fn rom_maps_whole_file() -> Result<()> {
+        let expected = std::fs::read(ROM_PATH)?;
+        let mut rom = Rom::new(ROM_PATH.to_owned(), 0, None)?;
+        assert_eq!(rom.size(), expected.len() + ROM_PADDING);
+
+        let mut data = vec![0; rom.size()];
+        rom.read(0, &mut data, AccessContext::CpuLoad)?;
+        assert_eq!(&data[..expected.len()], &expected[..]);
+        assert_eq!(&data[expected.len()..], &[0; ROM_PADDING]);
+        assert!(rom.peek(rom.size() as Address, &mut [0]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rom_maps_partition() -> Result<()> {
+        let expected = std::fs::read(ROM_PATH)?;
+        let rom = Rom::new(ROM_PATH.to_owned(), 8, Some(16))?;
+        assert_eq!(rom.size(), 16 + ROM_PADDING);
+
+        let mut data = [0; 16];
+        rom.peek(0, &mut data)?;
+        assert_eq!(&data[..], &expected[8..24]);
+        Ok(())
+    }
+
+    #[test]
+    fn rom_writes_do_not_reach_file() -> Result<()> {
+        let expected = std::fs::read(ROM_PATH)?;
+        let mut rom = Rom::new(ROM_PATH.to_owned(), 0, None)?;
+        rom.write(0, &[0xff; 4], AccessContext::Debugger)?;
+
+        let mut data = [0; 4];
+        rom.peek(0, &mut data)?;
+        assert_eq!(data, [0xff; 4]);
+        assert_eq!(std::fs::read(ROM_PATH)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn rom_rejects_out_of_range_partition() {
+        let len = std::fs::metadata(ROM_PATH).unwrap().len();
+        assert!(Rom::new(ROM_PATH.to_owned(), len + 1, None).is_err());
+        assert!(Rom::new(ROM_PATH.to_owned(), 4, Some(len)).is_err());
+        assert!(Rom::new("./does/not/exist.rom".to_owned(), 0, None).is_err());
+    }
 }
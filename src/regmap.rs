@@ -0,0 +1,368 @@
+//! Register maps that name the memory-mapped registers of devices and their bit fields.
+//!
+//! With a register map, device accesses on the timeline and in the crash dump are decoded as
+//! `UART.LSR.THRE=1` instead of an offset and a hex value, and watch expressions can read
+//! registers by name as `mmio[UART.LSR.THRE]`. Maps use a small subset of TOML with a table per
+//! peripheral and per register, where the keys of a register other than `offset` and `size`
+//! are its fields, given by a bit number or a `"msb:lsb"` range:
+//!
+//! ```toml
+//! [UART]
+//! base = 0x1f000900    # physical address
+//!
+//! [UART.LSR]
+//! offset = 0x14
+//! size = 1             # bytes, 4 by default
+//! DR = 0
+//! THRE = 5
+//!
+//! [UART.DLL]
+//! offset = 0x00
+//! DIVISOR = "15:0"
+//! ```
+
+use std::fmt::Write;
+
+use crate::util::parse::{self, parse_integer};
+use crate::Address;
+
+/// A bit field of a register.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub lsb: u32,
+    pub width: u32,
+}
+
+impl Field {
+    /// Extracts the field from the value of its register.
+    pub fn extract(&self, value: u32) -> u32 {
+        let mask = u32::MAX >> (32 - self.width);
+        (value >> self.lsb) & mask
+    }
+}
+
+/// A memory-mapped register of a peripheral.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Register {
+    pub name: String,
+    pub offset: Address,
+    /// Width of the register in bytes.
+    pub size: usize,
+    pub fields: Vec<Field>,
+}
+
+/// A device and the registers it maps at `base`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Peripheral {
+    pub name: String,
+    /// Physical address of the first register.
+    pub base: Address,
+    pub registers: Vec<Register>,
+}
+
+/// A register resolved by name, at the physical address it is mapped at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegisterRef<'a> {
+    pub address: Address,
+    pub register: &'a Register,
+    pub field: Option<&'a Field>,
+}
+
+/// Parses a field given as a bit number or a quoted `"msb:lsb"` range.
+fn parse_field(name: &str, value: &str) -> Result<Field, String> {
+    let (msb, lsb) = match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(range) => {
+            let (msb, lsb) = range
+                .split_once(':')
+                .ok_or_else(|| format!("expected a \"msb:lsb\" bit range: {}", value))?;
+            (parse_integer(msb.trim())?, parse_integer(lsb.trim())?)
+        }
+        None => {
+            let bit = parse_integer(value)?;
+            (bit, bit)
+        }
+    };
+
+    if msb > 31 || lsb > msb {
+        return Err(format!("invalid bit range for field {}: {}", name, value));
+    }
+    Ok(Field {
+        name: name.to_owned(),
+        lsb,
+        width: msb - lsb + 1,
+    })
+}
+
+/// Returns true if `name` can be used in the dotted names of registers.
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The peripherals of a machine, in the order of the map file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegisterMap {
+    peripherals: Vec<Peripheral>,
+}
+
+impl RegisterMap {
+    /// Parses a register map, reporting the line of the first error.
+    pub fn parse(map: &str) -> Result<Self, String> {
+        let mut peripherals: Vec<Peripheral> = Vec::new();
+        // Index of the register the following keys belong to, if they are not the peripheral's
+        let mut register: Option<usize> = None;
+        // Registers whose `offset` was given
+        let mut placed: Vec<(usize, usize)> = Vec::new();
+
+        for (number, line) in parse::lines(map) {
+            let error = |msg: String| format!("line {}: {}", number, msg);
+
+            if let Some(header) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                let (name, reg) = match header.split_once('.') {
+                    Some((name, reg)) => (name.trim(), Some(reg.trim())),
+                    None => (header.trim(), None),
+                };
+                if !is_identifier(name) || !reg.is_none_or(is_identifier) {
+                    return Err(error(format!("invalid table name: [{}]", header)));
+                }
+
+                let index = peripherals.iter().position(|p| p.name == name);
+                register = match (index, reg) {
+                    (Some(_), None) => {
+                        return Err(error(format!("peripheral {} is given twice", name)))
+                    }
+                    (None, None) => {
+                        peripherals.push(Peripheral {
+                            name: name.to_owned(),
+                            base: Address::MAX,
+                            registers: Vec::new(),
+                        });
+                        None
+                    }
+                    (None, Some(_)) => {
+                        return Err(error(format!("register of unknown peripheral {}", name)))
+                    }
+                    (Some(index), Some(reg)) => {
+                        if index != peripherals.len() - 1 {
+                            return Err(error(format!(
+                                "registers of {} must follow its table",
+                                name
+                            )));
+                        }
+                        let registers = &mut peripherals[index].registers;
+                        if registers.iter().any(|r| r.name == reg) {
+                            return Err(error(format!("register {}.{} is given twice", name, reg)));
+                        }
+                        registers.push(Register {
+                            name: reg.to_owned(),
+                            offset: 0,
+                            size: 4,
+                            fields: Vec::new(),
+                        });
+                        Some(registers.len() - 1)
+                    }
+                };
+                continue;
+            }
+
+            let (key, value) = parse::key_value(line)
+                .ok_or_else(|| error(format!("expected a [table] or `key = value`: {}", line)))?;
+            let peripheral = peripherals
+                .last_mut()
+                .ok_or_else(|| error(format!("`{}` is outside of a table", key)))?;
+
+            match (register, key) {
+                (None, "base") => peripheral.base = parse_integer(value).map_err(error)?,
+                (None, _) => return Err(error(format!("unknown peripheral key `{}`", key))),
+                (Some(index), "offset") => {
+                    peripheral.registers[index].offset = parse_integer(value).map_err(error)?;
+                    placed.push((peripherals.len() - 1, index));
+                }
+                (Some(index), "size") => {
+                    peripheral.registers[index].size = match parse_integer(value).map_err(error)? {
+                        size @ (1 | 2 | 4) => size as usize,
+                        _ => return Err(error(format!("size must be 1, 2 or 4: {}", value))),
+                    }
+                }
+                (Some(index), name) if is_identifier(name) => {
+                    let field = parse_field(name, value).map_err(error)?;
+                    peripheral.registers[index].fields.push(field);
+                }
+                (Some(_), _) => return Err(error(format!("invalid field name `{}`", key))),
+            }
+        }
+
+        for (p, peripheral) in peripherals.iter().enumerate() {
+            if peripheral.base == Address::MAX {
+                return Err(format!("peripheral {} has no `base`", peripheral.name));
+            }
+            for (r, register) in peripheral.registers.iter().enumerate() {
+                if !placed.contains(&(p, r)) {
+                    return Err(format!(
+                        "register {}.{} has no `offset`",
+                        peripheral.name, register.name
+                    ));
+                }
+                if let Some(field) = register
+                    .fields
+                    .iter()
+                    .find(|field| field.lsb + field.width > 8 * register.size as u32)
+                {
+                    return Err(format!(
+                        "field {}.{}.{} does not fit into the register",
+                        peripheral.name, register.name, field.name
+                    ));
+                }
+            }
+        }
+        Ok(Self { peripherals })
+    }
+
+    pub fn peripherals(&self) -> &[Peripheral] {
+        &self.peripherals
+    }
+
+    /// Resolves `PERIPHERAL.REGISTER` or `PERIPHERAL.REGISTER.FIELD`.
+    pub fn resolve(&self, name: &str) -> Option<RegisterRef<'_>> {
+        let mut parts = name.trim().split('.');
+        let (peripheral, register) = (parts.next()?, parts.next()?);
+        let field = parts.next();
+        if parts.next().is_some() {
+            return None;
+        }
+
+        let peripheral = self.peripherals.iter().find(|p| p.name == peripheral)?;
+        let register = peripheral.registers.iter().find(|r| r.name == register)?;
+        let field = match field {
+            Some(field) => Some(register.fields.iter().find(|f| f.name == field)?),
+            None => None,
+        };
+        Some(RegisterRef {
+            address: peripheral.base.wrapping_add(register.offset),
+            register,
+            field,
+        })
+    }
+
+    /// Returns the register that starts at the physical `address`.
+    pub fn lookup(&self, address: Address) -> Option<(&Peripheral, &Register)> {
+        self.peripherals.iter().find_map(|peripheral| {
+            let register = peripheral
+                .registers
+                .iter()
+                .find(|r| peripheral.base.wrapping_add(r.offset) == address)?;
+            Some((peripheral, register))
+        })
+    }
+
+    /// Decodes a value of the register at the physical `address`, as `UART.LSR.THRE=1
+    /// UART.LSR.DR=0`, or as `UART.THR=0x41` for a register without fields.
+    pub fn decode(&self, address: Address, value: u32) -> Option<String> {
+        let (peripheral, register) = self.lookup(address)?;
+        if register.fields.is_empty() {
+            return Some(format!(
+                "{}.{}=0x{:0w$x}",
+                peripheral.name,
+                register.name,
+                value,
+                w = register.size * 2
+            ));
+        }
+
+        let mut decoded = String::new();
+        for field in &register.fields {
+            if !decoded.is_empty() {
+                decoded.push(' ');
+            }
+            let _ = write!(
+                decoded,
+                "{}.{}.{}={}",
+                peripheral.name,
+                register.name,
+                field.name,
+                field.extract(value)
+            );
+        }
+        Some(decoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const UART: &str = r#"
+        # 16550 compatible UART
+        [UART]
+        base = 0x1f00_0900
+
+        [UART.THR]
+        offset = 0
+        size = 1
+
+        [UART.LSR]
+        offset = 0x14
+        size = 1
+        DR = 0
+        THRE = 5
+        ERRORS = "4:1"
+    "#;
+
+    #[test]
+    fn register_map_decode() {
+        let map = RegisterMap::parse(UART).unwrap();
+        assert_eq!(
+            map.decode(0x1f00_0914, 0x21).as_deref(),
+            Some("UART.LSR.DR=1 UART.LSR.THRE=1 UART.LSR.ERRORS=0")
+        );
+        assert_eq!(
+            map.decode(0x1f00_0914, 0x0c).as_deref(),
+            Some("UART.LSR.DR=0 UART.LSR.THRE=0 UART.LSR.ERRORS=6")
+        );
+        assert_eq!(
+            map.decode(0x1f00_0900, 0x41).as_deref(),
+            Some("UART.THR=0x41")
+        );
+        assert_eq!(map.decode(0x1f00_0904, 0), None);
+
+        let thre = map.resolve("UART.LSR.THRE").unwrap();
+        assert_eq!(thre.address, 0x1f00_0914);
+        assert_eq!(thre.register.size, 1);
+        assert_eq!(thre.field.map(|field| field.lsb), Some(5));
+        assert_eq!(map.resolve("UART.LSR").unwrap().field, None);
+        assert_eq!(map.resolve("UART.LSR.TEMT"), None);
+        assert_eq!(map.resolve("UART"), None);
+    }
+
+    #[test]
+    fn register_map_errors() {
+        let error = |map: &str| RegisterMap::parse(map).unwrap_err();
+
+        assert_eq!(error("base = 0"), "line 1: `base` is outside of a table");
+        assert_eq!(
+            error("[UART.LSR]\noffset = 0"),
+            "line 1: register of unknown peripheral UART"
+        );
+        assert_eq!(
+            error("[UART]\nbase = 0\n[UART.LSR]\nsize = 1"),
+            "register UART.LSR has no `offset`"
+        );
+        assert_eq!(
+            error("[UART]\nbase = 0\n[UART.LSR]\noffset = 0\nsize = 3"),
+            "line 5: size must be 1, 2 or 4: 3"
+        );
+        assert_eq!(
+            error("[UART]\nbase = 0\n[UART.LSR]\noffset = 0\nsize = 1\nBAUD = \"15:0\""),
+            "field UART.LSR.BAUD does not fit into the register"
+        );
+        assert_eq!(
+            error("[UART]\noffset = 0"),
+            "line 2: unknown peripheral key `offset`"
+        );
+        assert_eq!(error("[UART]"), "peripheral UART has no `base`");
+    }
+}
@@ -0,0 +1,155 @@
+//! Debug sessions that keep the breakpoints, watchpoints and patches of a program between runs.
+//!
+//! With `--debug-session` the emulator restores a session when it starts and saves it when the
+//! run ends, so repeated debugging runs of the same firmware start with the breakpoints that GDB
+//! had inserted the last time it resumed the program. Sessions use a small subset of TOML with
+//! one single-line array per key:
+//!
+//! ```toml
+//! breakpoints = [0xbfc00120, 0xbfc00400]   # virtual addresses
+//! watchpoints = [0x80001000]
+//! patches = ["0xbfc00200=0x00000000"]     # like --patch
+//! ```
+
+use std::fmt;
+
+use crate::util::opts::{parse_address, CodePatch};
+use crate::util::parse::{self, parse_array, parse_text};
+use crate::Address;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DebugSession {
+    pub breakpoints: Vec<Address>,
+    pub watchpoints: Vec<Address>,
+    pub patches: Vec<CodePatch>,
+}
+
+/// Returns `addresses` without duplicates, in the order they were first given.
+pub(crate) fn unique(addresses: &[Address]) -> Vec<Address> {
+    let mut unique = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        if !unique.contains(address) {
+            unique.push(*address);
+        }
+    }
+    unique
+}
+
+impl DebugSession {
+    /// Parses a debug session, reporting the line of the first error.
+    pub fn parse(session: &str) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        for (number, line) in parse::lines(session) {
+            let error = |msg: String| format!("line {}: {}", number, msg);
+            let (key, value) = parse::key_value(line)
+                .ok_or_else(|| error(format!("expected `key = [values]`: {}", line)))?;
+            let items = parse_array(value).map_err(error)?;
+
+            match key {
+                "breakpoints" => {
+                    parsed.breakpoints = items
+                        .map(parse_address)
+                        .collect::<Result<_, _>>()
+                        .map_err(error)?
+                }
+                "watchpoints" => {
+                    parsed.watchpoints = items
+                        .map(parse_address)
+                        .collect::<Result<_, _>>()
+                        .map_err(error)?
+                }
+                "patches" => {
+                    parsed.patches = items
+                        .map(|item| parse_text(item)?.parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(error)?
+                }
+                key => return Err(error(format!("unknown key `{}`", key))),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Adds `patch`, replacing an earlier patch of the same word.
+    pub fn add_patch(&mut self, patch: CodePatch) {
+        self.patches.retain(|other| other.address != patch.address);
+        self.patches.push(patch);
+    }
+}
+
+impl fmt::Display for DebugSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let addresses = |addresses: &[Address]| {
+            addresses
+                .iter()
+                .map(|address| format!("0x{:08x}", address))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let patches = self
+            .patches
+            .iter()
+            .map(|patch| format!("\"0x{:08x}=0x{:08x}\"", patch.address, patch.value))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        writeln!(f, "# rmips debug session, saved when the run ends")?;
+        writeln!(f, "breakpoints = [{}]", addresses(&self.breakpoints))?;
+        writeln!(f, "watchpoints = [{}]", addresses(&self.watchpoints))?;
+        writeln!(f, "patches = [{}]", patches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn debug_session_round_trip() {
+        let session = DebugSession::parse(
+            r#"
+            # Breakpoints in the boot code
+            breakpoints = [0xbfc00120, 0xbfc00400]
+            watchpoints = []
+            patches = ["0xbfc00200=0", "0xbfc00204=0x1000ffff"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(session.breakpoints, [0xbfc0_0120, 0xbfc0_0400]);
+        assert!(session.watchpoints.is_empty());
+
+        let mut session = DebugSession::parse(&session.to_string()).unwrap();
+        session.add_patch(CodePatch {
+            address: 0xbfc0_0200,
+            value: 0x2402_0001,
+        });
+        assert_eq!(
+            session.to_string(),
+            "# rmips debug session, saved when the run ends\n\
+             breakpoints = [0xbfc00120, 0xbfc00400]\n\
+             watchpoints = []\n\
+             patches = [\"0xbfc00204=0x1000ffff\", \"0xbfc00200=0x24020001\"]\n"
+        );
+        assert_eq!(unique(&[4, 8, 4, 12, 8]), [4, 8, 12]);
+    }
+
+    #[test]
+    fn debug_session_errors() {
+        let error = |session: &str| DebugSession::parse(session).unwrap_err();
+
+        assert_eq!(
+            error("breakpoints = 0x100"),
+            "line 1: expected an array: 0x100"
+        );
+        assert_eq!(
+            error("\nbreakpoints = [0x100, main]"),
+            "line 2: invalid address: main"
+        );
+        assert_eq!(
+            error("patches = [0x100=0]"),
+            "line 1: expected a quoted string, found 0x100=0"
+        );
+        assert_eq!(error("steps = []"), "line 1: unknown key `steps`");
+    }
+}
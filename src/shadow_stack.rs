@@ -0,0 +1,179 @@
+//! Shadow stack of return addresses for detecting corrupted returns in guest code.
+//!
+//! Every taken call (`jal`, `jalr`, `bltzal` and `bgezal`) pushes the address it will return to.
+//! A `jr ra` is then checked against the innermost call, so a smashed stack is reported at the
+//! return itself rather than after the wild jump crashes somewhere else.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::control::instruction::Instruction;
+use crate::control::registers::Register;
+use crate::Address;
+
+/// What to do when a return does not match the shadow stack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowStackMode {
+    /// Print a warning and keep running.
+    Warn,
+    /// Stop emulation with an error.
+    Stop,
+}
+
+impl FromStr for ShadowStackMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(ShadowStackMode::Warn),
+            "stop" => Ok(ShadowStackMode::Stop),
+            _ => Err(format!("invalid shadow stack mode: {}", s)),
+        }
+    }
+}
+
+/// Returns true if `instr` is a call, which links the return address when it is taken.
+pub fn is_call(instr: Instruction) -> bool {
+    matches!(instr.mnemonic(), "jal" | "jalr" | "bltzal" | "bgezal")
+}
+
+/// Returns true if `instr` is a return from a function, `jr ra`.
+pub fn is_return(instr: Instruction) -> bool {
+    instr.mnemonic() == "jr" && instr.rs() == Register::Ra as usize
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Frame {
+    call_site: Address,
+    return_address: Address,
+}
+
+/// A `jr ra` that did not return to the innermost call site.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReturnMismatch {
+    /// Address of the `jr ra` instruction.
+    pub pc: Address,
+    /// Address the return actually jumped to.
+    pub target: Address,
+    pub call_site: Address,
+    pub expected: Address,
+}
+
+impl fmt::Display for ReturnMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Return at PC=0x{:08x} jumps to 0x{:08x}, but the call at 0x{:08x} returns to 0x{:08x}",
+            self.pc, self.target, self.call_site, self.expected
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ShadowStack {
+    frames: Vec<Frame>,
+}
+
+impl ShadowStack {
+    /// Updates the stack for `instr` executed at `pc`, where `target` is the destination
+    /// of the control transfer if it was taken.
+    ///
+    /// Returns the mismatch if `instr` is a `jr ra` that does not return to the innermost call.
+    pub fn check(
+        &mut self,
+        pc: Address,
+        instr: Instruction,
+        target: Option<Address>,
+    ) -> Option<ReturnMismatch> {
+        let target = target?;
+
+        if is_call(instr) {
+            self.frames.push(Frame {
+                call_site: pc,
+                return_address: pc.wrapping_add(8),
+            });
+            None
+        } else if is_return(instr) {
+            self.pop(pc, target)
+        } else {
+            None
+        }
+    }
+
+    fn pop(&mut self, pc: Address, target: Address) -> Option<ReturnMismatch> {
+        // Returns that skip frames, like longjmp, unwind to the matching outer call
+        let frame = self.frames.pop()?;
+        if frame.return_address == target {
+            return None;
+        }
+        if let Some(pos) = self
+            .frames
+            .iter()
+            .rposition(|outer| outer.return_address == target)
+        {
+            self.frames.truncate(pos);
+            return None;
+        }
+
+        Some(ReturnMismatch {
+            pc,
+            target,
+            call_site: frame.call_site,
+            expected: frame.return_address,
+        })
+    }
+
+    /// Returns the number of calls that have not returned yet.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const JAL: Instruction = Instruction(0x0ff0_0010);
+    const JR_RA: Instruction = Instruction(0x03e0_0008);
+    const JR_K0: Instruction = Instruction(0x0340_0008);
+
+    #[test]
+    fn shadow_stack_returns() {
+        let mut stack = ShadowStack::default();
+
+        assert_eq!(stack.check(0x100, JAL, Some(0x200)), None);
+        assert_eq!(stack.check(0x204, JAL, Some(0x300)), None);
+        assert_eq!(stack.depth(), 2);
+        assert_eq!(stack.check(0x310, JR_RA, Some(0x20c)), None);
+        assert_eq!(stack.check(0x400, JR_K0, Some(0x123)), None);
+        assert_eq!(stack.check(0x210, JR_RA, Some(0x108)), None);
+        assert_eq!(stack.depth(), 0);
+
+        // Returns without a recorded call are not checked
+        assert_eq!(stack.check(0x108, JR_RA, Some(0x500)), None);
+    }
+
+    #[test]
+    fn shadow_stack_mismatch() {
+        let mut stack = ShadowStack::default();
+        stack.check(0x100, JAL, Some(0x200));
+        stack.check(0x204, JAL, Some(0x300));
+        stack.check(0x304, JAL, Some(0x400));
+
+        // Unwinding past several frames to an outer caller is allowed
+        assert_eq!(stack.check(0x410, JR_RA, Some(0x108)), None);
+        assert_eq!(stack.depth(), 0);
+
+        stack.check(0x100, JAL, Some(0x200));
+        assert_eq!(
+            stack.check(0x210, JR_RA, Some(0x4141_4141)),
+            Some(ReturnMismatch {
+                pc: 0x210,
+                target: 0x4141_4141,
+                call_site: 0x100,
+                expected: 0x108,
+            })
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! Snapshots of the machine state and concurrent exploration from a common snapshot.
+//!
+//! A snapshot holds the architectural `Cpu` state and the contents of every device that behaves
+//! like plain memory. Forks are independent emulators built from the same options on their own
+//! threads, so they share nothing but the read-only snapshot they start from. Devices whose
+//! state lives on the host, such as files or sockets, and output files would be shared between
+//! the forks and are rejected.
+//!
+//! Snapshot files written by `Emulator::save_snapshot` hold the complete machine state instead,
+//! including CP0 with the TLB, the attached coprocessors and the registers of every device, so
+//! that long-running boots can be checkpointed and resumed by another process.
+
+use std::thread;
+
+use crate::control::cpu::CpuState;
+use crate::emulator::{Emulator, RunSummary};
+use crate::util::error::{Result, RmipsError};
+use crate::util::opts::Opts;
+use crate::Address;
+
+/// Identifies a snapshot file.
+pub(crate) const SNAPSHOT_MAGIC: &[u8] = b"RMIPSSNP";
+/// Version of the snapshot file layout, which changes whenever the state of a part changes.
+pub(crate) const SNAPSHOT_VERSION: u32 = 1;
+
+/// The state of an emulator at one point of its execution.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub(crate) cpu: CpuState,
+    /// Physical base address and contents of each memory device.
+    pub(crate) memories: Vec<(Address, Vec<u8>)>,
+    pub(crate) instruction_count: usize,
+}
+
+impl Snapshot {
+    /// Returns the architectural state of the `Cpu`.
+    pub fn cpu(&self) -> &CpuState {
+        &self.cpu
+    }
+
+    /// Returns the number of instructions executed before the snapshot was taken.
+    pub fn instruction_count(&self) -> usize {
+        self.instruction_count
+    }
+
+    /// Reads memory at a physical address, returning `None` if it is not inside a single
+    /// memory device.
+    pub fn read(&self, address: Address, data: &mut [u8]) -> Option<()> {
+        let (base, memory) = self
+            .memories
+            .iter()
+            .find(|(base, memory)| (*base..*base + memory.len() as Address).contains(&address))?;
+        let start = (address - base) as usize;
+        data.copy_from_slice(memory.get(start..start + data.len())?);
+        Some(())
+    }
+}
+
+/// Returns an error naming the first device or output file configured in `opts` that the
+/// forks would share with the host.
+fn check_forkable(opts: &Opts) -> Result<()> {
+    let shared = [
+        (opts.ramdump.is_some(), "RAM dump"),
+        (opts.signaldump.is_some(), "signal dump"),
+        (opts.savesnapshot.is_some(), "saved snapshot"),
+        (opts.blockprofile.is_some(), "block profile"),
+        (opts.timeline.is_some(), "timeline"),
+        (opts.trace.is_some(), "trace"),
+        (opts.nvram.is_some(), "non-volatile storage"),
+        (opts.sharedmemory.is_some(), "shared memory"),
+        (opts.netlisten.is_some(), "network"),
+        (opts.seriallink.is_some(), "serial link"),
+        (opts.keyboard, "keyboard"),
+        (opts.debug, "GDB stub"),
+    ];
+
+    match shared.iter().find(|(enabled, _)| *enabled) {
+        Some((_, device)) => Err(RmipsError::SharedHostState(device)),
+        None => Ok(()),
+    }
+}
+
+/// Forks one emulator per input from `snapshot` and runs the forks concurrently.
+///
+/// Every fork is created from `opts` on its own thread, restored to `snapshot` and handed to
+/// `prepare` together with its input before it runs until it halts. The run summaries and
+/// final states are returned in the order of the inputs.
+pub fn explore<I, F>(
+    opts: &Opts,
+    snapshot: &Snapshot,
+    inputs: Vec<I>,
+    prepare: F,
+) -> Result<Vec<Result<(RunSummary, Snapshot)>>>
+where
+    I: Send,
+    F: Fn(&mut Emulator, I) + Sync,
+{
+    check_forkable(opts)?;
+
+    let prepare = &prepare;
+    Ok(thread::scope(|scope| {
+        let forks: Vec<_> = inputs
+            .into_iter()
+            .map(|input| {
+                scope.spawn(move || {
+                    let mut emulator = Emulator::new(opts.clone())?;
+                    emulator.restore(snapshot)?;
+                    prepare(&mut emulator, input);
+                    let summary = emulator.run()?;
+                    Ok((summary, emulator.snapshot()))
+                })
+            })
+            .collect();
+
+        forks
+            .into_iter()
+            .map(|fork| {
+                fork.join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    }))
+}
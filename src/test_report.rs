@@ -0,0 +1,235 @@
+//! Results of the unit tests that the guest reported to the test device.
+//!
+//! The report is written as TAP, or as JUnit XML when the file name ends in `.xml`, so that CI
+//! systems can show the results of on-target test runs like those of host tests.
+
+use std::fmt;
+
+/// A test case and the assertions reported while it ran.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TestCase {
+    pub name: String,
+    pub assertions: usize,
+    /// Messages of the failed assertions.
+    pub failures: Vec<String>,
+    /// The reason the test case was skipped, if it was.
+    pub skipped: Option<String>,
+}
+
+impl TestCase {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TestReport {
+    cases: Vec<TestCase>,
+}
+
+/// Escapes a string for use inside an XML attribute.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("&#{};", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl TestReport {
+    /// Starts the test case `name`, which the following assertions belong to.
+    pub fn begin(&mut self, name: String) {
+        self.cases.push(TestCase {
+            name,
+            ..Default::default()
+        });
+    }
+
+    /// Returns the running test case, starting one if the guest asserted before naming it.
+    fn current(&mut self) -> &mut TestCase {
+        if self.cases.is_empty() {
+            self.begin("unnamed".to_owned());
+        }
+        self.cases.last_mut().expect("a test case was started")
+    }
+
+    /// Records an assertion of the running test case, with `message` describing a failure.
+    pub fn assert(&mut self, passed: bool, message: Option<String>) {
+        let case = self.current();
+        case.assertions += 1;
+        if !passed {
+            let message = message
+                .unwrap_or_else(|| format!("assertion {} failed", case.assertions))
+                .replace('\n', " ");
+            case.failures.push(message);
+        }
+    }
+
+    /// Skips the running test case for `reason`.
+    pub fn skip(&mut self, reason: String) {
+        self.current().skipped = Some(reason.replace('\n', " "));
+    }
+
+    pub fn cases(&self) -> &[TestCase] {
+        &self.cases
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cases.is_empty()
+    }
+
+    /// Returns the number of test cases with failed assertions.
+    pub fn failed(&self) -> usize {
+        self.cases.iter().filter(|case| !case.passed()).count()
+    }
+
+    /// Returns the number of skipped test cases without failed assertions.
+    pub fn skipped(&self) -> usize {
+        self.cases
+            .iter()
+            .filter(|case| case.passed() && case.skipped.is_some())
+            .count()
+    }
+
+    /// Returns the report in the Test Anything Protocol.
+    pub fn to_tap(&self) -> String {
+        let mut tap = format!("TAP version 13\n1..{}\n", self.cases.len());
+        for (number, case) in self.cases.iter().enumerate() {
+            let status = match case.passed() {
+                true => "ok",
+                false => "not ok",
+            };
+            tap.push_str(&format!("{} {} - {}", status, number + 1, case.name));
+            match &case.skipped {
+                Some(reason) if case.passed() => tap.push_str(&format!(" # SKIP {}\n", reason)),
+                _ => tap.push('\n'),
+            }
+            for failure in &case.failures {
+                tap.push_str(&format!("# {}\n", failure));
+            }
+        }
+        tap
+    }
+
+    /// Returns the report as a JUnit XML test suite.
+    pub fn to_junit(&self) -> String {
+        let mut xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuite name=\"rmips\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">\n",
+            self.cases.len(),
+            self.failed(),
+            self.skipped()
+        );
+        for case in &self.cases {
+            let name = escape(&case.name);
+            match &case.skipped {
+                _ if !case.passed() => {
+                    xml.push_str(&format!("  <testcase name=\"{}\">\n", name));
+                    for failure in &case.failures {
+                        xml.push_str(&format!("    <failure message=\"{}\"/>\n", escape(failure)));
+                    }
+                    xml.push_str("  </testcase>\n");
+                }
+                Some(reason) => xml.push_str(&format!(
+                    "  <testcase name=\"{}\">\n    <skipped message=\"{}\"/>\n  </testcase>\n",
+                    name,
+                    escape(reason)
+                )),
+                None => xml.push_str(&format!("  <testcase name=\"{}\"/>\n", name)),
+            }
+        }
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let failed = self.failed();
+        let skipped = self.skipped();
+        write!(
+            f,
+            "Tests: {} passed, {} failed, {} skipped",
+            self.cases.len() - failed - skipped,
+            failed,
+            skipped
+        )?;
+        for case in self.cases.iter().filter(|case| !case.passed()) {
+            for failure in &case.failures {
+                write!(f, "\n  {}: {}", case.name, failure)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn report() -> TestReport {
+        let mut report = TestReport::default();
+        report.begin("parse_number".to_owned());
+        report.assert(true, None);
+        report.assert(true, None);
+        report.begin("parse_<hex>".to_owned());
+        report.assert(true, None);
+        report.assert(false, Some("expected \"0x10\"".to_owned()));
+        report.assert(false, None);
+        report.begin("flash_write".to_owned());
+        report.skip("no flash".to_owned());
+        report
+    }
+
+    #[test]
+    fn test_report_formats() {
+        let report = report();
+        assert_eq!((report.failed(), report.skipped()), (1, 1));
+        assert_eq!(
+            report.to_string(),
+            "Tests: 1 passed, 1 failed, 1 skipped\n  \
+             parse_<hex>: expected \"0x10\"\n  \
+             parse_<hex>: assertion 3 failed"
+        );
+        assert_eq!(
+            report.to_tap(),
+            "TAP version 13\n\
+             1..3\n\
+             ok 1 - parse_number\n\
+             not ok 2 - parse_<hex>\n\
+             # expected \"0x10\"\n\
+             # assertion 3 failed\n\
+             ok 3 - flash_write # SKIP no flash\n"
+        );
+        assert_eq!(
+            report.to_junit(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuite name=\"rmips\" tests=\"3\" failures=\"1\" skipped=\"1\">\n  \
+             <testcase name=\"parse_number\"/>\n  \
+             <testcase name=\"parse_&lt;hex&gt;\">\n    \
+             <failure message=\"expected &quot;0x10&quot;\"/>\n    \
+             <failure message=\"assertion 3 failed\"/>\n  \
+             </testcase>\n  \
+             <testcase name=\"flash_write\">\n    \
+             <skipped message=\"no flash\"/>\n  \
+             </testcase>\n\
+             </testsuite>\n"
+        );
+    }
+
+    #[test]
+    fn test_report_unnamed_case() {
+        let mut report = TestReport::default();
+        report.assert(false, None);
+        assert_eq!(report.cases()[0].name, "unnamed");
+        assert_eq!(report.failed(), 1);
+    }
+}
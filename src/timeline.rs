@@ -0,0 +1,117 @@
+//! Timeline of significant emulation events in the Chrome trace event format.
+//!
+//! The file can be opened in `chrome://tracing` or Perfetto. Timestamps use the virtual
+//! clock of the emulator: each executed instruction advances it by one microsecond.
+
+/// An instant event on the timeline.
+#[derive(Clone, Debug, PartialEq)]
+struct TraceEvent {
+    timestamp: u64,
+    category: &'static str,
+    name: String,
+    args: Vec<(&'static str, String)>,
+}
+
+#[derive(Debug, Default)]
+pub struct Timeline {
+    events: Vec<TraceEvent>,
+}
+
+/// Escapes a string for use inside a JSON string literal.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Timeline {
+    /// Records an event of `category` at instruction count `timestamp`.
+    pub fn record(
+        &mut self,
+        timestamp: u64,
+        category: &'static str,
+        name: String,
+        args: Vec<(&'static str, String)>,
+    ) {
+        self.events.push(TraceEvent {
+            timestamp,
+            category,
+            name,
+            args,
+        });
+    }
+
+    /// Returns the number of recorded events.
+    pub fn event_count(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Serializes the events as a Chrome trace JSON object.
+    pub fn to_json(&self) -> String {
+        let events: Vec<String> = self
+            .events
+            .iter()
+            .map(|event| {
+                let args: Vec<String> = event
+                    .args
+                    .iter()
+                    .map(|(key, value)| format!("\"{}\": \"{}\"", key, escape(value)))
+                    .collect();
+                let args = match args.is_empty() {
+                    true => "{}".to_owned(),
+                    false => format!("{{ {} }}", args.join(", ")),
+                };
+                format!(
+                    "    {{ \"name\": \"{}\", \"cat\": \"{}\", \"ph\": \"i\", \"s\": \"g\", \"ts\": {}, \"pid\": 0, \"tid\": 0, \"args\": {} }}",
+                    escape(&event.name),
+                    event.category,
+                    event.timestamp,
+                    args
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\n  \"traceEvents\": [\n{}\n  ],\n  \"displayTimeUnit\": \"ms\"\n}}\n",
+            events.join(",\n")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn timeline_json() {
+        let mut timeline = Timeline::default();
+        timeline.record(
+            12,
+            "exception",
+            "Syscall".to_owned(),
+            vec![("pc", "0xbfc00010".to_owned())],
+        );
+        timeline.record(40, "device", "write \"test-device\"".to_owned(), Vec::new());
+
+        assert_eq!(timeline.event_count(), 2);
+        assert_eq!(
+            timeline.to_json(),
+            r#"{
+  "traceEvents": [
+    { "name": "Syscall", "cat": "exception", "ph": "i", "s": "g", "ts": 12, "pid": 0, "tid": 0, "args": { "pc": "0xbfc00010" } },
+    { "name": "write \"test-device\"", "cat": "device", "ph": "i", "s": "g", "ts": 40, "pid": 0, "tid": 0, "args": {} }
+  ],
+  "displayTimeUnit": "ms"
+}
+"#
+        );
+    }
+}
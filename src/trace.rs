@@ -0,0 +1,325 @@
+//! Machine-readable trace of every executed instruction for `--trace`.
+//!
+//! Each step is written as one JSON object per line with the program counter, the raw
+//! instruction word, the registers it wrote and the data loads and stores it made. The written
+//! registers are the destinations of the decoded instruction, or the CP0 registers that an
+//! exception sets, so a register that is written with the value it already held is listed too.
+//! The trace only depends on the guest, so two runs of the same configuration write the same
+//! file and a trace can be compared line by line against one converted from another emulator
+//! such as QEMU. Values and addresses are written as hexadecimal strings, memory addresses are
+//! physical and the virtual address of a load or store is given as `vaddr`.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::control::cp1::FCSR;
+use crate::control::cpu::Cpu;
+use crate::control::exception::Exception;
+use crate::control::instruction::Instruction;
+use crate::control::isa::{Destination, Op};
+use crate::control::registers::{Cp0Register, REGISTER_NAMES};
+use crate::memory::monitor::{AccessKind, DataAccess};
+use crate::util::error::Result;
+use crate::Address;
+
+/// The CP0 registers and their names in the order `capture` lists them.
+const CP0_REGISTERS: [(Cp0Register, &str); 9] = [
+    (Cp0Register::Index, "Index"),
+    (Cp0Register::Random, "Random"),
+    (Cp0Register::EntryLo, "EntryLo"),
+    (Cp0Register::Context, "Context"),
+    (Cp0Register::BadVaddr, "BadVAddr"),
+    (Cp0Register::EntryHi, "EntryHi"),
+    (Cp0Register::Status, "Status"),
+    (Cp0Register::Cause, "Cause"),
+    (Cp0Register::Epc, "EPC"),
+];
+
+/// Index of `hi` in the order of `register_names`, which follows the general-purpose registers.
+const HI: usize = 32;
+/// Index of the first CP0 register in the order of `register_names`.
+const CP0: usize = HI + 2;
+/// Index of `$f0` in the order of `register_names`.
+const FPR: usize = CP0 + CP0_REGISTERS.len();
+/// Index of the FCSR in the order of `register_names`.
+const FCSR_INDEX: usize = FPR + 32;
+
+/// Returns the CP0 registers that the exception `code` sets.
+fn exception_destinations(code: Exception) -> Vec<Destination> {
+    let mut registers = vec![Cp0Register::Status, Cp0Register::Cause, Cp0Register::Epc];
+    match code {
+        Exception::AddressLoadError | Exception::AddressStoreError => {
+            registers.push(Cp0Register::BadVaddr)
+        }
+        Exception::TLBModification | Exception::TLBLoadMiss | Exception::TLBStoreMiss => registers
+            .extend(&[
+                Cp0Register::BadVaddr,
+                Cp0Register::Context,
+                Cp0Register::EntryHi,
+            ]),
+        _ => {}
+    }
+    registers.into_iter().map(Destination::Cp0).collect()
+}
+
+/// Returns the names of the registers that `capture` lists, with the FPU registers if `fpu`.
+fn register_names(fpu: bool) -> Vec<String> {
+    let mut names: Vec<String> = REGISTER_NAMES
+        .iter()
+        .map(|name| format!("${}", name))
+        .collect();
+    names.extend(
+        ["hi", "lo"]
+            .iter()
+            .map(|name| name.to_string())
+            .chain(CP0_REGISTERS.iter().map(|(_, name)| name.to_string())),
+    );
+    if fpu {
+        names.extend((0..32).map(|reg| format!("$f{}", reg)));
+        names.push("FCSR".to_owned());
+    }
+    names
+}
+
+/// Replaces `values` with the registers of `cpu` in the order of `register_names`.
+fn capture(cpu: &Cpu, values: &mut Vec<u32>) {
+    let cpzero = &cpu.cpzero;
+    values.clear();
+    values.extend_from_slice(&cpu.reg);
+    values.extend_from_slice(&[
+        cpu.high,
+        cpu.low,
+        cpzero.index.into(),
+        cpzero.random.into(),
+        cpzero.entrylo,
+        cpzero.context.into(),
+        cpzero.badvaddr.into(),
+        cpzero.entryhi,
+        cpzero.status.into(),
+        cpzero.cause.into(),
+        cpzero.epc.into(),
+    ]);
+    if let Some(fpu) = cpu.attached_coprocessor(1) {
+        values.extend((0..32).map(|reg| fpu.read_register(reg)));
+        values.push(fpu.read_control(FCSR));
+    }
+}
+
+/// What one step executed, as written to a line of the trace.
+pub struct TraceStep<'a> {
+    /// Number of instructions executed before the step.
+    pub step: usize,
+    pub pc: Address,
+    /// The executed instruction, or `None` for a monitor PROM service and for a step that
+    /// took an exception before an instruction was fetched.
+    pub instr: Option<Instruction>,
+    pub accesses: &'a [DataAccess],
+    /// The exception raised by the step.
+    pub exception: Option<Exception>,
+}
+
+/// Writes the trace of a run to a file.
+pub struct Tracer {
+    out: BufWriter<File>,
+    names: Vec<String>,
+    before: Vec<u32>,
+    after: Vec<u32>,
+    steps: usize,
+}
+
+impl Tracer {
+    /// Creates the trace file at `path` for a run of `cpu`.
+    pub fn create(path: &str, cpu: &Cpu) -> Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+            names: register_names(cpu.attached_coprocessor(1).is_some()),
+            before: Vec::new(),
+            after: Vec::new(),
+            steps: 0,
+        })
+    }
+
+    /// Captures the registers of `cpu` before a step, so that changes made between steps,
+    /// such as by a debugger, are not reported as writes of the step.
+    pub fn begin(&mut self, cpu: &Cpu) {
+        capture(cpu, &mut self.before);
+    }
+
+    /// Writes the line of `step`, which left `cpu` in its current state.
+    pub fn record(&mut self, step: &TraceStep, cpu: &Cpu) -> Result<()> {
+        capture(cpu, &mut self.after);
+        let line = self.line(step);
+        self.steps += 1;
+        writeln!(self.out, "{}", line)?;
+        Ok(())
+    }
+
+    /// Returns the position of `destination` in `names`, or `None` for a register that the
+    /// trace does not list.
+    fn index(&self, destination: Destination) -> Option<usize> {
+        let index = match destination {
+            Destination::Gpr(reg) => reg,
+            Destination::Hi => HI,
+            Destination::Lo => HI + 1,
+            Destination::Cp0(reg) => CP0 + CP0_REGISTERS.iter().position(|(r, _)| *r == reg)?,
+            Destination::Fpr(reg) => FPR + reg,
+            Destination::Fcsr => FCSR_INDEX,
+        };
+        Some(index).filter(|index| *index < self.names.len())
+    }
+
+    /// Returns the positions in `names` of the registers that `step` wrote, in that order.
+    ///
+    /// An instruction that raised an exception did not complete, so only the registers of the
+    /// exception are written. A monitor PROM service is not an instruction, so the registers it
+    /// wrote are found by comparing `before` with `after`.
+    fn written(&self, step: &TraceStep) -> Vec<usize> {
+        let destinations = match (step.exception, step.instr) {
+            (Some(code), _) => exception_destinations(code),
+            (None, Some(instr)) => Op::decode(instr)
+                .map(|op| op.destinations(instr))
+                .unwrap_or_default(),
+            (None, None) => {
+                return (0..self.names.len())
+                    .filter(|index| self.before[*index] != self.after[*index])
+                    .collect()
+            }
+        };
+        let mut written: Vec<usize> = destinations
+            .into_iter()
+            .filter_map(|destination| self.index(destination))
+            .collect();
+        written.sort_unstable();
+        written.dedup();
+        written
+    }
+
+    /// Formats `step` with the registers that it wrote, holding their values in `after`.
+    fn line(&self, step: &TraceStep) -> String {
+        let mut line = format!("{{\"step\":{},\"pc\":\"0x{:08x}\"", step.step, step.pc);
+        match step.instr {
+            Some(instr) => line += &format!(",\"insn\":\"0x{:08x}\"", instr.0),
+            None => line += ",\"insn\":null",
+        }
+
+        let registers: Vec<String> = self
+            .written(step)
+            .into_iter()
+            .map(|index| format!("\"{}\":\"0x{:08x}\"", self.names[index], self.after[index]))
+            .collect();
+        line += &format!(",\"regs\":{{{}}}", registers.join(","));
+
+        // The base register of a load or store still holds its value from before the step
+        if let Some(instr) = step.instr.filter(|instr| instr.data_access().is_some()) {
+            let vaddress = self.before[instr.rs()].wrapping_add(instr.simmed());
+            line += &format!(",\"vaddr\":\"0x{:08x}\"", vaddress);
+        }
+        let accesses: Vec<String> = step
+            .accesses
+            .iter()
+            .map(|access| {
+                let kind = match access.kind {
+                    AccessKind::Read => "r",
+                    AccessKind::Write => "w",
+                };
+                format!(
+                    "{{\"rw\":\"{}\",\"addr\":\"0x{:08x}\",\"size\":{},\"value\":\"0x{:0w$x}\"}}",
+                    kind,
+                    access.address,
+                    access.len,
+                    access.value,
+                    w = access.len * 2
+                )
+            })
+            .collect();
+        line += &format!(",\"mem\":[{}]", accesses.join(","));
+
+        if let Some(exception) = step.exception {
+            line += &format!(",\"exception\":\"{:?}\"", exception);
+        }
+        line + "}"
+    }
+
+    /// Flushes the trace file and returns the number of steps it holds.
+    pub fn finish(&mut self) -> Result<usize> {
+        self.out.flush()?;
+        Ok(self.steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::registers::Register;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn trace_line() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("rmips-{}-trace.jsonl", std::process::id()));
+        let mut cpu = Cpu::new(false);
+        cpu.reset();
+        let mut tracer = Tracer::create(path.to_str().unwrap(), &cpu)?;
+
+        // addu $t0, $t0, $zero writes $t0 although its value stays the same
+        cpu.reg[Register::T0] = 0x1234;
+        tracer.begin(&cpu);
+        let step = TraceStep {
+            step: 0,
+            pc: 0xbfc0_0000,
+            instr: Some(Instruction(0x0100_4021)),
+            accesses: &[],
+            exception: None,
+        };
+        tracer.record(&step, &cpu)?;
+
+        // sh $t0, 0($a0)
+        cpu.reg[Register::A0] = 0x8000_1000;
+        tracer.begin(&cpu);
+        let accesses = [DataAccess {
+            kind: AccessKind::Write,
+            address: 0x1000,
+            len: 2,
+            value: 0x34,
+        }];
+        let step = TraceStep {
+            step: 1,
+            pc: 0xbfc0_0004,
+            instr: Some(Instruction(0xa488_0000)),
+            accesses: &accesses,
+            exception: None,
+        };
+        tracer.record(&step, &cpu)?;
+
+        // syscall only writes the registers of the exception
+        tracer.begin(&cpu);
+        cpu.cpzero.epc.address = 0xbfc0_0008;
+        let step = TraceStep {
+            step: 2,
+            pc: 0xbfc0_0008,
+            instr: Some(Instruction(0x0000_000c)),
+            accesses: &[],
+            exception: Some(Exception::Syscall),
+        };
+        tracer.record(&step, &cpu)?;
+        assert_eq!(tracer.finish()?, 3);
+
+        let status: u32 = cpu.cpzero.status.into();
+        let cause: u32 = cpu.cpzero.cause.into();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!(
+                "{{\"step\":0,\"pc\":\"0xbfc00000\",\"insn\":\"0x01004021\",\
+                 \"regs\":{{\"$t0\":\"0x00001234\"}},\"mem\":[]}}\n\
+                 {{\"step\":1,\"pc\":\"0xbfc00004\",\"insn\":\"0xa4880000\",\"regs\":{{}},\
+                 \"vaddr\":\"0x80001000\",\
+                 \"mem\":[{{\"rw\":\"w\",\"addr\":\"0x00001000\",\"size\":2,\"value\":\"0x0034\"}}]}}\n\
+                 {{\"step\":2,\"pc\":\"0xbfc00008\",\"insn\":\"0x0000000c\",\
+                 \"regs\":{{\"Status\":\"0x{:08x}\",\"Cause\":\"0x{:08x}\",\"EPC\":\"0xbfc00008\"}},\
+                 \"mem\":[],\"exception\":\"Syscall\"}}\n",
+                status, cause
+            )
+        );
+        std::fs::remove_file(path).unwrap();
+        Ok(())
+    }
+}
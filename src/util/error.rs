@@ -1,21 +1,59 @@
 use std::fmt;
 use std::io;
 
-use crate::Address;
+use crate::control::invariants::InvariantViolation;
+use crate::control::model::MAX_TLB_ENTRIES;
+use crate::emulator::MAX_STRING_LEN;
+use crate::shadow_stack::ReturnMismatch;
+use crate::{Address, HaltReason};
 
 /// A type alias for `Result<T, RmipsError>`.
 pub type Result<T> = std::result::Result<T, RmipsError>;
 
 #[derive(Debug)]
 pub enum RmipsError {
-    Halt,
+    AccessWidth(Address, usize),
+    Assembly(usize, String),
+    BusError(Address),
+    Config(String),
+    DebugSession(String, String),
+    DeviceBoundary(Address),
+    GuardRegion(Address),
+    Halt(HaltReason),
+    HostInput(&'static str),
     // InvalidInstruction(u32),
+    InputScript(String, String),
+    Invariant(Box<InvariantViolation>),
     Io(io::Error),
+    LoadAddress(Address),
+    Machine(String, String),
     MemoryRangeOverlap,
     MemoryRead(Address),
     MemoryWrite(Address),
+    NoTranslation(Address),
+    PrivilegeViolation(Address),
+    RamImage(String),
+    RegisterMap(String, String),
+    Reset,
     RomLoading(String),
+    ShadowStack(ReturnMismatch),
+    SharedHostState(&'static str),
+    Snapshot(String),
+    TlbSize(usize),
+    UnknownSymbol(String),
     UnmappedAddress(Address),
+    UnterminatedString(Address),
+}
+
+impl RmipsError {
+    /// Returns true for access errors that a `Cpu` load, store or fetch reports to the
+    /// program as a bus error exception instead of stopping the emulator.
+    pub fn is_bus_error(&self) -> bool {
+        matches!(
+            self,
+            RmipsError::AccessWidth(..) | RmipsError::BusError(_) | RmipsError::DeviceBoundary(_)
+        )
+    }
 }
 
 impl std::error::Error for RmipsError {}
@@ -25,22 +63,81 @@ impl fmt::Display for RmipsError {
         use self::RmipsError::*;
 
         match self {
-            Halt => write!(f, "System halt triggered"),
+            AccessWidth(address, len) => write!(
+                f,
+                "Device at 0x{:08x} does not support {}-byte accesses",
+                address, len
+            ),
+            Assembly(line, msg) => write!(f, "Assembly error on line {}: {}", line, msg),
+            BusError(address) => write!(f, "Bus error accessing 0x{:08x}", address),
+            Config(msg) => write!(f, "Invalid configuration: {}", msg),
+            DebugSession(path, msg) => write!(f, "Invalid debug session {}: {}", path, msg),
+            DeviceBoundary(address) => {
+                write!(f, "Access at 0x{:08x} crosses the end of a device", address)
+            }
+            GuardRegion(address) => write!(f, "Access to guard region at 0x{:08x}", address),
+            Halt(_) => write!(f, "System halt triggered"),
+            HostInput(device) => write!(
+                f,
+                "Cannot verify the determinism of a run using the {}, its input comes from the host",
+                device
+            ),
             // InvalidInstruction(instr) => write!(
             //     f,
             //     "Attempted to execute an invalid instruction: 0x{:08x}",
             //     instr
             // ),
+            InputScript(path, msg) => write!(f, "Invalid input script {}: {}", path, msg),
+            Invariant(violation) => violation.fmt(f),
             Io(err) => err.fmt(f),
+            LoadAddress(address) => write!(
+                f,
+                "Load address 0x{:08x} is in a segment mapped by the TLB, use a kseg1 \
+                 (0xa0000000-0xbfffffff) or kseg0 (0x80000000-0x9fffffff) address, or a physical \
+                 address below 0x20000000",
+                address
+            ),
+            Machine(path, msg) => write!(f, "Invalid machine file {}: {}", path, msg),
             MemoryRangeOverlap => write!(f, "New memory range overlaps an existing one"),
             MemoryRead(address) => write!(f, "Failed to read memory from 0x{:08x}", address),
             MemoryWrite(address) => write!(f, "Failed to write memory to 0x{:08x}", address),
+            NoTranslation(address) => write!(
+                f,
+                "Virtual address 0x{:08x} has no valid TLB translation",
+                address
+            ),
+            PrivilegeViolation(address) => write!(
+                f,
+                "User mode attempted to access kernel address 0x{:08x}",
+                address
+            ),
+            RamImage(path) => write!(f, "Failed to load RAM image: {}", path),
+            RegisterMap(path, msg) => write!(f, "Invalid register map {}: {}", path, msg),
+            Reset => write!(f, "The guest requested a reset"),
             RomLoading(path) => write!(f, "Failed to load ROM file: {}", path),
+            ShadowStack(mismatch) => mismatch.fmt(f),
+            SharedHostState(device) => write!(
+                f,
+                "Cannot fork an emulator using the {}, its state is shared with the host",
+                device
+            ),
+            Snapshot(msg) => write!(f, "Invalid snapshot: {}", msg),
+            TlbSize(entries) => write!(
+                f,
+                "TLB size of {} entries is not between 1 and {}",
+                entries, MAX_TLB_ENTRIES
+            ),
+            UnknownSymbol(name) => write!(f, "Unknown symbol: {}", name),
             UnmappedAddress(address) => write!(
                 f,
                 "Address 0x{:08x} is not in a valid address space",
                 address
             ),
+            UnterminatedString(address) => write!(
+                f,
+                "String at physical address 0x{:08x} has no NUL within {} bytes or before the end of memory",
+                address, MAX_STRING_LEN
+            ),
         }
     }
 }
@@ -1,2 +1,6 @@
 pub mod error;
 pub mod opts;
+pub(crate) mod parse;
+pub(crate) mod rng;
+pub mod signals;
+pub(crate) mod state;
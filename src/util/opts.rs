@@ -1,19 +1,80 @@
-use clap::{crate_authors, crate_description, crate_version, Clap};
+use std::net::SocketAddr;
+use std::str::FromStr;
 
+use clap::{crate_authors, crate_description, crate_version, AppSettings, ArgSettings, Clap};
+
+use crate::control::model::CpuModel;
+use crate::devices::i2c::I2cSlaveSpec;
+use crate::devices::serial_link::{SerialEndpoint, DEFAULT_FIFO_DEPTH};
+use crate::devices::spi::SpiSlaveSpec;
+use crate::memory::faults::FaultKind;
+use crate::shadow_stack::ShadowStackMode;
+use crate::util::parse::parse_integer;
+use crate::watch::WatchExpr;
+
+/// The command line, which runs the ROM given by the options unless a subcommand is given first.
+#[derive(Clap)]
+#[clap(
+    version = crate_version!(),
+    author = crate_authors!(),
+    about = crate_description!(),
+    setting = AppSettings::ArgsNegateSubcommands
+)]
+pub struct Cli {
+    #[clap(flatten)]
+    pub opts: Opts,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+// The subcommands that inspect a ROM or the emulator instead of running it. A doc comment would
+// replace the description of the command line in `--help`.
 #[derive(Clap)]
-#[clap(version = crate_version!(), author = crate_authors!(), about = crate_description!())]
+pub enum Command {
+    /// List the instructions of a ROM image without running it.
+    Disasm(DisasmOpts),
+    /// Print the instruction reference generated from the instruction table as Markdown.
+    Instructions,
+    /// Print the machine that the options of a run build, without running it.
+    Describe(Box<Opts>),
+}
+
+#[derive(Clap, Clone, Debug)]
 pub struct Opts {
-    /// ROM file to be loaded into memory.
+    /// ROM file to be loaded into memory, or MIPS assembly source (`.s` or `.asm`) to assemble.
+    /// May be left out when the machine file names the ROM.
+    #[clap(default_value = "", setting = ArgSettings::AllowEmptyValues)]
     pub romfile: String,
+    /// Machine file that describes the CPU, memory, ROM and devices of the board, for the options
+    /// that are not given on the command line.
+    #[clap(long)]
+    pub machine: Option<String>,
     /// Print verbose logging output.
     #[clap(short, long, parse(from_occurrences))]
     pub verbose: i32,
-    /// Virtual address where the ROM will be loaded.
+    /// Address where the ROM will be loaded, in kseg1, kseg0 or as a physical address below
+    /// 512MB, which is always physical with `--no-mmu`.
     #[clap(short, long, default_value = "3217031168")]
     pub loadaddress: u32,
+    /// Offset into the ROM file where the mapped image starts.
+    #[clap(long, default_value = "0")]
+    pub romoffset: u64,
+    /// Number of bytes of the ROM file to map, defaults to the rest of the file.
+    #[clap(long)]
+    pub romlength: Option<u64>,
     /// Size of the virtual CPU's physical memory in bytes.
     #[clap(short, long, default_value = "1048576")]
     pub memsize: usize,
+    /// Allocate RAM in 64KB chunks on first write instead of all at once, so large memory
+    /// sizes only use host memory for the parts the guest touches.
+    #[clap(long = "sparse-ram")]
+    pub sparseram: bool,
+    /// Processor model to emulate (r3000 or r4000).
+    #[clap(long, default_value = "r3000")]
+    pub cpumodel: CpuModel,
+    /// Number of TLB entries, defaults to the size used by the processor model.
+    #[clap(long)]
+    pub tlbentries: Option<usize>,
     /// Enable GDB stub for debugging.
     #[clap(short, long)]
     pub debug: bool,
@@ -23,7 +84,12 @@ pub struct Opts {
     /// IP address for the GDB stub to listen on.
     #[clap(short = 'i', long = "ip", default_value = "127.0.0.1")]
     pub debugip: String,
-    /// Interpret the ROM as a big-endian binary.
+    /// Show the tasks of an RTOS kernel as GDB threads, given the TCB list layout as
+    /// `tcbs=ADDR,current=ADDR,next=OFF[,context=OFF][,name=OFF]`.
+    #[clap(long, requires = "debug")]
+    pub rtos: Option<RtosLayout>,
+    /// Run a big-endian machine, which loads and stores words and fetches instructions in
+    /// big-endian byte order.
     #[clap(long)]
     pub bigendian: bool,
     /// Display the memory mappings for the emulator on startup.
@@ -32,29 +98,800 @@ pub struct Opts {
     /// Disassemble and print instructions as they are executed.
     #[clap(long)]
     pub instrdump: bool,
+    /// Describe each executed instruction and print the registers and memory it changed.
+    #[clap(long)]
+    pub explain: bool,
+    /// Write the PC, instruction word, register writes and memory accesses of every executed
+    /// instruction to a file as JSON lines.
+    #[clap(long)]
+    pub trace: Option<String>,
+    /// Physical base address of RAM, or `random[:seed]` for a page-aligned base below 512MB
+    /// that leaves an unmapped guard page on both sides of RAM.
+    #[clap(long = "ram-base")]
+    pub rambase: Option<RamBase>,
+    /// Make a region unmapped as `address+length`, e.g. below the stack, may be repeated.
+    ///
+    /// Any access to a guard region stops the run at the offending instruction with exit
+    /// status 1 instead of raising a bus error in the program.
+    /// Region addresses in kseg0 and kseg1 are translated to the physical addresses they map to,
+    /// other addresses are taken as physical addresses.
+    #[clap(long)]
+    pub guard: Vec<GuardRegion>,
+    /// Mirror another physical region as `address+length=target[%stride]`, may be repeated.
+    ///
+    /// The first `stride` bytes at the target repeat across the whole region, which
+    /// defaults to a plain alias of `length` bytes.
+    #[clap(long)]
+    pub alias: Vec<AliasRegion>,
+    /// Connect the devices in a physical region as `address+length` with swapped byte lanes,
+    /// for peripherals of the other endianness, may be repeated.
+    #[clap(long = "byte-swap")]
+    pub byteswap: Vec<ByteSwapRegion>,
+    /// Preload RAM from a file, optionally at an offset into RAM (`file.bin[@offset]`).
+    #[clap(long = "ram-image")]
+    pub ramimage: Option<RamImage>,
+    /// Write the contents of RAM to a file when the emulator halts.
+    #[clap(long = "ram-dump")]
+    pub ramdump: Option<String>,
+    /// Write the registers and memory map to a file when SIGINT or SIGTERM stops the emulator.
+    #[clap(long = "signal-dump")]
+    pub signaldump: Option<String>,
+    /// Write the complete machine state to a snapshot file when the emulator halts, such as at
+    /// `--stop-at`.
+    #[clap(long = "save-snapshot")]
+    pub savesnapshot: Option<String>,
+    /// Resume from a snapshot file saved by a run with the same options.
+    #[clap(long = "load-snapshot")]
+    pub loadsnapshot: Option<String>,
+    /// Write the executed basic blocks, their edges and the instruction mix as JSON when the emulator halts.
+    #[clap(long = "block-profile")]
+    pub blockprofile: Option<String>,
+    /// Count the executed instructions and list the ones that never executed when the emulator
+    /// halts.
+    #[clap(long)]
+    pub coverage: bool,
+    /// Write the reads, writes and instruction fetches of every 4KB physical page when the
+    /// emulator halts, as CSV if the file name ends in `.csv` and as JSON otherwise.
+    #[clap(long)]
+    pub heatmap: Option<String>,
+    /// Write a timeline of exceptions, device accesses and debugger stops in the Chrome trace format when the emulator halts.
+    #[clap(long)]
+    pub timeline: Option<String>,
+    /// Host file backing the non-volatile storage device, which is only mapped when set.
+    #[clap(long)]
+    pub nvram: Option<String>,
+    /// Size of the non-volatile storage device in bytes.
+    #[clap(long, default_value = "4096")]
+    pub nvramsize: usize,
+    /// Host file mapped as memory shared with the guest, which is only mapped when set.
+    #[clap(long = "shared-memory")]
+    pub sharedmemory: Option<String>,
+    /// Size of the shared memory device in bytes.
+    #[clap(long = "shared-memory-size", default_value = "65536")]
+    pub sharedmemorysize: usize,
+    /// Host UDP address for the network device to receive frames on, which is only mapped when set.
+    #[clap(long = "net-listen")]
+    pub netlisten: Option<SocketAddr>,
+    /// Host UDP address of another network device to send frames to, may be repeated.
+    #[clap(long = "net-peer", requires = "netlisten")]
+    pub netpeer: Vec<SocketAddr>,
+    /// Host end of the null-modem serial device, such as `tcp-listen:127.0.0.1:4000`,
+    /// `tcp:HOST:PORT`, `unix-listen:PATH` or `unix:PATH`. The device is only mapped when set.
+    #[clap(long = "serial-link")]
+    pub seriallink: Option<SerialEndpoint>,
+    /// Number of received bytes the serial link FIFO holds.
+    #[clap(long = "serial-fifo-depth", default_value = "16")]
+    pub serialfifodepth: usize,
+    /// Attach a slave model to the I2C controller as `MODEL@ADDRESS`, where the model is
+    /// `lm75` or `24c02`, may be repeated. The controller is only mapped when a slave is given.
+    #[clap(long = "i2c-slave")]
+    pub i2cslave: Vec<I2cSlaveSpec>,
+    /// Attach a `25lc256` slave model to the next chip select of the SPI controller, may be
+    /// repeated. The controller is only mapped when a slave is given.
+    #[clap(long = "spi-slave")]
+    pub spislave: Vec<SpiSlaveSpec>,
+    /// Map a PS/2 keyboard that types the characters read from standard input.
+    #[clap(long)]
+    pub keyboard: bool,
+    /// Map a row of LEDs and a 7-segment display, which are printed whenever they change.
+    #[clap(long)]
+    pub leds: bool,
+    /// Map a word that prints the NUL-terminated string at the virtual address stored to it.
+    #[clap(long = "debug-print")]
+    pub debugprint: bool,
+    /// Map a read-only block that identifies the emulator with its version, features and virtual
+    /// time.
+    #[clap(long = "emulator-info")]
+    pub emulatorinfo: bool,
+    /// Map a programmable timer at 0x01010000 that raises hardware interrupt IP7 when its
+    /// interval of virtual microseconds expires.
+    #[clap(long)]
+    pub clock: bool,
+    /// Replay the serial bytes and interrupt line changes scheduled in an input script at the
+    /// same instruction counts on every run.
+    #[clap(long)]
+    pub inject: Option<String>,
+    /// Name the registers and bit fields of devices with a register map file, which decodes
+    /// device accesses and lets watch expressions read `mmio[PERIPHERAL.REGISTER.FIELD]`.
+    #[clap(long)]
+    pub regmap: Option<String>,
+    /// Restore the breakpoints, watchpoints and patches of a debug session file when starting,
+    /// and save them to it when the run ends.
+    #[clap(long = "debug-session")]
+    pub debugsession: Option<String>,
+    /// Map the built-in monitor PROM, which provides putchar, getenv and exit callbacks.
+    #[clap(long = "monitor-prom", alias = "monitorprom")]
+    pub monitorprom: bool,
+    /// Environment variable for the monitor PROM as `NAME=VALUE`, may be repeated.
+    #[clap(long = "prom-env", alias = "promenv", parse(try_from_str = parse_env_var))]
+    pub promenv: Vec<(String, String)>,
+    /// Overwrite a word of guest code after loading the ROM as `ADDRESS=VALUE`, may be repeated.
+    #[clap(long)]
+    pub patch: Vec<CodePatch>,
+    /// Make a function return 0 at once, given as a virtual address or as a label of an assembly
+    /// source ROM, may be repeated.
+    #[clap(long = "skip-function")]
+    pub skipfunction: Vec<String>,
+    /// Stop on the first access to a region as `address+length[:r|w|rw]`, may be repeated.
+    ///
+    /// Region addresses in kseg0 and kseg1 are translated to the physical addresses they map to,
+    /// other addresses are taken as physical addresses.
+    #[clap(long = "break-on-access")]
+    pub breakonaccess: Vec<AccessBreak>,
+    /// Stop when an expression such as `reg[a0] == 0xdeadbeef` becomes true, may be repeated.
+    #[clap(long)]
+    pub watch: Vec<WatchExpr>,
+    /// Stop when a condition is reached, currently only `pc=ADDRESS` for the first time the
+    /// program counter reaches a virtual address.
+    #[clap(long = "stop-at")]
+    pub stopat: Option<StopAt>,
+    /// Replay the run from reset and stop this many instructions before the `--stop-at`
+    /// condition was reached.
+    #[clap(long, requires = "stopat")]
+    pub rewind: Option<u64>,
+    /// Warn about writes to `$zero`, HI/LO hazards, reserved encodings and branches in delay
+    /// slots in the executed code.
+    #[clap(long)]
+    pub lint: bool,
+    /// Cross-check the decoding of every executed instruction against Capstone and report
+    /// mismatches.
+    #[clap(long = "verify-decode")]
+    pub verifydecode: bool,
+    /// Run the configuration this many times and check that every run executes the same
+    /// instructions and ends in the same state.
+    #[clap(long = "verify-determinism")]
+    pub verifydeterminism: Option<usize>,
+    /// Check that `jr ra` returns to the innermost call and `warn` or `stop` when it does not.
+    #[clap(long = "shadow-stack")]
+    pub shadowstack: Option<ShadowStackMode>,
+    /// Entry point of the guest `malloc`, enables the heap checker together with `--free`.
+    #[clap(long, parse(try_from_str = parse_address), requires = "free")]
+    pub malloc: Option<u32>,
+    /// Entry point of the guest `free`, enables the heap checker together with `--malloc`.
+    #[clap(long, parse(try_from_str = parse_address), requires = "malloc")]
+    pub free: Option<u32>,
+    /// Inject a fault into this many out of every million loads and stores.
+    #[clap(long = "fault-rate", default_value = "0")]
+    pub faultrate: u32,
+    /// Kind of fault to inject: `bus` errors, RAM `bitflip`s or `any`.
+    #[clap(long = "fault-kind", default_value = "any")]
+    pub faultkind: FaultKind,
+    /// Seed for choosing when and where faults are injected.
+    #[clap(long = "fault-seed", default_value = "1")]
+    pub faultseed: u64,
+    /// Let devices see the monotonic host time instead of the virtual time, which makes runs
+    /// depend on the speed of the host.
+    #[clap(long = "host-clock")]
+    pub hostclock: bool,
+    /// Throttle the emulator to run for at most this percentage of the wall-clock time.
+    #[clap(long = "cpu-quota", parse(try_from_str = parse_percentage))]
+    pub cpuquota: Option<u32>,
+    /// Pause the emulator while the host has fewer than this many megabytes of memory available.
+    #[clap(long = "min-host-memory")]
+    pub minhostmemory: Option<u64>,
     /// Do not map the halt device into physical memory.
     #[clap(long)]
     pub nohaltdevice: bool,
+    /// Physical address to map the halt device at instead of 0x01010024.
+    #[clap(long = "halt-device-at", parse(try_from_str = parse_address))]
+    pub haltdeviceat: Option<u32>,
+    /// Map a word at 0x01010028 that resets the CPU when the guest writes a nonzero value to it.
+    ///
+    /// Memory and devices keep their state, like a warm reboot. A GDB session stays connected
+    /// and sees the reset as a stop with SIGPWR.
+    #[clap(long = "reset-device")]
+    pub resetdevice: bool,
+    /// Physical address to map the test device at instead of 0x02010000.
+    #[clap(long = "test-device-at", parse(try_from_str = parse_address))]
+    pub testdeviceat: Option<u32>,
+    /// Write the results that the guest reported to the test device when the emulator halts, as
+    /// JUnit XML if the file name ends in `.xml` and as TAP otherwise.
+    #[clap(long = "test-report")]
+    pub testreport: Option<String>,
+    /// Stop with an error instead of raising an address exception when user mode accesses kernel memory.
+    #[clap(long = "privilege-errors")]
+    pub privilegeerrors: bool,
+    /// Treat every address as physical instead of translating the R3000 segments, for flat
+    /// firmware that does its own address math. The ROM is mapped at the load address and
+    /// execution starts there.
+    #[clap(long = "no-mmu", conflicts_with = "monitorprom")]
+    pub nommu: bool,
+    /// Leave the CP1 slot empty instead of attaching the R3010 floating-point coprocessor, so
+    /// floating-point instructions raise Coprocessor Unusable for a software emulator.
+    #[clap(long = "no-fpu")]
+    pub nofpu: bool,
     /// Do not halt the program when encountering a break instruction.
     #[clap(long)]
     pub nohaltbreak: bool,
 }
 
+/// The defaults of the command line, for library users. `Config::validate` checks the options
+/// whether they come from here or from the command line.
 impl Default for Opts {
     fn default() -> Self {
         Opts {
             romfile: String::from(""),
+            machine: None,
             verbose: 0,
-            loadaddress: 3217031168,
-            memsize: 1048576,
+            loadaddress: 0xbfc0_0000,
+            romoffset: 0,
+            romlength: None,
+            memsize: 0x10_0000,
+            sparseram: false,
+            cpumodel: CpuModel::R3000,
+            tlbentries: None,
             debug: false,
             debugport: 9001,
             debugip: String::from("127.0.0.1"),
+            rtos: None,
             bigendian: false,
             memmap: false,
             instrdump: false,
+            explain: false,
+            trace: None,
+            rambase: None,
+            guard: Vec::new(),
+            alias: Vec::new(),
+            byteswap: Vec::new(),
+            ramimage: None,
+            ramdump: None,
+            savesnapshot: None,
+            loadsnapshot: None,
+            signaldump: None,
+            blockprofile: None,
+            coverage: false,
+            heatmap: None,
+            timeline: None,
+            nvram: None,
+            nvramsize: 4096,
+            sharedmemory: None,
+            sharedmemorysize: 65536,
+            netlisten: None,
+            netpeer: Vec::new(),
+            seriallink: None,
+            serialfifodepth: DEFAULT_FIFO_DEPTH,
+            i2cslave: Vec::new(),
+            spislave: Vec::new(),
+            keyboard: false,
+            leds: false,
+            debugprint: false,
+            emulatorinfo: false,
+            clock: false,
+            inject: None,
+            regmap: None,
+            debugsession: None,
+            monitorprom: false,
+            promenv: Vec::new(),
+            patch: Vec::new(),
+            skipfunction: Vec::new(),
+            breakonaccess: Vec::new(),
+            watch: Vec::new(),
+            stopat: None,
+            rewind: None,
+            lint: false,
+            verifydecode: false,
+            verifydeterminism: None,
+            shadowstack: None,
+            malloc: None,
+            free: None,
+            faultrate: 0,
+            faultkind: FaultKind::Any,
+            faultseed: 1,
+            hostclock: false,
+            cpuquota: None,
+            minhostmemory: None,
             nohaltdevice: false,
+            haltdeviceat: None,
+            resetdevice: false,
+            testdeviceat: None,
+            testreport: None,
+            privilegeerrors: false,
+            nommu: false,
+            nofpu: false,
             nohaltbreak: false,
         }
     }
 }
+
+/// Options of the `disasm` subcommand, which lists a ROM image without running it.
+#[derive(Clap, Clone)]
+pub struct DisasmOpts {
+    /// ROM file to be disassembled, or MIPS assembly source (`.s` or `.asm`) to assemble.
+    pub romfile: String,
+    /// Virtual address where the ROM is loaded.
+    #[clap(long, default_value = "0xbfc00000", parse(try_from_str = parse_address))]
+    pub base: u32,
+    /// Number of instructions to list, defaults to the whole image.
+    #[clap(long)]
+    pub count: Option<usize>,
+    /// Offset into the ROM file where the image starts.
+    #[clap(long, default_value = "0")]
+    pub romoffset: u64,
+    /// Interpret the ROM as a big-endian binary.
+    #[clap(long)]
+    pub bigendian: bool,
+    /// List every instruction as is instead of folding idioms like `lui` and `ori` into `li`.
+    #[clap(long = "no-fold")]
+    pub nofold: bool,
+}
+
+/// A file to preload into RAM and the physical offset to load it at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RamImage {
+    pub path: String,
+    pub offset: usize,
+}
+
+impl FromStr for RamImage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, offset) = match s.rsplit_once('@') {
+            Some((path, offset)) => {
+                let offset = parse_integer(offset)
+                    .map_err(|_| format!("invalid RAM image offset: {}", offset))?;
+                (path, offset)
+            }
+            None => (s, 0),
+        };
+
+        Ok(RamImage {
+            path: path.to_owned(),
+            offset,
+        })
+    }
+}
+
+/// A memory region to stop on the first matching access to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AccessBreak {
+    pub address: u32,
+    pub len: usize,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl FromStr for AccessBreak {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (region, mode) = s.split_once(':').unwrap_or((s, "rw"));
+        let (read, write) = match mode {
+            "r" => (true, false),
+            "w" => (false, true),
+            "rw" | "wr" => (true, true),
+            _ => return Err(format!("invalid access mode: {}", mode)),
+        };
+
+        let (address, len) = parse_region(region)?;
+        Ok(AccessBreak {
+            address,
+            len,
+            read,
+            write,
+        })
+    }
+}
+
+/// A region made unmapped by `--guard`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GuardRegion {
+    pub address: u32,
+    pub len: usize,
+}
+
+impl FromStr for GuardRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, len) = parse_region(s)?;
+        Ok(GuardRegion { address, len })
+    }
+}
+
+/// A physical region whose devices have swapped byte lanes, set with `--byte-swap`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ByteSwapRegion {
+    pub address: u32,
+    pub len: usize,
+}
+
+impl FromStr for ByteSwapRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, len) = parse_region(s)?;
+        Ok(ByteSwapRegion { address, len })
+    }
+}
+
+/// A physical region that mirrors another one, set with `--alias`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AliasRegion {
+    pub address: u32,
+    pub len: usize,
+    pub target: u32,
+    pub stride: Option<usize>,
+}
+
+impl FromStr for AliasRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid alias: {}", s);
+        let (region, target) = s.split_once('=').ok_or_else(invalid)?;
+        let (address, len) = parse_region(region)?;
+        let (target, stride) = match target.split_once('%') {
+            Some((target, stride)) => match parse_integer::<usize>(stride) {
+                Ok(stride) if stride > 0 => (target, Some(stride)),
+                _ => return Err(invalid()),
+            },
+            None => (target, None),
+        };
+        let target = parse_address(target).map_err(|_| invalid())?;
+        Ok(AliasRegion {
+            address,
+            len,
+            target,
+            stride,
+        })
+    }
+}
+
+/// Where the kernel keeps its task control blocks, set with `--rtos`.
+///
+/// The TCBs form a list that starts at the pointer stored at `tcbs` and is linked through the
+/// pointer at offset `next` of each TCB. A TCB points to the saved context of its task at offset
+/// `context`, which holds `r0`-`r31`, `lo`, `hi` and the PC as words. The task name, if any, is a
+/// NUL-terminated string at offset `name`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RtosLayout {
+    /// Address of the pointer to the first TCB.
+    pub tcbs: u32,
+    /// Address of the pointer to the TCB of the running task.
+    pub current: u32,
+    pub next: u32,
+    pub context: u32,
+    pub name: Option<u32>,
+}
+
+impl FromStr for RtosLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut tcbs, mut current, mut next, mut context, mut name) =
+            (None, None, None, None, None);
+        for field in s.split(',') {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("invalid RTOS layout field: {}", field))?;
+            let value = parse_address(value)?;
+            let slot = match key {
+                "tcbs" => &mut tcbs,
+                "current" => &mut current,
+                "next" => &mut next,
+                "context" => &mut context,
+                "name" => &mut name,
+                _ => return Err(format!("unknown RTOS layout field: {}", key)),
+            };
+            *slot = Some(value);
+        }
+
+        let layout = RtosLayout {
+            tcbs: tcbs.ok_or("the RTOS layout needs the `tcbs` list address")?,
+            current: current.ok_or("the RTOS layout needs the `current` task address")?,
+            next: next.ok_or("the RTOS layout needs the `next` offset")?,
+            context: context.unwrap_or(0),
+            name,
+        };
+        let words = [layout.tcbs, layout.current, layout.next, layout.context];
+        if !words.iter().all(|word| word.is_multiple_of(4)) {
+            return Err(format!("RTOS layout pointers are not word aligned: {}", s));
+        }
+        Ok(layout)
+    }
+}
+
+/// Where `--ram-base` maps RAM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RamBase {
+    /// A fixed physical address.
+    Fixed(u32),
+    /// A random base chosen with the seed, or with a seed taken from the clock.
+    Random(Option<u64>),
+}
+
+impl FromStr for RamBase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            _ if s == "random" => Ok(RamBase::Random(None)),
+            Some(("random", seed)) => seed
+                .parse()
+                .map(|seed| RamBase::Random(Some(seed)))
+                .map_err(|_| format!("invalid seed: {}", seed)),
+            _ => parse_address(s).map(RamBase::Fixed),
+        }
+    }
+}
+
+/// Parses a nonempty region given as `address+length`.
+fn parse_region(region: &str) -> Result<(u32, usize), String> {
+    let invalid = || format!("invalid region: {}", region);
+    let (address, len) = region.split_once('+').ok_or_else(invalid)?;
+    let address: u32 = parse_integer(address).map_err(|_| invalid())?;
+    let len: usize = parse_integer(len).map_err(|_| invalid())?;
+    if len == 0 || (address as u64 + len as u64 - 1) > u32::MAX as u64 {
+        return Err(invalid());
+    }
+    Ok((address, len))
+}
+
+/// A word written over guest code by `--patch`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CodePatch {
+    /// Virtual address of the word.
+    pub address: u32,
+    pub value: u32,
+}
+
+impl FromStr for CodePatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid patch: {}", s))?;
+        let address = parse_address(address)?;
+        if !address.is_multiple_of(4) {
+            return Err(format!("patch address is not word-aligned: {}", s));
+        }
+
+        Ok(CodePatch {
+            address,
+            value: parse_address(value).map_err(|_| format!("invalid patch value: {}", value))?,
+        })
+    }
+}
+
+/// A condition for `--stop-at` to stop the emulator at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopAt {
+    /// The program counter reaches the virtual address.
+    Pc(u32),
+}
+
+impl FromStr for StopAt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some(("pc", address)) => parse_address(address).map(StopAt::Pc),
+            _ => Err(format!("invalid stop condition: {}", s)),
+        }
+    }
+}
+
+/// Parses a 32-bit address given in decimal or as hexadecimal prefixed with `0x`.
+pub(crate) fn parse_address(s: &str) -> Result<u32, String> {
+    parse_integer(s).map_err(|_| format!("invalid address: {}", s))
+}
+
+/// Parses a percentage from 1 to 100.
+fn parse_percentage(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(percentage @ 1..=100) => Ok(percentage),
+        _ => Err(format!("invalid percentage, expected 1 to 100: {}", s)),
+    }
+}
+
+/// Parses an environment variable given as `NAME=VALUE`.
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_owned(), value.to_owned())),
+        _ => Err(format!("invalid environment variable: {}", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn default_matches_command_line() {
+        let cli = Opts::try_parse_from(["rmips", "rom.bin"]).unwrap();
+        let default = Opts::default();
+
+        assert_eq!(cli.loadaddress, default.loadaddress);
+        assert_eq!(cli.romoffset, default.romoffset);
+        assert_eq!(cli.memsize, default.memsize);
+        assert_eq!(cli.cpumodel, default.cpumodel);
+        assert_eq!(cli.debugport, default.debugport);
+        assert_eq!(cli.debugip, default.debugip);
+        assert_eq!(cli.nvramsize, default.nvramsize);
+        assert_eq!(cli.sharedmemorysize, default.sharedmemorysize);
+        assert_eq!(cli.serialfifodepth, default.serialfifodepth);
+        assert_eq!(cli.faultrate, default.faultrate);
+        assert_eq!(cli.faultkind, default.faultkind);
+        assert_eq!(cli.faultseed, default.faultseed);
+    }
+
+    #[test]
+    fn subcommands() {
+        let cli = Cli::try_parse_from(["rmips", "rom.bin", "--memsize", "4096"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.opts.romfile, "rom.bin");
+
+        let cli = Cli::try_parse_from(["rmips", "disasm", "rom.bin", "--count", "4"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Disasm(opts)) if opts.count == Some(4)));
+        let cli =
+            Cli::try_parse_from(["rmips", "describe", "rom.bin", "--memsize", "4096"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Describe(opts)) if opts.memsize == 4096));
+        let cli = Cli::try_parse_from(["rmips", "instructions"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Instructions)));
+
+        // A subcommand is only recognized in place of the ROM
+        assert!(Cli::try_parse_from(["rmips", "rom.bin", "describe"]).is_err());
+        assert!(Cli::try_parse_from(["rmips", "instructions", "rom.bin"]).is_err());
+    }
+
+    #[test]
+    fn monitor_prom_flags() {
+        let cli = Opts::try_parse_from(["rmips", "rom.bin", "--monitor-prom", "--prom-env", "A=1"])
+            .unwrap();
+        assert!(cli.monitorprom);
+        assert_eq!(cli.promenv, vec![("A".to_owned(), "1".to_owned())]);
+
+        // The original spelling is still accepted
+        let cli = Opts::try_parse_from(["rmips", "--monitorprom", "rom.bin"]).unwrap();
+        assert!(cli.monitorprom);
+    }
+
+    #[test]
+    fn ram_image_from_str() {
+        let image = |path: &str, offset| RamImage {
+            path: path.to_owned(),
+            offset,
+        };
+
+        assert_eq!("ram.bin".parse(), Ok(image("ram.bin", 0)));
+        assert_eq!("ram.bin@4096".parse(), Ok(image("ram.bin", 4096)));
+        assert_eq!("ram.bin@0x8000".parse(), Ok(image("ram.bin", 0x8000)));
+        assert_eq!("a@b.bin@0x10".parse(), Ok(image("a@b.bin", 0x10)));
+        assert!("ram.bin@0xzz".parse::<RamImage>().is_err());
+    }
+
+    #[test]
+    fn access_break_from_str() {
+        let region = |address, len, read, write| AccessBreak {
+            address,
+            len,
+            read,
+            write,
+        };
+
+        assert_eq!(
+            "0x80000000+0x1000:w".parse(),
+            Ok(region(0x8000_0000, 0x1000, false, true))
+        );
+        assert_eq!(
+            "0x8000_0100+16:r".parse(),
+            Ok(region(0x8000_0100, 16, true, false))
+        );
+        assert_eq!("4096+4".parse(), Ok(region(4096, 4, true, true)));
+        assert!("0x80000000:w".parse::<AccessBreak>().is_err());
+        assert!("0x80000000+0".parse::<AccessBreak>().is_err());
+        assert!("0xfffffff0+0x20".parse::<AccessBreak>().is_err());
+        assert!("0x80000000+4:x".parse::<AccessBreak>().is_err());
+    }
+
+    #[test]
+    fn ram_base_from_str() {
+        assert_eq!("0x100000".parse(), Ok(RamBase::Fixed(0x10_0000)));
+        assert_eq!("random".parse(), Ok(RamBase::Random(None)));
+        assert_eq!("random:42".parse(), Ok(RamBase::Random(Some(42))));
+        assert!("random:x".parse::<RamBase>().is_err());
+        assert!("ram".parse::<RamBase>().is_err());
+    }
+
+    #[test]
+    fn alias_region_from_str() {
+        let alias = |address, len, target, stride| {
+            Ok(AliasRegion {
+                address,
+                len,
+                target,
+                stride,
+            })
+        };
+
+        assert_eq!(
+            "0x1000000+0x100000=0x0".parse(),
+            alias(0x100_0000, 0x10_0000, 0, None)
+        );
+        assert_eq!(
+            "0x100000+0x300000=0x0%0x100000".parse(),
+            alias(0x10_0000, 0x30_0000, 0, Some(0x10_0000))
+        );
+        assert!("0x100000+0x1000".parse::<AliasRegion>().is_err());
+        assert!("0x100000+0x1000=0x0%0".parse::<AliasRegion>().is_err());
+        assert!("0x100000=0x0".parse::<AliasRegion>().is_err());
+    }
+
+    #[test]
+    fn code_patch_from_str() {
+        let patch = |address, value| Ok(CodePatch { address, value });
+
+        assert_eq!("0xbfc00120=0x00000000".parse(), patch(0xbfc0_0120, 0));
+        assert_eq!(
+            "0xbfc00120=0x03e00008".parse(),
+            patch(0xbfc0_0120, 0x03e0_0008)
+        );
+        assert!("0xbfc00122=0".parse::<CodePatch>().is_err());
+        assert!("0xbfc00120".parse::<CodePatch>().is_err());
+        assert!("0xbfc00120=nop".parse::<CodePatch>().is_err());
+    }
+
+    #[test]
+    fn stop_at_from_str() {
+        assert_eq!("pc=0x80001234".parse(), Ok(StopAt::Pc(0x8000_1234)));
+        assert_eq!("pc=4096".parse(), Ok(StopAt::Pc(4096)));
+        assert!("pc=0x100000000".parse::<StopAt>().is_err());
+        assert!("sp=0x80001234".parse::<StopAt>().is_err());
+        assert!("0x80001234".parse::<StopAt>().is_err());
+    }
+
+    #[test]
+    fn rtos_layout_from_str() {
+        assert_eq!(
+            "tcbs=0x80001000,current=0x80001004,next=0x4c,name=0x34".parse(),
+            Ok(RtosLayout {
+                tcbs: 0x8000_1000,
+                current: 0x8000_1004,
+                next: 0x4c,
+                context: 0,
+                name: Some(0x34),
+            })
+        );
+        assert!("tcbs=0x80001000,next=4".parse::<RtosLayout>().is_err());
+        assert!("tcbs=0x80001000,current=0x80001004,next=6"
+            .parse::<RtosLayout>()
+            .is_err());
+        assert!("tcbs=0x80001000,current=0x80001004,next=4,prio=8"
+            .parse::<RtosLayout>()
+            .is_err());
+    }
+
+    #[test]
+    fn env_var_from_str() {
+        let var = |name: &str, value: &str| Ok((name.to_owned(), value.to_owned()));
+
+        assert_eq!(parse_env_var("console=ttyS0"), var("console", "ttyS0"));
+        assert_eq!(parse_env_var("root="), var("root", ""));
+        assert_eq!(parse_env_var("a=b=c"), var("a", "b=c"));
+        assert!(parse_env_var("=value").is_err());
+        assert!(parse_env_var("novalue").is_err());
+    }
+}
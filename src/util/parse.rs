@@ -0,0 +1,146 @@
+//! The small subset of TOML that machine files, register maps, input scripts and debug sessions
+//! are written in.
+//!
+//! Each line is a `[table]` or `[[table]]` header or a `key = value` pair, and `#` starts a
+//! comment outside of a string. Values are integers in decimal or hexadecimal with `_`
+//! separators, basic strings, booleans and single-line arrays, which each file format reads with
+//! the functions of this module.
+
+use std::convert::TryFrom;
+
+/// Removes a trailing comment, keeping `#` characters inside a string.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Returns the lines of `text` that are not blank once their comment is removed, trimmed and
+/// numbered from 1 for error messages.
+pub(crate) fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, strip_comment(line).trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+/// Splits a `key = value` line into its trimmed key and value.
+pub(crate) fn key_value(line: &str) -> Option<(&str, &str)> {
+    line.split_once('=')
+        .map(|(key, value)| (key.trim(), value.trim()))
+}
+
+/// Parses a decimal integer or a hexadecimal one prefixed with `0x`, which may contain `_`
+/// separators, as any unsigned integer type it fits.
+pub(crate) fn parse_integer<T: TryFrom<u64>>(value: &str) -> Result<T, String> {
+    let digits = value.replace('_', "");
+    match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .ok()
+    .and_then(|integer| T::try_from(integer).ok())
+    .ok_or_else(|| format!("invalid integer: {}", value))
+}
+
+/// Parses `true` or `false`.
+pub(crate) fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("expected true or false: {}", value)),
+    }
+}
+
+/// Parses a TOML basic string, including the quotes.
+pub(crate) fn parse_string(value: &str) -> Result<Vec<u8>, String> {
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a quoted string, found {}", value))?;
+
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            return Err(format!("unescaped quote in {}", value));
+        } else if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('"') => bytes.push(b'"'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("invalid escape \\x{} in {}", hex, value))?;
+                bytes.push(byte);
+            }
+            _ => return Err(format!("invalid escape in {}", value)),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Parses a basic string that must be valid UTF-8, such as a file name.
+pub(crate) fn parse_text(value: &str) -> Result<String, String> {
+    String::from_utf8(parse_string(value)?).map_err(|_| format!("invalid UTF-8 in {}", value))
+}
+
+/// Returns the items of a single-line array. Items are split at every comma, so strings in the
+/// array cannot contain one.
+pub(crate) fn parse_array(value: &str) -> Result<impl Iterator<Item = &str>, String> {
+    let items = value
+        .strip_prefix('[')
+        .and_then(|items| items.strip_suffix(']'))
+        .ok_or_else(|| format!("expected an array: {}", value))?;
+    Ok(items
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn config_lines() {
+        let text = "# comment\n\n[UART]   # table\nname = \"a # b\" # comment\n";
+        let lines: Vec<_> = lines(text).collect();
+        assert_eq!(lines, vec![(3, "[UART]"), (4, "name = \"a # b\"")]);
+        assert_eq!(key_value(lines[1].1), Some(("name", "\"a # b\"")));
+        assert_eq!(parse_string("\"a # b\""), Ok(b"a # b".to_vec()));
+
+        // An escaped quote does not end the string
+        assert_eq!(strip_comment(r##"s = "\"#" # c"##), r##"s = "\"#" "##);
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(parse_integer::<u32>("0x1f00_0900"), Ok(0x1f00_0900));
+        assert_eq!(parse_integer::<u64>("1_000"), Ok(1000));
+        assert_eq!(parse_integer::<u8>("255"), Ok(255));
+        assert!(parse_integer::<u8>("256").is_err());
+        assert!(parse_integer::<u32>("0x1_0000_0000").is_err());
+        assert!(parse_integer::<u32>("-1").is_err());
+        assert!(parse_integer::<u32>("").is_err());
+    }
+}
@@ -0,0 +1,22 @@
+//! A small seeded pseudo-random generator, so randomized runs can be reproduced from their seed.
+
+/// Xorshift64* generator.
+#[derive(Clone, Debug)]
+pub(crate) struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // Xorshift must not start from zero
+        Self { state: seed.max(1) }
+    }
+
+    /// Returns the next pseudo-random number.
+    pub fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
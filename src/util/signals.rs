@@ -0,0 +1,45 @@
+//! Clean shutdown on SIGINT and SIGTERM.
+//!
+//! The handlers only record the signal, and the run loop stops at the next instruction
+//! boundary so that the usual halt output and dumps are still written. A second signal that
+//! arrives before the first one was handled terminates the process at once, in case the
+//! emulator is blocked outside of the run loop, e.g. waiting for a debugger to connect.
+
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// The first signal received, or 0 if there was none.
+static PENDING: AtomicI32 = AtomicI32::new(0);
+
+#[cfg(unix)]
+extern "C" fn handle(signal: libc::c_int) {
+    if PENDING.swap(signal, Ordering::SeqCst) != 0 {
+        // SAFETY: _exit is async-signal-safe
+        unsafe { libc::_exit(128 + signal) };
+    }
+}
+
+/// Installs the SIGINT and SIGTERM handlers.
+#[cfg(unix)]
+pub fn install() {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: The handler only touches an atomic and calls async-signal-safe functions
+        unsafe {
+            libc::signal(
+                signal,
+                handle as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+    }
+}
+
+/// Installs the SIGINT and SIGTERM handlers.
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// Returns the signal that requested a shutdown, if any.
+pub fn pending() -> Option<i32> {
+    match PENDING.load(Ordering::Relaxed) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
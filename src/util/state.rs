@@ -0,0 +1,145 @@
+//! Little-endian encoding of the machine state in snapshot files.
+//!
+//! Every part of the machine writes its state as a sequence of words and length-prefixed byte
+//! strings and reads it back in the same order. The encoding carries no field names, so the
+//! layout of a part only changes together with `SNAPSHOT_VERSION` in `snapshot.rs`.
+
+use std::convert::TryInto;
+
+use crate::util::error::{Result, RmipsError};
+
+/// Collects the state of the machine.
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn word(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.word(value as u32);
+    }
+
+    /// Writes `data` prefixed with its length.
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.word(data.len() as u32);
+        self.bytes.extend_from_slice(data);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads the state of the machine in the order it was written.
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(RmipsError::Snapshot("the file is truncated".to_owned()));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn word(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn bool(&mut self) -> Result<bool> {
+        Ok(self.word()? != 0)
+    }
+
+    /// Reads a byte string written by `StateWriter::bytes`.
+    pub fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.word()? as usize;
+        self.take(len)
+    }
+
+    /// Reads a byte string of exactly `len` bytes.
+    pub fn bytes_of_len(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.bytes()?;
+        match bytes.len() == len {
+            true => Ok(bytes),
+            false => Err(RmipsError::Snapshot(format!(
+                "expected {} bytes of state, found {}",
+                len,
+                bytes.len()
+            ))),
+        }
+    }
+
+    /// Checks that all of the state was read.
+    pub fn finish(self) -> Result<()> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            len => Err(RmipsError::Snapshot(format!(
+                "{} bytes of state were not read",
+                len
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn state_round_trip() -> Result<()> {
+        let mut writer = StateWriter::new();
+        writer.word(0xbfc0_0000);
+        writer.u64(1 << 40);
+        writer.bool(true);
+        writer.bytes(&[1, 2, 3]);
+        let bytes = writer.into_bytes();
+
+        let mut reader = StateReader::new(&bytes);
+        assert_eq!(reader.word()?, 0xbfc0_0000);
+        assert_eq!(reader.u64()?, 1 << 40);
+        assert!(reader.bool()?);
+        assert_eq!(reader.bytes()?, &[1, 2, 3]);
+        reader.finish()?;
+
+        let mut reader = StateReader::new(&bytes[..6]);
+        reader.word()?;
+        assert_eq!(
+            reader.word().unwrap_err().to_string(),
+            "Invalid snapshot: the file is truncated"
+        );
+        let mut reader = StateReader::new(&bytes);
+        reader.word()?;
+        assert_eq!(
+            reader.finish().unwrap_err().to_string(),
+            "Invalid snapshot: 19 bytes of state were not read"
+        );
+        Ok(())
+    }
+}
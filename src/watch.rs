@@ -0,0 +1,351 @@
+//! Watch expressions that stop emulation when a condition on the machine state becomes true.
+//!
+//! Expressions such as `reg[a0] == 0xdeadbeef` or `word[0x8000_1234] != 0 && pc == 0xbfc00100`
+//! are parsed once into a `WatchExpr`, so evaluating them after every step only reads the
+//! operands and compares them. Device registers named by a register map, as in
+//! `mmio[UART.LSR.THRE] == 1`, are resolved to their physical address before the run.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::control::cpu::Cpu;
+use crate::control::registers::REGISTER_NAMES;
+use crate::memory::bus::Bus;
+use crate::regmap::RegisterMap;
+use crate::util::parse::parse_integer;
+use crate::Address;
+
+/// A value read from the machine state or a constant.
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Constant(u32),
+    Register(usize),
+    Pc,
+    Hi,
+    Lo,
+    /// `len` bytes of memory at a virtual address.
+    Memory(Address, usize),
+    /// A device register or bit field named by the register map, which is not resolved yet.
+    Named(String),
+    /// `len` bytes of a device register at a physical address, or the `width` bits of one of
+    /// its fields starting at bit `lsb`.
+    Device {
+        address: Address,
+        len: usize,
+        lsb: u32,
+        width: u32,
+    },
+}
+
+impl Operand {
+    /// Reads the value of the operand, or `None` if the memory is not mapped.
+    fn value(&self, cpu: &Cpu, bus: &Bus) -> Option<u32> {
+        Some(match *self {
+            Operand::Constant(value) => value,
+            Operand::Register(index) => cpu.reg[index],
+            Operand::Pc => cpu.pc,
+            Operand::Hi => cpu.high,
+            Operand::Lo => cpu.low,
+            Operand::Memory(vaddress, len) => {
+                let mut data = [0; 4];
+                bus.peek(cpu.cpzero.translate(vaddress)?, &mut data[..len])
+                    .ok()?;
+                bus.endian().value(&data[..len])
+            }
+            Operand::Named(_) => return None,
+            Operand::Device {
+                address,
+                len,
+                lsb,
+                width,
+            } => {
+                let mut data = [0; 4];
+                bus.peek(address, &mut data[..len]).ok()?;
+                (bus.endian().value(&data[..len]) >> lsb) & (u32::MAX >> (32 - width))
+            }
+        })
+    }
+
+    /// Replaces a named register by its physical address in `map`.
+    fn resolve(&mut self, map: &RegisterMap) -> Result<(), String> {
+        let Operand::Named(name) = self else {
+            return Ok(());
+        };
+        let register = map
+            .resolve(name)
+            .ok_or_else(|| format!("unknown register {}", name))?;
+        let (lsb, width) = match register.field {
+            Some(field) => (field.lsb, field.width),
+            None => (0, 8 * register.register.size as u32),
+        };
+        *self = Operand::Device {
+            address: register.address,
+            len: register.register.size,
+            lsb,
+            width,
+        };
+        Ok(())
+    }
+}
+
+impl FromStr for Operand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s {
+            "pc" => return Ok(Operand::Pc),
+            "hi" => return Ok(Operand::Hi),
+            "lo" => return Ok(Operand::Lo),
+            _ => {}
+        }
+
+        if let Some((kind, index)) = s.strip_suffix(']').and_then(|s| s.split_once('[')) {
+            let index = index.trim();
+            return match kind.trim() {
+                "reg" => parse_register(index).map(Operand::Register),
+                "word" => parse_integer(index).map(|address| Operand::Memory(address, 4)),
+                "half" => parse_integer(index).map(|address| Operand::Memory(address, 2)),
+                "byte" => parse_integer(index).map(|address| Operand::Memory(address, 1)),
+                "mmio" if !index.is_empty() => Ok(Operand::Named(index.to_owned())),
+                _ => Err(format!("unknown operand {}", s)),
+            };
+        }
+
+        parse_integer(s).map(Operand::Constant)
+    }
+}
+
+/// Parses a register given by ABI name or number, with an optional `$` prefix.
+fn parse_register(s: &str) -> Result<usize, String> {
+    let name = s.strip_prefix('$').unwrap_or(s);
+    match REGISTER_NAMES.iter().position(|r| *r == name) {
+        Some(index) => Ok(index),
+        None => match name.parse() {
+            Ok(index @ 0..=31) => Ok(index),
+            _ => Err(format!("invalid register {}", s)),
+        },
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    /// Operators ordered so that no operator is matched by a prefix of another.
+    const OPERATORS: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        ("<=", Comparison::Le),
+        (">=", Comparison::Ge),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+    ];
+
+    fn apply(self, lhs: u32, rhs: u32) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// A single `lhs <op> rhs` comparison of unsigned values.
+#[derive(Clone, Debug, PartialEq)]
+struct Condition {
+    lhs: Operand,
+    comparison: Comparison,
+    rhs: Operand,
+}
+
+impl Condition {
+    fn eval(&self, cpu: &Cpu, bus: &Bus) -> bool {
+        match (self.lhs.value(cpu, bus), self.rhs.value(cpu, bus)) {
+            (Some(lhs), Some(rhs)) => self.comparison.apply(lhs, rhs),
+            _ => false,
+        }
+    }
+
+    fn resolve(&mut self, map: &RegisterMap) -> Result<(), String> {
+        self.lhs.resolve(map)?;
+        self.rhs.resolve(map)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        for (operator, comparison) in Comparison::OPERATORS.iter() {
+            if let Some((lhs, rhs)) = s.split_once(operator) {
+                return Ok(Condition {
+                    lhs: lhs.parse()?,
+                    comparison: *comparison,
+                    rhs: rhs.parse()?,
+                });
+            }
+        }
+
+        Err(format!("expected a comparison: {}", s.trim()))
+    }
+}
+
+/// A compiled watch expression: conditions joined with `&&`, and alternatives joined with `||`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchExpr {
+    source: String,
+    alternatives: Vec<Vec<Condition>>,
+    /// Result of the last evaluation, so the expression only fires when it becomes true.
+    triggered: bool,
+}
+
+impl WatchExpr {
+    /// Returns true if the expression holds for the current machine state.
+    pub(crate) fn eval(&self, cpu: &Cpu, bus: &Bus) -> bool {
+        self.alternatives
+            .iter()
+            .any(|conditions| conditions.iter().all(|cond| cond.eval(cpu, bus)))
+    }
+
+    /// Evaluates the expression and returns true if it changed from false to true.
+    pub(crate) fn became_true(&mut self, cpu: &Cpu, bus: &Bus) -> bool {
+        let value = self.eval(cpu, bus);
+        let rising = value && !self.triggered;
+        self.triggered = value;
+        rising
+    }
+
+    /// Resolves the `mmio[...]` operands with the register map. Until then they never hold.
+    pub fn resolve(&mut self, map: &RegisterMap) -> Result<(), String> {
+        self.alternatives
+            .iter_mut()
+            .flatten()
+            .try_for_each(|condition| condition.resolve(map))
+    }
+}
+
+impl FromStr for WatchExpr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let alternatives = s
+            .split("||")
+            .map(|alternative| alternative.split("&&").map(str::parse).collect())
+            .collect::<Result<_, _>>()?;
+
+        Ok(WatchExpr {
+            source: s.trim().to_owned(),
+            alternatives,
+            triggered: false,
+        })
+    }
+}
+
+impl fmt::Display for WatchExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::registers::Register;
+    use crate::memory::ram::Ram;
+    use crate::memory::AccessContext;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn watch_expr_from_str() {
+        let expr: WatchExpr = "reg[a0] == 0xdeadbeef".parse().unwrap();
+        assert_eq!(
+            expr.alternatives,
+            vec![vec![Condition {
+                lhs: Operand::Register(4),
+                comparison: Comparison::Eq,
+                rhs: Operand::Constant(0xdead_beef),
+            }]]
+        );
+
+        let expr: WatchExpr = "word[0x8000_1234] != 0 && pc >= 0xbfc00100 || byte[16] < 2"
+            .parse()
+            .unwrap();
+        assert_eq!(expr.alternatives.len(), 2);
+        assert_eq!(expr.alternatives[0][0].lhs, Operand::Memory(0x8000_1234, 4));
+        assert_eq!(expr.alternatives[0][1].comparison, Comparison::Ge);
+        assert_eq!(expr.alternatives[1][0].lhs, Operand::Memory(16, 1));
+
+        assert!("reg[$t9] == reg[31]".parse::<WatchExpr>().is_ok());
+        assert!("reg[t10] == 0".parse::<WatchExpr>().is_err());
+        assert!("quad[0] == 0".parse::<WatchExpr>().is_err());
+        assert!("pc".parse::<WatchExpr>().is_err());
+    }
+
+    #[test]
+    fn watch_expr_became_true() {
+        let mut cpu = Cpu::new(false);
+        let mut bus = Bus::new();
+        bus.register(Box::new(Ram::new(0x1000)), 0, 0x1000).unwrap();
+
+        let mut expr: WatchExpr = "reg[v0] == 7 && half[0x80000010] != 0".parse().unwrap();
+        cpu.reg[Register::V0] = 7;
+        assert!(!expr.became_true(&cpu, &bus));
+
+        bus.write(0x10, &[1, 0], AccessContext::Debugger).unwrap();
+        assert!(expr.became_true(&cpu, &bus));
+        assert!(!expr.became_true(&cpu, &bus));
+
+        cpu.reg[Register::V0] = 0;
+        assert!(!expr.became_true(&cpu, &bus));
+        cpu.reg[Register::V0] = 7;
+        assert!(expr.became_true(&cpu, &bus));
+    }
+
+    #[test]
+    fn watch_expr_resolve() {
+        let mut bus = Bus::new();
+        bus.register(Box::new(Ram::new(0x1000)), 0, 0x1000).unwrap();
+        let cpu = Cpu::new(false);
+        let map = RegisterMap::parse(
+            "[UART]\nbase = 0x100\n[UART.LSR]\noffset = 0x14\nsize = 1\nTHRE = 5\n",
+        )
+        .unwrap();
+
+        let mut expr: WatchExpr = "mmio[UART.LSR.THRE] == 1".parse().unwrap();
+        bus.write(0x114, &[0x20], AccessContext::Debugger).unwrap();
+        assert!(!expr.eval(&cpu, &bus));
+        expr.resolve(&map).unwrap();
+        assert_eq!(
+            expr.alternatives[0][0].lhs,
+            Operand::Device {
+                address: 0x114,
+                len: 1,
+                lsb: 5,
+                width: 1
+            }
+        );
+        assert!(expr.eval(&cpu, &bus));
+
+        let mut expr: WatchExpr = "mmio[UART.LSR] == 0x20".parse().unwrap();
+        expr.resolve(&map).unwrap();
+        assert!(expr.eval(&cpu, &bus));
+
+        let mut expr: WatchExpr = "mmio[UART.IER] == 0".parse().unwrap();
+        assert_eq!(
+            expr.resolve(&map),
+            Err("unknown register UART.IER".to_owned())
+        );
+        assert!("mmio[] == 0".parse::<WatchExpr>().is_err());
+    }
+}
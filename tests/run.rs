@@ -1,16 +1,76 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use gdbstub::target::ext::base::singlethread::SingleThreadBase;
+use gdbstub::target::TargetError;
 use pretty_assertions::assert_eq;
 
-use rmips::emulator::Emulator;
+use rmips::coverage::InstructionCoverage;
+use rmips::determinism;
+use rmips::emulator::{Emulator, SliceBudget, SliceOutcome, SLICE_CLOCK_INTERVAL};
 use rmips::registers::Register;
-use rmips::util::error::Result;
-use rmips::util::opts::Opts;
+use rmips::shadow_stack::ShadowStackMode;
+use rmips::snapshot;
+use rmips::util::error::{Result, RmipsError};
+use rmips::util::opts::{AccessBreak, CodePatch, Opts, RamImage, StopAt};
+use rmips::{AccessKind, EmulationEvent, Exception, FaultKind, HaltReason};
+
+/// A file in the temporary directory that is deleted when it is dropped, also when the test
+/// fails.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    /// Returns a path ending in `name` that no other test of the process uses. The file is
+    /// created by the test.
+    fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        let name = format!("rmips-{}-{}-{}", std::process::id(), id, name);
+        TempFile(std::env::temp_dir().join(name))
+    }
+}
+
+impl Deref for TempFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // The test may not have created the file, or removed it itself
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Maps the first page of kuseg to physical address 0, for test ROMs that use absolute
+/// addresses off `$zero` without setting up the TLB themselves.
+fn map_zero_page(emulator: &mut Emulator) {
+    let cpzero = &mut emulator.cpu.cpzero;
+    cpzero.write_index(0);
+    cpzero.entryhi = 0;
+    cpzero.entrylo = 0x700; // Dirty, valid and global
+    cpzero.tlbwi_emulate();
+}
 
 #[ignore]
 #[test]
 fn arithmetic_program() -> Result<()> {
-    let mut opts = Opts::default();
-    opts.romfile = String::from("./tests/build/arithmetic.rom");
-    opts.instrdump = true;
+    let opts = Opts {
+        romfile: String::from("./tests/build/arithmetic.rom"),
+        instrdump: true,
+        ..Default::default()
+    };
 
     let mut emulator = Emulator::new(opts)?;
     let result = emulator.run();
@@ -33,9 +93,11 @@ fn arithmetic_program() -> Result<()> {
 
 #[test]
 fn bitwise_program() -> Result<()> {
-    let mut opts = Opts::default();
-    opts.romfile = String::from("./tests/build/bitwise.rom");
-    opts.instrdump = true;
+    let opts = Opts {
+        romfile: String::from("./tests/build/bitwise.rom"),
+        instrdump: true,
+        ..Default::default()
+    };
 
     let mut emulator = Emulator::new(opts)?;
     let result = emulator.run();
@@ -59,9 +121,11 @@ fn bitwise_program() -> Result<()> {
 
 #[test]
 fn branch_program() -> Result<()> {
-    let mut opts = Opts::default();
-    opts.romfile = String::from("./tests/build/branch.rom");
-    opts.instrdump = true;
+    let opts = Opts {
+        romfile: String::from("./tests/build/branch.rom"),
+        instrdump: true,
+        ..Default::default()
+    };
 
     let mut emulator = Emulator::new(opts)?;
     let result = emulator.run();
@@ -73,9 +137,11 @@ fn branch_program() -> Result<()> {
 
 #[test]
 fn logic_program() -> Result<()> {
-    let mut opts = Opts::default();
-    opts.romfile = String::from("./tests/build/logic.rom");
-    opts.instrdump = true;
+    let opts = Opts {
+        romfile: String::from("./tests/build/logic.rom"),
+        instrdump: true,
+        ..Default::default()
+    };
 
     let mut emulator = Emulator::new(opts)?;
     let result = emulator.run();
@@ -91,11 +157,14 @@ fn logic_program() -> Result<()> {
 
 #[test]
 fn memory_program() -> Result<()> {
-    let mut opts = Opts::default();
-    opts.romfile = String::from("./tests/build/memory.rom");
-    opts.instrdump = true;
+    let opts = Opts {
+        romfile: String::from("./tests/build/memory.rom"),
+        instrdump: true,
+        ..Default::default()
+    };
 
     let mut emulator = Emulator::new(opts)?;
+    map_zero_page(&mut emulator);
     let result = emulator.run();
     assert_eq!(result.is_ok(), true);
 
@@ -118,3 +187,2432 @@ fn memory_program() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn unaligned_stores() -> Result<()> {
+    // The unaligned store sequence that compilers emit for packed struct copies
+    let source = r#"
+            li    $t0, 0x80000100
+            li    $t1, 0x11223344
+            sw    $zero, 0($t0)
+            sw    $zero, 4($t0)
+            swl   $t1, 4($t0)
+            swr   $t1, 1($t0)
+            lw    $s0, 0($t0)
+            lw    $s1, 4($t0)
+
+            li    $t2, -1
+            sw    $t2, 8($t0)
+            swl   $t1, 9($t0)
+            swr   $t1, 10($t0)
+            lw    $s2, 8($t0)
+
+            lwl   $s3, 4($t0)
+            lwr   $s3, 1($t0)
+            break
+    "#;
+
+    let path = TempFile::new("unaligned.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0x2233_4400);
+    assert_eq!(emulator.cpu.reg[Register::S1], 0x0000_0011);
+    // swl stores the upper half below byte 10, swr the lower half from byte 10
+    assert_eq!(emulator.cpu.reg[Register::S2], 0x3344_1122);
+    // lwl and lwr load the word stored by the first pair back
+    assert_eq!(emulator.cpu.reg[Register::S3], 0x1122_3344);
+    Ok(())
+}
+
+/// Reports the instructions exercised by the test ROMs, shown with `--nocapture`.
+#[test]
+fn test_rom_coverage() -> Result<()> {
+    let mut roms: Vec<_> = std::fs::read_dir("./tests/build")?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rom"))
+        .collect();
+    roms.sort();
+
+    let mut coverage = InstructionCoverage::default();
+    for rom in &roms {
+        let opts = Opts {
+            romfile: rom.to_string_lossy().into_owned(),
+            coverage: true,
+            ..Default::default()
+        };
+
+        let mut emulator = Emulator::new(opts)?;
+        map_zero_page(&mut emulator);
+        emulator.run()?;
+        coverage.merge(emulator.coverage().unwrap());
+    }
+    println!("{}", coverage);
+
+    assert_eq!(roms.len(), 5);
+    for mnemonic in &["addu", "lw", "sw", "beq", "jal"] {
+        assert!(coverage.count(mnemonic) > 0, "{} is not covered", mnemonic);
+    }
+    Ok(())
+}
+
+#[test]
+fn ram_image_and_dump() -> Result<()> {
+    let image_path = TempFile::new("ram-image.bin");
+    let dump_path = TempFile::new("ram-dump.bin");
+    std::fs::write(&image_path, b"rmips")?;
+
+    let opts = Opts {
+        romfile: String::from("./tests/build/branch.rom"),
+        memsize: 0x10000,
+        ramimage: Some(RamImage {
+            path: image_path.to_string_lossy().into_owned(),
+            offset: 0x8000,
+        }),
+        ramdump: Some(dump_path.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    emulator.run()?;
+
+    let dump = std::fs::read(&dump_path)?;
+    assert_eq!(dump.len(), 0x10000);
+    assert_eq!(&dump[0x8000..0x8005], b"rmips");
+    Ok(())
+}
+
+#[test]
+fn monitor_prom_callbacks() -> Result<()> {
+    #[rustfmt::skip]
+    let program: [u32; 16] = [
+        0x3c04bfc0, // lui   a0, 0xbfc0
+        0x34840040, // ori   a0, a0, 0x40
+        0x3c08bfd0, // lui   t0, 0xbfd0
+        0x8d080004, // lw    t0, 4(t0)      getenv
+        0x00000000, // nop
+        0x0100f809, // jalr  t0
+        0x00000000, // nop
+        0x00408025, // move  s0, v0
+        0x3c08bfd0, // lui   t0, 0xbfd0
+        0x8d080008, // lw    t0, 8(t0)      exit
+        0x34040003, // li    a0, 3
+        0x0100f809, // jalr  t0
+        0x00000000, // nop
+        0x1000ffff, // b     .
+        0x00000000, // nop
+        0x00000000,
+    ];
+    let mut rom: Vec<u8> = program.iter().flat_map(|w| w.to_le_bytes()).collect();
+    rom.extend_from_slice(b"console\0");
+
+    let rom_path = TempFile::new("prom.rom");
+    std::fs::write(&rom_path, &rom)?;
+
+    let opts = Opts {
+        romfile: rom_path.to_string_lossy().into_owned(),
+        monitorprom: true,
+        promenv: vec![
+            (String::from("root"), String::from("/dev/sda1")),
+            (String::from("console"), String::from("ttyS0")),
+        ],
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Exit(3));
+    assert_eq!(summary.exit_code, Some(3));
+
+    // "console=" follows "root=/dev/sda1\0" at the start of the environment
+    assert_eq!(emulator.cpu.reg[Register::S0], 0xbfd0_0100 + 15 + 8);
+    assert_eq!(emulator.cpu.reg[Register::A0], 3);
+    Ok(())
+}
+
+#[test]
+fn assembly_source_program() -> Result<()> {
+    let source = r#"
+        # Sum the table and store the result
+            .text
+        start:
+            la    $t0, table
+            lw    $t1, count
+            move  $v0, $zero
+        loop:
+            lw    $t2, 0($t0)
+            addiu $t0, $t0, 4
+            addu  $v0, $v0, $t2
+            addiu $t1, $t1, -1
+            bgtz  $t1, loop
+            nop
+            sw    $v0, result
+            lw    $s0, result
+            break
+
+            .data
+        count:  .word 4
+        table:  .word 1, 2, 3, 0x100
+        result: .space 4
+    "#;
+
+    let path = TempFile::new("program.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(summary.instructions, 33);
+    assert_eq!(summary.cycles, summary.instructions);
+    assert_eq!(summary.exit_code, None);
+
+    assert_eq!(emulator.cpu.reg[Register::V0], 0x106);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0x106);
+    Ok(())
+}
+
+#[test]
+fn break_on_first_write() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80000100
+            lw    $t1, 0($t0)
+            li    $t2, 42
+            sw    $t2, 4($t0)
+            li    $s0, 1
+            break
+    "#;
+
+    let path = TempFile::new("break.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        breakonaccess: vec!["0x80000100+0x10:w".parse::<AccessBreak>().unwrap()],
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    emulator.run()?;
+
+    // The load is ignored and emulation stops right after the store
+    assert_eq!(emulator.cpu.reg[Register::T2], 42);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0);
+    Ok(())
+}
+
+#[test]
+fn watch_expression_stops_emulation() -> Result<()> {
+    let source = r#"
+            move  $t0, $zero
+        loop:
+            addiu $t0, $t0, 1
+            b     loop
+            nop
+    "#;
+
+    let path = TempFile::new("watch.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let index = emulator.add_watch("reg[t0] == 3".parse().unwrap());
+    loop {
+        if emulator.step()? == EmulationEvent::WatchExpression(index) {
+            break;
+        }
+    }
+    assert_eq!(emulator.cpu.reg[Register::T0], 3);
+    Ok(())
+}
+
+#[test]
+fn step_events() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80001000
+            li    $t1, 0x1234
+            sw    $t1, 0($t0)
+            syscall
+            .align 8
+            .space 0x80
+        handler:
+            b     handler
+            nop
+    "#;
+
+    let path = TempFile::new("events.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        breakonaccess: vec!["0x80001000+4:w".parse().unwrap()],
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let mut events = Vec::new();
+    while events.len() < 3 {
+        match emulator.step()? {
+            EmulationEvent::Step => {}
+            event => events.push(event),
+        }
+    }
+    assert_eq!(
+        events,
+        vec![
+            EmulationEvent::Watch {
+                kind: AccessKind::Write,
+                address: 0x1000,
+                value: 0x1234,
+            },
+            EmulationEvent::Exception {
+                code: Exception::Syscall,
+                epc: 0xbfc00010,
+            },
+            EmulationEvent::IdleLoop { pc: 0xbfc00180 },
+        ]
+    );
+    assert!(events[0].is_stop());
+    assert!(!events[1].is_stop());
+    Ok(())
+}
+
+#[test]
+fn shadow_stack_detects_corrupted_return() -> Result<()> {
+    let source = r#"
+            li    $sp, 0x80001000
+            jal   smash
+            nop
+            break
+
+        # Saves the return address and then overwrites it before returning
+        smash:
+            addiu $sp, $sp, -8
+            sw    $ra, 4($sp)
+            li    $t0, 0xbfc00100
+            sw    $t0, 4($sp)
+            lw    $ra, 4($sp)
+            addiu $sp, $sp, 8
+            jr    $ra
+            nop
+    "#;
+
+    let path = TempFile::new("shadow.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        shadowstack: Some(ShadowStackMode::Stop),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    match emulator.run() {
+        Err(RmipsError::ShadowStack(mismatch)) => {
+            assert_eq!(mismatch.target, 0xbfc0_0100);
+            assert_eq!(mismatch.expected, 0xbfc0_0010);
+        }
+        result => panic!("expected a shadow stack error, got {:?}", result),
+    }
+    Ok(())
+}
+
+#[test]
+fn heap_checker_reports_misuse() -> Result<()> {
+    let source = r#"
+            li    $s0, 0x80010000       # next free heap address
+            li    $a0, 16
+            jal   malloc
+            nop
+            move  $s1, $v0
+            li    $a0, 8
+            jal   malloc
+            nop
+            move  $a0, $s1
+            jal   free
+            nop
+            lw    $t0, 4($s1)           # use after free
+            move  $a0, $s1
+            jal   free                  # double free
+            nop
+            break
+
+        # Bump allocator that never reuses memory
+        malloc:
+            move  $v0, $s0
+            addu  $s0, $s0, $a0
+            jr    $ra
+            nop
+        free:
+            jr    $ra
+            nop
+    "#;
+
+    let path = TempFile::new("heap.s");
+    std::fs::write(&path, source)?;
+
+    // malloc and free are after the first 16 instructions of the program
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        malloc: Some(0xbfc0_0040),
+        free: Some(0xbfc0_0050),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    emulator.run()?;
+
+    let heap = emulator.heap().unwrap();
+    let leaks: Vec<_> = heap
+        .leaks()
+        .map(|block| (block.address, block.size))
+        .collect();
+    assert_eq!(leaks, vec![(0x8001_0010, 8)]);
+    assert_eq!(heap.errors().len(), 2);
+    Ok(())
+}
+
+#[test]
+fn injected_bus_error_raises_exception() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80000000
+            lw    $t1, 0($t0)
+            li    $s0, 1
+            break
+
+            .space 0x180 - 16
+        # General exception vector while the boot exception vectors are enabled
+            mfc0  $k0, $13
+            break
+    "#;
+
+    let path = TempFile::new("fault.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        faultrate: 1_000_000,
+        faultkind: FaultKind::BusError,
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    emulator.run()?;
+
+    // Cause holds the Data Bus Error exception code
+    assert_eq!((emulator.cpu.reg[Register::K0] >> 2) & 0x1f, 7);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0);
+    Ok(())
+}
+
+#[test]
+fn timeline_records_events() -> Result<()> {
+    let source = r#"
+            li    $t0, 0xa2010000
+            sw    $zero, 0x20($t0)
+            syscall
+            break
+
+            .space 0x180 - 16
+        # General exception vector while the boot exception vectors are enabled
+            break
+    "#;
+
+    let path = TempFile::new("timeline.s");
+    let timeline = TempFile::new("timeline.json");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        timeline: Some(timeline.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    emulator.run()?;
+
+    let json = std::fs::read_to_string(&timeline)?;
+    assert!(json.contains(r#""name": "write test-device", "cat": "device""#));
+    assert!(json.contains(r#""name": "Syscall", "cat": "exception", "ph": "i", "s": "g", "ts": 2"#));
+    assert!(json.contains(r#""name": "Halt", "cat": "halt""#));
+    Ok(())
+}
+
+#[test]
+fn timeline_records_interrupts_and_snapshots() -> Result<()> {
+    let source = r#"
+            nop
+            nop
+            nop
+            nop
+            nop
+            break
+    "#;
+    let script = r#"
+        [[event]]
+        at = 1
+        irq = 3
+
+        [[event]]
+        at = 3
+        irq = 3
+        level = false
+    "#;
+
+    let path = TempFile::new("timeline-irq.s");
+    let script_path = TempFile::new("timeline-irq.toml");
+    let timeline = TempFile::new("timeline-irq.json");
+    let snapshot = TempFile::new("timeline-irq.snap");
+    std::fs::write(&path, source)?;
+    std::fs::write(&script_path, script)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        inject: Some(script_path.to_string_lossy().into_owned()),
+        timeline: Some(timeline.to_string_lossy().into_owned()),
+        savesnapshot: Some(snapshot.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+    Emulator::new(opts)?.run()?;
+
+    // Interrupts are disabled at reset, so the line changes without an exception
+    let json = std::fs::read_to_string(&timeline)?;
+    assert!(json.contains(r#""name": "Assert IP3", "cat": "irq", "ph": "i", "s": "g", "ts": 1"#));
+    assert!(json.contains(r#""source": "interrupt controller""#));
+    assert!(json.contains(r#""name": "Deassert IP3", "cat": "irq", "ph": "i", "s": "g", "ts": 3"#));
+    assert!(json.contains(r#""name": "Save snapshot", "cat": "snapshot""#));
+    Ok(())
+}
+
+#[test]
+fn network_devices_exchange_frames() -> Result<()> {
+    let sender = r#"
+            li    $t0, 0xa2030000
+            li    $t1, 0x2a
+            sb    $t1, 0x800($t0)
+            li    $t1, 1
+            sw    $t1, 8($t0)
+            break
+    "#;
+    let receiver = r#"
+            li    $t0, 0xa2030000
+            li    $t2, 100000
+        wait:
+            lw    $t1, 0($t0)
+            bne   $t1, $zero, done
+            nop
+            addiu $t2, $t2, -1
+            bgtz  $t2, wait
+            nop
+        done:
+            lbu   $s0, 0x1000($t0)
+            lw    $s1, 4($t0)
+            break
+    "#;
+
+    let sender_path = TempFile::new("net-tx.s");
+    let receiver_path = TempFile::new("net-rx.s");
+    std::fs::write(&sender_path, sender)?;
+    std::fs::write(&receiver_path, receiver)?;
+
+    // Find a free port for the receiving machine
+    let receiver_addr = std::net::UdpSocket::bind("127.0.0.1:0")?.local_addr()?;
+
+    let mut receiver = Emulator::new(Opts {
+        romfile: receiver_path.to_string_lossy().into_owned(),
+        netlisten: Some(receiver_addr),
+        ..Default::default()
+    })?;
+    let mut sender = Emulator::new(Opts {
+        romfile: sender_path.to_string_lossy().into_owned(),
+        netlisten: Some("127.0.0.1:0".parse().unwrap()),
+        netpeer: vec![receiver_addr],
+        ..Default::default()
+    })?;
+
+    sender.run()?;
+    receiver.run()?;
+    assert_eq!(receiver.cpu.reg[Register::S0], 0x2a);
+    assert_eq!(receiver.cpu.reg[Register::S1], 1);
+    Ok(())
+}
+
+#[test]
+fn memory_map_lists_devices() -> Result<()> {
+    let path = TempFile::new("memmap.s");
+    std::fs::write(&path, "break\n")?;
+
+    let emulator = Emulator::new(Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        memmap: true,
+        ..Default::default()
+    })?;
+
+    let labels: Vec<_> = emulator
+        .memory_map()
+        .into_iter()
+        .map(|entry| (entry.base, entry.label))
+        .collect();
+    assert_eq!(labels[0], (0, "RAM".to_owned()));
+    assert_eq!(labels[1], (0x0101_0024, "halt-device".to_owned()));
+    assert_eq!(labels[2], (0x0201_0000, "test-device".to_owned()));
+    assert_eq!(labels[3].0, 0x1fc0_0000);
+    Ok(())
+}
+
+#[test]
+fn describe_machine() -> Result<()> {
+    let path = TempFile::new("describe.s");
+    let script = TempFile::new("describe.toml");
+    std::fs::write(&path, "break\n")?;
+    std::fs::write(&script, "[[event]]\nat = 10\nirq = 5\n")?;
+
+    let emulator = Emulator::new(Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        cpumodel: "r4000".parse().unwrap(),
+        inject: Some(script.to_string_lossy().into_owned()),
+        ..Default::default()
+    })?;
+
+    let description = emulator.describe();
+    assert!(description.starts_with("CPU: r4000 (PRId 0x00000422) with 48 TLB entries\n"));
+    assert!(description.contains("Reset PC: 0xbfc00000\n"));
+    assert!(description.contains("  0x01010024-0x01010027        4B  halt-device\n"));
+    assert!(description.contains("Interrupt lines:\n  IP5  input script\n"));
+    assert!(description.contains("memsize: 1048576,"));
+    Ok(())
+}
+
+#[test]
+fn halt_device_base_address_override() -> Result<()> {
+    let source = r#"
+            li    $t0, 0xa1000000
+            li    $t1, 1
+            sw    $t1, 0($t0)
+            li    $s0, 1
+            break
+    "#;
+
+    let path = TempFile::new("halt-at.s");
+    std::fs::write(&path, source)?;
+
+    let mut emulator = Emulator::new(Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        haltdeviceat: Some(0x0100_0000),
+        testdeviceat: Some(0x0300_0000),
+        ..Default::default()
+    })?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::HaltDevice);
+    assert_eq!(summary.exit_code, Some(0));
+    assert_eq!(emulator.cpu.reg[Register::S0], 0);
+
+    let labels: Vec<_> = emulator
+        .memory_map()
+        .into_iter()
+        .map(|entry| (entry.base, entry.label))
+        .collect();
+    assert!(labels.contains(&(0x0100_0000, "halt-device".to_owned())));
+    assert!(labels.contains(&(0x0300_0000, "test-device".to_owned())));
+    Ok(())
+}
+
+#[test]
+fn rewind_before_stop_at() -> Result<()> {
+    let source = r#"
+            li    $t0, 0
+            li    $t1, 10
+        loop:
+            addiu $t0, $t0, 1
+            bne   $t0, $t1, loop
+            nop
+        done:
+            move  $s0, $t0
+            break
+    "#;
+
+    let path = TempFile::new("rewind.s");
+    std::fs::write(&path, source)?;
+
+    let run = |rewind| -> Result<_> {
+        let mut emulator = Emulator::new(Opts {
+            romfile: path.to_string_lossy().into_owned(),
+            stopat: Some(StopAt::Pc(0xbfc0_0014)),
+            rewind,
+            ..Default::default()
+        })?;
+        let summary = emulator.run()?;
+        Ok((summary, emulator))
+    };
+
+    let (summary, emulator) = run(None)?;
+    assert_eq!(summary.halt_reason, HaltReason::StopAt);
+    assert_eq!(summary.instructions, 32);
+    assert_eq!(emulator.cpu.pc, 0xbfc0_0014);
+    assert_eq!(emulator.cpu.reg[Register::T0], 10);
+
+    // Four instructions earlier the last iteration has not incremented the counter yet
+    let (summary, emulator) = run(Some(4))?;
+    assert_eq!(summary.halt_reason, HaltReason::StopAt);
+    assert_eq!(summary.instructions, 28);
+    assert_eq!(emulator.cpu.reg[Register::T0], 9);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0);
+
+    // The replay starts from the state saved in memory instead of setting up the machine
+    // again, so the ROM and the host files of the devices are not opened a second time
+    let nvram = TempFile::new("rewind.nvram");
+    let mut emulator = Emulator::new(Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        nvram: Some(nvram.to_string_lossy().into_owned()),
+        stopat: Some(StopAt::Pc(0xbfc0_0014)),
+        rewind: Some(4),
+        ..Default::default()
+    })?;
+    std::fs::remove_file(&path)?;
+    std::fs::remove_file(&nvram)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.instructions, 28);
+    assert_eq!(emulator.cpu.reg[Register::T0], 9);
+    assert!(!nvram.exists());
+    Ok(())
+}
+
+#[test]
+fn explore_forks_from_snapshot() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80000100
+            lw    $t1, 0($t0)
+            move  $v0, $zero
+        loop:
+            beqz  $t1, done
+            nop
+            addu  $v0, $v0, $t1
+            addiu $t1, $t1, -1
+            b     loop
+            nop
+        done:
+            sw    $v0, 4($t0)
+            break
+    "#;
+
+    let path = TempFile::new("explore.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    // Take the snapshot after the count has been loaded and let each fork replace it
+    let mut emulator = Emulator::new(Opts {
+        stopat: Some(StopAt::Pc(0xbfc0_000c)),
+        ..opts.clone()
+    })?;
+    emulator.run()?;
+    let start = emulator.snapshot();
+
+    let forks = snapshot::explore(&opts, &start, vec![3, 10, 100], |emulator, count| {
+        emulator.cpu.reg[Register::T1] = count;
+    })?;
+
+    let sums: Vec<_> = forks
+        .into_iter()
+        .map(|fork| {
+            let (summary, state) = fork?;
+            assert_eq!(summary.halt_reason, HaltReason::Break);
+
+            let mut data = [0; 4];
+            state.read(0x104, &mut data).unwrap();
+            assert_eq!(state.cpu().reg[Register::V0], u32::from_le_bytes(data));
+            Ok(u32::from_le_bytes(data))
+        })
+        .collect::<Result<_>>()?;
+    assert_eq!(sums, vec![6, 55, 5050]);
+
+    // The snapshot itself is left untouched by the forks
+    assert_eq!(start.instruction_count(), 3);
+
+    let shared = Opts {
+        nvram: Some(String::from("nvram.bin")),
+        ..opts.clone()
+    };
+    assert!(matches!(
+        snapshot::explore(&shared, &start, vec![1], |_, _: u32| {}),
+        Err(RmipsError::SharedHostState(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn patch_out_delay_loop() -> Result<()> {
+    let source = r#"
+            li    $t0, 1000000
+        delay:
+            addiu $t0, $t0, -1
+            bnez  $t0, delay
+            nop
+            li    $s0, 1
+            break
+    "#;
+
+    let path = TempFile::new("patch.s");
+    std::fs::write(&path, source)?;
+
+    let mut emulator = Emulator::new(Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    })?;
+
+    // bnez t0, delay
+    let branch = 0x1500_fffe_u32;
+    let patch = emulator.patch(0xbfc0_000c, &[0; 4])?;
+    assert_eq!(patch.original(), &branch.to_le_bytes());
+    assert_eq!(emulator.patch(0xbfc0_000c, &[0; 4])?.original(), &[0; 4]);
+
+    // Reverting restores the branch, so patching it out again finds it in place
+    emulator.revert(patch)?;
+    let patch = emulator.patch(0xbfc0_000c, &[0; 4])?;
+    assert_eq!(patch.address(), 0xbfc0_000c);
+    assert_eq!(patch.original(), &branch.to_le_bytes());
+
+    // A kuseg address without a TLB entry is reported instead of being taken as physical
+    assert!(matches!(
+        emulator.patch(0x0000_1000, &[0; 4]),
+        Err(RmipsError::NoTranslation(0x0000_1000))
+    ));
+
+    let summary = emulator.run()?;
+    assert_eq!(summary.instructions, 6);
+    assert_eq!(emulator.cpu.reg[Register::T0], 999_999);
+    assert_eq!(emulator.cpu.reg[Register::S0], 1);
+    Ok(())
+}
+
+#[test]
+fn patch_and_skip_function_options() -> Result<()> {
+    let source = r#"
+            jal   crc_check
+            nop
+            move  $s0, $v0
+            li    $t0, 1000000
+        delay:
+            addiu $t0, $t0, -1
+            bnez  $t0, delay
+            nop
+            break
+
+        crc_check:
+            b     crc_check
+            li    $v0, 1
+    "#;
+
+    let path = TempFile::new("skip.s");
+    std::fs::write(&path, source)?;
+
+    let opts = |skipfunction: &str| Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        patch: vec![CodePatch {
+            address: 0xbfc0_0018,
+            value: 0,
+        }],
+        skipfunction: vec![skipfunction.to_owned()],
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts("crc_check"))?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0);
+    assert_eq!(emulator.cpu.reg[Register::T0], 999_999);
+
+    let mut emulator = Emulator::new(opts("0xbfc00024"))?;
+    emulator.run()?;
+    assert_eq!(emulator.cpu.reg[Register::S0], 0);
+
+    assert!(matches!(
+        Emulator::new(opts("crc")),
+        Err(RmipsError::UnknownSymbol(name)) if name == "crc"
+    ));
+    Ok(())
+}
+
+#[test]
+fn debug_session_is_restored_and_saved() -> Result<()> {
+    let source = r#"
+            li    $s0, 1
+            li    $s1, 2
+            break
+    "#;
+
+    let path = TempFile::new("session.s");
+    let session = TempFile::new("session.toml");
+    std::fs::write(&path, source)?;
+    std::fs::write(
+        &session,
+        "breakpoints = [0xbfc00008]\npatches = [\"0xbfc00000=0x24100007\"]\n",
+    )?;
+
+    // The patch of the session and the one of the command line both apply
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        debugsession: Some(session.to_string_lossy().into_owned()),
+        patch: vec![CodePatch {
+            address: 0xbfc0_0004,
+            value: 0x2411_0009,
+        }],
+        ..Default::default()
+    };
+    let mut emulator = Emulator::new(opts)?;
+    emulator.run()?;
+    assert_eq!(emulator.cpu.reg[Register::S0], 7);
+    assert_eq!(emulator.cpu.reg[Register::S1], 9);
+
+    assert_eq!(
+        std::fs::read_to_string(&session)?,
+        "# rmips debug session, saved when the run ends\n\
+         breakpoints = [0xbfc00008]\n\
+         watchpoints = []\n\
+         patches = [\"0xbfc00000=0x24100007\", \"0xbfc00004=0x24110009\"]\n"
+    );
+
+    // A malformed session stops the emulator from starting
+    std::fs::write(&session, "breakpoints = main")?;
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        debugsession: Some(session.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        Emulator::new(opts),
+        Err(RmipsError::DebugSession(_, msg)) if msg == "line 1: expected an array: main"
+    ));
+    Ok(())
+}
+
+#[test]
+fn no_mmu_uses_physical_addresses() -> Result<()> {
+    let source = r#"
+            la    $t0, value
+            lw    $s0, 0($t0)
+            li    $t1, 0x100
+            sw    $s0, 0($t1)
+            lw    $s1, 0($t1)
+            break
+        value:
+            .word 0x12345678
+    "#;
+
+    let path = TempFile::new("no-mmu.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        loadaddress: 0x1000_0000,
+        nommu: true,
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(emulator.cpu.reg[Register::T0], 0x1000_001c);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0x1234_5678);
+    assert_eq!(emulator.cpu.reg[Register::S1], 0x1234_5678);
+    Ok(())
+}
+
+#[test]
+fn sparse_ram_maps_large_memory() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x93fffff0
+            li    $t1, 42
+            sw    $t1, 0($t0)
+            lw    $s0, 0($t0)
+            lw    $s1, -0x1000($t0)
+            break
+    "#;
+
+    let path = TempFile::new("sparse-ram.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        memsize: 0x1000_0000,
+        sparseram: true,
+        rambase: Some("0x04000000".parse().unwrap()),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(emulator.cpu.reg[Register::S0], 42);
+    assert_eq!(emulator.cpu.reg[Register::S1], 0);
+    Ok(())
+}
+
+#[test]
+fn byte_swapped_ram() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80000000
+            lw    $s0, 0($t0)
+            lbu   $s1, 0($t0)
+            break
+    "#;
+
+    let path = TempFile::new("byte-swap.s");
+    let image_path = TempFile::new("byte-swap.bin");
+    std::fs::write(&path, source)?;
+    std::fs::write(&image_path, [1, 2, 3, 4])?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ramimage: Some(RamImage {
+            path: image_path.to_string_lossy().into_owned(),
+            offset: 0,
+        }),
+        byteswap: vec!["0x0+0x100000".parse().unwrap()],
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0x0102_0304);
+    assert_eq!(emulator.cpu.reg[Register::S1], 4);
+    Ok(())
+}
+
+#[test]
+fn emulator_info_identifies_machine() -> Result<()> {
+    let source = r#"
+            li    $t0, 0xa20a0000
+            lw    $s0, 0x00($t0)
+            lw    $s1, 0x08($t0)
+            lw    $s2, 0x0c($t0)
+            nop
+            nop
+            lw    $s3, 0x18($t0)
+            lw    $s4, 0x1c($t0)
+            break
+    "#;
+
+    let path = TempFile::new("emulator-info.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        emulatorinfo: true,
+        monitorprom: true,
+        tlbentries: Some(16),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    emulator.run()?;
+    assert_eq!(emulator.cpu.reg[Register::S0], u32::from_le_bytes(*b"RMIP"));
+    // The FPU, MMU and monitor PROM bits
+    assert_eq!(emulator.cpu.reg[Register::S1], 0b111);
+    assert_eq!(emulator.cpu.reg[Register::S2], 16);
+    // Each instruction takes a microsecond, and six run before the time is read
+    assert_eq!(emulator.cpu.reg[Register::S3], 6);
+    assert_eq!(emulator.cpu.reg[Register::S4], 0);
+    Ok(())
+}
+
+#[test]
+fn verify_determinism_compares_runs() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80001000
+            li    $t1, 10000
+        loop:
+            sw    $t1, 0($t0)
+            addiu $t1, $t1, -1
+            bnez  $t1, loop
+            nop
+            break
+    "#;
+
+    let path = TempFile::new("determinism.s");
+    std::fs::write(&path, source)?;
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    let report = determinism::verify(&opts, 3)?;
+    assert_eq!(report.divergence, None);
+    assert_eq!(
+        report.to_string(),
+        "All 3 runs executed the same 40003 instructions and ended in the same state"
+    );
+
+    // Devices fed by the host cannot be compared
+    let opts = Opts {
+        keyboard: true,
+        ..opts
+    };
+    assert!(matches!(
+        determinism::verify(&opts, 2),
+        Err(RmipsError::HostInput("keyboard"))
+    ));
+    Ok(())
+}
+
+#[test]
+fn debug_print_strings() -> Result<()> {
+    let source = r#"
+            li    $t0, 0xa2090000
+            la    $t1, message
+            sw    $t1, 0($t0)
+            lw    $s0, 0($t0)
+            li    $t2, 0xa3000000
+            sw    $t2, 0($t0)
+            lw    $s1, 0($t0)
+
+            # 4KB of 'A' without a NUL
+            li    $t3, 0x80001000
+            li    $t4, 0x1000
+            li    $t5, 0x41
+        fill:
+            sb    $t5, 0($t3)
+            addiu $t4, $t4, -1
+            bnez  $t4, fill
+            addiu $t3, $t3, 1
+            li    $t3, 0x80001000
+            sw    $t3, 0($t0)
+            lw    $s2, 0($t0)
+            break
+        message:
+            .asciiz "Hello from the guest\n"
+    "#;
+
+    let path = TempFile::new("debug-print.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        debugprint: true,
+        ..Default::default()
+    };
+
+    // A pointer to unmapped memory or to a string without a NUL in its first 4KB is reported
+    // without stopping the emulator, and the word reads as zero
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_ne!(emulator.cpu.reg[Register::S0], 0);
+    assert_eq!(emulator.cpu.reg[Register::S1], 0);
+    assert_eq!(emulator.cpu.reg[Register::S2], 0);
+    Ok(())
+}
+
+#[test]
+fn test_device_reports_results() -> Result<()> {
+    let source = r#"
+            li    $t0, 0xa2010000
+            la    $t1, first
+            sw    $t1, 0($t0)
+            li    $t2, 1
+            sw    $t2, 8($t0)
+            la    $t1, second
+            sw    $t1, 0($t0)
+            la    $t1, message
+            sw    $t1, 4($t0)
+            sw    $zero, 8($t0)
+            la    $t1, third
+            sw    $t1, 0($t0)
+            la    $t1, reason
+            sw    $t1, 12($t0)
+            lw    $s0, 0($t0)
+            lw    $s1, 8($t0)
+            sw    $t2, 16($t0)
+            break
+        first:
+            .asciiz "adds"
+        second:
+            .asciiz "divides"
+        third:
+            .asciiz "flushes"
+        message:
+            .asciiz "remainder is 3"
+        reason:
+            .asciiz "no cache"
+    "#;
+
+    let path = TempFile::new("test-device.s");
+    let report = TempFile::new("test-device.xml");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        testreport: Some(report.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+
+    // The failed assertion fails the run, which stops at the `DONE` register
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::TestsFinished);
+    assert_eq!(summary.exit_code, Some(1));
+    assert_eq!(emulator.cpu.reg[Register::S0], 3);
+    assert_eq!(emulator.cpu.reg[Register::S1], 1);
+    assert_eq!(
+        emulator.test_report().to_string(),
+        "Tests: 1 passed, 1 failed, 1 skipped\n  divides: remainder is 3"
+    );
+
+    let junit = std::fs::read_to_string(&report)?;
+    assert!(junit.contains("<testsuite name=\"rmips\" tests=\"3\" failures=\"1\" skipped=\"1\">"));
+    assert!(junit.contains("<failure message=\"remainder is 3\"/>"));
+    Ok(())
+}
+
+#[test]
+fn rom_load_address_segments() -> Result<()> {
+    let source = r#"
+            la    $s0, here
+        here:
+            break
+    "#;
+
+    let path = TempFile::new("load-address.s");
+    std::fs::write(&path, source)?;
+    let opts = |loadaddress| Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        loadaddress,
+        ..Default::default()
+    };
+
+    // kseg0 and physical load addresses map the ROM at the same physical address as kseg1
+    for (loadaddress, here) in &[(0x9fc0_0000, 0x9fc0_0008), (0x1fc0_0000, 0xbfc0_0008)] {
+        let mut emulator = Emulator::new(opts(*loadaddress))?;
+        assert_eq!(emulator.run()?.halt_reason, HaltReason::Break);
+        assert_eq!(emulator.cpu.reg[Register::S0], *here);
+    }
+
+    assert!(matches!(
+        Emulator::new(opts(0x4000_0000)),
+        Err(RmipsError::LoadAddress(0x4000_0000))
+    ));
+    Ok(())
+}
+
+#[test]
+fn unsupported_access_width_bus_error() -> Result<()> {
+    let source = r#"
+            li    $t0, 0xa2010000
+            lb    $t1, 0($t0)
+            li    $s0, 1
+            break
+            .align 8
+            .space 0x80
+        handler:
+            mfc0  $s1, $13
+            mfc0  $s2, $14
+            break
+    "#;
+
+    let path = TempFile::new("access-width.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    // The test device only responds to word accesses, so the byte load is a Data Bus Error
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0);
+    assert_eq!(emulator.cpu.reg[Register::S1] & 0x7c, 7 << 2);
+    assert_eq!(emulator.cpu.reg[Register::S2], 0xbfc0_0004);
+    Ok(())
+}
+
+#[test]
+fn serial_link_loopback_interrupt() -> Result<()> {
+    let source = r#"
+            li    $t0, 0xa2040000
+            li    $t1, 3
+            sb    $t1, 8($t0)
+            li    $t1, 0x00400401
+            mtc0  $t1, $12
+            li    $t1, 0x41
+            sb    $t1, 0($t0)
+        interrupted:
+            nop
+            break
+            .align 8
+            .space 0x80
+        handler:
+            mfc0  $s0, $13
+            mfc0  $s1, $14
+            lbu   $s2, 0($t0)
+            lbu   $s3, 4($t0)
+            la    $s4, interrupted
+            break
+    "#;
+
+    let path = TempFile::new("serial-irq.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        seriallink: Some("tcp-listen:127.0.0.1:0".parse().unwrap()),
+        ..Default::default()
+    };
+
+    // The byte written in loopback mode raises IP2 before the next instruction
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0x400);
+    assert_eq!(
+        emulator.cpu.reg[Register::S1],
+        emulator.cpu.reg[Register::S4]
+    );
+    assert_eq!(emulator.cpu.reg[Register::S2], 0x41);
+    assert_eq!(emulator.cpu.reg[Register::S3], 0);
+    Ok(())
+}
+
+#[test]
+fn tlb_mapped_pages() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x00400000
+            mtc0  $t0, $10
+            li    $t0, 0x00001600
+            mtc0  $t0, $2
+            li    $t0, 0x500
+            mtc0  $t0, $0
+            tlbwi
+            li    $t1, 0x00400010
+            li    $t2, 0x12345678
+            sw    $t2, 0($t1)
+            li    $t3, 0x80001010
+            lw    $s0, 0($t3)
+            tlbp
+            mfc0  $s1, $0
+            lw    $s2, 0x1000($t1)
+            break
+            .align 8
+        refill:
+            mfc0  $s3, $8
+            mfc0  $s4, $13
+            mfc0  $s5, $10
+            break
+    "#;
+
+    let path = TempFile::new("tlb.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    // The store goes through the TLB entry, the load from the next page takes a refill
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0x1234_5678);
+    assert_eq!(emulator.cpu.reg[Register::S1], 0x500);
+    assert_eq!(emulator.cpu.reg[Register::S2], 0);
+    assert_eq!(emulator.cpu.reg[Register::S3], 0x0040_1010);
+    assert_eq!(emulator.cpu.reg[Register::S4] & 0x7c, 2 << 2);
+    assert_eq!(emulator.cpu.reg[Register::S5], 0x0040_1000);
+    Ok(())
+}
+
+#[test]
+fn injected_inputs_replay() -> Result<()> {
+    let source = r#"
+            li    $t0, 0xa2040000
+        wait:
+            lbu   $t1, 4($t0)
+            andi  $t1, $t1, 1
+            beqz  $t1, wait
+            nop
+            lbu   $s0, 0($t0)
+            lbu   $s1, 0($t0)
+            li    $t1, 0x00400801
+            mtc0  $t1, $12
+        spin:
+            b     spin
+            nop
+            .align 8
+            .space 0x80
+        handler:
+            mfc0  $s2, $13
+            break
+    "#;
+    let script = r#"
+        [[event]]
+        at = 40
+        serial = "hi"
+
+        [[event]]
+        time = "1ms"
+        irq = 3
+    "#;
+
+    let path = TempFile::new("inject.s");
+    let script_path = TempFile::new("inject.toml");
+    std::fs::write(&path, source)?;
+    std::fs::write(&script_path, script)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        seriallink: Some("tcp-listen:127.0.0.1:0".parse().unwrap()),
+        inject: Some(script_path.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+
+    // The scripted IRQ is taken right after the 1000th instruction on every run
+    for _ in 0..2 {
+        let mut emulator = Emulator::new(opts.clone())?;
+        let summary = emulator.run()?;
+        assert_eq!(summary.halt_reason, HaltReason::Break);
+        assert_eq!(summary.instructions, 1002);
+        assert_eq!(emulator.cpu.reg[Register::S0], b'h' as u32);
+        assert_eq!(emulator.cpu.reg[Register::S1], b'i' as u32);
+        assert_eq!(emulator.cpu.reg[Register::S2], 0x800);
+    }
+
+    // Serial input without a serial link is rejected
+    let opts = Opts {
+        seriallink: None,
+        ..opts
+    };
+    assert!(matches!(
+        Emulator::new(opts),
+        Err(RmipsError::InputScript(..))
+    ));
+    Ok(())
+}
+
+#[test]
+fn governor_throttles_run() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x20000
+        loop:
+            addiu $t0, $t0, -1
+            bnez  $t0, loop
+            nop
+            break
+    "#;
+
+    let path = TempFile::new("governor.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        cpuquota: Some(50),
+        minhostmemory: Some(1),
+        ..Default::default()
+    };
+
+    // Throttling only slows the run down, it still executes every instruction
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(summary.instructions, 1 + 3 * 0x20000);
+    Ok(())
+}
+
+#[test]
+fn clock_follows_instructions() -> Result<()> {
+    let source = r#"
+            li    $t0, 100
+        loop:
+            addiu $t0, $t0, -1
+            bnez  $t0, loop
+            nop
+            break
+    "#;
+
+    let path = TempFile::new("clock.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    // Without --host-clock the host time seen by devices is the virtual time
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    let clock = emulator.clock();
+    assert_eq!(
+        clock.virtual_time(),
+        Duration::from_micros(summary.instructions)
+    );
+    assert_eq!(clock.host_time(), clock.virtual_time());
+    Ok(())
+}
+
+#[test]
+fn ram_base_and_guard_regions() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80000ff8
+            li    $t1, 42
+            sw    $t1, 0($t0)
+            lw    $s0, 0($t0)
+            break
+    "#;
+
+    let path = TempFile::new("guard.s");
+    std::fs::write(&path, source)?;
+
+    let opts = |rambase: &str, guard: &[&str]| Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        rambase: Some(rambase.parse().unwrap()),
+        guard: guard.iter().map(|region| region.parse().unwrap()).collect(),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts("0", &[]))?;
+    emulator.run()?;
+    assert_eq!(emulator.cpu.reg[Register::S0], 42);
+
+    // Code assuming RAM at physical address zero fails at the first access
+    let mut emulator = Emulator::new(opts("random:7", &[]))?;
+    assert!(matches!(
+        emulator.run(),
+        Err(RmipsError::UnmappedAddress(0xff8))
+    ));
+
+    // A store into a guard region stops the run at the store without writing to RAM
+    let mut emulator = Emulator::new(opts("0", &["0x80000ff0+0x10"]))?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::GuardRegion(0xff8));
+    assert_eq!(summary.exit_code, None);
+    assert_eq!(emulator.cpu.pc, 0xbfc0000c);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0);
+    Ok(())
+}
+
+#[test]
+fn jump_into_device_memory_raises_bus_error() -> Result<()> {
+    let source = r#"
+            li    $t0, 0xa2010000
+            jr    $t0
+            nop
+    "#;
+
+    let path = TempFile::new("wild-jump.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::InstructionBusError);
+    assert_eq!(emulator.cpu.pc, 0xa201_0000);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn sigterm_stops_at_instruction_boundary() -> Result<()> {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    let source = r#"
+        spin:
+            addiu $s0, $s0, 1
+            b     spin
+            nop
+    "#;
+
+    let path = TempFile::new("sigterm.s");
+    let dump = TempFile::new("sigterm.txt");
+    std::fs::write(&path, source)?;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rmips"))
+        .arg(&*path)
+        .arg("--signal-dump")
+        .arg(&*dump)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+
+    // The handlers are installed once the machine is reset
+    while !stdout.next().unwrap()?.contains("[ RESET ]") {}
+    let status = Command::new("kill")
+        .arg("-TERM")
+        .arg(child.id().to_string())
+        .status()?;
+    assert!(status.success());
+
+    let output: Vec<String> = stdout.collect::<std::io::Result<_>>()?;
+    assert_eq!(child.wait()?.code(), Some(128 + 15));
+    assert!(output
+        .iter()
+        .any(|line| line.starts_with("Stopped by signal 15")));
+    assert!(output.iter().any(|line| line.starts_with("Executed ")));
+    assert!(std::fs::read_to_string(&dump)?.contains(" s0  ="));
+    Ok(())
+}
+
+#[test]
+fn gdb_reads_memory_in_bulk() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80000ff8
+            li    $t1, 0x11223344
+            sw    $t1, 0($t0)
+            sw    $t1, 4($t0)
+            sw    $t1, 8($t0)
+            sw    $t1, 12($t0)
+            break
+    "#;
+
+    let path = TempFile::new("gdbread.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    emulator.run()?;
+
+    // The read spans a page boundary and is translated once per page
+    let mut data = [0; 16];
+    assert!(SingleThreadBase::read_addrs(&mut emulator, 0x80000ff8, &mut data).is_ok());
+    assert_eq!(data.to_vec(), [0x44, 0x33, 0x22, 0x11].repeat(4));
+
+    // All of RAM can be read at once, and a read past its end returns the bytes up to the end
+    let mut ram = vec![0; 0x10_0000];
+    assert!(matches!(
+        SingleThreadBase::read_addrs(&mut emulator, 0x8000_0000, &mut ram),
+        Ok(0x10_0000)
+    ));
+    assert_eq!(ram[0xff8..0x1000].to_vec(), data[..8].to_vec());
+    let mut past_end = [0; 8];
+    assert!(matches!(
+        SingleThreadBase::read_addrs(&mut emulator, 0x800f_fff8, &mut past_end),
+        Ok(8)
+    ));
+    assert!(matches!(
+        SingleThreadBase::read_addrs(&mut emulator, 0x800f_fffc, &mut past_end),
+        Ok(4)
+    ));
+
+    // A read that faults at its first byte is answered with EIO
+    assert!(matches!(
+        SingleThreadBase::read_addrs(&mut emulator, 0x8010_0000, &mut past_end),
+        Err(TargetError::Errno(5))
+    ));
+
+    // Kernel addresses have no translation in user mode, which is answered with EFAULT
+    emulator.cpu.cpzero.status.set_kuc();
+    assert!(matches!(
+        SingleThreadBase::read_addrs(&mut emulator, 0x8000_0ff8, &mut past_end),
+        Err(TargetError::Errno(14))
+    ));
+    Ok(())
+}
+
+#[test]
+fn step_over_and_finish() -> Result<()> {
+    let source = r#"
+            li    $sp, 0x80010000
+            li    $a0, 3
+            jal   count          # 0xbfc00008
+            nop
+            move  $s1, $v0
+            break
+        count:                   # Returns $a0 by calling itself $a0 times
+            addiu $sp, $sp, -8
+            sw    $ra, 0($sp)
+            beqz  $a0, out
+            move  $v0, $zero
+            addiu $a0, $a0, -1
+            jal   count          # 0xbfc0002c
+            nop
+            addiu $v0, $v0, 1
+        out:
+            lw    $ra, 0($sp)
+            addiu $sp, $sp, 8
+            jr    $ra
+            nop
+    "#;
+
+    let path = TempFile::new("stepover.s");
+    std::fs::write(&path, source)?;
+
+    let opts = || Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    // Stepping over the outer call runs the whole recursion
+    let mut emulator = Emulator::new(opts())?;
+    emulator.step()?;
+    emulator.step()?;
+    assert_eq!(emulator.step_over()?, EmulationEvent::Step);
+    assert_eq!(emulator.cpu.pc, 0xbfc0_0010);
+    assert_eq!(emulator.cpu.reg[Register::V0], 3);
+
+    // The deeper frames return to the same call site, but only this frame's return stops
+    let mut emulator = Emulator::new(opts())?;
+    while emulator.cpu.pc != 0xbfc0_002c {
+        emulator.step()?;
+    }
+    let sp = emulator.cpu.reg[Register::Sp];
+    assert_eq!(emulator.step_over()?, EmulationEvent::Step);
+    assert_eq!(emulator.cpu.pc, 0xbfc0_0034);
+    assert_eq!(emulator.cpu.reg[Register::V0], 2);
+    assert_eq!(emulator.cpu.reg[Register::Sp], sp);
+
+    // Finishing from the outermost frame of the recursion returns to the caller
+    let mut emulator = Emulator::new(opts())?;
+    while emulator.cpu.pc != 0xbfc0_002c {
+        emulator.step()?;
+    }
+    assert_eq!(emulator.finish()?, EmulationEvent::Step);
+    assert_eq!(emulator.cpu.pc, 0xbfc0_0010);
+    assert_eq!(emulator.cpu.reg[Register::V0], 3);
+
+    // Reaching the --stop-at address in the callee stops the run first
+    let mut emulator = Emulator::new(Opts {
+        stopat: Some(StopAt::Pc(0xbfc0_0034)),
+        ..opts()
+    })?;
+    emulator.step()?;
+    emulator.step()?;
+    assert_eq!(
+        emulator.step_over()?,
+        EmulationEvent::Halted(HaltReason::StopAt)
+    );
+    assert_eq!(emulator.cpu.reg[Register::A0], 0);
+    Ok(())
+}
+
+#[test]
+fn register_map_names_device_registers() -> Result<()> {
+    let source = r#"
+            li    $t0, 0xa2080000
+            li    $t1, 0x21
+            sb    $t1, 0($t0)
+        loop:
+            b     loop
+            nop
+    "#;
+    let regmap = r#"
+        [LEDS]
+        base = 0x02080000
+
+        [LEDS.STATE]
+        offset = 0
+        size = 1
+        GREEN = 0
+        RED = 5
+        OTHERS = "4:1"
+    "#;
+
+    let path = TempFile::new("regmap.s");
+    let map = TempFile::new("regmap.toml");
+    let timeline = TempFile::new("regmap.json");
+    std::fs::write(&path, source)?;
+    std::fs::write(&map, regmap)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        leds: true,
+        regmap: Some(map.to_string_lossy().into_owned()),
+        watch: vec!["mmio[LEDS.STATE.RED] == 1".parse().unwrap()],
+        timeline: Some(timeline.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts.clone())?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::WatchExpression(0));
+    assert!(emulator
+        .crashdump()
+        .ends_with("0x02080000  LEDS.STATE.GREEN=1 LEDS.STATE.RED=1 LEDS.STATE.OTHERS=0"));
+
+    let json = std::fs::read_to_string(&timeline)?;
+    assert!(
+        json.contains(r#""register": "LEDS.STATE.GREEN=1 LEDS.STATE.RED=1 LEDS.STATE.OTHERS=0""#)
+    );
+
+    // Registers that are not in the map are rejected before the run
+    let opts = Opts {
+        watch: vec!["mmio[LEDS.DISPLAY] != 0".parse().unwrap()],
+        ..opts
+    };
+    assert!(matches!(Emulator::new(opts), Err(RmipsError::Config(_))));
+    Ok(())
+}
+
+#[test]
+fn reset_device_reboots_with_memory_intact() -> Result<()> {
+    // Counts the boots in RAM and resets until the third one
+    let source = r#"
+            li    $t0, 0x80001000
+            lw    $t1, 0($t0)
+            addiu $t1, $t1, 1
+            sw    $t1, 0($t0)
+            li    $t2, 3
+            beq   $t1, $t2, done
+            nop
+            li    $t3, 0xa1010028
+            li    $t4, 1
+            sw    $t4, 0($t3)
+        done:
+            break
+    "#;
+
+    let path = TempFile::new("reset.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        resetdevice: true,
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(emulator.cpu.reg[Register::T1], 3);
+    Ok(())
+}
+
+#[test]
+fn inspector_samples_a_running_guest() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80001000
+            li    $t1, 0x20000
+        loop:
+            sw    $t1, 0($t0)
+            addiu $t1, $t1, -1
+            bnez  $t1, loop
+            nop
+            break
+    "#;
+
+    let path = TempFile::new("inspect.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let inspector = emulator.inspector(vec![0x80001000, 0xc0000000], 1000);
+    assert_eq!(inspector.latest().unwrap().instruction_count, 0);
+
+    // Samples taken while the guest runs are consistent snapshots
+    let reader = {
+        let inspector = inspector.clone();
+        std::thread::spawn(move || {
+            let mut last = 0;
+            while last < 0x40000 {
+                let sample = inspector.latest().unwrap();
+                assert!(sample.instruction_count >= last);
+                assert_eq!(sample.instruction_count % 1000, 0);
+                last = sample.instruction_count;
+                std::thread::yield_now();
+            }
+        })
+    };
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    reader.join().unwrap();
+
+    let sample = inspector.latest().unwrap();
+    assert_eq!(sample.words[0].0, 0x80001000);
+    assert_eq!(sample.words[1], (0xc0000000, None));
+    assert!(matches!(sample.words[0].1, Some(count) if count > 0 && count <= 0x20000));
+    Ok(())
+}
+
+#[test]
+fn big_and_little_endian_memory() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80001000
+            li    $t1, 0x11223344
+            sw    $t1, 0($t0)
+            lbu   $s0, 0($t0)
+            lhu   $s1, 2($t0)
+            li    $t2, 0xaabb
+            sh    $t2, 4($t0)
+            lw    $s2, 4($t0)
+            lw    $s3, data
+            li    $t3, 0xa20a0000
+            lw    $s4, 0($t3)
+            break
+        data:
+            .word 0xcafef00d
+    "#;
+
+    let path = TempFile::new("endian.s");
+    std::fs::write(&path, source)?;
+
+    for bigendian in [false, true] {
+        let opts = Opts {
+            romfile: path.to_string_lossy().into_owned(),
+            bigendian,
+            emulatorinfo: true,
+            ..Default::default()
+        };
+
+        let mut emulator = Emulator::new(opts)?;
+        let summary = emulator.run()?;
+        assert_eq!(summary.halt_reason, HaltReason::Break);
+
+        let reg = &emulator.cpu.reg;
+        let expected = match bigendian {
+            true => [0x11, 0x3344, 0xaabb_0000],
+            false => [0x44, 0x1122, 0x0000_aabb],
+        };
+        assert_eq!(
+            [reg[Register::S0], reg[Register::S1], reg[Register::S2]],
+            expected
+        );
+        // Words of the ROM and device registers read the same values in either byte order
+        assert_eq!(reg[Register::S3], 0xcafe_f00d);
+        assert_eq!(reg[Register::S4], u32::from_le_bytes(*b"RMIP"));
+    }
+    Ok(())
+}
+
+#[test]
+fn big_endian_unaligned_store() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80001000
+            li    $t1, 0x11223344
+            swl   $t1, 9($t0)
+            swr   $t1, 12($t0)
+            lw    $s0, 8($t0)
+            lw    $s1, 12($t0)
+            break
+    "#;
+
+    let path = TempFile::new("unaligned-be.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        bigendian: true,
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    // The bytes 11 22 33 44 from address 9
+    assert_eq!(emulator.cpu.reg[Register::S0], 0x0011_2233);
+    assert_eq!(emulator.cpu.reg[Register::S1], 0x4400_0000);
+    Ok(())
+}
+
+#[test]
+fn heatmap_counts_page_accesses() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80001000
+            li    $t1, 4
+        loop:
+            sw    $t1, 0($t0)
+            lw    $t2, 0($t0)
+            addiu $t1, $t1, -1
+            bnez  $t1, loop
+            nop
+            break
+    "#;
+
+    let path = TempFile::new("heatmap.s");
+    let heatmap = TempFile::new("heatmap.csv");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        heatmap: Some(heatmap.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+
+    let summary = Emulator::new(opts)?.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+
+    let csv = std::fs::read_to_string(&heatmap)?;
+    let rom = path.to_string_lossy();
+    assert_eq!(
+        csv,
+        format!(
+            "page,device,reads,writes,executes\n0x00001000,RAM,4,4,0\n0x1fc00000,{},0,0,{}\n",
+            rom,
+            summary.instructions + 1
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn isolated_cache_flush_keeps_memory() -> Result<()> {
+    // Flushes two lines of the instruction cache the R3000 way, with IsC and SwC set
+    let source = r#"
+            li    $t0, 0x80001000
+            li    $t1, 0x12345678
+            sw    $t1, 0($t0)
+            li    $t2, 0x30000
+            mtc0  $t2, $12
+            nop
+            sw    $zero, 0($t0)
+            sb    $zero, 5($t0)
+            lw    $s1, 0($t0)
+            mtc0  $zero, $12
+            nop
+            lw    $s0, 0($t0)
+            break
+    "#;
+
+    let path = TempFile::new("isc.s");
+    let timeline = TempFile::new("isc.json");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        timeline: Some(timeline.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(emulator.cpu.reg[Register::S0], 0x1234_5678);
+    assert_eq!(emulator.cpu.reg[Register::S1], 0);
+
+    let json = std::fs::read_to_string(&timeline)?;
+    assert_eq!(json.matches("Invalidate instructions").count(), 2);
+    assert!(json.contains(r#""address": "0x00001004", "size": "4""#));
+    Ok(())
+}
+
+#[test]
+fn clock_interrupts_the_cpu() -> Result<()> {
+    // Starts a 50 microsecond timer and counts loop iterations until its interrupt arrives
+    let source = r#"
+            b     main
+            nop
+
+            .space 0x180 - 8
+        # General exception vector while the boot exception vectors are enabled
+            mfc0  $k0, $13
+            break
+
+        main:
+            li    $t0, 0xa1010000
+            li    $t1, 50
+            sw    $t1, 8($t0)
+            li    $t1, 7
+            sw    $t1, 0($t0)
+            li    $t1, 0x00408001
+            mtc0  $t1, $12
+        loop:
+            b     loop
+            addiu $s0, $s0, 1
+    "#;
+
+    let path = TempFile::new("clock.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        clock: true,
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+
+    // Cause holds an Interrupt exception with IP7 pending
+    let cause = emulator.cpu.reg[Register::K0];
+    assert_eq!((cause >> 2) & 0x1f, 0);
+    assert_eq!(cause & 0xff00, 0x8000);
+    assert_eq!(emulator.cpu.reg[Register::S0], 23);
+    Ok(())
+}
+
+#[test]
+fn interrupt_latency_is_reported() -> Result<()> {
+    // Takes three interrupts of a periodic timer, the first of which waits for interrupts to be
+    // enabled
+    let source = r#"
+            b     main
+            nop
+
+            .space 0x180 - 8
+        # General exception vector while the boot exception vectors are enabled
+            li    $k0, 0xa1010000
+            li    $k1, 1
+            sw    $k1, 4($k0)
+            addiu $s0, $s0, 1
+            li    $k1, 3
+            beq   $s0, $k1, done
+            nop
+            mfc0  $k0, $14
+            jr    $k0
+            rfe
+        done:
+            break
+
+        main:
+            li    $t0, 0xa1010000
+            li    $t1, 20
+            sw    $t1, 8($t0)
+            li    $t1, 7
+            sw    $t1, 0($t0)
+            li    $t1, 0x00408000
+            mtc0  $t1, $12
+            li    $t2, 30
+        wait:
+            addiu $t2, $t2, -1
+            bne   $t2, $zero, wait
+            nop
+            li    $t1, 0x00408001
+            mtc0  $t1, $12
+        loop:
+            b     loop
+            nop
+    "#;
+
+    let path = TempFile::new("latency.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        clock: true,
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let summary = emulator.run()?;
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+    assert_eq!(emulator.cpu.reg[Register::S0], 3);
+
+    // The first interrupt waits out the rest of the loop with interrupts disabled, the others
+    // are taken right away or after the delay slot of the idle loop
+    let latency = summary.interrupt_latency.expect("interrupts were taken");
+    assert_eq!((latency.count, latency.min, latency.max), (3, 1, 79));
+    assert_eq!(latency.total, 82);
+    assert!((latency.average() - 82.0 / 3.0).abs() < 1e-9);
+    Ok(())
+}
+
+#[test]
+fn fpu_computes_and_traps() -> Result<()> {
+    // Divides 3 by 2 in double precision, then traps on a division by zero
+    let source = r#"
+            b     main
+            nop
+
+            .space 0x180 - 8
+        # General exception vector while the boot exception vectors are enabled
+            mfc0  $k0, $13
+            break
+
+        main:
+            li    $t1, 0x20400000
+            mtc0  $t1, $12
+            li    $t0, 3
+            mtc1  $t0, $f0
+            li    $t0, 2
+            mtc1  $t0, $f1
+            cvt.d.w $f2, $f0
+            cvt.d.w $f4, $f1
+            div.d $f6, $f2, $f4
+            cvt.w.d $f8, $f6
+            mfc1  $s0, $f8
+            c.lt.d $f4, $f6
+            bc1f  ordered
+            nop
+            li    $s1, 1
+        ordered:
+            mtc1  $zero, $f10
+            mtc1  $zero, $f11
+            li    $t0, 0x400
+            ctc1  $t0, $31
+            div.d $f12, $f2, $f10
+            li    $s2, 1
+            break
+    "#;
+
+    let path = TempFile::new("fpu.s");
+    std::fs::write(&path, source)?;
+
+    for nofpu in [false, true] {
+        let opts = Opts {
+            romfile: path.to_string_lossy().into_owned(),
+            nofpu,
+            ..Default::default()
+        };
+
+        let mut emulator = Emulator::new(opts)?;
+        let summary = emulator.run()?;
+        assert_eq!(summary.halt_reason, HaltReason::Break);
+
+        let cause = emulator.cpu.reg[Register::K0];
+        if nofpu {
+            // Without an FPU the first move raises Coprocessor Unusable for CP1
+            assert_eq!((cause >> 2) & 0x1f, 11);
+            assert_eq!((cause >> 28) & 0x3, 1);
+            assert_eq!(emulator.cpu.reg[Register::S0], 0);
+            continue;
+        }
+
+        // 1.5 rounds to the even 2, and the enabled division by zero traps
+        assert_eq!(emulator.cpu.reg[Register::S0], 2);
+        assert_eq!(emulator.cpu.reg[Register::S1], 0);
+        assert_eq!((cause >> 2) & 0x1f, 15);
+        assert_eq!(emulator.cpu.reg[Register::S2], 0);
+    }
+    Ok(())
+}
+
+#[test]
+fn interrupt_controller_raises_lines_from_other_threads() -> Result<()> {
+    // Spins with IP5 unmasked until another thread raises the line
+    let source = r#"
+            b     main
+            nop
+
+            .space 0x180 - 8
+        # General exception vector while the boot exception vectors are enabled
+            mfc0  $k0, $13
+            break
+
+        main:
+            li    $t1, 0x00402001
+            mtc0  $t1, $12
+            li    $t2, 0x100000
+        loop:
+            addiu $t2, $t2, -1
+            bnez  $t2, loop
+            addiu $s0, $s0, 1
+            break
+    "#;
+
+    let path = TempFile::new("intctrl.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    let mut emulator = Emulator::new(opts)?;
+    let intctrl = emulator.interrupt_controller();
+    let inspector = emulator.inspector(Vec::new(), 100);
+    let raiser = std::thread::spawn(move || {
+        while inspector.latest().unwrap().reg[Register::S0 as usize] < 1000 {
+            std::thread::yield_now();
+        }
+        intctrl.raise(5);
+    });
+    let summary = emulator.run()?;
+    raiser.join().unwrap();
+    assert_eq!(summary.halt_reason, HaltReason::Break);
+
+    // Cause holds an Interrupt exception with IP5 pending, taken before the loop ran out
+    let cause = emulator.cpu.reg[Register::K0];
+    assert_eq!((cause >> 2) & 0x1f, 0);
+    assert_eq!(cause & 0xff00, 0x2000);
+    assert!(emulator.cpu.reg[Register::T2] > 0);
+    Ok(())
+}
+
+#[test]
+fn run_slice_continues_where_it_stopped() -> Result<()> {
+    // Slices end in the middle of the loop, including between branches and their delay slots
+    let source = r#"
+            li    $t0, 500
+        loop:
+            addiu $t0, $t0, -1
+            bnez  $t0, loop
+            addiu $s0, $s0, 3
+            break
+    "#;
+
+    let path = TempFile::new("slice.s");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        ..Default::default()
+    };
+
+    let mut whole = Emulator::new(opts.clone())?;
+    let expected = whole.run()?;
+
+    let mut sliced = Emulator::new(opts.clone())?;
+    let mut slices = 0;
+    let summary = loop {
+        match sliced.run_slice(SliceBudget::Instructions(7))? {
+            SliceOutcome::Yielded => slices += 1,
+            SliceOutcome::Halted(summary) => break summary,
+        }
+    };
+    assert_eq!(summary, expected);
+    assert_eq!(slices, expected.instructions / 7);
+    assert_eq!(sliced.cpu.reg[Register::S0], 1500);
+
+    // A host time budget yields on its own
+    let mut timed = Emulator::new(opts)?;
+    assert_eq!(
+        timed.run_slice(SliceBudget::HostTime(Duration::ZERO))?,
+        SliceOutcome::Yielded
+    );
+    assert_eq!(
+        timed.clock().virtual_time(),
+        Duration::from_micros(SLICE_CLOCK_INTERVAL as u64)
+    );
+    Ok(())
+}
+
+#[test]
+fn snapshot_file_resumes_run() -> Result<()> {
+    // The loop depends on the TLB, the FPU and the timer, which all have to be restored
+    let source = r#"
+            li    $t1, 0x20400000
+            mtc0  $t1, $12
+            li    $t0, 0xa1010000
+            li    $t1, 100000
+            sw    $t1, 8($t0)
+            li    $t1, 1
+            sw    $t1, 0($t0)
+            li    $t1, 0x00400000
+            mtc0  $t1, $10
+            li    $t1, 0x00001600
+            mtc0  $t1, $2
+            li    $t1, 0x500
+            mtc0  $t1, $0
+            tlbwi
+            li    $t2, 0x00400010
+            li    $t3, 1000
+            mtc1  $t3, $f2
+        loop:
+            lw    $t4, 0($t2)
+            addu  $t4, $t4, $t3
+            sw    $t4, 0($t2)
+            addiu $t3, $t3, -1
+            bnez  $t3, loop
+            nop
+            lw    $s0, 12($t0)
+            lw    $s1, 0($t2)
+            mfc1  $s2, $f2
+            break
+    "#;
+
+    let path = TempFile::new("snapshot.s");
+    let snapshot = TempFile::new("snapshot.bin");
+    let snapshot = snapshot.to_string_lossy().into_owned();
+    std::fs::write(&path, source)?;
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        clock: true,
+        ..Default::default()
+    };
+
+    let mut whole = Emulator::new(opts.clone())?;
+    let expected = whole.run()?;
+    assert_eq!(whole.cpu.reg[Register::S1], 500_500);
+
+    // Stop in the middle of the loop and resume in a new emulator
+    let mut first = Emulator::new(opts.clone())?;
+    assert_eq!(
+        first.run_slice(SliceBudget::Instructions(2001))?,
+        SliceOutcome::Yielded
+    );
+    first.save_snapshot(&snapshot)?;
+
+    let mut resumed = Emulator::new(Opts {
+        loadsnapshot: Some(snapshot.clone()),
+        ..opts.clone()
+    })?;
+    assert_eq!(resumed.run()?, expected);
+    for reg in [Register::S0, Register::S1, Register::S2].iter() {
+        assert_eq!(resumed.cpu.reg[*reg], whole.cpu.reg[*reg]);
+    }
+
+    // The devices of the snapshot must match the emulator loading it
+    let mut other = Emulator::new(Opts {
+        clock: false,
+        ..opts
+    })?;
+    assert_eq!(
+        other.load_snapshot(&snapshot).unwrap_err().to_string(),
+        "Invalid snapshot: the snapshot has 5 devices instead of 4"
+    );
+    Ok(())
+}
+
+#[test]
+fn trace_records_every_step() -> Result<()> {
+    let source = r#"
+            li    $t0, 0x80001000
+            li    $t1, 0x2a
+            sw    $t1, 4($t0)
+            lw    $t2, 4($t0)
+            syscall
+            break
+
+            .space 0x180 - 28
+        # General exception vector while the boot exception vectors are enabled
+            break
+    "#;
+
+    let path = TempFile::new("trace.s");
+    let trace = TempFile::new("trace.jsonl");
+    std::fs::write(&path, source)?;
+
+    let opts = Opts {
+        romfile: path.to_string_lossy().into_owned(),
+        trace: Some(trace.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+
+    Emulator::new(opts.clone())?.run()?;
+    let first = std::fs::read_to_string(&trace)?;
+    let lines: Vec<&str> = first.lines().collect();
+    assert_eq!(lines.len(), 7);
+    assert_eq!(
+        lines[3],
+        r#"{"step":3,"pc":"0xbfc0000c","insn":"0xad090004","regs":{},"vaddr":"0x80001004","mem":[{"rw":"w","addr":"0x00001004","size":4,"value":"0x0000002a"}]}"#
+    );
+    assert!(lines[4].contains(r#""regs":{"$t2":"0x0000002a"}"#));
+    assert!(lines[4].contains(r#""mem":[{"rw":"r","addr":"0x00001004""#));
+    assert!(lines[5].ends_with(r#""EPC":"0xbfc00014"},"mem":[],"exception":"Syscall"}"#));
+    assert!(lines[6].starts_with(r#"{"step":6,"pc":"0xbfc00180","insn":"0x0000000d""#));
+
+    // The trace does not depend on the host, so a second run writes the same file
+    Emulator::new(opts)?.run()?;
+    assert_eq!(std::fs::read_to_string(&trace)?, first);
+    Ok(())
+}